@@ -1,68 +1,143 @@
-use cmake::Config;
+use std::env;
 use std::path::PathBuf;
 
 fn main() {
-    // Get absolute paths to V4 repositories
-    // In V4-project workspace, v4_cli is sibling to V4-engine and V4-front
-    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    let v4_path = manifest_dir.parent().unwrap().join("V4-engine");
-    let v4front_path = manifest_dir.parent().unwrap().join("V4-front");
-
-    // Build V4 VM library first
-    let mut v4_config = Config::new(&v4_path);
-    v4_config
-        .define("V4_BUILD_TESTS", "OFF")
-        .define("V4_BUILD_TOOLS", "OFF")
-        .define("V4_ENABLE_MOCK_HAL", "OFF")
-        .out_dir(manifest_dir.join("target/v4"));
-
-    // Set build profile for Windows
-    #[cfg(target_os = "windows")]
-    {
-        if cfg!(debug_assertions) {
-            v4_config.profile("Debug");
-        } else {
-            v4_config.profile("Release");
-        }
+    // Prebuilt static libraries skip CMake for that library entirely
+    println!("cargo:rerun-if-env-changed=V4_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=V4FRONT_LIB_DIR");
+    let v4_lib_dir = env::var_os("V4_LIB_DIR").map(PathBuf::from);
+    let v4front_lib_dir = env::var_os("V4FRONT_LIB_DIR").map(PathBuf::from);
+    for dir in [&v4_lib_dir, &v4front_lib_dir].into_iter().flatten() {
+        println!("cargo:rustc-link-search=native={}", dir.display());
     }
 
-    let v4_dst = v4_config.build();
+    if v4_lib_dir.is_none() || v4front_lib_dir.is_none() {
+        vendored::build(v4_lib_dir.is_none(), v4front_lib_dir.is_none());
+    }
 
-    // Build V4-front compiler library (depends on V4)
-    let mut v4front_config = Config::new(&v4front_path);
-    v4front_config
-        .define("V4FRONT_BUILD_TESTS", "OFF")
-        .define("V4_SRC_DIR", v4_path.to_str().unwrap())
-        .out_dir(manifest_dir.join("target/v4front"));
+    println!("cargo:rustc-link-lib=static=v4engine");
+    println!("cargo:rustc-link-lib=static=v4front");
 
-    // Set build profile for Windows
-    #[cfg(target_os = "windows")]
-    {
-        if cfg!(debug_assertions) {
-            v4front_config.profile("Debug");
-        } else {
-            v4front_config.profile("Release");
+    // Link C++ standard library (required by V4-front)
+    // macOS uses libc++, Windows uses built-in, other platforms use libstdc++
+    #[cfg(target_os = "macos")]
+    println!("cargo:rustc-link-lib=c++");
+    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
+    println!("cargo:rustc-link-lib=stdc++");
+}
+
+/// Build V4 and V4-front from source with CMake
+#[cfg(feature = "vendored")]
+mod vendored {
+    use cmake::Config;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    /// Build the libraries not given prebuilt, both at once
+    pub fn build(v4: bool, v4front: bool) {
+        // Get absolute paths to V4 repositories
+        // In V4-project workspace, v4_cli is sibling to V4-engine and V4-front
+        let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let v4_path = manifest_dir.parent().unwrap().join("V4-engine");
+        let v4front_path = manifest_dir.parent().unwrap().join("V4-front");
+
+        // V4-front compiles against the V4 sources, not the built library, so
+        // the two builds don't wait for each other
+        thread::scope(|scope| {
+            let v4_build = v4.then(|| scope.spawn(|| build_v4(&v4_path, &manifest_dir)));
+            let v4front_build = v4front
+                .then(|| scope.spawn(|| build_v4front(&v4front_path, &v4_path, &manifest_dir)));
+            if let Some(v4_build) = v4_build {
+                let v4_dst = v4_build
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                link_v4(&v4_dst);
+            }
+            if let Some(v4front_build) = v4front_build {
+                let v4front_dst = v4front_build
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                link_v4front(&v4front_dst);
+            }
+        });
+
+        // Rebuild triggers
+        let mut sources = Vec::new();
+        if v4 {
+            sources.push(&v4_path);
+        }
+        if v4front {
+            sources.push(&v4front_path);
+        }
+        for path in sources {
+            println!("cargo:rerun-if-changed={}/src", path.display());
+            println!("cargo:rerun-if-changed={}/include", path.display());
         }
     }
 
-    let v4front_dst = v4front_config.build();
+    fn build_v4(v4_path: &Path, manifest_dir: &Path) -> PathBuf {
+        let mut v4_config = Config::new(v4_path);
+        v4_config
+            .define("V4_BUILD_TESTS", "OFF")
+            .define("V4_BUILD_TOOLS", "OFF")
+            // v4 only runs the engine on the host, against the mock HAL
+            .define("V4_ENABLE_MOCK_HAL", "ON")
+            .out_dir(manifest_dir.join("target/v4"));
+
+        // Set build profile for Windows
+        #[cfg(target_os = "windows")]
+        v4_config.profile(profile());
+
+        v4_config.build()
+    }
+
+    fn build_v4front(v4front_path: &Path, v4_path: &Path, manifest_dir: &Path) -> PathBuf {
+        let mut v4front_config = Config::new(v4front_path);
+        v4front_config
+            .define("V4FRONT_BUILD_TESTS", "OFF")
+            .define("V4_SRC_DIR", v4_path.to_str().unwrap())
+            .out_dir(manifest_dir.join("target/v4front"));
+
+        // Set build profile for Windows
+        #[cfg(target_os = "windows")]
+        v4front_config.profile(profile());
+
+        v4front_config.build()
+    }
 
-    // Link libraries
-    // On Windows, CMake generates libraries in Debug/Release subdirectories
     #[cfg(target_os = "windows")]
-    {
-        let profile = if cfg!(debug_assertions) {
+    fn profile() -> &'static str {
+        if cfg!(debug_assertions) {
             "Debug"
         } else {
             "Release"
-        };
+        }
+    }
+
+    fn link_v4(v4_dst: &Path) {
         // V4 VM has install target, libraries go to lib/
         let v4_lib_path = v4_dst.join("lib");
         println!("cargo:rustc-link-search=native={}", v4_lib_path.display());
+        #[cfg(target_os = "windows")]
         println!("cargo:warning=V4 lib path: {}", v4_lib_path.display());
+    }
 
+    // On Unix, V4-front libraries are in build/
+    #[cfg(not(target_os = "windows"))]
+    fn link_v4front(v4front_dst: &Path) {
+        // V4-front doesn't have install target, link directly from build directory
+        println!(
+            "cargo:rustc-link-search=native={}/build",
+            v4front_dst.display()
+        );
+    }
+
+    // On Windows, CMake generates libraries in Debug/Release subdirectories
+    #[cfg(target_os = "windows")]
+    fn link_v4front(v4front_dst: &Path) {
+        let profile = profile();
         // V4-front doesn't have install target, check multiple possible locations
-        let v4front_build_path = v4front_dst.join("build").join(&profile);
+        let v4front_build_path = v4front_dst.join("build").join(profile);
         println!(
             "cargo:rustc-link-search=native={}",
             v4front_build_path.display()
@@ -101,7 +176,7 @@ fn main() {
         }
 
         // Check v4front_dst directory
-        if let Ok(entries) = std::fs::read_dir(&v4front_dst) {
+        if let Ok(entries) = std::fs::read_dir(v4front_dst) {
             println!(
                 "cargo:warning=V4-front dst ({}) contents:",
                 v4front_dst.display()
@@ -111,32 +186,11 @@ fn main() {
             }
         }
     }
+}
 
-    // On Unix, libraries are in lib/ or build/
-    #[cfg(not(target_os = "windows"))]
-    {
-        // V4 VM has install target, libraries go to lib/
-        println!("cargo:rustc-link-search=native={}/lib", v4_dst.display());
-        // V4-front doesn't have install target, link directly from build directory
-        println!(
-            "cargo:rustc-link-search=native={}/build",
-            v4front_dst.display()
-        );
-    }
-
-    println!("cargo:rustc-link-lib=static=v4engine");
-    println!("cargo:rustc-link-lib=static=v4front");
-
-    // Link C++ standard library (required by V4-front)
-    // macOS uses libc++, Windows uses built-in, other platforms use libstdc++
-    #[cfg(target_os = "macos")]
-    println!("cargo:rustc-link-lib=c++");
-    #[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
-    println!("cargo:rustc-link-lib=stdc++");
-
-    // Rebuild triggers
-    println!("cargo:rerun-if-changed={}/src", v4_path.display());
-    println!("cargo:rerun-if-changed={}/include", v4_path.display());
-    println!("cargo:rerun-if-changed={}/src", v4front_path.display());
-    println!("cargo:rerun-if-changed={}/include", v4front_path.display());
+/// Without the `vendored` feature the libraries come from the system library
+/// path, or from `V4_LIB_DIR` and `V4FRONT_LIB_DIR`
+#[cfg(not(feature = "vendored"))]
+mod vendored {
+    pub fn build(_v4: bool, _v4front: bool) {}
 }
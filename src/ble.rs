@@ -0,0 +1,257 @@
+//! V4-link over Bluetooth LE (requires the `ble` feature)
+//!
+//! Devices expose the Nordic UART Service: frames are written to the RX
+//! characteristic in chunks that fit the default ATT MTU, and responses
+//! arrive as notifications on the TX characteristic, possibly split across
+//! several of them. btleplug is async, so the transport drives its own
+//! Tokio runtime and blocks on each operation.
+
+use crate::protocol::{Checksum, Frame, FrameDecoder, Framing};
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use btleplug::api::{
+    BDAddr, Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, ValueNotification,
+    WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::stream::{Stream, StreamExt};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+/// Nordic UART Service
+pub const UART_SERVICE: Uuid = Uuid::from_u128(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E);
+
+/// Characteristic the host writes frames to
+const UART_RX: Uuid = Uuid::from_u128(0x6E400002_B5A3_F393_E0A9_E50E24DCCA9E);
+
+/// Characteristic the device notifies responses on
+const UART_TX: Uuid = Uuid::from_u128(0x6E400003_B5A3_F393_E0A9_E50E24DCCA9E);
+
+/// Bytes per write: the default ATT MTU (23) minus the 3-byte ATT header
+const WRITE_CHUNK_SIZE: usize = 20;
+
+/// How long to scan for the addressed device before giving up
+const CONNECT_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval between checks of the discovered peripherals while scanning
+const SCAN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+type Notifications = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// Device advertising the UART service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BleDevice {
+    /// Bluetooth address (`AA:BB:CC:DD:EE:FF`)
+    pub address: String,
+    pub name: Option<String>,
+    /// Signal strength in dBm at discovery
+    pub rssi: Option<i16>,
+}
+
+/// V4-link over the BLE UART service
+pub struct V4Ble {
+    runtime: Runtime,
+    peripheral: Peripheral,
+    rx: Characteristic,
+    notifications: Notifications,
+    /// Bytes received but not yet returned as a frame
+    pending: FrameDecoder,
+}
+
+impl V4Ble {
+    /// Scan for the device with Bluetooth address `addr` and connect to it
+    pub fn connect(addr: &str) -> Result<Self> {
+        let target: BDAddr = addr
+            .parse()
+            .map_err(|_| V4Error::Ble(format!("Invalid Bluetooth address '{}'", addr)))?;
+        let runtime = Runtime::new()?;
+
+        let (peripheral, rx, notifications) = runtime.block_on(async {
+            let adapter = adapter().await?;
+            adapter
+                .start_scan(ScanFilter {
+                    services: vec![UART_SERVICE],
+                })
+                .await
+                .map_err(ble_error)?;
+
+            let deadline = Instant::now() + CONNECT_SCAN_TIMEOUT;
+            let peripheral = loop {
+                let found = adapter.peripherals().await.map_err(ble_error)?;
+                if let Some(peripheral) = found.into_iter().find(|p| p.address() == target) {
+                    break peripheral;
+                }
+                if Instant::now() >= deadline {
+                    let _ = adapter.stop_scan().await;
+                    return Err(V4Error::Ble(format!("Device {} not found", target)));
+                }
+                tokio::time::sleep(SCAN_POLL_INTERVAL).await;
+            };
+            let _ = adapter.stop_scan().await;
+
+            peripheral.connect().await.map_err(ble_error)?;
+            peripheral.discover_services().await.map_err(ble_error)?;
+            let characteristic = |uuid: Uuid| {
+                peripheral
+                    .characteristics()
+                    .into_iter()
+                    .find(|c| c.uuid == uuid)
+                    .ok_or_else(|| V4Error::Ble(format!("Device {} has no UART service", target)))
+            };
+            let rx = characteristic(UART_RX)?;
+            let tx = characteristic(UART_TX)?;
+
+            peripheral.subscribe(&tx).await.map_err(ble_error)?;
+            let notifications = peripheral.notifications().await.map_err(ble_error)?;
+            Ok::<_, V4Error>((peripheral, rx, notifications))
+        })?;
+
+        Ok(Self {
+            runtime,
+            peripheral,
+            rx,
+            notifications,
+            pending: FrameDecoder::new(),
+        })
+    }
+
+    /// Wait up to `timeout` for one notification from the TX characteristic
+    ///
+    /// Returns `false` on timeout.
+    fn read_notification(&mut self, timeout: Duration) -> Result<bool> {
+        let notifications = &mut self.notifications;
+        let next = self
+            .runtime
+            .block_on(async { tokio::time::timeout(timeout, notifications.next()).await });
+        match next {
+            Ok(Some(notification)) => {
+                if notification.uuid == UART_TX {
+                    self.pending.push(&notification.value);
+                }
+                Ok(true)
+            }
+            Ok(None) => Err(V4Error::Ble("Device disconnected".to_string())),
+            Err(_) => Ok(false),
+        }
+    }
+}
+
+impl Drop for V4Ble {
+    fn drop(&mut self) {
+        let _ = self.runtime.block_on(self.peripheral.disconnect());
+    }
+}
+
+/// Scan for devices advertising the UART service
+pub fn scan(duration: Duration) -> Result<Vec<BleDevice>> {
+    Runtime::new()?.block_on(async {
+        let adapter = adapter().await?;
+        adapter
+            .start_scan(ScanFilter {
+                services: vec![UART_SERVICE],
+            })
+            .await
+            .map_err(ble_error)?;
+        tokio::time::sleep(duration).await;
+        let _ = adapter.stop_scan().await;
+
+        let mut devices = Vec::new();
+        for peripheral in adapter.peripherals().await.map_err(ble_error)? {
+            let properties = peripheral.properties().await.map_err(ble_error)?;
+            let Some(properties) = properties else {
+                continue;
+            };
+            if !properties.services.contains(&UART_SERVICE) {
+                continue;
+            }
+            devices.push(BleDevice {
+                address: peripheral.address().to_string(),
+                name: properties.local_name,
+                rssi: properties.rssi,
+            });
+        }
+        devices.sort_by(|a, b| a.address.cmp(&b.address));
+        Ok(devices)
+    })
+}
+
+/// First Bluetooth adapter of the system
+async fn adapter() -> Result<Adapter> {
+    let manager = Manager::new().await.map_err(ble_error)?;
+    manager
+        .adapters()
+        .await
+        .map_err(ble_error)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| V4Error::Ble("No Bluetooth adapter found".to_string()))
+}
+
+fn ble_error(error: btleplug::Error) -> V4Error {
+    V4Error::Ble(error.to_string())
+}
+
+impl Transport for V4Ble {
+    /// Write a frame to the RX characteristic, one ATT packet at a time
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        let encoded = self.pending.framing().encode(frame);
+        log::trace!("TX frame ({} bytes): {:02X?}", encoded.len(), encoded);
+        self.runtime.block_on(async {
+            for chunk in encoded.chunks(WRITE_CHUNK_SIZE) {
+                self.peripheral
+                    .write(&self.rx, chunk, WriteType::WithResponse)
+                    .await
+                    .map_err(ble_error)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Read whatever arrives within the timeout, pending bytes first
+    fn read_raw(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        if self.pending.is_empty() {
+            self.read_notification(timeout)?;
+        }
+        Ok(self.pending.read_into(buf))
+    }
+
+    fn switches_framing(&self) -> bool {
+        true
+    }
+
+    fn set_framing(&mut self, framing: Framing) -> Result<()> {
+        self.pending.set_framing(framing);
+        Ok(())
+    }
+
+    fn checksum(&self) -> Checksum {
+        self.pending.framing().checksum
+    }
+
+    /// Drop pending bytes and notifications already received
+    fn discard_input(&mut self) -> Result<()> {
+        while self.read_notification(Duration::ZERO)? {}
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Receive response with timeout, reassembling split notifications
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let start = Instant::now();
+
+        loop {
+            if let Some(frame) = self.pending.next_frame() {
+                let frame = frame?;
+                log::trace!("RX frame ({} bytes): {:02X?}", frame.len(), frame);
+                return Ok(frame);
+            }
+
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() || !self.read_notification(remaining)? {
+                return Err(V4Error::Timeout);
+            }
+        }
+    }
+}
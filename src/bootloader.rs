@@ -0,0 +1,523 @@
+//! ESP32 ROM serial bootloader client
+//!
+//! Speaks the protocol of the bootloader in the chip's mask ROM (the one
+//! esptool uses): SLIP-framed request/response packets to erase, write and
+//! checksum flash. Only the commands needed to flash an image are
+//! implemented, and only the ESP32-C6 is accepted for now.
+//!
+//! Packets: request `[0x00][OP][SIZE u16][CHECKSUM u32][DATA...]`,
+//! response `[0x01][OP][SIZE u16][VALUE u32][DATA...][STATUS...]`.
+
+use crate::{Result, V4Error};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+/// SLIP frame delimiter
+const SLIP_END: u8 = 0xC0;
+/// SLIP escape byte
+const SLIP_ESC: u8 = 0xDB;
+/// Escaped `SLIP_END`
+const SLIP_ESC_END: u8 = 0xDC;
+/// Escaped `SLIP_ESC`
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// Bytes written per FLASH_DATA packet by the ROM loader
+pub const FLASH_BLOCK_SIZE: usize = 0x400;
+/// Chip ID the ROM reports for the ESP32-C6
+pub const ESP32C6_CHIP_ID: u32 = 13;
+/// First byte of an ESP application or bootloader image
+pub const IMAGE_MAGIC: u8 = 0xE9;
+
+/// Seed of the FLASH_DATA payload checksum
+const CHECKSUM_SEED: u8 = 0xEF;
+/// Status bytes the ROM loader appends to every response
+const ROM_STATUS_LEN: usize = 4;
+/// SYNC packets sent before giving up
+const SYNC_ATTEMPTS: usize = 7;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+const SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+/// Erasing is done by FLASH_BEGIN and scales with the image size
+const ERASE_TIMEOUT_PER_MB: Duration = Duration::from_secs(30);
+const MD5_TIMEOUT_PER_MB: Duration = Duration::from_secs(8);
+
+/// Bootloader commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Op {
+    FlashBegin = 0x02,
+    FlashData = 0x03,
+    FlashEnd = 0x04,
+    Sync = 0x08,
+    SpiAttach = 0x0D,
+    SpiFlashMd5 = 0x13,
+    GetSecurityInfo = 0x14,
+}
+
+/// Chip name for a ROM chip ID
+pub fn chip_name(chip_id: u32) -> &'static str {
+    match chip_id {
+        0 => "ESP32",
+        2 => "ESP32-S2",
+        5 => "ESP32-C3",
+        9 => "ESP32-S3",
+        12 => "ESP32-C2",
+        ESP32C6_CHIP_ID => "ESP32-C6",
+        16 => "ESP32-H2",
+        _ => "unknown chip",
+    }
+}
+
+/// Wrap a packet in a SLIP frame
+pub fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(packet.len() + 2);
+    frame.push(SLIP_END);
+    for &byte in packet {
+        match byte {
+            SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            _ => frame.push(byte),
+        }
+    }
+    frame.push(SLIP_END);
+    frame
+}
+
+/// Incremental SLIP decoder
+#[derive(Debug, Default)]
+struct SlipDecoder {
+    packet: Vec<u8>,
+    in_frame: bool,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    /// Feed one byte, returning a packet when it completes one
+    ///
+    /// Bytes outside a frame (boot log text) are dropped.
+    fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if byte == SLIP_END {
+            if self.in_frame && !self.packet.is_empty() {
+                self.in_frame = false;
+                return Some(std::mem::take(&mut self.packet));
+            }
+            // Start of a frame, or an empty one
+            self.in_frame = true;
+            return None;
+        }
+        if !self.in_frame {
+            return None;
+        }
+
+        if self.escaped {
+            self.escaped = false;
+            self.packet.push(match byte {
+                SLIP_ESC_END => SLIP_END,
+                SLIP_ESC_ESC => SLIP_ESC,
+                other => other,
+            });
+        } else if byte == SLIP_ESC {
+            self.escaped = true;
+        } else {
+            self.packet.push(byte);
+        }
+        None
+    }
+}
+
+/// Checksum of FLASH_DATA payloads: XOR of all bytes, seeded with 0xEF
+pub fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(CHECKSUM_SEED, |acc, b| acc ^ b) as u32
+}
+
+/// Check that an image starts like an ESP image built for the ESP32-C6
+///
+/// The chip ID sits at offset 12 of the extended image header.
+pub fn check_image(image: &[u8]) -> Result<()> {
+    if image.first() != Some(&IMAGE_MAGIC) {
+        return Err(V4Error::Bootloader(format!(
+            "Not an ESP firmware image (first byte {:#04x}, expected {:#04x})",
+            image.first().copied().unwrap_or(0),
+            IMAGE_MAGIC
+        )));
+    }
+    match image.get(12..14) {
+        Some(&[lo, hi]) if u16::from_le_bytes([lo, hi]) as u32 == ESP32C6_CHIP_ID => Ok(()),
+        Some(&[lo, hi]) => Err(V4Error::Bootloader(format!(
+            "Image is built for {}, not the ESP32-C6",
+            chip_name(u16::from_le_bytes([lo, hi]) as u32)
+        ))),
+        _ => Err(V4Error::Bootloader(
+            "Image too short for an ESP image header".to_string(),
+        )),
+    }
+}
+
+/// Bootloader session over a serial link already in download mode
+pub struct Loader<P> {
+    port: P,
+    decoder: SlipDecoder,
+    /// Packets decoded but not yet consumed
+    received: VecDeque<Vec<u8>>,
+}
+
+impl<P: Read + Write> Loader<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            decoder: SlipDecoder::default(),
+            received: VecDeque::new(),
+        }
+    }
+
+    /// The underlying port, e.g. to toggle reset lines
+    pub fn port_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Synchronize with the ROM, which also lets it detect the baud rate
+    pub fn sync(&mut self) -> Result<()> {
+        let mut payload = vec![0x07, 0x07, 0x12, 0x20];
+        payload.extend_from_slice(&[0x55; 32]);
+
+        for _ in 0..SYNC_ATTEMPTS {
+            // The ROM answers one SYNC several times; `command` skips the
+            // extra answers when waiting for the next response
+            match self.command(Op::Sync, &payload, 0, SYNC_TIMEOUT) {
+                Ok(_) => return Ok(()),
+                Err(V4Error::Timeout) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(V4Error::Bootloader(
+            "No answer from the ROM bootloader; hold BOOT while resetting the board".to_string(),
+        ))
+    }
+
+    /// Read the chip ID from GET_SECURITY_INFO
+    pub fn chip_id(&mut self) -> Result<u32> {
+        let (_, data) = self.command(Op::GetSecurityInfo, &[], 0, DEFAULT_TIMEOUT)?;
+        // [FLAGS u32][CRYPT_CNT][KEY_PURPOSES x7][CHIP_ID u32][ECO u32]
+        match data.get(12..16) {
+            Some(id) => Ok(u32::from_le_bytes([id[0], id[1], id[2], id[3]])),
+            None => Err(V4Error::Bootloader(format!(
+                "Security info too short: {} bytes",
+                data.len()
+            ))),
+        }
+    }
+
+    /// Attach the SPI flash with the default pins
+    pub fn attach_flash(&mut self) -> Result<()> {
+        self.command(Op::SpiAttach, &[0; 8], 0, DEFAULT_TIMEOUT)
+            .map(|_| ())
+    }
+
+    /// Erase and write `image` at `offset`, reporting bytes written
+    pub fn write_flash(
+        &mut self,
+        image: &[u8],
+        offset: u32,
+        on_progress: &mut dyn FnMut(usize),
+    ) -> Result<()> {
+        let blocks = image.len().div_ceil(FLASH_BLOCK_SIZE);
+        let mut begin = Vec::with_capacity(20);
+        for word in [image.len(), blocks, FLASH_BLOCK_SIZE, offset as usize, 0] {
+            begin.extend_from_slice(&(word as u32).to_le_bytes());
+        }
+        self.command(
+            Op::FlashBegin,
+            &begin,
+            0,
+            scaled(ERASE_TIMEOUT_PER_MB, image.len()),
+        )?;
+
+        for (seq, chunk) in image.chunks(FLASH_BLOCK_SIZE).enumerate() {
+            let mut block = chunk.to_vec();
+            block.resize(FLASH_BLOCK_SIZE, 0xFF);
+
+            let mut data = Vec::with_capacity(16 + FLASH_BLOCK_SIZE);
+            for word in [FLASH_BLOCK_SIZE, seq, 0, 0] {
+                data.extend_from_slice(&(word as u32).to_le_bytes());
+            }
+            data.extend_from_slice(&block);
+            self.command(Op::FlashData, &data, checksum(&block), DEFAULT_TIMEOUT)?;
+            on_progress((seq * FLASH_BLOCK_SIZE + chunk.len()).min(image.len()));
+        }
+        Ok(())
+    }
+
+    /// MD5 of a flash region, computed by the chip
+    pub fn flash_md5(&mut self, offset: u32, size: usize) -> Result<[u8; 16]> {
+        let mut payload = Vec::with_capacity(16);
+        for word in [offset, size as u32, 0, 0] {
+            payload.extend_from_slice(&word.to_le_bytes());
+        }
+        let (_, data) = self.command(
+            Op::SpiFlashMd5,
+            &payload,
+            0,
+            scaled(MD5_TIMEOUT_PER_MB, size),
+        )?;
+
+        // The ROM answers with 32 hex digits
+        let hex = std::str::from_utf8(data.get(..32).unwrap_or_default()).unwrap_or_default();
+        let mut digest = [0u8; 16];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = hex
+                .get(i * 2..i * 2 + 2)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| V4Error::Bootloader("Malformed MD5 response".to_string()))?;
+        }
+        Ok(digest)
+    }
+
+    /// Leave flash mode; the chip is reset separately
+    pub fn finish(&mut self) -> Result<()> {
+        // 1 = stay in the loader, the caller resets through RTS
+        self.command(Op::FlashEnd, &1u32.to_le_bytes(), 0, DEFAULT_TIMEOUT)
+            .map(|_| ())
+    }
+
+    /// Send a request and wait for its response: (value, data without status)
+    fn command(
+        &mut self,
+        op: Op,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<(u32, Vec<u8>)> {
+        let mut packet = vec![0x00, op as u8];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(data);
+        self.port.write_all(&slip_encode(&packet))?;
+        self.port.flush()?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let response = self.read_packet(remaining)?;
+            // Skip answers to earlier commands, e.g. repeated SYNC answers
+            if let Some(parsed) = parse_response(op, &response)? {
+                return Ok(parsed);
+            }
+        }
+    }
+
+    /// Read the next SLIP packet
+    fn read_packet(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 256];
+        loop {
+            if let Some(packet) = self.received.pop_front() {
+                return Ok(packet);
+            }
+            if Instant::now() >= deadline {
+                return Err(V4Error::Timeout);
+            }
+            match self.port.read(&mut buf) {
+                // Only in-memory ports run dry; serial ports time out instead
+                Ok(0) => return Err(V4Error::Timeout),
+                Ok(n) => {
+                    let decoder = &mut self.decoder;
+                    let packets = buf[..n].iter().filter_map(|&byte| decoder.push(byte));
+                    self.received.extend(packets);
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Parse a response to `op`, `None` if it answers a different command
+fn parse_response(op: Op, packet: &[u8]) -> Result<Option<(u32, Vec<u8>)>> {
+    let malformed = || V4Error::Bootloader(format!("Malformed response: {:02X?}", packet));
+    let header = packet.get(..8).ok_or_else(malformed)?;
+    if header[0] != 0x01 || header[1] != op as u8 {
+        return Ok(None);
+    }
+
+    let size = u16::from_le_bytes([header[2], header[3]]) as usize;
+    let value = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let body = packet.get(8..8 + size).ok_or_else(malformed)?;
+    if body.len() < ROM_STATUS_LEN {
+        return Err(malformed());
+    }
+
+    let (data, status) = body.split_at(body.len() - ROM_STATUS_LEN);
+    if status[0] != 0 {
+        return Err(V4Error::Bootloader(format!(
+            "{:?} failed with error {:#04x}",
+            op, status[1]
+        )));
+    }
+    Ok(Some((value, data.to_vec())))
+}
+
+/// Timeout for an operation proportional to `size`, at least the default
+fn scaled(per_mb: Duration, size: usize) -> Duration {
+    let scaled = per_mb.mul_f64(size as f64 / (1024.0 * 1024.0));
+    scaled.max(DEFAULT_TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// In-memory port: reads scripted bytes, records writes
+    #[derive(Default)]
+    struct FakePort {
+        input: VecDeque<u8>,
+        written: Vec<u8>,
+    }
+
+    impl Read for FakePort {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakePort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn response(op: Op, data: &[u8], status: [u8; 4]) -> Vec<u8> {
+        let mut packet = vec![0x01, op as u8];
+        packet.extend_from_slice(&((data.len() + 4) as u16).to_le_bytes());
+        packet.extend_from_slice(&[0; 4]);
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(&status);
+        slip_encode(&packet)
+    }
+
+    /// Split the written bytes into decoded request packets
+    fn requests(written: &[u8]) -> Vec<Vec<u8>> {
+        let mut decoder = SlipDecoder::default();
+        written.iter().filter_map(|&b| decoder.push(b)).collect()
+    }
+
+    #[test]
+    fn test_slip_round_trip() {
+        let packet = [0x01, SLIP_END, 0x02, SLIP_ESC, 0x03];
+        let frame = slip_encode(&packet);
+        assert_eq!(
+            frame,
+            vec![0xC0, 0x01, 0xDB, 0xDC, 0x02, 0xDB, 0xDD, 0x03, 0xC0]
+        );
+
+        // Boot log text before the frame is dropped
+        let mut stream = b"rst:0x1\r\n".to_vec();
+        stream.extend(&frame);
+        assert_eq!(requests(&stream), vec![packet.to_vec()]);
+    }
+
+    #[test]
+    fn test_sync_skips_repeated_answers() {
+        let mut port = FakePort::default();
+        for _ in 0..3 {
+            port.input.extend(response(Op::Sync, &[], [0; 4]));
+        }
+        port.input.extend(response(Op::SpiAttach, &[], [0; 4]));
+
+        let mut loader = Loader::new(port);
+        loader.sync().unwrap();
+        // The repeated SYNC answers are skipped
+        loader.attach_flash().unwrap();
+
+        let sent = requests(&loader.port.written);
+        assert_eq!(sent[0][1], Op::Sync as u8);
+        assert_eq!(&sent[0][8..12], &[0x07, 0x07, 0x12, 0x20]);
+        assert_eq!(sent[1][1], Op::SpiAttach as u8);
+    }
+
+    #[test]
+    fn test_write_flash_blocks() {
+        let mut port = FakePort::default();
+        for op in [Op::FlashBegin, Op::FlashData, Op::FlashData] {
+            port.input.extend(response(op, &[], [0; 4]));
+        }
+
+        let image = vec![0xAB; FLASH_BLOCK_SIZE + 10];
+        let mut progress = Vec::new();
+        let mut loader = Loader::new(port);
+        loader
+            .write_flash(&image, 0x10000, &mut |n| progress.push(n))
+            .unwrap();
+        assert_eq!(progress, vec![FLASH_BLOCK_SIZE, FLASH_BLOCK_SIZE + 10]);
+
+        let sent = requests(&loader.port.written);
+        // FLASH_BEGIN: size, blocks, block size, offset, encrypted
+        assert_eq!(
+            sent[0][8..12],
+            ((FLASH_BLOCK_SIZE + 10) as u32).to_le_bytes()
+        );
+        assert_eq!(sent[0][12..16], 2u32.to_le_bytes());
+        assert_eq!(sent[0][20..24], 0x10000u32.to_le_bytes());
+
+        // The last block is padded with 0xFF and checksummed
+        let last = &sent[2];
+        let mut block = vec![0xAB; 10];
+        block.resize(FLASH_BLOCK_SIZE, 0xFF);
+        assert_eq!(last[4..8], checksum(&block).to_le_bytes());
+        assert_eq!(last[12..16], 1u32.to_le_bytes());
+        assert_eq!(last[24..], block[..]);
+    }
+
+    #[test]
+    fn test_error_status() {
+        let mut port = FakePort::default();
+        port.input
+            .extend(response(Op::FlashBegin, &[], [1, 0x05, 0, 0]));
+
+        let mut loader = Loader::new(port);
+        let err = loader.write_flash(&[0; 4], 0, &mut |_| {}).unwrap_err();
+        assert!(matches!(err, V4Error::Bootloader(msg) if msg.contains("0x05")));
+    }
+
+    #[test]
+    fn test_flash_md5_and_chip_id() {
+        let mut port = FakePort::default();
+        port.input.extend(response(
+            Op::SpiFlashMd5,
+            b"d41d8cd98f00b204e9800998ecf8427e",
+            [0; 4],
+        ));
+        let mut info = vec![0; 12];
+        info.extend_from_slice(&ESP32C6_CHIP_ID.to_le_bytes());
+        info.extend_from_slice(&0u32.to_le_bytes());
+        port.input
+            .extend(response(Op::GetSecurityInfo, &info, [0; 4]));
+
+        let mut loader = Loader::new(port);
+        assert_eq!(
+            loader.flash_md5(0, 0).unwrap(),
+            [
+                0xd4, 0x1d, 0x8c, 0xd9, 0x8f, 0x00, 0xb2, 0x04, 0xe9, 0x80, 0x09, 0x98, 0xec, 0xf8,
+                0x42, 0x7e
+            ]
+        );
+        assert_eq!(loader.chip_id().unwrap(), ESP32C6_CHIP_ID);
+    }
+
+    #[test]
+    fn test_check_image() {
+        let mut image = vec![0; 24];
+        image[0] = IMAGE_MAGIC;
+        image[12] = ESP32C6_CHIP_ID as u8;
+        assert!(check_image(&image).is_ok());
+
+        image[12] = 5;
+        assert!(check_image(&image).is_err());
+        assert!(check_image(&[0x7F, 0x45, 0x4C, 0x46]).is_err());
+    }
+}
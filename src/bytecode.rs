@@ -0,0 +1,159 @@
+//! .v4b bytecode files: header parsing and the checks before a push
+//!
+//! A .v4b file starts with V4-front's 16-byte `V4BytecodeHeader`: "V4BC",
+//! format major and minor version, flags (u16), code_size (u32) and, from
+//! v0.2, word_count (u32), little-endian. The main code follows, then the
+//! word section.
+
+use crate::{Result, V4Error};
+
+/// .v4b header size: "V4BC", version, flags, code_size, word_count
+pub const V4B_HEADER_SIZE: usize = 16;
+
+/// Magic number at the start of every .v4b file
+pub const V4B_MAGIC: &[u8; 4] = b"V4BC";
+
+/// Newest .v4b format version this tool understands
+pub const SUPPORTED_VERSION: (u8, u8) = (0, 2);
+
+/// .v4b header fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V4bHeader {
+    pub version: (u8, u8),
+    pub flags: u16,
+    /// Code size declared in the header
+    pub code_size: usize,
+    /// Word definition count (v0.2+)
+    pub word_count: Option<u32>,
+}
+
+impl V4bHeader {
+    /// Parse the header of a .v4b file
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < V4B_HEADER_SIZE {
+            return Err(V4Error::Protocol(
+                "File too small to contain V4 bytecode header".to_string(),
+            ));
+        }
+        if !data.starts_with(V4B_MAGIC) {
+            return Err(V4Error::Protocol(
+                "Invalid V4 bytecode file (missing V4BC magic number)".to_string(),
+            ));
+        }
+
+        let version = (data[4], data[5]);
+        Ok(Self {
+            version,
+            flags: u16::from_le_bytes([data[6], data[7]]),
+            code_size: u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize,
+            word_count: (version >= (0, 2))
+                .then(|| u32::from_le_bytes([data[12], data[13], data[14], data[15]])),
+        })
+    }
+
+    /// Check that a file of `file_len` bytes holds the code the header declares
+    pub fn check_size(&self, file_len: usize) -> Result<()> {
+        let body = file_len.saturating_sub(V4B_HEADER_SIZE);
+        if self.code_size > body {
+            return Err(V4Error::Protocol(format!(
+                "Header declares {} bytes of code, file has {}",
+                self.code_size, body
+            )));
+        }
+        Ok(())
+    }
+
+    /// Why a VM of `vm_version` can't run this file, `None` if it can
+    ///
+    /// A VM reads the files of its own major version up to its minor
+    /// version: VM 0.2 runs v0.1 and v0.2 files, VM 0.1 can't read the word
+    /// section v0.2 added.
+    pub fn incompatibility(&self, vm_version: (u8, u8, u8)) -> Option<String> {
+        let ((major, minor), (vm_major, vm_minor, vm_patch)) = (self.version, vm_version);
+        let vm = format!("VM v{}.{}.{}", vm_major, vm_minor, vm_patch);
+        if major != vm_major {
+            Some(format!(
+                ".v4b v{}.{} is for VM v{}.x, the device runs {}",
+                major, minor, major, vm
+            ))
+        } else if minor > vm_minor {
+            Some(format!(
+                ".v4b v{}.{} needs VM v{}.{} or newer, the device runs {}",
+                major, minor, major, minor, vm
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Check a .v4b file before sending it
+///
+/// The header must be complete and start with the magic, and the file must
+/// hold the code the header declares.
+pub fn validate(data: &[u8]) -> Result<V4bHeader> {
+    let header = V4bHeader::parse(data)?;
+    if data.len() == V4B_HEADER_SIZE {
+        return Err(V4Error::Protocol("Bytecode file too small".to_string()));
+    }
+    header.check_size(data.len())?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(version: (u8, u8), code_size: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = V4B_MAGIC.to_vec();
+        data.extend_from_slice(&[version.0, version.1, 0, 0]);
+        data.extend_from_slice(&code_size.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let header = V4bHeader::parse(&file((0, 2), 1, &[0x51])).unwrap();
+        assert_eq!(header.version, (0, 2));
+        assert_eq!(header.code_size, 1);
+        assert_eq!(header.word_count, Some(3));
+        // v0.1 has no word count
+        let header = V4bHeader::parse(&file((0, 1), 1, &[0x51])).unwrap();
+        assert_eq!(header.word_count, None);
+
+        assert!(V4bHeader::parse(b"V4BC\x00\x02").is_err());
+        let mut data = file((0, 2), 1, &[0x51]);
+        data[0] = b'X';
+        assert!(V4bHeader::parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_validate_checks_code_size() {
+        assert!(validate(&file((0, 2), 2, &[0x76, 0x51])).is_ok());
+        let err = validate(&file((0, 2), 9, &[0x76, 0x51])).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("declares 9 bytes of code, file has 2")
+        );
+        assert!(validate(&file((0, 2), 0, &[])).is_err());
+    }
+
+    #[test]
+    fn test_incompatibility_with_vm_version() {
+        let header = V4bHeader::parse(&file((0, 2), 1, &[0x51])).unwrap();
+        assert_eq!(header.incompatibility((0, 2, 0)), None);
+        assert_eq!(header.incompatibility((0, 3, 1)), None);
+        assert_eq!(
+            header.incompatibility((0, 1, 4)).unwrap(),
+            ".v4b v0.2 needs VM v0.2 or newer, the device runs VM v0.1.4"
+        );
+        assert!(
+            header
+                .incompatibility((1, 2, 0))
+                .unwrap()
+                .contains("is for VM v0.x")
+        );
+    }
+}
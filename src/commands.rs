@@ -1,13 +1,64 @@
+//! Command implementations behind the `v4` subcommands
+//!
+//! One-shot commands return structured reports and leave printing to the
+//! caller. The REPL is interactive and talks to the terminal directly.
+
+pub mod bench;
+pub mod build;
 pub mod compile;
+pub mod compiled;
+pub mod config;
+pub mod disasm;
+pub mod dump;
 pub mod exec;
+pub mod fanout;
+pub mod flash;
+pub mod gpio;
+pub mod info;
+pub mod inspect;
+pub mod learn;
+pub mod lsp;
+pub mod memdiff;
+pub mod monitor;
+pub mod new;
 pub mod ping;
+pub mod ports;
+pub mod pull;
 pub mod push;
 pub mod repl;
 pub mod reset;
+pub mod run;
+pub mod script;
+pub mod size;
+pub mod stack;
+pub mod test;
 
+pub use bench::bench;
+pub use build::{build, deploy};
 pub use compile::compile;
+pub use config::{config_get, config_list, config_set};
+pub use disasm::disasm;
+pub use dump::dump;
 pub use exec::exec;
+pub use flash::flash;
+pub use gpio::gpio;
+pub use info::info;
+pub use inspect::inspect;
+pub use learn::run_learn;
+pub use lsp::lsp;
+pub use memdiff::memdiff;
+pub use monitor::{monitor, monitor_raw};
+pub use new::{init, new_project};
 pub use ping::ping;
-pub use push::push;
-pub use repl::run_repl;
+pub use ports::list_ports;
+pub use pull::pull;
+pub use push::{push, push_file};
+pub use repl::{
+    PipeSummary, ReplaySummary, pipe_loop, repl_loop, replay_loop, run_pipe, run_repl, run_replay,
+};
 pub use reset::reset;
+pub use run::run;
+pub use script::run_script;
+pub use size::size;
+pub use stack::stack;
+pub use test::run_test;
@@ -0,0 +1,296 @@
+//! `v4 bench`: link latency and throughput measurements
+//!
+//! Three phases, each optional:
+//!
+//! - PING round trips, summarized as min/avg/p99/max latency
+//! - EXEC of payloads of several sizes, timed per EXEC
+//! - one large chunked EXEC for the sustained transfer rate
+//!
+//! The EXEC payloads are jumps over zero filler ending in RET, so the VM
+//! does the same trivial work whatever the size and the time is spent on
+//! the link.
+
+use crate::device::V4Device;
+use crate::protocol::ErrorCode;
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use std::time::{Duration, Instant};
+
+/// Smallest EXEC payload: `JMP +0` and `RET`
+pub const MIN_PAYLOAD_SIZE: usize = 4;
+
+/// JMP opcode and the longest forward jump its i16 offset allows
+const JMP: u8 = 0x40;
+const RET: u8 = 0x51;
+const MAX_JUMP: usize = i16::MAX as usize;
+
+/// What to measure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// PINGs to time (0 skips the latency phase)
+    pub pings: usize,
+    /// EXEC payload sizes in bytes
+    pub sizes: Vec<usize>,
+    /// EXECs per payload size (0 skips the throughput phase)
+    pub rounds: usize,
+    /// Size of the sustained transfer (0 skips it)
+    pub transfer_size: usize,
+}
+
+/// PING round-trip times
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    /// PINGs answered
+    pub samples: usize,
+    /// PINGs that timed out
+    pub lost: usize,
+    pub min: Duration,
+    pub avg: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Summarize round-trip times, `None` if there are none
+    pub fn from_samples(samples: &[Duration], lost: usize) -> Option<Self> {
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+        let (&min, &max) = (sorted.first()?, sorted.last()?);
+        // Nearest-rank percentile
+        let p99 = sorted[(sorted.len() * 99).div_ceil(100) - 1];
+        let avg = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+        Some(Self {
+            samples: sorted.len(),
+            lost,
+            min,
+            avg,
+            p99,
+            max,
+        })
+    }
+}
+
+/// EXEC timing for one payload size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThroughputSample {
+    pub size: usize,
+    pub rounds: usize,
+    /// Average time from sending EXEC to its response
+    pub avg: Duration,
+    pub bytes_per_sec: f64,
+}
+
+/// One large transfer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferStats {
+    pub size: usize,
+    pub elapsed: Duration,
+    pub bytes_per_sec: f64,
+}
+
+/// Everything `v4 bench` measured
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BenchReport {
+    pub latency: Option<LatencyStats>,
+    pub exec: Vec<ThroughputSample>,
+    pub transfer: Option<TransferStats>,
+}
+
+/// Run the benchmark phases selected by `options`
+///
+/// Lost PINGs are counted; a failed EXEC ends the benchmark with an error.
+pub fn bench(
+    device: &mut V4Device,
+    options: &BenchOptions,
+    timeout: Duration,
+) -> Result<BenchReport> {
+    if let Some(&size) = options
+        .sizes
+        .iter()
+        .chain(Some(&options.transfer_size).filter(|&&s| s > 0))
+        .find(|&&size| size < MIN_PAYLOAD_SIZE)
+    {
+        return Err(V4Error::Cli(format!(
+            "Payload size {} is below the minimum of {} bytes",
+            size, MIN_PAYLOAD_SIZE
+        )));
+    }
+    let transport = device.transport();
+    let mut report = BenchReport::default();
+
+    if options.pings > 0 {
+        log::info!("Timing {} PING(s)", options.pings);
+        let (mut samples, mut lost) = (Vec::with_capacity(options.pings), 0);
+        for _ in 0..options.pings {
+            let start = Instant::now();
+            match transport.ping(timeout) {
+                Ok(ErrorCode::Ok) => samples.push(start.elapsed()),
+                Ok(code) => {
+                    return Err(V4Error::Device(format!("PING failed: {}", code.name())));
+                }
+                Err(V4Error::Timeout) => lost += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        report.latency = LatencyStats::from_samples(&samples, lost);
+    }
+
+    for &size in &options.sizes {
+        log::info!("Timing {} EXEC(s) of {} bytes", options.rounds, size);
+        let program = skip_program(size);
+        let mut total = Duration::ZERO;
+        for _ in 0..options.rounds {
+            total += timed_exec(transport, &program, timeout)?;
+        }
+        if options.rounds > 0 {
+            report.exec.push(ThroughputSample {
+                size,
+                rounds: options.rounds,
+                avg: total / options.rounds as u32,
+                bytes_per_sec: rate(size * options.rounds, total),
+            });
+        }
+    }
+
+    if options.transfer_size > 0 {
+        log::info!("Sending {} bytes in one EXEC", options.transfer_size);
+        let program = skip_program(options.transfer_size);
+        let elapsed = timed_exec(transport, &program, timeout)?;
+        report.transfer = Some(TransferStats {
+            size: options.transfer_size,
+            elapsed,
+            bytes_per_sec: rate(options.transfer_size, elapsed),
+        });
+    }
+
+    Ok(report)
+}
+
+/// Send one EXEC and return how long it took
+fn timed_exec(
+    transport: &mut dyn Transport,
+    program: &[u8],
+    timeout: Duration,
+) -> Result<Duration> {
+    let start = Instant::now();
+    let response = transport.exec(program, timeout)?;
+    if response.error_code != ErrorCode::Ok {
+        return Err(V4Error::Device(format!(
+            "EXEC of {} bytes failed: {}",
+            program.len(),
+            response.error_code.name()
+        )));
+    }
+    Ok(start.elapsed())
+}
+
+fn rate(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Bytecode of exactly `size` bytes that jumps over its filler and returns
+///
+/// Filler longer than one jump can cover is split into several jumps.
+/// `size` must be at least [`MIN_PAYLOAD_SIZE`].
+fn skip_program(size: usize) -> Vec<u8> {
+    let body = size - 1;
+    let blocks = body.div_ceil(3 + MAX_JUMP);
+    let mut program = Vec::with_capacity(size);
+    for i in 0..blocks {
+        // Spread the remainder so every block holds at least its JMP
+        let len = body / blocks + usize::from(i < body % blocks);
+        let skip = (len - 3) as u16;
+        program.push(JMP);
+        program.extend_from_slice(&skip.to_le_bytes());
+        program.resize(program.len() + len - 3, 0);
+    }
+    program.push(RET);
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn test_skip_program() {
+        assert_eq!(skip_program(4), vec![JMP, 0, 0, RET]);
+        assert_eq!(skip_program(6), vec![JMP, 2, 0, 0, 0, RET]);
+
+        // Follow the jumps the way the VM would
+        let long = skip_program(70_000);
+        assert_eq!(long.len(), 70_000);
+        let (mut pc, mut jumps) = (0, 0);
+        while long[pc] == JMP {
+            pc += 3 + u16::from_le_bytes([long[pc + 1], long[pc + 2]]) as usize;
+            jumps += 1;
+        }
+        assert_eq!(jumps, 3);
+        assert_eq!(pc, long.len() - 1);
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        let stats = LatencyStats::from_samples(&samples, 2).unwrap();
+        assert_eq!(stats.samples, 100);
+        assert_eq!(stats.lost, 2);
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.p99, Duration::from_millis(99));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert_eq!(stats.avg, Duration::from_micros(50_500));
+        assert_eq!(LatencyStats::from_samples(&[], 3), None);
+    }
+
+    #[test]
+    fn test_bench_counts_lost_pings_and_stops_on_failed_exec() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_timeout();
+        transport.push_response(ErrorCode::BufferFull, &[]);
+        let mut device = V4Device::from_transport(Box::new(transport), "mock");
+
+        let options = BenchOptions {
+            pings: 2,
+            sizes: vec![16],
+            rounds: 1,
+            transfer_size: 0,
+        };
+        let err = bench(&mut device, &options, TIMEOUT).unwrap_err();
+        assert!(err.to_string().contains("BUFFER_FULL"), "{}", err);
+    }
+
+    #[test]
+    fn test_bench_reports_each_phase() {
+        // Every PING, EXEC and chunk is acknowledged
+        let mut transport = MockTransport::new();
+        for _ in 0..200 {
+            transport.push_response(ErrorCode::Ok, &[]);
+        }
+        let mut device = V4Device::from_transport(Box::new(transport), "mock");
+        let options = BenchOptions {
+            pings: 3,
+            sizes: vec![4, 600],
+            rounds: 2,
+            transfer_size: 40_000,
+        };
+        let report = bench(&mut device, &options, TIMEOUT).unwrap();
+
+        assert_eq!(report.latency.unwrap().samples, 3);
+        assert_eq!(report.exec.len(), 2);
+        assert_eq!(report.exec[1].size, 600);
+        assert_eq!(report.transfer.unwrap().size, 40_000);
+
+        let too_small = BenchOptions {
+            sizes: vec![2],
+            ..options
+        };
+        assert!(matches!(
+            bench(&mut device, &too_small, TIMEOUT),
+            Err(V4Error::Cli(_))
+        ));
+    }
+}
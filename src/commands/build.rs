@@ -0,0 +1,140 @@
+use crate::Result;
+use crate::commands::compile::{self, CompileOptions, CompileReport};
+use crate::device::{PushReport, ResetReport, V4Device};
+use crate::project::deps::{self, Package};
+use crate::project::{Hook, Project};
+use crate::serial::SerialSettings;
+use crate::transport::RetryPolicy;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Result of `v4 build`
+#[derive(Debug)]
+pub struct BuildReport {
+    /// Dependencies compiled before the project, in order
+    pub dependencies: Vec<Package>,
+    /// Every source file compiled, in order
+    pub inputs: Vec<PathBuf>,
+    pub compile: CompileReport,
+}
+
+/// Result of `v4 deploy` after the build
+#[derive(Debug)]
+pub struct DeployReport {
+    /// Set if the VM was reset before the push
+    pub reset: Option<ResetReport>,
+    pub push: PushReport,
+}
+
+/// Compile the project's dependencies and sources into its bytecode file
+///
+/// Dependencies come first (see [`deps::resolve`]), then the sources in
+/// manifest order. `[constants]` are defined ahead of everything, overridden
+/// by same-named `options.defines`. Runs the `pre_build` and `post_build`
+/// hooks around the compile.
+pub fn build(project: &Project, options: &CompileOptions) -> Result<BuildReport> {
+    project.run_hook(Hook::PreBuild)?;
+
+    let dependencies = deps::resolve(project, false)?;
+    let inputs: Vec<PathBuf> = dependencies
+        .iter()
+        .flat_map(|package| package.sources.iter().cloned())
+        .chain(project.manifest.sources().iter().map(|s| project.path(s)))
+        .collect();
+    let output = project.path(&project.manifest.output());
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let names: Vec<String> = inputs
+        .iter()
+        .map(|input| input.to_string_lossy().into_owned())
+        .collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    // Defines from the command line override [constants]
+    let mut options = options.clone();
+    let cli_defines = std::mem::take(&mut options.defines);
+    options.defines = project.manifest.defines()?;
+    options.defines.extend(cli_defines);
+    let compile = compile::compile(&names, Some(&output.to_string_lossy()), &options)?;
+
+    project.run_hook(Hook::PostBuild)?;
+    Ok(BuildReport {
+        dependencies,
+        inputs,
+        compile,
+    })
+}
+
+/// Fetch the newest commits of git dependencies and rewrite `v4.lock`
+pub fn update(project: &Project) -> Result<Vec<Package>> {
+    deps::resolve(project, true)
+}
+
+/// Where and how [`deploy`] pushes the bytecode
+pub struct DeployTarget<'a> {
+    pub port: Option<&'a str>,
+    pub settings: &'a SerialSettings,
+    pub retry: RetryPolicy,
+    pub timeout: Duration,
+    /// Reset the VM first, waiting this long for it to answer PING again
+    pub reset: Option<Duration>,
+}
+
+/// Push a built project to the device, resetting the VM first if asked
+///
+/// Runs the `pre_deploy` and `post_deploy` hooks around the push.
+/// `on_progress` receives the bytes acknowledged so far and the total size.
+pub fn deploy(
+    project: &Project,
+    build: &CompileReport,
+    target: &DeployTarget,
+    on_progress: &mut dyn FnMut(usize, usize),
+) -> Result<DeployReport> {
+    let bytecode = fs::read(&build.output)?;
+    project.run_hook(Hook::PreDeploy)?;
+
+    let mut device = V4Device::open(target.port, target.settings)?.with_retry(target.retry)?;
+    let reset = match target.reset {
+        Some(ready_timeout) => Some(device.reset(target.timeout, ready_timeout)?),
+        None => None,
+    };
+    let total = bytecode.len();
+    let push = device.push(&bytecode, target.timeout, &mut |sent| {
+        on_progress(sent, total)
+    })?;
+
+    project.run_hook(Hook::PostDeploy)?;
+    Ok(DeployReport { reset, push })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{self, Template};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_build_runs_hooks_and_compiles_sources_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = Template {
+            name: "gen".to_string(),
+            ..Template::default()
+        };
+        project::scaffold(dir.path(), &template).unwrap();
+        let mut project = Project::load(dir.path()).unwrap();
+        let manifest = &mut project.manifest;
+        manifest.build.sources = vec!["src/consts.v4".into(), "src/main.v4".into()];
+        manifest.hooks.pre_build = Some("echo ': SEVEN 7 ;' > src/consts.v4".into());
+        manifest.hooks.post_build = Some("cp \"$V4_OUTPUT\" copy.v4b".into());
+        fs::write(dir.path().join("src/main.v4"), "SEVEN DUP *\n").unwrap();
+
+        let report = build(&project, &CompileOptions::default()).unwrap().compile;
+        assert_eq!(report.output, dir.path().join("target/gen.v4b"));
+        assert_eq!(report.source_size, ": SEVEN 7 ;\nSEVEN DUP *\n".len());
+        assert_eq!(
+            fs::read(dir.path().join("copy.v4b")).unwrap(),
+            fs::read(&report.output).unwrap()
+        );
+    }
+}
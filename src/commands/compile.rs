@@ -1,49 +1,822 @@
-use crate::Result;
-use crate::v4front_ffi;
+use crate::emit::{self, Emit};
+use crate::ffi::{CompileError, CompiledBuffer, Compiler, Optimization};
+use crate::listing;
+use crate::repl::{ShadowedWord, find_shadowed_words};
+use crate::source::{self, Define, Loaded};
+use crate::sourcemap::SourceMap;
+use crate::{Result, V4Error};
+use serde::Serialize;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-/// Compile Forth source to V4 bytecode
-pub fn compile(input: &str, output: Option<&str>) -> Result<()> {
-    // Read source file
-    let input_path = Path::new(input);
-    if !input_path.exists() {
-        return Err(crate::V4Error::Io(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("Source file not found: {}", input),
-        )));
+/// Result of compiling a source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileReport {
+    /// Source size in bytes
+    pub source_size: usize,
+    /// Written bytecode file, in the `--emit` format
+    pub output: PathBuf,
+    /// Size of the written file in bytes
+    pub output_size: u64,
+    /// Words defined more than once (the last definition wins)
+    pub shadowed: Vec<ShadowedWord>,
+    /// Written listing file
+    pub listing: Option<PathBuf>,
+    /// Bytecode size without and with optimization, if any pass ran
+    pub optimized: Option<SizeChange>,
+    /// Bytecode size and the `--max-size` it stayed within
+    pub budget: Option<Budget>,
+    /// Written source map
+    pub source_map: Option<PathBuf>,
+}
+
+/// Bytecode size measured against a budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub used: usize,
+    pub max: usize,
+}
+
+/// Bytecode bytes per word, for finding what eats the dictionary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeBreakdown {
+    /// Word names and their bytecode size, in definition order
+    pub words: Vec<(String, usize)>,
+    /// Size of the main code
+    pub main: usize,
+}
+
+impl SizeBreakdown {
+    /// Sizes of the words and main code in `buf`
+    pub fn of(buf: &CompiledBuffer) -> Self {
+        Self {
+            words: buf
+                .words()
+                .map(|word| (word.name.into_owned(), word.code.len()))
+                .collect(),
+            main: buf.bytecode().len(),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.words.iter().map(|(_, size)| size).sum::<usize>() + self.main
+    }
+
+    /// Rows of the table, largest first, with the main code as `<main>`
+    pub fn rows(&self) -> Vec<(&str, usize)> {
+        let mut rows: Vec<(&str, usize)> = self
+            .words
+            .iter()
+            .map(|(name, size)| (name.as_str(), *size))
+            .chain((self.main > 0).then_some(("<main>", self.main)))
+            .collect();
+        rows.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+        rows
+    }
+}
+
+impl fmt::Display for SizeBreakdown {
+    /// Size table: bytes, share of the total and name per row, then the total
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        let share = |size: usize| match total {
+            0 => 0.0,
+            _ => size as f64 * 100.0 / total as f64,
+        };
+        writeln!(f, "{:>7} {:>6}  Word", "Bytes", "%")?;
+        for (name, size) in self.rows() {
+            writeln!(f, "{:>7} {:>5.1}%  {}", size, share(size), name)?;
+        }
+        write!(f, "{:>7} {:>5.1}%  total", total, share(total))
+    }
+}
+
+/// Bytecode size (words plus main code) before and after a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeChange {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// Options shared by [`compile`] and [`compile_to_bytes`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileOptions {
+    /// Fail instead of reporting words defined more than once
+    pub deny_shadowing: bool,
+    /// Also write an annotated listing here (see [`listing::listing`])
+    pub listing: Option<PathBuf>,
+    /// Constants defined ahead of the source (see [`source::defines_prelude`])
+    pub defines: Vec<Define>,
+    /// Optimization passes to run
+    pub optimization: Optimization,
+    /// Fail when the bytecode (words plus main code) exceeds this many bytes
+    pub max_size: Option<usize>,
+    /// Also write a source map next to the output (see [`SourceMap::path_for`])
+    pub source_map: bool,
+    /// Output format
+    pub emit: Emit,
+    /// Array name for C and Rust output, [`emit::DEFAULT_SYMBOL`] if unset
+    pub symbol: Option<String>,
+}
+
+/// How serious a [`Diagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Compiler message tied to a source position, for `compile --check`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub file: String,
+    /// 1-based line, if known
+    pub line: Option<usize>,
+    /// 1-based column, if known
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    /// `file:line:column: severity: message`, leaving out unknown positions
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, ": {}: {}", severity, self.message)
     }
+}
+
+/// Result of `compile --check`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CheckReport {
+    pub diagnostics: Vec<Diagnostic>,
+}
 
-    let source = fs::read_to_string(input_path)?;
-    println!("Compiling {} ({} bytes)...", input, source.len());
+impl CheckReport {
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+}
+
+/// Input name that reads source from stdin
+pub const STDIN: &str = "-";
+
+/// Read and concatenate source inputs in order
+///
+/// `-` reads stdin. Each input is loaded with [`source::load`]: its shebang
+/// line is blanked and `INCLUDE "file"` lines are expanded. Every input ends
+/// with a newline so definitions can't run into the next file.
+pub fn read_sources(inputs: &[&str]) -> Result<String> {
+    load_sources(inputs).map(|loaded| loaded.text)
+}
 
+/// Like [`read_sources`], keeping track of where each line came from
+pub fn load_sources(inputs: &[&str]) -> Result<Loaded> {
+    if inputs.is_empty() {
+        return Err(V4Error::Cli("No input files".to_string()));
+    }
+    if inputs.iter().filter(|&&i| i == STDIN).count() > 1 {
+        return Err(V4Error::Cli(
+            "stdin ('-') can only be read once".to_string(),
+        ));
+    }
+
+    let mut combined = Loaded::default();
+    for &input in inputs {
+        let loaded = if input == STDIN {
+            let text = std::io::read_to_string(std::io::stdin())?;
+            source::expand_includes(&text, &std::env::current_dir()?)?
+        } else {
+            let path = Path::new(input);
+            if !path.exists() {
+                return Err(V4Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Source file not found: {}", input),
+                )));
+            }
+            source::load(path)?
+        };
+
+        combined.append(loaded);
+    }
+    Ok(combined)
+}
+
+/// [`load_sources`] preceded by the constants of `options`
+fn load_with_defines(inputs: &[&str], options: &CompileOptions) -> Result<Loaded> {
+    let mut loaded = source::defines_prelude(&options.defines);
+    loaded.append(load_sources(inputs)?);
+    Ok(loaded)
+}
+
+/// Compile without writing anything and collect diagnostics
+///
+/// V4-front stops at the first error, so there is at most one error from
+/// the compiler. Words defined more than once are warnings, or errors with
+/// `deny_shadowing`. Unreadable inputs are still returned as `Err`.
+pub fn check(inputs: &[&str], options: &CompileOptions) -> Result<CheckReport> {
+    let loaded = load_with_defines(inputs, options)?;
+    let compiled = Compiler::compile_standalone(&loaded.text).map(|buf| buf.word_names());
+    Ok(CheckReport {
+        diagnostics: source_diagnostics(&loaded, compiled, options.deny_shadowing),
+    })
+}
+
+/// Diagnostics for a compile of `source`: the error, or shadowed words
+///
+/// `compiled` holds the names of the words defined, in definition order.
+pub(crate) fn source_diagnostics(
+    source: &Loaded,
+    compiled: std::result::Result<Vec<String>, CompileError>,
+    deny_shadowing: bool,
+) -> Vec<Diagnostic> {
+    let names = match compiled {
+        Ok(names) => names,
+        Err(error) => return vec![error_diagnostic(source, &error)],
+    };
+
+    let severity = match deny_shadowing {
+        true => Severity::Error,
+        false => Severity::Warning,
+    };
+    find_shadowed_words(names.iter().map(String::as_str))
+        .into_iter()
+        .map(|word| {
+            let (line, column) = locate_definition(&source.text, &word.name, word.count)
+                .map_or((None, None), |(line, column)| (Some(line), Some(column)));
+            diagnostic(
+                source,
+                line,
+                column,
+                severity,
+                format!(
+                    "'{}' is defined {} times; the last definition wins",
+                    word.name, word.count
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Compile Forth source to V4 bytecode
+///
+/// Inputs are concatenated in order (e.g. a library, then the app). The
+/// output defaults to the first input with the extension of the `emit`
+/// format, .v4b unless another is chosen. Words defined
+/// more than once are listed in the report, or fail the compilation when
+/// `deny_shadowing` is set.
+pub fn compile(
+    inputs: &[&str],
+    output: Option<&str>,
+    options: &CompileOptions,
+) -> Result<CompileReport> {
     // Determine output filename
-    let output_path = if let Some(out) = output {
-        Path::new(out).to_path_buf()
-    } else {
-        // Default: replace .v4 extension with .v4b
-        let mut out = input_path.to_path_buf();
-        out.set_extension("v4b");
-        out
+    let output_path = match (output, inputs.first().copied().unwrap_or_default()) {
+        (Some(out), _) => PathBuf::from(out),
+        (None, STDIN) => {
+            return Err(V4Error::Cli(
+                "Output path required when reading stdin (use --output or --stdout)".to_string(),
+            ));
+        }
+        (None, input) => {
+            // Default: replace .v4 extension with .v4b (or .h, .hex, ...)
+            let mut out = PathBuf::from(input);
+            out.set_extension(options.emit.extension());
+            out
+        }
     };
 
-    // Compile source code
-    let buf = v4front_ffi::compile_source(&source).map_err(crate::V4Error::Protocol)?;
+    if options.source_map && options.optimization.is_enabled() {
+        return Err(V4Error::Cli(
+            "Source maps describe unoptimized code; leave out -O and --tail-call".to_string(),
+        ));
+    }
 
-    println!("✓ Compilation successful");
+    let source = load_with_defines(inputs, options)?;
+    let (shadowed, optimized, budget) = compile_to_file(&source, &output_path, options)?;
+    let output_size = fs::metadata(&output_path)?.len();
+    write_listing(&source.text, options)?;
+    let source_map = match options.source_map {
+        true => {
+            let path = SourceMap::path_for(&output_path);
+            let mut compiler = Compiler::new().map_err(V4Error::Compilation)?;
+            SourceMap::build(&mut compiler, &source)?.save(&path)?;
+            Some(path)
+        }
+        false => None,
+    };
 
-    // Save bytecode to file
-    v4front_ffi::save_bytecode(&buf, &output_path).map_err(crate::V4Error::Protocol)?;
+    Ok(CompileReport {
+        source_size: source.text.len(),
+        output: output_path,
+        output_size,
+        shadowed,
+        listing: options.listing.clone(),
+        optimized,
+        budget,
+        source_map,
+    })
+}
 
-    // Free the buffer
-    v4front_ffi::free_bytecode(buf);
+/// Compile to bytes in the `emit` format in memory, e.g. to pipe them elsewhere
+///
+/// Returns the bytecode and any shadowed words, see [`compile`].
+pub fn compile_to_bytes(
+    inputs: &[&str],
+    options: &CompileOptions,
+) -> Result<(Vec<u8>, Vec<ShadowedWord>)> {
+    let source = load_with_defines(inputs, options)?;
 
-    let output_size = fs::metadata(&output_path)?.len();
-    println!(
-        "✓ Bytecode saved to {} ({} bytes)",
-        output_path.display(),
-        output_size
-    );
+    // V4-front only writes .v4b files; go through a temporary one
+    let temp = std::env::temp_dir().join(format!(
+        "v4-compile-{}.{}",
+        std::process::id(),
+        options.emit.extension()
+    ));
+    let result = compile_to_file(&source, &temp, options)
+        .and_then(|(shadowed, _, _)| Ok((fs::read(&temp)?, shadowed)));
+    let _ = fs::remove_file(&temp);
+    write_listing(&source.text, options)?;
+    result
+}
 
+/// Write the listing file if one was requested
+fn write_listing(source: &str, options: &CompileOptions) -> Result<()> {
+    if let Some(path) = &options.listing {
+        fs::write(path, listing::listing(source)?)?;
+    }
     Ok(())
 }
+
+/// Compile source and save the .v4b file
+///
+/// With optimization on, the source is also compiled without it to report
+/// the size difference. Nothing is written when the bytecode is over
+/// `max_size`; the error lists the size of each word.
+fn compile_to_file(
+    source: &Loaded,
+    path: &Path,
+    options: &CompileOptions,
+) -> Result<(Vec<ShadowedWord>, Option<SizeChange>, Option<Budget>)> {
+    // Compile source code, pointing errors at the file they came from
+    let placed = |error: CompileError| V4Error::Compilation(placed_message(source, &error));
+    let optimization = &options.optimization;
+    let (buf, optimized) = if optimization.is_enabled() {
+        let buf = Compiler::compile_optimized(&source.text, optimization).map_err(placed)?;
+        let before = Compiler::compile_standalone(&source.text)
+            .map_err(placed)?
+            .code_size();
+        let sizes = SizeChange {
+            before,
+            after: buf.code_size(),
+        };
+        (buf, Some(sizes))
+    } else {
+        (
+            Compiler::compile_standalone(&source.text).map_err(placed)?,
+            None,
+        )
+    };
+
+    // Check for accidental redefinitions across all inputs
+    let shadowed = shadowed_words(source, &buf.word_names());
+    if options.deny_shadowing && !shadowed.is_empty() {
+        let names: Vec<&str> = shadowed.iter().map(|w| w.name.as_str()).collect();
+        return Err(V4Error::Compilation(format!(
+            "Shadowed word definitions: {} (--deny-shadowing)",
+            names.join(", ")
+        )));
+    }
+
+    let budget = options
+        .max_size
+        .map(|max| check_budget(&buf, max))
+        .transpose()?;
+
+    match options.emit {
+        Emit::V4b => buf.save(path).map_err(V4Error::Protocol)?,
+        format => {
+            let words: Vec<_> = buf.words().collect();
+            let symbol = options.symbol.as_deref().unwrap_or(emit::DEFAULT_SYMBOL);
+            let bytes = emit::emit(
+                format,
+                buf.bytecode(),
+                words.iter().map(|word| (word.name.as_ref(), word.code)),
+                symbol,
+            )?;
+            fs::write(path, bytes)?;
+        }
+    }
+
+    Ok((shadowed, optimized, budget))
+}
+
+/// Fail with a per-word size table if `buf` is bigger than `max` bytes
+fn check_budget(buf: &CompiledBuffer, max: usize) -> Result<Budget> {
+    let breakdown = SizeBreakdown::of(buf);
+    let used = breakdown.total();
+    if used > max {
+        return Err(V4Error::Compilation(format!(
+            "Bytecode is {} bytes, {} over the --max-size budget of {}\n{}",
+            used,
+            used - max,
+            max,
+            breakdown
+        )));
+    }
+    Ok(Budget { used, max })
+}
+
+/// Compile error message prefixed with the `file:line` it points at, if known
+pub(crate) fn placed_message(source: &Loaded, error: &CompileError) -> String {
+    let diagnostic = error_diagnostic(source, error);
+    match diagnostic.line {
+        Some(_) => format!("{}: {}", location(&diagnostic), diagnostic.message),
+        None => diagnostic.message,
+    }
+}
+
+/// Words defined more than once, each with the place of its winning definition
+fn shadowed_words(source: &Loaded, names: &[String]) -> Vec<ShadowedWord> {
+    let mut shadowed = find_shadowed_words(names.iter().map(String::as_str));
+    for word in &mut shadowed {
+        word.winner = locate_definition(&source.text, &word.name, word.count)
+            .map(|(line, _)| diagnostic(source, Some(line), None, Severity::Warning, String::new()))
+            .filter(|diagnostic| diagnostic.line.is_some())
+            .map(|diagnostic| location(&diagnostic));
+    }
+    shadowed
+}
+
+/// Diagnostic for a V4-front error, mapped back to the input file
+///
+/// Without a position from V4-front, a quoted or trailing word in the
+/// message (`Unknown word: FOO`) is looked up in the source instead.
+fn error_diagnostic(source: &Loaded, error: &CompileError) -> Diagnostic {
+    let (line, column) = match error.line {
+        Some(line) => (Some(line), error.column),
+        None => offending_word(&error.message)
+            .and_then(|word| locate_word(&source.text, word))
+            .map_or((None, None), |(line, column)| (Some(line), Some(column))),
+    };
+    diagnostic(source, line, column, Severity::Error, error.message.clone())
+}
+
+/// Diagnostic at a line of the combined source, translated to its origin
+fn diagnostic(
+    source: &Loaded,
+    line: Option<usize>,
+    column: Option<usize>,
+    severity: Severity,
+    message: String,
+) -> Diagnostic {
+    let origin = line.and_then(|line| source.origin(line));
+    let file = match (origin, source.files.first()) {
+        (Some(origin), _) => origin.file.display().to_string(),
+        (None, Some(file)) => file.display().to_string(),
+        (None, None) => source::STDIN_NAME.to_string(),
+    };
+    Diagnostic {
+        file,
+        line: origin.map(|o| o.line),
+        column: origin.and(column),
+        severity,
+        message,
+    }
+}
+
+/// `file:line[:column]` of a placed diagnostic
+fn location(diagnostic: &Diagnostic) -> String {
+    let mut location = diagnostic.file.clone();
+    for n in [diagnostic.line, diagnostic.column].into_iter().flatten() {
+        location.push_str(&format!(":{}", n));
+    }
+    location
+}
+
+/// Word an error message complains about: `'FOO'` or a trailing `: FOO`
+fn offending_word(message: &str) -> Option<&str> {
+    let quoted = message
+        .split('\'')
+        .nth(1)
+        .filter(|word| !word.is_empty() && !word.contains(char::is_whitespace));
+    quoted.or_else(|| {
+        let (_, word) = message.rsplit_once(": ")?;
+        let word = word.trim();
+        (!word.is_empty() && !word.contains(char::is_whitespace)).then_some(word)
+    })
+}
+
+/// 1-based line and column of the first use of `word`, outside `\` comments
+fn locate_word(text: &str, word: &str) -> Option<(usize, usize)> {
+    tokens(text)
+        .find(|(_, _, token)| token.eq_ignore_ascii_case(word))
+        .map(|(line, column, _)| (line, column))
+}
+
+/// Position of the `nth` (1-based) `: word` definition
+fn locate_definition(text: &str, word: &str, nth: usize) -> Option<(usize, usize)> {
+    let mut tokens = tokens(text).peekable();
+    let mut seen = 0;
+    while let Some((_, _, token)) = tokens.next() {
+        if token != ":" {
+            continue;
+        }
+        match tokens.peek() {
+            Some(&(line, column, name)) if name.eq_ignore_ascii_case(word) => {
+                seen += 1;
+                if seen == nth {
+                    return Some((line, column));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whitespace-separated tokens with 1-based line and column, skipping `\` comments
+pub(crate) fn tokens(text: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    text.lines().enumerate().flat_map(|(line_no, line)| {
+        let mut column = 0;
+        line.split(' ')
+            .flat_map(|part| part.split('\t'))
+            .map(move |token| {
+                let start = column;
+                column += token.chars().count() + 1;
+                (line_no + 1, start + 1, token)
+            })
+            .filter(|(_, _, token)| !token.is_empty())
+            .take_while(|(_, _, token)| *token != "\\")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn source_file(text: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "{}", text).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_sources_concatenates_in_order() {
+        let lib = source_file("#!/usr/bin/env v4\n: SQ DUP * ;");
+        let app = source_file("3 SQ\n");
+        let inputs = [lib.path().to_str().unwrap(), app.path().to_str().unwrap()];
+
+        assert_eq!(read_sources(&inputs).unwrap(), "\n: SQ DUP * ;\n3 SQ\n");
+    }
+
+    #[test]
+    fn test_read_sources_errors() {
+        assert!(read_sources(&[]).is_err());
+        assert!(read_sources(&[STDIN, STDIN]).is_err());
+        assert!(read_sources(&["/nonexistent/app.v4"]).is_err());
+    }
+
+    #[test]
+    fn test_stdin_needs_output() {
+        let err = compile(&[STDIN], None, &CompileOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("--stdout"), "{}", err);
+    }
+
+    #[test]
+    fn test_compile_writes_listing() {
+        let app = source_file(": SQ DUP * ;\n3 SQ\n");
+        let dir = tempfile::tempdir().unwrap();
+        let options = CompileOptions {
+            listing: Some(dir.path().join("app.lst")),
+            ..CompileOptions::default()
+        };
+        let output = dir.path().join("app.v4b");
+
+        let report = compile(&[app.path().to_str().unwrap()], output.to_str(), &options).unwrap();
+        let listing = fs::read_to_string(report.listing.unwrap()).unwrap();
+        assert!(listing.starts_with("    1  : SQ DUP * ;\n"), "{}", listing);
+        assert_eq!(report.optimized, None);
+    }
+
+    #[test]
+    fn test_compile_emits_c_array_next_to_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("blink.v4");
+        fs::write(&input, ": SQ DUP * ;\n3 SQ\n").unwrap();
+        let options = CompileOptions {
+            emit: Emit::CArray,
+            symbol: Some("blink".to_string()),
+            ..CompileOptions::default()
+        };
+
+        let report = compile(&[input.to_str().unwrap()], None, &options).unwrap();
+        assert_eq!(report.output, dir.path().join("blink.h"));
+        let c = fs::read_to_string(&report.output).unwrap();
+        assert!(c.contains("const uint8_t blink[] = {\n    0x56, 0x34, 0x42, 0x43,"));
+
+        let raw = CompileOptions {
+            emit: Emit::Raw,
+            ..CompileOptions::default()
+        };
+        assert!(compile(&[input.to_str().unwrap()], None, &raw).is_err());
+    }
+
+    #[cfg(feature = "optimizer")]
+    #[test]
+    fn test_compile_reports_optimized_size() {
+        let app = source_file(": SQ DUP * ;\n3 SQ\n");
+        let dir = tempfile::tempdir().unwrap();
+        let options = CompileOptions {
+            optimization: Optimization {
+                level: 2,
+                ..Optimization::default()
+            },
+            ..CompileOptions::default()
+        };
+        let output = dir.path().join("app.v4b");
+
+        let report = compile(&[app.path().to_str().unwrap()], output.to_str(), &options).unwrap();
+        let sizes = report.optimized.unwrap();
+        assert!(
+            sizes.after > 0 && sizes.after <= sizes.before,
+            "{:?}",
+            sizes
+        );
+    }
+
+    #[test]
+    fn test_max_size_fails_with_word_sizes() {
+        let app = source_file(": SQ DUP * ;\n: CUBE DUP SQ * ;\n3 CUBE\n");
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("app.v4b");
+        let inputs = [app.path().to_str().unwrap()];
+
+        let fits = CompileOptions {
+            max_size: Some(1000),
+            ..CompileOptions::default()
+        };
+        let report = compile(&inputs, output.to_str(), &fits).unwrap();
+        let used = report.budget.unwrap().used;
+        assert!(used > 0);
+        fs::remove_file(&output).unwrap();
+
+        let tight = CompileOptions {
+            max_size: Some(used - 1),
+            ..CompileOptions::default()
+        };
+        let err = compile(&inputs, output.to_str(), &tight)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("1 over the --max-size budget"), "{}", err);
+        assert!(
+            err.contains("  CUBE\n") && err.contains("  <main>\n"),
+            "{}",
+            err
+        );
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_size_breakdown_table() {
+        let breakdown = SizeBreakdown {
+            words: vec![("SQ".to_string(), 3), ("CUBE".to_string(), 5)],
+            main: 2,
+        };
+        assert_eq!(breakdown.total(), 10);
+        assert_eq!(breakdown.rows(), [("CUBE", 5), ("SQ", 3), ("<main>", 2)]);
+        assert_eq!(
+            breakdown.to_string(),
+            "  Bytes      %  Word\n      5  50.0%  CUBE\n      3  30.0%  SQ\n      2  20.0%  <main>\n     10 100.0%  total"
+        );
+    }
+
+    #[test]
+    fn test_compile_writes_source_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib.v4");
+        fs::write(&lib, ": SQ DUP * ;\n").unwrap();
+        let app = dir.path().join("app.v4");
+        fs::write(&app, "INCLUDE \"lib.v4\"\n\n3 SQ\n").unwrap();
+        let options = CompileOptions {
+            source_map: true,
+            ..CompileOptions::default()
+        };
+
+        let report = compile(&[app.to_str().unwrap()], None, &options).unwrap();
+        let path = report.source_map.unwrap();
+        assert_eq!(path, dir.path().join("app.v4map"));
+        let map = SourceMap::load(&path).unwrap();
+        let at = map.locate(Some("sq"), 1).unwrap();
+        assert_eq!((at.file, at.line), (lib.display().to_string(), 1));
+        let at = map.locate(None, 0).unwrap();
+        assert_eq!((at.file, at.line), (app.display().to_string(), 3));
+    }
+
+    #[test]
+    fn test_defines_precede_source() {
+        let app = source_file("GPIO_LED DUP *\n");
+        let inputs = [app.path().to_str().unwrap()];
+        let report = check(&inputs, &CompileOptions::default()).unwrap();
+        assert_eq!(report.errors(), 1);
+
+        let options = CompileOptions {
+            defines: vec![source::parse_define("GPIO_LED=2").unwrap()],
+            ..CompileOptions::default()
+        };
+        assert_eq!(check(&inputs, &options).unwrap().diagnostics, []);
+        let source = load_with_defines(&inputs, &options).unwrap();
+        assert_eq!(source.text, ": GPIO_LED 2 ;\nGPIO_LED DUP *\n");
+    }
+
+    #[test]
+    fn test_check_locates_error_in_its_file() {
+        let lib = source_file(": SQ DUP * ;\n");
+        let app = source_file("\\ app\n3 SQ\n  1 CUBE \\ CUBE is missing\n");
+        let inputs = [lib.path().to_str().unwrap(), app.path().to_str().unwrap()];
+
+        let report = check(&inputs, &CompileOptions::default()).unwrap();
+        assert_eq!(report.errors(), 1);
+        let diagnostic = &report.diagnostics[0];
+        assert_eq!(diagnostic.file, inputs[1]);
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(3), Some(5)));
+        assert_eq!(
+            diagnostic.to_string(),
+            format!("{}:3:5: error: Unknown word: CUBE", inputs[1])
+        );
+
+        // A regular compile reports the same position
+        let err = compile_to_bytes(&inputs, &CompileOptions::default()).unwrap_err();
+        assert!(
+            err.to_string().contains(":3:5: Unknown word: CUBE"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_check_reports_shadowed_words() {
+        let app = source_file(": SQ DUP * ;\n: SQ DUP ;\n3 SQ\n");
+        let inputs = [app.path().to_str().unwrap()];
+
+        let report = check(&inputs, &CompileOptions::default()).unwrap();
+        assert_eq!((report.errors(), report.warnings()), (0, 1));
+        assert_eq!(report.diagnostics[0].line, Some(2));
+        assert_eq!(report.diagnostics[0].column, Some(3));
+
+        let options = CompileOptions {
+            deny_shadowing: true,
+            ..CompileOptions::default()
+        };
+        assert_eq!(check(&inputs, &options).unwrap().errors(), 1);
+    }
+
+    #[test]
+    fn test_shadowed_words_name_the_winning_line() {
+        let app = source_file(": A 1 ;\n: FOO 1 ;\n: B 2 ;\n: C 3 ;\n\n: FOO 2 ;\n");
+        let source = source::load(app.path()).unwrap();
+        let names = ["A", "FOO", "B", "C", "FOO"].map(String::from);
+
+        let shadowed = shadowed_words(&source, &names);
+        assert_eq!(shadowed.len(), 1);
+        assert_eq!(shadowed[0].count, 2);
+        assert_eq!(
+            shadowed[0].winner,
+            Some(format!("{}:6", app.path().display()))
+        );
+    }
+
+    #[test]
+    fn test_offending_word() {
+        assert_eq!(offending_word("Unknown word: FOO"), Some("FOO"));
+        assert_eq!(
+            offending_word("undefined word 'BAR' in definition"),
+            Some("BAR")
+        );
+        assert_eq!(offending_word("Unexpected end of input"), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! Compile server for editor integration (`v4 compiled`)
+//!
+//! Checks the `.v4` files of a directory tree (like `v4 lsp`, skipping
+//! hidden directories and `target`), and each one again when it, or a file
+//! it includes, changes; [`FileWatcher`](crate::commands::exec::FileWatcher)
+//! reports the changes. One V4-front context is kept for the whole
+//! session instead of starting `v4 compile --check` on every keystroke.
+
+use crate::commands::compile::{CompileOptions, Diagnostic, Severity, source_diagnostics};
+use crate::commands::lsp::source_files;
+use crate::ffi::{CompileError, Compiler};
+use crate::source;
+use crate::{Result, V4Error};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Check result for one source file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileCheck {
+    pub file: String,
+    /// The file was deleted; its diagnostics are gone with it
+    pub removed: bool,
+    pub errors: usize,
+    pub warnings: usize,
+    /// May point into files the source includes
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl FileCheck {
+    fn new(file: &Path, diagnostics: Vec<Diagnostic>) -> Self {
+        let count = |severity| {
+            diagnostics
+                .iter()
+                .filter(|d| d.severity == severity)
+                .count()
+        };
+        Self {
+            file: file.display().to_string(),
+            removed: false,
+            errors: count(Severity::Error),
+            warnings: count(Severity::Warning),
+            diagnostics,
+        }
+    }
+}
+
+/// Diagnostics of every file, as written to the `--output` file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    pub errors: usize,
+    pub warnings: usize,
+    pub files: BTreeMap<String, Vec<Diagnostic>>,
+}
+
+/// A source file seen in the directory
+struct Tracked {
+    /// The file and everything it includes, canonicalized
+    watched: Vec<PathBuf>,
+    check: FileCheck,
+}
+
+/// Incremental checker for the `.v4` files below a directory
+pub struct CompileServer {
+    dir: PathBuf,
+    options: CompileOptions,
+    compiler: Compiler,
+    files: BTreeMap<PathBuf, Tracked>,
+}
+
+impl CompileServer {
+    /// Watch `dir`; only `defines` and `deny_shadowing` of `options` apply
+    pub fn new(dir: &Path, options: CompileOptions) -> Result<Self> {
+        if !dir.is_dir() {
+            return Err(V4Error::Cli(format!("Not a directory: {}", dir.display())));
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            options,
+            compiler: Compiler::new().map_err(V4Error::Compilation)?,
+            files: BTreeMap::new(),
+        })
+    }
+
+    /// Check the files that are new since the last call or that depend on
+    /// one of the `changed` files
+    ///
+    /// Deleted files are reported as removed. The first call checks every
+    /// file.
+    pub fn poll(&mut self, changed: &[PathBuf]) -> Vec<FileCheck> {
+        let mut present = Vec::new();
+        source_files(&self.dir, &mut present);
+        present.sort();
+        let mut checks = Vec::new();
+
+        let gone: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|path| !present.contains(path))
+            .cloned()
+            .collect();
+        for path in gone {
+            self.files.remove(&path);
+            checks.push(FileCheck {
+                removed: true,
+                ..FileCheck::new(&path, Vec::new())
+            });
+        }
+
+        for path in present {
+            if let Some(tracked) = self.files.get(&path)
+                && !tracked.watched.iter().any(|file| changed.contains(file))
+            {
+                continue;
+            }
+            let tracked = self.check(&path);
+            checks.push(tracked.check.clone());
+            self.files.insert(path, tracked);
+        }
+        checks
+    }
+
+    /// Files included from outside the directory, which need watching on
+    /// their own
+    pub fn outside_files(&self) -> Vec<PathBuf> {
+        let dir = canonical(&self.dir);
+        let mut files: Vec<PathBuf> = Vec::new();
+        for file in self.files.values().flat_map(|tracked| &tracked.watched) {
+            if !file.starts_with(&dir) && !files.contains(file) {
+                files.push(file.clone());
+            }
+        }
+        files
+    }
+
+    /// Current diagnostics of all files
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for tracked in self.files.values() {
+            snapshot.errors += tracked.check.errors;
+            snapshot.warnings += tracked.check.warnings;
+            snapshot.files.insert(
+                tracked.check.file.clone(),
+                tracked.check.diagnostics.clone(),
+            );
+        }
+        snapshot
+    }
+
+    /// Compile one file from scratch in the shared context
+    fn check(&mut self, path: &Path) -> Tracked {
+        let mut loaded = source::defines_prelude(&self.options.defines);
+        let diagnostics = match source::load(path) {
+            Ok(file) => {
+                loaded.append(file);
+                self.compiler.reset();
+                let compiled = self
+                    .compiler
+                    .compile(&loaded.text)
+                    .map(|result| result.words.into_iter().map(|word| word.name).collect())
+                    .map_err(|message| CompileError::parse(&message));
+                source_diagnostics(&loaded, compiled, self.options.deny_shadowing)
+            }
+            // E.g. a missing include; fixing it should trigger a new check
+            Err(e) => vec![Diagnostic {
+                file: path.display().to_string(),
+                line: None,
+                column: None,
+                severity: Severity::Error,
+                message: e.to_string(),
+            }],
+        };
+
+        let mut watched = vec![canonical(path)];
+        for file in loaded.files.iter().map(|file| canonical(file)) {
+            if !watched.contains(&file) {
+                watched.push(file);
+            }
+        }
+        Tracked {
+            watched,
+            check: FileCheck::new(path, diagnostics),
+        }
+    }
+}
+
+/// `path` with symlinks and `..` resolved, as file watchers report it
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Write `snapshot` to `path`, replacing it in one step
+///
+/// Editors reading the file never see it half written.
+pub fn write_snapshot(snapshot: &Snapshot, path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(snapshot).map_err(|e| V4Error::Cli(e.to_string()))?;
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, json + "\n")?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_checks_changed_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib.v4");
+        let app = dir.path().join("src").join("app.v4");
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git").join("old.v4"), "CUBE").unwrap();
+        fs::write(&lib, ": SQ DUP * ;\n").unwrap();
+        fs::write(&app, "INCLUDE \"../lib.v4\"\n3 SQ\n").unwrap();
+
+        let mut server = CompileServer::new(dir.path(), CompileOptions::default()).unwrap();
+        let checks = server.poll(&[]);
+        assert_eq!(checks.len(), 2);
+        assert!(checks.iter().all(|check| check.errors == 0));
+        assert!(server.poll(&[]).is_empty());
+        assert!(server.outside_files().is_empty());
+
+        // Breaking the library shows up in both files
+        fs::write(&lib, ": SQ DUP CUBE ;\n").unwrap();
+        let checks = server.poll(&[lib.canonicalize().unwrap()]);
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].file, lib.display().to_string());
+        let included = Path::new(&checks[1].diagnostics[0].file);
+        assert_eq!(
+            included.canonicalize().unwrap(),
+            lib.canonicalize().unwrap()
+        );
+        assert_eq!(server.snapshot().errors, 2);
+
+        fs::remove_file(&app).unwrap();
+        let checks = server.poll(&[]);
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].removed);
+        assert_eq!(server.snapshot().files.len(), 1);
+    }
+
+    #[test]
+    fn test_write_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app.v4"), "1 CUBE\n").unwrap();
+        let mut server = CompileServer::new(dir.path(), CompileOptions::default()).unwrap();
+        server.poll(&[]);
+
+        let path = dir.path().join("diagnostics.json");
+        write_snapshot(&server.snapshot(), &path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["errors"], 1);
+        let file = dir.path().join("app.v4").display().to_string();
+        assert_eq!(json["files"][&file][0]["message"], "Unknown word: CUBE");
+    }
+}
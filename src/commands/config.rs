@@ -0,0 +1,41 @@
+use crate::Result;
+use crate::config::{Config, KEYS};
+use std::path::PathBuf;
+
+/// Configuration file path, or an error if it can't be determined
+fn config_path() -> Result<PathBuf> {
+    Config::path().ok_or_else(|| {
+        crate::V4Error::Config("Cannot determine config file location; set V4_CONFIG".to_string())
+    })
+}
+
+/// Get a single configuration value, `None` if unset
+pub fn config_get(key: &str) -> Result<Option<String>> {
+    Config::load_from(&config_path()?)?.get(key)
+}
+
+/// Set a configuration value and save the file
+///
+/// Returns the path of the saved file.
+pub fn config_set(key: &str, value: &str) -> Result<PathBuf> {
+    let path = config_path()?;
+    let mut config = Config::load_from(&path)?;
+    config.set(key, value)?;
+    config.save_to(&path)?;
+    Ok(path)
+}
+
+/// Configuration keys paired with their values (`None` if unset)
+pub type ConfigValues = Vec<(&'static str, Option<String>)>;
+
+/// All configuration keys with their values, and the file they come from
+pub fn config_list() -> Result<(PathBuf, ConfigValues)> {
+    let path = config_path()?;
+    let config = Config::load_from(&path)?;
+
+    let values = KEYS
+        .iter()
+        .map(|&key| Ok((key, config.get(key)?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((path, values))
+}
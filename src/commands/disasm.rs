@@ -0,0 +1,46 @@
+use crate::Result;
+use crate::bytecode::{V4B_HEADER_SIZE, V4B_MAGIC, V4bHeader};
+use std::fs;
+
+/// Bytecode extracted from a file for disassembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    /// `None` for raw bytecode
+    pub header: Option<V4bHeader>,
+    /// Code section to disassemble (see [`crate::disasm::listing`])
+    pub code: Vec<u8>,
+    /// Bytes after the code section that are not disassembled
+    pub trailing: usize,
+}
+
+impl Disassembly {
+    /// Whether the header declares more code than the file contains
+    pub fn is_truncated(&self) -> bool {
+        self.header.is_some_and(|h| h.code_size > self.code.len())
+    }
+}
+
+/// Read a bytecode file for disassembly
+///
+/// Accepts .v4b files (header is decoded and skipped) and raw bytecode.
+pub fn disasm(file: &str) -> Result<Disassembly> {
+    let data = fs::read(file)?;
+
+    if !data.starts_with(V4B_MAGIC) {
+        return Ok(Disassembly {
+            header: None,
+            code: data,
+            trailing: 0,
+        });
+    }
+
+    let header = V4bHeader::parse(&data)?;
+    let body = &data[V4B_HEADER_SIZE..];
+    let code_end = body.len().min(header.code_size);
+
+    Ok(Disassembly {
+        header: Some(header),
+        code: body[..code_end].to_vec(),
+        trailing: body.len() - code_end,
+    })
+}
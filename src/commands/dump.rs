@@ -0,0 +1,103 @@
+//! Read device memory into a file or a hexdump (`v4 dump`)
+
+use crate::device::V4Device;
+use crate::protocol::MemoryDump;
+use crate::serial::SerialSettings;
+use crate::transport::RetryPolicy;
+use crate::{Result, ui};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Read `len` bytes of VM memory at `addr`
+///
+/// Reads of more than [`MEMORY_CHUNK_SIZE`](crate::device::MEMORY_CHUNK_SIZE)
+/// bytes take several queries; `on_progress` receives the bytes read so far.
+pub fn dump(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    addr: u32,
+    len: u32,
+    timeout: Duration,
+    on_progress: &mut dyn FnMut(usize),
+) -> Result<MemoryDump> {
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    device.read_memory(addr, len, timeout, on_progress)
+}
+
+/// Parse an address or length, decimal or `0x` hex
+pub fn parse_number(value: &str) -> std::result::Result<u32, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", value))
+}
+
+/// Rows of 16 bytes: address, hex values and printable characters
+pub fn hexdump(dump: &MemoryDump) -> String {
+    let mut out = String::new();
+    for (i, chunk) in dump.bytes.chunks(16).enumerate() {
+        let offset = dump.addr.wrapping_add((i * 16) as u32);
+        let _ = write!(out, "{}  ", ui::address(format!("{:08X}", offset)));
+
+        // Hex values, padded if the last row is incomplete
+        for j in 0..16 {
+            match chunk.get(j) {
+                Some(byte) => {
+                    let _ = write!(out, "{:02X} ", byte);
+                }
+                None => out.push_str("   "),
+            }
+            if j == 7 {
+                out.push(' ');
+            }
+        }
+
+        let ascii: String = chunk.iter().map(|&byte| printable(byte)).collect();
+        let _ = writeln!(out, " |{}|", ui::ascii(ascii));
+    }
+    out
+}
+
+/// Character shown for `byte` in a hexdump's text column
+pub fn printable(byte: u8) -> char {
+    if (0x20..=0x7E).contains(&byte) {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump_rows() {
+        let dump = MemoryDump {
+            addr: 0x100,
+            bytes: b"Hello, V4!\x00\x01\x02\x03\x04\x05\x06\x07".to_vec(),
+        };
+        let text = console::strip_ansi_codes(&hexdump(&dump)).into_owned();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "00000100  48 65 6C 6C 6F 2C 20 56  34 21 00 01 02 03 04 05  |Hello, V4!......|"
+        );
+        assert_eq!(
+            lines[1],
+            "00000110  06 07                                             |..|"
+        );
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("0x2000"), Ok(0x2000));
+        assert_eq!(parse_number("4096"), Ok(4096));
+        assert!(parse_number("0xZZ").is_err());
+    }
+}
@@ -1,251 +1,253 @@
-use crate::Result;
-use crate::protocol::ErrorCode;
-use crate::repl::Compiler;
-use crate::serial::V4Serial;
-use rustyline::DefaultEditor;
-use rustyline::error::ReadlineError;
-use std::fs;
+use crate::device::{ExecReport, V4Device};
+use crate::source::{self, Define};
+use crate::{Result, V4Error};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{DebounceEventResult, Debouncer, new_debouncer};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
 use std::time::Duration;
 
-const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Quiet time after the last change to a watched file before it counts
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(250);
 
 /// Execute Forth source file on device
-pub fn exec(file: &str, port: &str, timeout: Duration, enter_repl: bool) -> Result<()> {
-    // Read Forth source file
-    let source = fs::read_to_string(file)?;
-
-    // Open serial connection
-    let mut serial = V4Serial::open_default(port)?;
-
-    // Create compiler
-    let mut compiler = Compiler::new().map_err(crate::V4Error::Compilation)?;
-
-    println!("Compiling {}...", file);
-
-    // Compile Forth source
-    let compiled = compiler
-        .compile(&source)
-        .map_err(crate::V4Error::Compilation)?;
-
-    // Send word definitions first
-    if !compiled.words.is_empty() {
-        println!("Compiled {} word(s)", compiled.words.len());
-
-        for word in &compiled.words {
-            println!(
-                "  Sending word '{}'... ({} bytes)",
-                word.name,
-                word.bytecode.len()
-            );
-
-            let response = serial.exec(&word.bytecode, timeout)?;
-
-            if response.error_code != ErrorCode::Ok {
-                eprintln!("  Error: {}", response.error_code.name());
-                return Err(crate::V4Error::Protocol(format!(
-                    "Device returned error: {}",
-                    response.error_code.name()
-                )));
-            }
+///
+/// `INCLUDE "file"` lines are expanded first (see [`source::load`]). Words
+/// defined by the file stay registered in the device's compiler context, so
+/// later files and a REPL on the same `device` can use them.
+pub fn exec(device: &mut V4Device, file: &str, timeout: Duration) -> Result<ExecReport> {
+    let loaded = source::load(Path::new(file))?;
+    device.exec_loaded(&loaded, timeout)
+}
 
-            // Register word in compiler context
-            if let Some(&word_idx) = response.word_indices.first() {
-                println!("  Word '{}' registered at index {}", word.name, word_idx);
-                compiler
-                    .register_word_index(&word.name, word_idx as i32)
-                    .map_err(crate::V4Error::Compilation)?;
+/// Register constants as words on the device, ahead of the files to run
+///
+/// See [`source::defines_prelude`].
+pub fn define(device: &mut V4Device, defines: &[Define], timeout: Duration) -> Result<ExecReport> {
+    device.exec_source(&source::defines_prelude(defines).text, timeout)
+}
+
+/// Files to watch for a set of sources: the sources and everything they include
+///
+/// A source whose includes can't be resolved is watched on its own, so
+/// fixing it triggers a re-run.
+pub fn watched_files(files: &[&str]) -> Vec<PathBuf> {
+    let mut watched: Vec<PathBuf> = Vec::new();
+    for &file in files {
+        let path = Path::new(file);
+        let loaded = source::load(path).map(|l| l.files);
+        for path in loaded.unwrap_or_else(|_| vec![path.to_path_buf()]) {
+            if !watched.contains(&path) {
+                watched.push(path);
             }
         }
     }
+    watched
+}
 
-    // Execute main bytecode if present
-    if !compiled.bytecode.is_empty() {
-        println!(
-            "Executing main bytecode... ({} bytes)",
-            compiled.bytecode.len()
-        );
+/// Change notifications for a set of files and, optionally, a directory tree
+///
+/// Watches the directories holding the files rather than the files, since
+/// many editors save by replacing the file. Changes are debounced so an
+/// editor that writes in several steps triggers a single re-run.
+pub struct FileWatcher {
+    debouncer: Debouncer<RecommendedWatcher>,
+    events: Receiver<DebounceEventResult>,
+    dirs: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    /// Directory watched with everything below it
+    tree: Option<PathBuf>,
+}
 
-        let response = serial.exec(&compiled.bytecode, timeout)?;
+impl FileWatcher {
+    pub fn new() -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let debouncer = new_debouncer(WATCH_DEBOUNCE, sender).map_err(watch_error)?;
+        Ok(Self {
+            debouncer,
+            events,
+            dirs: Vec::new(),
+            files: Vec::new(),
+            tree: None,
+        })
+    }
 
-        if response.error_code != ErrorCode::Ok {
-            eprintln!("Error: {}", response.error_code.name());
-            return Err(crate::V4Error::Protocol(format!(
-                "Execution failed: {}",
-                response.error_code.name()
-            )));
+    /// Also watch every file below `dir`, including ones created later
+    pub fn watch_tree(&mut self, dir: &Path) -> Result<()> {
+        let dir = dir.canonicalize()?;
+        let watcher = self.debouncer.watcher();
+        if let Some(tree) = self.tree.take() {
+            watcher.unwatch(&tree).map_err(watch_error)?;
         }
-
-        println!("Execution complete");
-    } else if !compiled.words.is_empty() {
-        println!("Word definitions complete");
+        // The tree's watch covers these; two watches on one directory clash
+        for watched in self.dirs.iter().filter(|watched| watched.starts_with(&dir)) {
+            watcher.unwatch(watched).map_err(watch_error)?;
+        }
+        self.dirs.retain(|watched| !watched.starts_with(&dir));
+        watcher
+            .watch(&dir, RecursiveMode::Recursive)
+            .map_err(watch_error)?;
+        self.tree = Some(dir);
+        Ok(())
     }
 
-    // Enter REPL if requested
-    if enter_repl {
-        println!("\nEntering REPL...");
-        println!("Type 'bye' or press Ctrl+D to exit");
-        println!("Type '.help' for help\n");
+    /// Watch `paths` instead of the files watched so far
+    ///
+    /// A file may not exist yet, but its directory must.
+    pub fn watch(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        for path in paths {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let dir = dir.canonicalize()?;
+            if let Some(name) = path.file_name() {
+                files.push(dir.join(name));
+            }
+            if !dirs.contains(&dir) && !self.in_tree(&dir) {
+                dirs.push(dir);
+            }
+        }
 
-        let mut rl = DefaultEditor::new().map_err(|e| crate::V4Error::Repl(e.to_string()))?;
+        let watcher = self.debouncer.watcher();
+        for dir in self.dirs.iter().filter(|dir| !dirs.contains(dir)) {
+            watcher.unwatch(dir).map_err(watch_error)?;
+        }
+        for dir in dirs.iter().filter(|dir| !self.dirs.contains(dir)) {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(watch_error)?;
+        }
+        self.dirs = dirs;
+        self.files = files;
+        Ok(())
+    }
 
-        // REPL loop
+    /// Block until a watched file changes and return the changed files
+    ///
+    /// Changes made since the last call count, so an edit saved while the
+    /// previous run was going doesn't get lost.
+    pub fn wait(&self) -> Result<Vec<PathBuf>> {
         loop {
-            let readline = rl.readline("v4> ");
-
-            match readline {
-                Ok(line) => {
-                    let line = line.trim();
-
-                    // Skip empty lines
-                    if line.is_empty() {
-                        continue;
-                    }
-
-                    // Add to history
-                    let _ = rl.add_history_entry(line);
-
-                    // Check for exit commands
-                    if line == "bye" || line == "quit" || line == ".exit" {
-                        println!("Goodbye!");
-                        break;
-                    }
-
-                    // Check for meta-commands
-                    if line.starts_with('.') {
-                        if let Err(e) = handle_meta_command(line, &mut serial, &mut compiler) {
-                            eprintln!("Error: {}", e);
-                        }
-                        continue;
-                    }
-
-                    // Compile Forth code
-                    let compiled = match compiler.compile(line) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            eprintln!("Error: {}", e);
-                            continue;
-                        }
-                    };
-
-                    // Execute on device
-                    if let Err(e) =
-                        execute_on_device(&mut serial, &compiled, &mut compiler, timeout)
-                    {
-                        eprintln!("Error: {}", e);
-                        continue;
-                    }
-
-                    // Success
-                    println!(" ok");
-                }
-                Err(ReadlineError::Interrupted) => {
-                    // Ctrl+C
-                    println!("^C");
-                    continue;
-                }
-                Err(ReadlineError::Eof) => {
-                    // Ctrl+D
-                    println!("Goodbye!");
-                    break;
-                }
-                Err(err) => {
-                    eprintln!("Error: {}", err);
-                    break;
-                }
+            let events = self
+                .events
+                .recv()
+                .map_err(|_| V4Error::Io(std::io::Error::other("file watcher stopped")))?
+                .map_err(watch_error)?;
+            let changed: Vec<PathBuf> = events
+                .into_iter()
+                .map(|event| event.path)
+                .filter(|path| self.files.contains(path) || self.in_tree(path))
+                .collect();
+            if !changed.is_empty() {
+                return Ok(changed);
             }
         }
     }
 
-    Ok(())
+    fn in_tree(&self, path: &Path) -> bool {
+        self.tree
+            .as_ref()
+            .is_some_and(|tree| path.starts_with(tree))
+    }
 }
 
-/// Execute compiled bytecode on device
-fn execute_on_device(
-    serial: &mut V4Serial,
-    compiled: &crate::repl::CompileResult,
-    compiler: &mut Compiler,
-    timeout: Duration,
-) -> Result<()> {
-    // Execute word definitions first
-    for word in &compiled.words {
-        let response = serial.exec(&word.bytecode, timeout)?;
-        if response.error_code != ErrorCode::Ok {
-            return Err(crate::V4Error::Device(format!(
-                "Failed to register word '{}': {}",
-                word.name,
-                response.error_code.name()
-            )));
-        }
+fn watch_error(e: notify_debouncer_mini::notify::Error) -> V4Error {
+    V4Error::Io(std::io::Error::other(format!("Cannot watch files: {}", e)))
+}
 
-        // Register word index returned from device
-        if let Some(&word_idx) = response.word_indices.first() {
-            compiler
-                .register_word_index(&word.name, word_idx as i32)
-                .map_err(crate::V4Error::Compilation)?;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use crate::transport::mock::MockTransport;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_exec_sends_words_then_main() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "#!/usr/bin/env v4\n: SQ DUP * ;\n3 SQ").unwrap();
+
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[5]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        let mut device = V4Device::from_transport(Box::new(transport), "mock");
+
+        let path = file.path().to_str().unwrap();
+        let report = exec(&mut device, path, Duration::from_millis(10)).unwrap();
+        assert_eq!(report.words[0].name, "SQ");
+        assert_eq!(report.words[0].index, 5);
+        assert!(report.main_size > 0);
     }
 
-    // Execute main bytecode
-    if !compiled.bytecode.is_empty() {
-        let response = serial.exec(&compiled.bytecode, timeout)?;
-        if response.error_code != ErrorCode::Ok {
-            return Err(crate::V4Error::Device(format!(
-                "Execution failed: {}",
-                response.error_code.name()
-            )));
-        }
+    #[test]
+    fn test_exec_expands_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("lib.v4"), ": SQ DUP * ;\n").unwrap();
+        let main = dir.path().join("main.v4");
+        fs::write(&main, "INCLUDE \"lib.v4\"\n3 SQ\n").unwrap();
+
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[0]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        let mut device = V4Device::from_transport(Box::new(transport), "mock");
+
+        let report = exec(
+            &mut device,
+            main.to_str().unwrap(),
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        assert_eq!(report.words[0].name, "SQ");
+
+        let watched = watched_files(&[main.to_str().unwrap()]);
+        assert_eq!(watched, vec![main.clone(), dir.path().join("lib.v4")]);
     }
 
-    Ok(())
-}
-
-/// Handle meta-commands (.help, .ping, etc.)
-fn handle_meta_command(line: &str, serial: &mut V4Serial, compiler: &mut Compiler) -> Result<()> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    let command = parts[0];
-
-    match command {
-        ".help" => {
-            println!("Available commands:");
-            println!("  .help   - Show this help");
-            println!("  .ping   - Ping device");
-            println!("  .reset  - Reset VM and compiler context");
-            println!("  .exit   - Exit REPL");
-            Ok(())
-        }
-        ".ping" => {
-            let err_code = serial.ping(DEFAULT_TIMEOUT)?;
-            if err_code == ErrorCode::Ok {
-                println!("Device is responsive");
-            } else {
-                println!("Device returned: {}", err_code.name());
-            }
-            Ok(())
-        }
-        ".reset" => {
-            // Reset device VM
-            let err_code = serial.reset(DEFAULT_TIMEOUT)?;
-            if err_code != ErrorCode::Ok {
-                return Err(crate::V4Error::Device(format!(
-                    "Reset failed: {}",
-                    err_code.name()
-                )));
-            }
-
-            // Reset compiler context
-            compiler.reset();
+    #[test]
+    fn test_watcher_sees_changes_to_watched_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.v4");
+        fs::write(&path, "1 2 +\n").unwrap();
+        let mut watcher = FileWatcher::new().unwrap();
+        watcher.watch(std::slice::from_ref(&path)).unwrap();
+
+        let (done, finished) = mpsc::channel();
+        let waiter = std::thread::spawn(move || {
+            let result = watcher.wait();
+            done.send(()).unwrap();
+            result
+        });
+        fs::write(dir.path().join("other.v4"), "3\n").unwrap();
+        std::thread::sleep(WATCH_DEBOUNCE * 3);
+        assert!(finished.try_recv().is_err(), "woke for an unwatched file");
+
+        // Same size and, on coarse filesystems, the same mtime as before
+        fs::write(&path, "3 4 +\n").unwrap();
+        finished.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            waiter.join().unwrap().unwrap(),
+            vec![path.canonicalize().unwrap()]
+        );
+    }
 
-            println!("VM and compiler context reset");
-            Ok(())
-        }
-        ".exit" => {
-            // Handled in main loop
-            Ok(())
-        }
-        _ => {
-            println!("Unknown command: {}", command);
-            println!("Type '.help' for available commands");
-            Ok(())
-        }
+    #[test]
+    fn test_watcher_sees_new_files_in_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        let mut watcher = FileWatcher::new().unwrap();
+        watcher.watch_tree(dir.path()).unwrap();
+
+        let path = dir.path().join("src").join("new.v4");
+        fs::write(&path, "1\n").unwrap();
+        let changed = watcher.wait().unwrap();
+        assert!(
+            changed.contains(&path.canonicalize().unwrap()),
+            "{:?}",
+            changed
+        );
     }
 }
@@ -0,0 +1,131 @@
+//! Running a command against several devices at once
+//!
+//! `push`, `reset` and `exec` accept repeated or comma-separated `--port`
+//! values and `--group` names from the configuration file. Each target gets
+//! its own thread and connection; results are collected per device.
+
+use crate::config::Config;
+use crate::{Result, V4Error};
+use std::thread;
+
+/// Result of a command on one device
+#[derive(Debug)]
+pub struct DeviceOutcome<T> {
+    pub port: String,
+    pub result: Result<T>,
+}
+
+/// Ports to run a command on: `--port` values followed by the group's ports
+///
+/// Duplicates are dropped. Without either, falls back to the configured
+/// port; an empty list means the port should be auto-detected.
+pub fn targets(ports: &[String], group: Option<&str>, config: &Config) -> Result<Vec<String>> {
+    let mut targets: Vec<String> = Vec::new();
+    let grouped = match group {
+        Some(name) => config.group(name)?,
+        None => &[],
+    };
+    for port in ports.iter().chain(grouped) {
+        if !targets.contains(port) {
+            targets.push(port.clone());
+        }
+    }
+    if targets.is_empty() {
+        targets.extend(config.port.clone());
+    }
+    Ok(targets)
+}
+
+/// Run `op` on every port concurrently, one thread per port
+///
+/// Outcomes are returned in the order of `ports`.
+pub fn fan_out<T: Send>(
+    ports: &[String],
+    op: impl Fn(&str) -> Result<T> + Sync,
+) -> Vec<DeviceOutcome<T>> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = ports.iter().map(|port| scope.spawn(|| op(port))).collect();
+        ports
+            .iter()
+            .zip(handles)
+            .map(|(port, handle)| DeviceOutcome {
+                port: port.clone(),
+                result: handle
+                    .join()
+                    .unwrap_or_else(|_| Err(V4Error::Cli("Worker thread panicked".to_string()))),
+            })
+            .collect()
+    })
+}
+
+/// Error summarizing failed devices, `Ok` if all succeeded
+///
+/// The exit status is that of the device errors if they all agree.
+pub fn check_outcomes<T>(outcomes: &[DeviceOutcome<T>]) -> Result<()> {
+    let mut codes = outcomes
+        .iter()
+        .filter_map(|o| o.result.as_ref().err())
+        .map(V4Error::exit_code);
+    let Some(first) = codes.next() else {
+        return Ok(());
+    };
+    let failed = 1 + codes.clone().count();
+    Err(V4Error::DevicesFailed {
+        failed,
+        total: outcomes.len(),
+        exit_code: match codes.all(|code| code == first) {
+            true => first,
+            false => 1,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(list: &[&str]) -> Vec<String> {
+        list.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_targets() {
+        let mut config = Config::default();
+        assert!(targets(&[], None, &config).unwrap().is_empty());
+
+        config.port = Some("/dev/ttyACM9".to_string());
+        assert_eq!(targets(&[], None, &config).unwrap(), ["/dev/ttyACM9"]);
+
+        config
+            .set("groups.rack", "/dev/ttyACM1,/dev/ttyACM2")
+            .unwrap();
+        assert_eq!(
+            targets(
+                &ports(&["/dev/ttyACM1", "/dev/ttyACM0"]),
+                Some("rack"),
+                &config
+            )
+            .unwrap(),
+            ["/dev/ttyACM1", "/dev/ttyACM0", "/dev/ttyACM2"]
+        );
+        assert!(targets(&[], Some("lab"), &config).is_err());
+    }
+
+    #[test]
+    fn test_fan_out_keeps_order_and_failures() {
+        let outcomes = fan_out(&ports(&["a", "bad", "c"]), |port| match port {
+            "bad" => Err(V4Error::Timeout),
+            _ => Ok(port.len()),
+        });
+
+        let names: Vec<&str> = outcomes.iter().map(|o| o.port.as_str()).collect();
+        assert_eq!(names, ["a", "bad", "c"]);
+        assert!(matches!(outcomes[1].result, Err(V4Error::Timeout)));
+        assert_eq!(outcomes[2].result.as_ref().unwrap(), &1);
+
+        let err = check_outcomes(&outcomes).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 3 device(s) failed");
+        assert_eq!(err.exit_code(), crate::error::EXIT_TIMEOUT);
+        assert!(check_outcomes(&outcomes[..1]).is_ok());
+    }
+}
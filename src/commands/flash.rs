@@ -0,0 +1,249 @@
+use crate::bootloader::{self, ESP32C6_CHIP_ID, Loader};
+use crate::{Result, V4Error};
+use md5::{Digest, Md5};
+use serialport::{SerialPort, SerialPortType};
+use std::fs;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Espressif USB vendor ID
+const ESPRESSIF_VID: u16 = 0x303A;
+/// Product ID of the built-in USB-Serial/JTAG controller
+const USB_JTAG_SERIAL_PID: u16 = 0x1001;
+/// Read timeout of the port; the loader applies its own deadlines on top
+const READ_TIMEOUT: Duration = Duration::from_millis(50);
+/// Attempts at resetting into the bootloader and syncing
+const CONNECT_ATTEMPTS: usize = 3;
+
+/// Step of a firmware update, reported as it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashStage {
+    /// In download mode and talking to the ROM bootloader
+    Connected { chip: &'static str },
+    /// Erasing the region and writing the image
+    Writing { written: usize, total: usize },
+    /// Comparing the flash MD5 with the image
+    Verifying,
+}
+
+/// Result of a firmware update
+#[derive(Debug, Clone)]
+pub struct FlashReport {
+    pub port: String,
+    pub chip: &'static str,
+    pub offset: u32,
+    pub size: usize,
+    /// MD5 of the image, `None` if verification was skipped
+    pub md5: Option<[u8; 16]>,
+}
+
+/// Write a firmware image to an ESP32-C6 through its ROM serial bootloader
+///
+/// Resets the board into download mode with DTR/RTS, erases and writes the
+/// image at `offset`, optionally verifies it by MD5, and resets the board
+/// into the new firmware. Without `port`, the single Espressif USB port is
+/// used.
+pub fn flash(
+    file: &str,
+    port: Option<&str>,
+    baud: u32,
+    offset: u32,
+    verify: bool,
+    on_stage: &mut dyn FnMut(FlashStage),
+) -> Result<FlashReport> {
+    let image = fs::read(file)?;
+    bootloader::check_image(&image)?;
+
+    let port = match port {
+        Some(port) => crate::usb::resolve(port)?,
+        None => detect_port()?,
+    };
+    let usb_jtag = is_usb_jtag(&port);
+    let serial = serialport::new(&port, baud)
+        .timeout(READ_TIMEOUT)
+        .open()
+        .map_err(|source| V4Error::PortOpen {
+            path: port.clone(),
+            hint: None,
+            source,
+        })?;
+    let mut loader = Loader::new(serial);
+
+    connect(&mut loader, usb_jtag)?;
+    let chip_id = loader.chip_id()?;
+    let chip = bootloader::chip_name(chip_id);
+    if chip_id != ESP32C6_CHIP_ID {
+        return Err(V4Error::Bootloader(format!(
+            "Connected chip is {} (ID {}); only the ESP32-C6 is supported",
+            chip, chip_id
+        )));
+    }
+    on_stage(FlashStage::Connected { chip });
+
+    loader.attach_flash()?;
+    let total = image.len();
+    on_stage(FlashStage::Writing { written: 0, total });
+    loader.write_flash(&image, offset, &mut |written| {
+        on_stage(FlashStage::Writing { written, total })
+    })?;
+
+    let md5 = if verify {
+        on_stage(FlashStage::Verifying);
+        let expected: [u8; 16] = Md5::digest(&image).into();
+        let actual = loader.flash_md5(offset, image.len())?;
+        if actual != expected {
+            return Err(V4Error::Bootloader(format!(
+                "Verification failed: flash MD5 {} differs from image MD5 {}",
+                hex(&actual),
+                hex(&expected)
+            )));
+        }
+        Some(expected)
+    } else {
+        None
+    };
+
+    loader.finish()?;
+    hard_reset(loader.port_mut())?;
+
+    Ok(FlashReport {
+        port,
+        chip,
+        offset,
+        size: image.len(),
+        md5,
+    })
+}
+
+/// Parse a `--offset` value: decimal or `0x` hex
+pub fn parse_offset(value: &str) -> std::result::Result<u32, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid flash offset '{}'", value))
+}
+
+/// Lowercase hex string of a digest
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Reset into download mode and sync, retrying a few times
+fn connect(loader: &mut Loader<Box<dyn SerialPort>>, usb_jtag: bool) -> Result<()> {
+    let mut result = Ok(());
+    for _ in 0..CONNECT_ATTEMPTS {
+        enter_bootloader(loader.port_mut(), usb_jtag)?;
+        loader.port_mut().clear(serialport::ClearBuffer::Input)?;
+        result = loader.sync();
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Hold GPIO9 (BOOT) low through a chip reset
+///
+/// Boards with a USB-UART bridge wire DTR to BOOT and RTS to EN. The
+/// built-in USB-Serial/JTAG maps the same lines internally but needs the
+/// sequence esptool uses for it.
+fn enter_bootloader(port: &mut Box<dyn SerialPort>, usb_jtag: bool) -> Result<()> {
+    if usb_jtag {
+        port.write_request_to_send(false)?;
+        port.write_data_terminal_ready(false)?;
+        sleep(Duration::from_millis(100));
+        port.write_data_terminal_ready(true)?;
+        port.write_request_to_send(false)?;
+        sleep(Duration::from_millis(100));
+        port.write_request_to_send(true)?;
+        port.write_data_terminal_ready(false)?;
+        port.write_request_to_send(true)?;
+        sleep(Duration::from_millis(100));
+        port.write_data_terminal_ready(false)?;
+        port.write_request_to_send(false)?;
+    } else {
+        port.write_data_terminal_ready(false)?;
+        port.write_request_to_send(true)?;
+        sleep(Duration::from_millis(100));
+        port.write_data_terminal_ready(true)?;
+        port.write_request_to_send(false)?;
+        sleep(Duration::from_millis(50));
+        port.write_data_terminal_ready(false)?;
+    }
+    Ok(())
+}
+
+/// Pulse EN through RTS to boot the new firmware
+fn hard_reset(port: &mut Box<dyn SerialPort>) -> Result<()> {
+    port.write_request_to_send(true)?;
+    sleep(Duration::from_millis(100));
+    port.write_request_to_send(false)?;
+    Ok(())
+}
+
+/// Whether a port is the chip's built-in USB-Serial/JTAG controller
+fn is_usb_jtag(path: &str) -> bool {
+    serialport::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .any(|info| {
+            info.port_name == path
+                && matches!(&info.port_type, SerialPortType::UsbPort(usb)
+                    if usb.vid == ESPRESSIF_VID && usb.pid == USB_JTAG_SERIAL_PID)
+        })
+}
+
+/// The single port with an Espressif USB ID
+///
+/// Boards behind a third-party USB-UART bridge aren't recognized and need
+/// `--port`.
+fn detect_port() -> Result<String> {
+    let found: Vec<String> = serialport::available_ports()?
+        .into_iter()
+        .filter(|info| matches!(&info.port_type, SerialPortType::UsbPort(usb) if usb.vid == ESPRESSIF_VID))
+        .map(|info| info.port_name)
+        .collect();
+    match found.as_slice() {
+        [port] => Ok(port.clone()),
+        [] => Err(V4Error::Cli(
+            "No Espressif USB device found; pass --port".to_string(),
+        )),
+        _ => Err(V4Error::Cli(format!(
+            "Multiple Espressif USB devices found ({}); pass --port to choose one",
+            found.join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("0x10000"), Ok(0x10000));
+        assert_eq!(parse_offset("4096"), Ok(4096));
+        assert!(parse_offset("0xZZ").is_err());
+    }
+
+    #[test]
+    fn test_flash_rejects_non_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.v4b");
+        fs::write(&path, b"V4BC\0\x02\0\0").unwrap();
+
+        let result = flash(
+            path.to_str().unwrap(),
+            Some("/dev/null"),
+            115200,
+            0,
+            true,
+            &mut |_| {},
+        );
+        assert!(matches!(result, Err(V4Error::Bootloader(_))));
+    }
+}
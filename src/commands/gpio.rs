@@ -0,0 +1,200 @@
+//! Drive and read GPIO pins from the host (`v4 gpio`, `.gpio`)
+//!
+//! Each action runs a few generated instructions that leave the HAL's
+//! results on the data stack, reads them back with QUERY_STACK and drops
+//! them again, so the stack is unchanged afterwards.
+
+use crate::device::{self, V4Device};
+use crate::serial::SerialSettings;
+use crate::syscalls::{SYS_GPIO_GET, SYS_GPIO_INIT, SYS_GPIO_SET};
+use crate::transport::{ResultWait, RetryPolicy, Transport};
+use crate::{Result, V4Error};
+use std::fmt;
+use std::time::Duration;
+
+const LIT_U8: u8 = 0x76;
+const DROP: u8 = 0x02;
+const OR: u8 = 0x29;
+const RET: u8 = 0x51;
+const SYS: u8 = 0x60;
+
+/// GPIO_INIT mode for driven pins: input and output, so the level reads back
+const MODE_INPUT_OUTPUT: u8 = 3;
+
+/// What to do with a pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    On,
+    Off,
+    Read,
+    Toggle,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::On => "on",
+            Action::Off => "off",
+            Action::Read => "read",
+            Action::Toggle => "toggle",
+        })
+    }
+}
+
+/// Parse a GPIO action (on, off, read, toggle)
+pub fn parse_action(value: &str) -> std::result::Result<Action, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "high" | "1" => Ok(Action::On),
+        "off" | "low" | "0" => Ok(Action::Off),
+        "read" => Ok(Action::Read),
+        "toggle" => Ok(Action::Toggle),
+        _ => Err(format!(
+            "invalid GPIO action '{}' (expected on, off, read or toggle)",
+            value
+        )),
+    }
+}
+
+/// Apply `action` to `pin`, returning its level afterwards (true is high)
+pub fn gpio(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    pin: u8,
+    action: Action,
+    timeout: Duration,
+) -> Result<bool> {
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    apply(device.transport(), pin, action, timeout)
+}
+
+/// Like [`gpio`] on an open transport
+///
+/// `on` and `off` configure the pin first. `toggle` reads the pin and writes
+/// the opposite level.
+pub fn apply(
+    transport: &mut dyn Transport,
+    pin: u8,
+    action: Action,
+    timeout: Duration,
+) -> Result<bool> {
+    match action {
+        Action::On | Action::Off => {
+            let level = action == Action::On;
+            write(transport, pin, level, timeout)?;
+            Ok(level)
+        }
+        Action::Read => read(transport, pin, timeout),
+        Action::Toggle => {
+            let level = !read(transport, pin, timeout)?;
+            write(transport, pin, level, timeout)?;
+            Ok(level)
+        }
+    }
+}
+
+/// `pin 3 SYS SYS_GPIO_INIT pin level SYS SYS_GPIO_SET OR` ( -- err )
+fn write(transport: &mut dyn Transport, pin: u8, level: bool, timeout: Duration) -> Result<()> {
+    #[rustfmt::skip]
+    let code = [
+        LIT_U8, pin, LIT_U8, MODE_INPUT_OUTPUT, SYS, SYS_GPIO_INIT,
+        LIT_U8, pin, LIT_U8, level as u8, SYS, SYS_GPIO_SET,
+        OR,
+    ];
+    let cells = call(transport, &code, 1, timeout)?;
+    check_err(pin, "write", cells[0])
+}
+
+/// `pin SYS SYS_GPIO_GET` ( -- value err )
+fn read(transport: &mut dyn Transport, pin: u8, timeout: Duration) -> Result<bool> {
+    let cells = call(transport, &[LIT_U8, pin, SYS, SYS_GPIO_GET], 2, timeout)?;
+    check_err(pin, "read", cells[1])?;
+    Ok(cells[0] != 0)
+}
+
+fn check_err(pin: u8, what: &str, err: i32) -> Result<()> {
+    match err {
+        0 => Ok(()),
+        _ => Err(V4Error::Device(format!(
+            "GPIO {} {} failed with error {}",
+            pin, what, err
+        ))),
+    }
+}
+
+/// Run `code`, then take the `results` cells it left on the stack
+fn call(
+    transport: &mut dyn Transport,
+    code: &[u8],
+    results: usize,
+    timeout: Duration,
+) -> Result<Vec<i32>> {
+    run(transport, &[code, &[RET]].concat(), timeout)?;
+    let stack = transport.stack_snapshot(timeout)?;
+    let Some(start) = stack.data.len().checked_sub(results) else {
+        return Err(V4Error::Protocol(format!(
+            "GPIO call left {} cell(s) on the stack, expected {}",
+            stack.data.len(),
+            results
+        )));
+    };
+    let mut drop = vec![DROP; results];
+    drop.push(RET);
+    run(transport, &drop, timeout)?;
+    Ok(stack.data[start..].to_vec())
+}
+
+fn run(transport: &mut dyn Transport, bytecode: &[u8], timeout: Duration) -> Result<()> {
+    let response = transport.exec(bytecode, timeout)?;
+    let response = device::await_result(transport, response, ResultWait::Within(timeout))?;
+    device::check_exec(&response, "GPIO call failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use crate::transport::mock::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Answer one call: its EXEC, the stack it leaves and the EXEC dropping it
+    fn push_call(transport: &mut MockTransport, results: &[i32]) {
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_stack(results);
+        transport.push_response(ErrorCode::Ok, &[]);
+    }
+
+    #[test]
+    fn test_actions_drive_the_pin() {
+        let mut transport = MockTransport::new();
+        push_call(&mut transport, &[1, 0]);
+        assert!(apply(&mut transport, 7, Action::Read, TIMEOUT).unwrap());
+        let sent: Vec<_> = transport.sent.iter().map(|f| f.payload.clone()).collect();
+        assert_eq!(sent[0], [LIT_U8, 7, SYS, SYS_GPIO_GET, RET]);
+        assert_eq!(sent[2], [DROP, DROP, RET]);
+
+        // Toggle reads high and writes low
+        transport.sent.clear();
+        push_call(&mut transport, &[1, 0]);
+        push_call(&mut transport, &[0]);
+        assert!(!apply(&mut transport, 7, Action::Toggle, TIMEOUT).unwrap());
+        let write = &transport.sent[3].payload;
+        assert_eq!(&write[6..12], [LIT_U8, 7, LIT_U8, 0, SYS, SYS_GPIO_SET]);
+        assert_eq!(transport.sent[5].payload, [DROP, RET]);
+
+        push_call(&mut transport, &[-2]);
+        let err = apply(&mut transport, 8, Action::On, TIMEOUT).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Device error: GPIO 8 write failed with error -2"
+        );
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(parse_action("ON"), Ok(Action::On));
+        assert_eq!(parse_action("low"), Ok(Action::Off));
+        assert!(parse_action("blink").is_err());
+    }
+}
@@ -0,0 +1,16 @@
+use crate::Result;
+use crate::device::{DeviceInfo, V4Device};
+use crate::serial::SerialSettings;
+use crate::transport::RetryPolicy;
+use std::time::Duration;
+
+/// Query firmware version and VM capabilities
+pub fn info(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    timeout: Duration,
+) -> Result<DeviceInfo> {
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    device.info(timeout)
+}
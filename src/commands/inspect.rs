@@ -0,0 +1,216 @@
+use crate::Result;
+use crate::bytecode::{SUPPORTED_VERSION, V4B_HEADER_SIZE, V4bHeader};
+use crate::disasm;
+use crate::ffi::WordDef;
+use std::fs;
+
+/// Opcode of CALL, whose operand is a word index
+const OP_CALL: u8 = 0x50;
+/// Opcode of SYS, whose operand is a system call number
+const OP_SYS: u8 = 0x60;
+
+/// Validated .v4b file contents
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    pub header: V4bHeader,
+    pub file_size: usize,
+    /// CRC-32 (IEEE) of the whole file
+    pub checksum: u32,
+    /// Main code section
+    pub code: Vec<u8>,
+    /// Embedded word definitions, in file order
+    pub words: Vec<WordDef>,
+    /// Disassembly summary of the main code
+    pub summary: CodeSummary,
+}
+
+/// Statistics over a disassembled code section
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeSummary {
+    pub code_size: usize,
+    pub instructions: usize,
+    /// Bytes that don't decode to an instruction
+    pub invalid_bytes: usize,
+    /// Word indices called, sorted and deduplicated
+    pub calls: Vec<u16>,
+    /// System call numbers used, sorted and deduplicated
+    pub syscalls: Vec<u8>,
+}
+
+impl CodeSummary {
+    pub fn of(code: &[u8]) -> Self {
+        let mut summary = Self {
+            code_size: code.len(),
+            ..Self::default()
+        };
+
+        for insn in disasm::decode(code) {
+            let Some(info) = insn.info else {
+                summary.invalid_bytes += insn.bytes.len();
+                continue;
+            };
+            summary.instructions += 1;
+            match (info.opcode, insn.operand) {
+                (OP_CALL, Some(idx)) => summary.calls.push(idx as u16),
+                (OP_SYS, Some(num)) => summary.syscalls.push(num as u8),
+                _ => {}
+            }
+        }
+
+        summary.calls.sort_unstable();
+        summary.calls.dedup();
+        summary.syscalls.sort_unstable();
+        summary.syscalls.dedup();
+        summary
+    }
+}
+
+/// Read and validate a .v4b file without a device
+///
+/// Checks the magic, the format version and that the code and word
+/// sections are complete.
+pub fn inspect(file: &str) -> Result<Inspection> {
+    let data = fs::read(file)?;
+    parse(&data)
+}
+
+/// Validate in-memory .v4b contents, see [`inspect`]
+pub(crate) fn parse(data: &[u8]) -> Result<Inspection> {
+    let invalid = |msg: String| crate::V4Error::Protocol(msg);
+
+    let header = V4bHeader::parse(data)?;
+    if header.version > SUPPORTED_VERSION {
+        return Err(invalid(format!(
+            "Unsupported .v4b version {}.{} (newest supported: {}.{})",
+            header.version.0, header.version.1, SUPPORTED_VERSION.0, SUPPORTED_VERSION.1
+        )));
+    }
+
+    header.check_size(data.len())?;
+    let body = &data[V4B_HEADER_SIZE..];
+    let code = &body[..header.code_size];
+    let words = parse_words(&body[header.code_size..], header.word_count.unwrap_or(0))?;
+
+    Ok(Inspection {
+        header,
+        file_size: data.len(),
+        checksum: crc32(data),
+        code: code.to_vec(),
+        words,
+        summary: CodeSummary::of(code),
+    })
+}
+
+/// Parse the word section following the code
+///
+/// Each definition: [NAME_LEN][NAME...][CODE_LEN u16 LE][CODE...], the same
+/// layout QUERY_WORD uses.
+fn parse_words(mut data: &[u8], count: u32) -> Result<Vec<WordDef>> {
+    let truncated = |i: u32| {
+        crate::V4Error::Protocol(format!(
+            "Word section truncated at definition {} of {}",
+            i + 1,
+            count
+        ))
+    };
+
+    let mut words = Vec::new();
+    for i in 0..count {
+        let name_len = *data.first().ok_or_else(|| truncated(i))? as usize;
+        let name = data.get(1..1 + name_len).ok_or_else(|| truncated(i))?;
+        let len_at = 1 + name_len;
+        let len = data.get(len_at..len_at + 2).ok_or_else(|| truncated(i))?;
+        let code_len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        let code_at = len_at + 2;
+        let code = data
+            .get(code_at..code_at + code_len)
+            .ok_or_else(|| truncated(i))?;
+
+        words.push(WordDef {
+            name: String::from_utf8_lossy(name).to_string(),
+            bytecode: code.to_vec(),
+        });
+        data = &data[code_at + code_len..];
+    }
+
+    if !data.is_empty() {
+        return Err(crate::V4Error::Protocol(format!(
+            "{} unexpected bytes after the word section",
+            data.len()
+        )));
+    }
+    Ok(words)
+}
+
+/// CRC-32 (IEEE 802.3, as used by zip and Ethernet)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4b(version: (u8, u8), code: &[u8], words: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = b"V4BC".to_vec();
+        data.extend_from_slice(&[version.0, version.1, 0, 0]);
+        data.extend_from_slice(&(code.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(words.len() as u32).to_le_bytes());
+        data.extend_from_slice(code);
+        for (name, code) in words {
+            data.push(name.len() as u8);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&(code.len() as u16).to_le_bytes());
+            data.extend_from_slice(code);
+        }
+        data
+    }
+
+    #[test]
+    fn test_inspect_words_and_summary() {
+        // CALL 0, LIT_U8 7, SYS 1, RET
+        let code = [0x50, 0x00, 0x00, 0x76, 0x07, 0x60, 0x01, 0x51];
+        let data = v4b((0, 2), &code, &[("SQ", &[0x01, 0x12, 0x51])]);
+
+        let inspection = parse(&data).unwrap();
+        assert_eq!(inspection.words.len(), 1);
+        assert_eq!(inspection.words[0].name, "SQ");
+        assert_eq!(inspection.words[0].bytecode.len(), 3);
+        assert_eq!(inspection.summary.instructions, 4);
+        assert_eq!(inspection.summary.calls, vec![0]);
+        assert_eq!(inspection.summary.syscalls, vec![1]);
+        assert_eq!(inspection.checksum, crc32(&data));
+    }
+
+    #[test]
+    fn test_inspect_rejects_bad_files() {
+        assert!(parse(b"RIFF0000000000000000").is_err());
+        assert!(parse(&v4b((0, 3), &[0x51], &[])).is_err());
+
+        // Code shorter than declared
+        let mut short = v4b((0, 2), &[0x51, 0x51], &[]);
+        short.pop();
+        assert!(parse(&short).is_err());
+
+        // Word section cut off
+        let mut cut = v4b((0, 2), &[0x51], &[("SQ", &[0x01, 0x51])]);
+        cut.pop();
+        assert!(parse(&cut).is_err());
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,532 @@
+//! Guided tutorial in the REPL (`v4 learn`)
+//!
+//! Lessons are a list of exercises. Each exercise starts with an empty data
+//! stack, takes REPL input until the device is in the asked-for state, and
+//! checks it after every input: the stack contents, what a defined word
+//! computes, or the level of a pin.
+
+use crate::commands::repl::{self, LineOutcome, Session};
+use crate::device::{self, V4Device};
+use crate::ffi::Compiler;
+use crate::repl::needs_continuation;
+use crate::transport::{ResultWait, Transport};
+use crate::ui;
+use crate::{Result, V4Error};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const DROP: u8 = 0x02;
+const RET: u8 = 0x51;
+
+/// A topic with its exercises
+#[derive(Debug, Clone, Copy)]
+pub struct Lesson {
+    pub title: &'static str,
+    /// Explanation shown before the first exercise
+    pub intro: &'static str,
+    pub exercises: &'static [Exercise],
+}
+
+/// One task and how to tell it's done
+#[derive(Debug, Clone, Copy)]
+pub struct Exercise {
+    pub task: &'static str,
+    pub hint: &'static str,
+    /// Input that solves it, shown by `.skip`
+    pub solution: &'static str,
+    /// Words the input must use, so `2 1` doesn't pass for `1 2 SWAP`
+    pub uses: &'static [&'static str],
+    pub check: Check,
+}
+
+/// Device state that solves an exercise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// The data stack holds exactly these cells, bottom first
+    Stack(&'static [i32]),
+    /// `name` is defined and turns the `input` cells into `output`
+    Word {
+        name: &'static str,
+        input: &'static [i32],
+        output: &'static [i32],
+    },
+    /// A pin reads at this level
+    Pin { pin: u8, high: bool },
+}
+
+/// Result of checking an exercise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Solved,
+    /// Not solved, with what is still missing
+    NotYet(String),
+}
+
+/// The built-in course
+#[rustfmt::skip]
+pub const LESSONS: &[Lesson] = &[
+    Lesson {
+        title: "The data stack",
+        intro: "Forth works on a stack of numbers. Typing a number pushes it; words like + and\n\
+                DUP take their arguments from the top and push their results. Type .stack at any\n\
+                time to see what is on it.",
+        exercises: &[
+            Exercise {
+                task: "Push the numbers 1, 2 and 3.",
+                hint: "Type the numbers separated by spaces; the last one ends up on top.",
+                solution: "1 2 3",
+                uses: &[],
+                check: Check::Stack(&[1, 2, 3]),
+            },
+            Exercise {
+                task: "Leave the sum of 2 and 5 on the stack.",
+                hint: "Push both numbers, then + replaces them with their sum.",
+                solution: "2 5 +",
+                uses: &["+"],
+                check: Check::Stack(&[7]),
+            },
+            Exercise {
+                task: "Square 6 using DUP and *.",
+                hint: "DUP copies the top of the stack, * multiplies the top two.",
+                solution: "6 DUP *",
+                uses: &["DUP", "*"],
+                check: Check::Stack(&[36]),
+            },
+            Exercise {
+                task: "Push 1 and 2, then use SWAP so that 1 is on top.",
+                hint: "SWAP exchanges the top two cells.",
+                solution: "1 2 SWAP",
+                uses: &["SWAP"],
+                check: Check::Stack(&[2, 1]),
+            },
+        ],
+    },
+    Lesson {
+        title: "Defining words",
+        intro: "A colon definition names a sequence of words: `: NAME ... ;`. The new word takes\n\
+                its arguments from the stack like any other. A definition may span several lines;\n\
+                the prompt waits for the closing ;.",
+        exercises: &[
+            Exercise {
+                task: "Define SQ, which squares the number on top of the stack.",
+                hint: "Start with `: SQ` and end with `;`, with the code from the DUP exercise between.",
+                solution: ": SQ DUP * ;",
+                uses: &[],
+                check: Check::Word { name: "SQ", input: &[7], output: &[49] },
+            },
+            Exercise {
+                task: "Define CUBE using SQ.",
+                hint: "x cubed is x times x squared: keep a copy of x with DUP before calling SQ.",
+                solution: ": CUBE DUP SQ * ;",
+                uses: &["SQ"],
+                check: Check::Word { name: "CUBE", input: &[3], output: &[27] },
+            },
+        ],
+    },
+    Lesson {
+        title: "Controlling pins",
+        intro: "The device's hardware is reached through system calls: `SYS` followed by the\n\
+                call's number, which you can write as a SYS_* constant. From the REPL, .gpio\n\
+                drives a pin directly, which is handy for checking the wiring.",
+        exercises: &[
+            Exercise {
+                task: "Turn pin 2 on with the .gpio command.",
+                hint: "Type .gpio followed by the pin number and `on`.",
+                solution: ".gpio 2 on",
+                uses: &[],
+                check: Check::Pin { pin: 2, high: true },
+            },
+            Exercise {
+                task: "Turn pin 2 off from Forth with SYS SYS_GPIO_SET ( pin value -- err ), then DROP the error code.",
+                hint: "Push the pin and the level 0, call `SYS SYS_GPIO_SET`, and DROP what it leaves.",
+                solution: "2 0 SYS SYS_GPIO_SET DROP",
+                uses: &["SYS"],
+                check: Check::Pin { pin: 2, high: false },
+            },
+            Exercise {
+                task: "Read pin 2 with SYS SYS_GPIO_GET ( pin -- value err ).",
+                hint: "Push the pin number and call `SYS SYS_GPIO_GET`; the pin is off, so both results are 0.",
+                solution: "2 SYS SYS_GPIO_GET",
+                uses: &["SYS"],
+                check: Check::Stack(&[0, 0]),
+            },
+        ],
+    },
+];
+
+/// Exercises solved and skipped in a [`learn_loop`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LearnSummary {
+    pub solved: usize,
+    pub skipped: usize,
+    /// Whether the course was worked through to the end
+    pub finished: bool,
+}
+
+/// Run the course on an open device, starting at lesson `first` (from 1)
+///
+/// Like the REPL this is interactive and prints to the terminal. The VM is
+/// reset first, so the exercises start from an empty dictionary.
+pub fn run_learn(mut device: V4Device, first: usize) -> Result<LearnSummary> {
+    println!("V4 tutorial on {}", device.port());
+    println!("Type '.hint' for a hint, '.skip' to see the solution and move on, 'bye' to stop");
+    println!("REPL commands such as '.stack' work as usual");
+    device.reset(DEFAULT_TIMEOUT, DEFAULT_TIMEOUT)?;
+
+    let mut rl = DefaultEditor::new().map_err(|e| V4Error::Repl(e.to_string()))?;
+    let read_line = |prompt: &str| match rl.readline(&ui::prompt(prompt)) {
+        Ok(line) => {
+            let _ = rl.add_history_entry(line.as_str());
+            Some(line)
+        }
+        Err(ReadlineError::Interrupted) => Some(String::new()),
+        Err(_) => None,
+    };
+    let (transport, compiler) = device.parts()?;
+    learn_loop(transport, compiler, &LESSONS[first - 1..], read_line)
+}
+
+/// Work through `lessons`, reading input with `read_line(prompt)`
+///
+/// `read_line` returns `None` at the end of input, which stops the course
+/// like `bye` does.
+pub fn learn_loop(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    lessons: &[Lesson],
+    mut read_line: impl FnMut(&str) -> Option<String>,
+) -> Result<LearnSummary> {
+    let mut session = Session::default();
+    let mut summary = LearnSummary::default();
+
+    for lesson in lessons {
+        println!();
+        println!("== {} ==", lesson.title);
+        println!("{}", lesson.intro);
+
+        for (i, exercise) in lesson.exercises.iter().enumerate() {
+            clear_stack(transport)?;
+            println!();
+            println!(
+                "Exercise {} of {}: {}",
+                i + 1,
+                lesson.exercises.len(),
+                exercise.task
+            );
+
+            // Everything typed for this exercise, to check `uses`
+            let mut typed = String::new();
+            let mut pending = String::new();
+            loop {
+                let prompt = if pending.is_empty() { "learn>" } else { "...>" };
+                let Some(line) = read_line(prompt) else {
+                    return Ok(summary);
+                };
+                let line = line.trim();
+                if line.is_empty() {
+                    pending.clear();
+                    continue;
+                }
+                match line {
+                    ".hint" => {
+                        println!("Hint: {}", exercise.hint);
+                        continue;
+                    }
+                    ".skip" => {
+                        println!("Solution: {}", exercise.solution);
+                        summary.skipped += 1;
+                        break;
+                    }
+                    _ => {}
+                }
+
+                if !pending.is_empty() || !line.starts_with('.') {
+                    pending.push_str(line);
+                    pending.push('\n');
+                    if needs_continuation(&pending) {
+                        continue;
+                    }
+                }
+                let input = match pending.is_empty() {
+                    true => line.to_string(),
+                    false => std::mem::take(&mut pending),
+                };
+
+                match repl::run_line(&input, transport, compiler, &mut session).0 {
+                    Ok(LineOutcome::Exit) => return Ok(summary),
+                    Ok(LineOutcome::Continue) => {}
+                    Err(e) if e.is_disconnect() => return Err(e),
+                    Err(e) => eprintln!("{} {}", ui::error_label(), ui::error(e)),
+                }
+                typed.push_str(&input);
+                typed.push('\n');
+
+                match check(exercise, transport, compiler, &typed)? {
+                    Verdict::Solved => {
+                        println!("{} Well done!", ui::success());
+                        summary.solved += 1;
+                        break;
+                    }
+                    // Meta-commands such as .stack are for looking around
+                    Verdict::NotYet(reason) if !input.starts_with('.') => {
+                        println!("{} Not yet: {}", ui::failure(), reason);
+                    }
+                    Verdict::NotYet(_) => {}
+                }
+            }
+        }
+    }
+
+    summary.finished = true;
+    println!();
+    println!(
+        "Course complete: {} exercise(s) solved, {} skipped",
+        summary.solved, summary.skipped
+    );
+    Ok(summary)
+}
+
+/// Check whether the device is in the state `exercise` asks for
+///
+/// `typed` is the input given for the exercise so far.
+pub fn check(
+    exercise: &Exercise,
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    typed: &str,
+) -> Result<Verdict> {
+    let verdict = match exercise.check {
+        Check::Stack(expected) => {
+            let stack = transport.stack_snapshot(DEFAULT_TIMEOUT)?.data;
+            match stack == expected {
+                true => Verdict::Solved,
+                false => Verdict::NotYet(format!(
+                    "the stack holds {}, expected {}",
+                    cells(&stack),
+                    cells(expected)
+                )),
+            }
+        }
+        Check::Word {
+            name,
+            input,
+            output,
+        } => check_word(transport, compiler, name, input, output)?,
+        Check::Pin { pin, high } => {
+            let level = crate::commands::gpio::apply(
+                transport,
+                pin,
+                crate::commands::gpio::Action::Read,
+                DEFAULT_TIMEOUT,
+            )?;
+            match level == high {
+                true => Verdict::Solved,
+                false => Verdict::NotYet(format!(
+                    "pin {} is {}",
+                    pin,
+                    if level { "on" } else { "off" }
+                )),
+            }
+        }
+    };
+
+    let missing = exercise.uses.iter().find(|word| {
+        !typed
+            .split_whitespace()
+            .any(|w| w.eq_ignore_ascii_case(word))
+    });
+    Ok(match (verdict, missing) {
+        (Verdict::Solved, Some(word)) => Verdict::NotYet(format!("solve it using {}", word)),
+        (verdict, _) => verdict,
+    })
+}
+
+/// Run `input NAME` on an empty stack and compare what it leaves
+fn check_word(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    name: &str,
+    input: &[i32],
+    output: &[i32],
+) -> Result<Verdict> {
+    if !compiler
+        .word_names()
+        .any(|word| word.eq_ignore_ascii_case(name))
+    {
+        return Ok(Verdict::NotYet(format!("{} isn't defined yet", name)));
+    }
+
+    let test = format!("{} {}", cells(input), name);
+    let compiled = compiler.compile(&test).map_err(V4Error::Compilation)?;
+    clear_stack(transport)?;
+    let ran = run(transport, &compiled.bytecode);
+    let stack = transport.stack_snapshot(DEFAULT_TIMEOUT)?.data;
+    clear_stack(transport)?;
+    Ok(match ran {
+        Err(e @ V4Error::Device(_)) => Verdict::NotYet(format!("`{}` failed: {}", test, e)),
+        Err(e) => return Err(e),
+        Ok(()) if stack == output => Verdict::Solved,
+        Ok(()) => Verdict::NotYet(format!(
+            "`{}` leaves {}, expected {}",
+            test,
+            cells(&stack),
+            cells(output)
+        )),
+    })
+}
+
+/// Drop everything on the data stack
+fn clear_stack(transport: &mut dyn Transport) -> Result<()> {
+    let depth = transport.stack_snapshot(DEFAULT_TIMEOUT)?.data.len();
+    if depth == 0 {
+        return Ok(());
+    }
+    let mut code = vec![DROP; depth];
+    code.push(RET);
+    run(transport, &code)
+}
+
+fn run(transport: &mut dyn Transport, bytecode: &[u8]) -> Result<()> {
+    let response = transport.exec(bytecode, DEFAULT_TIMEOUT)?;
+    let response = device::await_result(transport, response, ResultWait::Within(DEFAULT_TIMEOUT))?;
+    device::check_exec(&response, "Execution failed")
+}
+
+/// Cells as typed in Forth, e.g. `1 2 3`, or `nothing`
+fn cells(cells: &[i32]) -> String {
+    match cells {
+        [] => "nothing".to_string(),
+        _ => cells
+            .iter()
+            .map(i32::to_string)
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use crate::transport::mock::MockTransport;
+
+    const fn exercise(check: Check, uses: &'static [&'static str]) -> Exercise {
+        Exercise {
+            task: "",
+            hint: "",
+            solution: "",
+            uses,
+            check,
+        }
+    }
+
+    #[test]
+    fn test_solutions_compile() {
+        // Later solutions call the words earlier ones define
+        let course: Vec<_> = LESSONS
+            .iter()
+            .flat_map(|lesson| lesson.exercises)
+            .map(|exercise| exercise.solution)
+            .filter(|solution| !solution.starts_with('.'))
+            .collect();
+        let mut compiler = Compiler::new().unwrap();
+        compiler.compile(&course.join("\n")).unwrap();
+    }
+
+    /// Answer one GPIO call, see `gpio::apply`
+    fn push_gpio(transport: &mut MockTransport, results: &[i32]) {
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_stack(results);
+        transport.push_response(ErrorCode::Ok, &[]);
+    }
+
+    #[test]
+    fn test_stack_check_wants_listed_words() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+
+        let swap = exercise(Check::Stack(&[2, 1]), &["SWAP"]);
+        transport.push_stack(&[]);
+        assert_eq!(
+            check(&swap, &mut transport, &mut compiler, "").unwrap(),
+            Verdict::NotYet("the stack holds nothing, expected 2 1".to_string())
+        );
+        transport.push_stack(&[2, 1]);
+        assert_eq!(
+            check(&swap, &mut transport, &mut compiler, "1 2 swap").unwrap(),
+            Verdict::Solved
+        );
+        transport.push_stack(&[2, 1]);
+        assert_eq!(
+            check(&swap, &mut transport, &mut compiler, "2 1").unwrap(),
+            Verdict::NotYet("solve it using SWAP".to_string())
+        );
+
+        transport.push_stack(&[2, 1]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        clear_stack(&mut transport).unwrap();
+        assert_eq!(transport.sent.last().unwrap().payload, [DROP, DROP, RET]);
+    }
+
+    #[test]
+    fn test_loop_hints_skips_and_checks_pins() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        const EXERCISES: &[Exercise] = &[
+            exercise(Check::Stack(&[1]), &[]),
+            exercise(Check::Pin { pin: 4, high: true }, &[]),
+            exercise(
+                Check::Pin {
+                    pin: 4,
+                    high: false,
+                },
+                &[],
+            ),
+        ];
+        let lessons = [Lesson {
+            title: "Pins",
+            intro: "",
+            exercises: EXERCISES,
+        }];
+
+        // Each exercise starts with an empty stack; every pin check reads
+        // the pin after the typed line ran
+        transport.push_stack(&[]);
+        transport.push_stack(&[]);
+        push_gpio(&mut transport, &[0, 0]);
+        push_gpio(&mut transport, &[0, 0]);
+        push_gpio(&mut transport, &[0]);
+        push_gpio(&mut transport, &[1, 0]);
+        transport.push_stack(&[]);
+        push_gpio(&mut transport, &[0]);
+        push_gpio(&mut transport, &[0, 0]);
+        let mut input = [
+            ".hint",
+            ".skip",
+            ".gpio 4 read",
+            ".gpio 4 on",
+            ".gpio 4 off",
+        ]
+        .into_iter()
+        .map(str::to_string);
+        let summary =
+            learn_loop(&mut transport, &mut compiler, &lessons, |_| input.next()).unwrap();
+        assert_eq!(
+            summary,
+            LearnSummary {
+                solved: 2,
+                skipped: 1,
+                finished: true
+            }
+        );
+
+        transport.push_stack(&[]);
+        let mut input = ["bye".to_string()].into_iter();
+        let summary =
+            learn_loop(&mut transport, &mut compiler, &lessons, |_| input.next()).unwrap();
+        assert!(!summary.finished);
+    }
+}
@@ -0,0 +1,799 @@
+//! `v4 lsp`: Language Server Protocol server over stdio
+//!
+//! Editors start `v4 lsp` and exchange JSON-RPC messages on stdin/stdout.
+//! The server provides:
+//!
+//! - diagnostics from compiling each open document with the V4-front
+//!   [`Compiler`] (`INCLUDE`s resolve relative to the document)
+//! - hover showing a word's definition
+//! - go-to-definition across the `.v4` files of the workspace
+//! - completion of core words and words defined in the workspace
+//!
+//! Documents are synced in full on every change.
+
+use super::compile::{self, Severity};
+use crate::ffi::{CompileError, Compiler};
+use crate::source;
+use crate::{Result, V4Error};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Extension of the source files indexed in the workspace
+const SOURCE_EXTENSION: &str = "v4";
+
+/// Most lines of a definition shown on hover
+const HOVER_MAX_LINES: usize = 12;
+
+/// JSON-RPC error codes
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// LSP enum values used in responses
+const SEVERITY_ERROR: u8 = 1;
+const SEVERITY_WARNING: u8 = 2;
+const KIND_FUNCTION: u8 = 3;
+const KIND_KEYWORD: u8 = 14;
+const SYNC_FULL: u8 = 1;
+
+/// Core words offered for completion and hover, with their stack effects
+pub(crate) const CORE_WORDS: &[(&str, &str)] = &[
+    ("DUP", "( a -- a a )"),
+    ("DROP", "( a -- )"),
+    ("SWAP", "( a b -- b a )"),
+    ("OVER", "( a b -- a b a )"),
+    (">R", "( a -- ) ( R: -- a )"),
+    ("R>", "( -- a ) ( R: a -- )"),
+    ("R@", "( -- a ) ( R: a -- a )"),
+    ("+", "( a b -- a+b )"),
+    ("-", "( a b -- a-b )"),
+    ("*", "( a b -- a*b )"),
+    ("/", "( a b -- a/b )"),
+    ("MOD", "( a b -- a%b )"),
+    ("=", "( a b -- flag )"),
+    ("<>", "( a b -- flag )"),
+    ("<", "( a b -- flag )"),
+    (">", "( a b -- flag )"),
+    ("<=", "( a b -- flag )"),
+    (">=", "( a b -- flag )"),
+    ("AND", "( a b -- a&b )"),
+    ("OR", "( a b -- a|b )"),
+    ("XOR", "( a b -- a^b )"),
+    ("INVERT", "( a -- ~a )"),
+    ("@", "( addr -- x )"),
+    ("!", "( x addr -- )"),
+    ("IF", "( flag -- )"),
+    ("ELSE", "( -- )"),
+    ("THEN", "( -- )"),
+    ("BEGIN", "( -- )"),
+    ("UNTIL", "( flag -- )"),
+    ("SYS", "( ... -- ... )"),
+    ("EMIT", "( char -- )"),
+    (".", "( a -- )"),
+];
+
+/// Word definition found in a source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Definition {
+    name: String,
+    /// 0-based position of the name, `character` in UTF-16 code units
+    line: usize,
+    character: usize,
+    /// Source lines from `:` to `;`
+    text: String,
+    /// `\` comment lines right above the definition
+    doc: Option<String>,
+}
+
+/// Source file known to the server
+#[derive(Debug, Default)]
+struct Document {
+    text: String,
+    /// Open in the editor; otherwise indexed from disk
+    open: bool,
+    definitions: Vec<Definition>,
+}
+
+impl Document {
+    fn new(text: String, open: bool) -> Self {
+        Self {
+            definitions: definitions(&text),
+            text,
+            open,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Server {
+    /// Workspace files and open documents by URI
+    documents: BTreeMap<String, Document>,
+    shutdown: bool,
+}
+
+type RequestResult = std::result::Result<Value, (i64, String)>;
+
+/// Run the server on stdin/stdout until the client exits
+pub fn lsp() -> Result<()> {
+    serve(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())
+}
+
+/// Serve messages from `input` until `exit` or end of input
+///
+/// Exiting without a `shutdown` request first is an error, as the LSP
+/// specification asks for a failure exit code then.
+pub fn serve(input: &mut impl BufRead, output: &mut impl Write) -> Result<()> {
+    let mut server = Server::default();
+
+    while let Some(body) = read_message(input)? {
+        let message: Value = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Ignoring invalid LSP message: {}", e);
+                continue;
+            }
+        };
+        let method = message.get("method").and_then(Value::as_str);
+        let params = message.get("params").unwrap_or(&Value::Null);
+
+        match (method, message.get("id")) {
+            (Some("exit"), _) => {
+                return match server.shutdown {
+                    true => Ok(()),
+                    false => Err(V4Error::Lsp("exit before shutdown".to_string())),
+                };
+            }
+            (Some(method), Some(id)) => {
+                log::debug!("LSP request {}", method);
+                let response = match server.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                };
+                write_message(output, &response)?;
+            }
+            (Some(method), None) => {
+                log::debug!("LSP notification {}", method);
+                for notification in server.notify(method, params) {
+                    write_message(output, &notification)?;
+                }
+            }
+            // Responses; the server never sends requests
+            (None, _) => {}
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Value) -> RequestResult {
+        match method {
+            "initialize" => {
+                if let Some(root) = workspace_root(params) {
+                    self.index_workspace(&root);
+                }
+                Ok(json!({
+                    "capabilities": {
+                        "textDocumentSync": SYNC_FULL,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "completionProvider": {},
+                    },
+                    "serverInfo": { "name": "v4", "version": env!("CARGO_PKG_VERSION") },
+                }))
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let (uri, word) = self.word_at(params)?;
+                Ok(self.hover(&uri, &word))
+            }
+            "textDocument/definition" => {
+                let (uri, word) = self.word_at(params)?;
+                let locations: Vec<Value> = self
+                    .find(&uri, &word)
+                    .into_iter()
+                    .map(|(uri, def)| {
+                        json!({
+                            "uri": uri,
+                            "range": range(def.line, def.character, utf16_len(&def.name)),
+                        })
+                    })
+                    .collect();
+                Ok(Value::Array(locations))
+            }
+            "textDocument/completion" => Ok(self.completions()),
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method: {}", method))),
+        }
+    }
+
+    /// Handle a notification, returning notifications to send back
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let document = &params["textDocument"];
+        let Some(uri) = document["uri"].as_str() else {
+            return Vec::new();
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = document["text"].as_str().unwrap_or_default();
+                self.open(uri, text.to_string());
+                vec![self.diagnostics(uri)]
+            }
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole text
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.open(uri, text.to_string());
+                }
+                vec![self.diagnostics(uri)]
+            }
+            "textDocument/didSave" => vec![self.diagnostics(uri)],
+            "textDocument/didClose" => {
+                // Fall back to the saved file, if there is one
+                match uri_to_path(uri).and_then(|path| fs::read_to_string(path).ok()) {
+                    Some(text) => {
+                        self.documents
+                            .insert(uri.to_string(), Document::new(text, false));
+                    }
+                    None => {
+                        self.documents.remove(uri);
+                    }
+                }
+                vec![publish(uri, Vec::new())]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn open(&mut self, uri: &str, text: String) {
+        self.documents
+            .insert(uri.to_string(), Document::new(text, true));
+    }
+
+    /// Index the source files under `root`, keeping open documents
+    fn index_workspace(&mut self, root: &Path) {
+        let mut files = Vec::new();
+        source_files(root, &mut files);
+        log::debug!(
+            "Indexed {} source file(s) in {}",
+            files.len(),
+            root.display()
+        );
+
+        for path in files {
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let uri = path_to_uri(&path);
+            if !self.documents.get(&uri).is_some_and(|d| d.open) {
+                self.documents.insert(uri, Document::new(text, false));
+            }
+        }
+    }
+
+    /// Document URI and the word under the cursor of a position request
+    fn word_at(&self, params: &Value) -> std::result::Result<(String, String), (i64, String)> {
+        let invalid = || {
+            (
+                INVALID_PARAMS,
+                "Expected a text document position".to_string(),
+            )
+        };
+        let uri = params["textDocument"]["uri"].as_str().ok_or_else(invalid)?;
+        let line = params["position"]["line"].as_u64().ok_or_else(invalid)?;
+        let character = params["position"]["character"]
+            .as_u64()
+            .ok_or_else(invalid)?;
+
+        let word = self
+            .documents
+            .get(uri)
+            .and_then(|doc| {
+                let line = line as usize;
+                let character = char_index(doc.text.lines().nth(line)?, character as usize);
+                word_at(&doc.text, line, character)
+            })
+            .unwrap_or_default();
+        Ok((uri.to_string(), word))
+    }
+
+    /// Definitions of `word`, those in `uri` first
+    fn find(&self, uri: &str, word: &str) -> Vec<(&str, &Definition)> {
+        let mut found: Vec<(&str, &Definition)> = Vec::new();
+        let documents = self.documents.get_key_value(uri).into_iter().chain(
+            self.documents
+                .iter()
+                .filter(|(other, _)| other.as_str() != uri),
+        );
+        for (doc_uri, doc) in documents {
+            for def in &doc.definitions {
+                if def.name.eq_ignore_ascii_case(word) {
+                    found.push((doc_uri, def));
+                }
+            }
+        }
+        found
+    }
+
+    fn hover(&self, uri: &str, word: &str) -> Value {
+        if word.is_empty() {
+            return Value::Null;
+        }
+        let value = match self.find(uri, word).first() {
+            Some((def_uri, def)) => {
+                let mut value = format!("```forth\n{}\n```", def.text);
+                if let Some(doc) = &def.doc {
+                    value.push_str(&format!("\n\n{}", doc));
+                }
+                let file = uri_to_path(def_uri)
+                    .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                    .unwrap_or_else(|| def_uri.to_string());
+                value.push_str(&format!("\n\nDefined in {}:{}", file, def.line + 1));
+                value
+            }
+            None => match core_word(word) {
+                Some((name, effect)) => format!("```forth\n{} {}\n```\n\nCore word", name, effect),
+                None => return Value::Null,
+            },
+        };
+        json!({ "contents": { "kind": "markdown", "value": value } })
+    }
+
+    fn completions(&self) -> Value {
+        let mut items: Vec<Value> = CORE_WORDS
+            .iter()
+            .map(|(name, effect)| json!({ "label": name, "kind": KIND_KEYWORD, "detail": effect }))
+            .collect();
+
+        let mut seen: Vec<String> = CORE_WORDS.iter().map(|(n, _)| n.to_string()).collect();
+        for (uri, doc) in &self.documents {
+            for def in &doc.definitions {
+                let key = def.name.to_ascii_uppercase();
+                if seen.contains(&key) {
+                    continue;
+                }
+                seen.push(key);
+                let file = uri_to_path(uri)
+                    .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                    .unwrap_or_default();
+                items.push(json!({
+                    "label": def.name,
+                    "kind": KIND_FUNCTION,
+                    "detail": format!("{}:{}", file, def.line + 1),
+                }));
+            }
+        }
+        Value::Array(items)
+    }
+
+    /// `publishDiagnostics` for a document, compiled with its includes
+    fn diagnostics(&self, uri: &str) -> Value {
+        let Some(doc) = self.documents.get(uri) else {
+            return publish(uri, Vec::new());
+        };
+        let path = uri_to_path(uri).unwrap_or_else(|| PathBuf::from(uri));
+
+        let loaded = match source::load_text(&doc.text, &path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                return publish(
+                    uri,
+                    vec![diagnostic(0, 0, 0, SEVERITY_ERROR, e.to_string())],
+                );
+            }
+        };
+        let compiled = Compiler::new()
+            .and_then(|mut compiler| compiler.compile(&loaded.text))
+            .map(|result| result.words.into_iter().map(|w| w.name).collect())
+            .map_err(|message| CompileError::parse(&message));
+
+        let diagnostics = compile::source_diagnostics(&loaded, compiled, false)
+            .into_iter()
+            .map(|d| {
+                let severity = match d.severity {
+                    Severity::Error => SEVERITY_ERROR,
+                    Severity::Warning => SEVERITY_WARNING,
+                };
+                match (d.line, Path::new(&d.file) == path) {
+                    (Some(line), true) => {
+                        let (line, column) = (line - 1, d.column.unwrap_or(1) - 1);
+                        let text = doc.text.lines().nth(line).unwrap_or_default();
+                        let len = word_at(&doc.text, line, column).map_or(0, |w| utf16_len(&w));
+                        let character = utf16_offset(text, column);
+                        diagnostic(line, character, len, severity, d.message)
+                    }
+                    // In an included file: point at the top of the document
+                    (Some(line), false) => diagnostic(
+                        0,
+                        0,
+                        0,
+                        severity,
+                        format!("{}:{}: {}", d.file, line, d.message),
+                    ),
+                    (None, _) => diagnostic(0, 0, 0, severity, d.message),
+                }
+            })
+            .collect();
+        publish(uri, diagnostics)
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn diagnostic(line: usize, character: usize, len: usize, severity: u8, message: String) -> Value {
+    json!({
+        "range": range(line, character, len),
+        "severity": severity,
+        "source": "v4",
+        "message": message,
+    })
+}
+
+/// Range within one line; `character` and `len` in UTF-16 code units
+fn range(line: usize, character: usize, len: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": character },
+        "end": { "line": line, "character": character + len },
+    })
+}
+
+fn core_word(word: &str) -> Option<(&'static str, &'static str)> {
+    CORE_WORDS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(word))
+        .copied()
+}
+
+/// Word definitions (`: NAME ... ;`) in source text
+fn definitions(text: &str) -> Vec<Definition> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut found = Vec::new();
+    let mut tokens = compile::tokens(text);
+
+    while let Some((start, _, token)) = tokens.next() {
+        if token != ":" {
+            continue;
+        }
+        let Some((line, column, name)) = tokens.next() else {
+            break;
+        };
+        let end = tokens
+            .by_ref()
+            .find(|(_, _, token)| *token == ";")
+            .map_or(line, |(end, _, _)| end);
+
+        let shown = &lines[start - 1..end.min(start - 1 + HOVER_MAX_LINES)];
+        let doc: Vec<&str> = lines[..start - 1]
+            .iter()
+            .rev()
+            .map(|l| l.trim())
+            .take_while(|l| *l == "\\" || l.starts_with("\\ "))
+            .map(|l| l.trim_start_matches('\\').trim())
+            .collect();
+
+        found.push(Definition {
+            name: name.to_string(),
+            line: line - 1,
+            character: utf16_offset(lines[line - 1], column - 1),
+            text: shown.join("\n"),
+            doc: (!doc.is_empty()).then(|| doc.into_iter().rev().collect::<Vec<_>>().join("\n")),
+        });
+    }
+    found
+}
+
+// LSP positions count UTF-16 code units, the tokenizer and compiler count chars
+
+/// Char index of the UTF-16 offset `character` in `line`
+fn char_index(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.chars().enumerate() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count()
+}
+
+/// UTF-16 offset of the char at index `chars` in `line`
+fn utf16_offset(line: &str, chars: usize) -> usize {
+    line.chars().take(chars).map(char::len_utf16).sum()
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Whitespace-delimited word at a 0-based char position
+fn word_at(text: &str, line: usize, character: usize) -> Option<String> {
+    let chars: Vec<char> = text.lines().nth(line)?.chars().collect();
+    let at = character.min(chars.len());
+    let start = chars[..at]
+        .iter()
+        .rposition(|c| c.is_whitespace())
+        .map_or(0, |i| i + 1);
+    let end = chars[at..]
+        .iter()
+        .position(|c| c.is_whitespace())
+        .map_or(chars.len(), |i| at + i);
+    (start < end).then(|| chars[start..end].iter().collect())
+}
+
+/// Workspace folder from `initialize` parameters
+fn workspace_root(params: &Value) -> Option<PathBuf> {
+    params["workspaceFolders"][0]["uri"]
+        .as_str()
+        .or_else(|| params["rootUri"].as_str())
+        .and_then(uri_to_path)
+        .or_else(|| params["rootPath"].as_str().map(PathBuf::from))
+}
+
+/// Source files under `dir`, skipping hidden directories, `target` and symlinks
+pub(crate) fn source_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if name.to_string_lossy().starts_with('.') || name == "target" {
+            continue;
+        }
+        let path = entry.path();
+        if file_type.is_dir() {
+            source_files(&path, files);
+        } else if file_type.is_file() && path.extension() == Some(OsStr::new(SOURCE_EXTENSION)) {
+            files.push(path);
+        }
+    }
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = (b == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Read one `Content-Length` framed message body, `None` at end of input
+fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length =
+        length.ok_or_else(|| V4Error::Lsp("Message without Content-Length header".to_string()))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_message(&mut out, &message).unwrap();
+        out
+    }
+
+    /// Run a session and return the messages the server sent
+    fn session(messages: Vec<Value>) -> (Result<()>, Vec<Value>) {
+        let input: Vec<u8> = messages.into_iter().flat_map(frame).collect();
+        let mut output = Vec::new();
+        let result = serve(&mut input.as_slice(), &mut output);
+
+        let mut sent = Vec::new();
+        let mut reader = output.as_slice();
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            sent.push(serde_json::from_slice(&body).unwrap());
+        }
+        (result, sent)
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn position(uri: &str, line: u64, character: u64) -> Value {
+        json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } })
+    }
+
+    #[test]
+    fn test_session() {
+        let uri = "file:///work/app.v4";
+        let text = ": SQ DUP * ;\n\\ x^3\n: CUBE DUP SQ * ;\n3 CUBE NOPE\n";
+        let (result, sent) = session(vec![
+            request(1, "initialize", json!({})),
+            notification(
+                "textDocument/didOpen",
+                json!({ "textDocument": { "uri": uri, "languageId": "forth", "version": 1, "text": text } }),
+            ),
+            request(2, "textDocument/hover", position(uri, 3, 3)),
+            request(3, "textDocument/definition", position(uri, 2, 12)),
+            request(4, "textDocument/completion", position(uri, 3, 0)),
+            request(5, "workspace/symbol", json!({})),
+            request(6, "shutdown", Value::Null),
+            notification("exit", Value::Null),
+        ]);
+        result.unwrap();
+
+        assert_eq!(sent[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        let diagnostics = &sent[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["message"], "Unknown word: NOPE");
+        assert_eq!(diagnostics[0]["range"], range(3, 7, 4));
+
+        let hover = sent[2]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains(": CUBE DUP SQ * ;"), "{}", hover);
+        assert!(hover.contains("x^3"), "{}", hover);
+        assert!(hover.contains("app.v4:3"), "{}", hover);
+
+        assert_eq!(sent[3]["result"][0]["range"], range(0, 2, 2));
+
+        let labels: Vec<&str> = sent[4]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect();
+        assert!(labels.contains(&"DUP") && labels.contains(&"CUBE"));
+
+        assert_eq!(sent[5]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(sent[6]["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_positions_count_utf16_code_units() {
+        let uri = "file:///work/app.v4";
+        // The emoji is one char but two UTF-16 code units
+        let text = ": SQ DUP * ;\n( 😀 ) SQ DUP\n( 😀 ) : CUBE DUP SQ * ;\nCUBE\n";
+        let (result, sent) = session(vec![
+            notification(
+                "textDocument/didOpen",
+                json!({ "textDocument": { "uri": uri, "text": text } }),
+            ),
+            // Right after SQ; as a char index this would be the D of DUP
+            request(1, "textDocument/hover", position(uri, 1, 9)),
+            request(2, "textDocument/definition", position(uri, 3, 0)),
+        ]);
+        result.unwrap();
+
+        let hover = sent[1]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains(": SQ DUP * ;"), "{}", hover);
+        assert_eq!(sent[2]["result"][0]["range"], range(2, 9, 4));
+
+        assert_eq!(char_index("( 😀 ) SQ", 7), 6);
+        assert_eq!(utf16_offset("( 😀 ) SQ", 6), 7);
+        assert_eq!(char_index("SQ", 10), 2);
+    }
+
+    #[test]
+    fn test_workspace_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("lib.v4"), ": BLINK 1 ;\n").unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/old.v4"), ": BLINK 2 ;\n").unwrap();
+        let app = path_to_uri(&dir.path().join("app.v4"));
+        let lib = path_to_uri(&dir.path().join("lib.v4"));
+
+        let (result, sent) = session(vec![
+            request(
+                1,
+                "initialize",
+                json!({ "rootUri": path_to_uri(dir.path()) }),
+            ),
+            notification(
+                "textDocument/didOpen",
+                json!({ "textDocument": { "uri": app, "text": "INCLUDE \"lib.v4\"\nBLINK\n" } }),
+            ),
+            request(2, "textDocument/definition", position(&app, 1, 0)),
+        ]);
+        // End of input without shutdown is not an error
+        result.unwrap();
+
+        assert_eq!(sent[1]["params"]["diagnostics"], json!([]));
+        let locations = sent[2]["result"].as_array().unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0]["uri"], lib);
+    }
+
+    #[test]
+    fn test_exit_without_shutdown_fails() {
+        let (result, _) = session(vec![notification("exit", Value::Null)]);
+        assert!(matches!(result, Err(V4Error::Lsp(_))));
+    }
+
+    #[test]
+    fn test_uri_round_trip() {
+        let path = Path::new("/tmp/my project/app.v4");
+        let uri = path_to_uri(path);
+        assert_eq!(uri, "file:///tmp/my%20project/app.v4");
+        assert_eq!(uri_to_path(&uri).unwrap(), path);
+        assert_eq!(uri_to_path("untitled:1"), None);
+    }
+
+    #[test]
+    fn test_word_at() {
+        assert_eq!(word_at("3 CUBE .", 0, 3).as_deref(), Some("CUBE"));
+        assert_eq!(word_at("3 CUBE .", 0, 2).as_deref(), Some("CUBE"));
+        assert_eq!(word_at("3 CUBE .", 0, 8).as_deref(), Some("."));
+        assert_eq!(word_at("3  CUBE", 0, 2), None);
+        assert_eq!(word_at("3", 4, 0), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Compare two memory snapshots (`v4 memdiff`, `.diff`)
+//!
+//! Snapshots are compared byte by byte in rows of 16, as `.dump` shows
+//! them. Rows with a change are printed twice, before (`-`) and after (`+`),
+//! and neighbouring changed rows form one hunk.
+
+use crate::commands::dump::printable;
+use crate::{Result, V4Error, ui};
+use std::fmt::Write;
+use std::fs;
+
+/// Bytes per row, as in hexdumps
+const ROW: usize = 16;
+
+/// Two snapshots of the same memory range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemDiff {
+    /// Address of the first byte of both snapshots
+    pub addr: u32,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+/// Run of consecutive rows with changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    /// Offset of the first row
+    pub offset: usize,
+    pub rows: usize,
+    /// Bytes that differ within the rows
+    pub changed: usize,
+}
+
+impl MemDiff {
+    /// Byte at `offset` differs; a byte only one snapshot has counts
+    fn differs(&self, offset: usize) -> bool {
+        self.old.get(offset) != self.new.get(offset)
+    }
+
+    fn len(&self) -> usize {
+        self.old.len().max(self.new.len())
+    }
+
+    /// Bytes that differ
+    pub fn changed(&self) -> usize {
+        (0..self.len()).filter(|&i| self.differs(i)).count()
+    }
+
+    /// Changed rows, merged where they touch
+    pub fn hunks(&self) -> Vec<Hunk> {
+        let mut hunks: Vec<Hunk> = Vec::new();
+        for offset in (0..self.len()).step_by(ROW) {
+            let end = (offset + ROW).min(self.len());
+            let changed = (offset..end).filter(|&i| self.differs(i)).count();
+            if changed == 0 {
+                continue;
+            }
+            match hunks.last_mut() {
+                Some(hunk) if hunk.offset + hunk.rows * ROW == offset => {
+                    hunk.rows += 1;
+                    hunk.changed += changed;
+                }
+                _ => hunks.push(Hunk {
+                    offset,
+                    rows: 1,
+                    changed,
+                }),
+            }
+        }
+        hunks
+    }
+}
+
+/// Load two snapshot files taken at `addr`
+pub fn memdiff(old: &str, new: &str, addr: u32) -> Result<MemDiff> {
+    let read = |path: &str| {
+        fs::read(path).map_err(|e| V4Error::Cli(format!("Cannot read {}: {}", path, e)))
+    };
+    Ok(MemDiff {
+        addr,
+        old: read(old)?,
+        new: read(new)?,
+    })
+}
+
+/// Changed rows as `-`/`+` hexdump pairs under a header per hunk
+///
+/// Changed bytes are highlighted. Bytes only one snapshot has are left
+/// blank in the other.
+pub fn format(diff: &MemDiff) -> String {
+    let mut out = String::new();
+    for hunk in diff.hunks() {
+        let addr = diff.addr.wrapping_add(hunk.offset as u32);
+        let _ = writeln!(out, "@@ 0x{:08X} ({} byte(s) changed)", addr, hunk.changed);
+        for row in 0..hunk.rows {
+            let offset = hunk.offset + row * ROW;
+            out.push_str(&diff_row(diff, offset, false));
+            out.push_str(&diff_row(diff, offset, true));
+        }
+    }
+    out
+}
+
+/// One row of either snapshot, marked `-` (old) or `+` (new)
+fn diff_row(diff: &MemDiff, offset: usize, new: bool) -> String {
+    let (marker, bytes) = match new {
+        false => ('-', &diff.old),
+        true => ('+', &diff.new),
+    };
+    let highlight = |text: String, i: usize| match (diff.differs(i), new) {
+        (false, _) => text,
+        (true, false) => ui::removed(text).to_string(),
+        (true, true) => ui::added(text).to_string(),
+    };
+    let addr = diff.addr.wrapping_add(offset as u32);
+    let mut line = format!("{} {}  ", marker, ui::address(format!("{:08X}", addr)));
+    let mut ascii = String::new();
+    for i in offset..offset + ROW {
+        match bytes.get(i) {
+            Some(&byte) => {
+                line.push_str(&highlight(format!("{:02X}", byte), i));
+                ascii.push_str(&highlight(printable(byte).to_string(), i));
+            }
+            None => line.push_str("  "),
+        }
+        line.push(' ');
+        if i - offset == 7 {
+            line.push(' ');
+        }
+    }
+    let _ = writeln!(line, " |{}|", ascii);
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshots(old: &[u8], new: &[u8]) -> MemDiff {
+        MemDiff {
+            addr: 0x2000,
+            old: old.to_vec(),
+            new: new.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_hunks_merge_adjacent_rows() {
+        let old = [0u8; 80];
+        let mut new = old;
+        new[3] = 1;
+        new[17] = 2;
+        new[18] = 3;
+        new[70] = 4;
+        let diff = snapshots(&old, &new);
+        assert_eq!(diff.changed(), 4);
+        assert_eq!(
+            diff.hunks(),
+            vec![
+                Hunk {
+                    offset: 0,
+                    rows: 2,
+                    changed: 3
+                },
+                Hunk {
+                    offset: 64,
+                    rows: 1,
+                    changed: 1
+                },
+            ]
+        );
+        assert!(snapshots(&old, &old).hunks().is_empty());
+    }
+
+    #[test]
+    fn test_format_shows_both_sides() {
+        let diff = snapshots(b"Hello, V4!", b"Hello, v4");
+        let text = console::strip_ansi_codes(&format(&diff)).into_owned();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "@@ 0x00002000 (2 byte(s) changed)");
+        assert_eq!(
+            lines[1],
+            "- 00002000  48 65 6C 6C 6F 2C 20 56  34 21                    |Hello, V4!|"
+        );
+        assert_eq!(
+            lines[2],
+            "+ 00002000  48 65 6C 6C 6F 2C 20 76  34                       |Hello, v4|"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::Result;
+use crate::monitor::{Decoder, Event};
+use crate::serial::SerialSettings;
+use crate::transport;
+use std::time::Duration;
+
+/// Read timeout per poll of the port
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Pass every chunk of bytes received from the device to `on_data`
+///
+/// Only reads; nothing is sent to the device. Runs until an error occurs
+/// or `on_data` returns `false`.
+pub fn monitor_raw(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    on_data: &mut dyn FnMut(&[u8]) -> bool,
+) -> Result<()> {
+    let (mut transport, port) = transport::open(port, settings)?;
+    log::info!("Monitoring {} (Ctrl+C to stop)", port);
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = transport.read_raw(&mut buf, POLL_TIMEOUT)?;
+        if n > 0 && !on_data(&buf[..n]) {
+            return Ok(());
+        }
+    }
+}
+
+/// Decode the device's stream into frames and text lines
+///
+/// See [`monitor_raw`]; `on_event` returns `false` to stop.
+pub fn monitor(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    on_event: &mut dyn FnMut(&Event) -> bool,
+) -> Result<()> {
+    let mut decoder = Decoder::new();
+    monitor_raw(port, settings, &mut |data| {
+        decoder.push(data).iter().all(&mut *on_event)
+    })
+}
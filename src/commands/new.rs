@@ -0,0 +1,61 @@
+use crate::Result;
+use crate::V4Error;
+use crate::project::{self, Template};
+use std::path::{Path, PathBuf};
+
+/// Result of `v4 new` / `v4 init`
+#[derive(Debug)]
+pub struct NewReport {
+    /// Project directory
+    pub root: PathBuf,
+    /// Project name written to the manifest
+    pub name: String,
+    /// Files created, relative to `root`
+    pub created: Vec<PathBuf>,
+}
+
+/// Options for a new project's manifest
+#[derive(Debug, Default, Clone)]
+pub struct NewOptions {
+    /// Project name (defaults to the directory name)
+    pub name: Option<String>,
+    /// Port written to `[deploy]`
+    pub port: Option<String>,
+    /// Baud rate written to `[deploy]`
+    pub baud_rate: Option<u32>,
+}
+
+/// Create a project in a new directory
+pub fn new_project(path: &str, options: &NewOptions) -> Result<NewReport> {
+    let root = Path::new(path);
+    if root.exists() {
+        return Err(V4Error::Project(format!(
+            "Destination {} already exists; use `v4 init` to turn it into a project",
+            root.display()
+        )));
+    }
+    scaffold(root, options)
+}
+
+/// Turn an existing directory into a project, keeping its files
+pub fn init(path: &str, options: &NewOptions) -> Result<NewReport> {
+    scaffold(Path::new(path), options)
+}
+
+fn scaffold(root: &Path, options: &NewOptions) -> Result<NewReport> {
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => project::name_from_dir(root)?,
+    };
+    let template = Template {
+        name: name.clone(),
+        port: options.port.clone(),
+        baud_rate: options.baud_rate,
+    };
+    let created = project::scaffold(root, &template)?;
+    Ok(NewReport {
+        root: root.to_path_buf(),
+        name,
+        created,
+    })
+}
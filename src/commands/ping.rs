@@ -1,25 +1,19 @@
 use crate::Result;
-use crate::protocol::ErrorCode;
-use crate::serial::V4Serial;
+use crate::device::V4Device;
+use crate::serial::SerialSettings;
+use crate::transport::RetryPolicy;
 use std::time::Duration;
 
 /// Send PING command to device
-pub fn ping(port: &str, timeout: Duration) -> Result<()> {
-    let mut serial = V4Serial::open_default(port)?;
-
-    println!("Sending PING to {}...", port);
-
-    let err_code = serial.ping(timeout)?;
-
-    println!("Response: {}", err_code.name());
-
-    if err_code == ErrorCode::Ok {
-        println!("✓ Device is responding");
-        Ok(())
-    } else {
-        Err(crate::V4Error::Device(format!(
-            "Device returned error: {}",
-            err_code.name()
-        )))
-    }
+///
+/// Returns the port the device answered on.
+pub fn ping(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    timeout: Duration,
+) -> Result<String> {
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    device.ping(timeout)?;
+    Ok(device.port().to_string())
 }
@@ -0,0 +1,162 @@
+use crate::Result;
+use crate::serial::{SerialSettings, V4Serial};
+use serde::Serialize;
+use serialport::{SerialPortInfo, SerialPortType};
+use std::time::Duration;
+
+/// PING timeout used when probing ports for V4 devices
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// How long `v4 ports --ble` listens for advertisements
+#[cfg(feature = "ble")]
+const BLE_SCAN_DURATION: Duration = Duration::from_secs(3);
+
+/// Serial port candidate
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortEntry {
+    pub path: String,
+    /// Connection type: "usb", "pci", "bluetooth", "ble" or "unknown"
+    pub kind: &'static str,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// Whether the port answered PING (`None` when not probed)
+    pub v4_device: Option<bool>,
+}
+
+impl From<&SerialPortInfo> for PortEntry {
+    fn from(info: &SerialPortInfo) -> Self {
+        let mut entry = PortEntry {
+            path: info.port_name.clone(),
+            kind: "unknown",
+            vid: None,
+            pid: None,
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            v4_device: None,
+        };
+
+        match &info.port_type {
+            SerialPortType::UsbPort(usb) => {
+                entry.kind = "usb";
+                entry.vid = Some(usb.vid);
+                entry.pid = Some(usb.pid);
+                entry.manufacturer = usb.manufacturer.clone();
+                entry.product = usb.product.clone();
+                entry.serial_number = usb.serial_number.clone();
+            }
+            SerialPortType::PciPort => entry.kind = "pci",
+            SerialPortType::BluetoothPort => entry.kind = "bluetooth",
+            SerialPortType::Unknown => {}
+        }
+
+        entry
+    }
+}
+
+impl PortEntry {
+    /// One-line human-readable description
+    pub fn describe(&self) -> String {
+        let mut line = self.path.clone();
+
+        match (self.vid, self.pid) {
+            (Some(vid), Some(pid)) => line.push_str(&format!("  USB {:04x}:{:04x}", vid, pid)),
+            _ => line.push_str(&format!("  {}", self.kind.to_uppercase())),
+        }
+        for text in [&self.manufacturer, &self.product].into_iter().flatten() {
+            line.push_str(&format!("  {}", text));
+        }
+        if let Some(serial) = &self.serial_number {
+            line.push_str(&format!("  (S/N {})", serial));
+        }
+        if self.v4_device == Some(true) {
+            line.push_str("  [V4 device]");
+        }
+
+        line
+    }
+}
+
+/// Enumerate serial ports, optionally probing each with PING
+pub fn list_ports(probe: bool, settings: &SerialSettings) -> Result<Vec<PortEntry>> {
+    let mut entries: Vec<PortEntry> = serialport::available_ports()?
+        .iter()
+        .map(PortEntry::from)
+        .collect();
+
+    if probe {
+        for entry in &mut entries {
+            entry.v4_device = Some(V4Serial::probe(&entry.path, settings, PROBE_TIMEOUT));
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Scan for Bluetooth LE devices advertising the UART service
+#[cfg(feature = "ble")]
+pub fn list_ble_devices() -> Result<Vec<PortEntry>> {
+    let devices = crate::ble::scan(BLE_SCAN_DURATION)?;
+    Ok(devices
+        .into_iter()
+        .map(|device| PortEntry {
+            path: format!("{}{}", crate::transport::BLE_SCHEME, device.address),
+            kind: "ble",
+            vid: None,
+            pid: None,
+            manufacturer: None,
+            product: device.name,
+            serial_number: None,
+            v4_device: None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb_entry() -> PortEntry {
+        PortEntry {
+            path: "/dev/ttyACM0".to_string(),
+            kind: "usb",
+            vid: Some(0x303a),
+            pid: Some(0x1001),
+            manufacturer: Some("Espressif".to_string()),
+            product: None,
+            serial_number: Some("AB:CD".to_string()),
+            v4_device: Some(true),
+        }
+    }
+
+    #[test]
+    fn test_describe_usb() {
+        assert_eq!(
+            usb_entry().describe(),
+            "/dev/ttyACM0  USB 303a:1001  Espressif  (S/N AB:CD)  [V4 device]"
+        );
+    }
+
+    #[test]
+    fn test_from_pci_info() {
+        let info = SerialPortInfo {
+            port_name: "/dev/ttyS0".to_string(),
+            port_type: SerialPortType::PciPort,
+        };
+        let entry = PortEntry::from(&info);
+        assert_eq!(entry.kind, "pci");
+        assert_eq!(entry.describe(), "/dev/ttyS0  PCI");
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let json = serde_json::to_value(usb_entry()).unwrap();
+        assert_eq!(json["path"], "/dev/ttyACM0");
+        assert_eq!(json["vid"], 0x303a);
+        assert_eq!(json["v4_device"], true);
+        assert!(json["product"].is_null());
+    }
+}
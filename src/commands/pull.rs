@@ -0,0 +1,141 @@
+//! Read the dictionary back from a device (`v4 pull`)
+//!
+//! Words are queried from index 0 until the device reports an unused index.
+//! The result is a .v4b file without main code, whose word section lists the
+//! definitions in index order, so pushing it into a reset VM gives each word
+//! its old index again.
+
+use crate::device::V4Device;
+use crate::emit;
+use crate::protocol::WordInfo;
+use crate::serial::SerialSettings;
+use crate::transport::RetryPolicy;
+use crate::{Result, V4Error};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Read all word definitions from the device
+///
+/// Fails if the device sent less bytecode for a word than it reported, since
+/// the backup would silently miss code.
+pub fn pull(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    timeout: Duration,
+) -> Result<Vec<WordInfo>> {
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    let words = device.words(timeout)?;
+    check_complete(&words)?;
+    Ok(words)
+}
+
+fn check_complete(words: &[WordInfo]) -> Result<()> {
+    match words
+        .iter()
+        .enumerate()
+        .find(|(_, word)| word.code.len() < word.code_len)
+    {
+        Some((idx, word)) => Err(V4Error::Protocol(format!(
+            "Word #{} ({}) has {} bytes of bytecode, but the device sent only {}",
+            idx,
+            word.display_name(),
+            word.code_len,
+            word.code.len()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Encode words as a .v4b file with an empty main code section
+pub fn to_v4b(words: &[WordInfo]) -> Vec<u8> {
+    emit::v4b_image(
+        &[],
+        words
+            .iter()
+            .map(|word| (word.name.as_deref().unwrap_or_default(), &word.code[..])),
+    )
+}
+
+/// Write each word's bytecode to `<dir>/<index>-<name>.bin`
+///
+/// Returns the files written, in index order.
+pub fn write_split(words: &[WordInfo], dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut files = Vec::with_capacity(words.len());
+    for (idx, word) in words.iter().enumerate() {
+        let path = dir.join(word_file_name(idx, word));
+        fs::write(&path, &word.code)?;
+        files.push(path);
+    }
+    Ok(files)
+}
+
+/// File name for a word; characters unsafe in paths become `_`
+fn word_file_name(idx: usize, word: &WordInfo) -> String {
+    match &word.name {
+        Some(name) => {
+            let name: String = name
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+                    true => c,
+                    false => '_',
+                })
+                .collect();
+            format!("{:03}-{}.bin", idx, name)
+        }
+        None => format!("{:03}.bin", idx),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::inspect;
+
+    fn word(name: Option<&str>, code: &[u8]) -> WordInfo {
+        WordInfo {
+            name: name.map(str::to_string),
+            code_len: code.len(),
+            code: code.to_vec(),
+            address: None,
+        }
+    }
+
+    #[test]
+    fn test_v4b_roundtrips_through_inspect() {
+        let words = [word(Some("SQ"), &[0x01, 0x12, 0x51]), word(None, &[0x51])];
+        let inspection = inspect::parse(&to_v4b(&words)).unwrap();
+        assert_eq!(inspection.header.word_count, Some(2));
+        assert!(inspection.code.is_empty());
+        assert_eq!(inspection.words[0].name, "SQ");
+        assert_eq!(inspection.words[0].bytecode, vec![0x01, 0x12, 0x51]);
+        assert_eq!(inspection.words[1].name, "");
+    }
+
+    #[test]
+    fn test_truncated_word_is_an_error() {
+        let mut long = word(Some("BIG"), &[0; 8]);
+        long.code_len = 600;
+        assert!(check_complete(&[word(Some("SQ"), &[0x51])]).is_ok());
+        assert!(matches!(
+            check_complete(&[word(Some("SQ"), &[0x51]), long]),
+            Err(V4Error::Protocol(msg)) if msg.starts_with("Word #1 (BIG)")
+        ));
+    }
+
+    #[test]
+    fn test_split_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let words = [
+            word(Some("LED-ON"), &[0x51]),
+            word(Some("+!"), &[]),
+            word(None, &[0x51]),
+        ];
+        let files = write_split(&words, dir.path()).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap()).collect();
+        assert_eq!(names, ["000-LED-ON.bin", "001-__.bin", "002.bin"]);
+        assert_eq!(fs::read(&files[0]).unwrap(), vec![0x51]);
+    }
+}
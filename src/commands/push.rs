@@ -1,14 +1,130 @@
-use crate::Result;
-use crate::protocol::ErrorCode;
-use crate::serial::V4Serial;
-use indicatif::{ProgressBar, ProgressStyle};
+use super::inspect;
+use crate::bytecode;
+use crate::device::{PushReport, V4Device};
+use crate::ffi::WordDef;
+use crate::protocol::WordInfo;
+use crate::serial::SerialSettings;
+use crate::sourcemap::{self, SourceMap};
+use crate::transport::RetryPolicy;
+use crate::{Result, V4Error};
 use std::fs;
 use std::path::Path;
 use std::time::Duration;
 
 /// Push bytecode to device
-pub fn push(file: &str, port: &str, detach: bool, timeout: Duration) -> Result<()> {
-    // Read bytecode file
+///
+/// `on_progress` receives the bytes acknowledged so far and the total size.
+/// `force` pushes a file the device's VM can't read, see [`check_version`].
+pub fn push(
+    file: &str,
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    force: bool,
+    timeout: Duration,
+    on_progress: &mut dyn FnMut(usize, usize),
+) -> Result<PushReport> {
+    let file_data = read_bytecode(file)?;
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    warn_compatibility(&check_version(&mut device, &file_data, force, timeout)?);
+    let total = file_data.len();
+    let result = device.push(&file_data, timeout, &mut |sent| on_progress(sent, total));
+    locate_fault(&mut device, file, result, timeout)
+}
+
+/// Push a bytecode file over an already open device, whatever its transport
+pub fn push_file(
+    device: &mut V4Device,
+    file: &str,
+    force: bool,
+    timeout: Duration,
+    on_progress: &mut dyn FnMut(usize, usize),
+) -> Result<PushReport> {
+    let file_data = read_bytecode(file)?;
+    warn_compatibility(&check_version(device, &file_data, force, timeout)?);
+    let total = file_data.len();
+    let result = device.push(&file_data, timeout, &mut |sent| on_progress(sent, total));
+    locate_fault(device, file, result, timeout)
+}
+
+/// Whether the device's VM can run a .v4b file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compatibility {
+    /// The VM reads the file's format version
+    Compatible,
+    /// The device didn't report its VM version, so the file wasn't checked
+    Unchecked(String),
+    /// The VM can't read the format version; pushed anyway with `--force`
+    Forced(String),
+}
+
+impl Compatibility {
+    /// Warning to show before pushing, if any
+    pub fn warning(&self) -> Option<String> {
+        match self {
+            Compatibility::Compatible => None,
+            Compatibility::Unchecked(reason) => Some(format!("VM version not checked: {}", reason)),
+            Compatibility::Forced(reason) => Some(format!("{}; pushing anyway (--force)", reason)),
+        }
+    }
+}
+
+/// Check a .v4b file against the VM of the connected device
+///
+/// Validates the header, then asks the device for its VM version with
+/// QUERY_INFO. A format version the VM can't read fails unless `force` is
+/// set. Devices that don't answer QUERY_INFO aren't checked.
+pub fn check_version(
+    device: &mut V4Device,
+    file_data: &[u8],
+    force: bool,
+    timeout: Duration,
+) -> Result<Compatibility> {
+    let header = bytecode::validate(file_data)?;
+    let info = match device.info(timeout) {
+        Ok(info) => info,
+        Err(e) => return Ok(Compatibility::Unchecked(e.to_string())),
+    };
+    match (header.incompatibility(info.vm_version), force) {
+        (None, _) => Ok(Compatibility::Compatible),
+        (Some(reason), true) => Ok(Compatibility::Forced(reason)),
+        (Some(reason), false) => Err(V4Error::Device(format!(
+            "{} (use --force to push anyway)",
+            reason
+        ))),
+    }
+}
+
+fn warn_compatibility(compatibility: &Compatibility) {
+    if let Some(warning) = compatibility.warning() {
+        log::warn!("{}", warning);
+    }
+}
+
+/// Point a VM fault at the source if the file has a source map next to it
+fn locate_fault(
+    device: &mut V4Device,
+    file: &str,
+    result: Result<PushReport>,
+    timeout: Duration,
+) -> Result<PushReport> {
+    let Err(fault @ V4Error::VmFault { .. }) = result else {
+        return result;
+    };
+    let path = SourceMap::path_for(Path::new(file));
+    let map = match path.exists() {
+        true => SourceMap::load(&path).unwrap_or_else(|e| {
+            log::warn!("{}", e);
+            SourceMap::default()
+        }),
+        false => SourceMap::default(),
+    };
+    let (transport, _) = device.parts()?;
+    Err(sourcemap::locate_fault(fault, transport, &map, timeout))
+}
+
+/// Read a bytecode file, naming it if it does not exist
+pub fn read_bytecode(file: &str) -> Result<Vec<u8>> {
     let path = Path::new(file);
     if !path.exists() {
         return Err(crate::V4Error::Io(std::io::Error::new(
@@ -16,82 +132,180 @@ pub fn push(file: &str, port: &str, detach: bool, timeout: Duration) -> Result<(
             format!("Bytecode file not found: {}", file),
         )));
     }
+    Ok(fs::read(path)?)
+}
 
-    let file_data = fs::read(path)?;
-    let file_size = file_data.len();
+/// Way a pushed word differs on the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The device has no word at the index it assigned
+    Missing,
+    /// The device knows the word under another name
+    Name(Option<String>),
+    /// The device reports a different bytecode length
+    Length { sent: usize, device: usize },
+    /// Offsets of the bytes that differ
+    Bytes(Vec<usize>),
+    /// The QUERY_WORD answer held only this many bytes, all matching
+    Truncated { received: usize },
+}
 
-    // .v4b files have a 16-byte header: "V4BC" + metadata
-    // V4-link expects raw bytecode only, so skip the header
-    const HEADER_SIZE: usize = 16;
+/// Pushed word that doesn't read back as sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub index: u16,
+    pub name: String,
+    pub problem: Problem,
+}
 
-    if file_size < HEADER_SIZE {
-        return Err(crate::V4Error::Protocol(
-            "File too small to contain V4 bytecode header".to_string(),
-        ));
-    }
+/// Result of reading the pushed words back
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// Words read back
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
 
-    // Verify magic number "V4BC"
-    if &file_data[0..4] != b"V4BC" {
-        return Err(crate::V4Error::Protocol(
-            "Invalid V4 bytecode file (missing V4BC magic number)".to_string(),
-        ));
+impl Verification {
+    /// Fail unless every word matched
+    pub fn check(&self) -> Result<()> {
+        match self.mismatches.len() {
+            0 => Ok(()),
+            n => Err(V4Error::Device(format!(
+                "Verification failed: {} of {} word(s) differ from the file",
+                n, self.checked
+            ))),
+        }
     }
+}
 
-    // Send entire .v4b file (including header)
-    // V4-link v0.2+ parses the header to extract word definitions
-    let bytecode = &file_data;
-    let size = bytecode.len();
-
-    println!(
-        "Loading bytecode from {} ({} bytes total)...",
-        file, size
-    );
+/// Read the words of a pushed .v4b file back and compare them with the file
+///
+/// Words are looked up at the indices the device assigned in `report`. Only
+/// word definitions are checked; the main code runs once and isn't stored.
+pub fn verify(
+    device: &mut V4Device,
+    file_data: &[u8],
+    report: &PushReport,
+    timeout: Duration,
+) -> Result<Verification> {
+    let sent = inspect::parse(file_data)?.words;
+    let mut verification = Verification::default();
+    for (word, &index) in sent.iter().zip(&report.word_indices) {
+        let problem = match device.word(index, timeout)? {
+            None => Some(Problem::Missing),
+            Some(read) => compare(word, &read),
+        };
+        verification.checked += 1;
+        if let Some(problem) = problem {
+            verification.mismatches.push(Mismatch {
+                index,
+                name: word.name.clone(),
+                problem,
+            });
+        }
+    }
+    Ok(verification)
+}
 
-    if size <= HEADER_SIZE {
-        return Err(crate::V4Error::Protocol(
-            "Bytecode file too small".to_string(),
-        ));
+fn compare(sent: &WordDef, read: &WordInfo) -> Option<Problem> {
+    if read.name.as_deref().unwrap_or_default() != sent.name {
+        return Some(Problem::Name(read.name.clone()));
+    }
+    if read.code_len != sent.bytecode.len() {
+        return Some(Problem::Length {
+            sent: sent.bytecode.len(),
+            device: read.code_len,
+        });
+    }
+    let differing: Vec<usize> = (0..read.code.len())
+        .filter(|&i| read.code[i] != sent.bytecode[i])
+        .collect();
+    if !differing.is_empty() {
+        return Some(Problem::Bytes(differing));
     }
+    (read.code.len() < read.code_len).then_some(Problem::Truncated {
+        received: read.code.len(),
+    })
+}
 
-    // Create progress bar
-    let pb = ProgressBar::new(size as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} {msg}")
-            .unwrap()
-            .progress_chars("=>-"),
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use crate::transport::mock::MockTransport;
 
-    // Open serial port
-    let mut serial = V4Serial::open_default(port)?;
+    const TIMEOUT: Duration = Duration::from_millis(10);
 
-    pb.set_message("Sending...");
+    /// v0.2 file with main code RET and the words SQ and TWO
+    fn file() -> Vec<u8> {
+        let mut data = b"V4BC\x00\x02\x00\x00".to_vec();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.push(0x51);
+        data.extend_from_slice(&[2, b'S', b'Q', 3, 0, 0x01, 0x12, 0x51]);
+        data.extend_from_slice(&[3, b'T', b'W', b'O', 1, 0, 0x51]);
+        data
+    }
 
-    // Send EXEC command
-    let response = serial.exec(bytecode, timeout)?;
+    fn verify_with(transport: MockTransport) -> Verification {
+        let mut device = V4Device::from_transport(Box::new(transport), "mock");
+        let report = PushReport {
+            size: file().len(),
+            word_indices: vec![4, 5],
+            compression: None,
+        };
+        verify(&mut device, &file(), &report, TIMEOUT).unwrap()
+    }
 
-    pb.inc(size as u64);
+    #[test]
+    fn test_verify_matching_words() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[2, b'S', b'Q', 3, 0, 0x01, 0x12, 0x51]);
+        transport.push_response(ErrorCode::Ok, &[3, b'T', b'W', b'O', 1, 0, 0x51]);
 
-    if detach {
-        pb.finish_with_message("Sent (detached)");
-        println!("Bytecode sent to device (not waiting for response)");
-        return Ok(());
+        let verification = verify_with(transport);
+        assert_eq!(verification.checked, 2);
+        assert!(verification.check().is_ok());
     }
 
-    pb.finish_with_message("Complete");
+    #[test]
+    fn test_verify_reports_differing_bytes() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[2, b'S', b'Q', 3, 0, 0x01, 0x13, 0x51]);
+        transport.push_response(ErrorCode::Error, &[]);
 
-    println!("Response: {}", response.error_code.name());
+        let verification = verify_with(transport);
+        let problems: Vec<_> = verification.mismatches.iter().map(|m| &m.problem).collect();
+        assert_eq!(problems, [&Problem::Bytes(vec![1]), &Problem::Missing]);
+        assert_eq!(verification.mismatches[1].index, 5);
+        assert!(verification.check().is_err());
+    }
 
-    if response.error_code == ErrorCode::Ok {
-        println!("✓ Bytecode deployed successfully");
-        if !response.word_indices.is_empty() {
-            println!("  Registered {} word(s)", response.word_indices.len());
-        }
-        Ok(())
-    } else {
-        Err(crate::V4Error::Device(format!(
-            "Device returned error: {}",
-            response.error_code.name()
-        )))
+    #[test]
+    fn test_check_version_against_device() {
+        let check = |vm_minor: Option<u8>, force: bool| {
+            let mut transport = MockTransport::new();
+            match vm_minor {
+                Some(minor) => transport.push_response(
+                    ErrorCode::Ok,
+                    &[[1, 0, 0, 0, minor, 3].as_slice(), &[0; 11]].concat(),
+                ),
+                None => transport.push_response(ErrorCode::Error, &[]),
+            }
+            let mut device = V4Device::from_transport(Box::new(transport), "mock");
+            check_version(&mut device, &file(), force, TIMEOUT)
+        };
+
+        assert_eq!(check(Some(2), false).unwrap(), Compatibility::Compatible);
+        let err = check(Some(1), false).unwrap_err().to_string();
+        assert!(err.contains("needs VM v0.2 or newer, the device runs VM v0.1.3"));
+        assert!(err.contains("--force"));
+        assert!(matches!(check(Some(1), true), Ok(Compatibility::Forced(_))));
+        // Firmware without QUERY_INFO gets the file unchecked
+        assert!(matches!(
+            check(None, false),
+            Ok(Compatibility::Unchecked(_))
+        ));
     }
 }
@@ -1,29 +1,126 @@
 use crate::Result;
-use crate::protocol::ErrorCode;
-use crate::repl::{CompileResult, Compiler};
-use crate::serial::V4Serial;
-use rustyline::DefaultEditor;
+use crate::commands::memdiff::{self, MemDiff};
+use crate::commands::{compile, gpio};
+use crate::debugger::{self, Breakpoint, Debugger, Location, Outcome};
+use crate::device::{self, DeviceInfo, Stopwatch, Timing, V4Device};
+use crate::diff;
+use crate::error_explain;
+use crate::ffi::{CompileError, CompileResult, Compiler};
+use crate::format::{self, OutputFormat};
+use crate::highlight::ReplHelper;
+use crate::interrupt;
+use crate::protocol::{ErrorCode, MemoryDump, WordInfo};
+use crate::repl::{WordSource, definition_sources, needs_continuation};
+use crate::session::{RestoreReport, SavedWord, SessionFile};
+use crate::source::{self, LineOrigin, Loaded};
+use crate::sourcemap::{self, SourceMap};
+use crate::transcript::{self, Step, TranscriptEvent, TranscriptWriter};
+use crate::transport::{self, Deadline, Transport};
+use crate::ui;
+use rustyline::Editor;
 use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
 use std::time::Duration;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
-/// Run interactive REPL session
-pub fn run_repl(port: &str, no_reset: bool) -> Result<()> {
-    // Open serial connection
-    let mut serial = V4Serial::open_default(port)?;
+/// Bytes shown by `.dump` when no length is given
+const DUMP_DEFAULT_LEN: u32 = 256;
+
+/// Largest range `.dump` reads at once
+const DUMP_MAX_LEN: u32 = 64 * 1024;
+
+/// How long the REPL waits for a device that went away to come back
+const RECONNECT_WAIT: Duration = Duration::from_secs(30);
+
+/// Largest range `.fill` writes at once
+const FILL_MAX_LEN: u32 = 64 * 1024;
+
+/// Line prefix in `.run` files that keeps replaying past a failing line
+pub(crate) const RUN_CONTINUE_PREFIX: char = '~';
+
+/// File name of typed input in source maps, with lines counted per session
+const INPUT_NAME: &str = "<repl>";
+
+/// State kept across REPL lines besides the compiler context
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    debugger: Debugger,
+    /// Where a bare `.dump` continues
+    next_dump: u32,
+    /// Word definitions sent since the last reset, for `.save`
+    words: Vec<SavedWord>,
+    /// Overall limit for running one line
+    pub(crate) deadline: Option<Duration>,
+    /// Set while `.time` is on
+    stopwatch: Option<Stopwatch>,
+    /// Text of the last `.edit` buffer, offered again by the next one
+    edit_buffer: String,
+    /// Where the code sent this session was typed, to place VM errors
+    source_map: SourceMap,
+    /// Lines of Forth code compiled so far
+    lines: usize,
+    /// Reading `--pipe` input: status lines take the place of ` ok`
+    pipe: bool,
+    /// Time the last line took with `.time` on, for its status line
+    timing: Option<Timing>,
+    /// Memory `.diff` compares against
+    baseline: Option<MemoryDump>,
+    /// How `.stack` and `.dump` write numbers
+    format: OutputFormat,
+    /// Set while a transcript is being recorded
+    transcript: Option<TranscriptWriter>,
+    /// Last transcript file, where a bare `.record on` resumes
+    transcript_path: Option<String>,
+}
 
-    // Create compiler
-    let mut compiler = Compiler::new().map_err(crate::V4Error::Compilation)?;
+impl Session {
+    /// Source of the latest definition of `name` this session, if recorded
+    fn source(&self, name: &str) -> Option<&str> {
+        self.words
+            .iter()
+            .rev()
+            .find(|word| word.name.eq_ignore_ascii_case(name))
+            .and_then(|word| word.source.as_deref())
+    }
 
-    // Create line editor
-    let mut rl = DefaultEditor::new().map_err(|e| crate::V4Error::Repl(e.to_string()))?;
+    /// Start recording to `transcript`, if given
+    fn with_transcript(mut self, transcript: Option<TranscriptWriter>) -> Self {
+        self.transcript_path = transcript.as_ref().map(|t| t.path().to_string());
+        self.transcript = transcript;
+        self
+    }
+}
+
+/// What the REPL should do after a dispatched line
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LineOutcome {
+    /// Keep reading input
+    Continue,
+    /// Leave the REPL (`bye`, `quit`, `.exit`)
+    Exit,
+}
 
+/// Run interactive REPL session on an open device
+///
+/// Unlike the other commands this is interactive and prints to the terminal.
+/// With a `transcript`, the session is recorded from the first input.
+pub fn run_repl(
+    mut device: V4Device,
+    no_reset: bool,
+    transcript: Option<TranscriptWriter>,
+) -> Result<()> {
     // Print welcome message
     println!("V4 REPL v{}", env!("CARGO_PKG_VERSION"));
-    println!("Connected to {}", port);
+    println!("Connected to {}", device.port());
     println!("Type 'bye' or press Ctrl+D to exit");
     println!("Type '.help' for help");
+    if let Some(transcript) = &transcript {
+        println!("Recording to {}", transcript.path());
+    }
     println!();
 
     // Reset device (unless --no-reset is specified)
@@ -33,16 +130,285 @@ pub fn run_repl(port: &str, no_reset: bool) -> Result<()> {
         println!("Use '.reset' to reset both VM and compiler context.\n");
     } else {
         println!("Resetting device...");
-        match serial.reset(DEFAULT_TIMEOUT) {
-            Ok(ErrorCode::Ok) => println!("Device ready\n"),
-            Ok(err) => println!("Warning: Reset returned {}\n", err.name()),
-            Err(e) => println!("Warning: Reset failed: {}\n", e),
+        match device.reset(DEFAULT_TIMEOUT, DEFAULT_TIMEOUT) {
+            Ok(report) => println!("Device ready ({} ms)\n", report.ready_after.as_millis()),
+            Err(e) => println!("Warning: {}\n", e),
+        }
+    }
+
+    let deadline = device.deadline();
+    let (transport, compiler) = device.parts()?;
+    repl_loop(transport, compiler, deadline, None, transcript)
+}
+
+/// Inputs run by [`pipe_loop`] and how many of them failed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipeSummary {
+    pub inputs: usize,
+    pub failed: usize,
+}
+
+/// Run REPL input from stdin without line editing (`v4 repl --pipe`)
+///
+/// Nothing is printed besides what the input produces and the status lines
+/// of [`pipe_loop`]. A failing reset ends the session before any input.
+pub fn run_pipe(
+    mut device: V4Device,
+    no_reset: bool,
+    transcript: Option<TranscriptWriter>,
+) -> Result<PipeSummary> {
+    if !no_reset {
+        device.reset(DEFAULT_TIMEOUT, DEFAULT_TIMEOUT)?;
+    }
+    let deadline = device.deadline();
+    let (transport, compiler) = device.parts()?;
+    pipe_loop(
+        transport,
+        compiler,
+        deadline,
+        std::io::stdin().lock(),
+        transcript,
+    )
+}
+
+/// Recorded inputs [`replay_loop`] ran and how many went differently
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub inputs: usize,
+    pub diverged: usize,
+}
+
+/// Run the inputs of a transcript again on an open device (`v4 repl --replay`)
+///
+/// The device is reset first unless `no_reset`, as it was when recording
+/// started. A new `transcript` records the replay itself.
+pub fn run_replay(
+    mut device: V4Device,
+    no_reset: bool,
+    steps: &[Step],
+    transcript: Option<TranscriptWriter>,
+) -> Result<ReplaySummary> {
+    println!("Replaying {} input(s) on {}", steps.len(), device.port());
+    if !no_reset {
+        device.reset(DEFAULT_TIMEOUT, DEFAULT_TIMEOUT)?;
+    }
+    println!();
+    let deadline = device.deadline();
+    let (transport, compiler) = device.parts()?;
+    replay_loop(transport, compiler, deadline, steps, transcript)
+}
+
+/// Run recorded inputs as if typed, pointing out where the session differs
+///
+/// An input diverges when it fails where it passed before, fails with
+/// another message, or its device output changed. Inputs whose outcome the
+/// transcript lacks are only compared by output. Replay stops after an
+/// exit word or when the connection is lost.
+pub fn replay_loop(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    deadline: Option<Duration>,
+    steps: &[Step],
+    transcript: Option<TranscriptWriter>,
+) -> Result<ReplaySummary> {
+    let mut session = Session {
+        deadline,
+        ..Session::default()
+    }
+    .with_transcript(transcript);
+    let mut summary = ReplaySummary::default();
+
+    for step in steps {
+        for (i, line) in step.input.lines().enumerate() {
+            let prompt = if i == 0 { "v4>" } else { "...>" };
+            println!("{}{}", ui::prompt(prompt), line);
+        }
+        summary.inputs += 1;
+        let (result, output) = run_line(&step.input, transport, compiler, &mut session);
+        if let Err(e) = &result {
+            eprintln!("{} {}", ui::error_label(), ui::error(e));
+        }
+
+        let differences = replay_differences(step, &result, &String::from_utf8_lossy(&output));
+        if !differences.is_empty() {
+            summary.diverged += 1;
+            println!("Replay differs from the recording:");
+            for (recorded, replayed) in differences {
+                println!("{}", ui::removed(format!("- recorded: {}", recorded)));
+                println!("{}", ui::added(format!("+ replayed: {}", replayed)));
+            }
+        }
+        match result {
+            Ok(LineOutcome::Exit) => break,
+            Err(e) if e.is_disconnect() => break,
+            _ => {}
+        }
+    }
+    Ok(summary)
+}
+
+/// How a replayed input differs from its recording, as (recorded, replayed)
+fn replay_differences(
+    step: &Step,
+    result: &Result<LineOutcome>,
+    output: &str,
+) -> Vec<(String, String)> {
+    let mut differences = Vec::new();
+    let replayed = match result {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("error: {}", e),
+    };
+    let recorded = match &step.outcome {
+        transcript::Outcome::Ok => Some("ok".to_string()),
+        transcript::Outcome::Error(message) => Some(format!("error: {}", message)),
+        transcript::Outcome::Unknown => None,
+    };
+    if let Some(recorded) = recorded
+        && recorded != replayed
+    {
+        differences.push((recorded, replayed));
+    }
+    if step.output != output {
+        differences.push((
+            format!("output {:?}", step.output),
+            format!("output {:?}", output),
+        ));
+    }
+    differences
+}
+
+/// Run REPL input line by line, reporting each result on stdout
+///
+/// Every complete input (a line, or a definition spanning lines) ends in
+/// one status line: `OK`, with the time when `.time` is on, or
+/// `ERR <CODE> <message>`. CODE is the device's response code when it
+/// answered with one, otherwise one of `COMPILE`, `TIMEOUT`, `IO`,
+/// `INTERRUPTED` or `FAILED`. Output of the input itself comes before its
+/// status line. After the last input, `DONE <inputs> <failed>` follows.
+/// Failed inputs don't stop the session, a lost connection does.
+pub fn pipe_loop(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    deadline: Option<Duration>,
+    input: impl BufRead,
+    transcript: Option<TranscriptWriter>,
+) -> Result<PipeSummary> {
+    let mut session = Session {
+        deadline,
+        pipe: true,
+        ..Session::default()
+    }
+    .with_transcript(transcript);
+    let mut summary = PipeSummary::default();
+    let mut pending = String::new();
+
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let starts_command = line.starts_with('.') || is_exit_word(line);
+        if !pending.is_empty() || !starts_command {
+            pending.push_str(line);
+            pending.push('\n');
+            if needs_continuation(&pending) {
+                continue;
+            }
+        }
+        let input = match pending.is_empty() {
+            true => line.to_string(),
+            false => std::mem::take(&mut pending),
+        };
+
+        summary.inputs += 1;
+        transport::take_open_line();
+        match run_line(&input, transport, compiler, &mut session).0 {
+            Ok(LineOutcome::Exit) => {
+                pipe_status("OK".to_string());
+                break;
+            }
+            Ok(LineOutcome::Continue) => pipe_status(match session.timing.take() {
+                Some(timing) => format!("OK ({})", timing),
+                None => "OK".to_string(),
+            }),
+            Err(e) => {
+                summary.failed += 1;
+                pipe_status(pipe_error(&e));
+                if e.is_disconnect() {
+                    break;
+                }
+            }
+        }
+    }
+    if !pending.is_empty() {
+        summary.inputs += 1;
+        summary.failed += 1;
+        pipe_status("ERR COMPILE Input ends inside an unfinished definition".to_string());
+    }
+    pipe_status(format!("DONE {} {}", summary.inputs, summary.failed));
+    Ok(summary)
+}
+
+/// Print a `--pipe` status line, starting it on a line of its own
+fn pipe_status(line: String) {
+    if transport::take_open_line() {
+        println!();
+    }
+    println!("{}", line);
+}
+
+/// Status line for a failed `--pipe` input, on one line
+fn pipe_error(error: &crate::V4Error) -> String {
+    use crate::V4Error;
+    let code = match error {
+        _ if error.is_disconnect() => "IO",
+        V4Error::Compilation(_) => "COMPILE",
+        V4Error::Timeout | V4Error::PhaseTimeout { .. } | V4Error::DeadlineExceeded { .. } => {
+            "TIMEOUT"
         }
+        V4Error::Interrupted | V4Error::Aborted => "INTERRUPTED",
+        V4Error::Io(_) | V4Error::Serial(_) => "IO",
+        _ => error_explain::code_of(error).map_or("FAILED", |code| code.name()),
+    };
+    let message = error.to_string().lines().collect::<Vec<_>>().join(" ");
+    format!("ERR {} {}", code, message)
+}
+
+/// Read-eval-print loop over an open connection
+///
+/// `deadline` limits how long each line may take on the device overall;
+/// with a `stopwatch` the REPL starts with `.time` on, with a `transcript`
+/// it starts recording.
+pub fn repl_loop(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    deadline: Option<Duration>,
+    stopwatch: Option<Stopwatch>,
+    transcript: Option<TranscriptWriter>,
+) -> Result<()> {
+    // Create line editor
+    let mut rl: Editor<ReplHelper, DefaultHistory> =
+        Editor::new().map_err(|e| crate::V4Error::Repl(e.to_string()))?;
+    rl.set_helper(Some(ReplHelper::default()));
+    let mut session = Session {
+        deadline,
+        stopwatch,
+        ..Session::default()
     }
+    .with_transcript(transcript);
+
+    // Lines of a definition still being typed
+    let mut pending = String::new();
 
     // REPL loop
     loop {
-        let readline = rl.readline("v4> ");
+        let prompt = match session.debugger.halted() {
+            _ if !pending.is_empty() => ui::prompt("...>"),
+            Some(_) => ui::prompt("v4 [halted]>"),
+            None => ui::prompt("v4>"),
+        };
+        let readline = rl.readline(&prompt);
 
         match readline {
             Ok(line) => {
@@ -56,41 +422,43 @@ pub fn run_repl(port: &str, no_reset: bool) -> Result<()> {
                 // Add to history
                 let _ = rl.add_history_entry(line);
 
-                // Check for exit commands
-                if line == "bye" || line == "quit" || line == ".exit" {
-                    println!("Goodbye!");
-                    break;
-                }
-
-                // Check for meta-commands
-                if line.starts_with('.') {
-                    if let Err(e) = handle_meta_command(line, &mut serial, &mut compiler) {
-                        eprintln!("Error: {}", e);
-                    }
-                    continue;
-                }
-
-                // Compile Forth code
-                let compiled = match compiler.compile(line) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        eprintln!("Error: {}", e);
+                // Keep reading until the definition is complete
+                let starts_command = line.starts_with('.') || is_exit_word(line);
+                if !pending.is_empty() || !starts_command {
+                    pending.push_str(line);
+                    pending.push('\n');
+                    if needs_continuation(&pending) {
                         continue;
                     }
+                }
+                let input = match pending.is_empty() {
+                    true => line.to_string(),
+                    false => std::mem::take(&mut pending),
                 };
 
-                // Execute on device
-                if let Err(e) = execute_on_device(&mut serial, &compiled, &mut compiler) {
-                    eprintln!("Error: {}", e);
-                    continue;
+                match run_line(&input, transport, compiler, &mut session).0 {
+                    Ok(LineOutcome::Exit) => {
+                        println!("Goodbye!");
+                        break;
+                    }
+                    Ok(LineOutcome::Continue) => {}
+                    Err(e) if e.is_disconnect() => {
+                        eprintln!("{} {}", ui::error_label(), ui::error(e));
+                        let _catch = interrupt::catch();
+                        if let Err(e) = cmd_reconnect(transport, compiler, &mut session, &[]) {
+                            eprintln!("{} {}", ui::error_label(), ui::error(e));
+                        }
+                    }
+                    Err(e) => eprintln!("{} {}", ui::error_label(), ui::error(e)),
+                }
+                if let Some(helper) = rl.helper_mut() {
+                    helper.set_words(compiler.word_names());
                 }
-
-                // Success
-                println!(" ok");
             }
             Err(ReadlineError::Interrupted) => {
-                // Ctrl+C
+                // Ctrl+C, also dropping an unfinished definition
                 println!("^C");
+                pending.clear();
                 continue;
             }
             Err(ReadlineError::Eof) => {
@@ -99,7 +467,7 @@ pub fn run_repl(port: &str, no_reset: bool) -> Result<()> {
                 break;
             }
             Err(err) => {
-                eprintln!("Error: {}", err);
+                eprintln!("{} {}", ui::error_label(), ui::error(err));
                 break;
             }
         }
@@ -108,21 +476,157 @@ pub fn run_repl(port: &str, no_reset: bool) -> Result<()> {
     Ok(())
 }
 
+/// Run one complete input, recording it while a transcript is on
+///
+/// Returns the device output the input produced along with its result.
+/// `.record` commands stay out of the transcripts they start and stop.
+pub(crate) fn run_line(
+    input: &str,
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+) -> (Result<LineOutcome>, Vec<u8>) {
+    let recorded = input.split_whitespace().next() != Some(".record");
+    if recorded && let Some(transcript) = &mut session.transcript {
+        transcript.record(TranscriptEvent::Input {
+            text: input.trim_end().to_string(),
+        });
+    }
+
+    transport::capture_output();
+    // Ctrl+C while the line runs aborts the program, not the REPL
+    let catch = interrupt::catch();
+    let result = dispatch_line(input, transport, compiler, session);
+    drop(catch);
+    let result = device::abort_on_interrupt(transport, result, DEFAULT_TIMEOUT);
+    let output = transport::take_captured_output();
+
+    if recorded && let Some(transcript) = &mut session.transcript {
+        transcript.record_result(&output, &result);
+    }
+    (result, output)
+}
+
+/// `bye`, `quit` or `.exit`
+fn is_exit_word(line: &str) -> bool {
+    line == "bye" || line == "quit" || line == ".exit"
+}
+
+/// Dispatch one line of input: exit words, meta-commands, or Forth code
+pub(crate) fn dispatch_line(
+    line: &str,
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+) -> Result<LineOutcome> {
+    let line = line.trim();
+
+    // Skip empty lines
+    if line.is_empty() {
+        return Ok(LineOutcome::Continue);
+    }
+
+    // Check for exit commands
+    if is_exit_word(line) {
+        return Ok(LineOutcome::Exit);
+    }
+
+    // Check for meta-commands
+    if line.starts_with('.') {
+        handle_meta_command(line, transport, compiler, session)?;
+        return Ok(LineOutcome::Continue);
+    }
+
+    // Compile Forth code and execute on device
+    let compiled = compiler
+        .compile(line)
+        .map_err(crate::V4Error::Compilation)?;
+    let input = typed_source(line, session.lines + 1);
+    session.lines += line.lines().count();
+    run_compiled(&input, &compiled, transport, compiler, session)?;
+    Ok(LineOutcome::Continue)
+}
+
+/// Send compiled `input` to the device and report how it ended
+///
+/// Definitions are recorded for `.source` and `.save`, and the lines of
+/// `input` for placing VM errors.
+fn run_compiled(
+    input: &Loaded,
+    compiled: &CompileResult,
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+) -> Result<()> {
+    let sources = definition_sources(&input.text);
+    match SourceMap::build(compiler, input) {
+        Ok(map) => session.source_map.extend(map),
+        Err(e) => log::debug!("No source map for this input: {}", e),
+    }
+
+    let executed = match session.stopwatch {
+        Some(stopwatch) => stopwatch
+            .time(transport, DEFAULT_TIMEOUT, |transport| {
+                execute_on_device(transport, compiled, &sources, compiler, session)
+            })
+            .map(|(outcome, timing)| (outcome, Some(timing))),
+        None => execute_on_device(transport, compiled, &sources, compiler, session)
+            .map(|outcome| (outcome, None)),
+    };
+    let (outcome, timing) = executed
+        .map_err(|e| sourcemap::locate_fault(e, transport, &session.source_map, DEFAULT_TIMEOUT))?;
+    if session.pipe && outcome == Outcome::Finished {
+        session.timing = timing;
+        Ok(())
+    } else {
+        report_outcome(transport, &session.debugger, outcome, timing)
+    }
+}
+
+/// Typed input as source, its lines numbered from `first_line`
+fn typed_source(text: &str, first_line: usize) -> Loaded {
+    Loaded {
+        text: text.to_string(),
+        files: Vec::new(),
+        lines: (first_line..)
+            .take(text.lines().count())
+            .map(|line| LineOrigin {
+                file: INPUT_NAME.into(),
+                line,
+            })
+            .collect(),
+    }
+}
+
 /// Execute compiled bytecode on device
+///
+/// Top-level code may stop at a breakpoint, leaving the VM halted.
+/// `sources` are the definitions as typed, recorded for `.source`.
 fn execute_on_device(
-    serial: &mut V4Serial,
+    transport: &mut dyn Transport,
     compiled: &CompileResult,
+    sources: &[WordSource],
     compiler: &mut Compiler,
-) -> Result<()> {
+    session: &mut Session,
+) -> Result<Outcome> {
+    session.debugger.ensure_running()?;
+    let deadline = Deadline::start(session.deadline);
+
     // Execute word definitions first
-    for word in &compiled.words {
-        eprintln!(
-            "[DEBUG] Executing word '{}' ({} bytes): {:02x?}",
+    let count = compiled.words.len();
+    for (i, word) in compiled.words.iter().enumerate() {
+        log::debug!(
+            "Executing word '{}' ({} bytes): {:02x?}",
             word.name,
             word.bytecode.len(),
             word.bytecode
         );
-        let response = serial.exec(&word.bytecode, DEFAULT_TIMEOUT)?;
+        let phase = format!("defining word '{}' ({} of {})", word.name, i + 1, count);
+        let timeout = deadline.timeout(DEFAULT_TIMEOUT, &phase)?;
+        let response = deadline.check(
+            transport.define_word(&word.name, &word.bytecode, timeout),
+            &phase,
+        )?;
         if response.error_code != ErrorCode::Ok {
             return Err(crate::V4Error::Device(format!(
                 "Failed to register word '{}': {}",
@@ -131,39 +635,110 @@ fn execute_on_device(
             )));
         }
 
+        let source = sources
+            .get(i)
+            .filter(|source| source.name.eq_ignore_ascii_case(&word.name))
+            .map(|source| source.text.clone());
+        let redefined = compiler
+            .word_names()
+            .any(|name| name.eq_ignore_ascii_case(&word.name));
+
         // Register word index returned from device
-        if let Some(&word_idx) = response.word_indices.first() {
-            eprintln!(
-                "[DEBUG] Device registered word '{}' at index {}",
-                word.name, word_idx
-            );
-            compiler
-                .register_word_index(&word.name, word_idx as i32)
-                .map_err(crate::V4Error::Compilation)?;
+        compiler
+            .register_word_indices(&[word.name.as_str()], &response.word_indices)
+            .map_err(crate::V4Error::Protocol)?;
+        log::debug!(
+            "Device registered word '{}' at index {}",
+            word.name,
+            response.word_indices[0]
+        );
+        if redefined {
+            report_redefinition(&word.name, session.source(&word.name), source.as_deref());
         }
+        session.words.push(SavedWord {
+            name: word.name.clone(),
+            index: response.word_indices[0],
+            bytecode: word.bytecode.clone(),
+            source,
+        });
     }
 
     // Execute main bytecode
     if !compiled.bytecode.is_empty() {
-        eprintln!(
-            "[DEBUG] Executing main bytecode ({} bytes): {:02x?}",
+        log::debug!(
+            "Executing main bytecode ({} bytes): {:02x?}",
             compiled.bytecode.len(),
             compiled.bytecode
         );
-        let response = serial.exec(&compiled.bytecode, DEFAULT_TIMEOUT)?;
-        if response.error_code != ErrorCode::Ok {
-            return Err(crate::V4Error::Device(format!(
-                "Execution failed: {}",
-                response.error_code.name()
-            )));
+        return session
+            .debugger
+            .exec(transport, &compiled.bytecode, DEFAULT_TIMEOUT, &deadline);
+    }
+
+    Ok(Outcome::Finished)
+}
+
+/// Show what changed when a word is defined again
+///
+/// Code compiled before keeps calling the old definition, so an accidental
+/// redefinition is easy to miss otherwise.
+fn report_redefinition(name: &str, old: Option<&str>, new: Option<&str>) {
+    match (old, new) {
+        (Some(old), Some(new)) if old == new => {
+            println!("Redefined '{}' (same source)", name);
+        }
+        (Some(old), Some(new)) => {
+            println!("Redefined '{}':", name);
+            for line in diff::lines(old, new) {
+                match line {
+                    diff::Line::Same(text) => println!("  {}", text),
+                    diff::Line::Removed(text) => println!("{}", ui::removed(format!("- {}", text))),
+                    diff::Line::Added(text) => println!("{}", ui::added(format!("+ {}", text))),
+                }
+            }
         }
+        _ => println!("Redefined '{}' (previous source unknown)", name),
     }
+}
 
-    Ok(())
+/// Print " ok" for finished code, or where the VM halted
+///
+/// With `.time` on, the time the line took follows: ` ok (3.2 ms)`.
+fn report_outcome(
+    transport: &mut dyn Transport,
+    debugger: &Debugger,
+    outcome: Outcome,
+    timing: Option<Timing>,
+) -> Result<()> {
+    match outcome {
+        Outcome::Finished => {
+            match timing {
+                Some(timing) => println!(" ok ({})", timing),
+                None => println!(" ok"),
+            }
+            Ok(())
+        }
+        Outcome::Halted(location) => show_halt(transport, debugger, location),
+    }
+}
+
+/// Show the halt location, next instruction and stacks
+fn show_halt(transport: &mut dyn Transport, debugger: &Debugger, location: Location) -> Result<()> {
+    print!(
+        "{}",
+        debugger.inspect(transport, location, DEFAULT_TIMEOUT)?
+    );
+    println!();
+    cmd_stack(transport, &OutputFormat::default())
 }
 
 /// Handle meta-commands (.help, .ping, etc.)
-fn handle_meta_command(line: &str, serial: &mut V4Serial, compiler: &mut Compiler) -> Result<()> {
+fn handle_meta_command(
+    line: &str,
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+) -> Result<()> {
     let parts: Vec<&str> = line.split_whitespace().collect();
     let command = parts[0];
 
@@ -173,7 +748,7 @@ fn handle_meta_command(line: &str, serial: &mut V4Serial, compiler: &mut Compile
             Ok(())
         }
         ".ping" => {
-            let err_code = serial.ping(DEFAULT_TIMEOUT)?;
+            let err_code = transport.ping(DEFAULT_TIMEOUT)?;
             if err_code == ErrorCode::Ok {
                 println!("Device is responsive");
             } else {
@@ -183,7 +758,7 @@ fn handle_meta_command(line: &str, serial: &mut V4Serial, compiler: &mut Compile
         }
         ".reset" => {
             // Reset device VM
-            let err_code = serial.reset(DEFAULT_TIMEOUT)?;
+            let err_code = transport.reset(DEFAULT_TIMEOUT)?;
             if err_code != ErrorCode::Ok {
                 return Err(crate::V4Error::Device(format!(
                     "Reset failed: {}",
@@ -191,16 +766,47 @@ fn handle_meta_command(line: &str, serial: &mut V4Serial, compiler: &mut Compile
                 )));
             }
 
-            // Reset compiler context
+            // Reset compiler context; the device drops its breakpoints too
             compiler.reset();
+            session.debugger.reset();
+            session.words.clear();
+            session.source_map = SourceMap::default();
 
             println!("VM and compiler context reset");
             Ok(())
         }
-        ".stack" => cmd_stack(serial),
-        ".rstack" => cmd_rstack(serial),
-        ".dump" => cmd_dump(serial, &parts[1..]),
-        ".see" => cmd_see(serial, &parts[1..]),
+        ".info" => {
+            print!("{}", DeviceInfo::query(transport, DEFAULT_TIMEOUT)?);
+            Ok(())
+        }
+        ".stack" => cmd_stack(transport, &session.format),
+        ".rstack" => cmd_rstack(transport),
+        ".dump" => cmd_dump(transport, session, &parts[1..]),
+        ".diff" => cmd_diff(transport, session, &parts[1..]),
+        ".format" => cmd_format(session, &parts[1..]),
+        ".poke" => cmd_poke(transport, &parts[1..]),
+        ".fill" => cmd_fill(transport, &parts[1..]),
+        ".gpio" => cmd_gpio(transport, &parts[1..]),
+        ".see" => cmd_see(transport, &parts[1..]),
+        ".words" => cmd_words(transport, compiler),
+        ".run" => cmd_run(transport, compiler, session, &parts[1..]),
+        ".edit" => cmd_edit(transport, compiler, session, &parts[1..]),
+        ".source" => cmd_source(compiler, session, &parts[1..]),
+        ".save" => cmd_save(session, &parts[1..]),
+        ".load" => cmd_load(transport, compiler, session, &parts[1..]),
+        ".restore" => cmd_restore(transport, compiler, session, &parts[1..]),
+        ".time" => cmd_time(transport, session, &parts[1..]),
+        ".record" => cmd_record(session, &parts[1..]),
+        ".break" => cmd_break(transport, &mut session.debugger, &parts[1..]),
+        ".step" => {
+            let outcome = session.debugger.step(transport, DEFAULT_TIMEOUT)?;
+            report_outcome(transport, &session.debugger, outcome, None)
+        }
+        ".continue" => {
+            let outcome = session.debugger.resume(transport, DEFAULT_TIMEOUT)?;
+            report_outcome(transport, &session.debugger, outcome, None)
+        }
+        ".reconnect" => cmd_reconnect(transport, compiler, session, &parts[1..]),
         ".exit" => {
             // Handled in main loop
             Ok(())
@@ -216,12 +822,33 @@ fn handle_meta_command(line: &str, serial: &mut V4Serial, compiler: &mut Compile
 fn print_help() {
     println!("Available commands:");
     println!("  .help              - Show this help");
+    println!("  .words             - List all defined words");
     println!("  .ping              - Check device connection");
     println!("  .reset             - Reset VM and compiler context");
+    println!("  .info              - Show firmware version and VM capabilities");
     println!("  .stack             - Show data and return stack contents");
     println!("  .rstack            - Show return stack with call trace");
     println!("  .dump [addr] [len] - Hexdump memory (default: continue from last)");
+    println!("  .dump ... > <file> - Save the dumped bytes to a file");
+    println!("  .format [style] [8|16|32] [little|big] - Number format of .stack and .dump");
+    println!("  .diff <addr> <len> - Take a memory baseline; .diff shows what changed");
+    println!("  .poke <addr> <b..> - Write bytes to memory");
+    println!("  .fill <a> <n> <b>  - Set n bytes of memory at a to b");
+    println!("  .gpio <pin> <act>  - Drive or read a pin (on, off, read, toggle)");
     println!("  .see <word_idx>    - Show word bytecode disassembly");
+    println!("  .run <file>        - Replay REPL lines from file (prefix '~' to ignore errors)");
+    println!("  .edit [word]       - Write code in $EDITOR and run it on exit");
+    println!("  .source <word>     - Show the source a word was defined with");
+    println!("  .save <file>       - Save the words defined this session");
+    println!("  .restore <file>    - Define the words from a saved session again");
+    println!("  .load <file>       - Compile and run a Forth source file");
+    println!("  .time [on|off]     - Show how long each line takes (no args: toggle)");
+    println!("  .record on [file]  - Record inputs and output to a transcript (.record off)");
+    println!("  .break [word] [n]  - Set breakpoint at offset n of a word (no args: list)");
+    println!("  .step              - Execute one instruction while halted");
+    println!("  .continue          - Resume until the next breakpoint");
+    println!("  .reconnect         - Reopen the port after the device went away");
+    println!("  .reconnect nosync  - Reopen without redefining this session's words");
     println!("  .exit              - Exit REPL (same as 'bye')");
     println!("  bye                - Exit REPL");
     println!();
@@ -234,184 +861,651 @@ fn print_help() {
     println!("  ↑/↓      - Navigate command history");
 }
 
-/// Display data and return stacks
-fn cmd_stack(serial: &mut V4Serial) -> Result<()> {
-    let response = serial.query_stack(DEFAULT_TIMEOUT)?;
-    if response.error_code != ErrorCode::Ok {
-        return Err(crate::V4Error::Device(format!(
-            "Query stack failed: {}",
-            response.error_code.name()
-        )));
-    }
-
-    let data = &response.data;
-    if data.is_empty() {
-        println!("No stack data received");
-        return Ok(());
+/// Replay REPL lines from a file
+///
+/// Each line goes through the same dispatch as interactive input, so files
+/// may mix Forth code and meta-commands. Replay stops at the first failing
+/// line unless that line is prefixed with `~`. An exit word ends the replay.
+fn cmd_run(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+    args: &[&str],
+) -> Result<()> {
+    if args.is_empty() {
+        return Err(crate::V4Error::Cli("Usage: .run <file>".to_string()));
     }
+    let path = args[0];
+    let script = fs::read_to_string(path)?;
+
+    for (lineno, raw) in script.lines().enumerate() {
+        let raw = raw.trim();
+        let (line, keep_going) = match raw.strip_prefix(RUN_CONTINUE_PREFIX) {
+            Some(rest) => (rest.trim(), true),
+            None => (raw, false),
+        };
+        if line.is_empty() {
+            continue;
+        }
 
-    // Parse data stack
-    let ds_depth = data[0] as usize;
-    let mut pos = 1;
-
-    println!("Data Stack (depth: {} / 256):", ds_depth);
-    if ds_depth == 0 {
-        println!("  <empty>");
-    } else {
-        for i in 0..ds_depth {
-            if pos + 4 > data.len() {
-                break;
+        println!("{}{}", ui::prompt("v4>"), line);
+        match dispatch_line(line, transport, compiler, session) {
+            Ok(LineOutcome::Continue) => {}
+            Ok(LineOutcome::Exit) => break,
+            Err(e) if keep_going => eprintln!("{} {}", ui::error_label(), ui::error(e)),
+            Err(e) => {
+                return Err(crate::V4Error::Repl(format!(
+                    "{}:{}: {}",
+                    path,
+                    lineno + 1,
+                    e
+                )));
             }
-            let value =
-                i32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
-            println!("  [{}]: 0x{:08X} ({})", i, value as u32, value);
-            pos += 4;
         }
     }
 
-    // Parse return stack
-    if pos >= data.len() {
+    Ok(())
+}
+
+/// Open `$VISUAL`/`$EDITOR` on a scratch buffer and run it once saved
+///
+/// The buffer starts out with the text of the previous `.edit`, so a failing
+/// definition can be fixed and sent again, or with the source of a word.
+fn cmd_edit(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+    args: &[&str],
+) -> Result<()> {
+    let initial = match args {
+        [] => session.edit_buffer.clone(),
+        [name] => word_source(compiler, session, name)?.to_string() + "\n",
+        _ => return Err(crate::V4Error::Cli("Usage: .edit [word]".to_string())),
+    };
+    let text = edit_text(&editor_command(), &initial)?;
+    if text.trim().is_empty() {
+        println!("Empty buffer, nothing to run");
         return Ok(());
     }
+    session.edit_buffer = text.clone();
+    run_buffer(&text, transport, compiler, session).map(|_| ())
+}
 
-    let rs_depth = data[pos] as usize;
-    pos += 1;
+/// Editor to run: `$VISUAL`, then `$EDITOR`, then the platform default
+fn editor_command() -> String {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string())
+}
 
-    println!("\nReturn Stack (depth: {} / 64):", rs_depth);
-    if rs_depth == 0 {
-        println!("  <empty>");
-    } else {
-        for i in 0..rs_depth {
-            if pos + 4 > data.len() {
-                break;
+/// Let the user edit `initial` in `editor` and return the saved text
+///
+/// `editor` is a shell command line such as `code --wait`; the path of a
+/// scratch file is appended to it.
+fn edit_text(editor: &str, initial: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!("v4-edit-{}.fth", std::process::id()));
+    fs::write(&path, initial)?;
+    let status = crate::project::shell(&format!("{} \"{}\"", editor, path.display())).status();
+    let text = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let status = status
+        .map_err(|e| crate::V4Error::Repl(format!("Cannot run editor '{}': {}", editor, e)))?;
+    if !status.success() {
+        return Err(crate::V4Error::Repl(format!(
+            "Editor '{}' failed ({}); buffer not run",
+            editor, status
+        )));
+    }
+    Ok(text?)
+}
+
+/// Run a buffer of REPL input as if typed line by line
+///
+/// Definitions may span lines as at the prompt; meta-commands are run when
+/// no definition is open. Stops at the first failing line or an exit word.
+fn run_buffer(
+    text: &str,
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+) -> Result<LineOutcome> {
+    let mut pending = String::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let prompt = if pending.is_empty() { "v4>" } else { "...>" };
+        println!("{}{}", ui::prompt(prompt), line);
+
+        let starts_command = line.starts_with('.') || is_exit_word(line);
+        if pending.is_empty() && starts_command {
+            if dispatch_line(line, transport, compiler, session)? == LineOutcome::Exit {
+                return Ok(LineOutcome::Exit);
             }
-            let value =
-                i32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
-            println!("  [{}]: 0x{:08X}", i, value as u32);
-            pos += 4;
+            continue;
+        }
+        pending.push_str(line);
+        pending.push('\n');
+        if !needs_continuation(&pending) {
+            dispatch_line(&std::mem::take(&mut pending), transport, compiler, session)?;
         }
     }
+    if !pending.is_empty() {
+        return Err(crate::V4Error::Repl(
+            "Buffer ends inside an unfinished definition".to_string(),
+        ));
+    }
+    Ok(LineOutcome::Continue)
+}
 
+/// Print the source a word was defined with
+fn cmd_source(compiler: &Compiler, session: &Session, args: &[&str]) -> Result<()> {
+    let [name] = args else {
+        return Err(crate::V4Error::Cli("Usage: .source <word>".to_string()));
+    };
+    println!("{}", word_source(compiler, session, name)?);
     Ok(())
 }
 
-/// Display return stack with call trace
-fn cmd_rstack(serial: &mut V4Serial) -> Result<()> {
-    let response = serial.query_stack(DEFAULT_TIMEOUT)?;
-    if response.error_code != ErrorCode::Ok {
-        return Err(crate::V4Error::Device(format!(
-            "Query stack failed: {}",
-            response.error_code.name()
-        )));
+/// Recorded source of `name`, or why there is none
+fn word_source<'a>(compiler: &Compiler, session: &'a Session, name: &str) -> Result<&'a str> {
+    if let Some(source) = session.source(name) {
+        return Ok(source);
     }
+    let known = compiler
+        .word_names()
+        .any(|word| word.eq_ignore_ascii_case(name));
+    Err(crate::V4Error::Repl(match known {
+        true => format!(
+            "No source recorded for '{}'; it was not defined in this session",
+            name
+        ),
+        false => format!("Unknown word: {}", name),
+    }))
+}
+
+/// Write the words defined since the last reset to a session file
+fn cmd_save(session: &Session, args: &[&str]) -> Result<()> {
+    let [path] = args else {
+        return Err(crate::V4Error::Cli("Usage: .save <file>".to_string()));
+    };
+    SessionFile::new(session.words.clone()).save(path)?;
+    println!("Saved {} word(s) to {}", session.words.len(), path);
+    Ok(())
+}
+
+/// Send the definitions from a session file, e.g. after a power cycle
+fn cmd_restore(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+    args: &[&str],
+) -> Result<()> {
+    let [path] = args else {
+        return Err(crate::V4Error::Cli("Usage: .restore <file>".to_string()));
+    };
+    let saved = SessionFile::load(path)?;
+    session.debugger.ensure_running()?;
 
-    let data = &response.data;
-    if data.is_empty() {
-        println!("No stack data received");
+    let report = saved.restore(transport, compiler, DEFAULT_TIMEOUT)?;
+    warn_moved(&report);
+    println!("Restored {} word(s) from {}", report.words.len(), path);
+    session.words.extend(report.words);
+    Ok(())
+}
+
+/// Compile a Forth source file in the session's context and run it
+///
+/// `INCLUDE`s are relative to the file, and compile and VM errors name the
+/// file and line they come from.
+fn cmd_load(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+    args: &[&str],
+) -> Result<()> {
+    let [path] = args else {
+        return Err(crate::V4Error::Cli("Usage: .load <file>".to_string()));
+    };
+    let text = fs::read_to_string(path)
+        .map_err(|e| crate::V4Error::Repl(format!("Cannot read {}: {}", path, e)))?;
+    let loaded = source::load_text(&text, Path::new(path))?;
+    let compiled = compiler.compile(&loaded.text).map_err(|e| {
+        crate::V4Error::Compilation(compile::placed_message(&loaded, &CompileError::parse(&e)))
+    })?;
+    let words = compiled.words.len();
+    run_compiled(&loaded, &compiled, transport, compiler, session)?;
+    println!("Loaded {} ({} word(s) defined)", path, words);
+    Ok(())
+}
+
+fn warn_moved(report: &RestoreReport) {
+    for moved in &report.moved {
+        println!(
+            "Warning: '{}' is now word #{} (was #{}); code calling it by index may reach another word",
+            moved.name, moved.assigned, moved.saved
+        );
+    }
+}
+
+/// Reopen the port after the device went away, e.g. re-enumerated on USB
+///
+/// Then checks whether the device kept its words. One that restarted gets
+/// the words defined this session again; the compiler context is synced
+/// with what the device has. `nosync` only reopens the port.
+fn cmd_reconnect(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+    args: &[&str],
+) -> Result<()> {
+    let sync = match args {
+        [] => true,
+        ["nosync"] => false,
+        _ => {
+            return Err(crate::V4Error::Cli(
+                "Usage: .reconnect [nosync]".to_string(),
+            ));
+        }
+    };
+    println!(
+        "Waiting up to {}s for the device (Ctrl+C to stop)...",
+        RECONNECT_WAIT.as_secs()
+    );
+    let path = transport.reconnect(RECONNECT_WAIT)?;
+    println!("Reconnected through {}", path);
+    if sync {
+        resync(transport, compiler, session)?;
+    }
+    Ok(())
+}
+
+/// Bring the compiler context and the device's dictionary together again
+fn resync(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    session: &mut Session,
+) -> Result<()> {
+    let Some(last) = session.words.last() else {
+        return Ok(());
+    };
+    let kept = device::query_word(transport, last.index, DEFAULT_TIMEOUT)?.is_some_and(|word| {
+        word.name
+            .as_deref()
+            .is_none_or(|name| name.eq_ignore_ascii_case(&last.name))
+    });
+    if kept {
+        println!("Device kept its words");
         return Ok(());
     }
 
-    // Skip data stack
-    let ds_depth = data[0] as usize;
-    let mut pos = 1 + ds_depth * 4;
+    compiler.reset();
+    session.debugger.reset();
+    register_words(compiler, &device_words(transport)?)?;
+    let report =
+        SessionFile::new(session.words.clone()).restore(transport, compiler, DEFAULT_TIMEOUT)?;
+    warn_moved(&report);
+    println!(
+        "Device restarted; defined {} word(s) from this session again",
+        report.words.len()
+    );
+    session.words = report.words;
+    Ok(())
+}
 
-    if pos >= data.len() {
-        println!("No return stack data available");
+/// Set a breakpoint by word name or index, or list breakpoints
+fn cmd_break(transport: &mut dyn Transport, debugger: &mut Debugger, args: &[&str]) -> Result<()> {
+    let Some(&word_arg) = args.first() else {
+        if debugger.breakpoints().is_empty() {
+            println!("No breakpoints set");
+        }
+        for bp in debugger.breakpoints() {
+            println!("  word #{} at {:04X}", bp.word, bp.offset);
+        }
         return Ok(());
+    };
+
+    let word = match word_arg.parse::<u16>() {
+        Ok(idx) => idx,
+        Err(_) => debugger::find_word(transport, word_arg, DEFAULT_TIMEOUT)?
+            .ok_or_else(|| crate::V4Error::Cli(format!("Unknown word: {}", word_arg)))?,
+    };
+    let offset = match args.get(1) {
+        Some(arg) => arg
+            .parse::<u16>()
+            .map_err(|_| crate::V4Error::Cli(format!("Invalid offset: {}", arg)))?,
+        None => 0,
+    };
+
+    debugger.set_breakpoint(transport, Breakpoint { word, offset }, DEFAULT_TIMEOUT)?;
+    println!("Breakpoint set at word #{} offset {:04X}", word, offset);
+    Ok(())
+}
+
+/// Display data and return stacks
+fn cmd_stack(transport: &mut dyn Transport, output: &OutputFormat) -> Result<()> {
+    let stack = transport.stack_snapshot(DEFAULT_TIMEOUT)?;
+    print!("{}", format::stack(&stack, output));
+    Ok(())
+}
+
+/// Set how `.stack` and `.dump` write numbers: `.format [style] [size] [endian]`
+///
+/// Style is hex, dec, signed, bin, c or python; size 8, 16 or 32 (bits per
+/// memory word); endian little or big. Arguments may come in any order and
+/// leave the others unchanged. Without arguments, shows the current format.
+fn cmd_format(session: &mut Session, args: &[&str]) -> Result<()> {
+    let mut output = session.format;
+    for arg in args {
+        if let Ok(style) = format::parse_style(arg) {
+            output.style = style;
+        } else if let Ok(size) = format::parse_word_size(arg) {
+            output.word_size = size;
+        } else if let Ok(endian) = format::parse_endian(arg) {
+            output.endian = endian;
+        } else {
+            return Err(crate::V4Error::Cli(format!(
+                "Invalid format: {} (expected hex, dec, signed, bin, c, python, 8, 16, 32, little or big)",
+                arg
+            )));
+        }
     }
+    session.format = output;
+    println!("Output format: {}", output);
+    Ok(())
+}
 
-    let rs_depth = data[pos] as usize;
-    pos += 1;
+/// Display return stack with call trace
+fn cmd_rstack(transport: &mut dyn Transport) -> Result<()> {
+    let stack = transport.stack_snapshot(DEFAULT_TIMEOUT)?;
 
-    println!("Return Stack (depth: {} / 64):", rs_depth);
-    if rs_depth == 0 {
+    println!("Return Stack (depth: {} / 64):", stack.ret.len());
+    if stack.ret.is_empty() {
         println!("  <empty>");
         return Ok(());
     }
 
     println!("\nCall trace (most recent first):");
-    for i in 0..rs_depth {
-        if pos + 4 > data.len() {
-            break;
-        }
-        let value = i32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
-        println!("  [{:2}]: 0x{:08X}", i, value as u32);
-        pos += 4;
+    for (i, value) in stack.ret.iter().enumerate() {
+        println!("  [{:2}]: 0x{:08X}", i, value);
     }
 
     Ok(())
 }
 
-/// Hexdump memory at address
-fn cmd_dump(serial: &mut V4Serial, args: &[&str]) -> Result<()> {
-    // TODO: Track last dump address for continuation
-    let addr: u32 = if args.is_empty() {
-        0 // Default to address 0
-    } else {
-        args[0]
-            .parse()
-            .map_err(|_| crate::V4Error::Cli(format!("Invalid address: {}", args[0])))?
+/// Hexdump memory at address, or save it to a file
+///
+/// `.dump [addr] [len] [> file]`: the address is a number (decimal or `0x`
+/// hex), a word name standing for its bytecode address, or either followed
+/// by `+offset` terms (`SQ+4`). Without an address the dump continues where
+/// the last one ended. The length may be written `+len`, so `.dump +64`
+/// continues with 64 bytes. Lengths over 256 bytes take several queries.
+/// With `> file` the raw bytes are written to the file instead.
+fn cmd_dump(transport: &mut dyn Transport, session: &mut Session, args: &[&str]) -> Result<()> {
+    let (args, out) = match args {
+        [args @ .., ">", file] => (args, Some(*file)),
+        [.., ">"] => {
+            return Err(crate::V4Error::Cli(
+                "Usage: .dump [addr] [len] > <file>".to_string(),
+            ));
+        }
+        args => (args, None),
+    };
+    let (addr, len_arg) = match args {
+        [] => (session.next_dump, None),
+        [len] if len.starts_with('+') => (session.next_dump, Some(*len)),
+        [addr, rest @ ..] => (resolve_address(transport, addr)?, rest.first().copied()),
     };
 
-    let len: u16 = if args.len() < 2 {
-        256 // Default to 256 bytes
-    } else {
-        args[1]
-            .parse::<u16>()
-            .map_err(|_| crate::V4Error::Cli(format!("Invalid length: {}", args[1])))?
-            .min(256)
+    let len = match len_arg {
+        Some(arg) => parse_length(arg.strip_prefix('+').unwrap_or(arg))?,
+        None => DUMP_DEFAULT_LEN,
     };
 
-    let response = serial.query_memory(addr, len, DEFAULT_TIMEOUT)?;
-    if response.error_code != ErrorCode::Ok {
-        return Err(crate::V4Error::Device(format!(
-            "Query memory failed: {}",
-            response.error_code.name()
-        )));
+    let dump = device::read_memory(transport, addr, len, DEFAULT_TIMEOUT, &mut |_| {})?;
+    session.next_dump = dump.end();
+    if let Some(file) = out {
+        fs::write(file, &dump.bytes)?;
+        println!(
+            "Saved {} bytes from 0x{:08X} to {}",
+            dump.bytes.len(),
+            addr,
+            file
+        );
+        return Ok(());
     }
+    // Arrays are meant for pasting elsewhere, so they come bare
+    if !session.format.is_array() {
+        println!(
+            "Memory dump at 0x{:08X} ({} bytes):\n",
+            addr,
+            dump.bytes.len()
+        );
+    }
+    print!("{}", format::memory(&dump, &session.format));
+    Ok(())
+}
 
-    let data = &response.data;
-    println!("Memory dump at 0x{:08X} ({} bytes):\n", addr, data.len());
-
-    // Display in 16-byte rows
-    for (i, chunk) in data.chunks(16).enumerate() {
-        let offset = addr + (i * 16) as u32;
-        print!("{:08X}  ", offset);
+/// Parse a `.dump` or `.diff` length, at most [`DUMP_MAX_LEN`]
+fn parse_length(arg: &str) -> Result<u32> {
+    parse_number(arg)
+        .filter(|&n| n <= DUMP_MAX_LEN)
+        .ok_or_else(|| {
+            crate::V4Error::Cli(format!(
+                "Invalid length: {} (at most {} bytes)",
+                arg, DUMP_MAX_LEN
+            ))
+        })
+}
 
-        // Hex values
-        for (j, byte) in chunk.iter().enumerate() {
-            print!("{:02X} ", byte);
-            if j == 7 {
-                print!(" ");
+/// Compare memory with a baseline: `.diff <addr> <len>`, then `.diff`
+///
+/// With an address and length, reads the range as the baseline. Without,
+/// reads the same range again and shows the rows that changed since. The
+/// baseline stays, so each `.diff` shows all changes since it was taken.
+fn cmd_diff(transport: &mut dyn Transport, session: &mut Session, args: &[&str]) -> Result<()> {
+    match args {
+        [] => {
+            let baseline = session.baseline.as_ref().ok_or_else(|| {
+                crate::V4Error::Cli("No baseline yet; take one with .diff <addr> <len>".to_string())
+            })?;
+            let len = baseline.bytes.len() as u32;
+            let current =
+                device::read_memory(transport, baseline.addr, len, DEFAULT_TIMEOUT, &mut |_| {})?;
+            let diff = MemDiff {
+                addr: baseline.addr,
+                old: baseline.bytes.clone(),
+                new: current.bytes,
+            };
+            match diff.changed() {
+                0 => println!("No changes in {} bytes at 0x{:08X}", len, diff.addr),
+                changed => {
+                    print!("{}", memdiff::format(&diff));
+                    println!("{} byte(s) changed", changed);
+                }
             }
         }
-
-        // Padding if last row is incomplete
-        for j in chunk.len()..16 {
-            print!("   ");
-            if j == 7 {
-                print!(" ");
-            }
+        [addr, len] => {
+            let addr = resolve_address(transport, addr)?;
+            let len = parse_length(len)?;
+            let dump = device::read_memory(transport, addr, len, DEFAULT_TIMEOUT, &mut |_| {})?;
+            println!("Baseline: {} bytes at 0x{:08X}", dump.bytes.len(), addr);
+            session.baseline = Some(dump);
+        }
+        _ => {
+            return Err(crate::V4Error::Cli(
+                "Usage: .diff <addr> <len> (take baseline), .diff (compare)".to_string(),
+            ));
         }
+    }
+    Ok(())
+}
 
-        // ASCII representation
-        print!(" |");
-        for byte in chunk {
-            let c = if *byte >= 0x20 && *byte <= 0x7E {
-                *byte as char
-            } else {
-                '.'
+/// Write bytes to memory: `.poke <addr> <byte>...`
+///
+/// The address takes the same forms as `.dump`.
+fn cmd_poke(transport: &mut dyn Transport, args: &[&str]) -> Result<()> {
+    let Some((addr, bytes)) = args.split_first().filter(|(_, bytes)| !bytes.is_empty()) else {
+        return Err(crate::V4Error::Cli(
+            "Usage: .poke <addr> <byte>...".to_string(),
+        ));
+    };
+
+    let addr = resolve_address(transport, addr)?;
+    let data = bytes
+        .iter()
+        .map(|b| parse_byte(b))
+        .collect::<Result<Vec<u8>>>()?;
+    write_memory(transport, addr, &data)
+}
+
+/// Turn line timing on or off: `.time [on|off]`
+fn cmd_time(transport: &mut dyn Transport, session: &mut Session, args: &[&str]) -> Result<()> {
+    let on = match args {
+        [] => session.stopwatch.is_none(),
+        ["on"] => true,
+        ["off"] => false,
+        _ => return Err(crate::V4Error::Cli("Usage: .time [on|off]".to_string())),
+    };
+    if !on {
+        session.stopwatch = None;
+        println!("Timing off");
+        return Ok(());
+    }
+
+    let stopwatch = Stopwatch::probe(transport, DEFAULT_TIMEOUT);
+    session.stopwatch = Some(stopwatch);
+    if stopwatch.reads_ticks() {
+        println!("Timing on (host clock and VM cycle counter)");
+    } else {
+        println!("Timing on (host clock, link included)");
+    }
+    Ok(())
+}
+
+/// Start or stop recording a transcript: `.record on [file]`, `.record off`
+///
+/// A bare `.record on` resumes the last transcript of the session.
+fn cmd_record(session: &mut Session, args: &[&str]) -> Result<()> {
+    match args {
+        [] => match &session.transcript {
+            Some(transcript) => println!("Recording to {}", transcript.path()),
+            None => println!("Not recording"),
+        },
+        ["on", rest @ ..] if rest.len() <= 1 => {
+            let path = match rest.first() {
+                Some(path) => path.to_string(),
+                None => session.transcript_path.clone().ok_or_else(|| {
+                    crate::V4Error::Cli("Usage: .record on <file> (nothing to resume)".to_string())
+                })?,
             };
-            print!("{}", c);
+            let transcript = TranscriptWriter::open(&path)?;
+            println!("Recording to {}", path);
+            session.transcript = Some(transcript);
+            session.transcript_path = Some(path);
+        }
+        ["off"] => match session.transcript.take() {
+            Some(transcript) => println!("Stopped recording to {}", transcript.path()),
+            None => println!("Not recording"),
+        },
+        _ => {
+            return Err(crate::V4Error::Cli(
+                "Usage: .record [on [file]|off]".to_string(),
+            ));
         }
-        println!("|");
     }
+    Ok(())
+}
 
+/// Set a memory range to one value: `.fill <addr> <len> <byte>`
+fn cmd_fill(transport: &mut dyn Transport, args: &[&str]) -> Result<()> {
+    let [addr, len, value] = args else {
+        return Err(crate::V4Error::Cli(
+            "Usage: .fill <addr> <len> <byte>".to_string(),
+        ));
+    };
+
+    let addr = resolve_address(transport, addr)?;
+    let len = parse_number(len)
+        .filter(|&n| n > 0 && n <= FILL_MAX_LEN)
+        .ok_or_else(|| crate::V4Error::Cli(format!("Invalid length: {}", len)))?;
+    let value = parse_byte(value)?;
+    write_memory(transport, addr, &vec![value; len as usize])
+}
+
+/// Drive or read a pin: `.gpio <pin> on|off|read|toggle`
+fn cmd_gpio(transport: &mut dyn Transport, args: &[&str]) -> Result<()> {
+    let [pin, action] = args else {
+        return Err(crate::V4Error::Cli(
+            "Usage: .gpio <pin> on|off|read|toggle".to_string(),
+        ));
+    };
+
+    let pin = parse_byte(pin)?;
+    let action = gpio::parse_action(action).map_err(crate::V4Error::Cli)?;
+    let level = gpio::apply(transport, pin, action, DEFAULT_TIMEOUT)?;
+    println!("GPIO {}: {}", pin, if level { "high" } else { "low" });
     Ok(())
 }
 
+fn write_memory(transport: &mut dyn Transport, addr: u32, data: &[u8]) -> Result<()> {
+    let response = transport.write_memory(addr, data, DEFAULT_TIMEOUT)?;
+    if response.error_code != ErrorCode::Ok {
+        return Err(crate::V4Error::Device(format!(
+            "Write memory failed: {}",
+            response.error_code.name()
+        )));
+    }
+    println!("Wrote {} byte(s) at 0x{:08X}", data.len(), addr);
+    Ok(())
+}
+
+/// Parse a byte value, decimal or `0x` hex
+fn parse_byte(text: &str) -> Result<u8> {
+    parse_number(text)
+        .and_then(|n| u8::try_from(n).ok())
+        .ok_or_else(|| crate::V4Error::Cli(format!("Invalid byte: {}", text)))
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Evaluate a `.dump` address: a number or word name plus `+offset` terms
+fn resolve_address(transport: &mut dyn Transport, expr: &str) -> Result<u32> {
+    let invalid = || crate::V4Error::Cli(format!("Invalid address: {}", expr));
+    let mut terms = expr.split('+');
+    let base = terms.next().filter(|t| !t.is_empty()).ok_or_else(invalid)?;
+
+    // A bad hex number (`0xZZ`) is a typo, not a word name
+    let mut addr = match parse_number(base) {
+        Some(addr) => addr,
+        None if base.to_ascii_lowercase().starts_with("0x") => return Err(invalid()),
+        None => word_address(transport, base)?,
+    };
+    for term in terms {
+        let offset = parse_number(term).ok_or_else(invalid)?;
+        addr = addr.checked_add(offset).ok_or_else(invalid)?;
+    }
+    Ok(addr)
+}
+
+/// Bytecode address of a named device word
+fn word_address(transport: &mut dyn Transport, name: &str) -> Result<u32> {
+    let idx = debugger::find_word(transport, name, DEFAULT_TIMEOUT)?
+        .ok_or_else(|| crate::V4Error::Cli(format!("Unknown word: {}", name)))?;
+    device::query_word(transport, idx, DEFAULT_TIMEOUT)?
+        .and_then(|word| word.address)
+        .ok_or_else(|| {
+            crate::V4Error::Device(format!("Device doesn't report the address of '{}'", name))
+        })
+}
+
 /// Show word bytecode disassembly
-fn cmd_see(serial: &mut V4Serial, args: &[&str]) -> Result<()> {
+fn cmd_see(transport: &mut dyn Transport, args: &[&str]) -> Result<()> {
     if args.is_empty() {
         return Err(crate::V4Error::Cli("Usage: .see <word_index>".to_string()));
     }
@@ -420,62 +1514,864 @@ fn cmd_see(serial: &mut V4Serial, args: &[&str]) -> Result<()> {
         .parse()
         .map_err(|_| crate::V4Error::Cli(format!("Invalid word index: {}", args[0])))?;
 
-    let response = serial.query_word(word_idx, DEFAULT_TIMEOUT)?;
-    if response.error_code != ErrorCode::Ok {
-        return Err(crate::V4Error::Device(format!(
-            "Query word failed: {}",
-            response.error_code.name()
-        )));
-    }
+    let word = device::query_word(transport, word_idx, DEFAULT_TIMEOUT)?
+        .ok_or_else(|| crate::V4Error::Device(format!("No word at index {}", word_idx)))?;
+
+    println!("Word: {}", word.display_name());
+    println!("Index: {}", word_idx);
+    println!("Bytecode length: {} bytes\n", word.code_len);
 
-    let data = &response.data;
-    if data.is_empty() {
-        println!("No word data received");
+    if word.code_len == 0 || word.code.is_empty() {
+        println!("No bytecode");
         return Ok(());
     }
 
-    // Parse response: [NAME_LEN][NAME...][CODE_LEN_L][CODE_LEN_H][CODE...]
-    let name_len = data[0] as usize;
-    let mut pos = 1;
+    println!("Disassembly:");
+    print!("{}", crate::disasm::listing(&word.code));
 
-    let name = if name_len > 0 && pos + name_len <= data.len() {
-        String::from_utf8_lossy(&data[pos..pos + name_len]).to_string()
-    } else {
-        "<anonymous>".to_string()
-    };
-    pos += name_len;
+    Ok(())
+}
 
-    if pos + 2 > data.len() {
-        println!("Incomplete word data");
+/// List device words by querying indices from 0 until the device refuses
+///
+/// Named words are registered in the compiler context so words defined
+/// before this session (e.g. with `--no-reset`) become callable.
+fn cmd_words(transport: &mut dyn Transport, compiler: &mut Compiler) -> Result<()> {
+    let words = device_words(transport)?;
+    if words.is_empty() {
+        println!("No words defined on device");
         return Ok(());
     }
 
-    let code_len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
-    pos += 2;
+    println!("Index  Name                  Bytes");
+    println!("-----  --------------------  -----");
+    for (idx, word) in &words {
+        println!(
+            "{:>5}  {:<20}  {:>5}",
+            idx,
+            word.display_name(),
+            word.code_len
+        );
+    }
+    register_words(compiler, &words)?;
+    println!("\n{} word(s), synced with compiler context", words.len());
 
-    println!("Word: {}", name);
-    println!("Index: {}", word_idx);
-    println!("Bytecode length: {} bytes\n", code_len);
+    Ok(())
+}
 
-    if code_len == 0 || pos >= data.len() {
-        println!("No bytecode");
-        return Ok(());
+/// Query device words from index 0 until the device refuses
+fn device_words(transport: &mut dyn Transport) -> Result<Vec<(u16, WordInfo)>> {
+    let words = device::read_words(transport, DEFAULT_TIMEOUT)?;
+    Ok((0..).zip(words).collect())
+}
+
+/// Make the named device words callable from the compiler context
+fn register_words(compiler: &mut Compiler, words: &[(u16, WordInfo)]) -> Result<()> {
+    for (idx, word) in words {
+        if let Some(name) = &word.name {
+            compiler
+                .register_word_index(name, *idx as i32)
+                .map_err(crate::V4Error::Repl)?;
+        }
     }
+    Ok(())
+}
 
-    println!("Disassembly:");
-    println!("Offset  Bytes");
-    println!("------  -------------------------");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use crate::transport::mock::MockTransport;
+    use std::io::Write;
+
+    #[test]
+    fn test_execute_registers_word_index() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        transport.push_word_indices(&[7]);
+
+        let compiled = compiler.compile(": SQUARE DUP * ;").unwrap();
+        execute_on_device(&mut transport, &compiled, &[], &mut compiler, &mut session).unwrap();
+
+        assert_eq!(transport.sent_commands(), vec![Command::Exec]);
+        assert_eq!(transport.sent[0].payload, compiled.words[0].bytecode);
+
+        // Word is now known to the compiler context
+        assert!(compiler.compile("5 SQUARE").is_ok());
+    }
+
+    #[test]
+    fn test_save_reset_restore_restores_words() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = format!(".save {}", file.path().display());
+        let restore = format!(".restore {}", file.path().display());
+
+        transport.push_word_indices(&[7]);
+        dispatch_line(
+            ": SQUARE DUP * ;",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+        handle_meta_command(&save, &mut transport, &mut compiler, &mut session).unwrap();
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        handle_meta_command(".reset", &mut transport, &mut compiler, &mut session).unwrap();
+        assert!(session.words.is_empty());
+
+        transport.push_word_indices(&[0]);
+        handle_meta_command(&restore, &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(session.words.len(), 1);
+        assert_eq!(session.words[0].name, "SQUARE");
+        assert_eq!(session.words[0].index, 0);
+        assert_eq!(transport.sent[2].payload, transport.sent[0].payload);
+        assert!(compiler.compile("5 SQUARE").is_ok());
+    }
+
+    #[test]
+    fn test_load_source_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("lib.fs"), ": SQ DUP * ;\n").unwrap();
+        let main = dir.path().join("main.fs");
+        fs::write(&main, "INCLUDE \"lib.fs\"\n: CUBE DUP SQ * ;\n3 CUBE\n").unwrap();
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        transport.push_word_indices(&[0]);
+        transport.push_word_indices(&[1]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        let load = format!(".load {}", main.display());
+        dispatch_line(&load, &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(transport.sent_commands().len(), 3);
+        assert_eq!(session.source("CUBE"), Some(": CUBE DUP SQ * ;"));
+        assert!(compiler.compile("2 CUBE").is_ok());
+
+        // Compile errors name the line in the file
+        fs::write(&main, "1 2 +\nBOGUS\n").unwrap();
+        let err = dispatch_line(&load, &mut transport, &mut compiler, &mut session).unwrap_err();
+        assert!(err.to_string().contains("main.fs:2"), "{}", err);
+    }
+
+    #[test]
+    fn test_multi_word_definition_then_reference() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        // One EXEC per definition, each answered with its own index
+        let compiled = compiler.compile(": ON 1 ; : OFF 0 ; : TOGGLE 2 ;").unwrap();
+        transport.push_word_indices(&[10]);
+        transport.push_word_indices(&[11]);
+        transport.push_word_indices(&[12]);
+        execute_on_device(&mut transport, &compiled, &[], &mut compiler, &mut session).unwrap();
+
+        let payloads: Vec<_> = transport.sent.iter().map(|f| f.payload.clone()).collect();
+        let expected: Vec<_> = compiled.words.iter().map(|w| w.bytecode.clone()).collect();
+        assert_eq!(payloads, expected);
+
+        // Later line references all of them
+        transport.push_response(ErrorCode::Ok, &[]);
+        dispatch_line("ON OFF TOGGLE", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(transport.sent.len(), 4);
+    }
+
+    #[test]
+    fn test_execute_rejects_index_count_mismatch() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        // Device claims two registrations for a single definition
+        let compiled = compiler.compile(": ON 1 ;").unwrap();
+        transport.push_word_indices(&[0, 1]);
+
+        let result = execute_on_device(&mut transport, &compiled, &[], &mut compiler, &mut session);
+        assert!(matches!(result, Err(crate::V4Error::Protocol(_))));
+        assert!(compiler.compile("ON").is_err());
+    }
+
+    #[test]
+    fn test_execute_device_error() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        transport.push_response(ErrorCode::VmError, &[]);
+
+        let compiled = compiler.compile("1 2 +").unwrap();
+        let result = execute_on_device(&mut transport, &compiled, &[], &mut compiler, &mut session);
+        assert!(matches!(result, Err(crate::V4Error::Device(_))));
+    }
+
+    #[test]
+    fn test_vm_error_names_source_line() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_word_indices(&[0]);
+        dispatch_line("1 2 +", &mut transport, &mut compiler, &mut session).unwrap();
+        dispatch_line(": SQ DUP * ;", &mut transport, &mut compiler, &mut session).unwrap();
+
+        // VM_ERROR at offset 1 of word #0, then QUERY_WORD naming it
+        transport.push_response(ErrorCode::VmError, &[0, 0, 1, 0]);
+        let word = WordInfo {
+            name: Some("SQ".to_string()),
+            code_len: 0,
+            code: Vec::new(),
+            address: None,
+        };
+        transport.push_response(ErrorCode::Ok, &word.to_payload());
+        let err = dispatch_line("3 SQ", &mut transport, &mut compiler, &mut session).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Device error: Execution failed: VM_ERROR at <repl>:2 in word SQ"
+        );
+    }
+
+    #[test]
+    fn test_meta_reset_clears_compiler() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        compiler.compile(": TEST 42 ;").unwrap();
+        compiler.register_word_index("TEST", 0).unwrap();
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        handle_meta_command(".reset", &mut transport, &mut compiler, &mut session).unwrap();
+
+        assert_eq!(transport.sent_commands(), vec![Command::Reset]);
+        assert!(compiler.compile("TEST").is_err());
+    }
 
-    let code = &data[pos..];
-    for (i, chunk) in code.chunks(16).enumerate() {
-        print!("{:04X}    ", i * 16);
-        for byte in chunk {
-            print!("{:02X} ", byte);
+    #[test]
+    fn test_definitions_record_source() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        transport.push_word_indices(&[0]);
+        transport.push_word_indices(&[1]);
+        let line = ": SQ DUP * ;\n: CUBE DUP\n  SQ * ;";
+        dispatch_line(line, &mut transport, &mut compiler, &mut session).unwrap();
+        transport.push_word_indices(&[2]);
+        dispatch_line(
+            ": SQ DUP DUP * * ;",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+
+        assert_eq!(session.source("sq"), Some(": SQ DUP DUP * * ;"));
+        assert_eq!(session.source("CUBE"), Some(": CUBE DUP\nSQ * ;"));
+        assert_eq!(session.words[0].source.as_deref(), Some(": SQ DUP * ;"));
+        assert!(word_source(&compiler, &session, "CUBE").is_ok());
+        assert!(matches!(
+            word_source(&compiler, &session, "NOPE"),
+            Err(crate::V4Error::Repl(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_buffer_joins_definition_lines() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        transport.push_word_indices(&[3]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        let text = ": SQUARE\n  DUP *\n;\n\n.help\n5 SQUARE\nbye\n1 2 +\n";
+        let outcome = run_buffer(text, &mut transport, &mut compiler, &mut session).unwrap();
+
+        assert_eq!(outcome, LineOutcome::Exit);
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::Exec, Command::Exec]
+        );
+
+        let result = run_buffer(": HALF 2 /", &mut transport, &mut compiler, &mut session);
+        assert!(matches!(result, Err(crate::V4Error::Repl(_))));
+        assert_eq!(transport.sent.len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_text_returns_saved_buffer() {
+        let text = edit_text("sed -i s/DUP/OVER/", ": SQ DUP * ;\n").unwrap();
+        assert_eq!(text, ": SQ OVER * ;\n");
+        assert!(matches!(
+            edit_text("false", ""),
+            Err(crate::V4Error::Repl(_))
+        ));
+    }
+
+    #[test]
+    fn test_meta_unknown_sends_nothing() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        handle_meta_command(".bogus", &mut transport, &mut compiler, &mut session).unwrap();
+        assert!(transport.sent.is_empty());
+    }
+
+    #[test]
+    fn test_meta_stack() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        // ds_depth=2 [3, -1], rs_depth=0
+        let mut payload = vec![2];
+        payload.extend_from_slice(&3i32.to_le_bytes());
+        payload.extend_from_slice(&(-1i32).to_le_bytes());
+        payload.push(0);
+        transport.push_response(ErrorCode::Ok, &payload);
+
+        handle_meta_command(".stack", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(transport.sent_commands(), vec![Command::QueryStack]);
+    }
+
+    #[test]
+    fn test_meta_stack_under_length() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        // Claims 3 data stack cells but carries only one
+        let mut payload = vec![3];
+        payload.extend_from_slice(&1i32.to_le_bytes());
+        transport.push_response(ErrorCode::Ok, &payload);
+
+        let result = handle_meta_command(".stack", &mut transport, &mut compiler, &mut session);
+        assert!(matches!(result, Err(crate::V4Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_time_toggles_line_timing() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        // No cycle counter: QUERY_INFO fails, the host clock is used
+        transport.push_response(ErrorCode::Error, &[]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        dispatch_line(".time", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(session.stopwatch, Some(Stopwatch::host()));
+        dispatch_line("1 2 +", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::QueryInfo, Command::Exec]
+        );
+
+        dispatch_line(".time", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(session.stopwatch, None);
+        assert!(dispatch_line(".time later", &mut transport, &mut compiler, &mut session).is_err());
+    }
+
+    #[test]
+    fn test_dispatch_line_outcomes() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        for exit in ["bye", "quit", ".exit"] {
+            let outcome = dispatch_line(exit, &mut transport, &mut compiler, &mut session).unwrap();
+            assert_eq!(outcome, LineOutcome::Exit);
         }
-        println!();
+        let outcome = dispatch_line("   ", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(outcome, LineOutcome::Continue);
+        assert!(transport.sent.is_empty());
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        dispatch_line(".ping", &mut transport, &mut compiler, &mut session).unwrap();
+        dispatch_line("1 2 +", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::Ping, Command::Exec]
+        );
     }
 
-    println!("\nNote: Use V4-front disassembler for opcode names.");
+    #[test]
+    fn test_pipe_loop_reports_each_input() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        transport.push_word_indices(&[7]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::BufferFull, &[]);
+
+        let input = ": SQ\n  DUP * ;\n\n3 SQ\nBOGUS\n4 SQ\nbye\n1 2 +\n";
+        let summary =
+            pipe_loop(&mut transport, &mut compiler, None, input.as_bytes(), None).unwrap();
+        assert_eq!(
+            summary,
+            PipeSummary {
+                inputs: 5,
+                failed: 2
+            }
+        );
+        // Nothing after the exit word runs
+        assert_eq!(transport.sent_commands(), vec![Command::Exec; 3]);
+
+        let summary = pipe_loop(
+            &mut transport,
+            &mut compiler,
+            None,
+            ": CUBE".as_bytes(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.failed, 1);
+    }
 
-    Ok(())
+    #[test]
+    fn test_transcript_records_and_replays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let path = path.to_str().unwrap();
+        // SQ, `.gpio 7 on` (see `gpio::apply`), then `3 SQ`; BOGUS doesn't compile
+        let push_gpio_on = |transport: &mut MockTransport| {
+            transport.push_response(ErrorCode::Ok, &[]);
+            transport.push_stack(&[0]);
+            transport.push_response(ErrorCode::Ok, &[]);
+        };
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[0]);
+        push_gpio_on(&mut transport);
+        transport.push_response(ErrorCode::Ok, &[]);
+        let mut compiler = Compiler::new().unwrap();
+
+        let input = ": SQ\n  DUP * ;\n.gpio 7 on\nBOGUS\n.record off\n3 SQ\n";
+        let transcript = TranscriptWriter::open(path).unwrap();
+        pipe_loop(
+            &mut transport,
+            &mut compiler,
+            None,
+            input.as_bytes(),
+            Some(transcript),
+        )
+        .unwrap();
+
+        let mut steps = transcript::Transcript::load(path).unwrap().steps();
+        let inputs: Vec<_> = steps.iter().map(|step| step.input.as_str()).collect();
+        assert_eq!(inputs, [": SQ\nDUP * ;", ".gpio 7 on", "BOGUS"]);
+        assert_eq!(steps[0].outcome, transcript::Outcome::Ok);
+        assert!(steps[1].output.contains("GPIO 7"));
+        assert!(matches!(steps[2].outcome, transcript::Outcome::Error(_)));
+
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[0]);
+        push_gpio_on(&mut transport);
+        let mut compiler = Compiler::new().unwrap();
+        let summary = replay_loop(&mut transport, &mut compiler, None, &steps, None).unwrap();
+        assert_eq!(
+            summary,
+            ReplaySummary {
+                inputs: 3,
+                diverged: 0
+            }
+        );
+
+        // Defining BOGUS first makes the recorded failure pass
+        steps.insert(0, steps[0].clone());
+        steps[0].input = ": BOGUS ;".to_string();
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[0]);
+        transport.push_word_indices(&[1]);
+        push_gpio_on(&mut transport);
+        transport.push_response(ErrorCode::Ok, &[]);
+        let mut compiler = Compiler::new().unwrap();
+        let summary = replay_loop(&mut transport, &mut compiler, None, &steps, None).unwrap();
+        assert_eq!(summary.diverged, 1);
+    }
+
+    #[test]
+    fn test_pipe_error_codes() {
+        use crate::V4Error;
+        let err = V4Error::Device("Execution failed: BUFFER_FULL".to_string());
+        assert_eq!(
+            pipe_error(&err),
+            "ERR BUFFER_FULL Device error: Execution failed: BUFFER_FULL"
+        );
+        let err = V4Error::Compilation("Unknown word: X".to_string());
+        assert!(pipe_error(&err).starts_with("ERR COMPILE "));
+        assert!(pipe_error(&V4Error::Timeout).starts_with("ERR TIMEOUT "));
+        let err = V4Error::Repl("first\nsecond".to_string());
+        assert_eq!(pipe_error(&err), "ERR FAILED REPL error: first second");
+    }
+
+    #[test]
+    fn test_run_stops_on_first_error() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, ".ping\n.see\n.ping").unwrap();
+        let path = script.path().to_str().unwrap();
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        let result = handle_meta_command(
+            &format!(".run {}", path),
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        );
+
+        assert!(matches!(result, Err(crate::V4Error::Repl(msg)) if msg.contains(":2:")));
+        assert_eq!(transport.sent_commands(), vec![Command::Ping]);
+    }
+
+    #[test]
+    fn test_run_continue_prefix() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "~ .see\n: SQ DUP * ;\nbye\n.ping").unwrap();
+        let path = script.path().to_str().unwrap();
+
+        transport.push_word_indices(&[0]);
+        handle_meta_command(
+            &format!(".run {}", path),
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+
+        // Word definition replayed, nothing sent after `bye`
+        assert_eq!(transport.sent_commands(), vec![Command::Exec]);
+        assert!(compiler.compile("3 SQ").is_ok());
+    }
+
+    #[test]
+    fn test_meta_see_requires_index() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        let result = handle_meta_command(".see", &mut transport, &mut compiler, &mut session);
+        assert!(matches!(result, Err(crate::V4Error::Cli(_))));
+        assert!(transport.sent.is_empty());
+    }
+
+    #[test]
+    fn test_meta_see_queries_word() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        // [NAME_LEN]["SQ"][CODE_LEN=3][DUP MUL RET]
+        transport.push_response(ErrorCode::Ok, &[2, b'S', b'Q', 3, 0, 0x01, 0x12, 0x51]);
+
+        handle_meta_command(".see 4", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(transport.sent_commands(), vec![Command::QueryWord]);
+        assert_eq!(transport.sent[0].payload, vec![4, 0]);
+    }
+
+    #[test]
+    fn test_meta_words_syncs_compiler() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        transport.push_response(ErrorCode::Ok, &[2, b'S', b'Q', 3, 0, 0x01, 0x12, 0x51]);
+        transport.push_response(ErrorCode::Ok, &[3, b'T', b'W', b'O', 1, 0, 0x51]);
+        transport.push_response(ErrorCode::Error, &[]);
+
+        handle_meta_command(".words", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(transport.sent_commands(), vec![Command::QueryWord; 3]);
+        assert_eq!(transport.sent[1].payload, vec![1, 0]);
+        assert!(compiler.compile("3 SQ TWO").is_ok());
+    }
+
+    #[test]
+    fn test_reconnect_restores_words_after_restart() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        transport.push_word_indices(&[7]);
+        dispatch_line(": SQ DUP * ;", &mut transport, &mut compiler, &mut session).unwrap();
+
+        // Still there: nothing to do
+        transport.push_response(ErrorCode::Ok, &[2, b'S', b'Q', 1, 0, 0x51]);
+        handle_meta_command(".reconnect", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(transport.reconnects, 1);
+        assert_eq!(transport.sent.len(), 2);
+
+        // Gone after a restart: the device is empty, SQ is defined again
+        transport.push_response(ErrorCode::Error, &[]);
+        transport.push_response(ErrorCode::Error, &[]);
+        transport.push_word_indices(&[0]);
+        handle_meta_command(".reconnect", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(
+            transport.sent_commands()[2..],
+            [Command::QueryWord, Command::QueryWord, Command::Exec]
+        );
+        assert_eq!(transport.sent[4].payload, transport.sent[0].payload);
+        assert_eq!(session.words[0].index, 0);
+        assert_eq!(session.words[0].source.as_deref(), Some(": SQ DUP * ;"));
+        assert!(compiler.compile("3 SQ").is_ok());
+
+        handle_meta_command(
+            ".reconnect nosync",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+        assert_eq!(transport.reconnects, 3);
+        assert_eq!(transport.sent.len(), 5);
+    }
+
+    #[test]
+    fn test_breakpoint_halts_line() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        handle_meta_command(".break 0 2", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(transport.sent[0].payload, vec![0, 0, 2, 0]);
+
+        // Halted in top-level code, then the stacks are queried
+        transport.push_response(ErrorCode::Halted, &[0xFF, 0xFF, 2, 0]);
+        transport.push_response(ErrorCode::Ok, &[0, 0]);
+        dispatch_line("1 2 +", &mut transport, &mut compiler, &mut session).unwrap();
+        assert!(session.debugger.halted().is_some());
+
+        // New code is refused until the VM resumes
+        assert!(dispatch_line("3", &mut transport, &mut compiler, &mut session).is_err());
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        dispatch_line(".continue", &mut transport, &mut compiler, &mut session).unwrap();
+        assert!(session.debugger.halted().is_none());
+        assert_eq!(
+            transport.sent_commands(),
+            vec![
+                Command::SetBreakpoint,
+                Command::Exec,
+                Command::QueryStack,
+                Command::Continue
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dump_continues() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        transport.push_response(ErrorCode::Ok, &[0; 16]);
+        transport.push_response(ErrorCode::Ok, &[0; 8]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        handle_meta_command(
+            ".dump 0x100 16",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+        handle_meta_command(".dump +8", &mut transport, &mut compiler, &mut session).unwrap();
+        handle_meta_command(".dump", &mut transport, &mut compiler, &mut session).unwrap();
+
+        // [ADDR u32][LEN u16]
+        assert_eq!(transport.sent[0].payload, vec![0x00, 0x01, 0, 0, 16, 0]);
+        assert_eq!(transport.sent[1].payload, vec![0x10, 0x01, 0, 0, 8, 0]);
+        assert_eq!(transport.sent[2].payload, vec![0x18, 0x01, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_dump_to_file_reads_in_chunks() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("mem.bin");
+
+        transport.push_response(ErrorCode::Ok, &[0x11; 256]);
+        transport.push_response(ErrorCode::Ok, &[0x22; 44]);
+        let line = format!(".dump 0x2000 300 > {}", file.display());
+        handle_meta_command(&line, &mut transport, &mut compiler, &mut session).unwrap();
+
+        let saved = fs::read(&file).unwrap();
+        assert_eq!(saved.len(), 300);
+        assert_eq!((saved[255], saved[256]), (0x11, 0x22));
+        assert_eq!(transport.sent[1].payload, vec![0x00, 0x21, 0, 0, 44, 0]);
+        assert_eq!(session.next_dump, 0x212C);
+
+        let err = handle_meta_command(".dump 0 16 >", &mut transport, &mut compiler, &mut session);
+        assert!(err.is_err());
+        let err = handle_meta_command(
+            ".dump 0 0x20000",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        );
+        assert!(err.unwrap_err().to_string().contains("at most 65536 bytes"));
+    }
+
+    #[test]
+    fn test_format_changes_given_parts() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        handle_meta_command(
+            ".format 16 dec",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+        handle_meta_command(".format be", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(
+            session.format,
+            OutputFormat {
+                style: format::Style::Dec,
+                word_size: 2,
+                endian: format::Endian::Big,
+            }
+        );
+        assert!(
+            handle_meta_command(".format 12", &mut transport, &mut compiler, &mut session).is_err()
+        );
+        assert_eq!(session.format.word_size, 2);
+    }
+
+    #[test]
+    fn test_diff_against_baseline() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+
+        assert!(handle_meta_command(".diff", &mut transport, &mut compiler, &mut session).is_err());
+        transport.push_response(ErrorCode::Ok, &[0; 32]);
+        transport.push_response(ErrorCode::Ok, &[0; 32]);
+        let mut changed = [0; 32];
+        changed[20] = 0x2A;
+        transport.push_response(ErrorCode::Ok, &changed);
+        handle_meta_command(
+            ".diff 0x2000 32",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+        handle_meta_command(".diff", &mut transport, &mut compiler, &mut session).unwrap();
+        handle_meta_command(".diff", &mut transport, &mut compiler, &mut session).unwrap();
+
+        // Every read covers the baseline's range
+        for frame in &transport.sent {
+            assert_eq!(frame.payload, vec![0x00, 0x20, 0, 0, 32, 0]);
+        }
+        assert_eq!(session.baseline.as_ref().unwrap().bytes, vec![0; 32]);
+    }
+
+    #[test]
+    fn test_dump_word_address() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        // SQ: RET at 0x2000, looked up by name and then queried for its address
+        let word = [2, b'S', b'Q', 1, 0, 0x51, 0x00, 0x20, 0x00, 0x00];
+        transport.push_response(ErrorCode::Ok, &word);
+        transport.push_response(ErrorCode::Ok, &word);
+        transport.push_response(ErrorCode::Ok, &[0x51]);
+
+        handle_meta_command(".dump SQ+4 +1", &mut transport, &mut compiler, &mut session).unwrap();
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::QueryWord, Command::QueryWord, Command::QueryMemory]
+        );
+        assert_eq!(transport.sent[2].payload, vec![0x04, 0x20, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_dump_rejects_bad_address() {
+        let mut transport = MockTransport::new();
+        for expr in ["0xZZ", "1+x", "+"] {
+            assert!(resolve_address(&mut transport, expr).is_err(), "{}", expr);
+        }
+        assert!(transport.sent.is_empty());
+        assert_eq!(parse_number("0x2000"), Some(0x2000));
+        assert_eq!(parse_number("42"), Some(42));
+    }
+
+    #[test]
+    fn test_gpio_on_then_read() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        for results in [&[0][..], &[1, 0]] {
+            transport.push_response(ErrorCode::Ok, &[]);
+            transport.push_stack(results);
+            transport.push_response(ErrorCode::Ok, &[]);
+        }
+
+        handle_meta_command(".gpio 7 on", &mut transport, &mut compiler, &mut session).unwrap();
+        handle_meta_command(".gpio 7 read", &mut transport, &mut compiler, &mut session).unwrap();
+        assert!(
+            handle_meta_command(".gpio 7 blink", &mut transport, &mut compiler, &mut session)
+                .is_err()
+        );
+        let call = [Command::Exec, Command::QueryStack, Command::Exec];
+        assert_eq!(transport.sent_commands(), [call, call].concat());
+    }
+
+    #[test]
+    fn test_poke_and_fill() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut session = Session::default();
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        handle_meta_command(
+            ".poke 0x100 1 0xFF",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+        handle_meta_command(
+            ".fill 0x200 3 0xAA",
+            &mut transport,
+            &mut compiler,
+            &mut session,
+        )
+        .unwrap();
+        assert_eq!(transport.sent_commands(), vec![Command::WriteMemory; 2]);
+        assert_eq!(
+            transport.sent[0].payload,
+            vec![0x00, 0x01, 0, 0, 0x01, 0xFF]
+        );
+        assert_eq!(
+            transport.sent[1].payload,
+            vec![0x00, 0x02, 0, 0, 0xAA, 0xAA, 0xAA]
+        );
+
+        // Bad values are rejected before anything is sent
+        for line in [
+            ".poke 0x100",
+            ".poke 0x100 256",
+            ".fill 0x100 0 1",
+            ".fill 0x100 4",
+        ] {
+            assert!(
+                handle_meta_command(line, &mut transport, &mut compiler, &mut session).is_err(),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(transport.sent.len(), 2);
+    }
 }
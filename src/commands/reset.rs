@@ -1,25 +1,118 @@
 use crate::Result;
-use crate::protocol::ErrorCode;
-use crate::serial::V4Serial;
+use crate::device::{ResetReport, V4Device};
+use crate::serial::SerialSettings;
+use crate::transport::RetryPolicy;
 use std::time::Duration;
 
-/// Send RESET command to device
-pub fn reset(port: &str, timeout: Duration) -> Result<()> {
-    let mut serial = V4Serial::open_default(port)?;
+/// Send RESET command to device and wait until it answers PING again
+pub fn reset(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    timeout: Duration,
+    ready_timeout: Duration,
+) -> Result<ResetReport> {
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    device.reset(timeout, ready_timeout)
+}
+
+/// VM resets around a push or exec, done in the same connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resets {
+    pub before: bool,
+    pub after: bool,
+    /// How long to wait for the device to answer PING after each reset
+    pub ready_timeout: Duration,
+}
 
-    println!("Sending RESET to {}...", port);
+/// When [`around`] reset the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetStage {
+    Before,
+    After,
+}
 
-    let err_code = serial.reset(timeout)?;
+/// Run `f` on an open device between the resets asked for
+///
+/// The reset after also runs when `f` fails, so the next run starts from a
+/// clean VM; the error from `f` is returned over one from that reset.
+/// `on_reset` receives each reset done.
+pub fn around<T>(
+    device: &mut V4Device,
+    resets: &Resets,
+    timeout: Duration,
+    on_reset: &mut dyn FnMut(ResetStage, &ResetReport),
+    f: impl FnOnce(&mut V4Device) -> Result<T>,
+) -> Result<T> {
+    if resets.before {
+        on_reset(
+            ResetStage::Before,
+            &device.reset(timeout, resets.ready_timeout)?,
+        );
+    }
+    let result = f(device);
+    if resets.after {
+        match device.reset(timeout, resets.ready_timeout) {
+            Ok(report) => on_reset(ResetStage::After, &report),
+            Err(e) if result.is_ok() => return Err(e),
+            Err(e) => log::warn!("Reset after the failed run also failed: {}", e),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use crate::transport::mock::MockTransport;
+
+    fn resets(before: bool, after: bool) -> Resets {
+        Resets {
+            before,
+            after,
+            ready_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_around_resets_in_order() {
+        let mut transport = MockTransport::new();
+        for _ in 0..2 {
+            transport.push_response(ErrorCode::Ok, &[]);
+            transport.push_response(ErrorCode::Ok, &[]);
+        }
+        transport.push_response(ErrorCode::Ok, &[]);
+        let mut device = V4Device::from_transport(Box::new(transport), "mock");
+
+        let mut stages = Vec::new();
+        around(
+            &mut device,
+            &resets(true, true),
+            Duration::from_secs(1),
+            &mut |stage, _| stages.push(stage),
+            |device| device.ping(Duration::from_secs(1)),
+        )
+        .unwrap();
+        assert_eq!(stages, [ResetStage::Before, ResetStage::After]);
+    }
 
-    println!("Response: {}", err_code.name());
+    #[test]
+    fn test_around_resets_after_failure() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        let mut device = V4Device::from_transport(Box::new(transport), "mock");
 
-    if err_code == ErrorCode::Ok {
-        println!("✓ VM reset successful");
-        Ok(())
-    } else {
-        Err(crate::V4Error::Device(format!(
-            "Device returned error: {}",
-            err_code.name()
-        )))
+        let mut stages = Vec::new();
+        let result: Result<()> = around(
+            &mut device,
+            &resets(false, true),
+            Duration::from_secs(1),
+            &mut |stage, _| stages.push(stage),
+            |_| Err(crate::V4Error::Device("boom".to_string())),
+        );
+        assert!(matches!(result, Err(crate::V4Error::Device(_))));
+        assert_eq!(stages, [ResetStage::After]);
     }
 }
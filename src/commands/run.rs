@@ -0,0 +1,58 @@
+use super::inspect;
+use crate::engine::{Engine, EngineError};
+use crate::{Result, V4Error};
+use std::fs;
+
+/// Result of running bytecode on the V4 engine
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    /// Word definitions loaded from the file
+    pub words: usize,
+    /// Data stack at the end, bottom first
+    pub data_stack: Vec<i32>,
+    /// Why execution stopped early, if it did
+    pub error: Option<EngineError>,
+}
+
+/// Run a bytecode file on the V4 engine, on the host
+///
+/// A .v4b file has its words defined before the main code runs; any other
+/// file is run as raw bytecode.
+pub fn run(file: &str) -> Result<RunReport> {
+    let data = fs::read(file)?;
+    let mut engine = Engine::new()?;
+
+    let (code, words) = if data.starts_with(b"V4BC") {
+        let inspection = inspect::parse(&data)?;
+        for word in &inspection.words {
+            engine.define(&word.name, &word.bytecode).map_err(|e| {
+                V4Error::Device(format!(
+                    "Cannot define '{}' from {}: {}",
+                    word.name, file, e
+                ))
+            })?;
+        }
+        (inspection.code, inspection.words.len())
+    } else {
+        (data, 0)
+    };
+
+    let error = engine.run(&code).err();
+    Ok(RunReport {
+        words,
+        data_stack: engine.data_stack(),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_example() {
+        let report = run("examples/led_on.v4b").unwrap();
+        assert_eq!(report.error, None);
+        assert_eq!(report.words, 0);
+    }
+}
@@ -0,0 +1,258 @@
+//! `v4 script`: replay a text file of REPL lines with host directives
+//!
+//! Lines run in order through the same dispatch as the REPL, so Forth code
+//! and meta-commands (`.reset`, `.dump`) mix freely. On top of that, the
+//! host understands a few directives:
+//!
+//! ```text
+//! # Lines starting with '#' are comments
+//! sleep 500ms
+//! expect-stack 1 2 3
+//! expect-memory 0x1000 0x2A 0
+//! ```
+//!
+//! `sleep` takes `ms` or `s` (a bare number is milliseconds). The
+//! assertions are described in [`crate::testing`].
+//!
+//! The script stops at the first failing line unless the line is prefixed
+//! with `~`, like `.run`.
+
+use super::repl::{LineOutcome, RUN_CONTINUE_PREFIX, Session, dispatch_line};
+use crate::ffi::Compiler;
+use crate::testing::{Assertion, AssertionResult};
+use crate::transport::Transport;
+use crate::ui;
+use crate::{Result, V4Error};
+use std::fs;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Timeout for the queries behind assertions
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Host-side script line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive {
+    /// Pause before the next line
+    Sleep(Duration),
+    /// Check device state (`expect-stack`, `expect-memory`)
+    Assert(Assertion),
+}
+
+impl Directive {
+    /// Parse a directive line, `None` if the line is Forth or a meta-command
+    pub fn parse(line: &str) -> Option<Result<Self>> {
+        if let Some(assertion) = Assertion::parse(line) {
+            return Some(assertion.map(Self::Assert));
+        }
+        let mut parts = line.split_whitespace();
+        if parts.next()? != "sleep" {
+            return None;
+        }
+        Some(match (parts.next(), parts.next()) {
+            (Some(duration), None) => parse_duration(duration).map(Self::Sleep),
+            _ => Err(V4Error::Cli("Usage: sleep <duration>".to_string())),
+        })
+    }
+}
+
+/// Result of a script that ran to the end
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScriptReport {
+    /// Lines executed, comments and blank lines excluded
+    pub lines: usize,
+    /// Assertions that passed
+    pub checks: usize,
+    /// Lines prefixed with `~` that failed
+    pub ignored_errors: usize,
+}
+
+/// Run a script file against an open connection
+///
+/// Each line is echoed as it runs. A failed assertion stops the script like
+/// any other failing line; failures carry the file and line number.
+pub fn run_script(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    path: &str,
+) -> Result<ScriptReport> {
+    replay(
+        transport,
+        compiler,
+        path,
+        &mut |result| match result.failure {
+            None => Ok(()),
+            Some(failure) => Err(V4Error::Script(failure)),
+        },
+    )
+}
+
+/// Replay a script, handing each checked assertion to `on_assertion`
+///
+/// An error from `on_assertion` fails the assertion's line.
+pub(crate) fn replay(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    path: &str,
+    on_assertion: &mut dyn FnMut(AssertionResult) -> Result<()>,
+) -> Result<ScriptReport> {
+    let script = fs::read_to_string(path)?;
+    let mut session = Session::default();
+    let mut report = ScriptReport::default();
+
+    for (lineno, raw) in script.lines().enumerate() {
+        let raw = raw.trim();
+        let (line, keep_going) = match raw.strip_prefix(RUN_CONTINUE_PREFIX) {
+            Some(rest) => (rest.trim(), true),
+            None => (raw, false),
+        };
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        println!("{}{}", ui::prompt("v4>"), line);
+        report.lines += 1;
+        let result = match Directive::parse(line) {
+            Some(directive) => directive.and_then(|directive| {
+                match directive {
+                    Directive::Sleep(duration) => sleep(duration),
+                    Directive::Assert(assertion) => {
+                        let failure = assertion.check(transport, QUERY_TIMEOUT)?;
+                        let passed = failure.is_none();
+                        on_assertion(AssertionResult {
+                            line: lineno + 1,
+                            source: line.to_string(),
+                            failure,
+                        })?;
+                        report.checks += usize::from(passed);
+                    }
+                }
+                Ok(LineOutcome::Continue)
+            }),
+            None => dispatch_line(line, transport, compiler, &mut session),
+        };
+
+        match result {
+            Ok(LineOutcome::Continue) => {}
+            Ok(LineOutcome::Exit) => break,
+            Err(e) if keep_going => {
+                eprintln!("{} {}", ui::error_label(), ui::error(e));
+                report.ignored_errors += 1;
+            }
+            Err(e) => {
+                let message = match e {
+                    V4Error::Script(message) => message,
+                    e => e.to_string(),
+                };
+                return Err(V4Error::Script(format!(
+                    "{}:{}: {}",
+                    path,
+                    lineno + 1,
+                    message
+                )));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Parse `500ms`, `2s` or a bare number of milliseconds
+fn parse_duration(text: &str) -> Result<Duration> {
+    let invalid = || V4Error::Cli(format!("Invalid duration: {}", text));
+    let (number, unit_ms) = match text.strip_suffix("ms") {
+        Some(number) => (number, 1),
+        None => match text.strip_suffix('s') {
+            Some(number) => (number, 1000),
+            None => (text, 1),
+        },
+    };
+    let value: u64 = number.parse().map_err(|_| invalid())?;
+    Ok(Duration::from_millis(
+        value.checked_mul(unit_ms).ok_or_else(invalid)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Command, ErrorCode};
+    use crate::transport::mock::MockTransport;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_directives() {
+        assert_eq!(
+            Directive::parse("sleep 500ms").unwrap().unwrap(),
+            Directive::Sleep(Duration::from_millis(500))
+        );
+        assert_eq!(
+            Directive::parse("sleep 2s").unwrap().unwrap(),
+            Directive::Sleep(Duration::from_secs(2))
+        );
+        assert_eq!(
+            Directive::parse("expect-stack 1 2").unwrap().unwrap(),
+            Directive::Assert(Assertion::Stack(vec![1, 2]))
+        );
+        assert!(Directive::parse("sleep soon").unwrap().is_err());
+        assert!(Directive::parse("sleep").unwrap().is_err());
+        assert!(Directive::parse("1 2 +").is_none());
+        assert!(Directive::parse(".stack").is_none());
+    }
+
+    #[test]
+    fn test_script_mixes_forth_meta_and_directives() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            script,
+            "# Setup\n.ping\n1 2\nsleep 0ms\nexpect-stack 1 2\n~ expect-stack 3\nexpect-stack"
+        )
+        .unwrap();
+        let path = script.path().to_str().unwrap();
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_stack(&[1, 2]);
+        transport.push_stack(&[1, 2]);
+        transport.push_stack(&[1, 2]);
+
+        let err = run_script(&mut transport, &mut compiler, path).unwrap_err();
+        assert!(
+            matches!(&err, V4Error::Script(msg) if msg.ends_with(":7: expected stack [], got [1 2]")),
+            "{}",
+            err
+        );
+        assert_eq!(
+            transport.sent_commands(),
+            vec![
+                Command::Ping,
+                Command::Exec,
+                Command::QueryStack,
+                Command::QueryStack,
+                Command::QueryStack
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script_report() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "expect-stack\n~ .see\nbye\n.ping").unwrap();
+        let path = script.path().to_str().unwrap();
+
+        transport.push_stack(&[]);
+        let report = run_script(&mut transport, &mut compiler, path).unwrap();
+        assert_eq!(
+            report,
+            ScriptReport {
+                lines: 3,
+                checks: 1,
+                ignored_errors: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use super::compile::SizeBreakdown;
+use super::inspect;
+use crate::Result;
+use std::fs;
+
+/// Bytecode footprint of a .v4b file, word by word
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeReport {
+    pub file_size: usize,
+    pub breakdown: SizeBreakdown,
+    /// Device dictionary size the bytecode is measured against
+    pub dict_size: Option<usize>,
+}
+
+impl SizeReport {
+    /// Percentage of the dictionary `bytes` take up
+    pub fn dict_share(&self, bytes: usize) -> Option<f64> {
+        self.dict_size
+            .map(|dict| bytes as f64 * 100.0 / dict.max(1) as f64)
+    }
+}
+
+/// Measure the words and main code of a .v4b file
+///
+/// The file is validated like [`inspect::inspect`] does.
+pub fn size(file: &str, dict_size: Option<usize>) -> Result<SizeReport> {
+    let data = fs::read(file)?;
+    let inspection = inspect::parse(&data)?;
+    Ok(SizeReport {
+        file_size: data.len(),
+        breakdown: SizeBreakdown {
+            words: inspection
+                .words
+                .iter()
+                .map(|word| (word.name.clone(), word.bytecode.len()))
+                .collect(),
+            main: inspection.code.len(),
+        },
+        dict_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::compile::{CompileOptions, compile};
+
+    #[test]
+    fn test_size_of_compiled_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("app.v4");
+        fs::write(&source, ": SQ DUP * ;\n: CUBE DUP SQ * ;\n3 CUBE\n").unwrap();
+        let output = dir.path().join("app.v4b");
+        let compiled = compile(
+            &[source.to_str().unwrap()],
+            output.to_str(),
+            &CompileOptions::default(),
+        )
+        .unwrap();
+
+        let report = size(output.to_str().unwrap(), Some(1024)).unwrap();
+        assert_eq!(report.file_size as u64, compiled.output_size);
+        let names: Vec<&str> = report
+            .breakdown
+            .words
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["SQ", "CUBE"]);
+        assert_eq!(report.dict_share(256), Some(25.0));
+        assert!(size(source.to_str().unwrap(), None).is_err());
+    }
+}
@@ -0,0 +1,17 @@
+use crate::Result;
+use crate::device::V4Device;
+use crate::protocol::StackSnapshot;
+use crate::serial::SerialSettings;
+use crate::transport::RetryPolicy;
+use std::time::Duration;
+
+/// Read the data and return stacks
+pub fn stack(
+    port: Option<&str>,
+    settings: &SerialSettings,
+    retry: RetryPolicy,
+    timeout: Duration,
+) -> Result<StackSnapshot> {
+    let mut device = V4Device::open(port, settings)?.with_retry(retry)?;
+    device.stack(timeout)
+}
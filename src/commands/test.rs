@@ -0,0 +1,108 @@
+//! `v4 test`: run a script and report every assertion
+//!
+//! Unlike `v4 script`, a failed assertion does not stop the run; each one is
+//! recorded so the whole script reports pass/fail per assertion. A line that
+//! fails to run (Forth error, timeout) still ends the run.
+
+use super::script;
+use crate::V4Error;
+use crate::ffi::Compiler;
+use crate::testing::AssertionResult;
+use crate::transport::Transport;
+use std::time::{Duration, Instant};
+
+/// Outcome of `v4 test` on one script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestReport {
+    pub file: String,
+    /// Assertions in the order they ran
+    pub assertions: Vec<AssertionResult>,
+    /// Why the script stopped early, if it did
+    pub error: Option<String>,
+    pub duration: Duration,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.assertions.iter().filter(|a| a.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.assertions.len() - self.passed()
+    }
+
+    /// Every assertion passed and the script ran to the end
+    pub fn success(&self) -> bool {
+        self.failed() == 0 && self.error.is_none()
+    }
+}
+
+/// Run a script, collecting the outcome of each assertion
+pub fn run_test(transport: &mut dyn Transport, compiler: &mut Compiler, path: &str) -> TestReport {
+    let start = Instant::now();
+    let mut assertions = Vec::new();
+    let result = script::replay(transport, compiler, path, &mut |result| {
+        assertions.push(result);
+        Ok(())
+    });
+
+    TestReport {
+        file: path.to_string(),
+        assertions,
+        error: result.err().map(|e| match e {
+            V4Error::Script(message) => message,
+            e => e.to_string(),
+        }),
+        duration: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use crate::transport::mock::MockTransport;
+    use std::io::Write;
+
+    #[test]
+    fn test_failed_assertions_do_not_stop_the_run() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            script,
+            "expect-stack 1\nexpect-stack\nexpect-memory 0x10 0x2A"
+        )
+        .unwrap();
+        let path = script.path().to_str().unwrap();
+
+        transport.push_stack(&[2]);
+        transport.push_stack(&[]);
+        transport.push_response(ErrorCode::Ok, &[0x2A]);
+
+        let report = run_test(&mut transport, &mut compiler, path);
+        assert_eq!(report.error, None);
+        assert_eq!((report.passed(), report.failed()), (2, 1));
+        assert!(!report.success());
+        assert_eq!(report.assertions[0].line, 1);
+        assert_eq!(
+            report.assertions[0].failure.as_deref(),
+            Some("expected stack [1], got [2]")
+        );
+    }
+
+    #[test]
+    fn test_line_error_ends_the_run() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "expect-stack\n.ping\nexpect-stack").unwrap();
+        let path = script.path().to_str().unwrap();
+
+        transport.push_stack(&[]);
+        let report = run_test(&mut transport, &mut compiler, path);
+        assert_eq!(report.assertions.len(), 1);
+        assert!(report.error.as_deref().unwrap().contains(":2: "));
+        assert!(!report.success());
+    }
+}
@@ -0,0 +1,101 @@
+//! Shell completion scripts for the `v4` binary
+//!
+//! The scripts are generated from the clap definitions. Bash, zsh and fish
+//! also complete `--port` values with the serial ports present when Tab is
+//! pressed, taken from `v4 ports --names`.
+
+use clap::Command;
+use clap_complete::{Shell, generate};
+use std::io::Write;
+
+/// Bash: complete ports after `--port`/`-p`, everything else as generated
+const BASH_PORTS: &str = r#"
+_v4_ports() {
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "$prev" == "--port" || "$prev" == "--ports" || "$prev" == "-p" ]]; then
+        COMPREPLY=($(compgen -W "$(v4 ports --names 2>/dev/null)" -- "${COMP_WORDS[COMP_CWORD]}"))
+        return 0
+    fi
+    _v4 "$@"
+}
+
+if [[ "${BASH_VERSINFO[0]}" -eq 4 && "${BASH_VERSINFO[1]}" -ge 4 || "${BASH_VERSINFO[0]}" -gt 4 ]]; then
+    complete -F _v4_ports -o nosort -o bashdefault -o default v4
+else
+    complete -F _v4_ports -o bashdefault -o default v4
+fi
+"#;
+
+/// Zsh: candidate function the generated `--port` specs are pointed at
+const ZSH_PORTS: &str = r#"
+(( $+functions[_v4_ports] )) ||
+_v4_ports() {
+    local -a ports
+    ports=(${(f)"$(v4 ports --names 2>/dev/null)"})
+    _describe -t ports 'serial port' ports
+    _files
+}
+"#;
+
+/// Fish: ports offered for `--port` in every subcommand
+const FISH_PORTS: &str = r#"
+complete -c v4 -s p -l port -f -a "(v4 ports --names 2>/dev/null)"
+"#;
+
+/// Write the completion script for `shell` to `out`
+pub fn write(shell: Shell, cmd: &mut Command, out: &mut dyn Write) -> std::io::Result<()> {
+    let name = cmd.get_name().to_string();
+    let mut script = Vec::new();
+    generate(shell, cmd, &name, &mut script);
+    let mut script = String::from_utf8_lossy(&script).into_owned();
+
+    match shell {
+        Shell::Bash => script.push_str(BASH_PORTS),
+        Shell::Zsh => {
+            script = script.replace(":PORT:_default", ":PORT:_v4_ports");
+            // The generated script ends by calling `_v4`; define ours before it
+            let at = script
+                .rfind("\nif [ \"$funcstack[1]\" = \"_v4\" ]")
+                .unwrap_or(script.len());
+            script.insert_str(at, ZSH_PORTS);
+        }
+        Shell::Fish => script.push_str(FISH_PORTS),
+        _ => {}
+    }
+    out.write_all(script.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, Command};
+
+    fn cli() -> Command {
+        Command::new("v4").subcommand(
+            Command::new("ping").arg(Arg::new("port").short('p').long("port").value_name("PORT")),
+        )
+    }
+
+    fn script(shell: Shell) -> String {
+        let mut out = Vec::new();
+        write(shell, &mut cli(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_zsh_ports_defined_before_entry_point() {
+        let zsh = script(Shell::Zsh);
+        assert!(zsh.contains(":PORT:_v4_ports"));
+        assert!(!zsh.contains(":PORT:_default"));
+        let defined = zsh.find("_v4_ports() {").unwrap();
+        let called = zsh.rfind("if [ \"$funcstack[1]\" = \"_v4\" ]").unwrap();
+        assert!(defined < called);
+    }
+
+    #[test]
+    fn test_bash_and_fish_ask_v4_for_ports() {
+        assert!(script(Shell::Bash).ends_with(BASH_PORTS));
+        assert!(script(Shell::Fish).contains("(v4 ports --names 2>/dev/null)"));
+        assert!(!script(Shell::PowerShell).contains("ports --names"));
+    }
+}
@@ -0,0 +1,535 @@
+//! User configuration file
+//!
+//! Defaults for the CLI are read from `~/.config/v4/config.toml`
+//! (`$XDG_CONFIG_HOME/v4/config.toml` when set). The `V4_CONFIG`
+//! environment variable points to an alternative file. Command-line flags
+//! always take precedence over values from the file.
+//!
+//! ```toml
+//! port = "/dev/ttyACM0"
+//! baud_rate = 115200
+//! data_bits = 8
+//! parity = "none"
+//! stop_bits = 1
+//! flow_control = "none"
+//! dtr = "low"
+//! rts = "low"
+//! timeout = 5
+//! retries = 3
+//! retry_delay = 100
+//!
+//! [repl]
+//! no_reset = false
+//!
+//! [groups]
+//! rack = ["/dev/ttyACM0", "/dev/ttyACM1", "tcp://10.0.0.7"]
+//! lab = ["bench1", "tcp://10.0.0.8"]
+//!
+//! [devices.bench1]
+//! port = "/dev/serial/by-id/usb-V4_Board_1234-if00"
+//! baud = 115200
+//! ```
+//!
+//! `--device <name>` selects a `[devices]` entry, whose port and serial
+//! settings replace the top-level ones, or a group for the commands that run
+//! on several devices. Groups may list device names as well as ports.
+
+use crate::serial::{self, SerialSettings};
+use crate::{Result, V4Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Key prefix of device groups (`groups.<name>`)
+const GROUP_PREFIX: &str = "groups.";
+
+/// Keys accepted by `v4 config get/set`, besides `groups.<name>`
+pub const KEYS: &[&str] = &[
+    "port",
+    "baud_rate",
+    "data_bits",
+    "parity",
+    "stop_bits",
+    "flow_control",
+    "dtr",
+    "rts",
+    "timeout",
+    "retries",
+    "retry_delay",
+    "repl.no_reset",
+];
+
+/// Configuration file contents
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Serial port path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    /// Serial baud rate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baud_rate: Option<u32>,
+    /// Serial data bits (5-8)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_bits: Option<u8>,
+    /// Serial parity: "none", "odd" or "even"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parity: Option<String>,
+    /// Serial stop bits (1, 2)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_bits: Option<u8>,
+    /// Serial flow control: "none", "software" or "hardware"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<String>,
+    /// DTR level set on open: "high" or "low"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dtr: Option<String>,
+    /// RTS level set on open: "high" or "low"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rts: Option<String>,
+    /// Response timeout in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Resends of a lost or corrupted frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Delay before the first resend in milliseconds (doubles per attempt)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<u64>,
+    /// REPL options
+    #[serde(skip_serializing_if = "ReplConfig::is_empty")]
+    pub repl: ReplConfig,
+    /// Named sets of ports for commands that accept `--group`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Named devices for `--device`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub devices: BTreeMap<String, DeviceConfig>,
+}
+
+/// `[devices.<name>]` section; unset serial keys fall back to the top level
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub port: String,
+    #[serde(default, alias = "baud", skip_serializing_if = "Option::is_none")]
+    pub baud_rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_bits: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_bits: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dtr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rts: Option<String>,
+}
+
+/// `[repl]` section
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplConfig {
+    /// Skip VM reset on REPL startup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_reset: Option<bool>,
+}
+
+impl ReplConfig {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Config {
+    /// Path of the configuration file, honoring `V4_CONFIG`
+    pub fn path() -> Option<PathBuf> {
+        resolve_path(
+            std::env::var_os("V4_CONFIG"),
+            std::env::var_os("XDG_CONFIG_HOME"),
+            std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")),
+        )
+    }
+
+    /// Load the configuration file, or defaults if it doesn't exist
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Load configuration from a specific file, or defaults if missing
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text)
+            .map_err(|e| V4Error::Config(format!("{}: {}", path.display(), e.message())))
+    }
+
+    /// Write configuration to a file, creating parent directories
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = toml::to_string(self).map_err(|e| V4Error::Config(e.to_string()))?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Get a value by key, `None` if unset
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        if let Some(name) = key.strip_prefix(GROUP_PREFIX) {
+            return Ok(self.groups.get(name).map(|ports| ports.join(",")));
+        }
+        Ok(match key {
+            "port" => self.port.clone(),
+            "baud_rate" => self.baud_rate.map(|v| v.to_string()),
+            "data_bits" => self.data_bits.map(|v| v.to_string()),
+            "parity" => self.parity.clone(),
+            "stop_bits" => self.stop_bits.map(|v| v.to_string()),
+            "flow_control" => self.flow_control.clone(),
+            "dtr" => self.dtr.clone(),
+            "rts" => self.rts.clone(),
+            "timeout" => self.timeout.map(|v| v.to_string()),
+            "retries" => self.retries.map(|v| v.to_string()),
+            "retry_delay" => self.retry_delay.map(|v| v.to_string()),
+            "repl.no_reset" => self.repl.no_reset.map(|v| v.to_string()),
+            _ => return Err(unknown_key(key)),
+        })
+    }
+
+    /// Set a value by key, parsing it to the key's type
+    ///
+    /// `groups.<name>` takes a comma-separated list of ports.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        if let Some(name) = key.strip_prefix(GROUP_PREFIX).filter(|n| !n.is_empty()) {
+            let ports = value
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(String::from)
+                .collect();
+            self.groups.insert(name.to_string(), ports);
+            return Ok(());
+        }
+        match key {
+            "port" => self.port = Some(value.to_string()),
+            "baud_rate" => self.baud_rate = Some(parse_value(key, value)?),
+            "data_bits" => {
+                serial::parse_data_bits(value).map_err(V4Error::Config)?;
+                self.data_bits = Some(parse_value(key, value)?);
+            }
+            "parity" => {
+                serial::parse_parity(value).map_err(V4Error::Config)?;
+                self.parity = Some(value.to_ascii_lowercase());
+            }
+            "stop_bits" => {
+                serial::parse_stop_bits(value).map_err(V4Error::Config)?;
+                self.stop_bits = Some(parse_value(key, value)?);
+            }
+            "flow_control" => {
+                serial::parse_flow_control(value).map_err(V4Error::Config)?;
+                self.flow_control = Some(value.to_ascii_lowercase());
+            }
+            "dtr" => {
+                serial::parse_line_level(value).map_err(V4Error::Config)?;
+                self.dtr = Some(value.to_ascii_lowercase());
+            }
+            "rts" => {
+                serial::parse_line_level(value).map_err(V4Error::Config)?;
+                self.rts = Some(value.to_ascii_lowercase());
+            }
+            "timeout" => self.timeout = Some(parse_value(key, value)?),
+            "retries" => self.retries = Some(parse_value(key, value)?),
+            "retry_delay" => self.retry_delay = Some(parse_value(key, value)?),
+            "repl.no_reset" => self.repl.no_reset = Some(parse_value(key, value)?),
+            _ => return Err(unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    /// Ports of a device group
+    pub fn group(&self, name: &str) -> Result<&[String]> {
+        match self.groups.get(name) {
+            Some(ports) if !ports.is_empty() => Ok(ports),
+            Some(_) => Err(V4Error::Config(format!("Device group '{}' is empty", name))),
+            None => Err(V4Error::Config(format!("Unknown device group '{}'", name))),
+        }
+    }
+
+    /// Configuration with a named device's port and serial settings applied
+    pub fn with_device(&self, name: &str) -> Result<Config> {
+        let device = self.devices.get(name).ok_or_else(|| {
+            V4Error::Config(format!(
+                "Unknown device '{}' (not in [devices] or [groups])",
+                name
+            ))
+        })?;
+        let mut config = self.clone();
+        config.port = Some(device.port.clone());
+        config.baud_rate = device.baud_rate.or(self.baud_rate);
+        config.data_bits = device.data_bits.or(self.data_bits);
+        config.parity = device.parity.clone().or(config.parity);
+        config.stop_bits = device.stop_bits.or(self.stop_bits);
+        config.flow_control = device.flow_control.clone().or(config.flow_control);
+        config.dtr = device.dtr.clone().or(config.dtr);
+        config.rts = device.rts.clone().or(config.rts);
+        Ok(config)
+    }
+
+    /// Port and configuration for a target of a multi-device command
+    ///
+    /// A device name stands for its port and settings; anything else is a
+    /// port used with this configuration.
+    pub fn target(&self, target: &str) -> Result<(String, Config)> {
+        match self.devices.get(target) {
+            Some(device) => Ok((device.port.clone(), self.with_device(target)?)),
+            None => Ok((target.to_string(), self.clone())),
+        }
+    }
+
+    /// Serial settings from the file, with defaults for unset keys
+    pub fn serial_settings(&self) -> Result<SerialSettings> {
+        let invalid = |e: String| V4Error::Config(format!("config file: {}", e));
+        let mut settings = SerialSettings::default();
+
+        if let Some(baud_rate) = self.baud_rate {
+            settings.baud_rate = baud_rate;
+        }
+        if let Some(data_bits) = self.data_bits {
+            settings.data_bits =
+                serial::parse_data_bits(&data_bits.to_string()).map_err(invalid)?;
+        }
+        if let Some(parity) = &self.parity {
+            settings.parity = serial::parse_parity(parity).map_err(invalid)?;
+        }
+        if let Some(stop_bits) = self.stop_bits {
+            settings.stop_bits =
+                serial::parse_stop_bits(&stop_bits.to_string()).map_err(invalid)?;
+        }
+        if let Some(flow_control) = &self.flow_control {
+            settings.flow_control = serial::parse_flow_control(flow_control).map_err(invalid)?;
+        }
+        if let Some(dtr) = &self.dtr {
+            settings.dtr = Some(serial::parse_line_level(dtr).map_err(invalid)?);
+        }
+        if let Some(rts) = &self.rts {
+            settings.rts = Some(serial::parse_line_level(rts).map_err(invalid)?);
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Resolve the config path from `V4_CONFIG`, `XDG_CONFIG_HOME` and home
+fn resolve_path(
+    v4_config: Option<OsString>,
+    xdg_config_home: Option<OsString>,
+    home: Option<OsString>,
+) -> Option<PathBuf> {
+    if let Some(path) = v4_config.filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = match xdg_config_home.filter(|p| !p.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(home?).join(".config"),
+    };
+    Some(config_dir.join("v4").join("config.toml"))
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| V4Error::Config(format!("Invalid value for {}: {}", key, value)))
+}
+
+fn unknown_key(key: &str) -> V4Error {
+    V4Error::Config(format!(
+        "Unknown key: {} (expected one of: {}, {}<name>)",
+        key,
+        KEYS.join(", "),
+        GROUP_PREFIX
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toml() {
+        let config: Config = toml::from_str(
+            r#"
+port = "/dev/ttyACM0"
+timeout = 10
+
+[repl]
+no_reset = true
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.port.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(config.baud_rate, None);
+        assert_eq!(config.timeout, Some(10));
+        assert_eq!(config.repl.no_reset, Some(true));
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        assert!(toml::from_str::<Config>("prot = \"/dev/ttyACM0\"").is_err());
+    }
+
+    #[test]
+    fn test_get_set() {
+        let mut config = Config::default();
+        assert_eq!(config.get("baud_rate").unwrap(), None);
+
+        config.set("baud_rate", "921600").unwrap();
+        config.set("repl.no_reset", "true").unwrap();
+        assert_eq!(config.get("baud_rate").unwrap().as_deref(), Some("921600"));
+        assert_eq!(
+            config.get("repl.no_reset").unwrap().as_deref(),
+            Some("true")
+        );
+
+        config.set("retries", "3").unwrap();
+        assert_eq!(config.retries, Some(3));
+
+        assert!(config.set("timeout", "soon").is_err());
+        assert!(config.set("retries", "-1").is_err());
+        assert!(config.set("parity", "mark").is_err());
+        assert!(config.set("data_bits", "9").is_err());
+        assert!(config.set("colour", "yes").is_err());
+        assert!(config.get("colour").is_err());
+    }
+
+    #[test]
+    fn test_groups() {
+        let config: Config =
+            toml::from_str("[groups]\nrack = [\"/dev/ttyACM0\", \"/dev/ttyACM1\"]\nnone = []")
+                .unwrap();
+        assert_eq!(
+            config.group("rack").unwrap(),
+            ["/dev/ttyACM0", "/dev/ttyACM1"]
+        );
+        assert!(config.group("none").is_err());
+        assert!(config.group("lab").is_err());
+
+        let mut config = Config::default();
+        config
+            .set("groups.lab", "/dev/ttyUSB0, tcp://10.0.0.7")
+            .unwrap();
+        assert_eq!(
+            config.group("lab").unwrap(),
+            ["/dev/ttyUSB0", "tcp://10.0.0.7"]
+        );
+        assert_eq!(
+            config.get("groups.lab").unwrap().as_deref(),
+            Some("/dev/ttyUSB0,tcp://10.0.0.7")
+        );
+        assert!(config.set("groups.", "/dev/ttyUSB0").is_err());
+    }
+
+    #[test]
+    fn test_devices() {
+        let config: Config = toml::from_str(
+            r#"
+baud_rate = 9600
+parity = "even"
+
+[groups]
+lab = ["bench1", "/dev/ttyACM1"]
+
+[devices.bench1]
+port = "/dev/serial/by-id/usb-V4-if00"
+baud = 115200
+"#,
+        )
+        .unwrap();
+
+        let bench = config.with_device("bench1").unwrap();
+        assert_eq!(bench.port.as_deref(), Some("/dev/serial/by-id/usb-V4-if00"));
+        let settings = bench.serial_settings().unwrap();
+        assert_eq!(settings.baud_rate, 115200);
+        assert_eq!(settings.parity, serialport::Parity::Even);
+        assert!(config.with_device("lab").is_err());
+
+        let (port, target) = config.target("bench1").unwrap();
+        assert_eq!(port, "/dev/serial/by-id/usb-V4-if00");
+        assert_eq!(target.baud_rate, Some(115200));
+        let (port, target) = config.target("/dev/ttyACM1").unwrap();
+        assert_eq!(port, "/dev/ttyACM1");
+        assert_eq!(target.baud_rate, Some(9600));
+
+        assert!(toml::from_str::<Config>("[devices.x]\nbaud = 9600").is_err());
+    }
+
+    #[test]
+    fn test_serial_settings() {
+        use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+        assert_eq!(
+            Config::default().serial_settings().unwrap(),
+            SerialSettings::default()
+        );
+
+        let mut config = Config::default();
+        config.set("baud_rate", "9600").unwrap();
+        config.set("data_bits", "7").unwrap();
+        config.set("parity", "Even").unwrap();
+        config.set("stop_bits", "2").unwrap();
+        config.set("flow_control", "hardware").unwrap();
+        config.set("dtr", "LOW").unwrap();
+        assert!(config.set("rts", "off").is_err());
+        assert_eq!(config.parity.as_deref(), Some("even"));
+
+        let settings = config.serial_settings().unwrap();
+        assert_eq!(settings.baud_rate, 9600);
+        assert_eq!(settings.data_bits, DataBits::Seven);
+        assert_eq!(settings.parity, Parity::Even);
+        assert_eq!(settings.stop_bits, StopBits::Two);
+        assert_eq!(settings.flow_control, FlowControl::Hardware);
+        assert_eq!((settings.dtr, settings.rts), (Some(false), None));
+
+        // Hand-edited files are validated when used
+        let config: Config = toml::from_str("parity = \"mark\"").unwrap();
+        assert!(config.serial_settings().is_err());
+    }
+
+    #[test]
+    fn test_save_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("config.toml");
+
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+
+        let mut config = Config::default();
+        config.set("port", "/dev/ttyUSB0").unwrap();
+        config.set("timeout", "3").unwrap();
+        config.save_to(&path).unwrap();
+
+        assert_eq!(Config::load_from(&path).unwrap(), config);
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let path = resolve_path(Some("/tmp/v4.toml".into()), None, Some("/home/u".into()));
+        assert_eq!(path, Some(PathBuf::from("/tmp/v4.toml")));
+
+        let path = resolve_path(None, Some("/xdg".into()), Some("/home/u".into()));
+        assert_eq!(path, Some(PathBuf::from("/xdg/v4/config.toml")));
+
+        let path = resolve_path(None, None, Some("/home/u".into()));
+        assert_eq!(path, Some(PathBuf::from("/home/u/.config/v4/config.toml")));
+
+        assert_eq!(resolve_path(None, None, None), None);
+    }
+}
@@ -0,0 +1,391 @@
+//! Keep a serial connection open across `v4` invocations
+//!
+//! `v4 daemon` opens the port once and listens on a Unix socket named after
+//! it, in a directory only the current user can use. Commands given the same port find the socket and talk to the device
+//! through it instead of opening the port themselves, which is faster and
+//! doesn't toggle DTR (resetting some boards). The daemon relays bytes both
+//! ways and serves one client at a time; others wait for their turn.
+
+use crate::serial::{SerialSettings, V4Serial};
+use crate::stream::{ByteStream, StreamTransport};
+use crate::{Result, V4Error};
+use serialport::SerialPort;
+use std::fs::{self, DirBuilder};
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long each side of the relay waits for data before checking for stop
+const RELAY_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Pause between checks for a new client
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// V4-link through a running daemon
+pub type V4Daemon = StreamTransport<UnixStream>;
+
+/// Directory the daemon sockets live in, private to the current user
+///
+/// `$XDG_RUNTIME_DIR/v4`, or `v4-<uid>` in the temp directory without it.
+pub fn socket_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("v4"),
+        None => std::env::temp_dir().join(format!("v4-{}", current_uid())),
+    }
+}
+
+/// Socket a daemon serving `port` listens on
+///
+/// Lives in [`socket_dir`] and is named after the resolved port path, so
+/// `/dev/serial/by-id/...` links and the device they point to share one
+/// daemon.
+pub fn socket_path(port: &str) -> PathBuf {
+    let resolved = std::fs::canonicalize(port).unwrap_or_else(|_| PathBuf::from(port));
+    let name: String = resolved
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    socket_dir().join(format!("v4-{}.sock", name.trim_start_matches('_')))
+}
+
+/// Connect to the daemon serving `port`, if one is running
+///
+/// A socket another user could have put there is refused: everything sent
+/// through it would reach them instead of the device.
+pub fn connect(port: &str) -> Option<V4Daemon> {
+    let socket = socket_path(port);
+    if !socket.exists() {
+        return None;
+    }
+    if let Err(e) = check_private(&socket) {
+        log::warn!("Not using daemon socket {}: {}", socket.display(), e);
+        return None;
+    }
+    match UnixStream::connect(&socket) {
+        Ok(stream) => {
+            log::info!(
+                "Talking to {} through the daemon at {}",
+                port,
+                socket.display()
+            );
+            Some(StreamTransport::new(stream))
+        }
+        Err(e) => {
+            log::debug!("Ignoring stale daemon socket {}: {}", socket.display(), e);
+            None
+        }
+    }
+}
+
+/// Create `dir` with mode 0700, or check that an existing one is private
+fn create_private_dir(dir: &Path) -> Result<()> {
+    match DirBuilder::new().recursive(true).mode(0o700).create(dir) {
+        Ok(()) => check_private(dir),
+        Err(e) => Err(V4Error::Runtime(format!(
+            "Cannot create {}: {}",
+            dir.display(),
+            e
+        ))),
+    }
+}
+
+/// Check that `path` and the directory holding it (or `path`, if it is
+/// one) belong to the current user, and that only they can use the directory
+fn check_private(path: &Path) -> Result<()> {
+    let metadata = check_owner(path)?;
+    let dir = match metadata.is_dir() {
+        true => path,
+        false => path.parent().unwrap_or(Path::new(".")),
+    };
+    let mode = check_owner(dir)?.mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(V4Error::Runtime(format!(
+            "{} is open to other users (mode {:o}); it should be 0700",
+            dir.display(),
+            mode
+        )));
+    }
+    Ok(())
+}
+
+/// Metadata of `path`, which must belong to the current user
+fn check_owner(path: &Path) -> Result<fs::Metadata> {
+    let metadata = fs::symlink_metadata(path)?;
+    let uid = current_uid();
+    if metadata.uid() != uid {
+        return Err(V4Error::Runtime(format!(
+            "{} belongs to uid {}, not to the current user ({})",
+            path.display(),
+            metadata.uid(),
+            uid
+        )));
+    }
+    Ok(metadata)
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+/// Open port shared with other invocations through a Unix socket
+pub struct Daemon<S> {
+    device: S,
+    port: String,
+    listener: UnixListener,
+    socket: PathBuf,
+}
+
+impl Daemon<Box<dyn SerialPort>> {
+    /// Open a serial port and listen on its socket
+    ///
+    /// A `usb:` port is looked up once; the daemon then holds that path.
+    pub fn open(port: &str, settings: &SerialSettings) -> Result<Self> {
+        let port = &crate::usb::resolve(port)?;
+        let socket = socket_path(port);
+        create_private_dir(&socket_dir())?;
+        // Bind first so a second daemon fails before touching the port
+        let listener = listen(&socket, port)?;
+        let device = match V4Serial::open(port, settings) {
+            Ok(serial) => serial.into_inner(),
+            Err(e) => {
+                let _ = std::fs::remove_file(&socket);
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            device,
+            port: port.to_string(),
+            listener,
+            socket,
+        })
+    }
+}
+
+impl<S: ByteStream + Send> Daemon<S> {
+    /// Share an already open byte stream on `socket`
+    pub fn bind(device: S, port: &str, socket: &Path) -> Result<Self> {
+        Ok(Self {
+            listener: listen(socket, port)?,
+            device,
+            port: port.to_string(),
+            socket: socket.to_path_buf(),
+        })
+    }
+
+    /// Port the daemon holds open
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    /// Socket clients connect to
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Serve clients one after another until `stop` is set
+    ///
+    /// `on_client` is told when a client connects (`true`) and leaves.
+    /// Fails if the device goes away.
+    pub fn serve(&mut self, stop: &AtomicBool, on_client: &mut dyn FnMut(bool)) -> Result<()> {
+        self.listener.set_nonblocking(true)?;
+        while !stop.load(Ordering::SeqCst) {
+            match self.listener.accept() {
+                Ok((client, _)) => {
+                    client.set_nonblocking(false)?;
+                    on_client(true);
+                    let result = self.relay(client, stop);
+                    on_client(false);
+                    result?;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass bytes between one client and the device until the client leaves
+    ///
+    /// Each direction has its own thread blocked on its read, so neither
+    /// waits for the other.
+    fn relay(&mut self, client: UnixStream, stop: &AtomicBool) -> Result<()> {
+        // Output that arrived while nobody listened would confuse the client
+        self.device.clear_input()?;
+        let mut device_in = self.device.try_clone_stream()?;
+        device_in.set_read_timeout(RELAY_TIMEOUT)?;
+        let mut client_out = client.try_clone()?;
+        let mut client_in = client;
+        client_in.set_read_timeout(Some(RELAY_TIMEOUT))?;
+        let done = AtomicBool::new(false);
+        let (device, port, done) = (&mut self.device, &self.port, &done);
+
+        std::thread::scope(|scope| {
+            let to_client = scope.spawn(move || {
+                let result = relay_to_client(&mut device_in, &mut client_out, port, done, stop);
+                // Unblocks the other direction if the device went away
+                let _ = client_out.shutdown(Shutdown::Both);
+                result
+            });
+            let result = relay_to_device(&mut client_in, device, done, stop);
+            done.store(true, Ordering::SeqCst);
+            let to_client = to_client
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            result.and(to_client)
+        })
+    }
+}
+
+/// Copy client requests to the device until the client leaves
+fn relay_to_device(
+    client: &mut UnixStream,
+    device: &mut impl Write,
+    done: &AtomicBool,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    while !stop.load(Ordering::SeqCst) && !done.load(Ordering::SeqCst) {
+        match client.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                device.write_all(&buf[..n])?;
+                device.flush()?;
+            }
+            Err(e) if is_idle(e.kind()) => {}
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Copy device output to the client until either side is gone
+fn relay_to_client(
+    device: &mut impl Read,
+    client: &mut UnixStream,
+    port: &str,
+    done: &AtomicBool,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    while !stop.load(Ordering::SeqCst) && !done.load(Ordering::SeqCst) {
+        match device.read(&mut buf) {
+            Ok(0) => {
+                return Err(V4Error::Device(format!("{} closed the connection", port)));
+            }
+            Ok(n) => {
+                if client.write_all(&buf[..n]).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) if is_idle(e.kind()) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+impl<S> Drop for Daemon<S> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+/// Listen on `socket`, replacing a stale one but not a live daemon's
+fn listen(socket: &Path, port: &str) -> Result<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(V4Error::Runtime(format!(
+                "A daemon already serves {} on {}",
+                port,
+                socket.display()
+            )));
+        }
+        std::fs::remove_file(socket)?;
+    }
+    Ok(UnixListener::bind(socket)?)
+}
+
+/// Read errors that only mean nothing arrived in time
+fn is_idle(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use crate::transport::Transport;
+    use std::sync::Arc;
+
+    /// PING response with no payload
+    const OK_RESPONSE: [u8; 5] = [0xA5, 0x01, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn test_socket_path_named_after_port() {
+        let path = socket_path("/nonexistent/ttyACM0");
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(name, "v4-nonexistent_ttyACM0.sock");
+    }
+
+    #[test]
+    fn test_socket_dir_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let sockets = dir.path().join("v4");
+        create_private_dir(&sockets).unwrap();
+        let mode = fs::metadata(&sockets).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let socket = sockets.join("v4-ttyACM0.sock");
+        let _listener = UnixListener::bind(&socket).unwrap();
+        check_private(&socket).unwrap();
+
+        fs::set_permissions(&sockets, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(create_private_dir(&sockets).is_err());
+        let err = check_private(&socket).unwrap_err();
+        assert!(err.to_string().contains("open to other users"), "{}", err);
+    }
+
+    #[test]
+    fn test_relay_between_client_and_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("v4.sock");
+        let (device, mut fake) = UnixStream::pair().unwrap();
+        let mut daemon = Daemon::bind(device, "fake", &socket).unwrap();
+        assert!(Daemon::bind(UnixStream::pair().unwrap().0, "fake", &socket).is_err());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || daemon.serve(&stop, &mut |_| {}))
+        };
+        let firmware = std::thread::spawn(move || {
+            let mut request = [0u8; 5];
+            fake.read_exact(&mut request).unwrap();
+            let mut response = OK_RESPONSE;
+            response[4] = crate::protocol::calc_crc8(&response[1..4]);
+            fake.write_all(&response).unwrap();
+            request[0]
+        });
+
+        let mut client = StreamTransport::new(UnixStream::connect(&socket).unwrap());
+        assert_eq!(client.ping(Duration::from_secs(2)).unwrap(), ErrorCode::Ok);
+        assert_eq!(firmware.join().unwrap(), 0xA5);
+
+        drop(client);
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,420 @@
+//! Breakpoint and single-step debugging
+//!
+//! The device VM halts when it reaches a breakpoint and answers the running
+//! EXEC (or STEP/CONTINUE) with HALTED and the stop location. [`Debugger`]
+//! tracks whether the VM is running or halted, refuses commands that don't
+//! fit the current state, and decodes the instruction at the stop location.
+
+use crate::device;
+use crate::disasm::{self, Instruction};
+use crate::protocol::{ErrorCode, Response, VmFault};
+use crate::transport::{Deadline, ResultWait, Transport};
+use crate::{Result, V4Error};
+use std::fmt;
+use std::time::Duration;
+
+/// Word index the device reports when halted in top-level bytecode
+pub const TOP_LEVEL: u16 = 0xFFFF;
+
+/// Where the VM stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// Word index, [`TOP_LEVEL`] for code outside any word
+    pub word: u16,
+    /// Offset of the next instruction within the word's bytecode
+    pub ip: u16,
+}
+
+impl Location {
+    /// Parse a HALTED payload: [WORD_IDX u16 LE][IP u16 LE]
+    pub fn from_payload(data: &[u8]) -> Result<Self> {
+        match data {
+            [w0, w1, i0, i1, ..] => Ok(Self {
+                word: u16::from_le_bytes([*w0, *w1]),
+                ip: u16::from_le_bytes([*i0, *i1]),
+            }),
+            _ => Err(V4Error::Protocol(format!(
+                "Halt location too short: {} bytes (expected 4)",
+                data.len()
+            ))),
+        }
+    }
+
+    /// Where a VM_ERROR stopped the VM
+    pub fn of_fault(fault: &VmFault) -> Self {
+        Self {
+            word: fault.word,
+            ip: fault.ip,
+        }
+    }
+
+    pub fn is_top_level(&self) -> bool {
+        self.word == TOP_LEVEL
+    }
+
+    /// `in word NAME at offset 0x0012`, naming the word by index without `name`
+    pub fn describe(&self, name: Option<&str>) -> String {
+        let word = match (self.is_top_level(), name) {
+            (true, _) => "the main code".to_string(),
+            (false, Some(name)) => format!("word {}", name),
+            (false, None) => format!("word #{}", self.word),
+        };
+        format!("in {} at offset 0x{:04X}", word, self.ip)
+    }
+}
+
+/// Error for a response that isn't OK
+///
+/// A VM_ERROR with a [`VmFault`] payload tells what went wrong and where;
+/// that becomes a [`V4Error::VmFault`]. Anything else is a plain device
+/// error naming the code.
+pub fn exec_error(response: &Response, context: &str) -> V4Error {
+    let fault = match response.error_code {
+        ErrorCode::VmError => VmFault::from_payload(&response.data),
+        _ => None,
+    };
+    match fault {
+        Some(fault) => V4Error::VmFault {
+            context: context.to_string(),
+            place: Location::of_fault(&fault).describe(None),
+            fault,
+        },
+        None => V4Error::Device(format!("{}: {}", context, response.error_code.name())),
+    }
+}
+
+/// Breakpoint at a bytecode offset within a word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub word: u16,
+    pub offset: u16,
+}
+
+/// Result of running the VM until it stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The program ran to completion
+    Finished,
+    /// The VM halted and waits for STEP or CONTINUE
+    Halted(Location),
+}
+
+/// Halt location with the instruction about to execute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stop {
+    pub location: Location,
+    /// Name of the halted word, if the device knows it
+    pub word_name: Option<String>,
+    /// `None` if the bytecode at the location isn't available
+    pub next: Option<Instruction>,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let word = match (&self.word_name, self.location.is_top_level()) {
+            (_, true) => "<top level>".to_string(),
+            (Some(name), false) => format!("{} (#{})", name, self.location.word),
+            (None, false) => format!("#{}", self.location.word),
+        };
+        writeln!(f, "Halted in {} at IP {:04X}", word, self.location.ip)?;
+        match &self.next {
+            Some(insn) => writeln!(f, "  next: {:04X}  {}", insn.offset, insn.text()),
+            None => writeln!(f, "  next: <end of code>"),
+        }
+    }
+}
+
+/// Debugger state machine for one device connection
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    halted: Option<Location>,
+    /// Last top-level bytecode run, for decoding top-level stops
+    top_level: Vec<u8>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Breakpoints set in this session
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Current halt location, `None` while the VM isn't halted
+    pub fn halted(&self) -> Option<Location> {
+        self.halted
+    }
+
+    /// Forget breakpoints and halt state, e.g. after a VM reset
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Set a breakpoint on the device
+    pub fn set_breakpoint(
+        &mut self,
+        transport: &mut dyn Transport,
+        breakpoint: Breakpoint,
+        timeout: Duration,
+    ) -> Result<()> {
+        let response = transport.set_breakpoint(breakpoint.word, breakpoint.offset, timeout)?;
+        if response.error_code != ErrorCode::Ok {
+            return Err(V4Error::Device(format!(
+                "Set breakpoint failed: {}",
+                response.error_code.name()
+            )));
+        }
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+        Ok(())
+    }
+
+    /// Run top-level bytecode, which may halt at a breakpoint
+    ///
+    /// A program the device ACCEPTED is waited for until it finishes, the
+    /// deadline passes or Ctrl+C is pressed.
+    pub fn exec(
+        &mut self,
+        transport: &mut dyn Transport,
+        bytecode: &[u8],
+        timeout: Duration,
+        deadline: &Deadline,
+    ) -> Result<Outcome> {
+        self.ensure_running()?;
+        self.top_level = bytecode.to_vec();
+        let phase = "running the program";
+        let response = deadline.check(
+            transport.exec(bytecode, deadline.timeout(timeout, phase)?),
+            phase,
+        )?;
+        let phase = "waiting for the program to finish";
+        let wait = deadline.wait(ResultWait::Forever, phase)?;
+        let response = deadline.check(device::await_result(transport, response, wait), phase)?;
+        self.finish(&response, "Execution failed")
+    }
+
+    /// Execute one instruction of the halted VM
+    pub fn step(&mut self, transport: &mut dyn Transport, timeout: Duration) -> Result<Outcome> {
+        self.ensure_halted()?;
+        let response = transport.step(timeout)?;
+        self.finish(&response, "Step failed")
+    }
+
+    /// Resume the halted VM until the next breakpoint or the end
+    pub fn resume(&mut self, transport: &mut dyn Transport, timeout: Duration) -> Result<Outcome> {
+        self.ensure_halted()?;
+        let response = transport.resume(timeout)?;
+        self.finish(&response, "Continue failed")
+    }
+
+    /// Describe a halt location, fetching the word's bytecode from the device
+    pub fn inspect(
+        &self,
+        transport: &mut dyn Transport,
+        location: Location,
+        timeout: Duration,
+    ) -> Result<Stop> {
+        let (word_name, code) = if location.is_top_level() {
+            (None, self.top_level.clone())
+        } else {
+            match device::query_word(transport, location.word, timeout)? {
+                Some(word) => (word.name, word.code),
+                None => (None, Vec::new()),
+            }
+        };
+
+        let next = disasm::decode(&code)
+            .into_iter()
+            .find(|insn| insn.offset == location.ip as usize);
+        Ok(Stop {
+            location,
+            word_name,
+            next,
+        })
+    }
+
+    /// Fail unless the VM may start new code
+    pub fn ensure_running(&self) -> Result<()> {
+        match self.halted {
+            Some(_) => Err(V4Error::Device(
+                "VM is halted at a breakpoint; step or continue first".to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn ensure_halted(&self) -> Result<()> {
+        match self.halted {
+            Some(_) => Ok(()),
+            None => Err(V4Error::Device("VM is not halted".to_string())),
+        }
+    }
+
+    /// Update the state from the response of a command that ran code
+    fn finish(&mut self, response: &Response, context: &str) -> Result<Outcome> {
+        self.halted = None;
+        match response.error_code {
+            ErrorCode::Ok => Ok(Outcome::Finished),
+            ErrorCode::Halted => {
+                let location = Location::from_payload(&response.data)?;
+                self.halted = Some(location);
+                Ok(Outcome::Halted(location))
+            }
+            _ => Err(exec_error(response, context)),
+        }
+    }
+}
+
+/// Find a word index by name, querying indices from 0 until the device refuses
+pub fn find_word(
+    transport: &mut dyn Transport,
+    name: &str,
+    timeout: Duration,
+) -> Result<Option<u16>> {
+    for idx in 0..=u16::MAX {
+        let Some(word) = device::query_word(transport, idx, timeout)? else {
+            break;
+        };
+        if word
+            .name
+            .is_some_and(|word_name| word_name.eq_ignore_ascii_case(name))
+        {
+            return Ok(Some(idx));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use crate::transport::mock::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn halted_at(word: u16, ip: u16) -> Vec<u8> {
+        let mut payload = word.to_le_bytes().to_vec();
+        payload.extend_from_slice(&ip.to_le_bytes());
+        payload
+    }
+
+    #[test]
+    fn test_break_step_continue() {
+        let mut transport = MockTransport::new();
+        let mut debugger = Debugger::new();
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        let breakpoint = Breakpoint { word: 3, offset: 1 };
+        debugger
+            .set_breakpoint(&mut transport, breakpoint, TIMEOUT)
+            .unwrap();
+        assert_eq!(transport.sent[0].payload, vec![3, 0, 1, 0]);
+
+        transport.push_response(ErrorCode::Halted, &halted_at(3, 1));
+        let outcome = debugger
+            .exec(
+                &mut transport,
+                &[0x50, 3, 0],
+                TIMEOUT,
+                &Deadline::start(None),
+            )
+            .unwrap();
+        assert_eq!(outcome, Outcome::Halted(Location { word: 3, ip: 1 }));
+
+        // New code is refused while halted
+        assert!(
+            debugger
+                .exec(&mut transport, &[0x01], TIMEOUT, &Deadline::start(None))
+                .is_err()
+        );
+
+        transport.push_response(ErrorCode::Halted, &halted_at(3, 2));
+        debugger.step(&mut transport, TIMEOUT).unwrap();
+        assert_eq!(debugger.halted(), Some(Location { word: 3, ip: 2 }));
+
+        transport.push_response(ErrorCode::Ok, &[]);
+        assert_eq!(
+            debugger.resume(&mut transport, TIMEOUT).unwrap(),
+            Outcome::Finished
+        );
+        assert_eq!(debugger.halted(), None);
+        assert_eq!(
+            transport.sent_commands(),
+            vec![
+                Command::SetBreakpoint,
+                Command::Exec,
+                Command::Step,
+                Command::Continue
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step_requires_halt() {
+        let mut transport = MockTransport::new();
+        let mut debugger = Debugger::new();
+
+        assert!(debugger.step(&mut transport, TIMEOUT).is_err());
+        assert!(debugger.resume(&mut transport, TIMEOUT).is_err());
+        assert!(transport.sent.is_empty());
+    }
+
+    #[test]
+    fn test_inspect_decodes_next_instruction() {
+        let mut transport = MockTransport::new();
+        let debugger = Debugger::new();
+        // SQ: DUP MUL RET
+        transport.push_response(ErrorCode::Ok, &[2, b'S', b'Q', 3, 0, 0x01, 0x12, 0x51]);
+
+        let stop = debugger
+            .inspect(&mut transport, Location { word: 4, ip: 1 }, TIMEOUT)
+            .unwrap();
+        assert_eq!(stop.word_name.as_deref(), Some("SQ"));
+        assert_eq!(stop.next.unwrap().text(), "MUL");
+        assert_eq!(transport.sent[0].payload, vec![4, 0]);
+    }
+
+    #[test]
+    fn test_find_word() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[2, b'O', b'N', 1, 0, 0x51]);
+        transport.push_response(ErrorCode::Ok, &[2, b'S', b'Q', 1, 0, 0x51]);
+        assert_eq!(find_word(&mut transport, "sq", TIMEOUT).unwrap(), Some(1));
+
+        transport.push_response(ErrorCode::Error, &[]);
+        assert_eq!(find_word(&mut transport, "NOPE", TIMEOUT).unwrap(), None);
+    }
+
+    #[test]
+    fn test_location_from_payload() {
+        let location = Location::from_payload(&halted_at(TOP_LEVEL, 7)).unwrap();
+        assert!(location.is_top_level());
+        assert_eq!(location.ip, 7);
+        assert!(Location::from_payload(&[1, 0, 2]).is_err());
+    }
+
+    #[test]
+    fn test_exec_error_describes_fault() {
+        let mut response = Response {
+            error_code: ErrorCode::VmError,
+            word_indices: Vec::new(),
+            data: vec![0x03, 0x00, 0x12, 0x00, 0x01, 0x00],
+            seq: None,
+        };
+        assert_eq!(
+            exec_error(&response, "Execution failed").to_string(),
+            "Device error: Execution failed: stack underflow in word #3 at offset 0x0012, stack depth 0"
+        );
+
+        response.data.clear();
+        assert_eq!(
+            exec_error(&response, "Execution failed").to_string(),
+            "Device error: Execution failed: VM_ERROR"
+        );
+    }
+}
@@ -0,0 +1,1116 @@
+//! High-level device API
+//!
+//! [`V4Device`] pairs a transport with the compiler context that tracks the
+//! device's word indices. Operations return report structs and never print,
+//! so the crate can be embedded in other tools:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use v4_cli::V4Device;
+//!
+//! let mut device = V4Device::connect("/dev/ttyACM0")?;
+//! device.ping(Duration::from_secs(1))?;
+//!
+//! let report = device.exec_source(": SQ DUP * ;\n5 SQ", Duration::from_secs(5))?;
+//! for word in &report.words {
+//!     println!("{} -> index {}", word.name, word.index);
+//! }
+//! # Ok::<(), v4_cli::V4Error>(())
+//! ```
+
+use crate::bytecode;
+use crate::debugger;
+use crate::ffi::{CompileResult, Compiler};
+use crate::interrupt;
+use crate::protocol::{
+    Command, ErrorCode, FEATURE_COBS, FEATURE_CRC16, FEATURE_CRC32, FEATURE_LZ4, FEATURE_SEQUENCE,
+    FEATURE_TICKS, Frame, Handshake, MemoryDump, PROTOCOL_VERSION, Response, StackSnapshot, Ticks,
+    WordInfo, compress,
+};
+use crate::serial::SerialSettings;
+use crate::source::Loaded;
+use crate::sourcemap::{self, SourceMap};
+use crate::transport::{self, Deadline, ResultWait, RetryPolicy, Retrying, Transport};
+use crate::{Result, V4Error};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Response timeout for the HELLO handshake
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Word definition registered on the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredWord {
+    pub name: String,
+    /// Device-assigned word index
+    pub index: u16,
+    /// Bytecode size in bytes
+    pub size: usize,
+    /// Time the definition took
+    pub timing: Timing,
+}
+
+/// Result of running Forth source on the device
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecReport {
+    /// Word definitions in source order
+    pub words: Vec<RegisteredWord>,
+    /// Size of the main (top-level) bytecode, 0 if there was none
+    pub main_size: usize,
+    /// Time the main bytecode took, if there was any
+    pub main_timing: Option<Timing>,
+    /// Time for the whole run, compiling excluded
+    pub elapsed: Duration,
+}
+
+/// How long a device command took
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// From sending the command to its result, link included
+    pub host: Duration,
+    /// VM time from the cycle counter, if the device has one
+    pub device: Option<Duration>,
+}
+
+impl fmt::Display for Timing {
+    /// `3.2 ms`, or `3.2 ms, VM 2.9 ms` with a device time
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", millis(self.host))?;
+        if let Some(device) = self.device {
+            write!(f, ", VM {}", millis(device))?;
+        }
+        Ok(())
+    }
+}
+
+/// Duration in milliseconds with one decimal, e.g. `3.2 ms`
+pub fn millis(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Times device commands, reading the VM cycle counter when there is one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stopwatch {
+    /// Whether to read QUERY_TICKS around each command
+    ticks: bool,
+}
+
+impl Stopwatch {
+    /// Time with the host clock only
+    pub fn host() -> Self {
+        Self::default()
+    }
+
+    /// Also read the cycle counter if QUERY_INFO lists one
+    ///
+    /// Falls back to the host clock when the device doesn't answer.
+    pub fn probe(transport: &mut dyn Transport, timeout: Duration) -> Self {
+        let ticks = match DeviceInfo::query(transport, timeout) {
+            Ok(info) => info.features & FEATURE_TICKS != 0,
+            Err(e) => {
+                log::debug!("No device timing: {}", e);
+                false
+            }
+        };
+        Self { ticks }
+    }
+
+    /// Whether timings include the VM time
+    pub fn reads_ticks(&self) -> bool {
+        self.ticks
+    }
+
+    /// Run `op` and measure how long it took
+    pub fn time<T>(
+        &self,
+        transport: &mut dyn Transport,
+        timeout: Duration,
+        op: impl FnOnce(&mut dyn Transport) -> Result<T>,
+    ) -> Result<(T, Timing)> {
+        let before = match self.ticks {
+            true => Some(query_ticks(transport, timeout)?),
+            false => None,
+        };
+        let start = Instant::now();
+        let value = op(&mut *transport)?;
+        let host = start.elapsed();
+        let device = match before {
+            Some(before) => Some(query_ticks(transport, timeout)?.since(&before)),
+            None => None,
+        };
+        Ok((value, Timing { host, device }))
+    }
+}
+
+/// Result of deploying a .v4b file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushReport {
+    /// Bytes sent, including the header
+    pub size: usize,
+    /// Word indices registered by the device, in definition order
+    pub word_indices: Vec<u16>,
+    /// Set when the file went out LZ4-compressed
+    pub compression: Option<CompressionStats>,
+}
+
+/// How much an LZ4-compressed push saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// File size
+    pub size: usize,
+    /// Bytes actually sent
+    pub packed: usize,
+    /// Time the compressed transfer took
+    pub elapsed: Duration,
+}
+
+impl CompressionStats {
+    /// Compressed size as a fraction of the file size
+    pub fn ratio(&self) -> f64 {
+        self.packed as f64 / self.size as f64
+    }
+
+    /// Estimated time the uncompressed transfer would have taken longer,
+    /// assuming transfer time grows with the bytes sent
+    pub fn time_saved(&self) -> Duration {
+        let unsent = self.size.saturating_sub(self.packed) as f64;
+        self.elapsed.mul_f64(unsent / self.packed.max(1) as f64)
+    }
+}
+
+/// Result of a VM reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetReport {
+    /// Time until the device answered PING again
+    pub ready_after: Duration,
+}
+
+/// Firmware and VM capabilities reported by QUERY_INFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub firmware_version: (u8, u8, u8),
+    pub vm_version: (u8, u8, u8),
+    /// Maximum number of word definitions
+    pub dictionary_capacity: u16,
+    /// Data stack size in cells
+    pub data_stack_size: u16,
+    /// Return stack size in cells
+    pub return_stack_size: u16,
+    /// Free VM memory in bytes
+    pub free_memory: u32,
+    /// Supported protocol feature bits (see [`crate::protocol::Handshake`])
+    pub features: u8,
+}
+
+/// Protocol feature bits and their names
+const FEATURE_NAMES: &[(u8, &str)] = &[
+    (FEATURE_SEQUENCE, "sequence numbers"),
+    (FEATURE_TICKS, "cycle counter"),
+    (FEATURE_CRC16, "CRC-16"),
+    (FEATURE_CRC32, "CRC-32"),
+    (FEATURE_COBS, "COBS framing"),
+];
+
+impl DeviceInfo {
+    /// Parse a QUERY_INFO payload
+    ///
+    /// Layout: [FW_MAJ][FW_MIN][FW_PATCH][VM_MAJ][VM_MIN][VM_PATCH]
+    /// [DICT_CAP u16][DS_SIZE u16][RS_SIZE u16][FREE_MEM u32][FEATURES],
+    /// little-endian. Trailing bytes from newer firmware are ignored.
+    pub fn from_payload(data: &[u8]) -> Result<Self> {
+        if data.len() < 17 {
+            return Err(V4Error::Protocol(format!(
+                "Info response too short: {} bytes (expected 17)",
+                data.len()
+            )));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        Ok(Self {
+            firmware_version: (data[0], data[1], data[2]),
+            vm_version: (data[3], data[4], data[5]),
+            dictionary_capacity: u16_at(6),
+            data_stack_size: u16_at(8),
+            return_stack_size: u16_at(10),
+            free_memory: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
+            features: data[16],
+        })
+    }
+
+    /// Send QUERY_INFO and parse the response
+    pub fn query(transport: &mut dyn Transport, timeout: Duration) -> Result<Self> {
+        let response = transport.query_info(timeout)?;
+        check(response.error_code, "Query info failed")?;
+        Self::from_payload(&response.data)
+    }
+
+    /// Names of the supported protocol features
+    pub fn feature_names(&self) -> Vec<&'static str> {
+        FEATURE_NAMES
+            .iter()
+            .filter(|(bit, _)| self.features & bit != 0)
+            .map(|&(_, name)| name)
+            .collect()
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let version =
+            |(major, minor, patch): (u8, u8, u8)| format!("{}.{}.{}", major, minor, patch);
+        let features = self.feature_names();
+
+        writeln!(f, "Firmware:      v{}", version(self.firmware_version))?;
+        writeln!(f, "VM:            v{}", version(self.vm_version))?;
+        writeln!(f, "Dictionary:    {} words", self.dictionary_capacity)?;
+        writeln!(f, "Data stack:    {} cells", self.data_stack_size)?;
+        writeln!(f, "Return stack:  {} cells", self.return_stack_size)?;
+        writeln!(f, "Free memory:   {} bytes", self.free_memory)?;
+        if features.is_empty() {
+            writeln!(f, "Features:      none")
+        } else {
+            writeln!(f, "Features:      {}", features.join(", "))
+        }
+    }
+}
+
+/// Send QUERY_WORD and parse the answer, `None` if the index is unused
+pub fn query_word(
+    transport: &mut dyn Transport,
+    idx: u16,
+    timeout: Duration,
+) -> Result<Option<WordInfo>> {
+    let response = transport.query_word(idx, timeout)?;
+    if response.error_code != ErrorCode::Ok {
+        return Ok(None);
+    }
+    WordInfo::from_payload(&response.data).map(Some)
+}
+
+/// Query words from index 0 until the device reports an unused index
+pub fn read_words(transport: &mut dyn Transport, timeout: Duration) -> Result<Vec<WordInfo>> {
+    let mut words = Vec::new();
+    for idx in 0..=u16::MAX {
+        let Some(word) = query_word(transport, idx, timeout)? else {
+            break;
+        };
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Send QUERY_TICKS and parse the answer
+pub fn query_ticks(transport: &mut dyn Transport, timeout: Duration) -> Result<Ticks> {
+    let response = transport.query_ticks(timeout)?;
+    check(response.error_code, "Query ticks failed")?;
+    Ticks::from_payload(&response.data)
+}
+
+/// Send QUERY_MEMORY and parse the answer
+pub fn query_memory(
+    transport: &mut dyn Transport,
+    addr: u32,
+    len: u16,
+    timeout: Duration,
+) -> Result<MemoryDump> {
+    let response = transport.query_memory(addr, len, timeout)?;
+    check(response.error_code, "Query memory failed")?;
+    MemoryDump::from_payload(addr, len, &response.data)
+}
+
+/// Most bytes one QUERY_MEMORY asks for
+pub const MEMORY_CHUNK_SIZE: u16 = 256;
+
+/// Read `len` bytes at `addr`, [`MEMORY_CHUNK_SIZE`] bytes per query
+///
+/// Stops early when the device returns fewer bytes than asked, as it does
+/// at the end of its memory. `on_progress` receives the bytes read so far.
+pub fn read_memory(
+    transport: &mut dyn Transport,
+    addr: u32,
+    len: u32,
+    timeout: Duration,
+    on_progress: &mut dyn FnMut(usize),
+) -> Result<MemoryDump> {
+    let mut dump = MemoryDump {
+        addr,
+        bytes: Vec::with_capacity(len as usize),
+    };
+    while dump.bytes.len() < len as usize {
+        let remaining = len as usize - dump.bytes.len();
+        let chunk = remaining.min(MEMORY_CHUNK_SIZE as usize) as u16;
+        let part = query_memory(transport, dump.end(), chunk, timeout)?;
+        dump.bytes.extend_from_slice(&part.bytes);
+        on_progress(dump.bytes.len());
+        if part.bytes.len() < chunk as usize {
+            break;
+        }
+    }
+    Ok(dump)
+}
+
+/// Connected V4 device
+pub struct V4Device {
+    transport: Box<dyn Transport>,
+    port: String,
+    /// Created on first use; compiling needs the V4-front library
+    compiler: Option<Compiler>,
+    /// Wait for ACCEPTED commands; the command timeout when `None`
+    result_wait: Option<ResultWait>,
+    /// Overall limit for one `exec_source` call
+    deadline: Option<Duration>,
+    stopwatch: Stopwatch,
+    /// Compress pushes too large for one frame if the device accepts it
+    compress: bool,
+    /// Whether the device takes LZ4 transfers, `None` until asked
+    lz4: Option<bool>,
+}
+
+impl V4Device {
+    /// Connect to a serial port path, `tcp://`, `rfc2217://` or `ws://` URL with default settings
+    pub fn connect(port: &str) -> Result<Self> {
+        Self::open(Some(port), &SerialSettings::default())
+    }
+
+    /// Connect with explicit serial settings, auto-detecting the port if `None`
+    pub fn open(port: Option<&str>, settings: &SerialSettings) -> Result<Self> {
+        let (transport, port) = transport::open(port, settings)?;
+        Ok(Self::from_transport(transport, port))
+    }
+
+    /// Wrap an already open transport
+    pub fn from_transport(transport: Box<dyn Transport>, port: impl Into<String>) -> Self {
+        Self {
+            transport,
+            port: port.into(),
+            compiler: None,
+            result_wait: None,
+            deadline: None,
+            stopwatch: Stopwatch::host(),
+            compress: false,
+            lz4: None,
+        }
+    }
+
+    /// Resend lost or corrupted frames according to `policy`
+    ///
+    /// Performs the HELLO handshake to enable sequence numbers, COBS
+    /// framing and the strongest checksum both sides support. Without sequence numbers
+    /// (older firmware) EXEC is never resent after a lost response, see
+    /// [`Retrying`].
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Result<Self> {
+        if policy.retries == 0 {
+            return Ok(self);
+        }
+
+        let lz4 = if self.compress { FEATURE_LZ4 } else { 0 };
+        let mut retrying = Retrying::new(self.transport, policy).requesting(lz4);
+        let handshake = retrying.negotiate(HANDSHAKE_TIMEOUT)?;
+        self.lz4 = Some(handshake.is_some_and(|h| h.supports_lz4()));
+        match handshake {
+            Some(handshake) => log::debug!(
+                "Protocol v{}, sequence numbers {}, framing {}",
+                handshake.version,
+                if handshake.supports_sequence() {
+                    "enabled"
+                } else {
+                    "disabled"
+                },
+                handshake.framing()
+            ),
+            None => log::debug!("Device doesn't support HELLO"),
+        }
+
+        self.transport = Box::new(retrying);
+        Ok(self)
+    }
+
+    /// LZ4-compress pushes too large for one frame, if the device accepts it
+    ///
+    /// Whether it does is asked with HELLO on the first such push, or in the
+    /// handshake of [`with_retry`](Self::with_retry), which must come after.
+    /// Files that don't get smaller are sent as they are.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// How long to wait for programs the device ACCEPTED to finish
+    ///
+    /// By default the result must arrive within the timeout passed to the
+    /// operation, like any other response. Ctrl+C stops the wait either way.
+    pub fn with_result_wait(mut self, wait: ResultWait) -> Self {
+        self.result_wait = Some(wait);
+        self
+    }
+
+    /// Limit each [`exec_source`](Self::exec_source) call to `limit` overall
+    ///
+    /// The per-answer timeout still applies; the deadline also covers
+    /// waiting for ACCEPTED results. The REPL applies it to each line.
+    pub fn with_deadline(mut self, limit: Duration) -> Self {
+        self.deadline = Some(limit);
+        self
+    }
+
+    /// Time `exec_source` with the VM cycle counter as well, if there is one
+    ///
+    /// Sends QUERY_INFO to find out; timings otherwise come from the host
+    /// clock and include the link.
+    pub fn with_timing(mut self) -> Self {
+        self.stopwatch = Stopwatch::probe(self.transport.as_mut(), HANDSHAKE_TIMEOUT);
+        self
+    }
+
+    /// How `exec_source` times commands
+    pub fn stopwatch(&self) -> Stopwatch {
+        self.stopwatch
+    }
+
+    /// Overall limit set with [`with_deadline`](Self::with_deadline)
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    /// Port name or URL this device is connected through
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    /// Underlying transport, for raw protocol commands
+    pub fn transport(&mut self) -> &mut dyn Transport {
+        self.transport.as_mut()
+    }
+
+    /// Compiler context tracking this device's words
+    pub fn compiler(&mut self) -> Result<&mut Compiler> {
+        Ok(self.parts()?.1)
+    }
+
+    /// Transport and compiler together, e.g. for the REPL loop
+    pub fn parts(&mut self) -> Result<(&mut dyn Transport, &mut Compiler)> {
+        if self.compiler.is_none() {
+            self.compiler = Some(Compiler::new().map_err(V4Error::Compilation)?);
+        }
+        let compiler = self.compiler.as_mut().expect("compiler initialized above");
+        Ok((self.transport.as_mut(), compiler))
+    }
+
+    /// Check that the device answers PING with OK
+    pub fn ping(&mut self, timeout: Duration) -> Result<()> {
+        let err_code = self.transport.ping(timeout)?;
+        check(err_code, "Device returned error")
+    }
+
+    /// Query firmware version and VM capabilities
+    pub fn info(&mut self, timeout: Duration) -> Result<DeviceInfo> {
+        DeviceInfo::query(self.transport.as_mut(), timeout)
+    }
+
+    /// Read the data and return stacks
+    pub fn stack(&mut self, timeout: Duration) -> Result<StackSnapshot> {
+        self.transport.stack_snapshot(timeout)
+    }
+
+    /// Read `len` bytes of VM memory at `addr`
+    pub fn memory(&mut self, addr: u32, len: u16, timeout: Duration) -> Result<MemoryDump> {
+        query_memory(self.transport.as_mut(), addr, len, timeout)
+    }
+
+    /// Read `len` bytes of VM memory at `addr` in as many queries as needed
+    ///
+    /// See [`read_memory`].
+    pub fn read_memory(
+        &mut self,
+        addr: u32,
+        len: u32,
+        timeout: Duration,
+        on_progress: &mut dyn FnMut(usize),
+    ) -> Result<MemoryDump> {
+        read_memory(self.transport.as_mut(), addr, len, timeout, on_progress)
+    }
+
+    /// Look up a word definition by index, `None` if the index is unused
+    pub fn word(&mut self, idx: u16, timeout: Duration) -> Result<Option<WordInfo>> {
+        query_word(self.transport.as_mut(), idx, timeout)
+    }
+
+    /// Read every word definition, in index order
+    pub fn words(&mut self, timeout: Duration) -> Result<Vec<WordInfo>> {
+        read_words(self.transport.as_mut(), timeout)
+    }
+
+    /// Reset the VM and wait until it answers PING again
+    ///
+    /// The compiler context is cleared too, since the device forgets all
+    /// word definitions.
+    pub fn reset(&mut self, timeout: Duration, ready_timeout: Duration) -> Result<ResetReport> {
+        let err_code = self.transport.reset(timeout)?;
+        check(err_code, "Reset failed")?;
+
+        if let Some(compiler) = &mut self.compiler {
+            compiler.reset();
+        }
+
+        let ready_after = self.transport.wait_ready(ready_timeout)?;
+        Ok(ResetReport { ready_after })
+    }
+
+    /// Deploy a .v4b file (header included)
+    ///
+    /// `on_progress` receives the number of bytes acknowledged so far.
+    /// Ctrl+C during the transfer or while the program runs sends ABORT and
+    /// fails with [`V4Error::Aborted`].
+    pub fn push(
+        &mut self,
+        file_data: &[u8],
+        timeout: Duration,
+        on_progress: &mut dyn FnMut(usize),
+    ) -> Result<PushReport> {
+        let header = bytecode::validate(file_data)?;
+        let block = self.compressed(file_data, timeout)?;
+        let wait = self.result_wait.unwrap_or(ResultWait::Within(timeout));
+        let transport = self.transport.as_mut();
+
+        // Send entire .v4b file (including header)
+        // V4-link v0.2+ parses the header to extract word definitions
+        let catch = interrupt::catch();
+        let started = Instant::now();
+        let sent = match &block {
+            Some(block) => {
+                transport.exec_compressed(block, file_data.len(), timeout, &mut |sent| {
+                    on_progress(sent * file_data.len() / block.len())
+                })
+            }
+            None => transport.exec_with_progress(file_data, timeout, on_progress),
+        };
+        let elapsed = started.elapsed();
+        let response = sent.and_then(|response| await_result(transport, response, wait));
+        drop(catch);
+        let response = abort_on_interrupt(transport, response, timeout)?;
+        if response.error_code != ErrorCode::Ok {
+            return Err(debugger::exec_error(&response, "Device returned error"));
+        }
+
+        // The device registers definitions in file order, one index per word,
+        // the same pairing the REPL and `v4 exec` rely on
+        if let Some(word_count) = header.word_count
+            && response.word_indices.len() != word_count as usize
+        {
+            return Err(V4Error::Protocol(format!(
+                "Device returned {} word index(es) for {} definition(s)",
+                response.word_indices.len(),
+                word_count
+            )));
+        }
+
+        Ok(PushReport {
+            size: file_data.len(),
+            word_indices: response.word_indices,
+            compression: block.map(|block| CompressionStats {
+                size: file_data.len(),
+                packed: block.len(),
+                elapsed,
+            }),
+        })
+    }
+
+    /// `file_data` as an LZ4 block, if it needs chunking, gets smaller and
+    /// the device takes it
+    fn compressed(&mut self, file_data: &[u8], timeout: Duration) -> Result<Option<Vec<u8>>> {
+        if !self.compress
+            || file_data.len() <= Frame::MAX_PAYLOAD_SIZE
+            || !self.accepts_lz4(timeout)?
+        {
+            return Ok(None);
+        }
+        let block = compress::compress(file_data);
+        if block.len() >= file_data.len() {
+            log::debug!(
+                "Bytecode doesn't compress ({} -> {} bytes), sending it as is",
+                file_data.len(),
+                block.len()
+            );
+            return Ok(None);
+        }
+        Ok(Some(block))
+    }
+
+    /// Whether the device takes LZ4 transfers, asking once with HELLO
+    ///
+    /// The HELLO requests nothing else, so sequence numbers and framing stay
+    /// as they are.
+    fn accepts_lz4(&mut self, timeout: Duration) -> Result<bool> {
+        if let Some(lz4) = self.lz4 {
+            return Ok(lz4);
+        }
+        let payload = [PROTOCOL_VERSION, FEATURE_LZ4];
+        let lz4 = match self.transport.send_command(
+            Command::Hello,
+            &payload,
+            timeout.min(HANDSHAKE_TIMEOUT),
+        ) {
+            Ok(response) => {
+                response.error_code == ErrorCode::Ok
+                    && Handshake::from_payload(&response.data).is_some_and(|h| h.supports_lz4())
+            }
+            Err(V4Error::Timeout) => false,
+            Err(e) => return Err(e),
+        };
+        self.lz4 = Some(lz4);
+        Ok(lz4)
+    }
+
+    /// Compile Forth source and run it on the device
+    ///
+    /// Word definitions are sent one per EXEC and registered in the compiler
+    /// context, then the main bytecode is executed. Ctrl+C sends ABORT and
+    /// fails with [`V4Error::Aborted`].
+    ///
+    /// `timeout` limits each device answer; the whole run is limited by the
+    /// deadline set with [`with_deadline`](Self::with_deadline), if any.
+    /// Timeouts name the word or phase they hit.
+    pub fn exec_source(&mut self, source: &str, timeout: Duration) -> Result<ExecReport> {
+        let wait = self.result_wait.unwrap_or(ResultWait::Within(timeout));
+        let (limit, stopwatch) = (self.deadline, self.stopwatch);
+        let (transport, compiler) = self.parts()?;
+        let compiled = compiler.compile(source).map_err(V4Error::Compilation)?;
+
+        let catch = interrupt::catch();
+        let deadline = Deadline::start(limit);
+        let report = run_compiled(
+            transport, compiler, &compiled, timeout, wait, &deadline, stopwatch,
+        );
+        drop(catch);
+        abort_on_interrupt(transport, report, timeout)
+    }
+
+    /// Like [`exec_source`](Self::exec_source) for a loaded file
+    ///
+    /// A VM fault the device locates is translated to the file and line it
+    /// came from, e.g. `at main.v4:17 in word FOO`.
+    pub fn exec_loaded(&mut self, loaded: &Loaded, timeout: Duration) -> Result<ExecReport> {
+        match self.exec_source(&loaded.text, timeout) {
+            Err(fault @ V4Error::VmFault { .. }) => {
+                let (transport, compiler) = self.parts()?;
+                let map = SourceMap::build(compiler, loaded).unwrap_or_default();
+                Err(sourcemap::locate_fault(fault, transport, &map, timeout))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Send compiled definitions and run the main bytecode
+fn run_compiled(
+    transport: &mut dyn Transport,
+    compiler: &mut Compiler,
+    compiled: &CompileResult,
+    timeout: Duration,
+    wait: ResultWait,
+    deadline: &Deadline,
+    stopwatch: Stopwatch,
+) -> Result<ExecReport> {
+    let start = Instant::now();
+    let mut report = ExecReport::default();
+
+    // Send word definitions first
+    let count = compiled.words.len();
+    for (i, word) in compiled.words.iter().enumerate() {
+        let phase = format!("defining word '{}' ({} of {})", word.name, i + 1, count);
+        let (response, timing) = stopwatch.time(transport, timeout, |transport| {
+            let frame_timeout = deadline.timeout(timeout, &phase)?;
+            let response = deadline.check(
+                transport.define_word(&word.name, &word.bytecode, frame_timeout),
+                &phase,
+            )?;
+            let wait = deadline.wait(wait, &phase)?;
+            deadline.check(await_result(transport, response, wait), &phase)
+        })?;
+        check_exec(&response, "Device returned error")?;
+
+        // Register word in compiler context
+        compiler
+            .register_word_indices(&[word.name.as_str()], &response.word_indices)
+            .map_err(V4Error::Protocol)?;
+        report.words.push(RegisteredWord {
+            name: word.name.clone(),
+            index: response.word_indices[0],
+            size: word.bytecode.len(),
+            timing,
+        });
+    }
+
+    // Execute main bytecode if present
+    if !compiled.bytecode.is_empty() {
+        let (response, timing) = stopwatch.time(transport, timeout, |transport| {
+            let phase = "running the program";
+            let frame_timeout = deadline.timeout(timeout, phase)?;
+            let response =
+                deadline.check(transport.exec(&compiled.bytecode, frame_timeout), phase)?;
+            let phase = "waiting for the program to finish";
+            let wait = deadline.wait(wait, phase)?;
+            deadline.check(await_result(transport, response, wait), phase)
+        })?;
+        check_exec(&response, "Execution failed")?;
+        report.main_size = compiled.bytecode.len();
+        report.main_timing = Some(timing);
+    }
+
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+/// Wait for the result if the device ACCEPTED a command, else return `response`
+///
+/// Ctrl+C stops the wait with [`V4Error::Interrupted`] while caught (see
+/// [`interrupt::catch`]).
+pub fn await_result(
+    transport: &mut dyn Transport,
+    response: Response,
+    wait: ResultWait,
+) -> Result<Response> {
+    if response.error_code != ErrorCode::Accepted {
+        return Ok(response);
+    }
+    log::debug!("Command accepted, waiting for the result");
+    transport.recv_result(&response, wait, interrupt::flag())
+}
+
+/// Send ABORT if Ctrl+C interrupted an operation; other results pass through
+///
+/// Returns [`V4Error::Aborted`] once the device stopped the program, or
+/// the interruption itself if ABORT failed.
+pub fn abort_on_interrupt<T>(
+    transport: &mut dyn Transport,
+    result: Result<T>,
+    timeout: Duration,
+) -> Result<T> {
+    if !matches!(result, Err(V4Error::Interrupted)) {
+        return result;
+    }
+    log::info!("Interrupted, sending ABORT");
+    match transport.abort(timeout) {
+        Ok(ErrorCode::Ok) => Err(V4Error::Aborted),
+        Ok(code) => {
+            log::warn!("Device answered ABORT with {}", code.name());
+            result
+        }
+        Err(e) => {
+            log::warn!("ABORT failed: {}", e);
+            result
+        }
+    }
+}
+
+/// Turn a non-OK error code into a device error
+fn check(err_code: ErrorCode, context: &str) -> Result<()> {
+    if err_code == ErrorCode::Ok {
+        Ok(())
+    } else {
+        Err(V4Error::Device(format!("{}: {}", context, err_code.name())))
+    }
+}
+
+/// Turn a failed EXEC or definition into a VM fault or protocol error
+pub(crate) fn check_exec(response: &Response, context: &str) -> Result<()> {
+    match response.error_code {
+        ErrorCode::Ok => Ok(()),
+        _ => match debugger::exec_error(response, context) {
+            fault @ V4Error::VmFault { .. } => Err(fault),
+            _ => Err(V4Error::Protocol(format!(
+                "{}: {}",
+                context,
+                response.error_code.name()
+            ))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use crate::transport::mock::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    fn header(major: u8, minor: u8, word_count: u32) -> Vec<u8> {
+        let mut data = b"V4BC".to_vec();
+        data.extend_from_slice(&[major, minor, 0, 0]);
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&word_count.to_le_bytes());
+        data
+    }
+
+    fn device(transport: MockTransport) -> V4Device {
+        V4Device::from_transport(Box::new(transport), "mock")
+    }
+
+    #[test]
+    fn test_push_reports_word_indices() {
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[4, 5]);
+        let mut file = header(0, 2, 2);
+        file.extend_from_slice(&[0x51]);
+
+        let report = device(transport).push(&file, TIMEOUT, &mut |_| {}).unwrap();
+        assert_eq!(report.size, 17);
+        assert_eq!(report.word_indices, vec![4, 5]);
+    }
+
+    #[test]
+    fn test_push_rejects_count_mismatch() {
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[4]);
+        let mut file = header(0, 2, 2);
+        file.extend_from_slice(&[0x51]);
+
+        let err = device(transport).push(&file, TIMEOUT, &mut |_| {});
+        assert!(matches!(err, Err(V4Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_push_compresses_when_the_device_accepts_lz4() {
+        let code: Vec<u8> = (0..300)
+            .flat_map(|i| [0x76, i as u8 % 3, 0x60, 0x11])
+            .collect();
+        let mut file = header(0, 2, 0);
+        file[8..12].copy_from_slice(&(code.len() as u32).to_le_bytes());
+        file.extend_from_slice(&code);
+
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[1, FEATURE_LZ4]);
+        for _ in 0..3 {
+            transport.push_response(ErrorCode::Ok, &[]);
+        }
+        let mut progress = Vec::new();
+        let report = device(transport)
+            .with_compression(true)
+            .push(&file, TIMEOUT, &mut |sent| progress.push(sent))
+            .unwrap();
+        let stats = report.compression.unwrap();
+        assert_eq!(stats.size, file.len());
+        assert!(stats.ratio() < 0.2, "{}", stats.ratio());
+        assert_eq!(progress.last(), Some(&file.len()));
+
+        // Firmware without HELLO gets the file as it is, in three chunks
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Error, &[]);
+        for _ in 0..5 {
+            transport.push_response(ErrorCode::Ok, &[]);
+        }
+        let report = device(transport)
+            .with_compression(true)
+            .push(&file, TIMEOUT, &mut |_| {})
+            .unwrap();
+        assert_eq!(report.compression, None);
+    }
+
+    #[test]
+    fn test_push_rejects_bad_magic() {
+        let mut file = header(0, 2, 0);
+        file[0] = b'X';
+        file.push(0x51);
+
+        let err = device(MockTransport::new()).push(&file, TIMEOUT, &mut |_| {});
+        assert!(matches!(err, Err(V4Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_exec_source_report() {
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[0]);
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        let mut device = device(transport);
+        let report = device.exec_source(": SQ DUP * ;\n3 SQ", TIMEOUT).unwrap();
+        assert_eq!(report.words.len(), 1);
+        assert_eq!(report.words[0].name, "SQ");
+        assert_eq!(report.words[0].index, 0);
+        assert!(report.main_size > 0);
+
+        // The word stays known to the compiler for later calls
+        assert!(device.compiler().unwrap().compile("SQ").is_ok());
+    }
+
+    const RETRY: RetryPolicy = RetryPolicy {
+        retries: 1,
+        delay: Duration::from_millis(1),
+    };
+
+    #[test]
+    fn test_exec_source_waits_for_accepted_result() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Accepted, &[]);
+        transport.push_response(ErrorCode::VmError, &[]);
+        let mut device = device(transport).with_result_wait(ResultWait::Within(TIMEOUT));
+
+        let err = device.exec_source("1 2 +", TIMEOUT).unwrap_err();
+        assert!(err.to_string().contains("VM_ERROR"), "{}", err);
+    }
+
+    #[test]
+    fn test_exec_source_names_the_phase_that_timed_out() {
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[0]);
+        transport.push_timeout();
+        let mut device = device(transport);
+
+        let err = device
+            .exec_source(": ONE 1 ;\n: TWO 2 ;\nONE TWO", TIMEOUT)
+            .unwrap_err();
+        assert!(matches!(err, V4Error::PhaseTimeout { .. }), "{}", err);
+        assert!(
+            err.to_string().contains("defining word 'TWO' (2 of 2)"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_exec_source_deadline_covers_accepted_result() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Accepted, &[]);
+        let mut device = device(transport)
+            .with_result_wait(ResultWait::Forever)
+            .with_deadline(Duration::from_millis(30));
+
+        let err = device.exec_source("1 2 +", TIMEOUT).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Deadline of 30ms exceeded while waiting for the program to finish"
+        );
+    }
+
+    #[test]
+    fn test_abort_on_interrupt() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        let passed = abort_on_interrupt(&mut transport, Ok(1), TIMEOUT);
+        assert_eq!(passed.unwrap(), 1);
+        let aborted = abort_on_interrupt::<()>(&mut transport, Err(V4Error::Interrupted), TIMEOUT);
+        assert!(matches!(aborted, Err(V4Error::Aborted)));
+        assert_eq!(transport.sent_commands(), vec![Command::Abort]);
+
+        // No answer to ABORT: the program may still run
+        let unanswered =
+            abort_on_interrupt::<()>(&mut transport, Err(V4Error::Interrupted), TIMEOUT);
+        assert!(matches!(unanswered, Err(V4Error::Interrupted)));
+    }
+
+    #[test]
+    fn test_with_retry_resends_ping() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Error, &[]);
+        transport.push_timeout();
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        let mut device = device(transport).with_retry(RETRY).unwrap();
+        device.ping(TIMEOUT).unwrap();
+    }
+
+    #[test]
+    fn test_with_retry_negotiates_sequence() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[1, crate::protocol::FEATURE_SEQUENCE]);
+        transport.push_sequenced(0, ErrorCode::Ok, &[]);
+
+        let mut device = device(transport).with_retry(RETRY).unwrap();
+        device.ping(TIMEOUT).unwrap();
+    }
+
+    #[test]
+    fn test_info() {
+        let payload = [
+            1, 2, 3, 0, 4, 0, 0x00, 0x01, 0x00, 0x01, 0x40, 0x00, 0x00, 0x10, 0x00, 0x00, 0x01,
+        ];
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &payload);
+
+        let info = device(transport).info(TIMEOUT).unwrap();
+        assert_eq!(info.firmware_version, (1, 2, 3));
+        assert_eq!(info.dictionary_capacity, 256);
+        assert_eq!(info.return_stack_size, 64);
+        assert_eq!(info.free_memory, 4096);
+        assert_eq!(info.feature_names(), vec!["sequence numbers"]);
+        assert!(info.to_string().contains("Firmware:      v1.2.3"));
+
+        assert!(DeviceInfo::from_payload(&payload[..16]).is_err());
+    }
+
+    #[test]
+    fn test_exec_source_timing_with_cycle_counter() {
+        let ticks = |count: u64| {
+            let mut payload = count.to_le_bytes().to_vec();
+            payload.extend_from_slice(&1_000_000u32.to_le_bytes());
+            payload
+        };
+        let mut info = vec![0; 16];
+        info.push(FEATURE_TICKS);
+
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &info);
+        transport.push_response(ErrorCode::Ok, &ticks(1_000));
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::Ok, &ticks(3_500));
+        let mut device = device(transport).with_timing();
+        assert!(device.stopwatch().reads_ticks());
+
+        let report = device.exec_source("1 2 +", TIMEOUT).unwrap();
+        let timing = report.main_timing.unwrap();
+        assert_eq!(timing.device, Some(Duration::from_micros(2_500)));
+        assert!(timing.to_string().ends_with(", VM 2.5 ms"), "{}", timing);
+        assert!(report.elapsed >= timing.host);
+    }
+
+    #[test]
+    fn test_stack_and_memory() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[1, 7, 0, 0, 0, 0]);
+        transport.push_response(ErrorCode::Ok, &[0xDE, 0xAD]);
+        transport.push_response(ErrorCode::Error, &[]);
+
+        let mut device = device(transport);
+        assert_eq!(device.stack(TIMEOUT).unwrap().data, vec![7]);
+        let dump = device.memory(0x40, 2, TIMEOUT).unwrap();
+        assert_eq!((dump.addr, dump.bytes), (0x40, vec![0xDE, 0xAD]));
+        assert!(device.word(9, TIMEOUT).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_memory_in_chunks() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[0xAA; 256]);
+        transport.push_response(ErrorCode::Ok, &[0xBB; 100]);
+
+        let mut progress = Vec::new();
+        let dump = read_memory(&mut transport, 0x1000, 600, TIMEOUT, &mut |n| {
+            progress.push(n)
+        })
+        .unwrap();
+        // The second answer falls short: memory ends at 0x1164
+        assert_eq!(dump.bytes.len(), 356);
+        assert_eq!(dump.end(), 0x1164);
+        assert_eq!(progress, vec![256, 356]);
+        // [ADDR u32][LEN u16]
+        assert_eq!(
+            transport.sent[0].payload,
+            vec![0x00, 0x10, 0, 0, 0x00, 0x01]
+        );
+        assert_eq!(
+            transport.sent[1].payload,
+            vec![0x00, 0x11, 0, 0, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_ping_error_code() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Error, &[]);
+
+        let err = device(transport).ping(TIMEOUT).unwrap_err();
+        assert!(matches!(err, V4Error::Device(_)));
+    }
+}
@@ -0,0 +1,76 @@
+//! Line diffs between two versions of a text, e.g. a redefined word
+
+/// One line of a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// In both texts
+    Same(&'a str),
+    /// Only in the old text
+    Removed(&'a str),
+    /// Only in the new text
+    Added(&'a str),
+}
+
+/// Diff `old` against `new` line by line (longest common subsequence)
+///
+/// Meant for short texts such as word definitions; time and memory grow with
+/// the product of the line counts.
+pub fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // common[i][j]: length of the LCS of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            diff.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            diff.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old[i..].iter().map(|line| Line::Removed(line)));
+    diff.extend(new[j..].iter().map(|line| Line::Added(line)));
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_keeps_common_lines() {
+        let diff = lines(
+            ": CLAMP\nDUP 0< IF DROP 0 THEN\n;",
+            ": CLAMP\nDUP 0< IF DROP 1 THEN\n;",
+        );
+        assert_eq!(
+            diff,
+            [
+                Line::Same(": CLAMP"),
+                Line::Removed("DUP 0< IF DROP 0 THEN"),
+                Line::Added("DUP 0< IF DROP 1 THEN"),
+                Line::Same(";"),
+            ]
+        );
+        assert_eq!(lines("a", "a\nb"), [Line::Same("a"), Line::Added("b")]);
+        assert_eq!(lines("a\nb", ""), [Line::Removed("a"), Line::Removed("b")]);
+    }
+}
@@ -0,0 +1,317 @@
+//! V4 bytecode disassembler
+//!
+//! Decodes raw VM bytecode into mnemonics with operands. Jump operands are
+//! relative to the end of the jump instruction and are resolved to absolute
+//! offsets. Bytes that don't start a known instruction are shown as `.byte`
+//! so a bad opcode never hides the rest of the listing.
+
+use crate::syscalls::{self, Syscall};
+use std::fmt::Write;
+
+/// Operand encoding following an opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    U8,
+    I8,
+    I16,
+    U16,
+    I32,
+    /// Signed 16-bit jump offset relative to the next instruction
+    Rel16,
+}
+
+impl Operand {
+    /// Encoded operand size in bytes
+    pub fn size(self) -> usize {
+        match self {
+            Operand::None => 0,
+            Operand::U8 | Operand::I8 => 1,
+            Operand::I16 | Operand::U16 | Operand::Rel16 => 2,
+            Operand::I32 => 4,
+        }
+    }
+}
+
+/// Opcode table entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpInfo {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operand: Operand,
+}
+
+const fn op(opcode: u8, mnemonic: &'static str, operand: Operand) -> OpInfo {
+    OpInfo {
+        opcode,
+        mnemonic,
+        operand,
+    }
+}
+
+/// Opcode of SYS, whose operand is a system call number
+const OP_SYS: u8 = 0x60;
+
+/// V4 VM opcodes (mirrors V4-engine `opcodes.def`)
+pub const OPCODES: &[OpInfo] = &[
+    // Literals and stack
+    op(0x00, "LIT", Operand::I32),
+    op(0x01, "DUP", Operand::None),
+    op(0x02, "DROP", Operand::None),
+    op(0x03, "SWAP", Operand::None),
+    op(0x04, "OVER", Operand::None),
+    op(0x05, ">R", Operand::None),
+    op(0x06, "R>", Operand::None),
+    op(0x07, "R@", Operand::None),
+    // Arithmetic
+    op(0x10, "ADD", Operand::None),
+    op(0x11, "SUB", Operand::None),
+    op(0x12, "MUL", Operand::None),
+    op(0x13, "DIV", Operand::None),
+    op(0x14, "MOD", Operand::None),
+    op(0x15, "DIVU", Operand::None),
+    op(0x16, "MODU", Operand::None),
+    // Comparison
+    op(0x20, "EQ", Operand::None),
+    op(0x21, "NE", Operand::None),
+    op(0x22, "LT", Operand::None),
+    op(0x23, "LE", Operand::None),
+    op(0x24, "GT", Operand::None),
+    op(0x25, "GE", Operand::None),
+    op(0x26, "LTU", Operand::None),
+    op(0x27, "GEU", Operand::None),
+    // Bitwise
+    op(0x28, "AND", Operand::None),
+    op(0x29, "OR", Operand::None),
+    op(0x2A, "XOR", Operand::None),
+    op(0x2B, "INVERT", Operand::None),
+    op(0x2C, "SHL", Operand::None),
+    op(0x2D, "SHR", Operand::None),
+    op(0x2E, "SAR", Operand::None),
+    // Memory
+    op(0x30, "LOAD", Operand::None),
+    op(0x31, "STORE", Operand::None),
+    op(0x32, "LOAD8U", Operand::None),
+    op(0x33, "LOAD16U", Operand::None),
+    op(0x34, "STORE8", Operand::None),
+    op(0x35, "STORE16", Operand::None),
+    // Control flow
+    op(0x40, "JMP", Operand::Rel16),
+    op(0x41, "JZ", Operand::Rel16),
+    op(0x42, "JNZ", Operand::Rel16),
+    op(0x50, "CALL", Operand::U16),
+    op(0x51, "RET", Operand::None),
+    // System calls
+    op(0x60, "SYS", Operand::U8),
+    // Compact literals
+    op(0x76, "LIT_U8", Operand::U8),
+    op(0x77, "LIT_I8", Operand::I8),
+    op(0x78, "LIT_I16", Operand::I16),
+];
+
+/// Look up an opcode
+pub fn lookup(opcode: u8) -> Option<&'static OpInfo> {
+    OPCODES.iter().find(|info| info.opcode == opcode)
+}
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Offset of the opcode byte
+    pub offset: usize,
+    /// Encoded bytes (opcode + operand)
+    pub bytes: Vec<u8>,
+    /// `None` for unknown opcodes and truncated instructions
+    pub info: Option<&'static OpInfo>,
+    /// Decoded operand value
+    pub operand: Option<i64>,
+    /// Absolute jump target for `Rel16` operands
+    pub target: Option<usize>,
+}
+
+impl Instruction {
+    /// Assembly text, e.g. `LIT_U8 7` or `JZ +4`
+    pub fn text(&self) -> String {
+        let Some(info) = self.info else {
+            let bytes: Vec<String> = self.bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+            return format!(".byte {}", bytes.join(", "));
+        };
+
+        match (info.operand, self.operand) {
+            (Operand::Rel16, Some(rel)) => format!("{} {:+}", info.mnemonic, rel),
+            (_, Some(value)) => format!("{} {}", info.mnemonic, value),
+            _ => info.mnemonic.to_string(),
+        }
+    }
+}
+
+/// Decode bytecode into instructions
+pub fn decode(code: &[u8]) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut pos = 0;
+
+    while pos < code.len() {
+        let insn = decode_at(code, pos);
+        pos += insn.bytes.len();
+        instructions.push(insn);
+    }
+
+    instructions
+}
+
+/// Decode the instruction starting at `pos` (which must be inside `code`)
+pub fn decode_at(code: &[u8], pos: usize) -> Instruction {
+    let info = lookup(code[pos]);
+    let len = 1 + info.map_or(0, |i| i.operand.size());
+
+    let Some(info) = info.filter(|_| pos + len <= code.len()) else {
+        // Unknown opcode: one byte. Truncated operand: the rest of the code.
+        let end = if info.is_some() { code.len() } else { pos + 1 };
+        return Instruction {
+            offset: pos,
+            bytes: code[pos..end].to_vec(),
+            info: None,
+            operand: None,
+            target: None,
+        };
+    };
+
+    let raw = &code[pos + 1..pos + len];
+    let operand = match info.operand {
+        Operand::None => None,
+        Operand::U8 => Some(raw[0] as i64),
+        Operand::I8 => Some(raw[0] as i8 as i64),
+        Operand::U16 => Some(u16::from_le_bytes([raw[0], raw[1]]) as i64),
+        Operand::I16 | Operand::Rel16 => Some(i16::from_le_bytes([raw[0], raw[1]]) as i64),
+        Operand::I32 => Some(i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as i64),
+    };
+    let target = match (info.operand, operand) {
+        (Operand::Rel16, Some(rel)) => usize::try_from((pos + len) as i64 + rel).ok(),
+        _ => None,
+    };
+
+    Instruction {
+        offset: pos,
+        bytes: code[pos..pos + len].to_vec(),
+        info: Some(info),
+        operand,
+        target,
+    }
+}
+
+/// Disassembly listing, one instruction per line
+///
+/// Jump targets are shown as absolute offsets; targets outside the code
+/// are flagged.
+pub fn listing(code: &[u8]) -> String {
+    let mut out = String::new();
+
+    for insn in decode(code) {
+        out.push_str(&listing_line(&insn, code.len()));
+        out.push('\n');
+    }
+
+    out
+}
+
+/// One listing line: offset, encoded bytes, instruction and jump target
+///
+/// `code_len` is the size of the decoded code, to flag targets past its end.
+pub fn listing_line(insn: &Instruction, code_len: usize) -> String {
+    let bytes: Vec<String> = insn.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let mut out = format!(
+        "{:04X}  {:<15} {}",
+        insn.offset,
+        bytes.join(" "),
+        insn.text()
+    );
+    if let Some(target) = insn.target {
+        let _ = write!(out, "  ; -> {:04X}", target);
+        if target > code_len {
+            out.push_str(" (out of range)");
+        }
+    } else if insn.info.is_some_and(|i| i.operand == Operand::Rel16) {
+        out.push_str("  ; -> (out of range)");
+    } else if let Some(syscall) = syscall(insn) {
+        let _ = write!(out, "  ; {}", syscall.constant());
+    }
+    out
+}
+
+/// Known system call a `SYS` instruction makes
+pub fn syscall(insn: &Instruction) -> Option<&'static Syscall> {
+    match (insn.info?.opcode, insn.operand) {
+        (OP_SYS, Some(number)) => syscalls::lookup(number as u8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcode_table_unique() {
+        for (i, a) in OPCODES.iter().enumerate() {
+            for b in &OPCODES[i + 1..] {
+                assert_ne!(a.opcode, b.opcode, "{} / {}", a.mnemonic, b.mnemonic);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_led_on() {
+        // examples/led_on.v4b: 7 3 SYS 0 DROP 7 1 SYS 1 DROP
+        let code = [
+            0x76, 0x07, 0x76, 0x03, 0x60, 0x00, 0x02, 0x76, 0x07, 0x76, 0x01, 0x60, 0x01, 0x02,
+            0x51,
+        ];
+        let text: Vec<String> = decode(&code).iter().map(Instruction::text).collect();
+        assert_eq!(
+            text,
+            vec![
+                "LIT_U8 7", "LIT_U8 3", "SYS 0", "DROP", "LIT_U8 7", "LIT_U8 1", "SYS 1", "DROP",
+                "RET"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_operands() {
+        let code = [0x00, 0xE8, 0x03, 0x00, 0x00, 0x77, 0xFF, 0x78, 0x00, 0x80];
+        let insns = decode(&code);
+        assert_eq!(insns[0].operand, Some(1000));
+        assert_eq!(insns[1].operand, Some(-1));
+        assert_eq!(insns[2].operand, Some(-32768));
+    }
+
+    #[test]
+    fn test_jump_targets() {
+        // 0: JZ +1 -> 4, 3: DUP, 4: JMP -7 -> 0
+        let code = [0x41, 0x01, 0x00, 0x01, 0x40, 0xF9, 0xFF];
+        let insns = decode(&code);
+        assert_eq!(insns[0].target, Some(4));
+        assert_eq!(insns[2].target, Some(0));
+        assert_eq!(insns[2].text(), "JMP -7");
+
+        let text = listing(&code);
+        assert!(text.contains("JZ +1  ; -> 0004"), "{}", text);
+    }
+
+    #[test]
+    fn test_listing_names_syscalls() {
+        let text = listing(&[0x76, 0x07, 0x60, 0x02, 0x60, 0xEE, 0x51]);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "0002  60 02           SYS 2  ; SYS_GPIO_GET");
+        assert_eq!(lines[2], "0004  60 EE           SYS 238");
+    }
+
+    #[test]
+    fn test_unknown_and_truncated() {
+        let insns = decode(&[0xEE, 0x01, 0x00, 0x01]);
+        assert_eq!(insns[0].text(), ".byte 0xEE");
+        assert_eq!(insns[1].text(), "DUP");
+        assert_eq!(insns[2].text(), ".byte 0x00, 0x01");
+        assert_eq!(insns.len(), 3);
+    }
+}
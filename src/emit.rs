@@ -0,0 +1,248 @@
+//! Bytecode output formats for embedding in firmware (`v4 compile --emit`)
+//!
+//! Besides the .v4b file V4-front writes, compiled code can be emitted as a
+//! C or Rust array or an Intel HEX file holding the same .v4b image, built
+//! here from the compiled words and main code, or as the bare main code.
+
+use crate::bytecode::{V4B_HEADER_SIZE, V4B_MAGIC};
+use crate::{Result, V4Error};
+use std::fmt::Write;
+
+/// .v4b version of the images built here, the first with a word section
+pub const V4B_VERSION: (u8, u8) = (0, 2);
+
+/// Array name when none is given
+pub const DEFAULT_SYMBOL: &str = "program";
+
+/// Bytes per line of C and Rust arrays
+const ARRAY_ROW: usize = 12;
+
+/// Data bytes per Intel HEX record
+const HEX_ROW: usize = 16;
+
+/// What `v4 compile` writes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Emit {
+    /// .v4b file, written by V4-front
+    #[default]
+    V4b,
+    /// Main code alone, without header or words
+    Raw,
+    /// `const uint8_t program[] = {...};` holding the .v4b image
+    CArray,
+    /// `pub static PROGRAM: [u8; N] = [...];` holding the .v4b image
+    RustArray,
+    /// Intel HEX records of the .v4b image, from address 0
+    IntelHex,
+}
+
+impl Emit {
+    /// Extension of the default output file
+    pub fn extension(self) -> &'static str {
+        match self {
+            Emit::V4b => "v4b",
+            Emit::Raw => "bin",
+            Emit::CArray => "h",
+            Emit::RustArray => "rs",
+            Emit::IntelHex => "hex",
+        }
+    }
+}
+
+/// Parse an output format (v4b, raw, c-array, rust-array, ihex)
+pub fn parse_emit(value: &str) -> std::result::Result<Emit, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "v4b" => Ok(Emit::V4b),
+        "raw" | "bin" => Ok(Emit::Raw),
+        "c-array" | "c" => Ok(Emit::CArray),
+        "rust-array" | "rust" => Ok(Emit::RustArray),
+        "ihex" | "intel-hex" | "hex" => Ok(Emit::IntelHex),
+        _ => Err(format!(
+            "invalid output format '{}' (expected v4b, raw, c-array, rust-array or ihex)",
+            value
+        )),
+    }
+}
+
+/// Parse an array name: a C identifier
+pub fn parse_symbol(value: &str) -> std::result::Result<String, String> {
+    let mut chars = value.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid {
+        true => Ok(value.to_string()),
+        false => Err(format!("'{}' is not a valid C or Rust identifier", value)),
+    }
+}
+
+/// Encode main code and word definitions as a .v4b file
+///
+/// Header: "V4BC", version, flags (u16), code_size (u32), word_count (u32),
+/// then the main code, then per word a name length (u8), the name, the
+/// code length (u16) and the code. Names are cut at 255 bytes.
+pub fn v4b_image<'a>(
+    code: &[u8],
+    words: impl ExactSizeIterator<Item = (&'a str, &'a [u8])>,
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(V4B_HEADER_SIZE + code.len());
+    data.extend_from_slice(V4B_MAGIC);
+    data.extend_from_slice(&[V4B_VERSION.0, V4B_VERSION.1]);
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&(code.len() as u32).to_le_bytes());
+    data.extend_from_slice(&(words.len() as u32).to_le_bytes());
+    data.extend_from_slice(code);
+    for (name, code) in words {
+        let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+        data.push(name.len() as u8);
+        data.extend_from_slice(name);
+        data.extend_from_slice(&(code.len() as u16).to_le_bytes());
+        data.extend_from_slice(code);
+    }
+    data
+}
+
+/// Output file contents in `format` for compiled code
+///
+/// `Emit::V4b` gives the image built here; `v4 compile` has V4-front
+/// write that format instead. Raw output fails for code that defines
+/// words, since it has nowhere to put them.
+pub fn emit<'a>(
+    format: Emit,
+    code: &[u8],
+    words: impl ExactSizeIterator<Item = (&'a str, &'a [u8])>,
+    symbol: &str,
+) -> Result<Vec<u8>> {
+    if format == Emit::Raw {
+        return match words.len() {
+            0 => Ok(code.to_vec()),
+            n => Err(V4Error::Compilation(format!(
+                "Raw output holds only the main code, but the source defines {} word(s); \
+                 use --emit c-array, rust-array, ihex or v4b",
+                n
+            ))),
+        };
+    }
+    let image = v4b_image(code, words);
+    Ok(match format {
+        Emit::V4b | Emit::Raw => image,
+        Emit::CArray => c_array(&image, symbol).into_bytes(),
+        Emit::RustArray => rust_array(&image, symbol).into_bytes(),
+        Emit::IntelHex => intel_hex(&image).into_bytes(),
+    })
+}
+
+/// C source defining `symbol` and `symbol_len`
+pub fn c_array(bytes: &[u8], symbol: &str) -> String {
+    let mut out = String::from("/* V4 bytecode, generated by v4 compile */\n");
+    out.push_str("#include <stddef.h>\n#include <stdint.h>\n\n");
+    let _ = writeln!(out, "const uint8_t {}[] = {{", symbol);
+    array_rows(&mut out, bytes);
+    let _ = writeln!(out, "}};\nconst size_t {0}_len = sizeof({0});", symbol);
+    out
+}
+
+/// Rust source defining `SYMBOL` as a byte array
+pub fn rust_array(bytes: &[u8], symbol: &str) -> String {
+    let mut out = String::from("// V4 bytecode, generated by v4 compile\n");
+    let _ = writeln!(
+        out,
+        "pub static {}: [u8; {}] = [",
+        symbol.to_ascii_uppercase(),
+        bytes.len()
+    );
+    array_rows(&mut out, bytes);
+    out.push_str("];\n");
+    out
+}
+
+fn array_rows(out: &mut String, bytes: &[u8]) {
+    for row in bytes.chunks(ARRAY_ROW) {
+        let row: Vec<String> = row.iter().map(|b| format!("0x{:02X},", b)).collect();
+        let _ = writeln!(out, "    {}", row.join(" "));
+    }
+}
+
+/// Intel HEX data records from address 0, with extended linear address
+/// records past 64 KiB and an end-of-file record
+pub fn intel_hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, row) in bytes.chunks(HEX_ROW).enumerate() {
+        let addr = i * HEX_ROW;
+        if addr > 0 && addr.is_multiple_of(0x10000) {
+            hex_record(&mut out, 0, 0x04, &((addr >> 16) as u16).to_be_bytes());
+        }
+        hex_record(&mut out, addr as u16, 0x00, row);
+    }
+    hex_record(&mut out, 0, 0x01, &[]);
+    out
+}
+
+/// `:LLAAAATT<data>CC` with the two's complement checksum
+fn hex_record(out: &mut String, addr: u16, kind: u8, data: &[u8]) {
+    let [hi, lo] = addr.to_be_bytes();
+    let mut sum = (data.len() as u8)
+        .wrapping_add(hi)
+        .wrapping_add(lo)
+        .wrapping_add(kind);
+    let _ = write!(out, ":{:02X}{:04X}{:02X}", data.len(), addr, kind);
+    for &byte in data {
+        let _ = write!(out, "{:02X}", byte);
+        sum = sum.wrapping_add(byte);
+    }
+    let _ = writeln!(out, "{:02X}", sum.wrapping_neg());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQ: [u8; 3] = [0x01, 0x12, 0x51];
+
+    fn words() -> impl ExactSizeIterator<Item = (&'static str, &'static [u8])> {
+        [("SQ", &SQ[..])].into_iter()
+    }
+
+    #[test]
+    fn test_v4b_image_layout() {
+        let image = v4b_image(&[0x76, 3, 0x51], words());
+        assert_eq!(&image[..6], b"V4BC\x00\x02");
+        assert_eq!(image[8], 3);
+        assert_eq!(image[12], 1);
+        assert_eq!(&image[16..19], &[0x76, 3, 0x51]);
+        assert_eq!(&image[19..], &[2, b'S', b'Q', 3, 0, 0x01, 0x12, 0x51]);
+    }
+
+    #[test]
+    fn test_arrays() {
+        let c = c_array(&[0x56, 0x34, 0xFF], "blink");
+        assert!(c.contains("const uint8_t blink[] = {\n    0x56, 0x34, 0xFF,\n};"));
+        assert!(c.contains("const size_t blink_len = sizeof(blink);"));
+        let rust = rust_array(&[1; 13], "blink");
+        assert!(rust.contains("pub static BLINK: [u8; 13] = [\n"));
+        assert!(rust.ends_with("    0x01,\n];\n"));
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        assert_eq!(
+            intel_hex(&[0x56, 0x34, 0x42, 0x43]),
+            ":0400000056344243ED\n:00000001FF\n"
+        );
+        let big = intel_hex(&vec![0; 0x10010]);
+        assert!(big.contains("\n:020000040001F9\n:10000000"));
+    }
+
+    #[test]
+    fn test_raw_rejects_words() {
+        let code = [0x76, 3, 0x51];
+        assert_eq!(
+            emit(Emit::Raw, &code, std::iter::empty(), "p").unwrap(),
+            code
+        );
+        assert!(emit(Emit::Raw, &code, words(), "p").is_err());
+        assert_eq!(parse_emit("C-ARRAY"), Ok(Emit::CArray));
+        assert!(parse_symbol("9lives").is_err());
+    }
+}
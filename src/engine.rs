@@ -0,0 +1,985 @@
+//! Bindings to the V4 engine library
+//!
+//! `v4` links the VM the firmware runs, built with the engine's mock HAL
+//! (`V4_ENABLE_MOCK_HAL=ON`, see `build.rs`), so bytecode runs on the host
+//! with the engine's own semantics. All engine C declarations live here;
+//! [`Engine`] owns one VM and its memory.
+//!
+//! The engine has no instruction limit and no way to stop a program from
+//! outside, so [`EngineTransport`], which answers V4-link commands and lets
+//! `v4 exec` and the REPL run unchanged, keeps its VM in a `v4 engine-server`
+//! child process ([`EngineProcess`]) that can be killed.
+
+use crate::debugger::{Location, TOP_LEVEL};
+use crate::disasm;
+use crate::interrupt;
+use crate::protocol::{
+    Command, ErrorCode, FaultKind, Frame, NOTIFY_OUTPUT, PROTOCOL_VERSION, Response, StackSnapshot,
+    VmFault, WordInfo, device_frame,
+};
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ffi::{CString, c_char, c_int, c_void};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Stdio};
+use std::ptr::{self, NonNull};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// VM memory handed to the engine, in bytes
+pub const MEMORY_SIZE: usize = 64 * 1024;
+/// Hidden subcommand running [`serve_stdio`]
+pub const SERVER_COMMAND: &str = "engine-server";
+
+/// Opcode of CALL, whose operand is a word index
+const OP_CALL: u8 = 0x50;
+/// How long the engine process gets to answer anything but a run
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[repr(C)]
+struct Vm {
+    _private: [u8; 0],
+}
+
+/// `Word` from `vm_api.h`: bytecode the VM can run
+#[repr(C)]
+struct V4Word {
+    name: *const c_char,
+    code: *const u8,
+    code_len: c_int,
+}
+
+/// `VmConfig` from `vm_api.h`
+#[repr(C)]
+struct VmConfig {
+    /// VM RAM; addresses in bytecode are offsets into it
+    mem: *mut u8,
+    mem_size: u32,
+    /// Memory-mapped I/O windows, unused on the host
+    mmio: *const c_void,
+    mmio_count: c_int,
+    /// Arena for word names; null lets the engine allocate them
+    arena: *mut c_void,
+}
+
+// Functions return 0 (or an index) on success and a negative v4_err otherwise
+unsafe extern "C" {
+    fn vm_create(cfg: *const VmConfig) -> *mut Vm;
+    fn vm_destroy(vm: *mut Vm);
+    fn vm_register_word(
+        vm: *mut Vm,
+        name: *const c_char,
+        code: *const u8,
+        code_len: c_int,
+    ) -> c_int;
+    fn vm_exec(vm: *mut Vm, entry: *mut V4Word) -> c_int;
+    fn vm_ds_depth_public(vm: *mut Vm) -> c_int;
+    fn vm_ds_peek_public(vm: *mut Vm, index_from_top: c_int) -> i32;
+}
+
+/// Why the engine didn't run code to completion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum EngineError {
+    /// Negative `v4_err` code returned by the engine
+    #[error("V4 engine error {0}")]
+    Code(i32),
+    /// Call to a word that isn't registered, refused before running
+    #[error("{} {}", .0.reason(), Location::of_fault(.0).describe(None))]
+    Fault(VmFault),
+}
+
+/// A registered word, kept alive for the VM
+struct EngineWord {
+    /// Index the engine assigned
+    idx: u16,
+    name: CString,
+    code: Vec<u8>,
+}
+
+/// One V4 engine VM
+///
+/// The engine keeps pointers to the memory and to registered word names and
+/// code, so they are owned here and outlive the VM.
+pub struct Engine {
+    vm: NonNull<Vm>,
+    memory: Vec<u8>,
+    words: Vec<EngineWord>,
+}
+
+impl Engine {
+    pub fn new() -> Result<Self> {
+        let mut memory = vec![0; MEMORY_SIZE];
+        let vm = Self::create(&mut memory)?;
+        Ok(Self {
+            vm,
+            memory,
+            words: Vec::new(),
+        })
+    }
+
+    fn create(memory: &mut [u8]) -> Result<NonNull<Vm>> {
+        let config = VmConfig {
+            mem: memory.as_mut_ptr(),
+            mem_size: memory.len() as u32,
+            mmio: ptr::null(),
+            mmio_count: 0,
+            arena: ptr::null_mut(),
+        };
+        NonNull::new(unsafe { vm_create(&config) })
+            .ok_or_else(|| V4Error::Device("Cannot create a V4 engine VM".to_string()))
+    }
+
+    /// Start over with a new VM: empty stacks, memory and dictionary
+    pub fn reset(&mut self) -> Result<()> {
+        let mut memory = vec![0; MEMORY_SIZE];
+        let vm = Self::create(&mut memory)?;
+        unsafe { vm_destroy(self.vm.as_ptr()) };
+        self.vm = vm;
+        self.memory = memory;
+        self.words.clear();
+        Ok(())
+    }
+
+    /// Register a word, returning its index
+    pub fn define(&mut self, name: &str, code: &[u8]) -> std::result::Result<u16, EngineError> {
+        let name = CString::new(name).map_err(|_| EngineError::Code(-1))?;
+        let code = code.to_vec();
+        let idx = unsafe {
+            vm_register_word(
+                self.vm.as_ptr(),
+                name.as_ptr(),
+                code.as_ptr(),
+                code.len() as c_int,
+            )
+        };
+        let idx = u16::try_from(idx).map_err(|_| EngineError::Code(idx))?;
+        self.words.push(EngineWord { idx, name, code });
+        Ok(idx)
+    }
+
+    /// Run code until it returns
+    ///
+    /// Calls to words that aren't registered are refused up front. Nothing
+    /// stops code that never returns short of ending the process; see
+    /// [`EngineProcess`].
+    pub fn run(&mut self, code: &[u8]) -> std::result::Result<(), EngineError> {
+        self.check_calls(code)?;
+        let mut entry = V4Word {
+            name: ptr::null(),
+            code: code.as_ptr(),
+            code_len: code.len() as c_int,
+        };
+        match unsafe { vm_exec(self.vm.as_ptr(), &mut entry) } {
+            0 => Ok(()),
+            code => Err(EngineError::Code(code)),
+        }
+    }
+
+    /// Fault for the first CALL to an unregistered word in `code` or in the
+    /// words it reaches
+    fn check_calls(&self, code: &[u8]) -> std::result::Result<(), EngineError> {
+        let mut pending = vec![(TOP_LEVEL, code)];
+        let mut seen = Vec::new();
+        while let Some((word, code)) = pending.pop() {
+            let calls = disasm::decode(code)
+                .into_iter()
+                .filter(|insn| insn.info.is_some_and(|info| info.opcode == OP_CALL));
+            for insn in calls {
+                let target = insn.operand.unwrap_or_default() as u16;
+                let Some(callee) = self.words.iter().find(|w| w.idx == target) else {
+                    return Err(EngineError::Fault(VmFault {
+                        word,
+                        ip: insn.offset as u16,
+                        kind: Some(FaultKind::UnknownWord),
+                        stack_depth: None,
+                    }));
+                };
+                if !seen.contains(&target) {
+                    seen.push(target);
+                    pending.push((target, &callee.code));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Data stack, bottom first
+    pub fn data_stack(&self) -> Vec<i32> {
+        let vm = self.vm.as_ptr();
+        let depth = unsafe { vm_ds_depth_public(vm) }.max(0);
+        (0..depth)
+            .rev()
+            .map(|from_top| unsafe { vm_ds_peek_public(vm, from_top) })
+            .collect()
+    }
+
+    /// A registered word's name and code
+    pub fn word(&self, idx: u16) -> Result<(&str, &[u8])> {
+        let word = self.words.iter().find(|word| word.idx == idx);
+        match word.and_then(|word| Some((word.name.to_str().ok()?, &word.code[..]))) {
+            Some(found) => Ok(found),
+            None => {
+                let fault = VmFault {
+                    word: idx,
+                    ip: 0,
+                    kind: Some(FaultKind::UnknownWord),
+                    stack_depth: None,
+                };
+                Err(V4Error::VmFault {
+                    context: "Engine".to_string(),
+                    place: format!("#{}", idx),
+                    fault,
+                })
+            }
+        }
+    }
+
+    /// `len` bytes of VM memory at `addr`, if in range
+    pub fn memory(&self, addr: u32, len: usize) -> Option<&[u8]> {
+        let start = addr as usize;
+        self.memory.get(start..start.checked_add(len)?)
+    }
+
+    /// Overwrite VM memory at `addr`; `None` if out of range
+    pub fn write_memory(&mut self, addr: u32, data: &[u8]) -> Option<()> {
+        let start = addr as usize;
+        self.memory
+            .get_mut(start..start.checked_add(data.len())?)?
+            .copy_from_slice(data);
+        Some(())
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        unsafe { vm_destroy(self.vm.as_ptr()) };
+    }
+}
+
+/// Request to the engine server, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Reset,
+    Define { name: String, code: Vec<u8> },
+    Run { code: Vec<u8> },
+    DataStack,
+    Word { idx: u16 },
+    Memory { addr: u32, len: usize },
+    WriteMemory { addr: u32, data: Vec<u8> },
+}
+
+/// Engine server's answer to a [`Request`], one JSON object per line
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Reply {
+    Done,
+    Index(u16),
+    /// Negative `v4_err` code
+    Refused(i32),
+    /// VM_ERROR payload of the fault
+    Fault(Vec<u8>),
+    Stack(Vec<i32>),
+    Word(Option<(String, Vec<u8>)>),
+    Memory(Option<Vec<u8>>),
+    Written(bool),
+    Error(String),
+}
+
+impl Reply {
+    fn from_engine_error(err: EngineError) -> Self {
+        match err {
+            EngineError::Code(code) => Reply::Refused(code),
+            EngineError::Fault(fault) => Reply::Fault(fault.to_payload()),
+        }
+    }
+
+    fn engine_error(&self) -> Option<EngineError> {
+        match self {
+            Reply::Refused(code) => Some(EngineError::Code(*code)),
+            Reply::Fault(payload) => VmFault::from_payload(payload).map(EngineError::Fault),
+            _ => None,
+        }
+    }
+}
+
+/// Answer one request from `engine`
+fn answer(engine: &mut Engine, request: Request) -> Reply {
+    match request {
+        Request::Reset => match engine.reset() {
+            Ok(()) => Reply::Done,
+            Err(e) => Reply::Error(e.to_string()),
+        },
+        Request::Define { name, code } => match engine.define(&name, &code) {
+            Ok(idx) => Reply::Index(idx),
+            Err(e) => Reply::from_engine_error(e),
+        },
+        Request::Run { code } => match engine.run(&code) {
+            Ok(()) => Reply::Done,
+            Err(e) => Reply::from_engine_error(e),
+        },
+        Request::DataStack => Reply::Stack(engine.data_stack()),
+        Request::Word { idx } => Reply::Word(
+            engine
+                .word(idx)
+                .ok()
+                .map(|(name, code)| (name.to_string(), code.to_vec())),
+        ),
+        Request::Memory { addr, len } => {
+            Reply::Memory(engine.memory(addr, len).map(<[u8]>::to_vec))
+        }
+        Request::WriteMemory { addr, data } => {
+            Reply::Written(engine.write_memory(addr, &data).is_some())
+        }
+    }
+}
+
+/// Answer engine requests, one per line, until they run out
+pub fn serve(
+    requests: impl IntoIterator<Item = io::Result<String>>,
+    output: &mut impl Write,
+) -> Result<()> {
+    let mut engine = Engine::new()?;
+    for line in requests {
+        let reply = match serde_json::from_str(&line?) {
+            Ok(request) => answer(&mut engine, request),
+            Err(e) => Reply::Error(format!("Invalid engine request: {}", e)),
+        };
+        serde_json::to_writer(&mut *output, &reply)
+            .map_err(|e| V4Error::Device(format!("Cannot write engine reply: {}", e)))?;
+        writeln!(output)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Serve requests from stdin to stdout, for `v4 engine-server`
+///
+/// Requests are read on their own thread, so the server exits as soon as
+/// the host closes stdin, even with a program still running. Ctrl+C reaches
+/// the whole process group; the host decides what to stop, so it is caught
+/// and ignored here.
+pub fn serve_stdio() -> Result<()> {
+    let _ctrl_c = interrupt::catch();
+    let (requests, incoming) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            if requests.send(line).is_err() {
+                return;
+            }
+        }
+        std::process::exit(0);
+    });
+    serve(incoming, &mut io::stdout().lock())
+}
+
+/// Line from the engine server
+enum ServerLine {
+    Reply(Reply),
+    /// Anything else, printed by the engine's mock HAL
+    Output(String),
+}
+
+/// Running `v4 engine-server` process
+struct Server {
+    child: Child,
+    requests: ChildStdin,
+    replies: mpsc::Receiver<ServerLine>,
+}
+
+/// V4 engine VM in a `v4 engine-server` child process
+///
+/// Code runs there so that a program that never returns can be stopped by
+/// killing the process. The VM's words and memory go with it: requests then
+/// fail until [`reset`](Self::reset) starts a new process.
+pub struct EngineProcess {
+    program: PathBuf,
+    server: Option<Server>,
+    /// A run was started and its result is still to come
+    running: bool,
+    /// Engine output not yet taken
+    output: Vec<String>,
+}
+
+impl EngineProcess {
+    /// Start `v4 engine-server` from the running executable
+    pub fn new() -> Result<Self> {
+        Self::spawn(std::env::current_exe()?)
+    }
+
+    /// Start `<program> engine-server`
+    pub fn spawn(program: impl Into<PathBuf>) -> Result<Self> {
+        let program = program.into();
+        let server = Self::start(&program)?;
+        Ok(Self {
+            program,
+            server: Some(server),
+            running: false,
+            output: Vec::new(),
+        })
+    }
+
+    fn start(program: &PathBuf) -> Result<Server> {
+        let mut child = std::process::Command::new(program)
+            .arg(SERVER_COMMAND)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                V4Error::Device(format!(
+                    "Cannot start the V4 engine ({} {}): {}",
+                    program.display(),
+                    SERVER_COMMAND,
+                    e
+                ))
+            })?;
+        let (requests, stdout) = child.stdin.take().zip(child.stdout.take()).ok_or_else(|| {
+            V4Error::Device("The V4 engine process has no stdin or stdout".to_string())
+        })?;
+
+        let (lines, replies) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { return };
+                let line = match serde_json::from_str(&line) {
+                    Ok(reply) => ServerLine::Reply(reply),
+                    Err(_) => ServerLine::Output(line),
+                };
+                if lines.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Server {
+            child,
+            requests,
+            replies,
+        })
+    }
+
+    /// Whether a program started with [`start_run`](Self::start_run) hasn't
+    /// returned yet
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Kill the engine process, with whatever it is running
+    pub fn stop(&mut self) {
+        if let Some(mut server) = self.server.take() {
+            let _ = server.child.kill();
+            let _ = server.child.wait();
+        }
+        self.running = false;
+    }
+
+    /// Start over with an empty VM, in a new process if the old one was
+    /// stopped or is busy
+    pub fn reset(&mut self) -> Result<()> {
+        if self.server.is_some() && !self.running {
+            return match self.call(&Request::Reset)? {
+                Reply::Done => Ok(()),
+                reply => Err(unexpected(reply)),
+            };
+        }
+        self.stop();
+        self.server = Some(Self::start(&self.program)?);
+        Ok(())
+    }
+
+    /// Register a word, returning its index or the engine's refusal
+    pub fn define(
+        &mut self,
+        name: &str,
+        code: &[u8],
+    ) -> Result<std::result::Result<u16, EngineError>> {
+        let request = Request::Define {
+            name: name.to_string(),
+            code: code.to_vec(),
+        };
+        match self.call(&request)? {
+            Reply::Index(idx) => Ok(Ok(idx)),
+            reply => reply
+                .engine_error()
+                .map(Err)
+                .ok_or_else(|| unexpected(reply)),
+        }
+    }
+
+    /// Start running code; [`wait_run`](Self::wait_run) gets the outcome
+    pub fn start_run(&mut self, code: &[u8]) -> Result<()> {
+        self.send(&Request::Run {
+            code: code.to_vec(),
+        })?;
+        self.running = true;
+        Ok(())
+    }
+
+    /// Outcome of the running program, or `None` if it is still running
+    /// after `timeout`
+    pub fn wait_run(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<std::result::Result<(), EngineError>>> {
+        let Some(reply) = self.receive(timeout)? else {
+            return Ok(None);
+        };
+        self.running = false;
+        match reply {
+            Reply::Done => Ok(Some(Ok(()))),
+            reply => reply
+                .engine_error()
+                .map(|e| Some(Err(e)))
+                .ok_or_else(|| unexpected(reply)),
+        }
+    }
+
+    /// Data stack, bottom first
+    pub fn data_stack(&mut self) -> Result<Vec<i32>> {
+        match self.call(&Request::DataStack)? {
+            Reply::Stack(data) => Ok(data),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// A registered word's name and code, if there is one at `idx`
+    pub fn word(&mut self, idx: u16) -> Result<Option<(String, Vec<u8>)>> {
+        match self.call(&Request::Word { idx })? {
+            Reply::Word(word) => Ok(word),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// `len` bytes of VM memory at `addr`, if in range
+    pub fn memory(&mut self, addr: u32, len: usize) -> Result<Option<Vec<u8>>> {
+        match self.call(&Request::Memory { addr, len })? {
+            Reply::Memory(bytes) => Ok(bytes),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Overwrite VM memory at `addr`; `None` if out of range
+    pub fn write_memory(&mut self, addr: u32, data: &[u8]) -> Result<Option<()>> {
+        let request = Request::WriteMemory {
+            addr,
+            data: data.to_vec(),
+        };
+        match self.call(&request)? {
+            Reply::Written(written) => Ok(written.then_some(())),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Output the engine printed since the last call
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn server(&mut self) -> Result<&mut Server> {
+        self.server.as_mut().ok_or_else(|| {
+            V4Error::Device(
+                "The V4 engine was stopped with a program still running; \
+                 RESET (`.reset` in the REPL) starts a new one"
+                    .to_string(),
+            )
+        })
+    }
+
+    fn send(&mut self, request: &Request) -> Result<()> {
+        if self.running {
+            return Err(V4Error::Device(
+                "A program is still running on the V4 engine".to_string(),
+            ));
+        }
+        let mut line = serde_json::to_vec(request)
+            .map_err(|e| V4Error::Device(format!("Cannot encode engine request: {}", e)))?;
+        line.push(b'\n');
+        let server = self.server()?;
+        if let Err(e) = server.requests.write_all(&line) {
+            self.stop();
+            return Err(V4Error::Device(format!(
+                "The V4 engine process is gone: {}",
+                e
+            )));
+        }
+        Ok(())
+    }
+
+    /// Next reply within `timeout`, collecting output on the way
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Reply>> {
+        let start = Instant::now();
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            match self.server()?.replies.recv_timeout(remaining) {
+                Ok(ServerLine::Reply(reply)) => return Ok(Some(reply)),
+                Ok(ServerLine::Output(text)) => self.output.push(format!("{}\n", text)),
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.stop();
+                    return Err(V4Error::Device("The V4 engine process exited".to_string()));
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, request: &Request) -> Result<Reply> {
+        self.send(request)?;
+        match self.receive(REPLY_TIMEOUT)? {
+            Some(Reply::Error(message)) => Err(V4Error::Device(message)),
+            Some(reply) => Ok(reply),
+            None => {
+                self.stop();
+                Err(V4Error::Device(
+                    "The V4 engine process stopped answering".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn unexpected(reply: Reply) -> V4Error {
+    V4Error::Device(format!("Unexpected reply from the V4 engine: {:?}", reply))
+}
+
+/// Transport answering V4-link commands from an [`EngineProcess`]
+///
+/// EXEC starts the program and the response comes once it returns, so the
+/// caller's timeout and Ctrl+C apply as they do on a device. ABORT, or any
+/// other request, while a program is running kills the engine process.
+/// Engine errors are answered with VM_ERROR and also reported as output.
+/// Breakpoints, QUERY_INFO and QUERY_TICKS need a device.
+pub struct EngineTransport {
+    engine: EngineProcess,
+    /// Encoded frames waiting to be received
+    pending: VecDeque<Vec<u8>>,
+    /// Chunked EXEC being assembled
+    exec_buf: Vec<u8>,
+}
+
+impl EngineTransport {
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_engine(EngineProcess::new()?))
+    }
+
+    pub fn with_engine(engine: EngineProcess) -> Self {
+        Self {
+            engine,
+            pending: VecDeque::new(),
+            exec_buf: Vec::new(),
+        }
+    }
+
+    pub fn engine(&mut self) -> &mut EngineProcess {
+        &mut self.engine
+    }
+
+    fn queue(&mut self, code: u8, data: &[u8]) {
+        self.pending.push_back(device_frame(code, data));
+    }
+
+    fn notify(&mut self, text: &str) {
+        self.queue(NOTIFY_OUTPUT, text.as_bytes());
+    }
+
+    /// Pass on what the engine printed
+    fn queue_output(&mut self) {
+        for text in self.engine.take_output() {
+            self.notify(&text);
+        }
+    }
+
+    /// Kill the engine if a program is still running on it
+    fn stop_running(&mut self) {
+        if self.engine.is_running() {
+            self.engine.stop();
+            self.notify("[engine] stopped the program still running\n");
+        }
+    }
+
+    fn failed(&mut self, err: V4Error, code: ErrorCode) -> (ErrorCode, Vec<u8>) {
+        self.notify(&format!("[engine] {}\n", err));
+        (code, Vec::new())
+    }
+
+    /// Start running code; `None` until it returns
+    fn run_code(&mut self, code: &[u8]) -> Option<(ErrorCode, Vec<u8>)> {
+        if code.starts_with(b"V4BC") {
+            self.notify("[engine] .v4b files aren't run over EXEC; use `v4 run`\n");
+            return Some((ErrorCode::Error, Vec::new()));
+        }
+        match self.engine.start_run(code) {
+            Ok(()) => None,
+            Err(e) => Some(self.failed(e, ErrorCode::VmError)),
+        }
+    }
+
+    fn run_result(&mut self, result: std::result::Result<(), EngineError>) -> (ErrorCode, Vec<u8>) {
+        match result {
+            Ok(()) => (ErrorCode::Ok, Vec::new()),
+            Err(EngineError::Fault(fault)) => (ErrorCode::VmError, fault.to_payload()),
+            Err(e) => {
+                self.notify(&format!("[engine] {}\n", e));
+                (ErrorCode::VmError, Vec::new())
+            }
+        }
+    }
+
+    fn word_payload(&mut self, idx: u16) -> Result<Option<Vec<u8>>> {
+        let word = self.engine.word(idx)?.map(|(name, code)| WordInfo {
+            name: Some(name),
+            code_len: code.len(),
+            code,
+            address: None,
+        });
+        Ok(word.map(|word| word.to_payload()))
+    }
+
+    /// Answer one request; `None` while the program it started runs
+    fn handle(&mut self, frame: &Frame) -> Option<(ErrorCode, Vec<u8>)> {
+        let payload = &frame.payload;
+        let u16_at = |i: usize| {
+            payload
+                .get(i..i + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+        let u32_at = |i: usize| {
+            payload
+                .get(i..i + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+
+        let answer = match frame.command {
+            Command::Hello => (ErrorCode::Ok, vec![PROTOCOL_VERSION, 0]),
+            Command::Ping => (ErrorCode::Ok, Vec::new()),
+            Command::Reset => match self.engine.reset() {
+                Ok(()) => (ErrorCode::Ok, Vec::new()),
+                Err(e) => self.failed(e, ErrorCode::Error),
+            },
+            Command::Exec => return self.run_code(payload),
+            Command::ExecBegin => match u32_at(0) {
+                Some(total) => {
+                    self.exec_buf = vec![0; total as usize];
+                    (ErrorCode::Ok, Vec::new())
+                }
+                None => (ErrorCode::InvalidFrame, Vec::new()),
+            },
+            Command::ExecData => {
+                let start = u32_at(0).map(|o| o as usize);
+                let chunk = payload.get(4..).unwrap_or_default();
+                match start.and_then(|s| self.exec_buf.get_mut(s..s + chunk.len())) {
+                    Some(dest) => {
+                        dest.copy_from_slice(chunk);
+                        (ErrorCode::Ok, Vec::new())
+                    }
+                    None => (ErrorCode::InvalidFrame, Vec::new()),
+                }
+            }
+            Command::ExecEnd => {
+                let code = std::mem::take(&mut self.exec_buf);
+                return self.run_code(&code);
+            }
+            // The engine doesn't expose its return stack
+            Command::QueryStack => match self.engine.data_stack() {
+                Ok(data) => {
+                    let snapshot = StackSnapshot {
+                        data,
+                        ret: Vec::new(),
+                    };
+                    (ErrorCode::Ok, snapshot.to_payload())
+                }
+                Err(e) => self.failed(e, ErrorCode::Error),
+            },
+            Command::QueryMemory => match u32_at(0).zip(u16_at(4)) {
+                Some((addr, len)) => match self.engine.memory(addr, len as usize) {
+                    Ok(Some(bytes)) => (ErrorCode::Ok, bytes),
+                    Ok(None) => (ErrorCode::Error, Vec::new()),
+                    Err(e) => self.failed(e, ErrorCode::Error),
+                },
+                None => (ErrorCode::Error, Vec::new()),
+            },
+            Command::WriteMemory => {
+                let data = payload.get(4..).unwrap_or_default();
+                match u32_at(0).map(|addr| self.engine.write_memory(addr, data)) {
+                    Some(Ok(Some(()))) => (ErrorCode::Ok, Vec::new()),
+                    Some(Err(e)) => self.failed(e, ErrorCode::Error),
+                    _ => (ErrorCode::Error, Vec::new()),
+                }
+            }
+            Command::QueryWord => match u16_at(0).map(|idx| self.word_payload(idx)) {
+                Some(Ok(Some(data))) => (ErrorCode::Ok, data),
+                Some(Err(e)) => self.failed(e, ErrorCode::Error),
+                _ => (ErrorCode::Error, Vec::new()),
+            },
+            // A running program was stopped in `send_frame`; only a partial
+            // transfer is left to drop
+            Command::Abort => {
+                self.exec_buf.clear();
+                (ErrorCode::Ok, Vec::new())
+            }
+            Command::QueryInfo | Command::QueryTicks => (ErrorCode::Error, Vec::new()),
+            Command::SetBreakpoint | Command::Step | Command::Continue => {
+                self.notify("[engine] breakpoints need a device\n");
+                (ErrorCode::Error, Vec::new())
+            }
+        };
+        Some(answer)
+    }
+}
+
+impl Transport for EngineTransport {
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.stop_running();
+        let answer = self.handle(frame);
+        self.queue_output();
+        if let Some((code, data)) = answer {
+            self.queue(code as u8, &data);
+        }
+        Ok(())
+    }
+
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        if self.pending.is_empty() && self.engine.is_running() {
+            let outcome = self.engine.wait_run(timeout);
+            self.queue_output();
+            let answer = match outcome {
+                Ok(Some(result)) => Some(self.run_result(result)),
+                Ok(None) => None,
+                Err(e) => Some(self.failed(e, ErrorCode::VmError)),
+            };
+            if let Some((code, data)) = answer {
+                self.queue(code as u8, &data);
+            }
+        }
+        self.pending.pop_front().ok_or(V4Error::Timeout)
+    }
+
+    fn define_word(&mut self, name: &str, bytecode: &[u8], _timeout: Duration) -> Result<Response> {
+        self.stop_running();
+        let defined = self.engine.define(name, bytecode)?;
+        self.queue_output();
+        let (error_code, word_indices) = match defined {
+            Ok(idx) => (ErrorCode::Ok, vec![idx]),
+            Err(_) => (ErrorCode::BufferFull, Vec::new()),
+        };
+        let mut data = vec![word_indices.len() as u8];
+        for idx in &word_indices {
+            data.extend_from_slice(&idx.to_le_bytes());
+        }
+        Ok(Response {
+            error_code,
+            word_indices,
+            data,
+            seq: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_and_define() {
+        let mut engine = Engine::new().unwrap();
+        // 7 3 SUB 2 MUL DUP  -> 8 8
+        let code = [0x76, 7, 0x76, 3, 0x11, 0x76, 2, 0x12, 0x01, 0x51];
+        engine.run(&code).unwrap();
+        assert_eq!(engine.data_stack(), [8, 8]);
+
+        // SQ: DUP MUL RET, then 3 SQ
+        let sq = engine.define("SQ", &[0x01, 0x12, 0x51]).unwrap();
+        let [lo, hi] = sq.to_le_bytes();
+        engine
+            .run(&[0x02, 0x02, 0x76, 3, 0x50, lo, hi, 0x51])
+            .unwrap();
+        assert_eq!(engine.data_stack(), [9]);
+        assert_eq!(engine.word(sq).unwrap(), ("SQ", &[0x01, 0x12, 0x51][..]));
+
+        engine.reset().unwrap();
+        assert!(engine.data_stack().is_empty());
+        assert!(matches!(engine.word(sq), Err(V4Error::VmFault { .. })));
+    }
+
+    #[test]
+    fn test_memory_is_shared_with_the_vm() {
+        let mut engine = Engine::new().unwrap();
+        // 0x1234 0x100 STORE16  0x100 LOAD
+        let code = [
+            0x78, 0x34, 0x12, 0x78, 0x00, 0x01, 0x35, 0x78, 0x00, 0x01, 0x30, 0x51,
+        ];
+        engine.run(&code).unwrap();
+        assert_eq!(engine.data_stack(), [0x1234]);
+        assert_eq!(engine.memory(0x100, 2), Some(&[0x34, 0x12][..]));
+
+        assert_eq!(engine.write_memory(0x101, &[0xAB]), Some(()));
+        assert_eq!(engine.memory(0x100, 2), Some(&[0x34, 0xAB][..]));
+        assert_eq!(engine.write_memory(MEMORY_SIZE as u32 - 1, &[0, 0]), None);
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        let mut engine = Engine::new().unwrap();
+        // DROP on an empty stack
+        assert!(engine.run(&[0x02, 0x51]).is_err());
+    }
+
+    #[test]
+    fn test_calls_to_unknown_words_are_refused() {
+        let mut engine = Engine::new().unwrap();
+        let sq = engine.define("SQ", &[0x01, 0x12, 0x51]).unwrap();
+        // BAD calls SQ, then word 200
+        let bad = engine
+            .define("BAD", &[0x50, sq as u8, 0, 0x50, 200, 0, 0x51])
+            .unwrap();
+        let [lo, hi] = bad.to_le_bytes();
+
+        let err = engine.run(&[0x76, 3, 0x50, lo, hi, 0x51]).unwrap_err();
+        let EngineError::Fault(fault) = err else {
+            panic!("{:?}", err);
+        };
+        assert_eq!((fault.word, fault.ip), (bad, 3));
+        assert_eq!(fault.kind, Some(FaultKind::UnknownWord));
+        assert!(engine.data_stack().is_empty(), "ran anyway");
+        assert!(matches!(engine.word(200), Err(V4Error::VmFault { .. })));
+    }
+
+    #[test]
+    fn test_serve_answers_one_reply_per_request() {
+        let requests = [
+            r#"{"op":"define","name":"SQ","code":[1,18,81]}"#,
+            r#"{"op":"run","code":[118,5,80,0,0,81]}"#,
+            r#"{"op":"data_stack"}"#,
+            r#"{"op":"run","code":[80,200,0,81]}"#,
+            r#"{"op":"memory","addr":65535,"len":2}"#,
+            r#"{"op":"launch"}"#,
+        ];
+        let mut output = Vec::new();
+        serve(requests.map(|line| Ok(line.to_string())), &mut output).unwrap();
+
+        let replies: Vec<Reply> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(replies.len(), requests.len());
+        assert_eq!(replies[0], Reply::Index(0));
+        assert_eq!(replies[1], Reply::Done);
+        assert_eq!(replies[2], Reply::Stack(vec![25]));
+        let fault = replies[3].engine_error();
+        assert!(
+            matches!(fault, Some(EngineError::Fault(f)) if f.kind == Some(FaultKind::UnknownWord)),
+            "{:?}",
+            replies[3]
+        );
+        assert_eq!(replies[4], Reply::Memory(None));
+        assert!(matches!(&replies[5], Reply::Error(e) if e.starts_with("Invalid engine request")));
+    }
+}
@@ -1,24 +1,100 @@
+use std::time::Duration;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, V4Error>;
 
+/// Exit status for invalid arguments or configuration (as clap uses)
+pub const EXIT_USAGE: i32 = 2;
+/// Exit status when the serial port, network link or a file failed
+pub const EXIT_IO: i32 = 3;
+/// Exit status when the device didn't answer in time
+pub const EXIT_TIMEOUT: i32 = 4;
+/// Exit status when the device or VM reported an error
+pub const EXIT_DEVICE: i32 = 5;
+/// Exit status when Forth source didn't compile
+pub const EXIT_COMPILE: i32 = 6;
+
 #[derive(Error, Debug)]
 pub enum V4Error {
     #[error("Serial port error: {0}")]
     Serial(#[from] serialport::Error),
 
+    #[error(
+        "Cannot open serial port {path}: {source}{}",
+        .hint.map(|h| format!("\nHint: {}", h)).unwrap_or_default()
+    )]
+    PortOpen {
+        path: String,
+        #[source]
+        source: serialport::Error,
+        hint: Option<&'static str>,
+    },
+
+    #[error("Cannot connect to {addr}: {source}")]
+    TcpConnect {
+        addr: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("WebSocket error: {0}")]
+    WebSocket(String),
+
+    #[error("RFC 2217 error: {0}")]
+    Rfc2217(String),
+
+    #[cfg(feature = "ble")]
+    #[error("Bluetooth error: {0}")]
+    Ble(String),
+
     #[error("Protocol error: {0}")]
     Protocol(String),
 
     #[error("CRC mismatch: expected {expected:#04x}, got {actual:#04x}")]
-    CrcMismatch { expected: u8, actual: u8 },
+    CrcMismatch { expected: u32, actual: u32 },
 
     #[error("Device error: {0}")]
     Device(String),
 
+    /// VM_ERROR from a device that said where the VM stopped
+    #[error("Device error: {context}: {} {place}{}", .fault.reason(), .fault.depth_note())]
+    VmFault {
+        context: String,
+        fault: crate::protocol::VmFault,
+        /// Where that is, e.g. `in word SQ at offset 0x0002` or a source line
+        place: String,
+    },
+
+    #[error("Bootloader error: {0}")]
+    Bootloader(String),
+
+    #[error("Session file error: {0}")]
+    Session(String),
+
+    #[error("Trace file error: {0}")]
+    Trace(String),
+
+    #[error("Language server error: {0}")]
+    Lsp(String),
+
+    #[error("Script failed: {0}")]
+    Script(String),
+
     #[error("Timeout waiting for response")]
     Timeout,
 
+    #[error("Timeout waiting for response while {phase}")]
+    PhaseTimeout { phase: String },
+
+    #[error("Deadline of {limit:?} exceeded while {phase}")]
+    DeadlineExceeded { phase: String, limit: Duration },
+
+    #[error("Interrupted; the program may still be running on the device")]
+    Interrupted,
+
+    #[error("Aborted; the device stopped the program")]
+    Aborted,
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -30,4 +106,89 @@ pub enum V4Error {
 
     #[error("CLI error: {0}")]
     Cli(String),
+
+    /// A failure while running that is none of the kinds above
+    #[error("{0}")]
+    Runtime(String),
+
+    #[error("Config error: {0}")]
+    Config(String),
+
+    #[error("Project error: {0}")]
+    Project(String),
+
+    /// Some devices of a multi-device command failed
+    #[error("{failed} of {total} device(s) failed")]
+    DevicesFailed {
+        failed: usize,
+        total: usize,
+        /// Exit status the failures share, or 1 if they differ
+        exit_code: i32,
+    },
+}
+
+impl V4Error {
+    /// Process exit status for this error, by kind of failure
+    ///
+    /// 2 usage, 3 serial/IO, 4 timeout, 5 device error, 6 compile error,
+    /// 130 interrupted and 1 for anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            V4Error::Cli(_) | V4Error::Config(_) => EXIT_USAGE,
+            V4Error::Serial(_)
+            | V4Error::PortOpen { .. }
+            | V4Error::TcpConnect { .. }
+            | V4Error::WebSocket(_)
+            | V4Error::Rfc2217(_)
+            | V4Error::CrcMismatch { .. }
+            | V4Error::Io(_) => EXIT_IO,
+            #[cfg(feature = "ble")]
+            V4Error::Ble(_) => EXIT_IO,
+            V4Error::Timeout | V4Error::PhaseTimeout { .. } | V4Error::DeadlineExceeded { .. } => {
+                EXIT_TIMEOUT
+            }
+            V4Error::Device(_) | V4Error::VmFault { .. } | V4Error::Bootloader(_) => EXIT_DEVICE,
+            V4Error::Compilation(_) => EXIT_COMPILE,
+            V4Error::Interrupted | V4Error::Aborted => crate::interrupt::EXIT_INTERRUPTED,
+            V4Error::DevicesFailed { exit_code, .. } => *exit_code,
+            V4Error::Protocol(_)
+            | V4Error::Session(_)
+            | V4Error::Trace(_)
+            | V4Error::Lsp(_)
+            | V4Error::Script(_)
+            | V4Error::Repl(_)
+            | V4Error::Project(_)
+            | V4Error::Runtime(_) => 1,
+        }
+    }
+
+    /// Whether the connection itself is gone, e.g. the USB device was unplugged
+    ///
+    /// [`Transport::reconnect`](crate::transport::Transport::reconnect) may
+    /// bring it back.
+    pub fn is_disconnect(&self) -> bool {
+        let io = match self {
+            V4Error::Serial(e) => match &e.kind {
+                serialport::ErrorKind::NoDevice => return true,
+                serialport::ErrorKind::Io(kind) => return is_disconnect_kind(*kind),
+                _ => return false,
+            },
+            V4Error::Io(e) => e,
+            _ => return false,
+        };
+        // EIO is what a tty answers once its device has gone away
+        is_disconnect_kind(io.kind()) || (cfg!(unix) && io.raw_os_error() == Some(5))
+    }
+}
+
+fn is_disconnect_kind(kind: std::io::ErrorKind) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        kind,
+        ErrorKind::BrokenPipe
+            | ErrorKind::NotConnected
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::UnexpectedEof
+    )
 }
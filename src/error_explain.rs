@@ -0,0 +1,185 @@
+//! Causes and fixes for V4-link error codes (`v4 explain`, `--explain`)
+//!
+//! Each response code gets a paragraph in the style of `rustc --explain`,
+//! with a link to the part of the documentation that covers it.
+
+use crate::protocol::ErrorCode;
+use crate::{Result, V4Error};
+use std::fmt;
+
+/// Documentation the explanations link to
+const DOCS: &str = env!("CARGO_PKG_REPOSITORY");
+
+/// What a response code means and what to do about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explanation {
+    pub code: ErrorCode,
+    /// One line, e.g. `the device ran out of buffer space`
+    pub summary: &'static str,
+    /// Causes and fixes, wrapped for the terminal
+    pub text: &'static str,
+    /// README section, appended to [`DOCS`]
+    anchor: &'static str,
+}
+
+impl Explanation {
+    /// Link to the documentation
+    pub fn docs(&self) -> String {
+        format!("{}#{}", DOCS, self.anchor)
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} (0x{:02X}): {}\n",
+            self.code.name(),
+            self.code as u8,
+            self.summary
+        )?;
+        writeln!(f, "{}\n", self.text)?;
+        write!(f, "See {}", self.docs())
+    }
+}
+
+/// Explanation of a response code
+pub fn explain(code: ErrorCode) -> Explanation {
+    let (summary, text, anchor) = match code {
+        ErrorCode::Ok => (
+            "the command succeeded",
+            "OK is not an error. It shows up in an error message when a command got OK\n\
+             where it expected something else, e.g. a debug command sent to firmware that\n\
+             runs programs to completion instead of halting. Check that the firmware\n\
+             supports the feature (`v4 info`).",
+            "response-format",
+        ),
+        ErrorCode::Error => (
+            "the device rejected the command",
+            "The firmware understood the frame but couldn't carry out the command: an\n\
+             unknown command byte (older firmware), a word index or memory address out of\n\
+             range, or a feature the firmware was built without. Run `v4 info` to see what\n\
+             the device supports, and update the firmware (`v4 flash`) if the command is\n\
+             newer than it.",
+            "response-format",
+        ),
+        ErrorCode::InvalidFrame => (
+            "the device received a damaged frame",
+            "The frame failed the device's CRC or length check, so bytes were lost or\n\
+             changed on the way. Common causes are a baud rate that doesn't match the\n\
+             firmware (`--baud`), a long or noisy cable, or another program reading the\n\
+             same port. `--retries N` resends rejected frames; if errors persist, lower the\n\
+             baud rate or enable flow control.",
+            "retrying-lost-frames",
+        ),
+        ErrorCode::BufferFull => (
+            "the device ran out of buffer space",
+            "The program or word definitions don't fit: the receive buffer is smaller than\n\
+             the frame, or the dictionary is full. Make the bytecode smaller (`v4 size`\n\
+             shows the largest words), split the program into several\n\
+             pushes, or `v4 reset` to clear old definitions. Programs over 508 bytes are\n\
+             sent in chunks of that size, so firmware needs a receive buffer of at least\n\
+             512 bytes. `--max-size` catches oversized bytecode at compile time.",
+            "size-budget",
+        ),
+        ErrorCode::VmError => (
+            "the program faulted while running",
+            "The bytecode ran but the VM stopped it: a stack underflow or overflow,\n\
+             division by zero, a call to an undefined word, or memory access out of bounds.\n\
+             Firmware that reports the fault detail names the word and offset; compile with\n\
+             `--source-map` to get a source line instead. Try the program on the host engine\n\
+             (`v4 exec --simulate`) or step through it with the REPL debugger.",
+            "source-maps",
+        ),
+        ErrorCode::Halted => (
+            "the VM stopped at a breakpoint",
+            "The program reached a breakpoint or finished a single step and is waiting.\n\
+             Outside the REPL debugger this means breakpoints from an earlier session are\n\
+             still set; `v4 reset` clears them.",
+            "response-format",
+        ),
+        ErrorCode::Accepted => (
+            "the command is still running",
+            "The device accepted the command and will send its result later. An error\n\
+             naming ACCEPTED means that result never arrived: the program may still be\n\
+             running or loop forever. Raise `--timeout`, or interrupt it with Ctrl-C,\n\
+             which sends ABORT.",
+            "response-format",
+        ),
+    };
+    Explanation {
+        code,
+        summary,
+        text,
+        anchor,
+    }
+}
+
+/// Parse a code given by name (`BUFFER_FULL`, `buffer-full`) or number (`3`, `0x03`)
+pub fn parse_code(text: &str) -> Result<ErrorCode> {
+    let number = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    let name = text.replace('-', "_");
+    number
+        .and_then(ErrorCode::from_u8)
+        .or_else(|| codes().find(|code| code.name().eq_ignore_ascii_case(&name)))
+        .ok_or_else(|| {
+            let names: Vec<&str> = codes().map(|code| code.name()).collect();
+            V4Error::Cli(format!(
+                "Unknown error code: {} (expected one of {})",
+                text,
+                names.join(", ")
+            ))
+        })
+}
+
+/// Response code an error reports, if it came from the device
+///
+/// Device errors end in the code's name, e.g. `Execution failed: BUFFER_FULL`.
+pub fn code_of(error: &V4Error) -> Option<ErrorCode> {
+    let message = match error {
+        V4Error::VmFault { .. } => return Some(ErrorCode::VmError),
+        V4Error::Device(message) | V4Error::Protocol(message) => message,
+        _ => return None,
+    };
+    let last = message.rsplit(": ").next()?;
+    codes().find(|code| *code != ErrorCode::Ok && code.name() == last)
+}
+
+/// Every response code, in numeric order
+fn codes() -> impl Iterator<Item = ErrorCode> {
+    (0..=u8::MAX).map_while(ErrorCode::from_u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_code() {
+        assert_eq!(parse_code("BUFFER_FULL").unwrap(), ErrorCode::BufferFull);
+        assert_eq!(parse_code("buffer-full").unwrap(), ErrorCode::BufferFull);
+        assert_eq!(parse_code("0x04").unwrap(), ErrorCode::VmError);
+        assert_eq!(parse_code("2").unwrap(), ErrorCode::InvalidFrame);
+        let err = parse_code("0x42").unwrap_err().to_string();
+        assert!(err.contains("OK, ERROR, INVALID_FRAME"), "{}", err);
+    }
+
+    #[test]
+    fn test_code_of_device_errors() {
+        let err = V4Error::Device("Execution failed: BUFFER_FULL".to_string());
+        assert_eq!(code_of(&err), Some(ErrorCode::BufferFull));
+        let err = V4Error::Device("/dev/ttyACM0 closed the connection".to_string());
+        assert_eq!(code_of(&err), None);
+        assert_eq!(code_of(&V4Error::Cli("ERROR".to_string())), None);
+    }
+
+    #[test]
+    fn test_explanation_links_docs() {
+        let text = explain(ErrorCode::BufferFull).to_string();
+        assert!(text.starts_with("BUFFER_FULL (0x03): the device ran out of buffer space\n"));
+        assert!(text.ends_with("/v4_cli#size-budget"), "{}", text);
+    }
+}
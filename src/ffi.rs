@@ -0,0 +1,742 @@
+//! Bindings to the V4-front compiler library
+//!
+//! All C declarations live here, once; the rest of the crate uses the safe
+//! [`Compiler`] and [`CompiledBuffer`] types. Everything V4-front allocates
+//! is freed when the owning Rust value is dropped.
+
+use crate::repl::{ShadowedWord, find_shadowed_words};
+use crate::source;
+use std::borrow::Cow;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::path::Path;
+use std::{ptr, slice};
+
+/// Size of the buffer V4-front writes error messages to
+const ERR_CAPACITY: usize = 256;
+
+#[repr(C)]
+struct V4FrontContext {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct V4FrontWord {
+    /// Word name, NUL-terminated
+    name: *mut c_char,
+    code: *mut u8,
+    code_len: u32,
+}
+
+/// Optimization passes, as `v4front_compile_optimized` takes them
+#[cfg(feature = "optimizer")]
+#[repr(C)]
+struct V4FrontOptions {
+    opt_level: c_int,
+    /// Non-zero to inline small words
+    inline_words: c_int,
+    /// Non-zero to turn a call right before `;` into a jump
+    tail_calls: c_int,
+}
+
+/// Compiler output, allocated by V4-front and released with `v4front_free`
+#[repr(C)]
+struct V4FrontBuf {
+    words: *mut V4FrontWord,
+    word_count: c_int,
+    /// Main bytecode
+    data: *mut u8,
+    size: usize,
+}
+
+// Functions return 0 on success and a negative V4-front error code otherwise
+unsafe extern "C" {
+    fn v4front_compile(
+        source: *const c_char,
+        out_buf: *mut V4FrontBuf,
+        err: *mut c_char,
+        err_cap: usize,
+    ) -> c_int;
+    #[cfg(feature = "optimizer")]
+    fn v4front_compile_optimized(
+        source: *const c_char,
+        options: *const V4FrontOptions,
+        out_buf: *mut V4FrontBuf,
+        err: *mut c_char,
+        err_cap: usize,
+    ) -> c_int;
+    fn v4front_save_bytecode(buf: *const V4FrontBuf, filename: *const c_char) -> c_int;
+    fn v4front_free(buf: *mut V4FrontBuf);
+
+    fn v4front_context_create() -> *mut V4FrontContext;
+    fn v4front_context_destroy(ctx: *mut V4FrontContext);
+    fn v4front_context_reset(ctx: *mut V4FrontContext);
+    fn v4front_context_register_word(
+        ctx: *mut V4FrontContext,
+        name: *const c_char,
+        vm_word_idx: c_int,
+    ) -> c_int;
+    fn v4front_compile_with_context(
+        ctx: *mut V4FrontContext,
+        source: *const c_char,
+        out_buf: *mut V4FrontBuf,
+        err: *mut c_char,
+        err_cap: usize,
+    ) -> c_int;
+}
+
+/// Compilation failure reported by V4-front
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub message: String,
+    /// 1-based position in the compiled source, when V4-front reports one
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl CompileError {
+    /// Split a V4-front error string into position and message
+    ///
+    /// Recognizes "3:5: msg", "3: msg", "line 3, column 5: msg", "line 3: msg"
+    /// and "msg at line 3[, column 5]". Anything else is kept as the message.
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        let unplaced = || CompileError {
+            message: text.to_string(),
+            line: None,
+            column: None,
+        };
+
+        if let Some((line, rest)) = take_number(text) {
+            let Some(rest) = rest.strip_prefix(':') else {
+                return unplaced();
+            };
+            let (column, rest) = match take_number(rest) {
+                Some((column, more)) if more.starts_with(':') => (Some(column), &more[1..]),
+                _ => (None, rest),
+            };
+            return CompileError {
+                message: rest.trim().to_string(),
+                line: Some(line),
+                column,
+            };
+        }
+
+        if let Some((line, column, rest)) = strip_prefix_ci(text, "line ").and_then(line_column) {
+            return CompileError {
+                message: rest.trim_start_matches(':').trim().to_string(),
+                line: Some(line),
+                column,
+            };
+        }
+
+        let lower = text.to_ascii_lowercase();
+        if let Some(at) = lower.rfind(" at line ")
+            && let Some((line, column, rest)) = line_column(&text[at + " at line ".len()..])
+            && rest.trim().is_empty()
+        {
+            return CompileError {
+                message: text[..at].trim().to_string(),
+                line: Some(line),
+                column,
+            };
+        }
+
+        unplaced()
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "line {}, column {}: {}", line, column, self.message)
+            }
+            (Some(line), None) => write!(f, "line {}: {}", line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Leading decimal number and the text after it
+fn take_number(text: &str) -> Option<(usize, &str)> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+fn strip_prefix_ci<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
+
+/// "3, column 5..." / "3 col 5..." / "3..." after the word "line"
+fn line_column(text: &str) -> Option<(usize, Option<usize>, &str)> {
+    let (line, rest) = take_number(text)?;
+    let after_sep = rest.trim_start_matches([',', ' ']);
+    let column = strip_prefix_ci(after_sep, "column ")
+        .or_else(|| strip_prefix_ci(after_sep, "col "))
+        .and_then(take_number);
+    Some(match column {
+        Some((column, rest)) => (line, Some(column), rest),
+        None => (line, None, rest),
+    })
+}
+
+/// Highest optimization level V4-front knows
+pub const MAX_OPT_LEVEL: u8 = 2;
+
+/// Optimization passes for [`Compiler::compile_optimized`]
+///
+/// The level and switches are passed through to V4-front, which decides
+/// what each level does. Tail calls are opt-in at any level because they
+/// drop the caller from the return stack, which the debugger shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Optimization {
+    pub level: u8,
+    pub inline: bool,
+    pub tail_call: bool,
+}
+
+impl Default for Optimization {
+    fn default() -> Self {
+        Self {
+            level: 0,
+            inline: true,
+            tail_call: false,
+        }
+    }
+}
+
+impl Optimization {
+    /// Whether any pass runs, i.e. the output may differ from a plain compile
+    pub fn is_enabled(&self) -> bool {
+        self.level > 0 || self.tail_call
+    }
+
+    #[cfg(feature = "optimizer")]
+    fn to_c(self) -> V4FrontOptions {
+        V4FrontOptions {
+            opt_level: c_int::from(self.level.min(MAX_OPT_LEVEL)),
+            inline_words: c_int::from(self.inline && self.level >= 2),
+            tail_calls: c_int::from(self.tail_call),
+        }
+    }
+}
+
+/// Output of a successful compile, freed on drop
+///
+/// Words and bytecode are borrowed from the buffer, so they can't outlive it.
+pub struct CompiledBuffer {
+    buf: V4FrontBuf,
+}
+
+/// Word definition borrowed from a CompiledBuffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledWord<'a> {
+    pub name: Cow<'a, str>,
+    pub code: &'a [u8],
+}
+
+impl CompiledBuffer {
+    /// Buffer for V4-front to fill; freeing it is fine even if it stays empty
+    fn empty() -> Self {
+        Self {
+            buf: V4FrontBuf {
+                words: ptr::null_mut(),
+                word_count: 0,
+                data: ptr::null_mut(),
+                size: 0,
+            },
+        }
+    }
+
+    /// Word definitions in definition order
+    pub fn words(&self) -> impl Iterator<Item = CompiledWord<'_>> {
+        let words = match self.buf.words.is_null() || self.buf.word_count <= 0 {
+            true => &[][..],
+            false => unsafe { slice::from_raw_parts(self.buf.words, self.buf.word_count as usize) },
+        };
+        words.iter().map(|word| CompiledWord {
+            name: match word.name.is_null() {
+                true => Cow::Borrowed(""),
+                false => unsafe { CStr::from_ptr(word.name) }.to_string_lossy(),
+            },
+            code: raw_bytes(word.code, word.code_len as usize),
+        })
+    }
+
+    /// Names of the word definitions, in definition order
+    pub fn word_names(&self) -> Vec<String> {
+        self.words().map(|word| word.name.into_owned()).collect()
+    }
+
+    /// Bytecode size of the words and the main code together
+    pub fn code_size(&self) -> usize {
+        self.words().map(|word| word.code.len()).sum::<usize>() + self.bytecode().len()
+    }
+
+    /// Main bytecode, run after the words are defined
+    pub fn bytecode(&self) -> &[u8] {
+        raw_bytes(self.buf.data, self.buf.size)
+    }
+
+    /// Write the buffer as a .v4b file
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let path_str = path.to_str().ok_or("Invalid path")?;
+        let c_path = CString::new(path_str).map_err(|_| "Invalid path string")?;
+
+        let result = unsafe { v4front_save_bytecode(&self.buf, c_path.as_ptr()) };
+
+        if result != 0 {
+            Err(format!("Failed to save bytecode (error code {})", result))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for CompiledBuffer {
+    fn drop(&mut self) {
+        unsafe { v4front_free(&mut self.buf) };
+    }
+}
+
+/// Bytes V4-front allocated; empty for a null pointer
+fn raw_bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+}
+
+/// Message V4-front left in `err_buf`, or one naming the error code
+fn error_text(err_buf: &[u8], code: c_int) -> String {
+    let message = CStr::from_bytes_until_nul(err_buf)
+        .map(|text| text.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if message.is_empty() {
+        format!("Compilation failed (error code {})", code)
+    } else {
+        message
+    }
+}
+
+/// Compiled word definition
+#[derive(Debug, Clone)]
+pub struct WordDef {
+    pub name: String,
+    pub bytecode: Vec<u8>,
+}
+
+/// Compilation result
+#[derive(Debug)]
+pub struct CompileResult {
+    pub words: Vec<WordDef>,
+    pub bytecode: Vec<u8>,
+}
+
+impl CompileResult {
+    /// Find word names defined more than once in this compilation unit
+    pub fn shadowed_words(&self) -> Vec<ShadowedWord> {
+        find_shadowed_words(self.words.iter().map(|w| w.name.as_str()))
+    }
+}
+
+/// V4-front compiler
+///
+/// Holds a context that remembers the words registered with device indices,
+/// as the REPL and `v4 exec` need; [`compile_standalone`](Self::compile_standalone)
+/// compiles whole programs without one. All of them resolve `SYS_*` constants
+/// first (see [`source::resolve_syscalls`]).
+pub struct Compiler {
+    ctx: *mut V4FrontContext,
+    next_word_id: i32,
+    /// Names registered with device indices, in registration order
+    words: Vec<String>,
+}
+
+impl Compiler {
+    /// Create a new compiler context
+    pub fn new() -> Result<Self, String> {
+        unsafe {
+            let ctx = v4front_context_create();
+            if ctx.is_null() {
+                return Err("Failed to create compiler context".to_string());
+            }
+
+            Ok(Compiler {
+                ctx,
+                next_word_id: 0,
+                words: Vec::new(),
+            })
+        }
+    }
+
+    /// Compile a whole program without a context, e.g. for a .v4b file
+    ///
+    /// Nothing registered with a [`Compiler`] is known to it.
+    pub fn compile_standalone(source: &str) -> Result<CompiledBuffer, CompileError> {
+        let source = source::resolve_syscalls(source, |_| false);
+        let c_source =
+            CString::new(&*source).map_err(|_| CompileError::parse("Invalid source string"))?;
+        let mut compiled = CompiledBuffer::empty();
+        let mut err_buf = [0u8; ERR_CAPACITY];
+        let result = unsafe {
+            v4front_compile(
+                c_source.as_ptr(),
+                &mut compiled.buf,
+                err_buf.as_mut_ptr() as *mut c_char,
+                err_buf.len(),
+            )
+        };
+        match result {
+            0 => Ok(compiled),
+            code => Err(CompileError::parse(&error_text(&err_buf, code))),
+        }
+    }
+
+    /// Like [`compile_standalone`](Self::compile_standalone), running the
+    /// optimization passes in `optimization`
+    ///
+    /// Needs the `optimizer` feature, which links V4-front's
+    /// `v4front_compile_optimized`; without it only a plain compile works.
+    #[cfg(feature = "optimizer")]
+    pub fn compile_optimized(
+        source: &str,
+        optimization: &Optimization,
+    ) -> Result<CompiledBuffer, CompileError> {
+        if !optimization.is_enabled() {
+            return Self::compile_standalone(source);
+        }
+        let source = source::resolve_syscalls(source, |_| false);
+        let c_source =
+            CString::new(&*source).map_err(|_| CompileError::parse("Invalid source string"))?;
+        let options = optimization.to_c();
+        let mut compiled = CompiledBuffer::empty();
+        let mut err_buf = [0u8; ERR_CAPACITY];
+        let result = unsafe {
+            v4front_compile_optimized(
+                c_source.as_ptr(),
+                &options,
+                &mut compiled.buf,
+                err_buf.as_mut_ptr() as *mut c_char,
+                err_buf.len(),
+            )
+        };
+        match result {
+            0 => Ok(compiled),
+            code => Err(CompileError::parse(&error_text(&err_buf, code))),
+        }
+    }
+
+    /// Without the `optimizer` feature, any enabled pass is an error
+    #[cfg(not(feature = "optimizer"))]
+    pub fn compile_optimized(
+        source: &str,
+        optimization: &Optimization,
+    ) -> Result<CompiledBuffer, CompileError> {
+        if optimization.is_enabled() {
+            return Err(CompileError::parse(
+                "Optimization needs a V4-front with v4front_compile_optimized; \
+                 rebuild v4 with --features optimizer",
+            ));
+        }
+        Self::compile_standalone(source)
+    }
+
+    /// Compile Forth source code
+    ///
+    /// Returns compiled bytecode and any word definitions. The words are not
+    /// callable from later source until their device indices are registered
+    /// with [`register_word_indices`](Self::register_word_indices).
+    pub fn compile(&mut self, source: &str) -> Result<CompileResult, String> {
+        let source = source::resolve_syscalls(source, |name| {
+            self.words
+                .iter()
+                .any(|word| word.eq_ignore_ascii_case(name))
+        });
+        let c_source = CString::new(&*source).map_err(|e| e.to_string())?;
+        let mut compiled = CompiledBuffer::empty();
+        let mut err_buf = [0u8; ERR_CAPACITY];
+        let result = unsafe {
+            v4front_compile_with_context(
+                self.ctx,
+                c_source.as_ptr(),
+                &mut compiled.buf,
+                err_buf.as_mut_ptr() as *mut c_char,
+                err_buf.len(),
+            )
+        };
+        if result != 0 {
+            return Err(error_text(&err_buf, result));
+        }
+
+        Ok(CompileResult {
+            words: compiled
+                .words()
+                .map(|word| WordDef {
+                    name: word.name.into_owned(),
+                    bytecode: word.code.to_vec(),
+                })
+                .collect(),
+            bytecode: compiled.bytecode().to_vec(),
+        })
+    }
+
+    /// Reset compiler context (clear all registered words)
+    pub fn reset(&mut self) {
+        unsafe {
+            v4front_context_reset(self.ctx);
+            self.next_word_id = 0;
+        }
+        self.words.clear();
+    }
+
+    /// Names of the words registered since the last reset
+    pub fn word_names(&self) -> impl Iterator<Item = &str> {
+        self.words.iter().map(String::as_str)
+    }
+
+    /// Register a word index from device
+    ///
+    /// Called after device executes bytecode and returns word index
+    pub fn register_word_index(&mut self, name: &str, vm_word_idx: i32) -> Result<(), String> {
+        unsafe {
+            let c_name = CString::new(name).map_err(|e| e.to_string())?;
+            let result = v4front_context_register_word(self.ctx, c_name.as_ptr(), vm_word_idx);
+            if result < 0 {
+                return Err(format!(
+                    "Failed to register word '{}' with index {}",
+                    name, vm_word_idx
+                ));
+            }
+            if !self.words.iter().any(|w| w == name) {
+                self.words.push(name.to_string());
+            }
+            Ok(())
+        }
+    }
+
+    /// Register device-assigned indices for a batch of word definitions
+    ///
+    /// The device assigns indices in the order definitions arrive, so `names`
+    /// (in definition order) pair up one-to-one with the returned `indices`.
+    /// This holds whether words are sent one per EXEC (REPL, `v4 exec`) or
+    /// all in a single EXEC (`v4 push`).
+    pub fn register_word_indices(&mut self, names: &[&str], indices: &[u16]) -> Result<(), String> {
+        if names.len() != indices.len() {
+            return Err(format!(
+                "Device returned {} word index(es) for {} definition(s)",
+                indices.len(),
+                names.len()
+            ));
+        }
+
+        for (name, &idx) in names.iter().zip(indices) {
+            self.register_word_index(name, idx as i32)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Compiler {
+    fn drop(&mut self) {
+        unsafe {
+            v4front_context_destroy(self.ctx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(text: &str) -> (Option<usize>, Option<usize>, String) {
+        let error = CompileError::parse(text);
+        (error.line, error.column, error.message)
+    }
+
+    #[test]
+    fn test_compile_error_positions() {
+        let placed =
+            |line, column: Option<usize>| (Some(line), column, "Unknown word: FOO".to_string());
+        assert_eq!(parsed("3:5: Unknown word: FOO"), placed(3, Some(5)));
+        assert_eq!(parsed("3: Unknown word: FOO"), placed(3, None));
+        assert_eq!(
+            parsed("line 3, column 5: Unknown word: FOO"),
+            placed(3, Some(5))
+        );
+        assert_eq!(
+            parsed("Line 3 col 5: Unknown word: FOO"),
+            placed(3, Some(5))
+        );
+        assert_eq!(parsed("Unknown word: FOO at line 3"), placed(3, None));
+        assert_eq!(
+            parsed("Unknown word: FOO"),
+            (None, None, "Unknown word: FOO".to_string())
+        );
+        assert_eq!(parsed("42 is too big").0, None);
+    }
+
+    #[test]
+    fn test_compiled_buffer_views() {
+        let compiled =
+            Compiler::compile_standalone(": SQ DUP * ;\n: CUBE DUP SQ * ;\n3 CUBE\n").unwrap();
+        assert_eq!(compiled.word_names(), ["SQ", "CUBE"]);
+        assert!(compiled.words().all(|word| !word.code.is_empty()));
+        assert!(!compiled.bytecode().is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.v4b");
+        compiled.save(&path).unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(b"V4BC"));
+
+        let error = Compiler::compile_standalone("1 NOPE").err().unwrap();
+        assert!(error.message.contains("NOPE"), "{}", error.message);
+    }
+
+    #[cfg(feature = "optimizer")]
+    #[test]
+    fn test_optimization_flags() {
+        let options = Optimization {
+            level: 2,
+            inline: false,
+            tail_call: true,
+        }
+        .to_c();
+        assert_eq!(
+            (options.opt_level, options.inline_words, options.tail_calls),
+            (2, 0, 1)
+        );
+        let options = Optimization {
+            level: 1,
+            ..Optimization::default()
+        }
+        .to_c();
+        assert_eq!(options.inline_words, 0);
+        assert!(!Optimization::default().is_enabled());
+
+        let source = ": SQ DUP * ;\n: CUBE DUP SQ * ;\n3 CUBE\n";
+        let plain = Compiler::compile_standalone(source).unwrap();
+        let optimized = Compiler::compile_optimized(
+            source,
+            &Optimization {
+                level: MAX_OPT_LEVEL,
+                inline: true,
+                tail_call: true,
+            },
+        )
+        .unwrap();
+        assert!(optimized.code_size() <= plain.code_size());
+    }
+
+    #[cfg(not(feature = "optimizer"))]
+    #[test]
+    fn test_optimization_needs_the_feature() {
+        assert!(!Optimization::default().is_enabled());
+        let plain = Compiler::compile_optimized("1 2 +", &Optimization::default()).unwrap();
+        assert!(plain.code_size() > 0);
+
+        let tail_call = Optimization {
+            tail_call: true,
+            ..Optimization::default()
+        };
+        let error = Compiler::compile_optimized("1 2 +", &tail_call)
+            .err()
+            .unwrap();
+        assert!(
+            error.message.contains("--features optimizer"),
+            "{}",
+            error.message
+        );
+    }
+
+    #[test]
+    fn test_compiler_creation() {
+        let compiler = Compiler::new();
+        assert!(compiler.is_ok());
+    }
+
+    #[test]
+    fn test_basic_compilation() {
+        let mut compiler = Compiler::new().unwrap();
+        let result = compiler.compile("1 2 +");
+        assert!(result.is_ok());
+        let compiled = result.unwrap();
+        assert!(!compiled.bytecode.is_empty());
+        assert!(compiled.words.is_empty());
+    }
+
+    #[test]
+    fn test_word_definition() {
+        let mut compiler = Compiler::new().unwrap();
+        let result = compiler.compile(": DOUBLE 2 * ;");
+        assert!(result.is_ok());
+        let compiled = result.unwrap();
+        assert_eq!(compiled.words.len(), 1);
+        assert_eq!(compiled.words[0].name, "DOUBLE");
+    }
+
+    #[test]
+    fn test_persistent_words() {
+        let mut compiler = Compiler::new().unwrap();
+
+        // Define word
+        let result1 = compiler.compile(": SQUARE DUP * ;");
+        assert!(result1.is_ok());
+        let compiled1 = result1.unwrap();
+        assert_eq!(compiled1.words.len(), 1);
+        assert_eq!(compiled1.words[0].name, "SQUARE");
+
+        // Simulate device registering the word at index 0
+        compiler.register_word_index("SQUARE", 0).unwrap();
+
+        // Now we can use the word
+        let result2 = compiler.compile("5 SQUARE");
+        assert!(result2.is_ok());
+    }
+
+    #[test]
+    fn test_register_word_indices() {
+        let mut compiler = Compiler::new().unwrap();
+        compiler.compile(": A 1 ; : B 2 ;").unwrap();
+
+        compiler
+            .register_word_indices(&["A", "B"], &[4, 5])
+            .unwrap();
+        assert!(compiler.compile("A B").is_ok());
+        assert_eq!(compiler.word_names().collect::<Vec<_>>(), ["A", "B"]);
+
+        compiler.reset();
+        assert_eq!(compiler.word_names().count(), 0);
+    }
+
+    #[test]
+    fn test_register_word_indices_count_mismatch() {
+        let mut compiler = Compiler::new().unwrap();
+        assert!(compiler.register_word_indices(&["A"], &[]).is_err());
+        assert!(compiler.register_word_indices(&["A"], &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let mut compiler = Compiler::new().unwrap();
+        let result = compiler.compile("UNKNOWN_WORD");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut compiler = Compiler::new().unwrap();
+
+        compiler.compile(": TEST 42 ;").unwrap();
+        compiler.reset();
+
+        // After reset, TEST should be unknown
+        let result = compiler.compile("TEST");
+        assert!(result.is_err());
+    }
+}
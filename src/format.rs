@@ -0,0 +1,409 @@
+//! Number formats for memory and stack output (`--output-format`, `.format`)
+//!
+//! Memory is grouped into 8-, 16- or 32-bit words of either endianness and
+//! shown as hex, unsigned or signed decimal, or binary, in rows under their
+//! address. The `c` and `python` styles print an array literal instead, for
+//! pasting into other tools. Stack cells are always 32-bit.
+
+use crate::commands::dump;
+use crate::protocol::{MemoryDump, StackSnapshot};
+use std::fmt::{self, Write};
+
+/// How values are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Style {
+    #[default]
+    Hex,
+    Dec,
+    Signed,
+    Bin,
+    /// C array initializer
+    C,
+    /// Python list
+    Python,
+}
+
+/// Byte order of multi-byte words
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Output format for memory and stack queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFormat {
+    pub style: Style,
+    /// Bytes per memory word: 1, 2 or 4
+    pub word_size: usize,
+    pub endian: Endian,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self {
+            style: Style::Hex,
+            word_size: 1,
+            endian: Endian::Little,
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let style = match self.style {
+            Style::Hex => "hex",
+            Style::Dec => "dec",
+            Style::Signed => "signed",
+            Style::Bin => "bin",
+            Style::C => "c",
+            Style::Python => "python",
+        };
+        let endian = match self.endian {
+            Endian::Little => "little-endian",
+            Endian::Big => "big-endian",
+        };
+        write!(f, "{}, {}-bit words, {}", style, self.word_size * 8, endian)
+    }
+}
+
+/// Parse a style: hex, dec, signed, bin, c or python
+pub fn parse_style(value: &str) -> Result<Style, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "hex" => Ok(Style::Hex),
+        "dec" => Ok(Style::Dec),
+        "signed" => Ok(Style::Signed),
+        "bin" | "binary" => Ok(Style::Bin),
+        "c" => Ok(Style::C),
+        "python" | "py" => Ok(Style::Python),
+        _ => Err(format!(
+            "invalid output format '{}' (expected hex, dec, signed, bin, c or python)",
+            value
+        )),
+    }
+}
+
+/// Parse a word size in bits (8, 16, 32) into bytes
+pub fn parse_word_size(value: &str) -> Result<usize, String> {
+    match value {
+        "8" => Ok(1),
+        "16" => Ok(2),
+        "32" => Ok(4),
+        _ => Err(format!(
+            "invalid word size '{}' (expected 8, 16 or 32)",
+            value
+        )),
+    }
+}
+
+/// Parse an endianness (little, big, le, be)
+pub fn parse_endian(value: &str) -> Result<Endian, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "little" | "le" => Ok(Endian::Little),
+        "big" | "be" => Ok(Endian::Big),
+        _ => Err(format!(
+            "invalid endianness '{}' (expected little or big)",
+            value
+        )),
+    }
+}
+
+impl OutputFormat {
+    /// Whether this prints an array literal rather than rows or lines
+    pub fn is_array(&self) -> bool {
+        matches!(self.style, Style::C | Style::Python)
+    }
+
+    /// One value of `bytes` in this format's style, decimals padded to the
+    /// word size's width if `pad`
+    fn value(&self, value: u32, bytes: usize, pad: bool) -> String {
+        let bits = bytes * 8;
+        let text = match self.style {
+            Style::Hex => format!("{:0w$X}", value, w = bytes * 2),
+            Style::C | Style::Python => format!("0x{:0w$X}", value, w = bytes * 2),
+            Style::Dec => value.to_string(),
+            Style::Signed => sign_extend(value, bits).to_string(),
+            Style::Bin => format!("{:0w$b}", value, w = bits),
+        };
+        let width = dec_width(self.word_size * 8);
+        match (pad, self.style) {
+            (true, Style::Dec) => format!("{:>w$}", text, w = width),
+            (true, Style::Signed) => format!("{:>w$}", text, w = width + 1),
+            _ => text,
+        }
+    }
+
+    /// Group bytes into words; a partial last word takes the bytes there are
+    fn words<'a>(&self, bytes: &'a [u8]) -> impl Iterator<Item = (u32, usize)> + 'a {
+        let endian = self.endian;
+        bytes.chunks(self.word_size).map(move |chunk| {
+            let value = match endian {
+                Endian::Little => chunk.iter().rev().fold(0, |v, &b| v << 8 | b as u32),
+                Endian::Big => chunk.iter().fold(0, |v, &b| v << 8 | b as u32),
+            };
+            (value, chunk.len())
+        })
+    }
+}
+
+fn sign_extend(value: u32, bits: usize) -> i32 {
+    let shift = 32 - bits as u32;
+    ((value << shift) as i32) >> shift
+}
+
+/// Digits of the largest unsigned value of `bits`
+fn dec_width(bits: usize) -> usize {
+    match bits {
+        8 => 3,
+        16 => 5,
+        _ => 10,
+    }
+}
+
+/// C element type for words of `bytes`
+fn c_type(bytes: usize) -> &'static str {
+    match bytes {
+        1 => "uint8_t",
+        2 => "uint16_t",
+        _ => "uint32_t",
+    }
+}
+
+/// Memory in the given format
+///
+/// Hex bytes are the classic hexdump of [`dump::hexdump`].
+pub fn memory(dump: &MemoryDump, format: &OutputFormat) -> String {
+    if format.style == Style::Hex && format.word_size == 1 {
+        return dump::hexdump(dump);
+    }
+    let row_bytes = match format.style {
+        Style::Bin => 4,
+        _ => 16,
+    };
+    let name = format!("mem_{:x}", dump.addr);
+    let mut out = String::new();
+    match format.style {
+        Style::C => {
+            let count = dump.bytes.len().div_ceil(format.word_size);
+            let _ = writeln!(
+                out,
+                "const {} {}[{}] = {{",
+                c_type(format.word_size),
+                name,
+                count
+            );
+        }
+        Style::Python => {
+            let _ = writeln!(out, "{} = [", name);
+        }
+        _ => {}
+    }
+
+    for (i, row) in dump.bytes.chunks(row_bytes).enumerate() {
+        let values: Vec<String> = format
+            .words(row)
+            .map(|(value, bytes)| format.value(value, bytes, true))
+            .collect();
+        match format.style {
+            Style::C | Style::Python => {
+                let _ = writeln!(out, "    {},", values.join(", "));
+            }
+            _ => {
+                let addr = dump.addr.wrapping_add((i * row_bytes) as u32);
+                let _ = write!(
+                    out,
+                    "{}  {}",
+                    crate::ui::address(format!("{:08X}", addr)),
+                    values.join(" ")
+                );
+                if format.style == Style::Hex {
+                    // Pad a short last row so the text column lines up
+                    let full = (row_bytes / format.word_size) * (format.word_size * 2 + 1) - 1;
+                    let width = values.join(" ").len();
+                    let ascii: String = row.iter().map(|&b| dump::printable(b)).collect();
+                    let _ = write!(
+                        out,
+                        "{:pad$}  |{}|",
+                        "",
+                        crate::ui::ascii(ascii),
+                        pad = full - width
+                    );
+                }
+                out.push('\n');
+            }
+        }
+    }
+
+    match format.style {
+        Style::C => out.push_str("};\n"),
+        Style::Python => out.push_str("]\n"),
+        _ => {}
+    }
+    out
+}
+
+/// Data and return stacks in the given format
+///
+/// Cells are 32-bit, so the word size and endianness don't apply. The
+/// default shows each data cell in hex and signed decimal.
+pub fn stack(stack: &StackSnapshot, format: &OutputFormat) -> String {
+    let data: Vec<u32> = stack.data.iter().map(|&cell| cell as u32).collect();
+    let mut out = String::new();
+    match format.style {
+        Style::C => {
+            let _ = writeln!(
+                out,
+                "const int32_t data_stack[{}] = {{{}}};",
+                data.len(),
+                list(&data, &OutputFormat::signed_cells())
+            );
+            let _ = writeln!(
+                out,
+                "const uint32_t return_stack[{}] = {{{}}};",
+                stack.ret.len(),
+                list(&stack.ret, format)
+            );
+        }
+        Style::Python => {
+            let _ = writeln!(
+                out,
+                "data_stack = [{}]",
+                list(&data, &OutputFormat::signed_cells())
+            );
+            let _ = writeln!(out, "return_stack = [{}]", list(&stack.ret, format));
+        }
+        _ => {
+            let _ = writeln!(out, "Data Stack (depth: {} / 256):", data.len());
+            cells(&mut out, &data, format, true);
+            let _ = writeln!(out, "\nReturn Stack (depth: {} / 64):", stack.ret.len());
+            cells(&mut out, &stack.ret, format, false);
+        }
+    }
+    out
+}
+
+impl OutputFormat {
+    /// Data stack cells in C and Python lists, as the VM sees them
+    fn signed_cells() -> Self {
+        Self {
+            style: Style::Signed,
+            ..Self::default()
+        }
+    }
+}
+
+/// Comma-separated 32-bit values
+fn list(values: &[u32], format: &OutputFormat) -> String {
+    let values: Vec<String> = values.iter().map(|&v| format.value(v, 4, false)).collect();
+    values.join(", ")
+}
+
+/// One `[i]: value` line per stack cell
+fn cells(out: &mut String, values: &[u32], format: &OutputFormat, signed_too: bool) {
+    if values.is_empty() {
+        out.push_str("  <empty>\n");
+    }
+    for (i, &value) in values.iter().enumerate() {
+        let _ = match format.style {
+            Style::Hex if signed_too => {
+                writeln!(out, "  [{}]: 0x{:08X} ({})", i, value, value as i32)
+            }
+            Style::Hex => writeln!(out, "  [{}]: 0x{:08X}", i, value),
+            _ => writeln!(out, "  [{}]: {}", i, format.value(value, 4, false)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(style: Style, word_size: usize, endian: Endian) -> OutputFormat {
+        OutputFormat {
+            style,
+            word_size,
+            endian,
+        }
+    }
+
+    fn plain(text: String) -> String {
+        console::strip_ansi_codes(&text).into_owned()
+    }
+
+    fn dump() -> MemoryDump {
+        MemoryDump {
+            addr: 0x2000,
+            bytes: vec![0x01, 0x02, 0xFF, 0xFF, 0x41],
+        }
+    }
+
+    #[test]
+    fn test_memory_words_and_endianness() {
+        let text = plain(memory(&dump(), &format(Style::Hex, 2, Endian::Little)));
+        assert!(text.starts_with("00002000  0201 FFFF 41 "), "{}", text);
+        assert!(text.ends_with("|....A|\n"));
+
+        let text = plain(memory(&dump(), &format(Style::Hex, 2, Endian::Big)));
+        assert!(text.starts_with("00002000  0102 FFFF 41"));
+
+        let text = plain(memory(&dump(), &format(Style::Signed, 2, Endian::Little)));
+        assert_eq!(text, "00002000     513     -1     65\n");
+
+        let text = plain(memory(&dump(), &format(Style::Dec, 4, Endian::Big)));
+        assert_eq!(text, "00002000    16973823         65\n");
+    }
+
+    #[test]
+    fn test_memory_as_array() {
+        let text = memory(&dump(), &format(Style::C, 2, Endian::Little));
+        assert_eq!(
+            text,
+            "const uint16_t mem_2000[3] = {\n    0x0201, 0xFFFF, 0x41,\n};\n"
+        );
+        let text = memory(&dump(), &format(Style::Python, 1, Endian::Little));
+        assert_eq!(text, "mem_2000 = [\n    0x01, 0x02, 0xFF, 0xFF, 0x41,\n]\n");
+    }
+
+    #[test]
+    fn test_binary_rows() {
+        let text = plain(memory(&dump(), &format(Style::Bin, 1, Endian::Little)));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "00002000  00000001 00000010 11111111 11111111",
+                "00002004  01000001"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_formats() {
+        let snapshot = StackSnapshot {
+            data: vec![5, -1],
+            ret: vec![0x1234],
+        };
+        let text = stack(&snapshot, &OutputFormat::default());
+        assert!(text.contains("  [1]: 0xFFFFFFFF (-1)\n"));
+        assert!(text.contains("  [0]: 0x00001234\n"));
+
+        let text = stack(&snapshot, &format(Style::Dec, 1, Endian::Little));
+        assert!(text.contains("  [1]: 4294967295\n"));
+
+        let text = stack(&snapshot, &format(Style::C, 1, Endian::Little));
+        assert_eq!(
+            text,
+            "const int32_t data_stack[2] = {5, -1};\nconst uint32_t return_stack[1] = {0x00001234};\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(parse_style("Binary"), Ok(Style::Bin));
+        assert!(parse_style("octal").is_err());
+        assert_eq!(parse_word_size("16"), Ok(2));
+        assert!(parse_word_size("64").is_err());
+        assert_eq!(parse_endian("be"), Ok(Endian::Big));
+    }
+}
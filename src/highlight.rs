@@ -0,0 +1,200 @@
+//! Syntax highlighting of the REPL input line
+//!
+//! Numbers, strings, comments, control words and known words get their own
+//! colors; a word neither the compiler nor the core word list knows is shown
+//! in red before the line is even sent. Colors follow `--color`/`NO_COLOR`.
+
+use crate::commands::lsp::CORE_WORDS;
+use console::style;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Helper, Result};
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Words that open, close or split definitions and control structures
+const CONTROL_WORDS: &[&str] = &[
+    ":", ";", "IF", "ELSE", "THEN", "BEGIN", "UNTIL", "AGAIN", "WHILE", "REPEAT", "DO", "?DO",
+    "LOOP", "+LOOP", "EXIT", "RECURSE",
+];
+
+/// rustyline helper for the REPL: highlights input as it is typed
+#[derive(Debug, Default)]
+pub struct ReplHelper {
+    /// Words defined on the device, uppercased
+    words: HashSet<String>,
+}
+
+impl ReplHelper {
+    /// Replace the words known besides the core ones
+    pub fn set_words<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        self.words = names.into_iter().map(str::to_ascii_uppercase).collect();
+    }
+
+    fn is_known(&self, upper: &str) -> bool {
+        self.words.contains(upper) || CORE_WORDS.iter().any(|(name, _)| *name == upper)
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if !console::colors_enabled() {
+            return Cow::Borrowed(line);
+        }
+        Cow::Owned(highlight(line, |word| self.is_known(word)))
+    }
+
+    /// Recolor on every keystroke; a word changes class as it is typed
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        console::colors_enabled()
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Validator for ReplHelper {
+    fn validate(
+        &self,
+        _ctx: &mut rustyline::validate::ValidationContext,
+    ) -> Result<rustyline::validate::ValidationResult> {
+        Ok(rustyline::validate::ValidationResult::Valid(None))
+    }
+}
+
+impl Helper for ReplHelper {}
+
+/// Color one line of Forth; `is_known` gets uppercased word names
+///
+/// Whitespace is kept as is, so the result lines up with the input.
+pub fn highlight(line: &str, is_known: impl Fn(&str) -> bool) -> String {
+    let mut out = String::with_capacity(line.len() * 2);
+    // Words defined earlier on the same line count as known
+    let mut defined: Vec<String> = Vec::new();
+    let mut naming = false;
+    let mut rest = line;
+
+    while !rest.is_empty() {
+        let start = rest
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(rest.len());
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let token = &rest[..end];
+        let upper = token.to_ascii_uppercase();
+
+        if token == "\\" {
+            out.push_str(&style(rest).dim().to_string());
+            break;
+        }
+        if token == "(" {
+            let close = rest.find(')').map_or(rest.len(), |i| i + 1);
+            out.push_str(&style(&rest[..close]).dim().to_string());
+            rest = &rest[close..];
+            continue;
+        }
+        if is_string_word(token) {
+            // The word, one space, then the text up to the closing quote
+            let text_end = rest[end..]
+                .get(1..)
+                .and_then(|text| text.find('"'))
+                .map_or(rest.len(), |i| end + i + 2);
+            out.push_str(&style(&rest[..text_end]).green().to_string());
+            rest = &rest[text_end..];
+            continue;
+        }
+
+        let styled = if naming {
+            naming = false;
+            defined.push(upper);
+            style(token).bold().to_string()
+        } else if CONTROL_WORDS.contains(&upper.as_str()) {
+            naming = upper == ":";
+            style(token).magenta().bold().to_string()
+        } else if is_number(token) {
+            style(token).yellow().to_string()
+        } else if is_known(&upper) || defined.contains(&upper) {
+            style(token).cyan().to_string()
+        } else {
+            style(token).red().to_string()
+        };
+        out.push_str(&styled);
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// `."`, `S"`, `ABORT"` and the like, which take text up to a `"`
+pub(crate) fn is_string_word(token: &str) -> bool {
+    token.len() > 1 && token.ends_with('"') && !token[..token.len() - 1].contains('"')
+}
+
+/// Decimal, `0x` hex or `0b` binary literal, optionally negative
+fn is_number(token: &str) -> bool {
+    let digits = token.strip_prefix('-').unwrap_or(token);
+    let lower = digits.to_ascii_lowercase();
+    let (digits, radix) = match (lower.strip_prefix("0x"), lower.strip_prefix("0b")) {
+        (Some(hex), _) => (hex, 16),
+        (_, Some(bin)) => (bin, 2),
+        _ => (lower.as_str(), 10),
+    };
+    !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes(line: &str) -> String {
+        console::set_colors_enabled(true);
+        highlight(line, |word| word == "SQ" || word == "DUP")
+    }
+
+    #[test]
+    fn test_highlight_classes() {
+        let out = classes(": CUBE DUP SQ * ; 3 CUBE FOO");
+        let expected = [
+            style(":").magenta().bold().to_string(),
+            style("CUBE").bold().to_string(),
+            style("DUP").cyan().to_string(),
+            style("SQ").cyan().to_string(),
+            style("*").red().to_string(),
+            style(";").magenta().bold().to_string(),
+            style("3").yellow().to_string(),
+            style("CUBE").cyan().to_string(),
+            style("FOO").red().to_string(),
+        ]
+        .join(" ");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_highlight_comments_and_strings() {
+        let out = classes("  .\" a b\" ( n -- ) -0x1F \\ rest ; IF");
+        let expected = format!(
+            "  {} {} {} {}",
+            style(".\" a b\"").green(),
+            style("( n -- )").dim(),
+            style("-0x1F").yellow(),
+            style("\\ rest ; IF").dim()
+        );
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_is_number() {
+        assert!(is_number("42") && is_number("-7") && is_number("0xff") && is_number("0b10"));
+        assert!(!is_number("-") && !is_number("0x") && !is_number("1+") && !is_number("0b2"));
+    }
+}
@@ -0,0 +1,91 @@
+//! Ctrl+C handling for operations on the device
+//!
+//! Once [`install`]ed, Ctrl+C still ends the process as usual, except while
+//! a [`Catch`] guard is alive: then it only raises a flag. Waits for device
+//! responses on the guarded thread notice it and fail with
+//! [`V4Error::Interrupted`], so the caller can send ABORT and return to its
+//! prompt instead of dying mid-transfer.
+
+use crate::{Result, V4Error};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Exit status for a process ended by Ctrl+C (128 + SIGINT)
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Live [`Catch`] guards in the process
+static GUARDS: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Live [`Catch`] guards on this thread
+    static THREAD_GUARDS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Install the Ctrl+C handler, once per process
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if GUARDS.load(Ordering::SeqCst) > 0 {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        } else {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    })
+    .map_err(|e| V4Error::Cli(format!("Cannot install Ctrl+C handler: {}", e)))
+}
+
+/// Set by Ctrl+C while a [`Catch`] guard is alive
+pub fn flag() -> &'static AtomicBool {
+    &INTERRUPTED
+}
+
+/// Whether this thread catches Ctrl+C, see [`catch`]
+pub fn catching() -> bool {
+    THREAD_GUARDS.with(Cell::get) > 0
+}
+
+/// Whether Ctrl+C was pressed and this thread catches it
+pub fn interrupted() -> bool {
+    catching() && INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Guard turning Ctrl+C into a flag instead of an exit
+#[must_use = "Ctrl+C is only caught while the guard is alive"]
+pub struct Catch(());
+
+/// Catch Ctrl+C until the guard is dropped
+///
+/// The outermost guard clears any earlier interrupt.
+pub fn catch() -> Catch {
+    if GUARDS.fetch_add(1, Ordering::SeqCst) == 0 {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+    THREAD_GUARDS.with(|guards| guards.set(guards.get() + 1));
+    Catch(())
+}
+
+impl Drop for Catch {
+    fn drop(&mut self) {
+        THREAD_GUARDS.with(|guards| guards.set(guards.get() - 1));
+        GUARDS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_is_per_thread_and_nests() {
+        assert!(!catching());
+        let outer = catch();
+        let inner = catch();
+        assert!(catching());
+        assert!(!std::thread::spawn(catching).join().unwrap());
+
+        drop(inner);
+        assert!(catching());
+        drop(outer);
+        assert!(!catching());
+    }
+}
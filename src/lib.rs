@@ -1,8 +1,45 @@
+#[cfg(feature = "ble")]
+pub mod ble;
+pub mod bootloader;
+pub mod bytecode;
 pub mod commands;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod debugger;
+pub mod device;
+pub mod diff;
+pub mod disasm;
+pub mod emit;
+pub mod engine;
 pub mod error;
+pub mod error_explain;
+pub mod ffi;
+pub mod format;
+pub mod highlight;
+pub mod interrupt;
+pub mod listing;
+pub mod logging;
+pub mod monitor;
+pub mod project;
 pub mod protocol;
+pub mod reconnect;
 pub mod repl;
+pub mod rfc2217;
 pub mod serial;
-pub mod v4front_ffi;
+pub mod session;
+pub mod source;
+pub mod sourcemap;
+pub mod stream;
+pub mod syscalls;
+pub mod tcp;
+pub mod testing;
+pub mod trace;
+pub mod transcript;
+pub mod transport;
+pub mod ui;
+pub mod usb;
+pub mod websocket;
 
+pub use device::V4Device;
 pub use error::{Result, V4Error};
@@ -0,0 +1,173 @@
+//! Annotated compile listings
+//!
+//! V4-front doesn't report which source line produced which bytes, so the
+//! listing recovers it by compiling growing prefixes of the source: the code
+//! a prefix adds over the previous one belongs to its last line. Lines inside
+//! an unfinished construct (a multi-line definition, `IF` without `THEN`)
+//! don't compile on their own, so their code is listed under the line that
+//! completes the construct.
+
+use crate::disasm::{self, Instruction};
+use crate::ffi::{CompileResult, Compiler};
+use crate::{Result, V4Error};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Opcode the compiler appends to the main code
+const OP_RET: u8 = 0x51;
+
+/// How much of the full compilation the listed lines account for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Progress {
+    /// Number of word definitions
+    words: usize,
+    /// Bytes of main code
+    main: usize,
+}
+
+/// Code that one source line completed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LineCode {
+    /// 1-based line number
+    pub line: usize,
+    /// Indices of the word definitions it completed
+    pub words: Range<usize>,
+    /// Bytes of main code it compiled to
+    pub main: Range<usize>,
+}
+
+/// Build a listing interleaving source lines with the code they compile to
+///
+/// Main code offsets are relative to the main code, word offsets to the
+/// word's own bytecode.
+pub fn listing(source: &str) -> Result<String> {
+    let mut compiler = Compiler::new().map_err(V4Error::Compilation)?;
+    let (compiled, lines) = line_code(&mut compiler, source)?;
+    let main = disasm::decode(&compiled.bytecode);
+    let main_len = compiled.bytecode.len();
+
+    let mut out = String::new();
+    let mut next = lines.iter().peekable();
+    let mut done = 0;
+    for (i, line) in source.lines().enumerate() {
+        let _ = writeln!(out, "{:>5}  {}", i + 1, line);
+        let Some(code) = next.next_if(|code| code.line == i + 1) else {
+            continue;
+        };
+        for word in &compiled.words[code.words.clone()] {
+            let _ = writeln!(out, "       ; {}", word.name);
+            let insns = disasm::decode(&word.bytecode);
+            write_range(
+                &mut out,
+                &insns,
+                0..word.bytecode.len(),
+                word.bytecode.len(),
+            );
+        }
+        write_range(&mut out, &main, code.main.clone(), main_len);
+        done = code.main.end;
+    }
+
+    // Code no line accounts for, like the final RET
+    if done < main_len {
+        out.push_str("       ; end\n");
+        write_range(&mut out, &main, done..main_len, main_len);
+    }
+    Ok(out)
+}
+
+/// Compile `source` and work out which line each part of the code came from
+///
+/// `compiler` may know words registered earlier; prefixes are compiled with
+/// it too. Lines that complete nothing are left out.
+pub(crate) fn line_code(
+    compiler: &mut Compiler,
+    source: &str,
+) -> Result<(CompileResult, Vec<LineCode>)> {
+    let compiled = compiler.compile(source).map_err(V4Error::Compilation)?;
+    let lines: Vec<&str> = source.lines().collect();
+
+    let mut code = Vec::new();
+    let mut done = Progress::default();
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(progress) = prefix_progress(compiler, &lines[..=i], &compiled, done) else {
+            continue;
+        };
+        code.push(LineCode {
+            line: i + 1,
+            words: done.words..progress.words,
+            main: done.main..progress.main,
+        });
+        done = progress;
+    }
+    Ok((compiled, code))
+}
+
+fn write_range(out: &mut String, insns: &[Instruction], range: Range<usize>, code_len: usize) {
+    for insn in insns.iter().filter(|insn| range.contains(&insn.offset)) {
+        let _ = writeln!(out, "       {}", disasm::listing_line(insn, code_len));
+    }
+}
+
+/// Code compiled from the first lines, if it extends what was already listed
+///
+/// The prefix must reproduce the start of the full compilation: the same
+/// leading words and main code (without the RET the compiler appends).
+fn prefix_progress(
+    compiler: &mut Compiler,
+    lines: &[&str],
+    full: &CompileResult,
+    done: Progress,
+) -> Option<Progress> {
+    let prefix = compiler.compile(&lines.join("\n")).ok()?;
+
+    let words = prefix.words.len();
+    let same_words = full.words.get(..words).is_some_and(|full_words| {
+        full_words
+            .iter()
+            .zip(&prefix.words)
+            .all(|(a, b)| a.name == b.name && a.bytecode == b.bytecode)
+    });
+    let main = match prefix.bytecode.split_last() {
+        Some((&OP_RET, body)) => body,
+        _ => &prefix.bytecode[..],
+    };
+
+    let extends = same_words
+        && words >= done.words
+        && main.len() >= done.main
+        && full.bytecode.starts_with(main);
+    extends.then_some(Progress {
+        words,
+        main: main.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words_follow_their_line() {
+        let text = listing(": SQ DUP * ;\n\n5 SQ\n").unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines[0], "    1  : SQ DUP * ;");
+        assert_eq!(lines[1], "       ; SQ");
+        let blank = lines.iter().position(|l| *l == "    2  ").unwrap();
+        let call = lines.iter().position(|l| *l == "    3  5 SQ").unwrap();
+        // Word code sits between its definition line and the next line
+        assert!(blank > 2 && call > blank);
+    }
+
+    #[test]
+    fn test_listing_reports_compile_errors() {
+        assert!(matches!(
+            listing("NOSUCHWORD"),
+            Err(V4Error::Compilation(_))
+        ));
+    }
+}
@@ -0,0 +1,86 @@
+//! Diagnostic logging to stderr
+//!
+//! Diagnostics go through the `log` facade so protocol traces are only
+//! printed when requested. Regular command output stays on stdout.
+//!
+//! | Flags   | Level | Shows                                  |
+//! |---------|-------|----------------------------------------|
+//! | `-q`    | error | errors only                            |
+//! | (none)  | info  | warnings and status notes              |
+//! | `-v`    | debug | command summaries and bytecode         |
+//! | `-vv`   | trace | raw frame hex dumps                    |
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Logger writing one line per record to stderr
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{}",
+                format_record(record.level(), &record.args().to_string())
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Level for the given `-v` count and `-q` flag
+pub fn level_filter(verbose: u8, quiet: bool) -> LevelFilter {
+    if quiet {
+        return LevelFilter::Error;
+    }
+    match verbose {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Install the stderr logger
+///
+/// Calling it more than once only updates the level.
+pub fn init(level: LevelFilter) {
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+fn format_record(level: Level, message: &str) -> String {
+    match level {
+        Level::Error => format!("Error: {}", message),
+        Level::Warn => format!("Warning: {}", message),
+        Level::Info => message.to_string(),
+        Level::Debug => format!("[DEBUG] {}", message),
+        Level::Trace => format!("[TRACE] {}", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_filter() {
+        assert_eq!(level_filter(0, false), LevelFilter::Info);
+        assert_eq!(level_filter(1, false), LevelFilter::Debug);
+        assert_eq!(level_filter(2, false), LevelFilter::Trace);
+        assert_eq!(level_filter(5, false), LevelFilter::Trace);
+        assert_eq!(level_filter(0, true), LevelFilter::Error);
+    }
+
+    #[test]
+    fn test_format_record() {
+        assert_eq!(format_record(Level::Warn, "x"), "Warning: x");
+        assert_eq!(format_record(Level::Info, "x"), "x");
+        assert_eq!(format_record(Level::Trace, "x"), "[TRACE] x");
+    }
+}
@@ -1,13 +1,70 @@
-use clap::{Parser, Subcommand};
-use std::time::Duration;
-use v4_cli::commands;
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use v4_cli::commands::compile::CompileOptions;
+use v4_cli::commands::compiled::{self, CompileServer};
+use v4_cli::commands::{self, fanout};
+use v4_cli::config::Config;
+use v4_cli::device::millis;
+use v4_cli::emit::{self, Emit};
+use v4_cli::engine::EngineTransport;
+use v4_cli::error_explain;
+use v4_cli::ffi::{self, Optimization};
+use v4_cli::format::{self, OutputFormat};
+use v4_cli::interrupt;
+use v4_cli::logging;
+use v4_cli::monitor::{self, EventKind};
+use v4_cli::project::Project;
+use v4_cli::serial::{self, SerialSettings};
+use v4_cli::source::{self, Define};
+use v4_cli::testing;
+use v4_cli::trace::{self, Trace};
+use v4_cli::transcript::{Transcript, TranscriptWriter};
+use v4_cli::transport::{ResultWait, RetryPolicy};
+use v4_cli::ui::{self, ColorChoice};
+use v4_cli::{V4Device, V4Error};
 
 #[derive(Parser)]
 #[command(name = "v4")]
 #[command(version, about = "CLI tool for V4 VM bytecode deployment", long_about = None)]
 struct Cli {
+    /// Show more diagnostics (-v: protocol commands, -vv: raw frames)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Colorize output: auto, always or never (auto honors NO_COLOR)
+    #[arg(long, global = true, default_value = "auto", value_parser = ui::parse_color_choice)]
+    color: ColorChoice,
+
+    /// Don't draw progress bars (they are also hidden when stderr is not a terminal)
+    #[arg(long, global = true)]
+    no_progress: bool,
+
+    /// Device or device group from the config file to use instead of --port
+    #[arg(long, global = true, value_name = "NAME")]
+    device: Option<String>,
+
+    /// Record every frame sent to or received from the device to this file (JSON Lines)
+    #[arg(long, global = true, value_name = "PATH")]
+    trace_file: Option<String>,
+
+    /// Explain the device error code an error reports (see `v4 explain`)
+    #[arg(long, global = true)]
+    explain: bool,
+
+    /// Print the help of v4 and every subcommand
+    #[arg(long, exclusive = true)]
+    help_long: bool,
+
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
@@ -17,110 +74,1945 @@ enum Commands {
         /// Bytecode file path
         file: String,
 
-        /// Serial port path (e.g., /dev/ttyACM0)
-        #[arg(short, long)]
-        port: String,
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL; repeat or comma-separate for several devices (auto-detected if omitted)
+        #[arg(short, long, alias = "ports", value_delimiter = ',')]
+        port: Vec<String>,
+
+        /// Also run on the ports of this device group from the config file
+        #[arg(long)]
+        group: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        #[command(flatten)]
+        resets: ResetArgs,
 
         /// Don't wait for response
         #[arg(long)]
         detach: bool,
 
-        /// Timeout in seconds
-        #[arg(long, default_value = "5")]
-        timeout: u64,
+        /// Read the words back after the transfer and compare them with the file
+        #[arg(long, conflicts_with = "detach")]
+        verify: bool,
+
+        /// Push even if the device's VM can't read the file's .v4b version
+        #[arg(long)]
+        force: bool,
+
+        /// Send the file as it is, even if the device takes LZ4-compressed transfers
+        #[arg(long)]
+        no_compress: bool,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Read the words defined on the device back into a .v4b file
+    Pull {
+        /// Output .v4b file, or a directory with --split
+        output: String,
+
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Write one raw bytecode file per word into the output directory
+        #[arg(long)]
+        split: bool,
+
+        /// Timeout in seconds per query [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Write a V4-runtime firmware image through the ESP32-C6 ROM bootloader
+    Flash {
+        /// Firmware image (.bin)
+        file: String,
+
+        /// Serial port path (the single Espressif USB port if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        /// Flash offset, decimal or 0x hex (0x0 for a merged image)
+        #[arg(long, default_value = "0x0", value_parser = commands::flash::parse_offset)]
+        offset: u32,
+
+        /// Baud rate of the bootloader link
+        #[arg(long, default_value_t = 115200)]
+        baud: u32,
+
+        /// Skip the MD5 check after writing
+        #[arg(long)]
+        no_verify: bool,
     },
 
     /// Check connection to device
     Ping {
-        /// Serial port path
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
         #[arg(short, long)]
-        port: String,
+        port: Option<String>,
 
-        /// Timeout in seconds
-        #[arg(long, default_value = "5")]
-        timeout: u64,
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Measure PING latency, EXEC throughput and sustained transfer rate
+    Bench {
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// PINGs to time (0 skips the latency test)
+        #[arg(long, default_value_t = 100)]
+        pings: usize,
+
+        /// EXEC payload sizes in bytes, comma-separated
+        #[arg(long, value_delimiter = ',', default_value = "16,64,256,1024,4096")]
+        sizes: Vec<usize>,
+
+        /// EXECs per payload size (0 skips the throughput test)
+        #[arg(long, default_value_t = 10)]
+        rounds: usize,
+
+        /// Bytes sent in one chunked EXEC for the sustained rate (0 skips it)
+        #[arg(long, default_value_t = 16 * 1024)]
+        transfer_size: usize,
+
+        /// Run on the V4 engine on the host instead of a device
+        #[arg(long, conflicts_with = "port")]
+        simulate: bool,
+    },
+
+    /// Show device firmware version and VM capabilities
+    Info {
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Show the data and return stacks
+    Stack {
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        #[command(flatten)]
+        format: FormatArgs,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Drive or read a GPIO pin
+    Gpio {
+        /// Pin number
+        pin: u8,
+
+        /// on, off, read or toggle
+        #[arg(value_parser = commands::gpio::parse_action)]
+        action: commands::gpio::Action,
+
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Read device memory into a file, or show it as a hexdump
+    Dump {
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Address of the first byte (decimal or 0x hex)
+        #[arg(long, value_parser = commands::dump::parse_number)]
+        addr: u32,
+
+        /// Number of bytes to read (decimal or 0x hex)
+        #[arg(long, default_value = "256", value_parser = commands::dump::parse_number)]
+        len: u32,
+
+        /// Write the raw bytes to this file instead of printing a hexdump
+        #[arg(short, long)]
+        out: Option<String>,
+
+        #[command(flatten)]
+        format: FormatArgs,
+
+        /// Timeout in seconds per query [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+
+    /// Show the bytes that differ between two memory snapshots
+    Memdiff {
+        /// Earlier snapshot, e.g. from `v4 dump --out`
+        old: String,
+
+        /// Later snapshot of the same range
+        new: String,
+
+        /// Address the snapshots were taken at, for the address column
+        #[arg(long, default_value = "0", value_parser = commands::dump::parse_number)]
+        addr: u32,
+    },
+
+    /// Print frames and text lines from the device as they arrive
+    Monitor {
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        /// Print received bytes unmodified, without decoding or timestamps
+        #[arg(long, conflicts_with = "filter")]
+        raw: bool,
+
+        /// Show bytes in hex instead of decoded text
+        #[arg(long)]
+        hex: bool,
+
+        /// Only show these frame types: output, response or text (repeatable)
+        #[arg(long, value_parser = monitor::parse_kind)]
+        filter: Vec<EventKind>,
+    },
+
+    /// Keep a serial port open for other v4 commands (Unix only)
+    ///
+    /// Commands given the same --port talk to the device through the daemon
+    /// instead of opening the port, which is faster and doesn't toggle DTR.
+    /// Runs until Ctrl+C.
+    Daemon {
+        /// Serial port path [default: port from the config file]
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+    },
+
+    /// List available serial ports
+    Ports {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Print only the port paths, one per line, without probing
+        #[arg(long, conflicts_with = "json")]
+        names: bool,
+
+        /// Don't PING ports to detect V4 devices
+        #[arg(long)]
+        no_probe: bool,
+
+        /// Also scan for Bluetooth LE devices advertising the UART service
+        #[cfg(feature = "ble")]
+        #[arg(long)]
+        ble: bool,
+
+        #[command(flatten)]
+        serial: SerialArgs,
     },
 
     /// Reset VM
     Reset {
-        /// Serial port path
-        #[arg(short, long)]
-        port: String,
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL; repeat or comma-separate for several devices (auto-detected if omitted)
+        #[arg(short, long, alias = "ports", value_delimiter = ',')]
+        port: Vec<String>,
+
+        /// Also run on the ports of this device group from the config file
+        #[arg(long)]
+        group: Option<String>,
 
-        /// Timeout in seconds
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// Seconds to wait for the device to answer PING after reset
         #[arg(long, default_value = "5")]
-        timeout: u64,
+        ready_timeout: u64,
     },
 
     /// Compile Forth source to bytecode
     Compile {
-        /// Input Forth source file path
-        input: String,
+        /// Forth source files, concatenated in order ('-' reads stdin)
+        #[arg(value_name = "INPUT", required = true)]
+        inputs: Vec<String>,
 
-        /// Output bytecode file path (default: input with .v4b extension)
+        /// Output bytecode file path (default: first input with the extension of the --emit format, e.g. .v4b)
         #[arg(short, long)]
         output: Option<String>,
+
+        /// Write bytecode to stdout instead of a file
+        #[arg(long, conflicts_with = "output")]
+        stdout: bool,
+
+        /// Fail instead of warning when a word is defined more than once
+        #[arg(long)]
+        deny_shadowing: bool,
+
+        /// Also write a listing of source lines with their bytecode
+        #[arg(long, value_name = "FILE")]
+        listing: Option<String>,
+
+        /// Also write a source map (app.v4map) so device errors can name source lines
+        #[arg(long, conflicts_with = "stdout")]
+        source_map: bool,
+
+        /// Only check the source: print diagnostics, write nothing
+        #[arg(long, conflicts_with_all = ["output", "stdout", "listing", "source_map"])]
+        check: bool,
+
+        /// Print --check diagnostics as JSON
+        #[arg(long, requires = "check")]
+        json: bool,
+
+        /// Define a constant word ahead of the source, e.g. GPIO_LED=1 (repeatable)
+        #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = source::parse_define)]
+        defines: Vec<Define>,
+
+        /// Optimization level passed to V4-front (needs the optimizer feature)
+        #[arg(short = 'O', long = "opt-level", value_name = "LEVEL", default_value = "0",
+              value_parser = clap::value_parser!(u8).range(0..=ffi::MAX_OPT_LEVEL as i64))]
+        opt_level: u8,
+
+        /// Don't inline words at -O2
+        #[arg(long)]
+        no_inline: bool,
+
+        /// Turn a call at the end of a word into a jump (hides the caller from backtraces)
+        #[arg(long)]
+        tail_call: bool,
+
+        /// Fail if the bytecode exceeds this many bytes, listing the size of each word
+        #[arg(long, value_name = "BYTES")]
+        max_size: Option<usize>,
+
+        /// Output format: v4b, raw (main code only), c-array, rust-array or ihex
+        #[arg(long, value_name = "FORMAT", default_value = "v4b", value_parser = emit::parse_emit)]
+        emit: Emit,
+
+        /// Array name for --emit c-array and rust-array [default: program]
+        #[arg(long, value_name = "NAME", value_parser = emit::parse_symbol)]
+        symbol: Option<String>,
+    },
+
+    /// Run a Language Server Protocol server on stdin/stdout for editors
+    Lsp,
+
+    /// Check .v4 files below a directory whenever they change, printing diagnostics as JSON lines
+    Compiled {
+        /// Directory to watch for .v4 files (searched recursively)
+        #[arg(long, value_name = "DIR")]
+        watch: String,
+
+        /// Also keep the diagnostics of all files in this JSON file
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+
+        /// Check every file once and exit
+        #[arg(long)]
+        once: bool,
+
+        /// Report words defined more than once as errors instead of warnings
+        #[arg(long)]
+        deny_shadowing: bool,
+
+        /// Define a constant word ahead of each source, e.g. GPIO_LED=1 (repeatable)
+        #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = source::parse_define)]
+        defines: Vec<Define>,
+    },
+
+    /// Disassemble bytecode (.v4b or raw) into opcode mnemonics
+    Disasm {
+        /// Bytecode file path
+        file: String,
+    },
+
+    /// Validate a .v4b file and summarize its header, words and code
+    Inspect {
+        /// Bytecode file path
+        file: String,
+    },
+
+    /// List the words of a .v4b file by bytecode size, largest first
+    Size {
+        /// Bytecode file path
+        file: String,
+
+        /// Device dictionary size in bytes, to show how much of it each word takes
+        #[arg(long, value_name = "BYTES")]
+        dict_size: Option<usize>,
+    },
+
+    /// Explain a V4-link error code: likely causes and fixes
+    Explain {
+        /// Code name or number, e.g. BUFFER_FULL or 0x03
+        code: String,
+    },
+
+    /// Run bytecode (.v4b or raw) on the V4 engine, on the host
+    Run {
+        /// Bytecode file path
+        file: String,
+    },
+
+    /// Run a script of Forth lines, meta-commands and host directives
+    Script {
+        /// Script file (`sleep 500ms`, `expect-stack 1 2 3`, REPL lines)
+        file: String,
+
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Skip VM reset before the script
+        #[arg(long)]
+        no_reset: bool,
+
+        /// Run on the V4 engine on the host instead of a device
+        #[arg(long, conflicts_with = "port")]
+        simulate: bool,
+    },
+
+    /// Run a script and report pass/fail for each assertion
+    Test {
+        /// Script file with `expect-stack` / `expect-memory` assertions
+        file: String,
+
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (auto-detected if omitted)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Skip VM reset before the script
+        #[arg(long)]
+        no_reset: bool,
+
+        /// Run on the V4 engine on the host instead of a device
+        #[arg(long, conflicts_with = "port")]
+        simulate: bool,
+
+        /// Also write the results as JUnit XML to this file
+        #[arg(long, value_name = "PATH")]
+        junit: Option<String>,
     },
 
     /// Start interactive REPL session
     Repl {
-        /// Serial port path (e.g., /dev/ttyACM0)
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (e.g., /dev/ttyACM0; auto-detected if omitted)
         #[arg(short, long)]
-        port: String,
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
 
         /// Skip VM reset on startup (preserves existing words)
         #[arg(long)]
         no_reset: bool,
+
+        /// Run offline on the V4 engine on the host instead of a device
+        #[arg(long, visible_alias = "local", conflicts_with = "port")]
+        simulate: bool,
+
+        /// Read input from stdin without line editing and answer each with `OK` or `ERR <code>`
+        #[arg(long)]
+        pipe: bool,
+
+        /// Record inputs, device output and errors to a transcript file (appends)
+        #[arg(long, value_name = "PATH")]
+        transcript: Option<String>,
+
+        /// Run the inputs of a transcript again and report where they differ
+        #[arg(long, value_name = "PATH", conflicts_with = "pipe")]
+        replay: Option<String>,
     },
 
-    /// Execute Forth source file on device
-    Exec {
-        /// Forth source file path
-        file: String,
+    /// Work through a guided tutorial with checked exercises
+    Learn {
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL (the host engine if omitted and none is found)
+        #[arg(short, long)]
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Practice on the host engine even if a device is connected
+        #[arg(long, conflicts_with = "port")]
+        simulate: bool,
+
+        /// Lesson to start at
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=commands::learn::LESSONS.len() as u64))]
+        lesson: u64,
+    },
+
+    /// Read or modify the configuration file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Create a V4 project in a new directory
+    New {
+        /// Directory to create
+        path: String,
+
+        #[command(flatten)]
+        project: ProjectArgs,
+    },
+
+    /// Make an existing directory a V4 project
+    Init {
+        /// Project directory
+        #[arg(default_value = ".")]
+        path: String,
+
+        #[command(flatten)]
+        project: ProjectArgs,
+    },
+
+    /// Compile the current project's sources into its bytecode file (see v4.toml)
+    Build {
+        /// Fail instead of warning when a word is defined more than once
+        #[arg(long)]
+        deny_shadowing: bool,
+
+        /// Define a constant word ahead of the sources, overriding [constants], e.g. GPIO_LED=1 (repeatable)
+        #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = source::parse_define)]
+        defines: Vec<Define>,
+
+        /// Fail if the bytecode exceeds this many bytes, listing the size of each word
+        #[arg(long, value_name = "BYTES")]
+        max_size: Option<usize>,
+    },
 
-        /// Serial port path (e.g., /dev/ttyACM0)
+    /// Fetch the newest commits of the project's git dependencies into v4.lock
+    Update,
+
+    /// Build the current project and push it to its device
+    Deploy {
+        /// Serial port path or URL [default: deploy.port from v4.toml]
         #[arg(short, long)]
-        port: String,
+        port: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Timeout in seconds [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// Don't reset the VM before pushing, whatever v4.toml says
+        #[arg(long)]
+        no_reset: bool,
 
-        /// Timeout in seconds
+        /// Seconds to wait for the device to answer PING after reset
         #[arg(long, default_value = "5")]
-        timeout: u64,
+        ready_timeout: u64,
 
-        /// Enter REPL after execution
+        /// Fail instead of warning when a word is defined more than once
+        #[arg(long)]
+        deny_shadowing: bool,
+
+        /// Define a constant word ahead of the sources, overriding [constants], e.g. GPIO_LED=1 (repeatable)
+        #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = source::parse_define)]
+        defines: Vec<Define>,
+    },
+
+    /// Inspect frame captures written with --trace-file
+    Trace {
+        #[command(subcommand)]
+        action: TraceAction,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        /// Shell to complete for
+        shell: Shell,
+    },
+
+    /// Host the V4 engine for `--simulate`, answering requests on stdin/stdout
+    #[command(hide = true)]
+    EngineServer,
+
+    /// Write man pages for v4 and each subcommand
+    #[command(hide = true)]
+    Mangen {
+        /// Directory to write the pages to
+        #[arg(value_name = "DIR")]
+        dir: String,
+    },
+
+    /// Execute Forth source file on device
+    Exec {
+        /// Forth source files, run in order in one compiler context
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<String>,
+
+        /// Define a constant word before the files run, e.g. GPIO_LED=1 (repeatable)
+        #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = source::parse_define)]
+        defines: Vec<Define>,
+
+        /// Serial port path, tcp://host[:port], rfc2217://host:port, ws://host[/path], ble://<address> or usb:SERIAL; repeat or comma-separate for several devices (auto-detected if omitted)
+        #[arg(short, long, alias = "ports", value_delimiter = ',')]
+        port: Vec<String>,
+
+        /// Also run on the ports of this device group from the config file
         #[arg(long)]
+        group: Option<String>,
+
+        #[command(flatten)]
+        serial: SerialArgs,
+
+        #[command(flatten)]
+        retry: RetryArgs,
+
+        /// Timeout in seconds for each device answer; for long-running programs this is the ACK [default: 5]
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// Seconds to wait for a program the device accepted to finish [default: until it finishes or Ctrl+C]
+        #[arg(long, value_name = "SECS")]
+        result_timeout: Option<u64>,
+
+        /// Overall seconds for each file, all definitions and the program included; with --repl, for each line [default: none]
+        #[arg(long, value_name = "SECS")]
+        deadline: Option<u64>,
+
+        /// Print how long each word definition and the program took; with --repl, time each line
+        #[arg(long)]
+        time: bool,
+
+        #[command(flatten)]
+        resets: ResetArgs,
+
+        /// Enter REPL after execution
+        #[arg(long, conflicts_with_all = ["watch", "reset_after"])]
         repl: bool,
+
+        /// Re-run the file every time it changes
+        #[arg(long, conflicts_with = "reset_after")]
+        watch: bool,
+
+        /// Reset the VM before each re-run in --watch mode
+        #[arg(long, requires = "watch")]
+        reset_on_change: bool,
+
+        /// Run on the V4 engine on the host instead of a device
+        #[arg(long, conflicts_with_all = ["port", "group"])]
+        simulate: bool,
     },
 }
 
+/// Manifest settings for `new` and `init`
+#[derive(Args)]
+struct ProjectArgs {
+    /// Project name [default: directory name]
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Port to deploy to, written to v4.toml
+    #[arg(short, long)]
+    port: Option<String>,
+
+    /// Baud rate, written to v4.toml
+    #[arg(long)]
+    baud: Option<u32>,
+}
+
+impl ProjectArgs {
+    fn options(self) -> commands::new::NewOptions {
+        commands::new::NewOptions {
+            name: self.name,
+            port: self.port,
+            baud_rate: self.baud,
+        }
+    }
+}
+
+/// Serial line options shared by device commands
+#[derive(Args)]
+struct SerialArgs {
+    /// Baud rate [default: 115200]
+    #[arg(long)]
+    baud: Option<u32>,
+
+    /// Data bits: 5, 6, 7 or 8 [default: 8]
+    #[arg(long, value_parser = serial::parse_data_bits)]
+    data_bits: Option<DataBits>,
+
+    /// Parity: none, odd or even [default: none]
+    #[arg(long, value_parser = serial::parse_parity)]
+    parity: Option<Parity>,
+
+    /// Stop bits: 1 or 2 [default: 1]
+    #[arg(long, value_parser = serial::parse_stop_bits)]
+    stop_bits: Option<StopBits>,
+
+    /// Flow control: none, software or hardware [default: none]
+    #[arg(long, value_parser = serial::parse_flow_control)]
+    flow_control: Option<FlowControl>,
+
+    /// Set DTR high or low after opening the port [default: as the OS leaves it]
+    #[arg(long, value_name = "LEVEL", value_parser = serial::parse_line_level)]
+    dtr: Option<bool>,
+
+    /// Keep DTR low, same as --dtr low; avoids the auto-reset of many ESP32 boards
+    #[arg(long, conflicts_with = "dtr")]
+    no_dtr: bool,
+
+    /// Set RTS high or low after opening the port [default: as the OS leaves it]
+    #[arg(long, value_name = "LEVEL", value_parser = serial::parse_line_level)]
+    rts: Option<bool>,
+}
+
+impl SerialArgs {
+    /// Serial settings from flags, falling back to the config file
+    fn settings(&self, config: &Config) -> v4_cli::Result<SerialSettings> {
+        let mut settings = config.serial_settings()?;
+        if let Some(baud) = self.baud {
+            settings.baud_rate = baud;
+        }
+        if let Some(data_bits) = self.data_bits {
+            settings.data_bits = data_bits;
+        }
+        if let Some(parity) = self.parity {
+            settings.parity = parity;
+        }
+        if let Some(stop_bits) = self.stop_bits {
+            settings.stop_bits = stop_bits;
+        }
+        if let Some(flow_control) = self.flow_control {
+            settings.flow_control = flow_control;
+        }
+        if self.no_dtr {
+            settings.dtr = Some(false);
+        }
+        if let Some(dtr) = self.dtr {
+            settings.dtr = Some(dtr);
+        }
+        if let Some(rts) = self.rts {
+            settings.rts = Some(rts);
+        }
+        Ok(settings)
+    }
+}
+
+/// Resend options for lost or corrupted frames
+#[derive(Args)]
+struct RetryArgs {
+    /// Resend a frame up to N times on timeout or CRC error [default: 0]
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+
+    /// Milliseconds before the first resend, doubling each time [default: 100]
+    #[arg(long, value_name = "MS")]
+    retry_delay: Option<u64>,
+}
+
+impl RetryArgs {
+    /// Retry policy from flags, falling back to the config file
+    fn policy(&self, config: &Config) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        if let Some(retries) = self.retries.or(config.retries) {
+            policy.retries = retries;
+        }
+        if let Some(delay) = self.retry_delay.or(config.retry_delay) {
+            policy.delay = Duration::from_millis(delay);
+        }
+        policy
+    }
+}
+
+/// Port and serial settings for one of [`fanout::targets`]
+///
+/// A device name from the config file brings its own port and settings;
+/// `None` auto-detects the port.
+fn target_settings(
+    target: Option<&str>,
+    serial: &SerialArgs,
+    config: &Config,
+) -> v4_cli::Result<(Option<String>, SerialSettings)> {
+    let Some(target) = target else {
+        return Ok((None, serial.settings(config)?));
+    };
+    let (port, config) = config.target(target)?;
+    Ok((Some(port), serial.settings(&config)?))
+}
+
+/// Number format of memory and stack output
+#[derive(Args)]
+struct FormatArgs {
+    /// How to write values: hex, dec, signed, bin, c (C array) or python (list)
+    #[arg(long, value_name = "STYLE", default_value = "hex", value_parser = format::parse_style)]
+    output_format: format::Style,
+
+    /// Bits per memory word: 8, 16 or 32
+    #[arg(long, value_name = "BITS", default_value = "8", value_parser = format::parse_word_size)]
+    word_size: usize,
+
+    /// Byte order of memory words: little or big
+    #[arg(long, default_value = "little", value_parser = format::parse_endian)]
+    endian: format::Endian,
+}
+
+impl FormatArgs {
+    fn format(&self) -> OutputFormat {
+        OutputFormat {
+            style: self.output_format,
+            word_size: self.word_size,
+            endian: self.endian,
+        }
+    }
+}
+
+/// VM resets around `push` and `exec`, in the same connection
+#[derive(Args)]
+struct ResetArgs {
+    /// Reset the VM before running
+    #[arg(long)]
+    reset_before: bool,
+
+    /// Reset the VM after running, also when it failed
+    #[arg(long)]
+    reset_after: bool,
+
+    /// Seconds to wait for the device to answer PING after a reset
+    #[arg(long, value_name = "SECS", default_value = "5")]
+    reset_ready_timeout: u64,
+}
+
+impl ResetArgs {
+    fn resets(&self) -> commands::reset::Resets {
+        commands::reset::Resets {
+            before: self.reset_before,
+            after: self.reset_after,
+            ready_timeout: Duration::from_secs(self.reset_ready_timeout),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print a configuration value
+    Get {
+        /// Key (see `v4 config list`)
+        key: String,
+    },
+
+    /// Set a configuration value
+    Set {
+        /// Key (see `v4 config list`)
+        key: String,
+
+        /// New value
+        value: String,
+    },
+
+    /// List all configuration values
+    List,
+}
+
+#[derive(Subcommand)]
+enum TraceAction {
+    /// Print the frames of a capture, decoded
+    Show {
+        /// Trace file path
+        file: String,
+
+        /// Also show each frame's bytes in hex
+        #[arg(long)]
+        hex: bool,
+    },
+}
+
+/// Default response timeout in seconds when neither CLI nor config set one
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+/// How long `v4 learn` waits for devices to answer before using the host engine
+const DISCOVERY_TIMEOUT: Duration = Duration::from_millis(300);
+
+mod completions;
+mod manpages;
+mod output;
+
 fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if cli.help_long {
+        print!("{}", manpages::help_long(Cli::command()));
+        return;
+    }
+    let Some(command) = cli.command.take() else {
+        Cli::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit();
+    };
+    ui::set_color_choice(cli.color);
+    logging::init(logging::level_filter(cli.verbose, cli.quiet));
+    if cli.no_progress || cli.quiet {
+        output::hide_progress();
+    }
+
+    if let Err(e) = interrupt::install() {
+        log::warn!("{}", e);
+    }
+
+    let explain = cli.explain;
+    if let Err(e) = run(cli, command) {
+        eprintln!("{} {}", ui::error_label(), e);
+        output::explain_error(&e, explain);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run(cli: Cli, command: Commands) -> v4_cli::Result<()> {
+    let mut config = Config::load()?;
+    // `--device` names a device, whose settings apply, or a group of them
+    let mut device_group = None;
+    match cli.device {
+        Some(name) if config.groups.contains_key(&name) => {
+            if !matches!(
+                command,
+                Commands::Push { .. } | Commands::Reset { .. } | Commands::Exec { .. }
+            ) {
+                return Err(V4Error::Cli(format!(
+                    "'{}' is a device group; only push, reset and exec run on several devices",
+                    name
+                )));
+            }
+            device_group = Some(name);
+        }
+        Some(name) => config = config.with_device(&name)?,
+        None => {}
+    }
+    let group_arg = |group: Option<String>| match (group, &device_group) {
+        (Some(_), Some(_)) => Err(V4Error::Cli(
+            "--device names a group; don't combine it with --group".to_string(),
+        )),
+        (group, device_group) => Ok(group.or_else(|| device_group.clone())),
+    };
+    if let Some(path) = &cli.trace_file {
+        trace::record_to(path)?;
+    }
+
+    // CLI flags take precedence over the config file
+    let timeout = |cli: Option<u64>| {
+        Duration::from_secs(cli.or(config.timeout).unwrap_or(DEFAULT_TIMEOUT_SECS))
+    };
+    let port = |cli: Option<String>| cli.or_else(|| config.port.clone());
 
-    let result = match cli.command {
+    match command {
         Commands::Push {
             file,
-            port,
+            port: port_arg,
+            group,
+            serial,
+            retry,
+            resets,
             detach,
-            timeout,
-        } => commands::push(&file, &port, detach, Duration::from_secs(timeout)),
+            verify,
+            force,
+            no_compress,
+            timeout: timeout_arg,
+        } => {
+            let targets = fanout::targets(&port_arg, group_arg(group)?.as_deref(), &config)?;
+            let (retry, timeout) = (retry.policy(&config), timeout(timeout_arg));
+            let resets = resets.resets();
+            let bytecode = commands::push::read_bytecode(&file)?;
+            let total = bytecode.len();
+            if targets.len() > 1 {
+                let outcomes = fanout::fan_out(&targets, |target| {
+                    let (port, settings) = target_settings(Some(target), &serial, &config)?;
+                    let mut device = V4Device::open(port.as_deref(), &settings)?
+                        .with_compression(!no_compress)
+                        .with_retry(retry)?;
+                    commands::reset::around(
+                        &mut device,
+                        &resets,
+                        timeout,
+                        &mut |_, _| {},
+                        |device| {
+                            let compatibility =
+                                commands::push::check_version(device, &bytecode, force, timeout)?;
+                            let report = device.push(&bytecode, timeout, &mut |_| {})?;
+                            if verify {
+                                commands::push::verify(device, &bytecode, &report, timeout)?
+                                    .check()?;
+                            }
+                            Ok((report, compatibility))
+                        },
+                    )
+                });
+                output::fan_out(&outcomes, |(report, compatibility)| {
+                    let mut deployed = format!(
+                        "deployed {} bytes, {} word(s)",
+                        report.size,
+                        report.word_indices.len()
+                    );
+                    if let Some(stats) = &report.compression {
+                        deployed += &format!(", compressed to {:.0}%", stats.ratio() * 100.0);
+                    }
+                    match compatibility.warning() {
+                        Some(warning) => format!("{} ({})", deployed, warning),
+                        None => deployed,
+                    }
+                });
+                return fanout::check_outcomes(&outcomes);
+            }
 
-        Commands::Ping { port, timeout } => commands::ping(&port, Duration::from_secs(timeout)),
+            let (port, settings) =
+                target_settings(targets.first().map(String::as_str), &serial, &config)?;
+            let mut device = V4Device::open(port.as_deref(), &settings)?
+                .with_compression(!no_compress)
+                .with_retry(retry)?;
+            commands::reset::around(
+                &mut device,
+                &resets,
+                timeout,
+                &mut |_, report| output::reset(report),
+                |device| {
+                    let compatibility =
+                        commands::push::check_version(device, &bytecode, force, timeout)?;
+                    output::compatibility(&compatibility);
+                    let mut pb = None;
+                    let report = device.push(&bytecode, timeout, &mut |sent| {
+                        pb.get_or_insert_with(|| output::progress_bar(total))
+                            .set_position(sent as u64)
+                    });
+                    if let Some(pb) = pb {
+                        match &report {
+                            Ok(_) => pb.finish_with_message("Complete"),
+                            Err(_) => pb.abandon_with_message("Failed"),
+                        }
+                    }
+                    let report = report?;
+                    output::push(&file, &report, detach);
+                    if verify {
+                        let verification =
+                            commands::push::verify(device, &bytecode, &report, timeout)?;
+                        output::verification(&verification);
+                        verification.check()?;
+                    }
+                    Ok(())
+                },
+            )?;
+        }
 
-        Commands::Reset { port, timeout } => commands::reset(&port, Duration::from_secs(timeout)),
+        Commands::Pull {
+            output: path,
+            port: port_arg,
+            serial,
+            retry,
+            split,
+            timeout: timeout_arg,
+        } => {
+            let words = commands::pull(
+                port(port_arg).as_deref(),
+                &serial.settings(&config)?,
+                retry.policy(&config),
+                timeout(timeout_arg),
+            )?;
+            match split {
+                true => {
+                    commands::pull::write_split(&words, Path::new(&path))?;
+                }
+                false => std::fs::write(&path, commands::pull::to_v4b(&words))?,
+            }
+            output::pulled(&words, &path);
+        }
 
-        Commands::Compile { input, output } => commands::compile(&input, output.as_deref()),
+        Commands::Flash {
+            file,
+            port: port_arg,
+            offset,
+            baud,
+            no_verify,
+        } => {
+            let mut pb = None;
+            let report = commands::flash(
+                &file,
+                port_arg.as_deref(),
+                baud,
+                offset,
+                !no_verify,
+                &mut |stage| output::flash_stage(stage, &mut pb),
+            );
+            if let Some(pb) = pb.filter(|pb| !pb.is_finished()) {
+                pb.abandon_with_message("Failed");
+            }
+            output::flash(&file, &report?);
+        }
 
-        Commands::Repl { port, no_reset } => commands::run_repl(&port, no_reset),
+        Commands::Ping {
+            port: port_arg,
+            serial,
+            retry,
+            timeout: timeout_arg,
+        } => {
+            let port = commands::ping(
+                port(port_arg).as_deref(),
+                &serial.settings(&config)?,
+                retry.policy(&config),
+                timeout(timeout_arg),
+            )?;
+            output::ping(&port);
+        }
 
-        Commands::Exec {
+        Commands::Bench {
+            port: port_arg,
+            serial,
+            retry,
+            timeout: timeout_arg,
+            pings,
+            sizes,
+            rounds,
+            transfer_size,
+            simulate,
+        } => {
+            let mut device = if simulate {
+                host_engine()?
+            } else {
+                V4Device::open(port(port_arg).as_deref(), &serial.settings(&config)?)?
+                    .with_retry(retry.policy(&config))?
+            };
+            let options = commands::bench::BenchOptions {
+                pings,
+                sizes,
+                rounds,
+                transfer_size,
+            };
+            output::bench(&commands::bench(
+                &mut device,
+                &options,
+                timeout(timeout_arg),
+            )?);
+        }
+
+        Commands::Info {
+            port: port_arg,
+            serial,
+            retry,
+            timeout: timeout_arg,
+        } => {
+            let info = commands::info(
+                port(port_arg).as_deref(),
+                &serial.settings(&config)?,
+                retry.policy(&config),
+                timeout(timeout_arg),
+            )?;
+            output::info(&info);
+        }
+
+        Commands::Stack {
+            port: port_arg,
+            serial,
+            retry,
+            format,
+            timeout: timeout_arg,
+        } => {
+            let stack = commands::stack(
+                port(port_arg).as_deref(),
+                &serial.settings(&config)?,
+                retry.policy(&config),
+                timeout(timeout_arg),
+            )?;
+            output::stack(&stack, &format.format());
+        }
+
+        Commands::Gpio {
+            pin,
+            action,
+            port: port_arg,
+            serial,
+            retry,
+            timeout: timeout_arg,
+        } => {
+            let level = commands::gpio(
+                port(port_arg).as_deref(),
+                &serial.settings(&config)?,
+                retry.policy(&config),
+                pin,
+                action,
+                timeout(timeout_arg),
+            )?;
+            output::gpio(pin, level);
+        }
+
+        Commands::Dump {
+            port: port_arg,
+            serial,
+            retry,
+            addr,
+            len,
+            out,
+            format,
+            timeout: timeout_arg,
+        } => {
+            let mut pb = None;
+            let result = commands::dump(
+                port(port_arg).as_deref(),
+                &serial.settings(&config)?,
+                retry.policy(&config),
+                addr,
+                len,
+                timeout(timeout_arg),
+                &mut |read| {
+                    pb.get_or_insert_with(|| {
+                        let pb = output::progress_bar(len as usize);
+                        pb.set_message("Reading...");
+                        pb
+                    })
+                    .set_position(read as u64)
+                },
+            );
+            if let Some(pb) = pb {
+                match &result {
+                    Ok(_) => pb.finish_with_message("Complete"),
+                    Err(_) => pb.abandon_with_message("Failed"),
+                }
+            }
+            let dump = result?;
+            match out {
+                Some(path) => {
+                    std::fs::write(&path, &dump.bytes)?;
+                    output::dump_saved(&dump, &path, len);
+                }
+                None => output::memory(&dump, &format.format()),
+            }
+        }
+
+        Commands::Memdiff { old, new, addr } => {
+            let diff = commands::memdiff(&old, &new, addr)?;
+            output::memdiff(&diff, &old, &new);
+        }
+
+        Commands::Monitor {
+            port: port_arg,
+            serial,
+            raw,
+            hex,
+            filter,
+        } => {
+            let port = port(port_arg);
+            let settings = serial.settings(&config)?;
+            if raw {
+                commands::monitor_raw(port.as_deref(), &settings, &mut |data| {
+                    output::monitor_raw(data, hex)
+                })?;
+            } else {
+                let start = Instant::now();
+                commands::monitor(port.as_deref(), &settings, &mut |event| {
+                    if !filter.is_empty() && !filter.contains(&event.kind) {
+                        return true;
+                    }
+                    output::monitor_event(event, start.elapsed(), hex)
+                })?;
+            }
+        }
+
+        Commands::Daemon {
+            port: port_arg,
+            serial,
+        } => {
+            let port = port(port_arg).ok_or_else(|| {
+                V4Error::Cli("v4 daemon needs --port or a port in the config file".to_string())
+            })?;
+            let settings = serial.settings(&config)?;
+            #[cfg(unix)]
+            {
+                let mut daemon = v4_cli::daemon::Daemon::open(&port, &settings)?;
+                output::daemon(daemon.port(), daemon.socket());
+                let _catch = interrupt::catch();
+                daemon.serve(interrupt::flag(), &mut output::daemon_client)?;
+                println!("\nDaemon stopped");
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = (port, settings);
+                return Err(V4Error::Cli(
+                    "v4 daemon needs Unix domain sockets and is not available on this platform"
+                        .to_string(),
+                ));
+            }
+        }
+
+        Commands::Ports {
+            json,
+            names,
+            no_probe,
+            serial,
+            #[cfg(feature = "ble")]
+            ble,
+        } => {
+            if names {
+                for entry in commands::list_ports(false, &SerialSettings::default())? {
+                    println!("{}", entry.path);
+                }
+                return Ok(());
+            }
+            let entries = commands::list_ports(!no_probe, &serial.settings(&config)?)?;
+            #[cfg(feature = "ble")]
+            let entries = match ble {
+                true => [entries, commands::ports::list_ble_devices()?].concat(),
+                false => entries,
+            };
+            output::ports(&entries, json)?;
+        }
+
+        Commands::Reset {
+            port: port_arg,
+            group,
+            serial,
+            retry,
+            timeout: timeout_arg,
+            ready_timeout,
+        } => {
+            let targets = fanout::targets(&port_arg, group_arg(group)?.as_deref(), &config)?;
+            let (retry, timeout) = (retry.policy(&config), timeout(timeout_arg));
+            let reset = |target: Option<&str>| {
+                let (port, settings) = target_settings(target, &serial, &config)?;
+                commands::reset(
+                    port.as_deref(),
+                    &settings,
+                    retry,
+                    timeout,
+                    Duration::from_secs(ready_timeout),
+                )
+            };
+            if targets.len() > 1 {
+                let outcomes = fanout::fan_out(&targets, |port| reset(Some(port)));
+                output::fan_out(&outcomes, |report| {
+                    format!("ready after {} ms", report.ready_after.as_millis())
+                });
+                return fanout::check_outcomes(&outcomes);
+            }
+            output::reset(&reset(targets.first().map(String::as_str))?);
+        }
+
+        Commands::Compile {
+            inputs,
+            output: output_arg,
+            stdout,
+            deny_shadowing,
+            listing,
+            source_map,
+            check,
+            json,
+            defines,
+            opt_level,
+            no_inline,
+            tail_call,
+            max_size,
+            emit,
+            symbol,
+        } => {
+            let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+            let options = CompileOptions {
+                deny_shadowing,
+                listing: listing.map(Into::into),
+                defines,
+                optimization: Optimization {
+                    level: opt_level,
+                    inline: !no_inline,
+                    tail_call,
+                },
+                max_size,
+                source_map,
+                emit,
+                symbol,
+            };
+            if check {
+                let report = commands::compile::check(&inputs, &options)?;
+                output::check(&report, json)?;
+                if report.errors() > 0 {
+                    std::process::exit(v4_cli::error::EXIT_COMPILE);
+                }
+            } else if stdout {
+                let (bytes, shadowed) = commands::compile::compile_to_bytes(&inputs, &options)?;
+                output::shadowed_words(&shadowed);
+                output::bytecode(&bytes)?;
+            } else {
+                let report = commands::compile(&inputs, output_arg.as_deref(), &options)?;
+                output::compile(&inputs, &report);
+            }
+        }
+
+        Commands::Lsp => commands::lsp()?,
+
+        Commands::EngineServer => v4_cli::engine::serve_stdio()?,
+
+        Commands::Compiled {
+            watch,
+            output: snapshot,
+            once,
+            deny_shadowing,
+            defines,
+        } => {
+            let options = CompileOptions {
+                deny_shadowing,
+                defines,
+                ..CompileOptions::default()
+            };
+            let mut server = CompileServer::new(Path::new(&watch), options)?;
+            // Set up before the first check so edits made during it count
+            let mut watcher = match once {
+                true => None,
+                false => {
+                    let mut watcher = commands::exec::FileWatcher::new()?;
+                    watcher.watch_tree(Path::new(&watch))?;
+                    Some(watcher)
+                }
+            };
+            let mut changed = Vec::new();
+            loop {
+                let checks = server.poll(&changed);
+                output::file_checks(&checks)?;
+                if let Some(path) = &snapshot
+                    && !checks.is_empty()
+                {
+                    compiled::write_snapshot(&server.snapshot(), Path::new(path))?;
+                }
+                let Some(watcher) = &mut watcher else {
+                    break;
+                };
+                watcher.watch(&server.outside_files())?;
+                changed = watcher.wait()?;
+            }
+        }
+
+        Commands::Disasm { file } => output::disasm(&file, &commands::disasm(&file)?),
+
+        Commands::Inspect { file } => output::inspect(&file, &commands::inspect(&file)?),
+
+        Commands::Size { file, dict_size } => {
+            output::size(&file, &commands::size(&file, dict_size)?)
+        }
+
+        Commands::Explain { code } => {
+            output::explain(&error_explain::explain(error_explain::parse_code(&code)?))
+        }
+
+        Commands::Run { file } => {
+            let report = commands::run(&file)?;
+            output::run(&report);
+            if let Some(error) = report.error {
+                return Err(V4Error::Device(format!("Execution failed: {}", error)));
+            }
+        }
+
+        Commands::Script {
             file,
-            port,
-            timeout,
+            port: port_arg,
+            serial,
+            retry,
+            no_reset,
+            simulate,
+        } => {
+            // Fail on a missing file before touching the device
+            std::fs::metadata(&file)?;
+            let mut device = if simulate {
+                host_engine()?
+            } else {
+                V4Device::open(port(port_arg).as_deref(), &serial.settings(&config)?)?
+                    .with_retry(retry.policy(&config))?
+            };
+            if !no_reset {
+                device.reset(timeout(None), timeout(None))?;
+            }
+            let (transport, compiler) = device.parts()?;
+            output::script(&file, &commands::run_script(transport, compiler, &file)?);
+        }
+
+        Commands::Test {
+            file,
+            port: port_arg,
+            serial,
+            retry,
+            no_reset,
+            simulate,
+            junit,
+        } => {
+            // Fail on a missing file before touching the device
+            std::fs::metadata(&file)?;
+            let mut device = if simulate {
+                host_engine()?
+            } else {
+                V4Device::open(port(port_arg).as_deref(), &serial.settings(&config)?)?
+                    .with_retry(retry.policy(&config))?
+            };
+            if !no_reset {
+                device.reset(timeout(None), timeout(None))?;
+            }
+            let (transport, compiler) = device.parts()?;
+            let report = commands::run_test(transport, compiler, &file);
+            if let Some(path) = junit {
+                let xml = testing::junit_xml(
+                    &file,
+                    &report.assertions,
+                    report.error.as_deref(),
+                    report.duration,
+                );
+                std::fs::write(path, xml)?;
+            }
+            output::test(&report);
+            if let Some(error) = report.error {
+                return Err(V4Error::Script(error));
+            }
+            if report.failed() > 0 {
+                return Err(V4Error::Script(format!(
+                    "{} of {} assertion(s) failed",
+                    report.failed(),
+                    report.assertions.len()
+                )));
+            }
+        }
+
+        Commands::Repl {
+            port: port_arg,
+            serial,
+            retry,
+            no_reset,
+            simulate,
+            pipe,
+            transcript,
+            replay,
+        } => {
+            let replay = replay.as_deref().map(Transcript::load).transpose()?;
+            let transcript = transcript
+                .as_deref()
+                .map(TranscriptWriter::open)
+                .transpose()?;
+            let device = if simulate {
+                host_engine()?
+            } else {
+                V4Device::open(port(port_arg).as_deref(), &serial.settings(&config)?)?
+                    .with_retry(retry.policy(&config))?
+            };
+            let no_reset = no_reset || config.repl.no_reset.unwrap_or(false);
+            if let Some(replay) = replay {
+                let summary = commands::run_replay(device, no_reset, &replay.steps(), transcript)?;
+                if summary.diverged > 0 {
+                    return Err(V4Error::Repl(format!(
+                        "{} of {} replayed input(s) went differently than recorded",
+                        summary.diverged, summary.inputs
+                    )));
+                }
+                println!("Replayed {} input(s) as recorded", summary.inputs);
+            } else if pipe {
+                let summary = commands::run_pipe(device, no_reset, transcript)?;
+                if summary.failed > 0 {
+                    return Err(V4Error::Repl(format!(
+                        "{} of {} input(s) failed",
+                        summary.failed, summary.inputs
+                    )));
+                }
+            } else {
+                commands::run_repl(device, no_reset, transcript)?
+            }
+        }
+
+        Commands::Learn {
+            port: port_arg,
+            serial,
+            retry,
+            simulate,
+            lesson,
+        } => {
+            let settings = serial.settings(&config)?;
+            let port = port(port_arg);
+            let device = match port {
+                None if simulate
+                    || serial::V4Serial::discover(&settings, DISCOVERY_TIMEOUT)?.is_empty() =>
+                {
+                    if !simulate {
+                        println!("No device found, practicing on the host engine");
+                    }
+                    host_engine()?
+                }
+                port => {
+                    V4Device::open(port.as_deref(), &settings)?.with_retry(retry.policy(&config))?
+                }
+            };
+            commands::run_learn(device, lesson as usize)?;
+        }
+
+        Commands::Exec {
+            files,
+            defines,
+            port: port_arg,
+            group,
+            serial,
+            retry,
+            timeout: timeout_arg,
+            result_timeout,
+            deadline,
+            time,
+            resets,
             repl,
-        } => commands::exec(&file, &port, Duration::from_secs(timeout), repl),
-    };
+            watch,
+            reset_on_change,
+            simulate,
+        } => {
+            // Fail on a missing file before touching the device
+            for file in &files {
+                std::fs::metadata(file)?;
+            }
+            let files: Vec<&str> = files.iter().map(String::as_str).collect();
+            let targets = fanout::targets(&port_arg, group_arg(group)?.as_deref(), &config)?;
+            let (retry, timeout) = (retry.policy(&config), timeout(timeout_arg));
+            let result_wait = match result_timeout {
+                Some(secs) => ResultWait::Within(Duration::from_secs(secs)),
+                None => ResultWait::Forever,
+            };
+            let deadline = deadline.map(Duration::from_secs);
+            let resets = resets.resets();
+
+            if targets.len() > 1 && !simulate {
+                if repl || watch {
+                    return Err(V4Error::Cli(
+                        "--repl and --watch need a single device".to_string(),
+                    ));
+                }
+                let outcomes = fanout::fan_out(&targets, |target| {
+                    let (port, settings) = target_settings(Some(target), &serial, &config)?;
+                    let mut device = V4Device::open(port.as_deref(), &settings)?
+                        .with_retry(retry)?
+                        .with_result_wait(result_wait);
+                    if let Some(limit) = deadline {
+                        device = device.with_deadline(limit);
+                    }
+                    if time {
+                        device = device.with_timing();
+                    }
+                    commands::reset::around(
+                        &mut device,
+                        &resets,
+                        timeout,
+                        &mut |_, _| {},
+                        |device| {
+                            if !defines.is_empty() {
+                                commands::exec::define(device, &defines, timeout)?;
+                            }
+                            files
+                                .iter()
+                                .map(|file| commands::exec(device, file, timeout))
+                                .collect::<v4_cli::Result<Vec<_>>>()
+                        },
+                    )
+                });
+                output::fan_out(&outcomes, |reports| {
+                    let words: usize = reports.iter().map(|r| r.words.len()).sum();
+                    let main: usize = reports.iter().map(|r| r.main_size).sum();
+                    let summary = format!("{} word(s), {} bytes executed", words, main);
+                    if time {
+                        let elapsed = reports.iter().map(|r| r.elapsed).sum();
+                        format!("{} in {}", summary, millis(elapsed))
+                    } else {
+                        summary
+                    }
+                });
+                return fanout::check_outcomes(&outcomes);
+            }
+
+            let mut device = if simulate {
+                host_engine()?
+            } else {
+                let (port, settings) =
+                    target_settings(targets.first().map(String::as_str), &serial, &config)?;
+                V4Device::open(port.as_deref(), &settings)?
+                    .with_retry(retry)?
+                    .with_result_wait(result_wait)
+            };
+            if let Some(limit) = deadline {
+                device = device.with_deadline(limit);
+            }
+            if time {
+                device = device.with_timing();
+            }
+
+            commands::reset::around(
+                &mut device,
+                &resets,
+                timeout,
+                &mut |_, report| output::reset(report),
+                |device| {
+                    if watch {
+                        watch_exec(device, &files, &defines, timeout, reset_on_change, time)?;
+                    }
+                    exec_files(device, &files, &defines, timeout, time)
+                },
+            )?;
+
+            // Enter REPL if requested
+            if repl {
+                println!("\nEntering REPL...");
+                println!("Type 'bye' or press Ctrl+D to exit");
+                println!("Type '.help' for help\n");
+
+                let deadline = device.deadline();
+                let stopwatch = time.then(|| device.stopwatch());
+                let (transport, compiler) = device.parts()?;
+                commands::repl_loop(transport, compiler, deadline, stopwatch, None)?;
+            }
+        }
 
-    if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+        Commands::Build {
+            deny_shadowing,
+            defines,
+            max_size,
+        } => {
+            let project = Project::current()?;
+            let options = CompileOptions {
+                deny_shadowing,
+                defines,
+                max_size,
+                ..CompileOptions::default()
+            };
+            output::build(&project, &commands::build(&project, &options)?);
+        }
+
+        Commands::Update => {
+            let project = Project::current()?;
+            output::dependencies(&commands::build::update(&project)?);
+        }
+
+        Commands::Deploy {
+            port: port_arg,
+            serial,
+            retry,
+            timeout: timeout_arg,
+            no_reset,
+            ready_timeout,
+            deny_shadowing,
+            defines,
+        } => {
+            let project = Project::current()?;
+            let deploy = &project.manifest.deploy;
+            // Flags first, then v4.toml, then the config file
+            let port = port_arg
+                .or_else(|| deploy.port.clone())
+                .or(config.port.clone());
+            let mut settings = serial.settings(&config)?;
+            if let (None, Some(baud)) = (serial.baud, deploy.baud_rate) {
+                settings.baud_rate = baud;
+            }
+            let target = commands::build::DeployTarget {
+                port: port.as_deref(),
+                settings: &settings,
+                retry: retry.policy(&config),
+                timeout: timeout(timeout_arg),
+                reset: (deploy.reset && !no_reset).then(|| Duration::from_secs(ready_timeout)),
+            };
+
+            let options = CompileOptions {
+                deny_shadowing,
+                defines,
+                ..CompileOptions::default()
+            };
+            let build = commands::build(&project, &options)?;
+            output::build(&project, &build);
+
+            let mut pb = None;
+            let report = commands::deploy(&project, &build.compile, &target, &mut |sent, total| {
+                pb.get_or_insert_with(|| output::progress_bar(total))
+                    .set_position(sent as u64)
+            });
+            if let Some(pb) = pb {
+                match &report {
+                    Ok(_) => pb.finish_with_message("Complete"),
+                    Err(_) => pb.abandon_with_message("Failed"),
+                }
+            }
+            output::deploy(&build.compile.output.to_string_lossy(), &report?);
+        }
+
+        Commands::New { path, project } => {
+            output::new_project(&commands::new_project(&path, &project.options())?);
+        }
+
+        Commands::Init { path, project } => {
+            output::new_project(&commands::init(&path, &project.options())?);
+        }
+
+        Commands::Config { action } => match action {
+            ConfigAction::Get { key } => output::config_get(commands::config_get(&key)?.as_deref()),
+            ConfigAction::Set { key, value } => {
+                let path = commands::config_set(&key, &value)?;
+                output::config_set(&key, &value, &path);
+            }
+            ConfigAction::List => {
+                let (path, values) = commands::config_list()?;
+                output::config_list(&path, &values);
+            }
+        },
+
+        Commands::Trace { action } => match action {
+            TraceAction::Show { file, hex } => output::trace(&Trace::load(&file)?, hex),
+        },
+
+        Commands::Completions { shell } => {
+            completions::write(shell, &mut Cli::command(), &mut std::io::stdout())?;
+        }
+
+        Commands::Mangen { dir } => {
+            let pages = manpages::write_all(Cli::command(), dir.as_ref())?;
+            output::man_pages(&dir, pages.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Device backed by the V4 engine linked into `v4`
+fn host_engine() -> v4_cli::Result<V4Device> {
+    Ok(V4Device::from_transport(
+        Box::new(EngineTransport::new()?),
+        "engine",
+    ))
+}
+
+/// Re-run the file every time it changes, until interrupted
+fn watch_exec(
+    device: &mut V4Device,
+    files: &[&str],
+    defines: &[Define],
+    timeout: Duration,
+    reset_on_change: bool,
+    time: bool,
+) -> v4_cli::Result<()> {
+    let mut watcher = commands::exec::FileWatcher::new()?;
+    watcher.watch(&commands::exec::watched_files(files))?;
+
+    println!(
+        "Watching {} for changes (Ctrl+C to stop)\n",
+        files.join(", ")
+    );
+
+    loop {
+        // Errors are reported but don't stop watching
+        if let Err(e) = exec_files(device, files, defines, timeout, time) {
+            eprintln!("{} {}", ui::error_label(), e);
+        }
+
+        // Includes may have changed with the last edit
+        watcher.watch(&commands::exec::watched_files(files))?;
+        println!("\nWaiting for changes...");
+        watcher.wait()?;
+        println!("\nSource changed");
+
+        if reset_on_change {
+            println!("Resetting device...");
+            if let Err(e) = device.reset(timeout, timeout) {
+                eprintln!("Warning: {}", e);
+            }
+        }
+    }
+}
+
+/// Run source files in order after the defines, stopping at the first failure
+fn exec_files(
+    device: &mut V4Device,
+    files: &[&str],
+    defines: &[Define],
+    timeout: Duration,
+    time: bool,
+) -> v4_cli::Result<()> {
+    if !defines.is_empty() {
+        log::info!("Defining {} constant(s)", defines.len());
+        commands::exec::define(device, defines, timeout)?;
+    }
+    for file in files {
+        println!("Compiling {}...", file);
+        output::exec(&commands::exec(device, file, timeout)?, time);
     }
+    Ok(())
 }
@@ -0,0 +1,90 @@
+//! Man pages and `--help-long` for the `v4` binary
+//!
+//! Both are rendered from the clap definitions, so they never drift from the
+//! options the binary actually accepts.
+
+use clap::Command;
+use clap_mangen::Man;
+use std::path::{Path, PathBuf};
+
+/// Write `v4.1` and a `v4-<subcommand>.1` page per visible subcommand to `dir`
+///
+/// Returns the paths written, the tool's own page first.
+pub fn write_all(mut cmd: Command, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    let mut written = Vec::new();
+    write_tree(&cmd, dir, &mut written)?;
+    Ok(written)
+}
+
+fn write_tree(cmd: &Command, dir: &Path, written: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    written.push(Man::new(cmd.clone()).generate_to(dir)?);
+    for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+        write_tree(sub, dir, written)?;
+    }
+    Ok(())
+}
+
+/// Long help of the tool followed by that of every visible subcommand
+pub fn help_long(mut cmd: Command) -> String {
+    cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    let mut text = String::new();
+    help_tree(&mut cmd, &mut text);
+    text
+}
+
+fn help_tree(cmd: &mut Command, text: &mut String) {
+    if !text.is_empty() {
+        let name = cmd
+            .get_display_name()
+            .unwrap_or(cmd.get_name())
+            .replace('-', " ");
+        text.push_str(&format!("\n{}\n{}\n\n", name, "=".repeat(name.len())));
+    }
+    text.push_str(&cmd.render_long_help().to_string());
+    for sub in cmd.get_subcommands_mut().filter(|s| !s.is_hide_set()) {
+        help_tree(sub, text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn cli() -> Command {
+        Command::new("v4")
+            .about("CLI tool")
+            .subcommand(
+                Command::new("trace")
+                    .about("Inspect captures")
+                    .subcommand(Command::new("show").arg(Arg::new("file").required(true))),
+            )
+            .subcommand(Command::new("mangen").hide(true))
+    }
+
+    #[test]
+    fn test_write_all_names_pages_after_subcommands() {
+        let dir = tempfile::tempdir().unwrap();
+        let written = write_all(cli(), dir.path()).unwrap();
+        let names: Vec<_> = written
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["v4.1", "v4-trace.1", "v4-trace-show.1"]);
+        let page = std::fs::read_to_string(dir.path().join("v4-trace-show.1")).unwrap();
+        assert!(page.starts_with(".ie"));
+        assert!(page.contains("v4\\-trace\\-show"));
+    }
+
+    #[test]
+    fn test_help_long_covers_nested_subcommands() {
+        let text = help_long(cli());
+        assert!(text.starts_with("CLI tool"));
+        assert!(text.contains("\nv4 trace show\n=============\n"));
+        assert!(!text.contains("mangen"));
+    }
+}
@@ -0,0 +1,199 @@
+//! Passive decoding of the device's serial stream
+//!
+//! Splits received bytes into V4-link frames and plain text lines printed
+//! by the firmware (boot messages, logs). Frames are recognized by STX and
+//! a plausible length; anything else is text.
+
+use crate::protocol::{Checksum, ErrorCode, Frame, Incoming};
+
+/// Start-of-frame marker
+const STX: u8 = 0xA5;
+
+/// What a monitor event carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Output notification frame
+    Output,
+    /// Response frame
+    Response,
+    /// Text line outside of frames
+    Text,
+}
+
+impl EventKind {
+    /// Short label for listings
+    pub fn label(self) -> &'static str {
+        match self {
+            EventKind::Output => "output",
+            EventKind::Response => "response",
+            EventKind::Text => "text",
+        }
+    }
+}
+
+/// Parse a `--filter` value
+pub fn parse_kind(value: &str) -> std::result::Result<EventKind, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "output" => Ok(EventKind::Output),
+        "response" => Ok(EventKind::Response),
+        "text" => Ok(EventKind::Text),
+        _ => Err(format!(
+            "invalid frame type '{}' (expected output, response or text)",
+            value
+        )),
+    }
+}
+
+/// One decoded frame or text line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Bytes as received (whole frame, or line including its newline)
+    pub raw: Vec<u8>,
+    /// Output text, response payload, or the line without its line ending
+    pub data: Vec<u8>,
+    /// Error code of a response
+    pub error_code: Option<ErrorCode>,
+}
+
+/// Incremental stream decoder
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Bytes not yet consumed
+    pending: Vec<u8>,
+    /// Text line in progress
+    line: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed received bytes, returning every event they complete
+    pub fn push(&mut self, data: &[u8]) -> Vec<Event> {
+        self.pending.extend_from_slice(data);
+        let mut events = Vec::new();
+
+        while let Some(&first) = self.pending.first() {
+            if first == STX {
+                if self.pending.len() < 3 {
+                    break;
+                }
+                let length = u16::from_le_bytes([self.pending[1], self.pending[2]]) as usize;
+                // Frames carry at least a code byte and at most a full payload
+                // plus code and SEQ; anything else is a text byte
+                if length == 0 || length > Frame::MAX_LENGTH {
+                    self.line.push(self.pending.remove(0));
+                    continue;
+                }
+                let total = 4 + length;
+                if self.pending.len() < total {
+                    break;
+                }
+
+                let raw: Vec<u8> = self.pending.drain(..total).collect();
+                match Frame::decode_incoming(&raw, false, Checksum::Crc8) {
+                    Ok(Incoming::Output(text)) => events.push(Event {
+                        kind: EventKind::Output,
+                        raw,
+                        data: text,
+                        error_code: None,
+                    }),
+                    Ok(Incoming::Response(response)) => events.push(Event {
+                        kind: EventKind::Response,
+                        raw,
+                        data: response.data,
+                        error_code: Some(response.error_code),
+                    }),
+                    Err(e) => log::warn!("Dropped corrupt frame ({}): {:02X?}", e, raw),
+                }
+                continue;
+            }
+
+            // Text runs until a newline or the next possible frame
+            match self.pending.iter().position(|&b| b == b'\n' || b == STX) {
+                Some(pos) if self.pending[pos] == b'\n' => {
+                    self.line.extend(self.pending.drain(..=pos));
+                    events.push(self.take_line());
+                }
+                Some(pos) => self.line.extend(self.pending.drain(..pos)),
+                None => self.line.append(&mut self.pending),
+            }
+        }
+
+        events
+    }
+
+    fn take_line(&mut self) -> Event {
+        let raw = std::mem::take(&mut self.line);
+        let data = raw
+            .strip_suffix(b"\n")
+            .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+            .unwrap_or(&raw)
+            .to_vec();
+        Event {
+            kind: EventKind::Text,
+            raw,
+            data,
+            error_code: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{NOTIFY_OUTPUT, device_frame};
+
+    #[test]
+    fn test_text_and_frames() {
+        let mut stream = b"boot ok\r\nready".to_vec();
+        stream.extend(device_frame(NOTIFY_OUTPUT, b"hi"));
+        stream.extend(b"\n");
+        stream.extend(device_frame(0x00, &[]));
+
+        let events = Decoder::new().push(&stream);
+        let kinds: Vec<EventKind> = events.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::Text,
+                EventKind::Output,
+                EventKind::Text,
+                EventKind::Response
+            ]
+        );
+        assert_eq!(events[0].data, b"boot ok");
+        assert_eq!(events[1].data, b"hi");
+        // The frame interrupted "ready", which continues up to the newline
+        assert_eq!(events[2].data, b"ready");
+        assert_eq!(events[3].error_code, Some(ErrorCode::Ok));
+    }
+
+    #[test]
+    fn test_split_frame() {
+        let bytes = device_frame(NOTIFY_OUTPUT, b"abc");
+        let mut decoder = Decoder::new();
+        assert!(decoder.push(&bytes[..2]).is_empty());
+        assert!(decoder.push(&bytes[2..5]).is_empty());
+        let events = decoder.push(&bytes[5..]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].raw, bytes);
+    }
+
+    #[test]
+    fn test_stray_stx_is_text() {
+        // 0xA5 followed by an implausible length
+        let events = Decoder::new().push(&[b'x', STX, 0xFF, 0xFF, b'\n']);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, EventKind::Text);
+        assert_eq!(events[0].data, vec![b'x', STX, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_parse_kind() {
+        assert_eq!(parse_kind("Output"), Ok(EventKind::Output));
+        assert!(parse_kind("frames").is_err());
+    }
+}
@@ -0,0 +1,916 @@
+//! Terminal output for the `v4` binary
+//!
+//! The library returns reports; everything printed by one-shot commands is
+//! formatted here.
+
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use v4_cli::V4Error;
+use v4_cli::bytecode::SUPPORTED_VERSION;
+use v4_cli::commands::bench::BenchReport;
+use v4_cli::commands::build::{BuildReport, DeployReport};
+use v4_cli::commands::compile::{self, CheckReport, CompileReport};
+use v4_cli::commands::compiled::FileCheck;
+use v4_cli::commands::disasm::Disassembly;
+use v4_cli::commands::fanout::DeviceOutcome;
+use v4_cli::commands::flash::{self, FlashReport, FlashStage};
+use v4_cli::commands::inspect::Inspection;
+use v4_cli::commands::memdiff::{self, MemDiff};
+use v4_cli::commands::new::NewReport;
+use v4_cli::commands::ports::PortEntry;
+use v4_cli::commands::push::{Compatibility, Problem, Verification};
+use v4_cli::commands::run::RunReport;
+use v4_cli::commands::script::ScriptReport;
+use v4_cli::commands::size::SizeReport;
+use v4_cli::commands::test::TestReport;
+use v4_cli::device::{self, DeviceInfo, ExecReport, PushReport, ResetReport};
+use v4_cli::disasm;
+use v4_cli::error_explain::{self, Explanation};
+use v4_cli::format::{self, OutputFormat};
+use v4_cli::monitor::{Event, EventKind};
+use v4_cli::project::Project;
+use v4_cli::project::deps::{Package, Source};
+use v4_cli::protocol::{Incoming, MemoryDump, StackSnapshot, WordInfo};
+use v4_cli::repl::ShadowedWord;
+use v4_cli::syscalls;
+use v4_cli::trace::{Decoded, Trace, TraceEvent};
+use v4_cli::ui;
+
+/// Whether progress bars are drawn (`--no-progress`, `--quiet`)
+static SHOW_PROGRESS: AtomicBool = AtomicBool::new(true);
+
+pub fn hide_progress() {
+    SHOW_PROGRESS.store(false, Ordering::Relaxed);
+}
+
+/// Byte progress bar for bytecode transfers
+///
+/// Advances per acknowledged chunk and shows the transfer rate and ETA.
+/// indicatif already hides it when stderr is not a terminal.
+pub fn progress_bar(total: usize) -> ProgressBar {
+    if !SHOW_PROGRESS.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new(total as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta} {msg}",
+            )
+            .unwrap()
+            .progress_chars("=>-"),
+    );
+    pb.set_message("Sending...");
+    pb
+}
+
+pub fn push(file: &str, report: &PushReport, detach: bool) {
+    println!(
+        "Loaded bytecode from {} ({} bytes total)",
+        file, report.size
+    );
+
+    if detach {
+        println!("Bytecode sent to device (not waiting for response)");
+        return;
+    }
+
+    println!("{} Bytecode deployed successfully", ui::success());
+    if let Some(stats) = &report.compression {
+        println!(
+            "  Compressed {} -> {} bytes ({:.0}%), about {:.1}s saved",
+            stats.size,
+            stats.packed,
+            stats.ratio() * 100.0,
+            stats.time_saved().as_secs_f64()
+        );
+    }
+    if !report.word_indices.is_empty() {
+        println!("  Registered {} word(s)", report.word_indices.len());
+        for (i, idx) in report.word_indices.iter().enumerate() {
+            println!("    Word #{} registered at index {}", i, idx);
+        }
+    }
+}
+
+/// Warn before pushing a file whose version wasn't or can't be checked
+pub fn compatibility(compatibility: &Compatibility) {
+    if let Some(warning) = compatibility.warning() {
+        log::warn!("{}", warning);
+    }
+}
+
+/// Pushed words read back with `--verify`
+pub fn verification(verification: &Verification) {
+    if verification.mismatches.is_empty() {
+        println!(
+            "{} Verified {} word(s) against the file",
+            ui::success(),
+            verification.checked
+        );
+        return;
+    }
+    for mismatch in &verification.mismatches {
+        let problem = match &mismatch.problem {
+            Problem::Missing => "not found on the device".to_string(),
+            Problem::Name(name) => format!(
+                "device calls it {}",
+                name.as_deref().unwrap_or("<anonymous>")
+            ),
+            Problem::Length { sent, device } => {
+                format!("{} bytes on the device, {} sent", device, sent)
+            }
+            Problem::Bytes(offsets) => {
+                let mut shown: Vec<String> = offsets
+                    .iter()
+                    .take(8)
+                    .map(|offset| format!("0x{:04X}", offset))
+                    .collect();
+                if offsets.len() > shown.len() {
+                    shown.push("...".to_string());
+                }
+                format!(
+                    "{} byte(s) differ at offset {}",
+                    offsets.len(),
+                    shown.join(", ")
+                )
+            }
+            Problem::Truncated { received } => {
+                format!("only the first {} bytes could be read back", received)
+            }
+        };
+        println!(
+            "{} Word #{} {}: {}",
+            ui::failure(),
+            mismatch.index,
+            mismatch.name,
+            problem
+        );
+    }
+}
+
+/// Words `v4 pull` read from the device and saved to `path`
+pub fn pulled(words: &[WordInfo], path: &str) {
+    let size: usize = words.iter().map(|word| word.code.len()).sum();
+    println!(
+        "{} Pulled {} word(s), {} bytes of bytecode, to {}",
+        ui::success(),
+        words.len(),
+        size,
+        path
+    );
+    for (idx, word) in words.iter().enumerate() {
+        println!(
+            "{:>5}  {:<20}  {:>5}",
+            idx,
+            word.display_name(),
+            word.code.len()
+        );
+    }
+}
+
+/// Report a firmware update stage, driving the write progress bar
+pub fn flash_stage(stage: FlashStage, pb: &mut Option<ProgressBar>) {
+    match stage {
+        FlashStage::Connected { chip } => println!("Connected to {} bootloader", chip),
+        FlashStage::Writing { written, total } => {
+            let pb = pb.get_or_insert_with(|| {
+                let pb = progress_bar(total);
+                pb.set_message("Writing...");
+                pb
+            });
+            pb.set_position(written as u64);
+            if written == total {
+                pb.finish_with_message("Written");
+            }
+        }
+        FlashStage::Verifying => println!("Verifying..."),
+    }
+}
+
+pub fn flash(file: &str, report: &FlashReport) {
+    println!(
+        "{} Flashed {} ({} bytes) at 0x{:06X} on {}",
+        ui::success(),
+        file,
+        report.size,
+        report.offset,
+        report.port
+    );
+    match report.md5 {
+        Some(md5) => println!("  Verified, MD5 {}", flash::hex(&md5)),
+        None => println!("  Not verified (--no-verify)"),
+    }
+}
+
+pub fn ping(port: &str) {
+    println!("{} Device on {} is responding", ui::success(), port);
+}
+
+pub fn bench(report: &BenchReport) {
+    let ms = |d: Duration| format!("{:.2} ms", d.as_secs_f64() * 1000.0);
+    let rate = |r: f64| format!("{}/s", HumanBytes(r as u64));
+
+    if let Some(latency) = &report.latency {
+        println!(
+            "PING latency ({} answered, {} lost)",
+            latency.samples, latency.lost
+        );
+        println!(
+            "  min {}  avg {}  p99 {}  max {}",
+            ms(latency.min),
+            ms(latency.avg),
+            ms(latency.p99),
+            ms(latency.max)
+        );
+    }
+    if !report.exec.is_empty() {
+        println!("EXEC throughput");
+        println!(
+            "  {:>10}  {:>6}  {:>10}  {:>12}",
+            "Size", "Rounds", "Avg", "Rate"
+        );
+        for sample in &report.exec {
+            println!(
+                "  {:>10}  {:>6}  {:>10}  {:>12}",
+                HumanBytes(sample.size as u64).to_string(),
+                sample.rounds,
+                ms(sample.avg),
+                rate(sample.bytes_per_sec)
+            );
+        }
+    }
+    if let Some(transfer) = &report.transfer {
+        println!(
+            "Sustained transfer: {} in {:.2} s ({})",
+            HumanBytes(transfer.size as u64),
+            transfer.elapsed.as_secs_f64(),
+            rate(transfer.bytes_per_sec)
+        );
+    }
+}
+
+pub fn info(info: &DeviceInfo) {
+    print!("{}", info);
+}
+
+/// Memory read by `v4 dump`, in the chosen format
+pub fn memory(memory: &MemoryDump, output: &OutputFormat) {
+    print!("{}", format::memory(memory, output));
+}
+
+/// Stacks read by `v4 stack`
+pub fn stack(stack: &StackSnapshot, output: &OutputFormat) {
+    print!("{}", format::stack(stack, output));
+}
+
+/// Level of a pin after `v4 gpio`
+pub fn gpio(pin: u8, level: bool) {
+    println!("GPIO {}: {}", pin, if level { "high" } else { "low" });
+}
+
+/// Memory `v4 dump` wrote to a file; `requested` bytes were asked for
+pub fn dump_saved(dump: &MemoryDump, path: &str, requested: u32) {
+    println!(
+        "Saved {} bytes from 0x{:08X} to {}",
+        dump.bytes.len(),
+        dump.addr,
+        path
+    );
+    if dump.bytes.len() < requested as usize {
+        println!(
+            "  Memory ends at 0x{:08X}, {} bytes short of the request",
+            dump.end(),
+            requested as usize - dump.bytes.len()
+        );
+    }
+}
+
+/// Changed rows between two snapshot files
+pub fn memdiff(diff: &MemDiff, old: &str, new: &str) {
+    if diff.changed() == 0 {
+        println!("{} and {} are identical", old, new);
+        return;
+    }
+    println!("{} {}", ui::removed("---"), old);
+    println!("{} {}", ui::added("+++"), new);
+    print!("{}", memdiff::format(diff));
+    let hunks = diff.hunks().len();
+    println!("{} byte(s) changed in {} range(s)", diff.changed(), hunks);
+    if diff.old.len() != diff.new.len() {
+        println!(
+            "  Sizes differ: {} bytes before, {} after",
+            diff.old.len(),
+            diff.new.len()
+        );
+    }
+}
+
+/// Per-device results of a command run on several devices
+pub fn fan_out<T>(outcomes: &[DeviceOutcome<T>], describe: impl Fn(&T) -> String) {
+    for outcome in outcomes {
+        match &outcome.result {
+            Ok(value) => println!("{} {}: {}", ui::success(), outcome.port, describe(value)),
+            Err(e) => println!("{} {}: {}", ui::failure(), outcome.port, e),
+        }
+    }
+    let succeeded = outcomes.iter().filter(|o| o.result.is_ok()).count();
+    println!("{}/{} device(s) succeeded", succeeded, outcomes.len());
+}
+
+pub fn reset(report: &ResetReport) {
+    println!("{} VM reset successful", ui::success());
+    println!(
+        "{} Device ready after {} ms",
+        ui::success(),
+        report.ready_after.as_millis()
+    );
+}
+
+pub fn compile(inputs: &[&str], report: &CompileReport) {
+    let names: Vec<&str> = inputs
+        .iter()
+        .map(|&i| if i == compile::STDIN { "<stdin>" } else { i })
+        .collect();
+    println!(
+        "Compiled {} ({} bytes)",
+        names.join(" + "),
+        report.source_size
+    );
+    shadowed_words(&report.shadowed);
+    println!("{} Compilation successful", ui::success());
+    if let Some(sizes) = report.optimized {
+        println!(
+            "{} Optimized bytecode from {} to {} bytes ({})",
+            ui::success(),
+            sizes.before,
+            sizes.after,
+            percent_change(sizes.before, sizes.after)
+        );
+    }
+    if let Some(budget) = report.budget {
+        println!(
+            "{} Bytecode uses {} of {} bytes ({:.1}%)",
+            ui::success(),
+            budget.used,
+            budget.max,
+            budget.used as f64 * 100.0 / budget.max.max(1) as f64
+        );
+    }
+    println!(
+        "{} Bytecode saved to {} ({} bytes)",
+        ui::success(),
+        report.output.display(),
+        report.output_size
+    );
+    if let Some(listing) = &report.listing {
+        println!("{} Listing written to {}", ui::success(), listing.display());
+    }
+    if let Some(map) = &report.source_map {
+        println!("{} Source map written to {}", ui::success(), map.display());
+    }
+}
+
+/// `-12.5%`, or `no change`
+fn percent_change(before: usize, after: usize) -> String {
+    if before == after || before == 0 {
+        return "no change".to_string();
+    }
+    let change = (after as f64 - before as f64) / before as f64 * 100.0;
+    format!("{:+.1}%", change)
+}
+
+pub fn build(project: &Project, report: &BuildReport) {
+    let package = &project.manifest.package;
+    println!(
+        "Building {} v{} ({})",
+        package.name,
+        package.version,
+        project.root.display()
+    );
+    dependencies(&report.dependencies);
+    let inputs: Vec<String> = report
+        .inputs
+        .iter()
+        .map(|input| {
+            let input = input.strip_prefix(&project.root).unwrap_or(input);
+            input.display().to_string()
+        })
+        .collect();
+    let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+    compile(&inputs, &report.compile);
+}
+
+pub fn dependencies(packages: &[Package]) {
+    for package in packages {
+        let source = match &package.source {
+            Source::Path(path) => path.display().to_string(),
+            Source::Git { url, commit } => format!("{}#{}", url, &commit[..commit.len().min(10)]),
+        };
+        println!("  {} {} ({})", ui::success(), package.name, source);
+    }
+}
+
+pub fn deploy(file: &str, report: &DeployReport) {
+    if let Some(reset) = &report.reset {
+        self::reset(reset);
+    }
+    push(file, &report.push, false);
+}
+
+/// Warn about words defined more than once (stderr, safe with `--stdout`)
+pub fn shadowed_words(shadowed: &[ShadowedWord]) {
+    for word in shadowed {
+        match &word.winner {
+            Some(location) => log::warn!(
+                "word '{}' is defined {} times; the definition at {} wins",
+                word.name,
+                word.count,
+                location
+            ),
+            None => log::warn!(
+                "word '{}' is defined {} times; the last definition wins",
+                word.name,
+                word.count
+            ),
+        }
+    }
+}
+
+/// Write compiled bytecode to stdout for piping
+pub fn bytecode(bytes: &[u8]) -> v4_cli::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(bytes)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Print what `exec` sent; with `time`, how long each step took
+pub fn exec(report: &ExecReport, time: bool) {
+    if !report.words.is_empty() {
+        println!("Compiled {} word(s)", report.words.len());
+        for word in &report.words {
+            if time {
+                println!(
+                    "  Word '{}' registered at index {} ({} bytes, {})",
+                    word.name, word.index, word.size, word.timing
+                );
+            } else {
+                println!(
+                    "  Word '{}' registered at index {} ({} bytes)",
+                    word.name, word.index, word.size
+                );
+            }
+        }
+    }
+
+    match report.main_timing {
+        Some(timing) if time => {
+            println!(
+                "Execution complete ({} bytes, {})",
+                report.main_size, timing
+            )
+        }
+        _ if report.main_size > 0 => println!("Execution complete ({} bytes)", report.main_size),
+        _ if !report.words.is_empty() => println!("Word definitions complete"),
+        _ => {}
+    }
+    if time {
+        println!("Total: {}", device::millis(report.elapsed));
+    }
+}
+
+pub fn script(file: &str, report: &ScriptReport) {
+    println!(
+        "{} Script {} passed: {} line(s), {} check(s)",
+        ui::success(),
+        file,
+        report.lines,
+        report.checks
+    );
+    if report.ignored_errors > 0 {
+        println!("  {} error(s) ignored on '~' lines", report.ignored_errors);
+    }
+}
+
+pub fn test(report: &TestReport) {
+    println!();
+    for assertion in &report.assertions {
+        match &assertion.failure {
+            None => println!(
+                "{} line {}: {}",
+                ui::success(),
+                assertion.line,
+                assertion.source
+            ),
+            Some(failure) => println!(
+                "{} line {}: {}\n    {}",
+                ui::failure(),
+                assertion.line,
+                assertion.source,
+                failure
+            ),
+        }
+    }
+    let mark = if report.success() {
+        ui::success()
+    } else {
+        ui::failure()
+    };
+    println!(
+        "{} {}: {} passed, {} failed ({:.2}s)",
+        mark,
+        report.file,
+        report.passed(),
+        report.failed(),
+        report.duration.as_secs_f64()
+    );
+}
+
+pub fn check(report: &CheckReport, json: bool) -> v4_cli::Result<()> {
+    if json {
+        let out = serde_json::to_string_pretty(&report.diagnostics)
+            .map_err(|e| v4_cli::V4Error::Cli(e.to_string()))?;
+        println!("{}", out);
+        return Ok(());
+    }
+
+    for diagnostic in &report.diagnostics {
+        println!("{}", diagnostic);
+    }
+    let mark = if report.errors() == 0 {
+        ui::success()
+    } else {
+        ui::failure()
+    };
+    println!(
+        "{} {} error(s), {} warning(s)",
+        mark,
+        report.errors(),
+        report.warnings()
+    );
+    Ok(())
+}
+
+/// One JSON object per line, flushed so editors see each as it comes
+pub fn file_checks(checks: &[FileCheck]) -> v4_cli::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    for check in checks {
+        let line = serde_json::to_string(check).map_err(|e| v4_cli::V4Error::Cli(e.to_string()))?;
+        writeln!(stdout, "{}", line)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+pub fn ports(entries: &[PortEntry], json: bool) -> v4_cli::Result<()> {
+    if json {
+        let out = serde_json::to_string_pretty(entries)
+            .map_err(|e| v4_cli::V4Error::Cli(e.to_string()))?;
+        println!("{}", out);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No serial ports found");
+        return Ok(());
+    }
+
+    for entry in entries {
+        println!("{}", entry.describe());
+    }
+    Ok(())
+}
+
+pub fn disasm(file: &str, result: &Disassembly) {
+    match &result.header {
+        Some(header) => {
+            println!("File: {}", file);
+            println!("Format: .v4b v{}.{}", header.version.0, header.version.1);
+            if header.version > SUPPORTED_VERSION {
+                println!(
+                    "Warning: format is newer than v{}.{}, the newest this tool knows",
+                    SUPPORTED_VERSION.0, SUPPORTED_VERSION.1
+                );
+            }
+            println!("Code size: {} bytes", header.code_size);
+            if let Some(word_count) = header.word_count {
+                println!("Word definitions: {}", word_count);
+            }
+            if result.is_truncated() {
+                println!(
+                    "Warning: header code size exceeds file ({} bytes available)",
+                    result.code.len()
+                );
+            }
+            if result.trailing > 0 {
+                println!(
+                    "Trailing data: {} bytes after code (not disassembled)",
+                    result.trailing
+                );
+            }
+        }
+        None => println!("File: {} (raw bytecode, {} bytes)", file, result.code.len()),
+    }
+
+    println!();
+    if result.code.is_empty() {
+        println!("No bytecode");
+    } else {
+        print!("{}", disasm::listing(&result.code));
+    }
+}
+
+pub fn inspect(file: &str, result: &Inspection) {
+    let header = &result.header;
+    let summary = &result.summary;
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+
+    println!("File:      {} ({} bytes)", file, result.file_size);
+    println!(
+        "Format:    .v4b v{}.{}, flags 0x{:04X}",
+        header.version.0, header.version.1, header.flags
+    );
+    println!("Checksum:  0x{:08X} (CRC-32)", result.checksum);
+    println!("Code:      {} bytes", summary.code_size);
+
+    println!("\nWords ({}):", result.words.len());
+    if result.words.is_empty() {
+        println!("  <none>");
+    }
+    for (i, word) in result.words.iter().enumerate() {
+        println!(
+            "  {:>3}  {:<20} {:>5} bytes",
+            i,
+            word.name,
+            word.bytecode.len()
+        );
+    }
+
+    println!("\nDisassembly:");
+    println!("  Instructions:  {}", summary.instructions);
+    println!("  Invalid bytes: {}", summary.invalid_bytes);
+    println!(
+        "  Calls:         {}",
+        list(summary.calls.iter().map(|i| format!("#{}", i)).collect())
+    );
+    println!(
+        "  System calls:  {}",
+        list(
+            summary
+                .syscalls
+                .iter()
+                .map(|&n| match syscalls::lookup(n) {
+                    Some(syscall) => format!("{} ({})", n, syscall.name),
+                    None => n.to_string(),
+                })
+                .collect()
+        )
+    );
+}
+
+pub fn size(file: &str, report: &SizeReport) {
+    let breakdown = &report.breakdown;
+    let total = breakdown.total();
+    let share = |bytes: usize| bytes as f64 * 100.0 / total.max(1) as f64;
+    let dict = |bytes: usize| {
+        report
+            .dict_share(bytes)
+            .map(|share| format!(" {:>6.1}%", share))
+            .unwrap_or_default()
+    };
+
+    println!("File: {} ({} bytes)\n", file, report.file_size);
+    let dict_header = match report.dict_size {
+        Some(_) => format!(" {:>7}", "Dict"),
+        None => String::new(),
+    };
+    println!("{:>7} {:>6}{}  Word", "Bytes", "%", dict_header);
+    for (name, bytes) in breakdown.rows() {
+        println!(
+            "{:>7} {:>5.1}%{}  {}",
+            bytes,
+            share(bytes),
+            dict(bytes),
+            name
+        );
+    }
+    println!(
+        "{:>7} {:>5.1}%{}  total ({} words)",
+        total,
+        share(total),
+        dict(total),
+        breakdown.words.len()
+    );
+
+    if let Some(dict_size) = report.dict_size
+        && total > dict_size
+    {
+        println!(
+            "\nWarning: bytecode is {} bytes larger than the {}-byte dictionary",
+            total - dict_size,
+            dict_size
+        );
+    }
+}
+
+pub fn explain(explanation: &Explanation) {
+    println!("{}", explanation);
+}
+
+/// After a device error: its explanation with `--explain`, otherwise how to get it
+pub fn explain_error(error: &V4Error, explain: bool) {
+    let Some(code) = error_explain::code_of(error) else {
+        return;
+    };
+    if explain {
+        eprintln!("\n{}", error_explain::explain(code));
+    } else {
+        eprintln!(
+            "For more information about this error, try `v4 explain {}`",
+            code.name()
+        );
+    }
+}
+
+pub fn run(report: &RunReport) {
+    if report.words > 0 {
+        println!("Loaded {} word(s)", report.words);
+    }
+
+    match &report.error {
+        Some(error) => println!("{} Stopped: {}", ui::failure(), error),
+        None => println!("{} Finished", ui::success()),
+    }
+
+    let cells: String = report
+        .data_stack
+        .iter()
+        .map(|v| format!(" {}", v))
+        .collect();
+    println!("Stack: <{}>{}", report.data_stack.len(), cells);
+}
+
+pub fn new_project(report: &NewReport) {
+    println!(
+        "{} Created project '{}' in {}",
+        ui::success(),
+        report.name,
+        report.root.display()
+    );
+    for file in &report.created {
+        println!("  {}", file.display());
+    }
+}
+
+pub fn config_get(value: Option<&str>) {
+    println!("{}", value.unwrap_or("(unset)"));
+}
+
+pub fn config_set(key: &str, value: &str, path: &Path) {
+    println!("{} {} = {} ({})", ui::success(), key, value, path.display());
+}
+
+pub fn config_list(path: &Path, values: &[(&str, Option<String>)]) {
+    println!("# {}", path.display());
+    for (key, value) in values {
+        println!("{} = {}", key, value.as_deref().unwrap_or("(unset)"));
+    }
+}
+
+pub fn man_pages(dir: &str, count: usize) {
+    println!("{} {} man pages written to {}", ui::success(), count, dir);
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    bytes.join(" ")
+}
+
+/// `v4 daemon` is listening
+pub fn daemon(port: &str, socket: &Path) {
+    println!("Serving {} on {}", port, socket.display());
+    println!(
+        "Other v4 commands with --port {} go through this daemon (Ctrl+C to stop)",
+        port
+    );
+}
+
+/// A client of `v4 daemon` connected or left
+pub fn daemon_client(connected: bool) {
+    if connected {
+        println!("Client connected");
+    } else {
+        println!("Client disconnected");
+    }
+}
+
+/// Print bytes from `v4 monitor --raw`; `false` once stdout is gone
+pub fn monitor_raw(data: &[u8], as_hex: bool) -> bool {
+    let mut stdout = std::io::stdout().lock();
+    let written = if as_hex {
+        writeln!(stdout, "{}", hex(data))
+    } else {
+        stdout.write_all(data)
+    };
+    written.and_then(|_| stdout.flush()).is_ok()
+}
+
+/// Print a frame capture from `v4 trace show`, one line per record
+pub fn trace(trace: &Trace, as_hex: bool) {
+    let width = trace
+        .records
+        .iter()
+        .map(|r| r.port.len())
+        .max()
+        .unwrap_or(0);
+    for (record, decoded) in trace.decode() {
+        let arrow = match record.event {
+            TraceEvent::Sent { .. } => "->",
+            _ => "<-",
+        };
+        let body = match (&record.event, decoded) {
+            (
+                _,
+                Some(Decoded::Request {
+                    code,
+                    command,
+                    seq,
+                    payload,
+                }),
+            ) => {
+                let name =
+                    command.map_or_else(|| format!("0x{:02X}", code), |c| format!("{:?}", c));
+                with_seq_and_data(name, seq, &payload)
+            }
+            (_, Some(Decoded::Incoming(Incoming::Response(response)))) => with_seq_and_data(
+                response.error_code.name().to_string(),
+                response.seq,
+                &response.data,
+            ),
+            (_, Some(Decoded::Incoming(Incoming::Output(text)))) => {
+                format!("output {:?}", String::from_utf8_lossy(&text))
+            }
+            (_, Some(Decoded::Invalid(e))) => format!("invalid frame: {}", e),
+            (TraceEvent::Error { message }, None) => format!("error: {}", message),
+            (_, None) => "timeout".to_string(),
+        };
+        println!(
+            "[{:>10.3}] {:<width$} {} {}",
+            record.elapsed().as_secs_f64(),
+            record.port,
+            arrow,
+            body
+        );
+        if as_hex
+            && let TraceEvent::Sent { frame, .. } | TraceEvent::Received { frame } = &record.event
+        {
+            println!("{:>13}{}", "", hex(frame));
+        }
+    }
+    println!("{} record(s)", trace.records.len());
+}
+
+fn with_seq_and_data(name: String, seq: Option<u8>, data: &[u8]) -> String {
+    let mut text = name;
+    if let Some(seq) = seq {
+        text += &format!(" #{}", seq);
+    }
+    if !data.is_empty() {
+        text += &format!(" [{}]", hex(data));
+    }
+    text
+}
+
+/// Print one `v4 monitor` event with its timestamp; `false` once stdout is gone
+pub fn monitor_event(event: &Event, elapsed: Duration, as_hex: bool) -> bool {
+    let body = if as_hex {
+        hex(&event.raw)
+    } else {
+        match (event.kind, event.error_code) {
+            (EventKind::Response, Some(code)) if event.data.is_empty() => code.name().to_string(),
+            (EventKind::Response, Some(code)) => format!("{} [{}]", code.name(), hex(&event.data)),
+            _ => String::from_utf8_lossy(&event.data)
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        }
+    };
+
+    writeln!(
+        std::io::stdout(),
+        "[{:>10.3}] {:<8} {}",
+        elapsed.as_secs_f64(),
+        event.kind.label(),
+        body
+    )
+    .is_ok()
+}
@@ -0,0 +1,546 @@
+//! V4 projects: a directory with a `v4.toml` manifest
+//!
+//! `v4 new` and `v4 init` create the standard layout:
+//!
+//! ```text
+//! blink/
+//! ├── .gitignore
+//! ├── v4.toml
+//! └── src/
+//!     └── main.v4
+//! ```
+//!
+//! The manifest names the sources to compile, in order, where the bytecode
+//! goes and which device it is deployed to:
+//!
+//! ```toml
+//! [package]
+//! name = "blink"
+//! version = "0.1.0"
+//!
+//! [build]
+//! sources = ["src/main.v4"]
+//! output = "target/blink.v4b"
+//!
+//! [deploy]
+//! port = "/dev/ttyACM0"
+//! baud_rate = 115200
+//! reset = true
+//!
+//! [hooks]
+//! pre_build = "./gen-constants.sh > src/constants.v4"
+//! post_deploy = "notify-send 'blink deployed'"
+//!
+//! [constants]
+//! GPIO_LED = 8
+//! BOARD_REV = "${BOARD_REV:-2}"
+//! ```
+//!
+//! Hooks are shell commands run from the project root, with `V4_PROJECT`
+//! set to the project name and `V4_OUTPUT` to the bytecode file. Libraries
+//! the program uses are listed in `[dependencies]`, see [`deps`].
+
+pub mod deps;
+
+use crate::source::{self, Define};
+use crate::{Result, V4Error};
+use deps::Dependency;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// File name of the project manifest
+pub const MANIFEST_FILE: &str = "v4.toml";
+
+/// Entry point created by `v4 new` and `v4 init`
+pub const MAIN_SOURCE: &str = "src/main.v4";
+
+/// Directory build output goes to
+pub const TARGET_DIR: &str = "target";
+
+/// `v4.toml` contents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub package: Package,
+    #[serde(default)]
+    pub build: Build,
+    #[serde(default)]
+    pub deploy: Deploy,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, Dependency>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub constants: BTreeMap<String, Constant>,
+}
+
+/// Value in `[constants]`: a number, or text with `${VAR}` references
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Constant {
+    Number(i64),
+    Text(String),
+}
+
+/// `[package]` section
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Package {
+    /// Project name, also the default bytecode file name
+    pub name: String,
+    #[serde(default = "default_version")]
+    pub version: String,
+}
+
+/// `[build]` section
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Build {
+    /// Source files relative to the project root, compiled in order
+    pub sources: Vec<String>,
+    /// Bytecode file relative to the project root
+    pub output: Option<String>,
+}
+
+/// `[deploy]` section
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Deploy {
+    /// Port of the target device (auto-detected if unset)
+    pub port: Option<String>,
+    /// Serial baud rate
+    pub baud_rate: Option<u32>,
+    /// Reset the VM before pushing the bytecode
+    pub reset: bool,
+}
+
+/// `[hooks]` section: shell commands run around `build` and `deploy`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_build: Option<String>,
+    pub post_build: Option<String>,
+    pub pre_deploy: Option<String>,
+    pub post_deploy: Option<String>,
+}
+
+/// Point in `build`/`deploy` a hook runs at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreBuild,
+    PostBuild,
+    PreDeploy,
+    PostDeploy,
+}
+
+impl Hook {
+    /// Key in the `[hooks]` section
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::PreBuild => "pre_build",
+            Hook::PostBuild => "post_build",
+            Hook::PreDeploy => "pre_deploy",
+            Hook::PostDeploy => "post_deploy",
+        }
+    }
+}
+
+impl Hooks {
+    /// Command configured for a hook
+    pub fn get(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::PreBuild => self.pre_build.as_deref(),
+            Hook::PostBuild => self.post_build.as_deref(),
+            Hook::PreDeploy => self.pre_deploy.as_deref(),
+            Hook::PostDeploy => self.post_deploy.as_deref(),
+        }
+        .filter(|command| !command.trim().is_empty())
+    }
+}
+
+fn default_version() -> String {
+    "0.1.0".to_string()
+}
+
+impl Manifest {
+    /// Read and validate a manifest file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let manifest: Self = toml::from_str(&text)
+            .map_err(|e| V4Error::Project(format!("{}: {}", path.display(), e.message())))?;
+        validate_name(&manifest.package.name)
+            .map_err(|e| V4Error::Project(format!("{}: {}", path.display(), e)))?;
+        Ok(manifest)
+    }
+
+    /// Source files, `src/main.v4` when the manifest lists none
+    pub fn sources(&self) -> Vec<String> {
+        if self.build.sources.is_empty() {
+            vec![MAIN_SOURCE.to_string()]
+        } else {
+            self.build.sources.clone()
+        }
+    }
+
+    /// `[constants]` as defines, environment variables filled in
+    pub fn defines(&self) -> Result<Vec<Define>> {
+        self.constants
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Constant::Number(n) => n.to_string(),
+                    Constant::Text(text) => {
+                        source::interpolate_env(text, |var| std::env::var(var).ok())
+                            .map_err(|e| V4Error::Project(format!("constant {}: {}", name, e)))?
+                    }
+                };
+                Define::new(name, &value).map_err(V4Error::Project)
+            })
+            .collect()
+    }
+
+    /// Bytecode file, `target/<name>.v4b` unless set
+    pub fn output(&self) -> String {
+        self.build
+            .output
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}.v4b", TARGET_DIR, self.package.name))
+    }
+}
+
+/// A project directory and its manifest
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Project {
+    /// Load the project whose manifest is in `root`
+    pub fn load(root: &Path) -> Result<Self> {
+        let manifest = Manifest::load(&root.join(MANIFEST_FILE))?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            manifest,
+        })
+    }
+
+    /// Find the project containing `dir`, looking in parent directories
+    pub fn discover(dir: &Path) -> Result<Option<Self>> {
+        for candidate in dir.ancestors() {
+            if candidate.join(MANIFEST_FILE).is_file() {
+                return Self::load(candidate).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find the project containing the current directory
+    pub fn current() -> Result<Self> {
+        let dir = std::env::current_dir()?;
+        Self::discover(&dir)?.ok_or_else(|| {
+            V4Error::Project(format!(
+                "No {} in {} or its parents; create one with `v4 init`",
+                MANIFEST_FILE,
+                dir.display()
+            ))
+        })
+    }
+
+    /// Path of a project-relative file
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.root.join(relative)
+    }
+
+    /// Run a hook from the manifest, if set, in the project root
+    ///
+    /// Returns whether a command ran; fails if it exits unsuccessfully.
+    pub fn run_hook(&self, hook: Hook) -> Result<bool> {
+        let Some(command) = self.manifest.hooks.get(hook) else {
+            return Ok(false);
+        };
+        log::info!("Running {} hook: {}", hook.name(), command);
+        let status = shell(command)
+            .current_dir(&self.root)
+            .env("V4_PROJECT", &self.manifest.package.name)
+            .env("V4_OUTPUT", self.path(&self.manifest.output()))
+            .status()
+            .map_err(|e| V4Error::Project(format!("Cannot run {} hook: {}", hook.name(), e)))?;
+        if !status.success() {
+            return Err(V4Error::Project(format!(
+                "{} hook failed ({}): {}",
+                hook.name(),
+                status,
+                command
+            )));
+        }
+        Ok(true)
+    }
+}
+
+/// Command running `command` in the platform shell
+pub(crate) fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Check a project name: letters, digits, `-` and `_`, starting with a letter
+pub fn validate_name(name: &str) -> std::result::Result<(), String> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid project name '{}': use letters, digits, '-' and '_', starting with a letter",
+            name
+        ))
+    }
+}
+
+/// Project name derived from a directory
+pub fn name_from_dir(dir: &Path) -> Result<String> {
+    let dir = if dir.as_os_str().is_empty() || dir == Path::new(".") {
+        std::env::current_dir()?
+    } else {
+        dir.to_path_buf()
+    };
+    dir.file_name()
+        .and_then(|n| n.to_str())
+        .map(String::from)
+        .ok_or_else(|| {
+            V4Error::Project(format!(
+                "Cannot derive a project name from {}; pass --name",
+                dir.display()
+            ))
+        })
+}
+
+/// Settings written to a new manifest
+#[derive(Debug, Default, Clone)]
+pub struct Template {
+    pub name: String,
+    pub port: Option<String>,
+    pub baud_rate: Option<u32>,
+}
+
+impl Template {
+    /// Manifest text, with commented-out examples for unset options
+    pub fn manifest(&self) -> String {
+        let port = match &self.port {
+            Some(port) => format!("port = {:?}", port),
+            None => "# port = \"/dev/ttyACM0\"".to_string(),
+        };
+        let baud_rate = match self.baud_rate {
+            Some(baud) => format!("baud_rate = {}", baud),
+            None => "# baud_rate = 115200".to_string(),
+        };
+        format!(
+            r#"[package]
+name = "{name}"
+version = "0.1.0"
+
+[build]
+# Source files, compiled in this order into one bytecode file
+sources = ["{main}"]
+output = "{target}/{name}.v4b"
+
+[deploy]
+# Device to deploy to; auto-detected when unset
+{port}
+{baud_rate}
+# Reset the VM before pushing
+reset = true
+
+[hooks]
+# Shell commands run from the project root around `v4 build` and `v4 deploy`
+# pre_build = "./generate.sh"
+# post_deploy = "echo deployed $V4_OUTPUT"
+
+[constants]
+# Defined as words ahead of the sources; text may use ${{VAR}} or ${{VAR:-default}}
+# GPIO_LED = 8
+# BAUD = "${{BAUD:-115200}}"
+
+[dependencies]
+# Libraries compiled before the sources above
+# i2c = {{ path = "../i2c-words" }}
+# spi = {{ git = "https://github.com/example/v4-spi.git", rev = "v1.2" }}
+"#,
+            name = self.name,
+            main = MAIN_SOURCE,
+            target = TARGET_DIR,
+        )
+    }
+
+    /// Starter program
+    pub fn main_source(&self) -> String {
+        format!(
+            "\\ {}: compiled and pushed by `v4 deploy`\n\n\
+             : SQUARE DUP * ;\n\n7 SQUARE\n",
+            self.name
+        )
+    }
+}
+
+const GITIGNORE: &str = "/target\n";
+
+/// Create the project layout in `root`
+///
+/// Fails if `root` already holds a manifest. Existing `src/main.v4` and
+/// `.gitignore` are kept, so this also adopts an existing directory.
+/// Returns the files written, relative to `root`.
+pub fn scaffold(root: &Path, template: &Template) -> Result<Vec<PathBuf>> {
+    validate_name(&template.name).map_err(V4Error::Project)?;
+    let manifest = root.join(MANIFEST_FILE);
+    if manifest.exists() {
+        return Err(V4Error::Project(format!(
+            "{} already exists",
+            manifest.display()
+        )));
+    }
+
+    fs::create_dir_all(root.join("src"))?;
+    let files = [
+        (MANIFEST_FILE, template.manifest()),
+        (MAIN_SOURCE, template.main_source()),
+        (".gitignore", GITIGNORE.to_string()),
+    ];
+    let mut created = Vec::new();
+    for (relative, contents) in files {
+        let path = root.join(relative);
+        if path.exists() {
+            continue;
+        }
+        fs::write(&path, contents)?;
+        created.push(PathBuf::from(relative));
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(name: &str) -> Template {
+        Template {
+            name: name.to_string(),
+            ..Template::default()
+        }
+    }
+
+    #[test]
+    fn test_scaffold_writes_loadable_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("blink");
+        let mut template = template("blink");
+        template.port = Some("/dev/ttyACM0".to_string());
+
+        let created = scaffold(&root, &template).unwrap();
+        assert_eq!(
+            created,
+            [MANIFEST_FILE, MAIN_SOURCE, ".gitignore"].map(PathBuf::from)
+        );
+
+        let project = Project::discover(&root.join("src")).unwrap().unwrap();
+        assert_eq!(project.root, root);
+        let manifest = project.manifest;
+        assert_eq!(manifest.package.name, "blink");
+        assert_eq!(manifest.sources(), [MAIN_SOURCE]);
+        assert_eq!(manifest.output(), "target/blink.v4b");
+        assert_eq!(manifest.deploy.port.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(manifest.deploy.baud_rate, None);
+        assert!(manifest.deploy.reset);
+    }
+
+    #[test]
+    fn test_scaffold_keeps_existing_files_and_refuses_second_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+
+        let created = scaffold(dir.path(), &template("app")).unwrap();
+        assert_eq!(created, [MANIFEST_FILE, MAIN_SOURCE].map(PathBuf::from));
+        let gitignore = fs::read_to_string(dir.path().join(".gitignore")).unwrap();
+        assert_eq!(gitignore, "*.log\n");
+
+        let err = scaffold(dir.path(), &template("app")).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+    }
+
+    #[test]
+    fn test_manifest_defaults_and_name_validation() {
+        let manifest: Manifest = toml::from_str("[package]\nname = \"x\"\n").unwrap();
+        assert_eq!(manifest.package.version, "0.1.0");
+        assert_eq!(manifest.sources(), [MAIN_SOURCE]);
+        assert_eq!(manifest.output(), "target/x.v4b");
+        assert!(!manifest.deploy.reset);
+
+        assert!(validate_name("led-blink_2").is_ok());
+        assert!(validate_name("2fast").is_err());
+        assert!(validate_name("my app").is_err());
+        assert!(validate_name("").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks_run_in_project_root() {
+        let dir = tempfile::tempdir().unwrap();
+        scaffold(dir.path(), &template("hooked")).unwrap();
+        let mut project = Project::load(dir.path()).unwrap();
+        assert!(!project.run_hook(Hook::PreBuild).unwrap());
+
+        project.manifest.hooks.pre_build =
+            Some("echo \"$V4_PROJECT $V4_OUTPUT\" > hook.txt".into());
+        assert!(project.run_hook(Hook::PreBuild).unwrap());
+        let written = fs::read_to_string(dir.path().join("hook.txt")).unwrap();
+        let output = dir.path().join("target/hooked.v4b");
+        assert_eq!(written.trim(), format!("hooked {}", output.display()));
+
+        project.manifest.hooks.post_deploy = Some("exit 3".into());
+        let err = project.run_hook(Hook::PostDeploy).unwrap_err();
+        assert!(err.to_string().contains("post_deploy hook failed"));
+    }
+
+    #[test]
+    fn test_constants_become_defines() {
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"x\"\n[constants]\nLED = 8\nMASK = \"0x${V4_TEST_UNSET_VAR:-F0}\"\n",
+        )
+        .unwrap();
+        let defines = manifest.defines().unwrap();
+        assert_eq!(
+            defines,
+            [
+                Define::new("LED", "8").unwrap(),
+                Define::new("MASK", "0xF0").unwrap()
+            ]
+        );
+
+        let manifest: Manifest = toml::from_str(
+            "[package]\nname = \"x\"\n[constants]\nPIN = \"${V4_TEST_UNSET_VAR}\"\n",
+        )
+        .unwrap();
+        let err = manifest.defines().unwrap_err().to_string();
+        assert!(err.contains("constant PIN: Environment variable V4_TEST_UNSET_VAR is not set"));
+    }
+
+    #[test]
+    fn test_discover_outside_project() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Project::discover(dir.path()).unwrap().is_none());
+    }
+}
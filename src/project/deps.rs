@@ -0,0 +1,586 @@
+//! Forth library dependencies of a project
+//!
+//! `[dependencies]` in `v4.toml` names other projects (or single `.v4`
+//! files) whose words the program uses:
+//!
+//! ```toml
+//! [dependencies]
+//! i2c = { path = "../i2c-words" }
+//! spi = { git = "https://github.com/example/v4-spi.git", rev = "v1.2" }
+//! ```
+//!
+//! Dependencies are compiled before the project, each after its own
+//! dependencies. Git dependencies are cloned into `target/deps/<name>`, and
+//! the commit each one resolved to is pinned in `v4.lock` until `v4 update`.
+
+use super::{MANIFEST_FILE, Manifest, Project};
+use crate::{Result, V4Error};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// File name of the lockfile, next to the manifest
+pub const LOCK_FILE: &str = "v4.lock";
+
+/// Where git dependencies are checked out, relative to the project root
+pub const DEPS_DIR: &str = "target/deps";
+
+const LOCK_HEADER: &str = "# Written by v4 build: dependencies in compile order, git ones pinned \
+     to a commit.\n# Refresh with `v4 update`.\n";
+
+/// Entry of `[dependencies]`: a local path or a git repository
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Dependency {
+    /// Project directory or `.v4` file, relative to the declaring project
+    pub path: Option<String>,
+    /// Repository URL of a project
+    pub git: Option<String>,
+    /// Branch, tag or commit of `git` [default: the remote's HEAD]
+    pub rev: Option<String>,
+}
+
+/// Where a resolved package came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Path(PathBuf),
+    Git { url: String, commit: String },
+}
+
+/// A dependency ready to compile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    pub source: Source,
+    /// Source files, in compile order
+    pub sources: Vec<PathBuf>,
+}
+
+/// `v4.lock` contents
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Lockfile {
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LockedPackage {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    git: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit: Option<String>,
+}
+
+/// Resolve and fetch the project's dependencies, in compile order
+///
+/// Git dependencies use the commit in `v4.lock` unless `update` is set or
+/// their entry in the manifest changed. The lockfile is rewritten when the
+/// result differs from it.
+pub fn resolve(project: &Project, update: bool) -> Result<Vec<Package>> {
+    let lock_path = project.root.join(LOCK_FILE);
+    let lock = match lock_path.is_file() && !update {
+        true => load_lock(&lock_path)?,
+        false => Lockfile::default(),
+    };
+
+    let mut resolver = Resolver {
+        deps_dir: project.root.join(DEPS_DIR),
+        locked: lock.package.iter().map(|p| (p.name.clone(), p)).collect(),
+        stack: Vec::new(),
+        declared: HashMap::new(),
+        packages: Vec::new(),
+        entries: Vec::new(),
+    };
+    resolver.visit_all(&project.manifest, &project.root)?;
+
+    let new_lock = Lockfile {
+        package: resolver.entries,
+    };
+    if new_lock.package.is_empty() {
+        if lock_path.is_file() {
+            fs::remove_file(&lock_path)?;
+        }
+    } else if new_lock != lock || !lock_path.is_file() {
+        let text = toml::to_string(&new_lock).map_err(|e| V4Error::Project(e.to_string()))?;
+        fs::write(&lock_path, format!("{}\n{}", LOCK_HEADER, text))?;
+    }
+    Ok(resolver.packages)
+}
+
+fn load_lock(path: &Path) -> Result<Lockfile> {
+    let text = fs::read_to_string(path)?;
+    toml::from_str(&text)
+        .map_err(|e| V4Error::Project(format!("{}: {}", path.display(), e.message())))
+}
+
+struct Resolver<'a> {
+    deps_dir: PathBuf,
+    locked: HashMap<String, &'a LockedPackage>,
+    /// Names being resolved, to report cycles
+    stack: Vec<String>,
+    /// Where each resolved name came from, to catch conflicting declarations
+    declared: HashMap<String, String>,
+    packages: Vec<Package>,
+    entries: Vec<LockedPackage>,
+}
+
+impl Resolver<'_> {
+    fn visit_all(&mut self, manifest: &Manifest, base: &Path) -> Result<()> {
+        for (name, dep) in &manifest.dependencies {
+            self.visit(name, dep, base)?;
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, name: &str, dep: &Dependency, base: &Path) -> Result<()> {
+        check_name(name)?;
+        if self.stack.iter().any(|n| n == name) {
+            let mut cycle = self.stack.clone();
+            cycle.push(name.to_string());
+            return Err(V4Error::Project(format!(
+                "Dependency cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+
+        let origin = origin(name, dep, base)?;
+        if let Some(previous) = self.declared.get(name) {
+            if *previous != origin {
+                return Err(V4Error::Project(format!(
+                    "Dependency '{}' is declared as both {} and {}",
+                    name, previous, origin
+                )));
+            }
+            return Ok(());
+        }
+
+        self.stack.push(name.to_string());
+        let (location, source, entry) = match (&dep.path, &dep.git) {
+            (Some(path), None) => {
+                let location = base.join(path);
+                let entry = LockedPackage {
+                    name: name.to_string(),
+                    path: Some(path.clone()),
+                    git: None,
+                    rev: None,
+                    commit: None,
+                };
+                (location.clone(), Source::Path(location), entry)
+            }
+            (None, Some(url)) => {
+                let dir = self.deps_dir.join(name);
+                let locked = self
+                    .locked
+                    .get(name)
+                    .filter(|l| l.git.as_ref() == Some(url) && l.rev == dep.rev)
+                    .and_then(|l| l.commit.as_deref());
+                let commit = checkout(&self.deps_dir, &dir, url, dep.rev.as_deref(), locked)?;
+                let entry = LockedPackage {
+                    name: name.to_string(),
+                    path: None,
+                    git: Some(url.clone()),
+                    rev: dep.rev.clone(),
+                    commit: Some(commit.clone()),
+                };
+                let source = Source::Git {
+                    url: url.clone(),
+                    commit,
+                };
+                (dir, source, entry)
+            }
+            _ => unreachable!("checked by origin()"),
+        };
+
+        let sources = if location.is_file() {
+            vec![location]
+        } else {
+            let manifest_path = location.join(MANIFEST_FILE);
+            if !manifest_path.is_file() {
+                return Err(V4Error::Project(format!(
+                    "Dependency '{}' at {} has no {}",
+                    name,
+                    location.display(),
+                    MANIFEST_FILE
+                )));
+            }
+            let manifest = Manifest::load(&manifest_path)?;
+            self.visit_all(&manifest, &location)?;
+            manifest
+                .sources()
+                .iter()
+                .map(|source| location.join(source))
+                .collect()
+        };
+
+        self.stack.pop();
+        self.declared.insert(name.to_string(), origin);
+        self.packages.push(Package {
+            name: name.to_string(),
+            source,
+            sources,
+        });
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+/// Dependency names become directories under `target/deps`, so they are
+/// limited to letters, digits, `_` and `-`
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(V4Error::Project(format!(
+            "Invalid dependency name '{}': use only letters, digits, '_' and '-'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Where a dependency comes from, for error messages and conflict checks
+fn origin(name: &str, dep: &Dependency, base: &Path) -> Result<String> {
+    match (&dep.path, &dep.git, &dep.rev) {
+        (Some(path), None, None) => {
+            let path = base.join(path);
+            let path = path.canonicalize().map_err(|e| {
+                V4Error::Project(format!(
+                    "Dependency '{}': cannot read {}: {}",
+                    name,
+                    path.display(),
+                    e
+                ))
+            })?;
+            Ok(path.display().to_string())
+        }
+        (None, Some(url), rev) => {
+            // git would read a leading '-' as an option
+            for (field, value) in [("git", Some(url)), ("rev", rev.as_ref())] {
+                if value.is_some_and(|v| v.starts_with('-')) {
+                    return Err(V4Error::Project(format!(
+                        "Dependency '{}': {} must not start with '-'",
+                        name, field
+                    )));
+                }
+            }
+            Ok(match rev {
+                Some(rev) => format!("{}#{}", url, rev),
+                None => url.clone(),
+            })
+        }
+        (Some(_), None, Some(_)) => Err(V4Error::Project(format!(
+            "Dependency '{}': rev only applies to git dependencies",
+            name
+        ))),
+        _ => Err(V4Error::Project(format!(
+            "Dependency '{}' needs exactly one of path or git",
+            name
+        ))),
+    }
+}
+
+/// Clone or update a git dependency in `dir` and check out its commit
+///
+/// Returns the commit checked out: `locked` if given, else `rev` (or the
+/// remote's HEAD) as fetched now.
+fn checkout(
+    deps_dir: &Path,
+    dir: &Path,
+    url: &str,
+    rev: Option<&str>,
+    locked: Option<&str>,
+) -> Result<String> {
+    let cloned = dir.join(".git").is_dir()
+        && git(Some(dir), &["remote", "get-url", "origin"]).is_ok_and(|origin| origin == url);
+    if !cloned {
+        // Never remove anything outside target/deps
+        if dir.parent() != Some(deps_dir) {
+            return Err(V4Error::Project(format!(
+                "Refusing to check out {} outside {}",
+                dir.display(),
+                deps_dir.display()
+            )));
+        }
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        if let Some(parent) = dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        log::info!("Cloning {}", url);
+        git(
+            None,
+            &["clone", "--quiet", "--", url, &dir.to_string_lossy()],
+        )?;
+    } else if locked.is_none_or(|commit| rev_parse(dir, commit).is_none()) {
+        log::info!("Fetching {}", url);
+        git(Some(dir), &["fetch", "--quiet", "--tags", "origin"])?;
+    }
+
+    let commit = match locked {
+        Some(commit) => rev_parse(dir, commit),
+        None => match rev {
+            Some(rev) => rev_parse(dir, &format!("origin/{}", rev)).or_else(|| rev_parse(dir, rev)),
+            None => rev_parse(dir, "origin/HEAD"),
+        },
+    };
+    let commit = commit.ok_or_else(|| {
+        V4Error::Project(format!(
+            "{}: revision '{}' not found",
+            url,
+            locked.or(rev).unwrap_or("HEAD")
+        ))
+    })?;
+    git(Some(dir), &["checkout", "--quiet", "--detach", &commit])?;
+    Ok(commit)
+}
+
+/// Commit a revision names in `dir`, if any
+fn rev_parse(dir: &Path, rev: &str) -> Option<String> {
+    if rev.starts_with('-') {
+        return None;
+    }
+    git(
+        Some(dir),
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", rev),
+        ],
+    )
+    .ok()
+}
+
+/// Run git and return its trimmed stdout
+fn git(dir: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command
+        .args(args)
+        .output()
+        .map_err(|e| V4Error::Project(format!("Cannot run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(V4Error::Project(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{Template, scaffold};
+
+    /// Project in `dir` with the given `[dependencies]` and main source
+    fn project(dir: &Path, name: &str, deps: &[(&str, Dependency)], main: &str) -> Project {
+        let template = Template {
+            name: name.to_string(),
+            ..Template::default()
+        };
+        scaffold(dir, &template).unwrap();
+        fs::write(dir.join("src/main.v4"), main).unwrap();
+        let mut project = Project::load(dir).unwrap();
+        for (dep_name, dep) in deps {
+            project
+                .manifest
+                .dependencies
+                .insert(dep_name.to_string(), dep.clone());
+        }
+        let text = toml::to_string(&project.manifest).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), text).unwrap();
+        project
+    }
+
+    fn path(path: &str) -> Dependency {
+        Dependency {
+            path: Some(path.to_string()),
+            ..Dependency::default()
+        }
+    }
+
+    fn names(packages: &[Package]) -> Vec<&str> {
+        packages.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_path_dependencies_compile_after_their_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        project(&root.join("bus"), "bus", &[], ": BUS ;\n");
+        project(
+            &root.join("i2c"),
+            "i2c",
+            &[("bus", path("../bus"))],
+            ": I2C ;\n",
+        );
+        fs::write(root.join("util.v4"), ": UTIL ;\n").unwrap();
+        let app = project(
+            &root.join("app"),
+            "app",
+            &[
+                ("i2c", path("../i2c")),
+                ("bus", path("../bus")),
+                ("util", path("../util.v4")),
+            ],
+            "I2C\n",
+        );
+
+        let packages = resolve(&app, false).unwrap();
+        assert_eq!(names(&packages), ["bus", "i2c", "util"]);
+        assert_eq!(packages[1].sources, [root.join("app/../i2c/src/main.v4")]);
+        assert_eq!(packages[2].sources, [root.join("app/../util.v4")]);
+
+        let lock = load_lock(&root.join("app").join(LOCK_FILE)).unwrap();
+        assert_eq!(lock.package.len(), 3);
+        assert_eq!(lock.package[0].path.as_deref(), Some("../bus"));
+    }
+
+    #[test]
+    fn test_cycles_and_invalid_entries_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        project(&root.join("a"), "a", &[("b", path("../b"))], "\n");
+        let b = project(&root.join("b"), "b", &[("a", path("../a"))], "\n");
+        let err = resolve(&b, false).unwrap_err().to_string();
+        assert!(err.contains("Dependency cycle: a -> b -> a"), "{}", err);
+
+        let both = Dependency {
+            path: Some("../a".into()),
+            git: Some("https://example.com/a.git".into()),
+            rev: None,
+        };
+        let c = project(&root.join("c"), "c", &[("a", both)], "\n");
+        let err = resolve(&c, false).unwrap_err().to_string();
+        assert!(err.contains("exactly one of path or git"), "{}", err);
+    }
+
+    #[test]
+    fn test_unsafe_names_and_git_arguments_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let git_dep = |url: &str, rev: Option<&str>| Dependency {
+            git: Some(url.to_string()),
+            rev: rev.map(str::to_string),
+            ..Dependency::default()
+        };
+
+        for name in ["../../src", "/home/me/src", "a.b", ""] {
+            let app = project(
+                &root.join("app"),
+                "app",
+                &[(name, git_dep("https://example.com/a.git", None))],
+                "\n",
+            );
+            let err = resolve(&app, false).unwrap_err().to_string();
+            assert!(err.contains("Invalid dependency name"), "{}", err);
+            fs::remove_dir_all(root.join("app")).unwrap();
+        }
+
+        let app = project(
+            &root.join("app"),
+            "app",
+            &[("evil", git_dep("--upload-pack=touch /tmp/pwned", None))],
+            "\n",
+        );
+        let err = resolve(&app, false).unwrap_err().to_string();
+        assert!(err.contains("git must not start with '-'"), "{}", err);
+        fs::remove_dir_all(root.join("app")).unwrap();
+
+        let app = project(
+            &root.join("app"),
+            "app",
+            &[(
+                "evil",
+                git_dep("https://example.com/a.git", Some("--output=/tmp/x")),
+            )],
+            "\n",
+        );
+        let err = resolve(&app, false).unwrap_err().to_string();
+        assert!(err.contains("rev must not start with '-'"), "{}", err);
+        assert!(!root.join("app").join(DEPS_DIR).exists());
+    }
+
+    #[test]
+    fn test_git_dependency_is_pinned_until_update() {
+        if git(None, &["--version"]).is_err() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let lib = root.join("lib");
+        project(&lib, "lib", &[], ": ONE 1 ;\n");
+        let commit_all = |message: &str| {
+            let lib = lib.to_str().unwrap();
+            git(None, &["-C", lib, "add", "-A"]).unwrap();
+            git(
+                None,
+                &[
+                    "-C",
+                    lib,
+                    "-c",
+                    "user.name=t",
+                    "-c",
+                    "user.email=t@t",
+                    "commit",
+                    "-qm",
+                    message,
+                ],
+            )
+            .unwrap();
+            git(Some(Path::new(lib)), &["rev-parse", "HEAD"]).unwrap()
+        };
+        git(None, &["init", "--quiet", lib.to_str().unwrap()]).unwrap();
+        let first = commit_all("one");
+
+        let url = format!("file://{}", lib.display());
+        let dep = Dependency {
+            git: Some(url.clone()),
+            ..Dependency::default()
+        };
+        let app = project(&root.join("app"), "app", &[("lib", dep)], "ONE\n");
+        let packages = resolve(&app, false).unwrap();
+        assert_eq!(
+            packages[0].source,
+            Source::Git {
+                url: url.clone(),
+                commit: first.clone()
+            }
+        );
+        assert_eq!(
+            packages[0].sources,
+            [root.join("app").join(DEPS_DIR).join("lib/src/main.v4")]
+        );
+
+        fs::write(lib.join("src/main.v4"), ": ONE 1 ; : TWO 2 ;\n").unwrap();
+        let second = commit_all("two");
+        let pinned = resolve(&app, false).unwrap();
+        assert_eq!(pinned[0].source, packages[0].source);
+
+        let updated = resolve(&app, true).unwrap();
+        assert_eq!(
+            updated[0].source,
+            Source::Git {
+                url,
+                commit: second
+            }
+        );
+        let lock = fs::read_to_string(root.join("app").join(LOCK_FILE)).unwrap();
+        assert!(lock.starts_with(LOCK_HEADER));
+    }
+}
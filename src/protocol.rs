@@ -1,7 +1,16 @@
-pub mod crc8;
+pub mod compress;
+pub mod crc;
 pub mod frame;
+pub mod payload;
 pub mod types;
 
-pub use crc8::calc_crc8;
-pub use frame::{Frame, FrameBuilder, Response};
-pub use types::{Command, ErrorCode};
+pub use crc::{Checksum, calc_crc8, calc_crc16, calc_crc32};
+pub use frame::{
+    FaultKind, Frame, FrameBuilder, FrameDecoder, Framing, Incoming, NOTIFY_OUTPUT, Response,
+    VmFault, device_frame,
+};
+pub use payload::{MemoryDump, StackSnapshot, Ticks, WordInfo};
+pub use types::{
+    Command, ErrorCode, FEATURE_COBS, FEATURE_CRC16, FEATURE_CRC32, FEATURE_LZ4, FEATURE_SEQUENCE,
+    FEATURE_TICKS, Handshake, PROTOCOL_VERSION,
+};
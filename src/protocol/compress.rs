@@ -0,0 +1,204 @@
+//! LZ4 block compression for bytecode transfers
+//!
+//! Devices that enable [`FEATURE_LZ4`](super::FEATURE_LZ4) in the HELLO
+//! handshake accept a chunked EXEC whose data is one LZ4 block (the raw
+//! block format, without the frame header), and decompress it before
+//! running. The block format needs no dictionary or window buffer beyond the
+//! output, which suits small firmware.
+//!
+//! Each sequence is a token (literal count in the high nibble, match length
+//! minus 4 in the low one), extra literal count bytes, the literals, a u16
+//! LE match offset and extra match length bytes. The last sequence holds
+//! literals only.
+
+use crate::{Result, V4Error};
+
+/// Shortest match worth encoding
+const MIN_MATCH: usize = 4;
+
+/// Farthest back a match may point
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+/// No match starts within this many bytes of the end
+const MF_LIMIT: usize = 12;
+
+/// The block ends in at least this many literals
+const LAST_LITERALS: usize = 5;
+
+/// Size of the match finder's hash table, in bits
+const HASH_BITS: u32 = 12;
+
+/// Compress `data` into one LZ4 block
+///
+/// Greedy and single-pass: bytecode is small, so speed matters less than
+/// a decoder-friendly result. Incompressible data grows slightly.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let (mut pos, mut anchor) = (0, 0);
+
+    let match_end = data.len().saturating_sub(LAST_LITERALS);
+    while pos + MF_LIMIT < data.len() {
+        let key = &data[pos..pos + MIN_MATCH];
+        let slot = &mut table[hash(key)];
+        let candidate = std::mem::replace(slot, pos);
+        let found = candidate != usize::MAX
+            && pos - candidate <= MAX_OFFSET
+            && &data[candidate..candidate + MIN_MATCH] == key;
+        if !found {
+            pos += 1;
+            continue;
+        }
+
+        let mut len = MIN_MATCH;
+        while pos + len < match_end && data[candidate + len] == data[pos + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &data[anchor..pos], Some((pos - candidate, len)));
+        pos += len;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &data[anchor..], None);
+    out
+}
+
+/// Decompress one LZ4 block that expands to exactly `size` bytes
+pub fn decompress(block: &[u8], size: usize) -> Result<Vec<u8>> {
+    let corrupt = |what: &str| V4Error::Protocol(format!("Corrupt LZ4 block: {}", what));
+    let mut out = Vec::with_capacity(size);
+    let mut i = 0;
+    while i < block.len() {
+        let token = block[i];
+        i += 1;
+
+        let literals = read_length(block, &mut i, (token >> 4) as usize)?;
+        let literals = i
+            .checked_add(literals)
+            .and_then(|end| block.get(i..end))
+            .filter(|literals| out.len() + literals.len() <= size)
+            .ok_or_else(|| corrupt("literals overrun"))?;
+        out.extend_from_slice(literals);
+        i += literals.len();
+        if i == block.len() {
+            break;
+        }
+
+        let offset = block
+            .get(i..i + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| corrupt("truncated match offset"))?;
+        i += 2;
+        let len = read_length(block, &mut i, (token & 0x0F) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() {
+            return Err(corrupt("match offset out of range"));
+        }
+        if out.len() + len > size {
+            return Err(corrupt("match overruns the output"));
+        }
+        // Matches may overlap their own output, so copy byte by byte
+        let start = out.len() - offset;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+
+    if out.len() != size {
+        return Err(corrupt(&format!("{} bytes, expected {}", out.len(), size)));
+    }
+    Ok(out)
+}
+
+fn hash(key: &[u8]) -> usize {
+    let word = u32::from_le_bytes([key[0], key[1], key[2], key[3]]);
+    (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Token, literals and, unless it ends the block, a match
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], found: Option<(usize, usize)>) {
+    let match_len = found.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) << 4) | match_len.min(15)) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = found {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_length(out, match_len - 15);
+        }
+    }
+}
+
+/// Length beyond a full nibble: 255s, then the remainder
+fn write_length(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 255 {
+        out.push(255);
+        n -= 255;
+    }
+    out.push(n as u8);
+}
+
+fn read_length(block: &[u8], i: &mut usize, nibble: usize) -> Result<usize> {
+    let mut n = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *block.get(*i).ok_or_else(|| {
+                V4Error::Protocol("Corrupt LZ4 block: truncated length".to_string())
+            })?;
+            *i += 1;
+            n += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_compresses_repetitive_bytecode() {
+        // LIT_U8 n, SYS 0x11 repeated, as unrolled loops compile to
+        let code: Vec<u8> = (0..200).flat_map(|i| [0x76, i % 4, 0x60, 0x11]).collect();
+        let block = compress(&code);
+        assert!(block.len() < code.len() / 4, "{} bytes", block.len());
+        assert_eq!(decompress(&block, code.len()).unwrap(), code);
+    }
+
+    #[test]
+    fn test_short_input_is_all_literals() {
+        assert_eq!(compress(b""), [0x00]);
+        assert_eq!(compress(b"V4BC"), [0x40, b'V', b'4', b'B', b'C']);
+        assert_eq!(
+            decompress(&[0x40, b'V', b'4', b'B', b'C'], 4).unwrap(),
+            b"V4BC"
+        );
+    }
+
+    #[test]
+    fn test_decompress_overlapping_match() {
+        // One literal 'a', then a 9-byte match at offset 1, then 'b'
+        let block = [0x15, b'a', 1, 0, 0x10, b'b'];
+        assert_eq!(decompress(&block, 11).unwrap(), b"aaaaaaaaaab");
+    }
+
+    #[test]
+    fn test_decompress_rejects_corrupt_blocks() {
+        assert!(decompress(&[0x15, b'a', 2, 0, 0x10, b'b'], 11).is_err());
+        assert!(decompress(&[0x15, b'a', 1], 11).is_err());
+        assert!(decompress(&[0x40, b'V', b'4'], 4).is_err());
+        assert!(decompress(&[0x40, b'V', b'4', b'B', b'C'], 5).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_round_trip(data in prop::collection::vec(0u8..8, 0..2000)) {
+            let block = compress(&data);
+            prop_assert_eq!(decompress(&block, data.len()).unwrap(), data);
+        }
+    }
+}
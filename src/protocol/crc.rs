@@ -0,0 +1,203 @@
+//! Frame checksums
+//!
+//! Frames end in a CRC-8 unless the HELLO handshake enabled a stronger
+//! checksum (see [`Checksum`]). Stronger checksums are stored little-endian.
+
+use super::types::{FEATURE_CRC16, FEATURE_CRC32};
+use crate::{Result, V4Error};
+
+/// Calculate CRC-8 checksum
+///
+/// Polynomial: 0x07
+/// Initial value: 0x00
+///
+/// # Examples
+///
+/// ```
+/// use v4_cli::protocol::calc_crc8;
+///
+/// let data = b"123456789";
+/// assert_eq!(calc_crc8(data), 0xF4);
+/// ```
+pub fn calc_crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if crc & 0x80 != 0 {
+                crc = (crc << 1) ^ 0x07;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Calculate CRC-16/CCITT-FALSE checksum
+///
+/// Polynomial: 0x1021
+/// Initial value: 0xFFFF
+///
+/// ```
+/// use v4_cli::protocol::calc_crc16;
+///
+/// assert_eq!(calc_crc16(b"123456789"), 0x29B1);
+/// ```
+pub fn calc_crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Calculate CRC-32 checksum (IEEE 802.3, as used by zlib)
+///
+/// ```
+/// use v4_cli::protocol::calc_crc32;
+///
+/// assert_eq!(calc_crc32(b"123456789"), 0xCBF43926);
+/// ```
+pub fn calc_crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xEDB8_8320;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+/// Checksum closing each frame, covering everything after STX
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Checksum {
+    /// Understood by every firmware
+    #[default]
+    Crc8,
+    Crc16,
+    Crc32,
+}
+
+impl Checksum {
+    /// Strongest first, the order the handshake prefers them in
+    pub const STRONGEST_FIRST: [Checksum; 3] = [Checksum::Crc32, Checksum::Crc16, Checksum::Crc8];
+
+    /// Bytes it takes at the end of a frame
+    pub fn size(self) -> usize {
+        match self {
+            Checksum::Crc8 => 1,
+            Checksum::Crc16 => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    /// The checksum taking `size` bytes
+    pub fn from_size(size: usize) -> Option<Self> {
+        Self::STRONGEST_FIRST
+            .into_iter()
+            .find(|checksum| checksum.size() == size)
+    }
+
+    /// HELLO feature bit requesting it, 0 for CRC-8
+    pub fn feature(self) -> u8 {
+        match self {
+            Checksum::Crc8 => 0,
+            Checksum::Crc16 => FEATURE_CRC16,
+            Checksum::Crc32 => FEATURE_CRC32,
+        }
+    }
+
+    /// Checksum bytes of `data`
+    pub fn compute(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Checksum::Crc8 => vec![calc_crc8(data)],
+            Checksum::Crc16 => calc_crc16(data).to_le_bytes().to_vec(),
+            Checksum::Crc32 => calc_crc32(data).to_le_bytes().to_vec(),
+        }
+    }
+
+    /// Check the checksum bytes `sum` closing a frame against `data`
+    pub fn verify(self, data: &[u8], sum: &[u8]) -> Result<()> {
+        let expected = self.compute(data);
+        if expected == sum {
+            return Ok(());
+        }
+        let value = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| (value << 8) | byte as u32)
+        };
+        Err(V4Error::CrcMismatch {
+            expected: value(&expected),
+            actual: value(sum),
+        })
+    }
+
+    /// Name as in logs, e.g. `CRC-16`
+    pub fn name(self) -> &'static str {
+        match self {
+            Checksum::Crc8 => "CRC-8",
+            Checksum::Crc16 => "CRC-16",
+            Checksum::Crc32 => "CRC-32",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc8_reference() {
+        // Reference test case from specification
+        let data = b"123456789";
+        assert_eq!(calc_crc8(data), 0xF4);
+    }
+
+    #[test]
+    fn test_crc8_empty() {
+        assert_eq!(calc_crc8(&[]), 0x00);
+    }
+
+    #[test]
+    fn test_crc8_single_byte() {
+        assert_eq!(calc_crc8(&[0x00]), 0x00);
+        assert_eq!(calc_crc8(&[0xFF]), 0xF3);
+    }
+
+    #[test]
+    fn test_crc8_protocol_frame() {
+        // Test with actual protocol frame data
+        // [LEN_L][LEN_H][CMD][DATA...]
+        let frame_data = [0x00, 0x00, 0x20]; // Ping command, no data
+        let crc = calc_crc8(&frame_data);
+        assert_eq!(crc, 0xE0);
+    }
+
+    #[test]
+    fn test_stronger_checksums() {
+        assert_eq!(calc_crc16(&[]), 0xFFFF);
+        assert_eq!(calc_crc32(&[]), 0);
+        assert_eq!(Checksum::Crc16.compute(b"123456789"), [0xB1, 0x29]);
+        assert_eq!(
+            Checksum::Crc32.compute(b"123456789"),
+            [0x26, 0x39, 0xF4, 0xCB]
+        );
+        assert_eq!(Checksum::from_size(4), Some(Checksum::Crc32));
+        assert_eq!(Checksum::from_size(3), None);
+    }
+}
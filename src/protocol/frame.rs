@@ -1,12 +1,13 @@
-use super::calc_crc8;
+use super::crc::{Checksum, calc_crc8};
 use super::types::{Command, ErrorCode};
 use crate::{Result, V4Error};
+use std::fmt;
 
 /// V4-link protocol start marker
 const STX: u8 = 0xA5;
 
-/// Maximum payload size (512 bytes)
-const MAX_PAYLOAD_SIZE: usize = 512;
+/// Delimiter ending each frame in COBS framing
+const COBS_END: u8 = 0x00;
 
 /// V4-link frame
 ///
@@ -16,10 +17,39 @@ const MAX_PAYLOAD_SIZE: usize = 512;
 /// - CMD: Command code
 /// - DATA: Payload (0-512 bytes)
 /// - CRC8: Checksum over [LEN_L][LEN_H][CMD][DATA...]
+///
+/// Once sequence numbers are negotiated (see [`Handshake`](super::Handshake)),
+/// a SEQ byte follows CMD and LEN counts it: [STX][LEN][CMD][SEQ][DATA...][CRC8].
 #[derive(Debug, Clone)]
 pub struct Frame {
     pub command: Command,
     pub payload: Vec<u8>,
+    /// Sequence number, echoed by the device in its response
+    pub seq: Option<u8>,
+}
+
+/// Code byte marking an output notification instead of a response
+pub const NOTIFY_OUTPUT: u8 = 0x80;
+
+/// Frame received from the device
+#[derive(Debug, Clone, PartialEq)]
+pub enum Incoming {
+    /// Answer to the pending command
+    Response(Response),
+    /// Program output (`."`, `EMIT`) sent while a command runs
+    Output(Vec<u8>),
+}
+
+/// Encode a frame as the device sends it: [STX][LEN_L][LEN_H][CODE][DATA...][CRC8]
+///
+/// For transports that stand in for a device.
+pub fn device_frame(code: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![STX];
+    frame.extend_from_slice(&((data.len() + 1) as u16).to_le_bytes());
+    frame.push(code);
+    frame.extend_from_slice(data);
+    frame.push(calc_crc8(&frame[1..]));
+    frame
 }
 
 /// Response from V4-link device
@@ -28,25 +58,48 @@ pub struct Response {
     pub error_code: ErrorCode,
     pub word_indices: Vec<u16>,
     pub data: Vec<u8>,
+    /// Sequence number of the request this answers (sequenced responses only)
+    pub seq: Option<u8>,
 }
 
 impl Frame {
+    /// Maximum payload size (512 bytes)
+    pub const MAX_PAYLOAD_SIZE: usize = 512;
+
+    /// Largest LEN of a device frame: a full payload plus code and SEQ
+    pub const MAX_LENGTH: usize = Self::MAX_PAYLOAD_SIZE + 2;
+
     /// Create a new frame
     pub fn new(command: Command, payload: Vec<u8>) -> Result<Self> {
-        if payload.len() > MAX_PAYLOAD_SIZE {
+        if payload.len() > Self::MAX_PAYLOAD_SIZE {
             return Err(V4Error::Protocol(format!(
                 "Payload too large: {} bytes (max {})",
                 payload.len(),
-                MAX_PAYLOAD_SIZE
+                Self::MAX_PAYLOAD_SIZE
             )));
         }
-        Ok(Self { command, payload })
+        Ok(Self {
+            command,
+            payload,
+            seq: None,
+        })
+    }
+
+    /// Attach a sequence number
+    pub fn with_seq(mut self, seq: u8) -> Self {
+        self.seq = Some(seq);
+        self
     }
 
     /// Encode frame to bytes
     pub fn encode(&self) -> Vec<u8> {
-        let length = self.payload.len() as u16;
-        let mut frame = Vec::with_capacity(5 + self.payload.len());
+        self.encode_with(Checksum::Crc8)
+    }
+
+    /// Encode frame to bytes, ending in `checksum`
+    pub fn encode_with(&self, checksum: Checksum) -> Vec<u8> {
+        let length = (self.payload.len() + self.seq.map_or(0, |_| 1)) as u16;
+        let mut frame = Vec::with_capacity(6 + self.payload.len());
 
         // STX
         frame.push(STX);
@@ -58,12 +111,17 @@ impl Frame {
         // Command
         frame.push(self.command as u8);
 
+        // Sequence number
+        if let Some(seq) = self.seq {
+            frame.push(seq);
+        }
+
         // Payload
         frame.extend_from_slice(&self.payload);
 
-        // CRC8 over everything except STX
-        let crc = calc_crc8(&frame[1..]);
-        frame.push(crc);
+        // Checksum over everything except STX
+        let crc = checksum.compute(&frame[1..]);
+        frame.extend_from_slice(&crc);
 
         frame
     }
@@ -73,6 +131,33 @@ impl Frame {
     /// Standard response (PING, RESET): [STX][0x01][0x00][ERR_CODE][CRC8]
     /// EXEC response: [STX][LEN_L][LEN_H][ERR_CODE][WORD_COUNT][WORD_IDX...][CRC8]
     pub fn decode_response(data: &[u8]) -> Result<Response> {
+        Self::decode(data, false, Checksum::Crc8)
+    }
+
+    /// Decode a response to a sequenced request
+    ///
+    /// Format: [STX][LEN_L][LEN_H][ERR_CODE][SEQ][DATA...][CRC8]
+    pub fn decode_sequenced_response(data: &[u8]) -> Result<Response> {
+        Self::decode(data, true, Checksum::Crc8)
+    }
+
+    /// Decode any frame sent by the device: a response or an output notification
+    ///
+    /// Output notifications are never sequenced:
+    /// [STX][LEN_L][LEN_H][0x80][TEXT...][CRC8]
+    ///
+    /// `data` must be exactly one frame, as transports return them, ending
+    /// in `checksum`: the one negotiated in HELLO, CRC-8 until then.
+    pub fn decode_incoming(data: &[u8], sequenced: bool, checksum: Checksum) -> Result<Incoming> {
+        let (code, body) = Self::unpack(data, checksum)?;
+        if code == NOTIFY_OUTPUT {
+            return Ok(Incoming::Output(body.to_vec()));
+        }
+        Self::decode(data, sequenced, checksum).map(Incoming::Response)
+    }
+
+    /// Validate a device frame and split it into its code byte and body
+    fn unpack(data: &[u8], checksum: Checksum) -> Result<(u8, &[u8])> {
         if data.len() < 5 {
             return Err(V4Error::Protocol(format!(
                 "Response too short: {} bytes (expected at least 5)",
@@ -88,66 +173,425 @@ impl Frame {
         }
 
         let length = u16::from_le_bytes([data[1], data[2]]) as usize;
-        let expected_frame_len = 4 + length; // STX(1) + LEN(2) + PAYLOAD(length) + CRC(1)
 
-        if data.len() < expected_frame_len {
+        // Length covers at least the code byte
+        if length == 0 {
+            return Err(V4Error::Protocol(
+                "Response length 0 has no error code".to_string(),
+            ));
+        }
+        let body_end = 3 + length; // STX(1) + LEN(2) + PAYLOAD(length)
+        let expected_frame_len = body_end + checksum.size();
+
+        if data.len() != expected_frame_len {
             return Err(V4Error::Protocol(format!(
-                "Response too short: {} bytes (expected {})",
+                "Response is {} bytes, expected {} with {}",
                 data.len(),
-                expected_frame_len
+                expected_frame_len,
+                checksum.name()
             )));
         }
 
-        let err_code = data[3];
-
-        // Extract payload (everything between error code and CRC)
-        let payload_start = 4;
-        let payload_end = 4 + length - 1; // -1 because length includes err_code
-        let payload = &data[payload_start..payload_end];
+        checksum.verify(&data[1..body_end], &data[body_end..])?;
 
-        // Verify CRC
-        let expected_crc = calc_crc8(&data[1..payload_end]);
-        let actual_crc = data[payload_end];
+        Ok((data[3], &data[4..body_end]))
+    }
 
-        if expected_crc != actual_crc {
-            return Err(V4Error::CrcMismatch {
-                expected: expected_crc,
-                actual: actual_crc,
-            });
-        }
+    fn decode(data: &[u8], sequenced: bool, checksum: Checksum) -> Result<Response> {
+        let (err_code, body) = Self::unpack(data, checksum)?;
+
+        // Sequenced responses carry SEQ right after the error code
+        let (seq, payload) = match (sequenced, body.split_first()) {
+            (false, _) => (None, body),
+            (true, Some((&seq, rest))) => (Some(seq), rest),
+            (true, None) => {
+                return Err(V4Error::Protocol(
+                    "Sequenced response missing SEQ byte".to_string(),
+                ));
+            }
+        };
 
         let err_code = ErrorCode::from_u8(err_code)
             .ok_or_else(|| V4Error::Protocol(format!("Unknown error code: {:#04x}", err_code)))?;
 
-        // Parse word indices if present
-        let word_indices = if !payload.is_empty() {
-            let word_count = payload[0] as usize;
-            let mut indices = Vec::with_capacity(word_count);
-
-            for i in 0..word_count {
-                let offset = 1 + i * 2;
-                if offset + 1 < payload.len() {
-                    let idx = u16::from_le_bytes([payload[offset], payload[offset + 1]]);
-                    indices.push(idx);
-                }
-            }
-            indices
-        } else {
-            Vec::new()
+        // EXEC answers start with [WORD_COUNT][WORD_IDX u16 LE...]; other
+        // payloads (see `payload`) only yield indices by accident, so a list
+        // the payload can't hold in full is ignored
+        let word_indices = match payload.split_first() {
+            Some((&count, rest)) if rest.len() >= count as usize * 2 => rest
+                .chunks_exact(2)
+                .take(count as usize)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+            _ => Vec::new(),
         };
 
         Ok(Response {
             error_code: err_code,
             word_indices,
             data: payload.to_vec(),
+            seq,
+        })
+    }
+}
+
+/// How frames are put on the wire
+///
+/// Both parts are negotiated with HELLO (see
+/// [`Handshake::framing`](super::Handshake::framing)); until then frames
+/// are sent as they are and end in CRC-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Framing {
+    pub checksum: Checksum,
+    /// COBS-encode each frame and end it with 0x00
+    ///
+    /// No other 0x00 is left on the wire, so a frame start is never found
+    /// inside a payload and the receiver resyncs at the next delimiter.
+    pub cobs: bool,
+}
+
+impl Framing {
+    /// Bytes to send for `frame`
+    pub fn encode(&self, frame: &Frame) -> Vec<u8> {
+        let encoded = frame.encode_with(self.checksum);
+        if !self.cobs {
+            return encoded;
+        }
+        let mut stuffed = cobs_encode(&encoded);
+        stuffed.push(COBS_END);
+        stuffed
+    }
+}
+
+impl fmt::Display for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.checksum.name())?;
+        if self.cobs {
+            f.write_str(", COBS")?;
+        }
+        Ok(())
+    }
+}
+
+/// Longest COBS-encoded frame: a full frame ending in CRC-32 plus one code
+/// byte per 254 bytes
+const MAX_STUFFED: usize = {
+    let frame = 3 + Frame::MAX_LENGTH + 4;
+    frame + frame / 254 + 1
+};
+
+/// Consistent Overhead Byte Stuffing: `data` without any 0x00
+///
+/// Each block starts with a code byte giving the distance to the next
+/// (removed) zero; 0xFF marks a block of 254 bytes not followed by one.
+pub(crate) fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 1);
+    let mut code_at = 0;
+    out.push(0);
+    for &byte in data {
+        if byte != 0 {
+            out.push(byte);
+        }
+        if byte == 0 || out.len() - code_at == 0xFF {
+            out[code_at] = (out.len() - code_at) as u8;
+            code_at = out.len();
+            out.push(0);
+        }
+    }
+    out[code_at] = (out.len() - code_at) as u8;
+    out
+}
+
+/// Undo [`cobs_encode`], `None` if `data` isn't valid COBS
+fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while let [code, tail @ ..] = rest {
+        let run = (*code as usize).checked_sub(1)?;
+        out.extend_from_slice(tail.get(..run)?);
+        rest = &tail[run..];
+        if *code != 0xFF && !rest.is_empty() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+/// Push parser splitting received bytes into device frames
+///
+/// Feed bytes as they arrive with [`push`](Self::push) and take complete
+/// frames with [`next_frame`](Self::next_frame). Bytes before an STX are
+/// noise and dropped, as is an STX followed by a length no frame has. A
+/// frame failing its CRC is reported once, then decoding resumes right
+/// after its STX, which may have been noise itself.
+///
+/// With COBS framing, frames end at the next 0x00 instead. A damaged frame
+/// costs only itself: the bytes up to the delimiter are dropped.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    /// Bytes received but not yet returned as a frame
+    buf: Vec<u8>,
+    framing: Framing,
+}
+
+/// What the bytes at the front of a [`FrameDecoder`] hold
+enum Step {
+    /// A frame has started but not all of it is there yet
+    Wait,
+    /// Noise to drop
+    Skip(usize),
+    /// A frame of this many bytes with a valid CRC
+    Frame(usize),
+    /// A complete frame whose CRC doesn't match
+    Corrupt(V4Error),
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Framing frames are expected in
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Expect frames in `framing` from now on
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Add received bytes
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Take the next complete frame, `None` until one has arrived
+    ///
+    /// Frames are returned raw, STX to CRC, for [`Frame::decode_incoming`].
+    pub fn next_frame(&mut self) -> Option<Result<Vec<u8>>> {
+        if self.framing.cobs {
+            return self.next_stuffed();
+        }
+        loop {
+            match scan(&self.buf, self.framing.checksum) {
+                Step::Wait => return None,
+                Step::Skip(n) => {
+                    log::trace!("Skipped {} noise byte(s): {:02X?}", n, &self.buf[..n]);
+                    self.buf.drain(..n);
+                }
+                Step::Frame(n) => return Some(Ok(self.buf.drain(..n).collect())),
+                Step::Corrupt(error) => {
+                    self.buf.remove(0);
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+
+    /// [`next_frame`](Self::next_frame) with COBS framing
+    fn next_stuffed(&mut self) -> Option<Result<Vec<u8>>> {
+        loop {
+            let Some(end) = self.buf.iter().position(|&b| b == COBS_END) else {
+                // No frame is this long; the start is noise
+                if self.buf.len() > MAX_STUFFED {
+                    let excess = self.buf.len() - MAX_STUFFED;
+                    log::trace!("Skipped {} noise byte(s)", excess);
+                    self.buf.drain(..excess);
+                }
+                return None;
+            };
+            let stuffed: Vec<u8> = self.buf.drain(..=end).collect();
+            if end == 0 {
+                continue;
+            }
+            let frame = cobs_decode(&stuffed[..end]).ok_or_else(|| {
+                V4Error::Protocol(format!("Invalid COBS frame: {:02X?}", &stuffed[..end]))
+            });
+            return Some(
+                frame.and_then(|frame| match scan(&frame, self.framing.checksum) {
+                    Step::Frame(n) if n == frame.len() => Ok(frame),
+                    Step::Corrupt(error) => Err(error),
+                    _ => Err(V4Error::Protocol(format!(
+                        "Malformed frame in COBS framing: {:02X?}",
+                        frame
+                    ))),
+                }),
+            );
+        }
+    }
+
+    /// Move undecoded bytes into `buf`, e.g. for raw reads; returns how many
+    pub fn read_into(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.buf.len());
+        buf[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.drain(..n);
+        n
+    }
+
+    /// No bytes are waiting
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Drop every byte received so far
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+/// Look for a frame ending in `checksum` at the start of `buf`
+fn scan(buf: &[u8], checksum: Checksum) -> Step {
+    match buf.iter().position(|&b| b == STX) {
+        None if buf.is_empty() => return Step::Wait,
+        None => return Step::Skip(buf.len()),
+        Some(0) => {}
+        Some(start) => return Step::Skip(start),
+    }
+    let [_, len_l, len_h, ..] = buf[..] else {
+        return Step::Wait;
+    };
+    // Frames carry at least a code byte
+    let length = u16::from_le_bytes([len_l, len_h]) as usize;
+    if length == 0 || length > Frame::MAX_LENGTH {
+        return Step::Skip(1);
+    }
+    let crc_at = 3 + length;
+    let end = crc_at + checksum.size();
+    let Some(sum) = buf.get(crc_at..end) else {
+        return Step::Wait;
+    };
+    match checksum.verify(&buf[1..crc_at], sum) {
+        Ok(()) => Step::Frame(end),
+        Err(error) => Step::Corrupt(error),
+    }
+}
+
+/// Why the VM stopped with VM_ERROR: the subcode of a [`VmFault`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    StackUnderflow,
+    StackOverflow,
+    ReturnStackUnderflow,
+    ReturnStackOverflow,
+    DivisionByZero,
+    InvalidInstruction,
+    MemoryOutOfBounds,
+    UnknownWord,
+    UnknownSyscall,
+    /// The instruction limit ran out
+    StepLimit,
+    /// A subcode this host doesn't know
+    Other(u8),
+}
+
+impl FaultKind {
+    const KNOWN: [FaultKind; 10] = [
+        FaultKind::StackUnderflow,
+        FaultKind::StackOverflow,
+        FaultKind::ReturnStackUnderflow,
+        FaultKind::ReturnStackOverflow,
+        FaultKind::DivisionByZero,
+        FaultKind::InvalidInstruction,
+        FaultKind::MemoryOutOfBounds,
+        FaultKind::UnknownWord,
+        FaultKind::UnknownSyscall,
+        FaultKind::StepLimit,
+    ];
+
+    /// Subcodes count from 0x01 in the order of [`FaultKind::KNOWN`]
+    pub fn from_u8(code: u8) -> Self {
+        Self::KNOWN
+            .into_iter()
+            .find(|kind| kind.code() == code)
+            .unwrap_or(FaultKind::Other(code))
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            FaultKind::Other(code) => code,
+            known => Self::KNOWN.iter().position(|&k| k == known).unwrap_or(0) as u8 + 1,
+        }
+    }
+
+    /// Lowercase description, e.g. `stack underflow`
+    pub fn description(self) -> String {
+        match self {
+            FaultKind::StackUnderflow => "stack underflow".to_string(),
+            FaultKind::StackOverflow => "stack overflow".to_string(),
+            FaultKind::ReturnStackUnderflow => "return stack underflow".to_string(),
+            FaultKind::ReturnStackOverflow => "return stack overflow".to_string(),
+            FaultKind::DivisionByZero => "division by zero".to_string(),
+            FaultKind::InvalidInstruction => "invalid instruction".to_string(),
+            FaultKind::MemoryOutOfBounds => "memory access out of bounds".to_string(),
+            FaultKind::UnknownWord => "call to an undefined word".to_string(),
+            FaultKind::UnknownSyscall => "unknown system call".to_string(),
+            FaultKind::StepLimit => "instruction limit reached".to_string(),
+            FaultKind::Other(code) => format!("VM error 0x{:02X}", code),
+        }
+    }
+}
+
+/// Details of a VM_ERROR response
+///
+/// Payload: [WORD_IDX u16 LE][IP u16 LE][SUBCODE][STACK_DEPTH]. The location
+/// comes first, laid out as in HALTED (word 0xFFFF is the main code); older
+/// firmware sends only the location, or nothing at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmFault {
+    pub word: u16,
+    /// Offset of the failing instruction within the word's bytecode
+    pub ip: u16,
+    pub kind: Option<FaultKind>,
+    /// Data stack depth when the VM stopped
+    pub stack_depth: Option<u8>,
+}
+
+impl VmFault {
+    /// Parse a VM_ERROR payload, `None` if it doesn't hold a location
+    pub fn from_payload(data: &[u8]) -> Option<Self> {
+        let [w0, w1, i0, i1, rest @ ..] = data else {
+            return None;
+        };
+        Some(Self {
+            word: u16::from_le_bytes([*w0, *w1]),
+            ip: u16::from_le_bytes([*i0, *i1]),
+            kind: rest.first().map(|&code| FaultKind::from_u8(code)),
+            stack_depth: rest.get(1).copied(),
         })
     }
+
+    /// Encode as a VM_ERROR payload, leaving out unknown trailing fields
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = self.word.to_le_bytes().to_vec();
+        payload.extend_from_slice(&self.ip.to_le_bytes());
+        if let Some(kind) = self.kind {
+            payload.push(kind.code());
+            payload.extend(self.stack_depth);
+        }
+        payload
+    }
+
+    /// What went wrong, `VM_ERROR` if the device didn't say
+    pub fn reason(&self) -> String {
+        self.kind
+            .map(FaultKind::description)
+            .unwrap_or_else(|| ErrorCode::VmError.name().to_string())
+    }
+
+    /// `, stack depth 2`, or nothing if the device didn't say
+    pub fn depth_note(&self) -> String {
+        self.stack_depth
+            .map(|depth| format!(", stack depth {}", depth))
+            .unwrap_or_default()
+    }
 }
 
 /// Builder for creating frames
 pub struct FrameBuilder {
     command: Command,
     payload: Vec<u8>,
+    seq: Option<u8>,
 }
 
 impl FrameBuilder {
@@ -155,6 +599,7 @@ impl FrameBuilder {
         Self {
             command,
             payload: Vec::new(),
+            seq: None,
         }
     }
 
@@ -163,14 +608,32 @@ impl FrameBuilder {
         self
     }
 
+    pub fn seq(mut self, seq: u8) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
     pub fn build(self) -> Result<Frame> {
-        Frame::new(self.command, self.payload)
+        let frame = Frame::new(self.command, self.payload)?;
+        Ok(match self.seq {
+            Some(seq) => frame.with_seq(seq),
+            None => frame,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::calc_crc8;
+    use proptest::prelude::*;
+
+    /// Every frame the decoder holds, `Err` for CRC failures
+    fn drain(decoder: &mut FrameDecoder) -> Vec<std::result::Result<Vec<u8>, String>> {
+        std::iter::from_fn(|| decoder.next_frame())
+            .map(|frame| frame.map_err(|e| e.to_string()))
+            .collect()
+    }
 
     #[test]
     fn test_ping_frame_encoding() {
@@ -252,6 +715,91 @@ mod tests {
         assert_eq!(result.word_indices[0], 0);
     }
 
+    #[test]
+    fn test_response_decode_query_payload() {
+        // QUERY_MEMORY answer whose first byte would announce 5 word indices
+        let response_data = vec![0x04, 0x00, 0x00, 0x05, 0x01, 0x02];
+        let mut response = vec![0xA5];
+        response.extend_from_slice(&response_data);
+        response.push(calc_crc8(&response_data));
+
+        let result = Frame::decode_response(&response).unwrap();
+        assert!(result.word_indices.is_empty());
+        assert_eq!(result.data, vec![0x05, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn test_sequenced_frame_encoding() {
+        let frame = Frame::new(Command::Exec, vec![0x51]).unwrap().with_seq(7);
+        let encoded = frame.encode();
+
+        // [STX][0x02][0x00][0x10][SEQ=7][0x51][CRC]
+        assert_eq!(&encoded[..6], &[0xA5, 0x02, 0x00, 0x10, 0x07, 0x51]);
+        assert_eq!(encoded[6], calc_crc8(&encoded[1..6]));
+    }
+
+    #[test]
+    fn test_sequenced_response_decode() {
+        // [STX][LEN=4][ERR_OK][SEQ=9][WORD_COUNT=1][WORD_IDX=3][CRC]
+        let response_data = vec![0x05, 0x00, 0x00, 0x09, 0x01, 0x03, 0x00];
+        let mut response = vec![0xA5];
+        response.extend_from_slice(&response_data);
+        response.push(calc_crc8(&response_data));
+
+        let result = Frame::decode_sequenced_response(&response).unwrap();
+        assert_eq!(result.seq, Some(9));
+        assert_eq!(result.word_indices, vec![3]);
+        assert_eq!(Frame::decode_response(&response).unwrap().seq, None);
+
+        // A plain response is too short to carry SEQ
+        let plain = vec![0xA5, 0x01, 0x00, 0x00, calc_crc8(&[0x01, 0x00, 0x00])];
+        assert!(Frame::decode_sequenced_response(&plain).is_err());
+    }
+
+    #[test]
+    fn test_decode_incoming_output() {
+        let body = [0x03, 0x00, NOTIFY_OUTPUT, b'h', b'i'];
+        let mut frame = vec![0xA5];
+        frame.extend_from_slice(&body);
+        frame.push(calc_crc8(&body));
+
+        assert_eq!(
+            Frame::decode_incoming(&frame, true, Checksum::Crc8).unwrap(),
+            Incoming::Output(b"hi".to_vec())
+        );
+        assert!(Frame::decode_response(&frame).is_err());
+
+        let ok = [0xA5, 0x01, 0x00, 0x00, calc_crc8(&[0x01, 0x00, 0x00])];
+        assert!(matches!(
+            Frame::decode_incoming(&ok, false, Checksum::Crc8).unwrap(),
+            Incoming::Response(_)
+        ));
+    }
+
+    #[test]
+    fn test_decode_incoming_uses_negotiated_checksum() {
+        let ok = device_frame(0x00, &[]);
+        let decode = |frame: &[u8], checksum| Frame::decode_incoming(frame, false, checksum);
+        assert!(decode(&ok, Checksum::Crc8).is_ok());
+
+        // A stray byte after a CRC-8 frame isn't read as CRC-16
+        let stray = [&ok[..], &[0x00]].concat();
+        assert!(matches!(
+            decode(&stray, Checksum::Crc8),
+            Err(V4Error::Protocol(_))
+        ));
+        assert!(decode(&ok, Checksum::Crc16).is_err());
+
+        let mut crc16 = ok[..4].to_vec();
+        crc16.extend_from_slice(&Checksum::Crc16.compute(&crc16[1..]));
+        assert!(decode(&crc16, Checksum::Crc16).is_ok());
+        // Cut short, it isn't checked as CRC-8
+        assert!(matches!(
+            decode(&crc16[..5], Checksum::Crc16),
+            Err(V4Error::Protocol(_))
+        ));
+    }
+
     #[test]
     fn test_response_decode_crc_mismatch() {
         // Invalid CRC
@@ -262,7 +810,7 @@ mod tests {
 
     #[test]
     fn test_payload_too_large() {
-        let payload = vec![0; MAX_PAYLOAD_SIZE + 1];
+        let payload = vec![0; Frame::MAX_PAYLOAD_SIZE + 1];
         let result = Frame::new(Command::Exec, payload);
         assert!(matches!(result, Err(V4Error::Protocol(_))));
     }
@@ -277,4 +825,182 @@ mod tests {
         assert_eq!(frame.command as u8, 0xFF);
         assert_eq!(frame.payload.len(), 0);
     }
+
+    #[test]
+    fn test_vm_fault_payload() {
+        let fault = VmFault::from_payload(&[0x03, 0x00, 0x12, 0x00, 0x01, 0x00]).unwrap();
+        assert_eq!((fault.word, fault.ip), (3, 0x12));
+        assert_eq!(fault.kind, Some(FaultKind::StackUnderflow));
+        assert_eq!(fault.stack_depth, Some(0));
+        assert_eq!(fault.reason(), "stack underflow");
+        assert_eq!(fault.to_payload(), [0x03, 0x00, 0x12, 0x00, 0x01, 0x00]);
+
+        // Location only, as sent by older firmware
+        let fault = VmFault::from_payload(&[0xFF, 0xFF, 0x02, 0x00]).unwrap();
+        assert_eq!(fault.kind, None);
+        assert_eq!(fault.reason(), "VM_ERROR");
+        assert_eq!(fault.depth_note(), "");
+        assert!(VmFault::from_payload(&[0x03, 0x00]).is_none());
+
+        assert_eq!(FaultKind::from_u8(0x05), FaultKind::DivisionByZero);
+        assert_eq!(FaultKind::from_u8(0x80), FaultKind::Other(0x80));
+        assert_eq!(FaultKind::Other(0x80).description(), "VM error 0x80");
+        assert_eq!(FaultKind::StepLimit.code(), 0x0A);
+    }
+
+    #[test]
+    fn test_decoder_resyncs_after_noise_and_crc_errors() {
+        let ok = device_frame(0x00, &[]);
+        let output = device_frame(NOTIFY_OUTPUT, b"hi");
+        let mut corrupt = device_frame(0x00, &[1, 2]);
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+
+        let mut decoder = FrameDecoder::new();
+        // Text, then an STX with an impossible length
+        decoder.push(b"boot\n\xA5\xFF\xFF");
+        decoder.push(&output[..3]);
+        assert!(drain(&mut decoder).is_empty());
+        decoder.push(&output[3..]);
+        assert_eq!(drain(&mut decoder), [Ok(output.clone())]);
+
+        decoder.push(&corrupt);
+        decoder.push(&ok);
+        let frames = drain(&mut decoder);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].as_ref().unwrap_err().starts_with("CRC mismatch"));
+        assert_eq!(frames[1], Ok(ok.clone()));
+        assert!(decoder.is_empty());
+
+        // A noise STX whose length swallows a real frame: the CRC fails and
+        // the frame is found again right after it
+        decoder.push(&[STX, 0x03, 0x00]);
+        decoder.push(&ok);
+        let frames = drain(&mut decoder);
+        assert!(frames[0].is_err());
+        assert_eq!(frames[1..], [Ok(ok.clone())]);
+
+        decoder.push(&ok[..2]);
+        let mut buf = [0u8; 8];
+        assert_eq!(decoder.read_into(&mut buf), 2);
+        assert_eq!(buf[..2], ok[..2]);
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn test_cobs_encoding() {
+        assert_eq!(cobs_encode(&[]), [0x01]);
+        assert_eq!(cobs_encode(&[0x00]), [0x01, 0x01]);
+        assert_eq!(cobs_encode(&[0x11, 0x00, 0x22]), [0x02, 0x11, 0x02, 0x22]);
+        // A full block needs no zero after it
+        let run: Vec<u8> = (1..=254).collect();
+        let stuffed = cobs_encode(&run);
+        assert_eq!(stuffed.len(), 256);
+        assert_eq!((stuffed[0], stuffed[255]), (0xFF, 0x01));
+        assert_eq!(cobs_decode(&stuffed).unwrap(), run);
+        assert_eq!(cobs_decode(&[0x03, 0x11]), None);
+        assert_eq!(cobs_decode(&[0x00]), None);
+    }
+
+    #[test]
+    fn test_decoder_with_cobs_framing() {
+        let framing = Framing {
+            checksum: Checksum::Crc16,
+            cobs: true,
+        };
+        let frame = |code: u8, payload: &[u8]| {
+            let mut frame = vec![STX];
+            frame.extend_from_slice(&((payload.len() + 1) as u16).to_le_bytes());
+            frame.push(code);
+            frame.extend_from_slice(payload);
+            frame.extend_from_slice(&framing.checksum.compute(&frame[1..]));
+            frame
+        };
+        let stuff = |frame: &[u8]| [cobs_encode(frame), vec![COBS_END]].concat();
+        // An STX and a plausible length inside the payload
+        let output = frame(NOTIFY_OUTPUT, &[STX, 0x01, 0x00, 0x00]);
+        let ok = frame(0x00, &[]);
+        let mut corrupt = frame(0x00, &[1, 2]);
+        corrupt[5] ^= 0xFF;
+
+        let mut decoder = FrameDecoder::new();
+        decoder.set_framing(framing);
+        decoder.push(b"\x00\x00");
+        decoder.push(&stuff(&corrupt));
+        decoder.push(&stuff(&output));
+        decoder.push(&stuff(&ok)[..3]);
+        let frames = drain(&mut decoder);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].as_ref().unwrap_err().starts_with("CRC mismatch"));
+        assert_eq!(frames[1], Ok(output));
+        decoder.push(&stuff(&ok)[3..]);
+        assert_eq!(drain(&mut decoder), [Ok(ok.clone())]);
+
+        // Plain frames don't pass for stuffed ones
+        decoder.push(&frame(0x00, &[0x05, 0x00]));
+        assert!(drain(&mut decoder)[0].is_err());
+
+        let request = Frame::new(Command::Ping, vec![0x00, STX]).unwrap();
+        let sent = framing.encode(&request);
+        assert_eq!(
+            sent.iter().position(|&b| b == COBS_END),
+            Some(sent.len() - 1)
+        );
+        assert_eq!(
+            cobs_decode(&sent[..sent.len() - 1]).unwrap(),
+            request.encode_with(Checksum::Crc16)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_cobs_roundtrip(data in prop::collection::vec(any::<u8>(), 0..600)) {
+            let stuffed = cobs_encode(&data);
+            prop_assert!(!stuffed.contains(&COBS_END));
+            prop_assert_eq!(cobs_decode(&stuffed), Some(data));
+        }
+
+        #[test]
+        fn prop_decoder_splits_frames_anywhere(
+            frames in prop::collection::vec(
+                (any::<u8>(), prop::collection::vec(any::<u8>(), 0..64)),
+                0..8,
+            ),
+            noise in prop::collection::vec(any::<u8>().prop_filter("not STX", |&b| b != STX), 0..16),
+            chunk in 1usize..32,
+        ) {
+            let frames: Vec<Vec<u8>> = frames
+                .iter()
+                .map(|(code, payload)| device_frame(*code, payload))
+                .collect();
+            let mut stream = Vec::new();
+            for frame in &frames {
+                stream.extend_from_slice(&noise);
+                stream.extend_from_slice(frame);
+            }
+
+            let mut decoder = FrameDecoder::new();
+            let mut decoded = Vec::new();
+            for piece in stream.chunks(chunk) {
+                decoder.push(piece);
+                decoded.extend(drain(&mut decoder));
+            }
+            let expected: Vec<_> = frames.into_iter().map(Ok).collect();
+            prop_assert_eq!(decoded, expected);
+            prop_assert!(decoder.is_empty());
+        }
+
+        #[test]
+        fn prop_decoder_only_yields_valid_frames(
+            bytes in prop::collection::vec(any::<u8>(), 0..600),
+            chunk in 1usize..64,
+        ) {
+            let mut decoder = FrameDecoder::new();
+            for piece in bytes.chunks(chunk) {
+                decoder.push(piece);
+                for frame in drain(&mut decoder).into_iter().flatten() {
+                    prop_assert!(Frame::unpack(&frame, Checksum::Crc8).is_ok(), "{:02X?}", frame);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,269 @@
+//! Typed payloads of query responses
+//!
+//! [`Response::data`](super::Response) holds the raw bytes after the error
+//! code (and SEQ); these types give the QUERY_STACK, QUERY_MEMORY,
+//! QUERY_WORD and QUERY_TICKS answers their structure. Parsing fails on truncated payloads
+//! rather than guessing.
+
+use crate::{Result, V4Error};
+
+/// Cell size in bytes
+const CELL_SIZE: usize = 4;
+
+/// QUERY_STACK answer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StackSnapshot {
+    /// Data stack cells, in the order the device sends them
+    pub data: Vec<i32>,
+    /// Return stack cells (return addresses and `>R` values), in the order
+    /// the device sends them
+    pub ret: Vec<u32>,
+}
+
+impl StackSnapshot {
+    /// Parse [DS_DEPTH][DS_CELLS...][RS_DEPTH][RS_CELLS...], cells i32 LE
+    pub fn from_payload(data: &[u8]) -> Result<Self> {
+        let (stack, rest) = cells(data)?;
+        let (ret, rest) = cells(rest)?;
+        if !rest.is_empty() {
+            return Err(malformed("stack", data.len()));
+        }
+        Ok(Self {
+            data: stack,
+            ret: ret.into_iter().map(|cell| cell as u32).collect(),
+        })
+    }
+
+    /// Encode as a QUERY_STACK payload, keeping the topmost 255 cells of each stack
+    pub fn to_payload(&self) -> Vec<u8> {
+        let ret: Vec<i32> = self.ret.iter().map(|&cell| cell as i32).collect();
+        let mut payload = Vec::new();
+        for stack in [&self.data, &ret] {
+            let stack = &stack[stack.len().saturating_sub(u8::MAX as usize)..];
+            payload.push(stack.len() as u8);
+            for cell in stack {
+                payload.extend_from_slice(&cell.to_le_bytes());
+            }
+        }
+        payload
+    }
+}
+
+/// Split a depth-prefixed run of cells off the front of `data`
+fn cells(data: &[u8]) -> Result<(Vec<i32>, &[u8])> {
+    let depth = *data.first().ok_or_else(|| malformed("stack", data.len()))? as usize;
+    let end = 1 + depth * CELL_SIZE;
+    let bytes = data
+        .get(1..end)
+        .ok_or_else(|| malformed("stack", data.len()))?;
+    let cells = bytes
+        .chunks_exact(CELL_SIZE)
+        .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    Ok((cells, &data[end..]))
+}
+
+/// QUERY_MEMORY answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDump {
+    /// Address of the first byte
+    pub addr: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl MemoryDump {
+    /// Parse the raw bytes read from `addr`
+    ///
+    /// The device may return fewer bytes than requested at the end of
+    /// memory, but never more.
+    pub fn from_payload(addr: u32, requested: u16, data: &[u8]) -> Result<Self> {
+        if data.len() > requested as usize {
+            return Err(V4Error::Protocol(format!(
+                "Memory response has {} bytes, {} requested",
+                data.len(),
+                requested
+            )));
+        }
+        Ok(Self {
+            addr,
+            bytes: data.to_vec(),
+        })
+    }
+
+    /// Address just past the last byte
+    pub fn end(&self) -> u32 {
+        self.addr.wrapping_add(self.bytes.len() as u32)
+    }
+}
+
+/// QUERY_WORD answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordInfo {
+    pub name: Option<String>,
+    /// Bytecode length reported by the device
+    pub code_len: usize,
+    /// Bytecode bytes received (may be shorter than `code_len`)
+    pub code: Vec<u8>,
+    /// Address of the bytecode in VM memory, if the firmware reports it
+    pub address: Option<u32>,
+}
+
+impl WordInfo {
+    /// Parse [NAME_LEN][NAME...][CODE_LEN u16 LE][CODE...]
+    ///
+    /// Newer firmware appends the bytecode address (u32 LE) after the code.
+    pub fn from_payload(data: &[u8]) -> Result<Self> {
+        let truncated = || malformed("word", data.len());
+        let name_len = *data.first().ok_or_else(truncated)? as usize;
+        let name_end = 1 + name_len;
+        let name = data.get(1..name_end).ok_or_else(truncated)?;
+        let len_bytes = data.get(name_end..name_end + 2).ok_or_else(truncated)?;
+        let code_len = u16::from_le_bytes([len_bytes[0], len_bytes[1]]) as usize;
+        let code_start = name_end + 2;
+        let code_end = data.len().min(code_start + code_len);
+        let address = data
+            .get(code_end..code_end + 4)
+            .filter(|_| code_end == code_start + code_len)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+        Ok(Self {
+            name: (name_len > 0).then(|| String::from_utf8_lossy(name).to_string()),
+            code_len,
+            code: data[code_start..code_end].to_vec(),
+            address,
+        })
+    }
+
+    /// Encode as a QUERY_WORD payload
+    pub fn to_payload(&self) -> Vec<u8> {
+        let name = self.name.as_deref().unwrap_or_default().as_bytes();
+        let name = &name[..name.len().min(u8::MAX as usize)];
+        let mut payload = vec![name.len() as u8];
+        payload.extend_from_slice(name);
+        payload.extend_from_slice(&(self.code_len as u16).to_le_bytes());
+        payload.extend_from_slice(&self.code);
+        if let Some(address) = self.address {
+            payload.extend_from_slice(&address.to_le_bytes());
+        }
+        payload
+    }
+
+    /// Name for listings
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<anonymous>")
+    }
+}
+
+/// QUERY_TICKS answer: the VM cycle counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ticks {
+    /// Cycles since the VM started
+    pub count: u64,
+    /// Cycles per second
+    pub rate_hz: u32,
+}
+
+impl Ticks {
+    /// Parse [TICKS u64 LE][RATE_HZ u32 LE]
+    pub fn from_payload(data: &[u8]) -> Result<Self> {
+        let [count @ .., r0, r1, r2, r3] = data else {
+            return Err(malformed("ticks", data.len()));
+        };
+        let count: [u8; 8] = count
+            .try_into()
+            .map_err(|_| malformed("ticks", data.len()))?;
+        let rate_hz = u32::from_le_bytes([*r0, *r1, *r2, *r3]);
+        if rate_hz == 0 {
+            return Err(malformed("ticks", data.len()));
+        }
+        Ok(Self {
+            count: u64::from_le_bytes(count),
+            rate_hz,
+        })
+    }
+
+    /// Time between an earlier reading and this one
+    pub fn since(&self, earlier: &Ticks) -> std::time::Duration {
+        let cycles = self.count.wrapping_sub(earlier.count) as u128;
+        let nanos = cycles * 1_000_000_000 / self.rate_hz as u128;
+        std::time::Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+}
+
+fn malformed(kind: &str, len: usize) -> V4Error {
+    V4Error::Protocol(format!("Malformed {} response ({} bytes)", kind, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_snapshot() {
+        // DS: 5, -1; RS: 0x1234
+        let payload = [2, 5, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 1, 0x34, 0x12, 0, 0];
+        let stack = StackSnapshot::from_payload(&payload).unwrap();
+        assert_eq!(stack.data, vec![5, -1]);
+        assert_eq!(stack.ret, vec![0x1234]);
+        assert_eq!(stack.to_payload(), payload);
+
+        let empty = StackSnapshot::from_payload(&[0, 0]).unwrap();
+        assert_eq!(empty, StackSnapshot::default());
+    }
+
+    #[test]
+    fn test_stack_snapshot_rejects_truncated() {
+        assert!(StackSnapshot::from_payload(&[]).is_err());
+        // Depth 1 with only 3 bytes of cell
+        assert!(StackSnapshot::from_payload(&[1, 5, 0, 0]).is_err());
+        // Return stack depth missing
+        assert!(StackSnapshot::from_payload(&[1, 5, 0, 0, 0]).is_err());
+        // Trailing byte
+        assert!(StackSnapshot::from_payload(&[0, 0, 9]).is_err());
+    }
+
+    #[test]
+    fn test_ticks() {
+        let mut payload = 1_000u64.to_le_bytes().to_vec();
+        payload.extend_from_slice(&1_000_000u32.to_le_bytes());
+        let before = Ticks::from_payload(&payload).unwrap();
+        payload[..8].copy_from_slice(&4_500u64.to_le_bytes());
+        let after = Ticks::from_payload(&payload).unwrap();
+        assert_eq!(
+            after.since(&before),
+            std::time::Duration::from_micros(3_500)
+        );
+
+        assert!(Ticks::from_payload(&payload[..11]).is_err());
+        assert!(Ticks::from_payload(&[0; 12]).is_err());
+    }
+
+    #[test]
+    fn test_memory_dump() {
+        let dump = MemoryDump::from_payload(0x100, 4, &[1, 2, 3]).unwrap();
+        assert_eq!(dump.bytes, vec![1, 2, 3]);
+        assert_eq!(dump.end(), 0x103);
+        assert!(MemoryDump::from_payload(0x100, 2, &[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_word_info() {
+        let word = WordInfo::from_payload(&[2, b'S', b'Q', 3, 0, 0x01, 0x12]).unwrap();
+        assert_eq!(word.name.as_deref(), Some("SQ"));
+        assert_eq!(word.code_len, 3);
+        assert_eq!(word.code, vec![0x01, 0x12]);
+        assert_eq!(word.address, None);
+
+        let payload = [1, b'X', 1, 0, 0x51, 0x00, 0x20, 0x00, 0x00];
+        let with_address = WordInfo::from_payload(&payload).unwrap();
+        assert_eq!(with_address.code, vec![0x51]);
+        assert_eq!(with_address.address, Some(0x2000));
+        assert_eq!(with_address.to_payload(), payload);
+
+        let anonymous = WordInfo::from_payload(&[0, 0, 0]).unwrap();
+        assert_eq!(anonymous.display_name(), "<anonymous>");
+
+        assert!(WordInfo::from_payload(&[5, b'A']).is_err());
+        assert!(WordInfo::from_payload(&[]).is_err());
+    }
+}
@@ -1,21 +1,106 @@
+use super::crc::Checksum;
+use super::frame::Framing;
+
+/// Highest V4-link protocol version spoken by this host
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// HELLO feature bit: frames carry a sequence number
+pub const FEATURE_SEQUENCE: u8 = 0x01;
+
+/// HELLO feature bit: frames end in a CRC-16 instead of a CRC-8
+pub const FEATURE_CRC16: u8 = 0x04;
+
+/// HELLO feature bit: frames end in a CRC-32 instead of a CRC-8
+pub const FEATURE_CRC32: u8 = 0x08;
+
+/// HELLO feature bit: frames are COBS-encoded and end in 0x00
+pub const FEATURE_COBS: u8 = 0x10;
+
+/// HELLO feature bit: the device takes LZ4-compressed chunked EXECs
+pub const FEATURE_LZ4: u8 = 0x20;
+
+/// QUERY_INFO feature bit: the VM has a cycle counter (QUERY_TICKS)
+pub const FEATURE_TICKS: u8 = 0x02;
+
 /// V4-link protocol commands
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Command {
+    /// Protocol version and feature negotiation
+    Hello = 0x01,
     /// Execute bytecode
     Exec = 0x10,
+    /// Start a chunked EXEC: total bytecode length (u32 LE)
+    ExecBegin = 0x11,
+    /// Chunked EXEC data: offset (u32 LE) followed by bytecode bytes
+    ExecData = 0x12,
+    /// Finish a chunked EXEC and execute the assembled bytecode
+    ExecEnd = 0x13,
     /// Connection check
     Ping = 0x20,
     /// Query stack state
     QueryStack = 0x30,
     /// Query memory dump
     QueryMemory = 0x40,
+    /// Write memory: address (u32 LE) followed by the bytes to store
+    WriteMemory = 0x41,
     /// Query word information
     QueryWord = 0x50,
+    /// Query firmware version and VM capabilities
+    QueryInfo = 0x60,
+    /// Read the VM cycle counter
+    QueryTicks = 0x61,
+    /// Set a breakpoint: word index (u16 LE) and bytecode offset (u16 LE)
+    SetBreakpoint = 0x70,
+    /// Execute one instruction of a halted VM
+    Step = 0x71,
+    /// Resume a halted VM until the next breakpoint or the end
+    Continue = 0x72,
+    /// Stop the running program, or drop a partial chunked EXEC
+    Abort = 0x73,
     /// VM reset
     Reset = 0xFF,
 }
 
+impl Command {
+    /// Convert u8 to Command
+    pub fn from_u8(value: u8) -> Option<Self> {
+        [
+            Command::Hello,
+            Command::Exec,
+            Command::ExecBegin,
+            Command::ExecData,
+            Command::ExecEnd,
+            Command::Ping,
+            Command::QueryStack,
+            Command::QueryMemory,
+            Command::WriteMemory,
+            Command::QueryWord,
+            Command::QueryInfo,
+            Command::QueryTicks,
+            Command::SetBreakpoint,
+            Command::Step,
+            Command::Continue,
+            Command::Abort,
+            Command::Reset,
+        ]
+        .into_iter()
+        .find(|&command| command as u8 == value)
+    }
+
+    /// Whether resending the command after a lost response is harmless
+    ///
+    /// EXEC, EXEC_END, STEP and CONTINUE run code on the device, so a
+    /// resend could run it twice. Chunk frames carry their offset and can
+    /// be repeated.
+    pub fn is_idempotent(self) -> bool {
+        !matches!(
+            self,
+            Command::Exec | Command::ExecEnd | Command::Step | Command::Continue
+        )
+    }
+}
+
 /// V4-link protocol error codes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -30,6 +115,10 @@ pub enum ErrorCode {
     BufferFull = 0x03,
     /// VM execution error
     VmError = 0x04,
+    /// VM stopped at a breakpoint or after a step; payload is the location
+    Halted = 0x05,
+    /// Command accepted and still running; the result follows in a later frame
+    Accepted = 0x06,
 }
 
 impl ErrorCode {
@@ -41,6 +130,8 @@ impl ErrorCode {
             0x02 => Some(ErrorCode::InvalidFrame),
             0x03 => Some(ErrorCode::BufferFull),
             0x04 => Some(ErrorCode::VmError),
+            0x05 => Some(ErrorCode::Halted),
+            0x06 => Some(ErrorCode::Accepted),
             _ => None,
         }
     }
@@ -53,6 +144,58 @@ impl ErrorCode {
             ErrorCode::InvalidFrame => "INVALID_FRAME",
             ErrorCode::BufferFull => "BUFFER_FULL",
             ErrorCode::VmError => "VM_ERROR",
+            ErrorCode::Halted => "HALTED",
+            ErrorCode::Accepted => "ACCEPTED",
+        }
+    }
+}
+
+/// Result of the HELLO handshake
+///
+/// The host sends its protocol version and the features it wants; the
+/// device answers with its own version and the features it enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u8,
+    pub features: u8,
+}
+
+impl Handshake {
+    /// Parse a HELLO response payload: [VERSION][FEATURES]
+    pub fn from_payload(data: &[u8]) -> Option<Self> {
+        match data {
+            [version, features, ..] => Some(Self {
+                version: *version,
+                features: *features,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the device tracks sequence numbers for duplicate detection
+    pub fn supports_sequence(&self) -> bool {
+        self.features & FEATURE_SEQUENCE != 0
+    }
+
+    /// Whether the device decompresses LZ4 EXEC transfers, see
+    /// [`compress`](crate::protocol::compress)
+    pub fn supports_lz4(&self) -> bool {
+        self.features & FEATURE_LZ4 != 0
+    }
+
+    /// Strongest checksum the device enabled, CRC-8 if none
+    pub fn checksum(&self) -> Checksum {
+        Checksum::STRONGEST_FIRST
+            .into_iter()
+            .find(|checksum| self.features & checksum.feature() != 0)
+            .unwrap_or_default()
+    }
+
+    /// Framing the device switched to for the frames after HELLO
+    pub fn framing(&self) -> Framing {
+        Framing {
+            checksum: self.checksum(),
+            cobs: self.features & FEATURE_COBS != 0,
         }
     }
 }
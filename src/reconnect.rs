@@ -0,0 +1,203 @@
+//! Reopen a serial port after the device re-enumerates
+//!
+//! Unplugging a USB device, or a board restarting its USB interface, leaves
+//! the open port dead for good. [`Reconnecting`] remembers how the port was
+//! opened and can wait for it to come back. It also follows the
+//! `/dev/serial/by-id` link to the device (Linux), which keeps its name when
+//! the device comes back under another `ttyACM` number.
+
+use crate::interrupt;
+use crate::protocol::{Checksum, Frame, Framing};
+use crate::serial::{SerialSettings, V4Serial};
+use crate::transport::Transport;
+use crate::usb::UsbSelector;
+use crate::{Result, V4Error};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Links named after the USB device rather than the order it appeared in
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+/// Pause between checks for the port
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Serial port transport that can be reopened
+pub struct Reconnecting {
+    /// `None` between closing the dead port and reopening it
+    inner: Option<V4Serial>,
+    port: String,
+    /// by-id link to the port, if there is one
+    stable: Option<PathBuf>,
+    /// How the port was picked, if by USB serial number
+    usb: Option<UsbSelector>,
+    settings: SerialSettings,
+}
+
+impl Reconnecting {
+    /// Open a serial port, remembering its stable link for later
+    pub fn open(port: &str, settings: &SerialSettings) -> Result<Self> {
+        let inner = V4Serial::open(port, settings)?;
+        let stable = stable_link(Path::new(BY_ID_DIR), Path::new(port));
+        if let Some(link) = &stable {
+            log::debug!("{} is also {}", port, link.display());
+        }
+        Ok(Self {
+            inner: Some(inner),
+            port: port.to_string(),
+            stable,
+            usb: None,
+            settings: *settings,
+        })
+    }
+
+    /// Look the port up by USB serial number again when reconnecting
+    pub fn with_usb(mut self, usb: Option<UsbSelector>) -> Self {
+        self.usb = usb;
+        self
+    }
+
+    fn serial(&mut self) -> Result<&mut V4Serial> {
+        self.inner.as_mut().ok_or_else(|| {
+            V4Error::Io(io::Error::new(
+                ErrorKind::NotConnected,
+                "port closed while reconnecting",
+            ))
+        })
+    }
+
+    /// Paths the device may come back under, the most stable first
+    fn candidates(&self) -> Vec<PathBuf> {
+        let located = self.usb.as_ref().and_then(|usb| usb.locate().ok());
+        let mut paths: Vec<PathBuf> = located.map(PathBuf::from).into_iter().collect();
+        paths.extend(self.stable.iter().cloned());
+        paths.push(PathBuf::from(&self.port));
+        paths
+    }
+}
+
+impl Transport for Reconnecting {
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.serial()?.send_frame(frame)
+    }
+
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        self.serial()?.recv_response(timeout)
+    }
+
+    fn read_raw(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.serial()?.read_raw(buf, timeout)
+    }
+
+    fn discard_input(&mut self) -> Result<()> {
+        self.serial()?.discard_input()
+    }
+
+    fn switches_framing(&self) -> bool {
+        true
+    }
+
+    /// Applies to the open port; a reopened one starts with plain CRC-8 frames
+    fn set_framing(&mut self, framing: Framing) -> Result<()> {
+        self.serial()?.set_framing(framing)
+    }
+
+    fn checksum(&self) -> Checksum {
+        self.inner
+            .as_ref()
+            .map_or(Checksum::Crc8, |serial| serial.checksum())
+    }
+
+    /// Close the port and open it again once it is back
+    ///
+    /// The old handle is closed first: while it stays open, Linux gives the
+    /// returning device another name. A path that exists but can't be
+    /// opened yet (udev still setting permissions) is tried again.
+    fn reconnect(&mut self, wait: Duration) -> Result<String> {
+        self.inner = None;
+        let start = Instant::now();
+        loop {
+            for path in self.candidates().iter().filter(|path| path.exists()) {
+                let path = path.to_string_lossy();
+                match V4Serial::open(&path, &self.settings) {
+                    Ok(serial) => {
+                        log::debug!("Reopened {}", path);
+                        self.inner = Some(serial);
+                        return Ok(path.into_owned());
+                    }
+                    Err(e) => log::debug!("{} not ready yet: {}", path, e),
+                }
+            }
+            if interrupt::interrupted() {
+                return Err(V4Error::Device(format!(
+                    "Stopped waiting for {}",
+                    self.port
+                )));
+            }
+            if start.elapsed() >= wait {
+                return Err(V4Error::Device(format!(
+                    "{} did not come back within {}s",
+                    self.port,
+                    wait.as_secs()
+                )));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Link in `dir` that resolves to the same device as `port`
+fn stable_link(dir: &Path, port: &Path) -> Option<PathBuf> {
+    if port.starts_with(dir) {
+        return Some(port.to_path_buf());
+    }
+    let target = std::fs::canonicalize(port).ok()?;
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|link| std::fs::canonicalize(link).is_ok_and(|resolved| resolved == target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stable_link_finds_symlink_to_port() {
+        let dir = tempfile::tempdir().unwrap();
+        let by_id = dir.path().join("by-id");
+        std::fs::create_dir(&by_id).unwrap();
+        let port = dir.path().join("ttyACM0");
+        std::fs::write(&port, b"").unwrap();
+        std::fs::write(dir.path().join("ttyACM1"), b"").unwrap();
+        let link = by_id.join("usb-V4_Board_1234-if00");
+        std::os::unix::fs::symlink(&port, &link).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("ttyACM1"), by_id.join("other")).unwrap();
+
+        assert_eq!(stable_link(&by_id, &port), Some(link.clone()));
+        assert_eq!(stable_link(&by_id, &link), Some(link));
+        assert_eq!(stable_link(&by_id, &dir.path().join("gone")), None);
+    }
+
+    #[test]
+    fn test_reconnect_gives_up_when_port_stays_away() {
+        let mut transport = Reconnecting {
+            inner: None,
+            port: "/nonexistent/ttyACM0".to_string(),
+            stable: None,
+            usb: None,
+            settings: SerialSettings::default(),
+        };
+        let err =
+            transport.send_frame(&Frame::new(crate::protocol::Command::Ping, vec![]).unwrap());
+        assert!(err.unwrap_err().is_disconnect());
+
+        let err = transport.reconnect(Duration::ZERO).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Device error: /nonexistent/ttyACM0 did not come back within 0s"
+        );
+    }
+}
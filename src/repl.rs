@@ -1,193 +1,167 @@
-//! Rust FFI bindings for V4-front compiler
+//! Source helpers for the REPL and the other commands that compile
 //!
-//! This module provides safe Rust wrappers around V4-front C API for
-//! compiling Forth source code to V4 bytecode.
+//! Decides when input needs more lines, splits source into definitions and
+//! finds words defined twice. The compiler itself lives in [`crate::ffi`].
 
-use std::ffi::{CStr, CString, c_char, c_int};
-use std::ptr;
-use std::slice;
-
-// ============================================================================
-// V4-front Compiler API
-// ============================================================================
-
-#[repr(C)]
-struct V4FrontContext {
-    _private: [u8; 0],
-}
-
-#[repr(C)]
-struct V4FrontWord {
-    name: *mut c_char,
-    code: *mut u8,
-    code_len: u32,
-}
-
-#[repr(C)]
-struct V4FrontBuf {
-    words: *mut V4FrontWord,
-    word_count: c_int,
-    data: *mut u8,
-    size: usize,
-}
-
-unsafe extern "C" {
-    fn v4front_context_create() -> *mut V4FrontContext;
-    fn v4front_context_destroy(ctx: *mut V4FrontContext);
-    fn v4front_context_reset(ctx: *mut V4FrontContext);
-    fn v4front_context_register_word(
-        ctx: *mut V4FrontContext,
-        name: *const c_char,
-        vm_word_idx: c_int,
-    ) -> c_int;
-    fn v4front_compile_with_context(
-        ctx: *mut V4FrontContext,
-        source: *const c_char,
-        out_buf: *mut V4FrontBuf,
-        err: *mut c_char,
-        err_cap: usize,
-    ) -> c_int;
-    fn v4front_free(buf: *mut V4FrontBuf);
-}
-
-// ============================================================================
-// Safe Rust Wrapper
-// ============================================================================
-
-/// Compiled word definition
-#[derive(Debug, Clone)]
-pub struct WordDef {
+/// Word name defined more than once in a single compilation unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowedWord {
     pub name: String,
-    pub bytecode: Vec<u8>,
+    /// Number of definitions with this name
+    pub count: usize,
+    /// Where the winning, last definition is (`file:line`), when the
+    /// source is at hand
+    pub winner: Option<String>,
 }
 
-/// Compilation result
-#[derive(Debug)]
-pub struct CompileResult {
-    pub words: Vec<WordDef>,
-    pub bytecode: Vec<u8>,
-}
-
-/// Stateful Forth compiler for REPL
-pub struct Compiler {
-    ctx: *mut V4FrontContext,
-    next_word_id: i32,
+/// Find duplicated word names in definition order
+///
+/// Later definitions shadow earlier ones, so the last occurrence wins.
+/// Results are ordered by the first occurrence of each name.
+pub fn find_shadowed_words<'a, I>(names: I) -> Vec<ShadowedWord>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut seen: Vec<ShadowedWord> = Vec::new();
+    for name in names {
+        match seen.iter_mut().find(|w| w.name == name) {
+            Some(word) => word.count += 1,
+            None => seen.push(ShadowedWord {
+                name: name.to_string(),
+                count: 1,
+                winner: None,
+            }),
+        }
+    }
+    seen.retain(|w| w.count > 1);
+    seen
 }
 
-impl Compiler {
-    /// Create a new compiler context
-    pub fn new() -> Result<Self, String> {
-        unsafe {
-            let ctx = v4front_context_create();
-            if ctx.is_null() {
-                return Err("Failed to create compiler context".to_string());
+/// Whether REPL input stops inside a definition, control structure,
+/// comment or string, so the next line continues it
+///
+/// Counts `:`/`;`, `IF`/`THEN`, `BEGIN`/`UNTIL`/`AGAIN`/`REPEAT` and
+/// `DO`/`LOOP` pairs, skipping `\` and `( )` comments and `."`/`S"` strings.
+pub fn needs_continuation(source: &str) -> bool {
+    let mut definitions = 0usize;
+    let mut structures = 0usize;
+    let mut words = Words::new(source);
+
+    for (_, token) in words.by_ref() {
+        match token.to_ascii_uppercase().as_str() {
+            ":" | ":NONAME" => definitions += 1,
+            ";" => definitions = definitions.saturating_sub(1),
+            "IF" | "BEGIN" | "DO" | "?DO" => structures += 1,
+            "THEN" | "UNTIL" | "AGAIN" | "REPEAT" | "LOOP" | "+LOOP" => {
+                structures = structures.saturating_sub(1)
             }
-
-            Ok(Compiler {
-                ctx,
-                next_word_id: 0,
-            })
+            _ => {}
         }
     }
+    words.unterminated || definitions > 0 || structures > 0
+}
 
-    /// Compile Forth source code
-    ///
-    /// Returns compiled bytecode and any word definitions.
-    /// Word definitions are automatically registered with the compiler context.
-    pub fn compile(&mut self, source: &str) -> Result<CompileResult, String> {
-        unsafe {
-            let c_source = CString::new(source).map_err(|e| e.to_string())?;
-            let mut out_buf = V4FrontBuf {
-                words: ptr::null_mut(),
-                word_count: 0,
-                data: ptr::null_mut(),
-                size: 0,
-            };
-            let mut err_buf = [0u8; 256];
-
-            let result = v4front_compile_with_context(
-                self.ctx,
-                c_source.as_ptr(),
-                &mut out_buf,
-                err_buf.as_mut_ptr() as *mut c_char,
-                err_buf.len(),
-            );
-
-            if result != 0 {
-                let err_msg = CStr::from_ptr(err_buf.as_ptr() as *const c_char)
-                    .to_string_lossy()
-                    .into_owned();
-                v4front_free(&mut out_buf);
-                return Err(if err_msg.is_empty() {
-                    format!("Compilation failed (error code: {})", result)
-                } else {
-                    err_msg
-                });
-            }
-
-            // Extract word definitions
-            let mut words = Vec::new();
-            if !out_buf.words.is_null() && out_buf.word_count > 0 {
-                let words_slice = slice::from_raw_parts(out_buf.words, out_buf.word_count as usize);
-
-                for word in words_slice {
-                    let name = CStr::from_ptr(word.name).to_string_lossy().into_owned();
-                    let bytecode = slice::from_raw_parts(word.code, word.code_len as usize);
-
-                    // Note: We don't register word index here.
-                    // The device will register the word and return its index,
-                    // which we'll then register via register_word_index()
+/// Source text of a `: NAME ... ;` definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordSource {
+    pub name: String,
+    /// From `:` to `;`, each line trimmed
+    pub text: String,
+}
 
-                    words.push(WordDef {
-                        name,
-                        bytecode: bytecode.to_vec(),
+/// Named definitions in REPL input, in order
+///
+/// `:NONAME` and a definition left open at the end are not included.
+pub fn definition_sources(source: &str) -> Vec<WordSource> {
+    let mut found = Vec::new();
+    // Offset of the open `:` and the name once seen
+    let mut open: Option<(usize, Option<&str>)> = None;
+
+    for (pos, token) in Words::new(source) {
+        match (token, &mut open) {
+            (":", None) => open = Some((pos, None)),
+            (";", Some((start, name))) => {
+                if let Some(name) = name {
+                    let text = source[*start..pos + 1].lines().map(str::trim);
+                    found.push(WordSource {
+                        name: name.to_string(),
+                        text: text.collect::<Vec<_>>().join("\n"),
                     });
                 }
+                open = None;
             }
-
-            // Extract main bytecode
-            let bytecode = if !out_buf.data.is_null() && out_buf.size > 0 {
-                slice::from_raw_parts(out_buf.data, out_buf.size).to_vec()
-            } else {
-                Vec::new()
-            };
-
-            v4front_free(&mut out_buf);
-
-            Ok(CompileResult { words, bytecode })
+            (_, Some((_, name @ None))) => *name = Some(token),
+            _ => {}
         }
     }
+    found
+}
 
-    /// Reset compiler context (clear all registered words)
-    pub fn reset(&mut self) {
-        unsafe {
-            v4front_context_reset(self.ctx);
-            self.next_word_id = 0;
+/// Words of Forth source with their byte offsets
+///
+/// `\` and `( )` comments are skipped, as is the text of `."`, `S"` and
+/// `ABORT"`; the string word itself is yielded.
+struct Words<'a> {
+    source: &'a str,
+    pos: usize,
+    /// Set when the source ends inside a comment or string
+    unterminated: bool,
+}
+
+impl<'a> Words<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            pos: 0,
+            unterminated: false,
         }
     }
 
-    /// Register a word index from device
-    ///
-    /// Called after device executes bytecode and returns word index
-    pub fn register_word_index(&mut self, name: &str, vm_word_idx: i32) -> Result<(), String> {
-        unsafe {
-            let c_name = CString::new(name).map_err(|e| e.to_string())?;
-            let result = v4front_context_register_word(self.ctx, c_name.as_ptr(), vm_word_idx);
-            if result < 0 {
-                return Err(format!(
-                    "Failed to register word '{}' with index {}",
-                    name, vm_word_idx
-                ));
+    /// Move past `len` bytes, or to the end as unterminated if `None`
+    fn skip(&mut self, len: Option<usize>) {
+        match len {
+            Some(len) => self.pos += len,
+            None => {
+                self.pos = self.source.len();
+                self.unterminated = true;
             }
-            Ok(())
         }
     }
 }
 
-impl Drop for Compiler {
-    fn drop(&mut self) {
-        unsafe {
-            v4front_context_destroy(self.ctx);
+impl<'a> Iterator for Words<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = &self.source[self.pos..];
+            let trimmed = rest.trim_start();
+            let start = self.pos + rest.len() - trimmed.len();
+            let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+            if end == 0 {
+                self.pos = self.source.len();
+                return None;
+            }
+            let token = &trimmed[..end];
+            self.pos = start + end;
+            let after = &self.source[self.pos..];
+
+            if token == "\\" {
+                self.pos += after.find('\n').unwrap_or(after.len());
+            } else if token == "(" {
+                self.skip(after.find(')').map(|close| close + 1));
+            } else {
+                if token.len() > 1 && token.ends_with('"') && !token[..end - 1].contains('"') {
+                    // `."`, `S"`, `ABORT"`: text up to the closing quote
+                    self.skip(
+                        after
+                            .get(1..)
+                            .and_then(|text| text.find('"'))
+                            .map(|close| close + 2),
+                    );
+                }
+                return Some((start, token));
+            }
         }
     }
 }
@@ -197,66 +171,68 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_compiler_creation() {
-        let compiler = Compiler::new();
-        assert!(compiler.is_ok());
+    fn test_needs_continuation() {
+        assert!(!needs_continuation("1 2 +"));
+        assert!(!needs_continuation(": SQ DUP * ;"));
+        assert!(needs_continuation(": SQ"));
+        assert!(needs_continuation(": SQ\n  DUP *"));
+        assert!(!needs_continuation(": SQ\n  DUP *\n;"));
+        assert!(needs_continuation(": ABS? DUP 0< IF NEGATE ;"));
+        assert!(needs_continuation("BEGIN 1 -"));
+        assert!(!needs_continuation("10 0 do i . loop"));
+        assert!(!needs_continuation(": X 1 IF 2 ELSE 3 THEN ;"));
     }
 
     #[test]
-    fn test_basic_compilation() {
-        let mut compiler = Compiler::new().unwrap();
-        let result = compiler.compile("1 2 +");
-        assert!(result.is_ok());
-        let compiled = result.unwrap();
-        assert!(!compiled.bytecode.is_empty());
-        assert!(compiled.words.is_empty());
+    fn test_needs_continuation_skips_comments_and_strings() {
+        assert!(!needs_continuation(": X ( a ; b -- ) 1 ;"));
+        assert!(needs_continuation(": X ( n --"));
+        assert!(!needs_continuation("1 \\ : comment only"));
+        assert!(needs_continuation(": X \\ ;\n 1"));
+        assert!(!needs_continuation(": HI .\" ; IF\" ;"));
+        assert!(needs_continuation(".\" unterminated"));
+        assert!(!needs_continuation(";"));
     }
 
     #[test]
-    fn test_word_definition() {
-        let mut compiler = Compiler::new().unwrap();
-        let result = compiler.compile(": DOUBLE 2 * ;");
-        assert!(result.is_ok());
-        let compiled = result.unwrap();
-        assert_eq!(compiled.words.len(), 1);
-        assert_eq!(compiled.words[0].name, "DOUBLE");
+    fn test_definition_sources() {
+        let input = "1 : SQ DUP * ; 2\n: CLAMP ( n -- n' )\n    DUP 0< IF DROP 0 THEN ;\n:NONAME 1 ; : OPEN";
+        let found = definition_sources(input);
+        assert_eq!(
+            found,
+            [
+                WordSource {
+                    name: "SQ".to_string(),
+                    text: ": SQ DUP * ;".to_string(),
+                },
+                WordSource {
+                    name: "CLAMP".to_string(),
+                    text: ": CLAMP ( n -- n' )\nDUP 0< IF DROP 0 THEN ;".to_string(),
+                },
+            ]
+        );
+        assert!(
+            definition_sources(": HI .\" ; \" ;")[0]
+                .text
+                .ends_with("\" ;")
+        );
     }
 
     #[test]
-    fn test_persistent_words() {
-        let mut compiler = Compiler::new().unwrap();
-
-        // Define word
-        let result1 = compiler.compile(": SQUARE DUP * ;");
-        assert!(result1.is_ok());
-        let compiled1 = result1.unwrap();
-        assert_eq!(compiled1.words.len(), 1);
-        assert_eq!(compiled1.words[0].name, "SQUARE");
-
-        // Simulate device registering the word at index 0
-        compiler.register_word_index("SQUARE", 0).unwrap();
-
-        // Now we can use the word
-        let result2 = compiler.compile("5 SQUARE");
-        assert!(result2.is_ok());
+    fn test_find_shadowed_words() {
+        let shadowed = find_shadowed_words(["LED_ON", "LED_OFF", "LED_ON", "BLINK", "LED_ON"]);
+        assert_eq!(
+            shadowed,
+            vec![ShadowedWord {
+                name: "LED_ON".to_string(),
+                count: 3,
+                winner: None,
+            }]
+        );
     }
 
     #[test]
-    fn test_error_handling() {
-        let mut compiler = Compiler::new().unwrap();
-        let result = compiler.compile("UNKNOWN_WORD");
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn test_reset() {
-        let mut compiler = Compiler::new().unwrap();
-
-        compiler.compile(": TEST 42 ;").unwrap();
-        compiler.reset();
-
-        // After reset, TEST should be unknown
-        let result = compiler.compile("TEST");
-        assert!(result.is_err());
+    fn test_find_shadowed_words_none() {
+        assert!(find_shadowed_words(["A", "B", "C"]).is_empty());
     }
 }
@@ -0,0 +1,534 @@
+//! Serial ports shared over the network (RFC 2217)
+//!
+//! Terminal servers and `ser2net` export a serial port as a Telnet
+//! connection with the COM-PORT-OPTION extension, which lets the client set
+//! the baud rate and line settings and drive DTR, RTS and break remotely.
+//! [`Rfc2217Stream`] handles the Telnet layer; data bytes reach
+//! [`StreamTransport`] unchanged.
+
+use crate::serial::SerialSettings;
+use crate::stream::{ByteStream, StreamTransport};
+use crate::tcp;
+use crate::{Result, V4Error};
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// `--port` prefix selecting the RFC 2217 transport
+pub const RFC2217_SCHEME: &str = "rfc2217://";
+
+/// Default port: RFC 2217 runs on top of Telnet
+pub const DEFAULT_RFC2217_PORT: u16 = 23;
+
+/// How long to wait for the server to accept COM-PORT-OPTION
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Telnet commands
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+// Telnet options
+const BINARY: u8 = 0;
+const SGA: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// COM-PORT-OPTION client commands; the server answers with code + 100
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+// SET-CONTROL values
+const FLOW_NONE: u8 = 1;
+const FLOW_XON_XOFF: u8 = 2;
+const FLOW_HARDWARE: u8 = 3;
+const BREAK_ON: u8 = 5;
+const BREAK_OFF: u8 = 6;
+const DTR_ON: u8 = 8;
+const DTR_OFF: u8 = 9;
+const RTS_ON: u8 = 11;
+const RTS_OFF: u8 = 12;
+
+/// PURGE-DATA value: the server's receive buffer
+const PURGE_RECEIVE: u8 = 1;
+
+/// Where the Telnet decoder is within the incoming bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    Iac,
+    /// After IAC WILL/WONT/DO/DONT, waiting for the option
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Telnet decoder: separates data from commands and queues the answers
+#[derive(Debug)]
+struct Telnet {
+    state: State,
+    /// Current subnegotiation, without IAC SB and IAC SE
+    sub: Vec<u8>,
+    /// Negotiation answers not sent yet
+    replies: Vec<u8>,
+    /// Whether the server agreed to COM-PORT-OPTION, once it answered
+    com_port: Option<bool>,
+}
+
+impl Telnet {
+    fn new() -> Self {
+        Self {
+            state: State::Data,
+            sub: Vec::new(),
+            replies: Vec::new(),
+            com_port: None,
+        }
+    }
+
+    /// Decode received bytes, appending data bytes to `data`
+    fn decode(&mut self, input: &[u8], data: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(byte);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Option(byte),
+                (State::Iac, SB) => {
+                    self.sub.clear();
+                    State::Subnegotiation
+                }
+                // NOP, GA and the other two-byte commands carry nothing for us
+                (State::Iac, _) => State::Data,
+                (State::Option(verb), option) => {
+                    self.negotiate(verb, option);
+                    State::Data
+                }
+                (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                (State::Subnegotiation, _) => {
+                    self.sub.push(byte);
+                    State::Subnegotiation
+                }
+                (State::SubnegotiationIac, SE) => {
+                    self.subnegotiation();
+                    State::Data
+                }
+                (State::SubnegotiationIac, _) => {
+                    self.sub.push(byte);
+                    State::Subnegotiation
+                }
+            };
+        }
+    }
+
+    /// Answer a WILL/WONT/DO/DONT from the server
+    fn negotiate(&mut self, verb: u8, option: u8) {
+        match (verb, option) {
+            (DO, COM_PORT_OPTION) => self.com_port = Some(true),
+            (DONT, COM_PORT_OPTION) => self.com_port = Some(false),
+            // Confirmations of what we asked for
+            (WILL | DO, BINARY | SGA) => {}
+            (WONT | DONT, _) => {}
+            (WILL, _) => self.replies.extend_from_slice(&[IAC, DONT, option]),
+            (DO, _) => self.replies.extend_from_slice(&[IAC, WONT, option]),
+            _ => {}
+        }
+    }
+
+    /// Handle a complete subnegotiation from the server
+    fn subnegotiation(&mut self) {
+        if let [COM_PORT_OPTION, command, value @ ..] = self.sub.as_slice() {
+            log::debug!(
+                "RFC 2217 server: command {} = {:02X?}",
+                command.wrapping_sub(SERVER_OFFSET),
+                value
+            );
+        }
+    }
+}
+
+/// Telnet connection to an RFC 2217 server, read and written as plain data
+///
+/// Data bytes equal to IAC are escaped on write and unescaped on read;
+/// Telnet negotiation is answered while reading.
+pub struct Rfc2217Stream {
+    socket: TcpStream,
+    telnet: Telnet,
+    /// Decoded data not returned by `read` yet
+    data: Vec<u8>,
+}
+
+impl Rfc2217Stream {
+    /// Connect to `host:port` and negotiate binary mode and COM-PORT-OPTION
+    pub fn connect(addr: &str) -> Result<Self> {
+        Self::negotiate(tcp::connect_stream(addr)?)
+    }
+
+    /// Negotiate over an established connection
+    ///
+    /// Fails if the server refuses COM-PORT-OPTION. A server that doesn't
+    /// answer within two seconds is assumed to accept it.
+    pub fn negotiate(socket: TcpStream) -> Result<Self> {
+        let mut stream = Self {
+            socket,
+            telnet: Telnet::new(),
+            data: Vec::new(),
+        };
+        stream.socket.write_all(&[
+            IAC,
+            WILL,
+            BINARY,
+            IAC,
+            DO,
+            BINARY,
+            IAC,
+            WILL,
+            SGA,
+            IAC,
+            DO,
+            SGA,
+            IAC,
+            WILL,
+            COM_PORT_OPTION,
+        ])?;
+
+        let start = Instant::now();
+        let mut buf = [0u8; 256];
+        while stream.telnet.com_port.is_none() {
+            let remaining = NEGOTIATION_TIMEOUT.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                log::warn!("RFC 2217 server did not answer COM-PORT-OPTION; continuing");
+                break;
+            }
+            stream.socket.set_read_timeout(Some(remaining))?;
+            match stream.fill(&mut buf) {
+                Ok(0) => {
+                    return Err(V4Error::Rfc2217(
+                        "server closed the connection during negotiation".to_string(),
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if stream.telnet.com_port == Some(false) {
+            return Err(V4Error::Rfc2217(
+                "server refused COM-PORT-OPTION; is the port in raw TCP mode? Use tcp:// instead"
+                    .to_string(),
+            ));
+        }
+        Ok(stream)
+    }
+
+    /// Apply baud rate, data bits, parity, stop bits and flow control, and
+    /// the DTR and RTS levels if set
+    pub fn apply_settings(&mut self, settings: &SerialSettings) -> io::Result<()> {
+        self.set_baud_rate(settings.baud_rate)?;
+        let data_bits = match settings.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        self.command(SET_DATASIZE, &[data_bits])?;
+        let parity = match settings.parity {
+            Parity::None => 1,
+            Parity::Odd => 2,
+            Parity::Even => 3,
+        };
+        self.command(SET_PARITY, &[parity])?;
+        let stop_bits = match settings.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        self.command(SET_STOPSIZE, &[stop_bits])?;
+        let flow = match settings.flow_control {
+            FlowControl::None => FLOW_NONE,
+            FlowControl::Software => FLOW_XON_XOFF,
+            FlowControl::Hardware => FLOW_HARDWARE,
+        };
+        self.command(SET_CONTROL, &[flow])?;
+        if let Some(dtr) = settings.dtr {
+            self.set_dtr(dtr)?;
+        }
+        if let Some(rts) = settings.rts {
+            self.set_rts(rts)?;
+        }
+        Ok(())
+    }
+
+    /// Change the remote port's baud rate
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        self.command(SET_BAUDRATE, &baud_rate.to_be_bytes())
+    }
+
+    /// Drive the remote DTR line
+    pub fn set_dtr(&mut self, on: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if on { DTR_ON } else { DTR_OFF }])
+    }
+
+    /// Drive the remote RTS line
+    pub fn set_rts(&mut self, on: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if on { RTS_ON } else { RTS_OFF }])
+    }
+
+    /// Start or stop a break condition on the remote port
+    pub fn set_break(&mut self, on: bool) -> io::Result<()> {
+        self.command(SET_CONTROL, &[if on { BREAK_ON } else { BREAK_OFF }])
+    }
+
+    /// Hold a break condition for `duration`
+    pub fn send_break(&mut self, duration: Duration) -> io::Result<()> {
+        self.set_break(true)?;
+        sleep(duration);
+        self.set_break(false)
+    }
+
+    /// The TCP connection
+    pub fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    /// Send a COM-PORT-OPTION subnegotiation
+    fn command(&mut self, command: u8, value: &[u8]) -> io::Result<()> {
+        let mut frame = vec![IAC, SB, COM_PORT_OPTION, command];
+        escape_into(value, &mut frame);
+        frame.extend_from_slice(&[IAC, SE]);
+        self.socket.write_all(&frame)
+    }
+
+    /// Read once from the socket into the decoded data
+    ///
+    /// Returns the raw byte count, so 0 still means the connection closed.
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.socket.read(buf)?;
+        self.telnet.decode(&buf[..n], &mut self.data);
+        if !self.telnet.replies.is_empty() {
+            let replies = std::mem::take(&mut self.telnet.replies);
+            self.socket.write_all(&replies)?;
+        }
+        Ok(n)
+    }
+}
+
+/// Append `data` with IAC bytes doubled
+fn escape_into(data: &[u8], out: &mut Vec<u8>) {
+    for &byte in data {
+        out.push(byte);
+        if byte == IAC {
+            out.push(IAC);
+        }
+    }
+}
+
+impl Read for Rfc2217Stream {
+    /// Read data bytes; a chunk holding only Telnet commands reads again
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0u8; 1024];
+        while self.data.is_empty() {
+            if self.fill(&mut raw)? == 0 {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for Rfc2217Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut escaped = Vec::with_capacity(buf.len());
+        escape_into(buf, &mut escaped);
+        self.socket.write_all(&escaped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+impl ByteStream for Rfc2217Stream {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        ByteStream::set_read_timeout(&mut self.socket, timeout)
+    }
+
+    /// Ask the server to drop what it buffered, then drain the socket
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.command(PURGE_DATA, &[PURGE_RECEIVE])?;
+        self.data.clear();
+        self.socket.set_nonblocking(true)?;
+        let mut raw = [0u8; 1024];
+        let result = loop {
+            match self.fill(&mut raw) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.socket.set_nonblocking(false)?;
+        self.data.clear();
+        result
+    }
+}
+
+/// V4-link over a serial port shared with RFC 2217
+pub type V4Rfc2217 = StreamTransport<Rfc2217Stream>;
+
+impl StreamTransport<Rfc2217Stream> {
+    /// Connect to `host:port` and configure the remote port with `settings`
+    pub fn connect(addr: &str, settings: &SerialSettings) -> Result<Self> {
+        let mut stream = Rfc2217Stream::connect(addr)?;
+        stream.apply_settings(settings)?;
+        Ok(Self::new(stream))
+    }
+}
+
+/// Parse `rfc2217://host[:port]` into a `host:port` address
+///
+/// Returns `None` when `port` doesn't use the RFC 2217 scheme.
+pub fn parse_address(port: &str) -> Option<String> {
+    let rest = port.strip_prefix(RFC2217_SCHEME)?;
+    Some(tcp::host_port(rest, DEFAULT_RFC2217_PORT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Command, ErrorCode, Frame, calc_crc8};
+    use crate::transport::Transport;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("rfc2217://ts.local:4001").as_deref(),
+            Some("ts.local:4001")
+        );
+        assert_eq!(
+            parse_address("rfc2217://10.0.0.5").as_deref(),
+            Some("10.0.0.5:23")
+        );
+        assert_eq!(parse_address("tcp://10.0.0.5:4001"), None);
+    }
+
+    #[test]
+    fn test_telnet_decoder() {
+        let mut telnet = Telnet::new();
+        let mut data = Vec::new();
+
+        // Escaped data, a refused option and a split subnegotiation
+        telnet.decode(&[0x01, IAC, IAC, 0x02, IAC, DO, 1, IAC, SB, 44], &mut data);
+        telnet.decode(&[101, 0, 1, IAC, IAC, 0, IAC, SE, IAC, DO], &mut data);
+        telnet.decode(&[COM_PORT_OPTION, 0x03], &mut data);
+
+        assert_eq!(data, vec![0x01, 0xFF, 0x02, 0x03]);
+        assert_eq!(telnet.replies, vec![IAC, WONT, 1]);
+        assert_eq!(telnet.com_port, Some(true));
+        assert_eq!(telnet.state, State::Data);
+
+        let mut escaped = Vec::new();
+        escape_into(&[0xA5, 0xFF, 0x00], &mut escaped);
+        assert_eq!(escaped, vec![0xA5, 0xFF, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn test_rfc2217_transport_over_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut ping = Vec::new();
+        escape_into(
+            &Frame::new(Command::Ping, vec![]).unwrap().encode(),
+            &mut ping,
+        );
+
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            socket.write_all(&[IAC, DO, COM_PORT_OPTION]).unwrap();
+
+            let mut received = Vec::new();
+            let mut buf = [0u8; 256];
+            while !received.ends_with(&ping) {
+                let n = socket.read(&mut buf).unwrap();
+                assert!(n > 0, "client closed the connection");
+                received.extend_from_slice(&buf[..n]);
+            }
+
+            // OK response carrying a 0xFF data byte, split by IAC NOP
+            let body = [0x02, 0x00, ErrorCode::Ok as u8, 0xFF];
+            let mut response = vec![0xA5, 0x02, 0x00, IAC, 241, ErrorCode::Ok as u8];
+            escape_into(&[0xFF, calc_crc8(&body)], &mut response);
+            socket.write_all(&response).unwrap();
+            received
+        });
+
+        let settings = SerialSettings {
+            baud_rate: 0x1C2FF,
+            ..SerialSettings::default()
+        };
+        let mut transport = V4Rfc2217::connect(&addr, &settings).unwrap();
+        let response = transport.ping(Duration::from_secs(2)).unwrap();
+        let received = server.join().unwrap();
+
+        assert_eq!(response, ErrorCode::Ok);
+        assert!(received.starts_with(&[IAC, WILL, BINARY]));
+        let contains = |needle: &[u8]| received.windows(needle.len()).any(|w| w == needle);
+        assert!(
+            contains(&[
+                IAC,
+                SB,
+                COM_PORT_OPTION,
+                SET_BAUDRATE,
+                0x00,
+                0x01,
+                0xC2,
+                0xFF,
+                0xFF,
+                IAC,
+                SE
+            ]),
+            "{:02X?}",
+            received
+        );
+        assert!(contains(&[
+            IAC,
+            SB,
+            COM_PORT_OPTION,
+            SET_PARITY,
+            1,
+            IAC,
+            SE
+        ]));
+        assert!(contains(&[
+            IAC,
+            SB,
+            COM_PORT_OPTION,
+            SET_CONTROL,
+            FLOW_NONE,
+            IAC,
+            SE
+        ]));
+    }
+}
@@ -1,172 +1,269 @@
-use crate::protocol::{Command, ErrorCode, Frame, Response};
+//! V4-link over a serial port
+//!
+//! The serial layer is blocking on purpose. [`StreamTransport`] waits on the
+//! port's read timeout rather than polling, and keeps bytes past the end of
+//! a frame, so output notifications and responses arriving together are
+//! both delivered. Ctrl-C cancels a wait for a response ([`crate::interrupt`]),
+//! and sharing a port or driving several devices is done with the daemon
+//! and threads. An async (tokio) serial layer would put every command behind
+//! a runtime and gain none of this; tokio stays limited to the optional BLE
+//! transport, whose library requires it.
+
+use crate::protocol::ErrorCode;
+use crate::stream::{ByteStream, StreamTransport};
+use crate::transport::Transport;
 use crate::{Result, V4Error};
-use serialport::SerialPort;
-use std::time::{Duration, Instant};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::fmt;
+use std::time::Duration;
 
 /// Default baud rate for V4-link protocol
 pub const DEFAULT_BAUD_RATE: u32 = 115200;
 
-/// V4 Serial port wrapper
-pub struct V4Serial {
-    port: Box<dyn SerialPort>,
+/// Serial line settings used when opening a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    /// DTR level set on open (`true` asserts it); `None` leaves it to the OS
+    pub dtr: Option<bool>,
+    /// RTS level set on open (`true` asserts it); `None` leaves it to the OS
+    pub rts: Option<bool>,
 }
 
-impl V4Serial {
-    /// Open a serial port
-    pub fn open(path: &str, baud_rate: u32) -> Result<Self> {
-        let port = serialport::new(path, baud_rate)
-            .timeout(Duration::from_secs(5))
-            .open()?;
+impl Default for SerialSettings {
+    /// 115200 baud, 8N1, no flow control
+    fn default() -> Self {
+        Self {
+            baud_rate: DEFAULT_BAUD_RATE,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            dtr: None,
+            rts: None,
+        }
+    }
+}
 
-        Ok(Self { port })
+impl fmt::Display for SerialSettings {
+    /// Conventional short form, e.g. `115200 8N1`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        write!(
+            f,
+            "{} {}{}{}",
+            self.baud_rate,
+            u8::from(self.data_bits),
+            parity,
+            u8::from(self.stop_bits)
+        )?;
+        match self.flow_control {
+            FlowControl::None => {}
+            FlowControl::Software => write!(f, " XON/XOFF")?,
+            FlowControl::Hardware => write!(f, " RTS/CTS")?,
+        }
+        for (line, level) in [("DTR", self.dtr), ("RTS", self.rts)] {
+            if let Some(level) = level {
+                write!(f, " {} {}", line, line_level_name(level))?;
+            }
+        }
+        Ok(())
     }
+}
 
-    /// Open with default baud rate
-    pub fn open_default(path: &str) -> Result<Self> {
-        Self::open(path, DEFAULT_BAUD_RATE)
+/// Parse a data bits value (5-8)
+pub fn parse_data_bits(value: &str) -> std::result::Result<DataBits, String> {
+    match value {
+        "5" => Ok(DataBits::Five),
+        "6" => Ok(DataBits::Six),
+        "7" => Ok(DataBits::Seven),
+        "8" => Ok(DataBits::Eight),
+        _ => Err(format!(
+            "invalid data bits '{}' (expected 5, 6, 7 or 8)",
+            value
+        )),
     }
+}
 
-    /// Send a frame
-    pub fn send_frame(&mut self, frame: &Frame) -> Result<()> {
-        let encoded = frame.encode();
-        eprintln!("DEBUG: Sending frame ({} bytes): {:02X?}", encoded.len(), encoded);
-        self.port.write_all(&encoded)?;
-        self.port.flush()?;
-        Ok(())
+/// Parse a parity value (none, odd, even)
+pub fn parse_parity(value: &str) -> std::result::Result<Parity, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "none" => Ok(Parity::None),
+        "odd" => Ok(Parity::Odd),
+        "even" => Ok(Parity::Even),
+        _ => Err(format!(
+            "invalid parity '{}' (expected none, odd or even)",
+            value
+        )),
     }
+}
 
-    /// Receive response with timeout
-    pub fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
-        const STX: u8 = 0xA5;
-        let start = Instant::now();
-        let mut buffer = Vec::new();
-
-        // Read bytes until we find STX or timeout
-        while start.elapsed() < timeout {
-            let available = self.port.bytes_to_read()? as usize;
-            if available > 0 {
-                let mut buf = vec![0u8; available];
-                let n = self.port.read(&mut buf)?;
-                buffer.extend_from_slice(&buf[..n]);
-
-                // Search for STX
-                if let Some(pos) = buffer.iter().position(|&b| b == STX) {
-                    // Found STX, need to read header first to get frame length
-                    let mut response = vec![STX];
-                    let mut remaining_start = pos + 1;
-
-                    // Read at least 4 bytes to get LEN field: STX + LEN_L + LEN_H + ERR_CODE
-                    while response.len() < 4 && start.elapsed() < timeout {
-                        if remaining_start < buffer.len() {
-                            let to_copy =
-                                std::cmp::min(4 - response.len(), buffer.len() - remaining_start);
-                            response.extend_from_slice(
-                                &buffer[remaining_start..remaining_start + to_copy],
-                            );
-                            remaining_start += to_copy;
-                        } else {
-                            // Need to read more data
-                            let available = self.port.bytes_to_read()? as usize;
-                            if available > 0 {
-                                let mut buf = vec![0u8; available];
-                                let n = self.port.read(&mut buf)?;
-                                buffer.extend_from_slice(&buf[..n]);
-                            } else {
-                                std::thread::sleep(Duration::from_millis(20));
-                            }
-                        }
-                    }
-
-                    if response.len() >= 4 {
-                        // Parse length field to determine total frame size
-                        let payload_len = u16::from_le_bytes([response[1], response[2]]) as usize;
-                        let total_frame_len = 1 + 2 + payload_len + 1; // STX + LEN(2) + PAYLOAD + CRC
-
-                        // Continue reading until we have the complete frame
-                        while response.len() < total_frame_len && start.elapsed() < timeout {
-                            if remaining_start < buffer.len() {
-                                let to_copy = std::cmp::min(
-                                    total_frame_len - response.len(),
-                                    buffer.len() - remaining_start,
-                                );
-                                response.extend_from_slice(
-                                    &buffer[remaining_start..remaining_start + to_copy],
-                                );
-                                remaining_start += to_copy;
-                            } else {
-                                // Need to read more data
-                                let available = self.port.bytes_to_read()? as usize;
-                                if available > 0 {
-                                    let mut buf = vec![0u8; available];
-                                    let n = self.port.read(&mut buf)?;
-                                    buffer.extend_from_slice(&buf[..n]);
-                                } else {
-                                    std::thread::sleep(Duration::from_millis(20));
-                                }
-                            }
-                        }
-
-                        if response.len() == total_frame_len {
-                            eprintln!("DEBUG: Received complete frame ({} bytes): {:02X?}", response.len(), response);
-                            return Ok(response);
-                        }
-                    }
-                }
-            }
-            std::thread::sleep(Duration::from_millis(20));
-        }
+/// Parse a stop bits value (1, 2)
+pub fn parse_stop_bits(value: &str) -> std::result::Result<StopBits, String> {
+    match value {
+        "1" => Ok(StopBits::One),
+        "2" => Ok(StopBits::Two),
+        _ => Err(format!("invalid stop bits '{}' (expected 1 or 2)", value)),
+    }
+}
 
-        Err(V4Error::Timeout)
+/// Parse a flow control value (none, software, hardware)
+pub fn parse_flow_control(value: &str) -> std::result::Result<FlowControl, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "none" => Ok(FlowControl::None),
+        "software" => Ok(FlowControl::Software),
+        "hardware" => Ok(FlowControl::Hardware),
+        _ => Err(format!(
+            "invalid flow control '{}' (expected none, software or hardware)",
+            value
+        )),
     }
+}
 
-    /// Send command and wait for response
-    pub fn send_command(
-        &mut self,
-        command: Command,
-        payload: &[u8],
-        timeout: Duration,
-    ) -> Result<Response> {
-        let frame = Frame::new(command, payload.to_vec())?;
-        self.send_frame(&frame)?;
+/// Parse a modem control line level (high or low)
+///
+/// `high` asserts the line; on USB-UART boards that drive EN or GPIO0
+/// through it, the pin then goes low.
+pub fn parse_line_level(value: &str) -> std::result::Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "high" => Ok(true),
+        "low" => Ok(false),
+        _ => Err(format!(
+            "invalid line level '{}' (expected high or low)",
+            value
+        )),
+    }
+}
 
-        let response = self.recv_response(timeout)?;
-        Frame::decode_response(&response)
+/// `high` or `low`, as accepted by [`parse_line_level`]
+pub fn line_level_name(level: bool) -> &'static str {
+    if level { "high" } else { "low" }
+}
+
+/// PING timeout per port during auto-detection
+const DISCOVERY_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// V4-link over a serial port
+pub type V4Serial = StreamTransport<Box<dyn SerialPort>>;
+
+impl ByteStream for Box<dyn SerialPort> {
+    fn set_read_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        Ok(self.set_timeout(timeout)?)
     }
 
-    /// Send PING command
-    pub fn ping(&mut self, timeout: Duration) -> Result<ErrorCode> {
-        Ok(self.send_command(Command::Ping, &[], timeout)?.error_code)
+    /// Drop unread bytes from the port's input buffer
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        Ok(self.clear(serialport::ClearBuffer::Input)?)
     }
 
-    /// Send RESET command
-    pub fn reset(&mut self, timeout: Duration) -> Result<ErrorCode> {
-        Ok(self.send_command(Command::Reset, &[], timeout)?.error_code)
+    fn try_clone_stream(&self) -> std::io::Result<Self> {
+        Ok(self.try_clone()?)
     }
+}
 
-    /// Send EXEC command with bytecode
-    pub fn exec(&mut self, bytecode: &[u8], timeout: Duration) -> Result<Response> {
-        self.send_command(Command::Exec, bytecode, timeout)
+impl StreamTransport<Box<dyn SerialPort>> {
+    /// Open a serial port
+    ///
+    /// DTR and RTS are set right after opening when `settings` asks for a
+    /// level; a port without them only gets a warning. Linux still asserts
+    /// both briefly while opening.
+    pub fn open(path: &str, settings: &SerialSettings) -> Result<Self> {
+        let mut builder = serialport::new(path, settings.baud_rate)
+            .data_bits(settings.data_bits)
+            .parity(settings.parity)
+            .stop_bits(settings.stop_bits)
+            .flow_control(settings.flow_control)
+            .timeout(Duration::from_secs(5));
+        if let Some(dtr) = settings.dtr {
+            builder = builder.dtr_on_open(dtr);
+        }
+        let mut port = builder.open().map_err(|source| V4Error::PortOpen {
+            path: path.to_string(),
+            hint: open_error_hint(&source.kind),
+            source,
+        })?;
+        // Virtual ports (PTYs, some USB bridges) have no modem lines
+        if let Some(rts) = settings.rts
+            && let Err(e) = port.write_request_to_send(rts)
+        {
+            log::warn!("Cannot set RTS on {}: {}", path, e);
+        }
+
+        Ok(Self::new(port))
     }
 
-    /// Query stack state (data stack + return stack)
-    pub fn query_stack(&mut self, timeout: Duration) -> Result<Response> {
-        self.send_command(Command::QueryStack, &[], timeout)
+    /// Open with default settings (115200 8N1)
+    pub fn open_default(path: &str) -> Result<Self> {
+        Self::open(path, &SerialSettings::default())
     }
 
-    /// Query memory dump at address
-    pub fn query_memory(&mut self, addr: u32, len: u16, timeout: Duration) -> Result<Response> {
-        let mut payload = Vec::with_capacity(6);
-        // Address (little-endian u32)
-        payload.extend_from_slice(&addr.to_le_bytes());
-        // Length (little-endian u16)
-        payload.extend_from_slice(&len.to_le_bytes());
-        self.send_command(Command::QueryMemory, &payload, timeout)
+    /// Check whether a V4 device answers PING on the given port
+    pub fn probe(path: &str, settings: &SerialSettings, timeout: Duration) -> bool {
+        match Self::open(path, settings) {
+            Ok(mut serial) => matches!(serial.ping(timeout), Ok(ErrorCode::Ok)),
+            Err(_) => false,
+        }
     }
 
-    /// Query word information by index
-    pub fn query_word(&mut self, word_idx: u16, timeout: Duration) -> Result<Response> {
-        let payload = word_idx.to_le_bytes();
-        self.send_command(Command::QueryWord, &payload, timeout)
+    /// Enumerate serial ports and return those that answer PING
+    pub fn discover(settings: &SerialSettings, timeout: Duration) -> Result<Vec<String>> {
+        Ok(serialport::available_ports()?
+            .into_iter()
+            .map(|info| info.port_name)
+            .filter(|path| Self::probe(path, settings, timeout))
+            .collect())
+    }
+
+    /// Use the given port, or auto-detect the single responsive V4 device
+    pub fn resolve_port(port: Option<&str>, settings: &SerialSettings) -> Result<String> {
+        if let Some(port) = port {
+            return Ok(port.to_string());
+        }
+
+        let port = select_detected(Self::discover(settings, DISCOVERY_TIMEOUT)?)?;
+        log::info!("Auto-detected V4 device on {}", port);
+        Ok(port)
+    }
+}
+
+/// Pick the auto-detected port, requiring exactly one responsive device
+fn select_detected(mut found: Vec<String>) -> Result<String> {
+    match found.len() {
+        0 => Err(V4Error::Cli(
+            "No V4 device found; connect a device or pass --port".to_string(),
+        )),
+        1 => Ok(found.remove(0)),
+        _ => Err(V4Error::Cli(format!(
+            "Multiple V4 devices found ({}); pass --port to choose one",
+            found.join(", ")
+        ))),
+    }
+}
+
+/// Troubleshooting hint for a failed port open
+fn open_error_hint(kind: &serialport::ErrorKind) -> Option<&'static str> {
+    use serialport::ErrorKind;
+    use std::io::ErrorKind as IoKind;
+
+    match kind {
+        ErrorKind::Io(IoKind::PermissionDenied) if cfg!(target_os = "linux") => Some(
+            "add your user to the 'dialout' group (sudo usermod -aG dialout $USER) and log in again",
+        ),
+        ErrorKind::Io(IoKind::PermissionDenied) => {
+            Some("check that no other program is using the port")
+        }
+        ErrorKind::NoDevice | ErrorKind::Io(IoKind::NotFound) => {
+            Some("run 'v4 ports' to list available serial devices")
+        }
+        _ => None,
     }
 }
 
@@ -178,4 +275,78 @@ mod tests {
     fn test_default_baud_rate() {
         assert_eq!(DEFAULT_BAUD_RATE, 115200);
     }
+
+    #[test]
+    fn test_settings_display() {
+        assert_eq!(SerialSettings::default().to_string(), "115200 8N1");
+
+        let settings = SerialSettings {
+            baud_rate: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::Hardware,
+            dtr: Some(false),
+            rts: None,
+        };
+        assert_eq!(settings.to_string(), "9600 7E2 RTS/CTS DTR low");
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!(parse_data_bits("7"), Ok(DataBits::Seven));
+        assert!(parse_data_bits("9").is_err());
+        assert_eq!(parse_parity("Even"), Ok(Parity::Even));
+        assert!(parse_parity("mark").is_err());
+        assert_eq!(parse_stop_bits("2"), Ok(StopBits::Two));
+        assert_eq!(parse_line_level("High"), Ok(true));
+        assert_eq!(parse_line_level("low"), Ok(false));
+        assert!(parse_line_level("1").is_err());
+        assert!(parse_stop_bits("1.5").is_err());
+        assert_eq!(parse_flow_control("hardware"), Ok(FlowControl::Hardware));
+        assert!(parse_flow_control("rts").is_err());
+    }
+
+    #[test]
+    fn test_select_detected() {
+        let one = select_detected(vec!["/dev/ttyACM0".to_string()]).unwrap();
+        assert_eq!(one, "/dev/ttyACM0");
+
+        assert!(matches!(select_detected(vec![]), Err(V4Error::Cli(_))));
+
+        let err = select_detected(vec!["/dev/ttyACM0".to_string(), "/dev/ttyACM1".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("/dev/ttyACM0, /dev/ttyACM1"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_explicit_port() {
+        let port =
+            V4Serial::resolve_port(Some("/dev/ttyUSB3"), &SerialSettings::default()).unwrap();
+        assert_eq!(port, "/dev/ttyUSB3");
+    }
+
+    #[test]
+    fn test_open_error_hint() {
+        use serialport::ErrorKind;
+        use std::io::ErrorKind as IoKind;
+
+        let hint = open_error_hint(&ErrorKind::NoDevice).unwrap();
+        assert!(hint.contains("v4 ports"));
+        let hint = open_error_hint(&ErrorKind::Io(IoKind::NotFound)).unwrap();
+        assert!(hint.contains("v4 ports"));
+        assert!(open_error_hint(&ErrorKind::Io(IoKind::PermissionDenied)).is_some());
+        assert!(open_error_hint(&ErrorKind::InvalidInput).is_none());
+    }
+
+    #[test]
+    fn test_open_missing_port_mentions_path() {
+        let err = match V4Serial::open("/dev/v4-does-not-exist", &SerialSettings::default()) {
+            Ok(_) => panic!("opening a missing port should fail"),
+            Err(e) => e,
+        };
+        let msg = err.to_string();
+        assert!(msg.contains("/dev/v4-does-not-exist"), "{}", msg);
+    }
 }
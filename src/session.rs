@@ -0,0 +1,237 @@
+//! REPL dictionary snapshots for `.save` / `.restore`
+//!
+//! A session file lists the words defined in a REPL session, in definition
+//! order, with their bytecode and the index the device assigned. Loading it
+//! sends the definitions again, so the dictionary survives a power cycle.
+//! The source of each definition is kept too when the REPL recorded it.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "words": [
+//!     { "name": "SQUARE", "index": 0, "bytecode": "02000351", "source": ": SQUARE DUP * ;" }
+//!   ]
+//! }
+//! ```
+
+use crate::ffi::Compiler;
+use crate::protocol::ErrorCode;
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs;
+use std::time::Duration;
+
+/// Session file format written by this version
+pub const SESSION_VERSION: u32 = 1;
+
+/// Word definition sent during a REPL session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedWord {
+    pub name: String,
+    /// Index the device assigned when the word was defined
+    pub index: u16,
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub bytecode: Vec<u8>,
+    /// Definition as typed, for `.source`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// Contents of a session file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionFile {
+    pub version: u32,
+    /// Definitions in the order they were sent
+    pub words: Vec<SavedWord>,
+}
+
+/// Word that got a different index than when it was saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovedWord {
+    pub name: String,
+    pub saved: u16,
+    pub assigned: u16,
+}
+
+/// Result of restoring a session file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    /// Definitions sent, with the indices the device assigned this time
+    pub words: Vec<SavedWord>,
+    /// Words whose index changed; bytecode calling them by their old index
+    /// now reaches a different word
+    pub moved: Vec<MovedWord>,
+}
+
+impl SessionFile {
+    pub fn new(words: Vec<SavedWord>) -> Self {
+        Self {
+            version: SESSION_VERSION,
+            words,
+        }
+    }
+
+    /// Write the session as JSON
+    pub fn save(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| V4Error::Session(format!("{}: {}", path, e)))?;
+        fs::write(path, json + "\n")?;
+        Ok(())
+    }
+
+    /// Read a session file, rejecting newer format versions
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let session: Self = serde_json::from_str(&text)
+            .map_err(|e| V4Error::Session(format!("{}: {}", path, e)))?;
+        if session.version > SESSION_VERSION {
+            return Err(V4Error::Session(format!(
+                "{}: format version {} is newer than supported ({})",
+                path, session.version, SESSION_VERSION
+            )));
+        }
+        Ok(session)
+    }
+
+    /// Send every definition in order and register the assigned indices
+    ///
+    /// Stops at the first definition the device rejects.
+    pub fn restore(
+        &self,
+        transport: &mut dyn Transport,
+        compiler: &mut Compiler,
+        timeout: Duration,
+    ) -> Result<RestoreReport> {
+        let mut report = RestoreReport::default();
+        for word in &self.words {
+            let response = transport.define_word(&word.name, &word.bytecode, timeout)?;
+            if response.error_code != ErrorCode::Ok {
+                return Err(V4Error::Device(format!(
+                    "Failed to register word '{}': {}",
+                    word.name,
+                    response.error_code.name()
+                )));
+            }
+            compiler
+                .register_word_indices(&[word.name.as_str()], &response.word_indices)
+                .map_err(V4Error::Protocol)?;
+
+            let assigned = response.word_indices[0];
+            if assigned != word.index {
+                report.moved.push(MovedWord {
+                    name: word.name.clone(),
+                    saved: word.index,
+                    assigned,
+                });
+            }
+            report.words.push(SavedWord {
+                index: assigned,
+                ..word.clone()
+            });
+        }
+        Ok(report)
+    }
+}
+
+/// Serialize bytes as an uppercase hex string
+pub(crate) fn to_hex<S: Serializer>(
+    bytes: &[u8],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    serializer.serialize_str(&hex)
+}
+
+/// Deserialize bytes written by [`to_hex`]
+pub(crate) fn from_hex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<u8>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    if hex.len() % 2 != 0 {
+        return Err(serde::de::Error::custom("odd number of hex digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid hex bytes: {}", hex)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use crate::transport::mock::MockTransport;
+
+    fn words() -> Vec<SavedWord> {
+        vec![
+            SavedWord {
+                name: "ON".to_string(),
+                index: 0,
+                bytecode: vec![0x00, 0x01, 0x51],
+                source: Some(": ON 1 ;".to_string()),
+            },
+            SavedWord {
+                name: "TWICE".to_string(),
+                index: 1,
+                bytecode: vec![0xAB],
+                source: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let session = SessionFile::new(words());
+        session.save(path).unwrap();
+        assert!(
+            fs::read_to_string(path)
+                .unwrap()
+                .contains("\"bytecode\": \"000151\"")
+        );
+        assert_eq!(SessionFile::load(path).unwrap(), session);
+
+        fs::write(path, r#"{"version": 2, "words": []}"#).unwrap();
+        assert!(matches!(SessionFile::load(path), Err(V4Error::Session(_))));
+        fs::write(
+            path,
+            r#"{"version": 1, "words": [{"name": "X", "index": 0, "bytecode": "0G"}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(SessionFile::load(path), Err(V4Error::Session(_))));
+    }
+
+    #[test]
+    fn test_restore_reports_moved_words() {
+        let mut transport = MockTransport::new();
+        let mut compiler = Compiler::new().unwrap();
+        transport.push_word_indices(&[0]);
+        transport.push_word_indices(&[5]);
+
+        let report = SessionFile::new(words())
+            .restore(&mut transport, &mut compiler, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(report.words.len(), 2);
+        assert_eq!(report.words[1].index, 5);
+        assert_eq!(
+            report.moved,
+            vec![MovedWord {
+                name: "TWICE".to_string(),
+                saved: 1,
+                assigned: 5
+            }]
+        );
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::Exec, Command::Exec]
+        );
+        assert_eq!(transport.sent[1].payload, vec![0xAB]);
+    }
+}
@@ -0,0 +1,517 @@
+//! Forth source preprocessing applied before compilation
+
+use crate::highlight::is_string_word;
+use crate::syscalls;
+use crate::{Result, V4Error};
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Blank out a leading `#!` shebang line
+///
+/// Only the very first line is treated as a shebang. It is replaced with an
+/// empty line rather than removed, so line numbers in compiler diagnostics
+/// still match the file on disk.
+///
+/// # Examples
+///
+/// ```
+/// use v4_cli::source::strip_shebang;
+///
+/// let src = "#!/usr/bin/env -S v4 exec --port /dev/ttyACM0\n1 2 +\n";
+/// assert_eq!(strip_shebang(src), "\n1 2 +\n");
+/// ```
+pub fn strip_shebang(source: &str) -> Cow<'_, str> {
+    if !source.starts_with("#!") {
+        return Cow::Borrowed(source);
+    }
+
+    match source.find('\n') {
+        Some(end) => Cow::Owned(source[end..].to_string()),
+        None => Cow::Owned(String::new()),
+    }
+}
+
+/// Source text with its `INCLUDE`s expanded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Loaded {
+    pub text: String,
+    /// Every file read, the top-level file first
+    pub files: Vec<PathBuf>,
+    /// Where each line of `text` came from
+    pub lines: Vec<LineOrigin>,
+}
+
+/// File and line an expanded source line was read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineOrigin {
+    pub file: PathBuf,
+    /// 1-based line number in `file`
+    pub line: usize,
+}
+
+/// Name used for source read from stdin
+pub const STDIN_NAME: &str = "<stdin>";
+
+impl Loaded {
+    /// Origin of a 1-based line of `text`
+    pub fn origin(&self, line: usize) -> Option<&LineOrigin> {
+        line.checked_sub(1).and_then(|i| self.lines.get(i))
+    }
+
+    /// Append another loaded source after this one
+    pub fn append(&mut self, other: Loaded) {
+        self.text.push_str(&other.text);
+        self.files.extend(other.files);
+        self.lines.extend(other.lines);
+    }
+}
+
+/// Read a source file, blank its shebang and expand `INCLUDE "file"` lines
+///
+/// An include directive must be alone on its line (a trailing `\` comment
+/// is allowed). Paths are relative to the including file. Included files
+/// are expanded in place each time they appear; an include cycle is an
+/// error.
+pub fn load(path: &Path) -> Result<Loaded> {
+    load_text(&fs::read_to_string(path)?, path)
+}
+
+/// Expand `INCLUDE` lines in the text of `path`, e.g. an unsaved editor buffer
+///
+/// Behaves like [`load`] with `source` in place of the file contents.
+pub fn load_text(source: &str, path: &Path) -> Result<Loaded> {
+    let mut loaded = Loaded::default();
+    expand(source, path, &mut vec![canonical(path)], &mut loaded)?;
+    Ok(loaded)
+}
+
+/// Expand `INCLUDE` lines in source that doesn't come from a file (stdin)
+///
+/// Includes are resolved relative to `base_dir`.
+pub fn expand_includes(source: &str, base_dir: &Path) -> Result<Loaded> {
+    let mut loaded = Loaded::default();
+    let stdin = base_dir.join(STDIN_NAME);
+    expand(source, &stdin, &mut Vec::new(), &mut loaded)?;
+    loaded.files.clear();
+    for origin in &mut loaded.lines {
+        if origin.file == stdin {
+            origin.file = PathBuf::from(STDIN_NAME);
+        }
+    }
+    Ok(loaded)
+}
+
+fn expand(source: &str, path: &Path, stack: &mut Vec<PathBuf>, out: &mut Loaded) -> Result<()> {
+    out.files.push(path.to_path_buf());
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    for (line_no, line) in strip_shebang(source).lines().enumerate() {
+        let Some(target) = include_target(line) else {
+            out.text.push_str(line);
+            out.text.push('\n');
+            out.lines.push(LineOrigin {
+                file: path.to_path_buf(),
+                line: line_no + 1,
+            });
+            continue;
+        };
+
+        let include_path = base_dir.join(target);
+        let key = canonical(&include_path);
+        if let Some(start) = stack.iter().position(|p| *p == key) {
+            let mut chain: Vec<String> = stack[start..]
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            chain.push(key.display().to_string());
+            return Err(V4Error::Compilation(format!(
+                "Include cycle: {}",
+                chain.join(" -> ")
+            )));
+        }
+
+        let text = fs::read_to_string(&include_path).map_err(|e| {
+            V4Error::Compilation(format!(
+                "{}:{}: cannot include '{}': {}",
+                path.display(),
+                line_no + 1,
+                target,
+                e
+            ))
+        })?;
+
+        stack.push(key);
+        expand(&text, &include_path, stack, out)?;
+        stack.pop();
+    }
+
+    Ok(())
+}
+
+/// Path used to recognize the same file reached through different paths
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// File named by an `INCLUDE "file"` line, if the line is one
+fn include_target(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let (word, rest) = line.split_at(line.find(char::is_whitespace)?);
+    if !word.eq_ignore_ascii_case("INCLUDE") {
+        return None;
+    }
+
+    let rest = rest.trim_start().strip_prefix('"')?;
+    let end = rest.find('"')?;
+    let trailing = rest[end + 1..].trim();
+    if !trailing.is_empty() && !trailing.starts_with('\\') {
+        return None;
+    }
+    Some(&rest[..end])
+}
+
+/// Origin file name of the lines [`defines_prelude`] generates
+pub const DEFINES_NAME: &str = "<define>";
+
+/// Constant injected ahead of the source (`--define NAME=value`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Define {
+    pub name: String,
+    /// Cell value; unsigned values up to `0xFFFFFFFF` are stored wrapped
+    pub value: i32,
+}
+
+impl Define {
+    /// Check the name and parse a decimal, `0x` hex or `0b` binary value
+    pub fn new(name: &str, value: &str) -> std::result::Result<Self, String> {
+        if name.is_empty() || name.chars().any(char::is_whitespace) {
+            return Err(format!("Invalid constant name '{}'", name));
+        }
+        if parse_cell(name).is_some() {
+            return Err(format!("Constant name '{}' is a number", name));
+        }
+        let value = parse_cell(value.trim()).ok_or_else(|| {
+            format!(
+                "Invalid value for {}: '{}' is not a 32-bit number",
+                name, value
+            )
+        })?;
+        Ok(Self {
+            name: name.to_string(),
+            value,
+        })
+    }
+}
+
+/// Parse `NAME=value` from the command line
+pub fn parse_define(arg: &str) -> std::result::Result<Define, String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=value, got '{}'", arg))?;
+    Define::new(name.trim(), value)
+}
+
+/// Parse a number that fits a 32-bit cell, signed or unsigned
+fn parse_cell(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let lower = digits.to_ascii_lowercase();
+    let magnitude = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse::<i64>().ok()?
+    } else {
+        return None;
+    };
+    let value = if negative { -magnitude } else { magnitude };
+    if value < i64::from(i32::MIN) || value > i64::from(u32::MAX) {
+        return None;
+    }
+    Some(value as u32 as i32)
+}
+
+/// Forth definitions of `defines`, one `: NAME value ;` line each
+///
+/// Compiled ahead of the program so its words can use the constants. A
+/// name listed twice keeps its last value.
+pub fn defines_prelude(defines: &[Define]) -> Loaded {
+    let mut loaded = Loaded::default();
+    let kept = defines
+        .iter()
+        .enumerate()
+        .filter(|&(i, d)| !defines[i + 1..].iter().any(|later| later.name == d.name))
+        .map(|(_, d)| d);
+    for (line, define) in kept.enumerate() {
+        loaded
+            .text
+            .push_str(&format!(": {} {} ;\n", define.name, define.value));
+        loaded.lines.push(LineOrigin {
+            file: PathBuf::from(DEFINES_NAME),
+            line: line + 1,
+        });
+    }
+    loaded
+}
+
+/// Replace the `SYS_*` constants of [`syscalls`] with their numbers
+///
+/// The catalog's names act as compile-time constants that are always
+/// defined. Unlike `-D` constants they are substituted in place rather than
+/// compiled as words, so they also work as the operand of `SYS`
+/// (`7 1 SYS SYS_GPIO_SET`) and take no dictionary slots. Names the source
+/// defines with `:`, or that `is_defined` knows, keep their meaning; comments
+/// and strings are left alone.
+pub fn resolve_syscalls(source: &str, is_defined: impl Fn(&str) -> bool) -> Cow<'_, str> {
+    let words: Vec<&str> = source.split_whitespace().collect();
+    let defined = |name: &str| {
+        is_defined(name)
+            || words
+                .windows(2)
+                .any(|pair| pair[0] == ":" && pair[1].eq_ignore_ascii_case(name))
+    };
+
+    let mut out = String::with_capacity(source.len());
+    let mut changed = false;
+    for line in source.split_inclusive('\n') {
+        let mut rest = line;
+        while !rest.is_empty() {
+            let start = rest
+                .find(|c: char| !c.is_whitespace())
+                .unwrap_or(rest.len());
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.is_empty() {
+                break;
+            }
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let token = &rest[..end];
+
+            // Comments and strings are copied up to where they end
+            let skip = if token == "\\" {
+                rest.len()
+            } else if token == "(" {
+                rest.find(')').map_or(rest.len(), |i| i + 1)
+            } else if is_string_word(token) {
+                rest[end..]
+                    .get(1..)
+                    .and_then(|text| text.find('"'))
+                    .map_or(rest.len(), |i| end + i + 2)
+            } else {
+                end
+            };
+            match syscalls::by_constant(token).filter(|_| skip == end && !defined(token)) {
+                Some(syscall) => {
+                    out.push_str(&syscall.number.to_string());
+                    changed = true;
+                }
+                None => out.push_str(&rest[..skip]),
+            }
+            rest = &rest[skip..];
+        }
+    }
+    match changed {
+        true => Cow::Owned(out),
+        false => Cow::Borrowed(source),
+    }
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` with values from `lookup`
+///
+/// A variable without a default that `lookup` doesn't know is an error.
+pub fn interpolate_env(
+    text: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> std::result::Result<String, String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Unterminated '${{' in '{}'", text))?;
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        match lookup(name)
+            .filter(|v| !v.is_empty())
+            .or(default.map(String::from))
+        {
+            Some(value) => out.push_str(&value),
+            None => return Err(format!("Environment variable {} is not set", name)),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_define() {
+        assert_eq!(
+            parse_define("GPIO_LED=1").unwrap(),
+            Define {
+                name: "GPIO_LED".into(),
+                value: 1
+            }
+        );
+        assert_eq!(parse_define("MASK=0xFFFFFFFF").unwrap().value, -1);
+        assert_eq!(parse_define("FLAGS=0b101").unwrap().value, 5);
+        assert_eq!(parse_define("OFFSET=-40").unwrap().value, -40);
+        assert!(parse_define("GPIO_LED").is_err());
+        assert!(parse_define("BIG=0x100000000").is_err());
+        assert!(parse_define("LED=on").is_err());
+        assert!(parse_define("42=1").is_err());
+        assert!(parse_define("MY LED=1").is_err());
+    }
+
+    #[test]
+    fn test_defines_prelude_keeps_last_value() {
+        let defines = [
+            parse_define("LED=1").unwrap(),
+            parse_define("BAUD=115200").unwrap(),
+            parse_define("LED=2").unwrap(),
+        ];
+        let prelude = defines_prelude(&defines);
+        assert_eq!(prelude.text, ": BAUD 115200 ;\n: LED 2 ;\n");
+        assert_eq!(prelude.origin(2).unwrap().file, Path::new(DEFINES_NAME));
+        assert!(prelude.files.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_syscalls() {
+        let resolve = |src| resolve_syscalls(src, |_| false).into_owned();
+        assert_eq!(
+            resolve(": LED-ON 7 1 SYS SYS_GPIO_SET DROP ;\n500 SYS sys_delay_ms\n"),
+            ": LED-ON 7 1 SYS 1 DROP ;\n500 SYS 34\n"
+        );
+        // Comments, strings and unknown names stay
+        let kept = "SYS_UART ( SYS_GPIO_SET ) .\" SYS_GPIO_SET\" \\ SYS_GPIO_SET\n";
+        assert!(matches!(
+            resolve_syscalls(kept, |_| false),
+            Cow::Borrowed(_)
+        ));
+        // As do names the program defines
+        let own = ": SYS_MILLIS 0 ;\nSYS_MILLIS SYS_MICROS";
+        assert_eq!(resolve(own), ": SYS_MILLIS 0 ;\nSYS_MILLIS 33");
+        assert_eq!(
+            resolve_syscalls("SYS_MILLIS", |name| name == "SYS_MILLIS"),
+            "SYS_MILLIS"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let lookup = |name: &str| (name == "PIN").then(|| "4".to_string());
+        assert_eq!(interpolate_env("${PIN}", lookup).unwrap(), "4");
+        assert_eq!(interpolate_env("0x${PIN}0", lookup).unwrap(), "0x40");
+        assert_eq!(interpolate_env("${BAUD:-9600}", lookup).unwrap(), "9600");
+        assert_eq!(interpolate_env("plain", lookup).unwrap(), "plain");
+        assert!(
+            interpolate_env("${BAUD}", lookup)
+                .unwrap_err()
+                .contains("BAUD")
+        );
+        assert!(interpolate_env("${PIN", lookup).is_err());
+    }
+
+    #[test]
+    fn test_strip_shebang() {
+        let src = "#!/usr/bin/env -S v4 exec --port /dev/ttyACM0\n: SQ DUP * ;\n";
+        assert_eq!(strip_shebang(src), "\n: SQ DUP * ;\n");
+    }
+
+    #[test]
+    fn test_strip_shebang_only_line() {
+        assert_eq!(strip_shebang("#!/usr/bin/env v4"), "");
+    }
+
+    #[test]
+    fn test_no_shebang_is_borrowed() {
+        let src = "1 2 +\n";
+        assert!(matches!(strip_shebang(src), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_shebang_not_on_first_line() {
+        let src = "1 2 +\n#!/usr/bin/env v4\n";
+        assert_eq!(strip_shebang(src), src);
+    }
+
+    fn write(dir: &Path, name: &str, text: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_include_target() {
+        assert_eq!(include_target("INCLUDE \"lib.v4\""), Some("lib.v4"));
+        assert_eq!(
+            include_target("  include \"a b.v4\" \\ helpers"),
+            Some("a b.v4")
+        );
+        assert_eq!(include_target("INCLUDE lib.v4"), None);
+        assert_eq!(include_target("INCLUDE \"lib.v4\" 1 2 +"), None);
+        assert_eq!(include_target("INCLUDED \"lib.v4\""), None);
+    }
+
+    #[test]
+    fn test_load_expands_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        write(dir.path(), "lib/math.v4", ": SQ DUP * ;\n");
+        write(
+            dir.path(),
+            "lib/all.v4",
+            "#!/usr/bin/env v4\nINCLUDE \"math.v4\"\n",
+        );
+        let main = write(dir.path(), "main.v4", "INCLUDE \"lib/all.v4\"\n3 SQ\n");
+
+        let loaded = load(&main).unwrap();
+        assert_eq!(loaded.text, "\n: SQ DUP * ;\n3 SQ\n");
+        assert_eq!(loaded.files.len(), 3);
+        assert_eq!(loaded.files[0], main);
+
+        // Line 2 comes from math.v4 through two includes
+        let origin = loaded.origin(2).unwrap();
+        assert!(origin.file.ends_with("lib/math.v4"));
+        assert_eq!(origin.line, 1);
+        assert_eq!(loaded.origin(3).unwrap().line, 2);
+        assert_eq!(loaded.origin(4), None);
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.v4", "INCLUDE \"b.v4\"\n");
+        write(dir.path(), "b.v4", "INCLUDE \"a.v4\"\n");
+
+        let err = load(&dir.path().join("a.v4")).unwrap_err().to_string();
+        assert!(err.contains("Include cycle"), "{}", err);
+        assert!(err.contains("a.v4 -> "), "{}", err);
+    }
+
+    #[test]
+    fn test_missing_include_names_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(dir.path(), "main.v4", "1\nINCLUDE \"nope.v4\"\n");
+
+        let err = load(&main).unwrap_err().to_string();
+        assert!(
+            err.contains("main.v4:2: cannot include 'nope.v4'"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Map bytecode offsets back to the Forth lines they came from
+//!
+//! Devices that report where a VM error happened give a word index and an
+//! offset into its bytecode. A [`SourceMap`] turns that into a file and line.
+//! `v4 compile --source-map` writes one next to the .v4b file; `exec` and the
+//! REPL build theirs as they compile. Lines are attributed like in
+//! [`listing`](crate::listing): code of a definition spanning several lines
+//! belongs to the line that ends it.
+
+use crate::debugger::Location;
+use crate::device;
+use crate::ffi::Compiler;
+use crate::listing;
+use crate::source::Loaded;
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Extension of source map files, written next to the bytecode
+pub const EXTENSION: &str = "v4map";
+
+/// Source map file format version
+pub const VERSION: u32 = 1;
+
+/// Where the code of each source line starts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    pub version: u32,
+    /// In compile order
+    pub entries: Vec<MapEntry>,
+}
+
+/// Code from one line, up to the next entry of the same word
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapEntry {
+    /// Word the code belongs to, `None` for the main code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub word: Option<String>,
+    /// Offset of the line's first byte within the word or main code
+    pub offset: usize,
+    pub file: String,
+    /// 1-based line in `file`
+    pub line: usize,
+}
+
+/// Source line a bytecode offset came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub word: Option<String>,
+}
+
+impl fmt::Display for SourceLocation {
+    /// `main.v4:17 in word FOO`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        if let Some(word) = &self.word {
+            write!(f, " in word {}", word)?;
+        }
+        Ok(())
+    }
+}
+
+impl SourceMap {
+    /// Compile `source` and record the line behind each part of the code
+    ///
+    /// `compiler` is only used to compile; nothing gets registered with it.
+    pub fn build(compiler: &mut Compiler, source: &Loaded) -> Result<Self> {
+        let (compiled, lines) = listing::line_code(compiler, &source.text)?;
+        let mut map = Self {
+            version: VERSION,
+            entries: Vec::new(),
+        };
+        for code in lines {
+            let Some(origin) = source.origin(code.line) else {
+                continue;
+            };
+            let file = origin.file.display().to_string();
+            let entry = |word: Option<&str>, offset| MapEntry {
+                word: word.map(str::to_string),
+                offset,
+                file: file.clone(),
+                line: origin.line,
+            };
+            for word in &compiled.words[code.words] {
+                map.entries.push(entry(Some(&word.name), 0));
+            }
+            if !code.main.is_empty() {
+                map.entries.push(entry(None, code.main.start));
+            }
+        }
+        Ok(map)
+    }
+
+    /// Line that compiled the byte at `ip` of `word` (`None` for main code)
+    ///
+    /// The last definition of a word counts, as on the device.
+    pub fn locate(&self, word: Option<&str>, ip: usize) -> Option<SourceLocation> {
+        let same_word = |entry: &&MapEntry| match (&entry.word, word) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            (None, None) => true,
+            _ => false,
+        };
+        let entries: Vec<&MapEntry> = self.entries.iter().filter(same_word).collect();
+        // A word's entries restart at offset 0 where it is defined again
+        let latest = entries.iter().rposition(|entry| entry.offset == 0)?;
+        let entry = entries[latest..]
+            .iter()
+            .take_while(|entry| entry.offset <= ip)
+            .last()?;
+        Some(SourceLocation {
+            file: entry.file.clone(),
+            line: entry.line,
+            word: entry.word.clone(),
+        })
+    }
+
+    /// Add the entries of code compiled later
+    ///
+    /// Its main code replaces the earlier main code; words defined again keep
+    /// their old entries behind the new ones, see [`locate`](Self::locate).
+    pub fn extend(&mut self, later: SourceMap) {
+        if later.entries.iter().any(|entry| entry.word.is_none()) {
+            self.entries.retain(|entry| entry.word.is_some());
+        }
+        self.entries.extend(later.entries);
+    }
+
+    /// Source map file belonging to a bytecode file: `app.v4b` -> `app.v4map`
+    pub fn path_for(bytecode: &Path) -> PathBuf {
+        bytecode.with_extension(EXTENSION)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(self).map_err(|e| V4Error::Cli(e.to_string()))?;
+        fs::write(path, json + "\n")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let map: Self = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| V4Error::Cli(format!("Invalid source map {}: {}", path.display(), e)))?;
+        if map.version > VERSION {
+            return Err(V4Error::Cli(format!(
+                "Source map {} has version {}; this v4 reads up to {}",
+                path.display(),
+                map.version,
+                VERSION
+            )));
+        }
+        Ok(map)
+    }
+}
+
+/// Say where a [`V4Error::VmFault`] happened in the source, if `map` knows
+///
+/// The word's name is asked from the device. Other errors pass through.
+pub fn locate_fault(
+    error: V4Error,
+    transport: &mut dyn Transport,
+    map: &SourceMap,
+    timeout: Duration,
+) -> V4Error {
+    let V4Error::VmFault { context, fault, .. } = error else {
+        return error;
+    };
+    let location = Location::of_fault(&fault);
+    let name = word_name(transport, location, timeout);
+    let place = match map.locate(name.as_deref(), location.ip as usize) {
+        Some(at) => format!("at {}", at),
+        None => location.describe(name.as_deref()),
+    };
+    V4Error::VmFault {
+        context,
+        fault,
+        place,
+    }
+}
+
+/// Name of the word a location is in, `None` for the main code
+fn word_name(
+    transport: &mut dyn Transport,
+    location: Location,
+    timeout: Duration,
+) -> Option<String> {
+    if location.is_top_level() {
+        return None;
+    }
+    match device::query_word(transport, location.word, timeout) {
+        Ok(word) => word.and_then(|word| word.name),
+        Err(e) => {
+            log::debug!("Couldn't name word #{}: {}", location.word, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(word: Option<&str>, offset: usize, line: usize) -> MapEntry {
+        MapEntry {
+            word: word.map(str::to_string),
+            offset,
+            file: "app.v4".to_string(),
+            line,
+        }
+    }
+
+    #[test]
+    fn test_locate_uses_latest_definition_and_main_code() {
+        let mut map = SourceMap {
+            version: VERSION,
+            entries: vec![
+                entry(Some("SQ"), 0, 1),
+                entry(None, 0, 2),
+                entry(None, 4, 3),
+            ],
+        };
+        let line = |map: &SourceMap, word, ip| map.locate(word, ip).map(|at| at.line);
+        assert_eq!(line(&map, Some("sq"), 2), Some(1));
+        assert_eq!(line(&map, None, 3), Some(2));
+        assert_eq!(line(&map, None, 9), Some(3));
+        assert_eq!(line(&map, Some("CUBE"), 0), None);
+
+        map.extend(SourceMap {
+            version: VERSION,
+            entries: vec![entry(Some("SQ"), 0, 7), entry(None, 0, 8)],
+        });
+        assert_eq!(line(&map, Some("SQ"), 2), Some(7));
+        assert_eq!(line(&map, None, 9), Some(8));
+        assert_eq!(
+            map.locate(Some("SQ"), 0).unwrap().to_string(),
+            "app.v4:7 in word SQ"
+        );
+    }
+}
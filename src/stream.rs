@@ -0,0 +1,336 @@
+//! V4-link over any byte stream
+//!
+//! [`Transport`] moves whole frames. Connections that are plain byte streams
+//! (serial ports, TCP sockets, PTYs, RFC 2217 clients, in-memory pipes) only
+//! need to implement [`ByteStream`]; [`StreamTransport`] splits the bytes
+//! into frames. [`V4Serial`](crate::serial::V4Serial) and
+//! [`V4Tcp`](crate::tcp::V4Tcp) are stream transports over a serial port and
+//! a TCP socket.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use v4_cli::V4Device;
+//! use v4_cli::stream::StreamTransport;
+//!
+//! let socket = std::net::TcpStream::connect("192.168.1.20:5400")?;
+//! let transport = StreamTransport::new(socket);
+//! let mut device = V4Device::from_transport(Box::new(transport), "gateway");
+//! device.ping(Duration::from_secs(1))?;
+//! # Ok::<(), v4_cli::V4Error>(())
+//! ```
+
+use crate::protocol::{Checksum, Frame, FrameDecoder, Framing};
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// Shortest read timeout; some streams treat zero as "block forever"
+const MIN_READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Byte stream a V4-link connection runs over
+///
+/// Reads must give up with [`ErrorKind::WouldBlock`] or
+/// [`ErrorKind::TimedOut`] once the read timeout passes without data;
+/// `Ok(0)` means the connection was closed.
+pub trait ByteStream: Read + Write {
+    /// Limit how long the following reads wait for data
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+
+    /// Drop bytes received but not read yet
+    ///
+    /// Reads until nothing arrives within [`MIN_READ_TIMEOUT`] by default.
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.set_read_timeout(MIN_READ_TIMEOUT)?;
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(());
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Second handle on the same connection, e.g. to read on another thread
+    ///
+    /// Not every stream can be shared; those fail with
+    /// [`ErrorKind::Unsupported`].
+    fn try_clone_stream(&self) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "This stream can't be cloned",
+        ))
+    }
+}
+
+impl ByteStream for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, Some(timeout.max(MIN_READ_TIMEOUT)))
+    }
+
+    fn try_clone_stream(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+
+    /// Drain what the socket already holds without waiting
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.set_nonblocking(true)?;
+        let mut buf = [0u8; 1024];
+        let result = loop {
+            match self.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.set_nonblocking(false)?;
+        result
+    }
+}
+
+#[cfg(unix)]
+impl ByteStream for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, Some(timeout.max(MIN_READ_TIMEOUT)))
+    }
+
+    fn try_clone_stream(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+}
+
+/// Frame transport over a [`ByteStream`]
+///
+/// Bytes read past the end of a frame are kept for the next call, so a
+/// response that follows an output notification closely isn't lost, and a
+/// frame cut off by the timeout is completed by the next wait.
+pub struct StreamTransport<S> {
+    stream: S,
+    /// Bytes received but not yet returned as a frame
+    pending: FrameDecoder,
+}
+
+impl<S: ByteStream> StreamTransport<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            pending: FrameDecoder::new(),
+        }
+    }
+
+    /// The underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// The underlying stream, e.g. to change line settings
+    ///
+    /// Reading from it directly can split a frame.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Give the stream back, dropping unread bytes
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+fn closed() -> V4Error {
+    V4Error::Io(io::Error::new(
+        ErrorKind::UnexpectedEof,
+        "connection closed by device",
+    ))
+}
+
+impl<S: ByteStream> Transport for StreamTransport<S> {
+    /// Send a frame
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        let encoded = self.pending.framing().encode(frame);
+        log::trace!("TX frame ({} bytes): {:02X?}", encoded.len(), encoded);
+        self.stream.write_all(&encoded)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Read whatever arrives within the timeout, pending bytes first
+    fn read_raw(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        if !self.pending.is_empty() {
+            return Ok(self.pending.read_into(buf));
+        }
+
+        self.stream
+            .set_read_timeout(timeout.max(MIN_READ_TIMEOUT))?;
+        match self.stream.read(buf) {
+            Ok(0) => Err(closed()),
+            Ok(n) => Ok(n),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(0),
+            Err(e) if e.kind() == ErrorKind::Interrupted => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn switches_framing(&self) -> bool {
+        true
+    }
+
+    fn set_framing(&mut self, framing: Framing) -> Result<()> {
+        self.pending.set_framing(framing);
+        Ok(())
+    }
+
+    fn checksum(&self) -> Checksum {
+        self.pending.framing().checksum
+    }
+
+    /// Drop pending bytes and anything the stream already holds
+    fn discard_input(&mut self) -> Result<()> {
+        self.pending.clear();
+        self.stream.clear_input()?;
+        Ok(())
+    }
+
+    /// Receive response with timeout
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let mut buf = [0u8; 1024];
+
+        loop {
+            if let Some(frame) = self.pending.next_frame() {
+                let frame = frame?;
+                log::trace!("RX frame ({} bytes): {:02X?}", frame.len(), frame);
+                return Ok(frame);
+            }
+
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(V4Error::Timeout);
+            }
+            self.stream.set_read_timeout(remaining)?;
+
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(closed()),
+                Ok(n) => self.pending.push(&buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(V4Error::Timeout);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ErrorCode, calc_crc8, device_frame};
+    use std::collections::VecDeque;
+
+    fn ok_response() -> Vec<u8> {
+        device_frame(ErrorCode::Ok as u8, &[])
+    }
+
+    /// In-memory stream delivering one queued chunk per read
+    #[derive(Default)]
+    struct Pipe {
+        incoming: VecDeque<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let chunk = self.incoming.pop_front().ok_or(ErrorKind::WouldBlock)?;
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ByteStream for Pipe {
+        fn set_read_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_transport_over_in_memory_pipe() {
+        let response = ok_response();
+        let mut pipe = Pipe::default();
+        pipe.incoming.push_back(response[..2].to_vec());
+        pipe.incoming.push_back(response[2..].to_vec());
+        let mut transport = StreamTransport::new(pipe);
+
+        assert_eq!(
+            transport.ping(Duration::from_millis(10)).unwrap(),
+            ErrorCode::Ok
+        );
+        let ping = Frame::new(crate::protocol::Command::Ping, vec![]).unwrap();
+        assert_eq!(transport.get_ref().written, ping.encode());
+        assert!(matches!(
+            transport.ping(Duration::from_millis(10)),
+            Err(V4Error::Timeout)
+        ));
+
+        transport.get_mut().incoming.push_back(vec![0xA5, 0x01]);
+        let mut buf = [0u8; 8];
+        assert_eq!(transport.read_raw(&mut buf, Duration::ZERO).unwrap(), 2);
+        transport.discard_input().unwrap();
+        assert!(transport.into_inner().incoming.is_empty());
+    }
+
+    #[test]
+    fn test_negotiated_framing_applies_after_hello() {
+        use crate::protocol::frame::cobs_encode;
+        use crate::protocol::{Checksum, FEATURE_COBS, FEATURE_CRC32, FEATURE_SEQUENCE, Handshake};
+        use crate::transport::{RetryPolicy, Retrying};
+
+        let features = FEATURE_SEQUENCE | FEATURE_CRC32 | FEATURE_COBS;
+        let mut hello = vec![0xA5, 0x03, 0x00, 0x00, 1, features];
+        hello.push(calc_crc8(&hello[1..]));
+        // OK to PING #0, ending in a CRC-32, stuffed and delimited
+        let mut pong = vec![0xA5, 0x02, 0x00, 0x00, 0x00];
+        pong.extend_from_slice(&Checksum::Crc32.compute(&pong[1..]));
+        let mut stuffed = cobs_encode(&pong);
+        stuffed.push(0x00);
+        assert!(!stuffed[..stuffed.len() - 1].contains(&0x00));
+        let mut pipe = Pipe::default();
+        pipe.incoming.extend([hello, stuffed]);
+
+        let mut transport =
+            Retrying::new(Box::new(StreamTransport::new(pipe)), RetryPolicy::default());
+        let handshake = transport.negotiate(Duration::from_millis(10)).unwrap();
+        assert_eq!(
+            handshake.as_ref().map(Handshake::framing),
+            Some(Framing {
+                checksum: Checksum::Crc32,
+                cobs: true,
+            })
+        );
+        assert_eq!(
+            transport.ping(Duration::from_millis(10)).unwrap(),
+            ErrorCode::Ok
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! `SYS` numbers of the device HAL
+//!
+//! Stack effects are those of the reference firmware. A device may offer
+//! calls missing here; they just have no name on the host. Forth source can
+//! write a call's number as its constant name, e.g. `7 1 SYS SYS_GPIO_SET`
+//! (see [`crate::source::resolve_syscalls`]).
+
+/// One system call of the HAL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Syscall {
+    pub number: u8,
+    pub name: &'static str,
+    /// Stack effect, as `( before -- after )`
+    pub effect: &'static str,
+    pub summary: &'static str,
+}
+
+impl Syscall {
+    /// Constant name in Forth source, e.g. `SYS_GPIO_SET`
+    pub fn constant(&self) -> String {
+        format!("{}{}", PREFIX, self.name)
+    }
+}
+
+/// Prefix of the constant names
+pub const PREFIX: &str = "SYS_";
+
+// GPIO
+/// Configure a pin; mode 3 is input and output
+pub const SYS_GPIO_INIT: u8 = 0x00;
+/// Drive a pin low (0) or high (non-zero)
+pub const SYS_GPIO_SET: u8 = 0x01;
+/// Read a pin's level as 0 or 1
+pub const SYS_GPIO_GET: u8 = 0x02;
+
+// Timers
+/// Milliseconds since boot
+pub const SYS_MILLIS: u8 = 0x20;
+/// Microseconds since boot, wrapping
+pub const SYS_MICROS: u8 = 0x21;
+/// Wait a number of milliseconds
+pub const SYS_DELAY_MS: u8 = 0x22;
+/// Wait a number of microseconds
+pub const SYS_DELAY_US: u8 = 0x23;
+
+// I2C
+/// Start the I2C bus at a clock rate in Hz
+pub const SYS_I2C_INIT: u8 = 0x30;
+/// Write bytes from VM memory to a device address
+pub const SYS_I2C_WRITE: u8 = 0x31;
+/// Read bytes from a device address into VM memory
+pub const SYS_I2C_READ: u8 = 0x32;
+
+const fn sys(
+    number: u8,
+    name: &'static str,
+    effect: &'static str,
+    summary: &'static str,
+) -> Syscall {
+    Syscall {
+        number,
+        name,
+        effect,
+        summary,
+    }
+}
+
+/// Known system calls, by number
+#[rustfmt::skip]
+pub const SYSCALLS: &[Syscall] = &[
+    sys(SYS_GPIO_INIT, "GPIO_INIT", "( pin mode -- err )", "Configure a pin"),
+    sys(SYS_GPIO_SET, "GPIO_SET", "( pin value -- err )", "Drive a pin low or high"),
+    sys(SYS_GPIO_GET, "GPIO_GET", "( pin -- value err )", "Read a pin's level"),
+    sys(SYS_MILLIS, "MILLIS", "( -- ms )", "Milliseconds since boot"),
+    sys(SYS_MICROS, "MICROS", "( -- us )", "Microseconds since boot"),
+    sys(SYS_DELAY_MS, "DELAY_MS", "( ms -- )", "Wait a number of milliseconds"),
+    sys(SYS_DELAY_US, "DELAY_US", "( us -- )", "Wait a number of microseconds"),
+    sys(SYS_I2C_INIT, "I2C_INIT", "( hz -- err )", "Start the I2C bus"),
+    sys(SYS_I2C_WRITE, "I2C_WRITE", "( addr buf len -- err )", "Write bytes to an I2C device"),
+    sys(SYS_I2C_READ, "I2C_READ", "( addr buf len -- err )", "Read bytes from an I2C device"),
+];
+
+/// System call with the given number
+pub fn lookup(number: u8) -> Option<&'static Syscall> {
+    SYSCALLS.iter().find(|syscall| syscall.number == number)
+}
+
+/// System call with the given name, ignoring case
+pub fn by_name(name: &str) -> Option<&'static Syscall> {
+    SYSCALLS
+        .iter()
+        .find(|syscall| syscall.name.eq_ignore_ascii_case(name))
+}
+
+/// System call named by a constant such as `SYS_GPIO_SET`, ignoring case
+pub fn by_constant(constant: &str) -> Option<&'static Syscall> {
+    let prefix = constant.get(..PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(PREFIX) {
+        return None;
+    }
+    by_name(&constant[PREFIX.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_is_sorted_and_unique() {
+        for pair in SYSCALLS.windows(2) {
+            assert!(pair[0].number < pair[1].number, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup(0x22).unwrap().name, "DELAY_MS");
+        assert_eq!(by_name("gpio_get").unwrap().number, SYS_GPIO_GET);
+        assert_eq!(by_constant("sys_i2c_write").unwrap().number, SYS_I2C_WRITE);
+        assert_eq!(lookup(SYS_GPIO_SET).unwrap().constant(), "SYS_GPIO_SET");
+        assert!(lookup(0xFF).is_none());
+        assert!(by_constant("GPIO_SET").is_none());
+        assert!(by_constant("SYS_UART_WRITE").is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::stream::StreamTransport;
+use crate::{Result, V4Error};
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// `--port` prefix selecting the TCP transport
+pub const TCP_SCHEME: &str = "tcp://";
+
+/// Default V4-link TCP port of the network gateway
+pub const DEFAULT_TCP_PORT: u16 = 5400;
+
+/// Connect timeout for TCP transports
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// V4-link over TCP (network gateway)
+pub type V4Tcp = StreamTransport<TcpStream>;
+
+impl StreamTransport<TcpStream> {
+    /// Connect to `host:port`
+    pub fn connect(addr: &str) -> Result<Self> {
+        Ok(Self::new(connect_stream(addr)?))
+    }
+}
+
+/// Open a TCP connection to `host:port`, trying each resolved address
+pub(crate) fn connect_stream(addr: &str) -> Result<TcpStream> {
+    let connect_error = |source| V4Error::TcpConnect {
+        addr: addr.to_string(),
+        source,
+    };
+
+    let mut last_error = None;
+    for sock_addr in addr.to_socket_addrs().map_err(connect_error)? {
+        match TcpStream::connect_timeout(&sock_addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(connect_error(last_error.unwrap_or_else(|| {
+        std::io::Error::new(ErrorKind::NotFound, "no addresses resolved")
+    })))
+}
+
+/// Parse `tcp://host[:port]` into a `host:port` address
+///
+/// Returns `None` when `port` doesn't use the TCP scheme.
+pub fn parse_address(port: &str) -> Option<String> {
+    let rest = port.strip_prefix(TCP_SCHEME)?;
+    Some(host_port(rest, DEFAULT_TCP_PORT))
+}
+
+/// `host[:port]` with `default_port` filled in when none is given
+pub(crate) fn host_port(rest: &str, default_port: u16) -> String {
+    let rest = rest.trim_end_matches('/');
+
+    // Bracketed IPv6 literal or host with an explicit port
+    let has_port = match rest.rfind(']') {
+        Some(end) => rest[end..].contains(':'),
+        None => rest.contains(':'),
+    };
+
+    if has_port {
+        rest.to_string()
+    } else {
+        format!("{}:{}", rest, default_port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ErrorCode, device_frame};
+    use crate::transport::Transport;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Start-of-frame marker
+    const STX: u8 = 0xA5;
+
+    fn ok_response() -> Vec<u8> {
+        device_frame(ErrorCode::Ok as u8, &[])
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("tcp://gateway.local:6000").as_deref(),
+            Some("gateway.local:6000")
+        );
+        assert_eq!(
+            parse_address("tcp://192.168.1.20").as_deref(),
+            Some("192.168.1.20:5400")
+        );
+        assert_eq!(parse_address("tcp://[::1]").as_deref(), Some("[::1]:5400"));
+        assert_eq!(parse_address("tcp://[::1]:7/").as_deref(), Some("[::1]:7"));
+        assert_eq!(parse_address("/dev/ttyACM0"), None);
+    }
+
+    #[test]
+    fn test_ping_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 5];
+            stream.read_exact(&mut request).unwrap();
+
+            // Leading noise, then the response split across two writes
+            let response = ok_response();
+            stream.write_all(&[0x00, 0xFF]).unwrap();
+            stream.write_all(&response[..2]).unwrap();
+            stream.flush().unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            stream.write_all(&response[2..]).unwrap();
+            request
+        });
+
+        let mut tcp = V4Tcp::connect(&addr).unwrap();
+        let code = tcp.ping(Duration::from_secs(2)).unwrap();
+        assert_eq!(code, ErrorCode::Ok);
+
+        let request = server.join().unwrap();
+        assert_eq!(request[0], STX);
+    }
+
+    #[test]
+    fn test_timeout_without_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut tcp = V4Tcp::connect(&addr).unwrap();
+        let _held = listener.accept().unwrap();
+        let err = tcp.ping(Duration::from_millis(100)).unwrap_err();
+        assert!(matches!(err, V4Error::Timeout));
+    }
+
+    #[test]
+    fn test_connect_refused_names_address() {
+        // Bind then drop to get a port nobody listens on
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        let err = match V4Tcp::connect(&addr) {
+            Err(e) => e,
+            Ok(_) => panic!("connect unexpectedly succeeded"),
+        };
+        assert!(err.to_string().contains(&addr));
+    }
+}
@@ -0,0 +1,302 @@
+//! Assertions against device state for automated tests
+//!
+//! An assertion reads the device through the typed query responses
+//! ([`StackSnapshot`](crate::protocol::StackSnapshot),
+//! [`MemoryDump`](crate::protocol::MemoryDump)) and compares the result with
+//! expected values. Scripts write them as `expect-stack` and `expect-memory`
+//! lines; `v4 test` collects the outcomes and can write them as JUnit XML.
+
+use crate::device;
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Most bytes one `expect-memory` compares (one QUERY_MEMORY request)
+pub const MEMORY_ASSERT_MAX: usize = 256;
+
+/// Expected device state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Assertion {
+    /// Data stack holds exactly these values, bottom first
+    Stack(Vec<i32>),
+    /// Memory at `addr` starts with these bytes
+    Memory { addr: u32, bytes: Vec<u8> },
+}
+
+impl Assertion {
+    /// Parse `expect-stack [values...]` or `expect-memory <addr> <bytes...>`
+    ///
+    /// Returns `None` for any other line.
+    pub fn parse(line: &str) -> Option<Result<Self>> {
+        let mut parts = line.split_whitespace();
+        let assertion = match parts.next()? {
+            "expect-stack" => parts
+                .map(parse_cell)
+                .collect::<Result<_>>()
+                .map(Self::Stack),
+            "expect-memory" => parse_memory(parts.collect()),
+            _ => return None,
+        };
+        Some(assertion)
+    }
+
+    /// Query the device and compare
+    ///
+    /// Returns the failure message if the state differs; communication
+    /// problems are errors.
+    pub fn check(
+        &self,
+        transport: &mut dyn Transport,
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        match self {
+            Self::Stack(expected) => {
+                let actual = transport.stack_snapshot(timeout)?.data;
+                Ok((actual != *expected).then(|| {
+                    format!(
+                        "expected stack [{}], got [{}]",
+                        join(expected, |c| c.to_string()),
+                        join(&actual, |c| c.to_string())
+                    )
+                }))
+            }
+            Self::Memory { addr, bytes } => {
+                let dump = device::query_memory(transport, *addr, bytes.len() as u16, timeout)?;
+                Ok((dump.bytes != *bytes).then(|| {
+                    format!(
+                        "expected memory at 0x{:08X} [{}], got [{}]",
+                        addr,
+                        join(bytes, |b| format!("{:02X}", b)),
+                        join(&dump.bytes, |b| format!("{:02X}", b))
+                    )
+                }))
+            }
+        }
+    }
+}
+
+/// Outcome of one assertion in a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssertionResult {
+    /// 1-based line in the script
+    pub line: usize,
+    /// Assertion as written
+    pub source: String,
+    /// Why it failed, `None` if it passed
+    pub failure: Option<String>,
+}
+
+impl AssertionResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// JUnit XML for one script run as a test suite
+///
+/// Each assertion is a test case. `error` (a line that failed to run)
+/// becomes an extra errored test case, since later assertions never ran.
+pub fn junit_xml(
+    suite: &str,
+    results: &[AssertionResult],
+    error: Option<&str>,
+    time: Duration,
+) -> String {
+    let failures = results.iter().filter(|r| !r.passed()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+        escape(suite),
+        results.len() + usize::from(error.is_some()),
+        failures,
+        usize::from(error.is_some()),
+        time.as_secs_f64()
+    );
+    for result in results {
+        let name = format!("line {}: {}", result.line, result.source);
+        match &result.failure {
+            None => {
+                let _ = writeln!(
+                    xml,
+                    "  <testcase classname=\"{}\" name=\"{}\"/>",
+                    escape(suite),
+                    escape(&name)
+                );
+            }
+            Some(failure) => {
+                let _ = writeln!(
+                    xml,
+                    "  <testcase classname=\"{}\" name=\"{}\">\n    <failure message=\"{}\"/>\n  </testcase>",
+                    escape(suite),
+                    escape(&name),
+                    escape(failure)
+                );
+            }
+        }
+    }
+    if let Some(error) = error {
+        let _ = writeln!(
+            xml,
+            "  <testcase classname=\"{}\" name=\"script\">\n    <error message=\"{}\"/>\n  </testcase>",
+            escape(suite),
+            escape(error)
+        );
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+fn parse_memory(args: Vec<&str>) -> Result<Assertion> {
+    let usage = || V4Error::Cli("Usage: expect-memory <addr> <byte>...".to_string());
+    let (addr, bytes) = args.split_first().ok_or_else(usage)?;
+    if bytes.is_empty() {
+        return Err(usage());
+    }
+    if bytes.len() > MEMORY_ASSERT_MAX {
+        return Err(V4Error::Cli(format!(
+            "expect-memory compares at most {} bytes",
+            MEMORY_ASSERT_MAX
+        )));
+    }
+    let addr =
+        parse_number(addr).ok_or_else(|| V4Error::Cli(format!("Invalid address: {}", addr)))?;
+    let bytes = bytes
+        .iter()
+        .map(|b| {
+            parse_number(b)
+                .and_then(|v| u8::try_from(v).ok())
+                .ok_or_else(|| V4Error::Cli(format!("Invalid byte: {}", b)))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Assertion::Memory { addr, bytes })
+}
+
+/// Parse a stack cell: decimal (possibly negative) or `0x` hex
+fn parse_cell(text: &str) -> Result<i32> {
+    let parsed = match text.strip_prefix('-') {
+        Some(_) => text.parse().ok(),
+        None => parse_number(text).map(|v| v as i32),
+    };
+    parsed.ok_or_else(|| V4Error::Cli(format!("Invalid stack value: {}", text)))
+}
+
+/// Parse a decimal or `0x` hex number
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn join<T>(values: &[T], format: impl Fn(&T) -> String) -> String {
+    values.iter().map(format).collect::<Vec<_>>().join(" ")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Command, ErrorCode, StackSnapshot};
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Assertion::parse("expect-stack 1 -2 0x10").unwrap().unwrap(),
+            Assertion::Stack(vec![1, -2, 16])
+        );
+        assert_eq!(
+            Assertion::parse("expect-stack").unwrap().unwrap(),
+            Assertion::Stack(vec![])
+        );
+        assert_eq!(
+            Assertion::parse("expect-memory 0x100 1 0xFF")
+                .unwrap()
+                .unwrap(),
+            Assertion::Memory {
+                addr: 0x100,
+                bytes: vec![1, 0xFF]
+            }
+        );
+        assert!(Assertion::parse("expect-stack one").unwrap().is_err());
+        assert!(Assertion::parse("expect-memory 0x100").unwrap().is_err());
+        assert!(
+            Assertion::parse("expect-memory 0x100 256")
+                .unwrap()
+                .is_err()
+        );
+        assert!(Assertion::parse("1 2 +").is_none());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut transport = MockTransport::new();
+        let stack = StackSnapshot {
+            data: vec![1, 2],
+            ret: vec![],
+        };
+        transport.push_response(ErrorCode::Ok, &stack.to_payload());
+        transport.push_response(ErrorCode::Ok, &[0xAA, 0xBB]);
+
+        let timeout = Duration::from_secs(1);
+        let stack = Assertion::Stack(vec![1, 3]);
+        assert_eq!(
+            stack.check(&mut transport, timeout).unwrap().as_deref(),
+            Some("expected stack [1 3], got [1 2]")
+        );
+        let memory = Assertion::Memory {
+            addr: 0x20,
+            bytes: vec![0xAA, 0xBB],
+        };
+        assert_eq!(memory.check(&mut transport, timeout).unwrap(), None);
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::QueryStack, Command::QueryMemory]
+        );
+
+        // No answer is an error, not a failed assertion
+        assert!(stack.check(&mut transport, timeout).is_err());
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let results = [
+            AssertionResult {
+                line: 3,
+                source: "expect-stack 1".to_string(),
+                failure: None,
+            },
+            AssertionResult {
+                line: 5,
+                source: "expect-stack".to_string(),
+                failure: Some("expected stack [], got [1 <2>]".to_string()),
+            },
+        ];
+        let xml = junit_xml("smoke.v4s", &results, None, Duration::from_millis(1500));
+
+        assert!(xml.contains(
+            "<testsuite name=\"smoke.v4s\" tests=\"2\" failures=\"1\" errors=\"0\" time=\"1.500\">"
+        ));
+        assert!(
+            xml.contains("<testcase classname=\"smoke.v4s\" name=\"line 3: expect-stack 1\"/>")
+        );
+        assert!(xml.contains("<failure message=\"expected stack [], got [1 &lt;2&gt;]\"/>"));
+
+        let xml = junit_xml(
+            "smoke.v4s",
+            &results[..1],
+            Some("line 4: Timeout"),
+            Duration::ZERO,
+        );
+        assert!(xml.contains("tests=\"2\" failures=\"0\" errors=\"1\""));
+        assert!(xml.contains("<error message=\"line 4: Timeout\"/>"));
+    }
+}
@@ -0,0 +1,724 @@
+//! Frame captures for protocol bug reports
+//!
+//! With `--trace-file` every frame sent to or received from a device is
+//! appended to a JSON Lines file: a header line, then one record per frame
+//! with the time since the capture started. Timeouts and receive errors are
+//! recorded too, so a capture shows what the host saw.
+//!
+//! ```text
+//! {"format":"v4-trace","version":1,"started_unix_ms":1760000000000}
+//! {"t_us":0,"port":"/dev/ttyUSB0","event":"sent","frame":"A5000020E0"}
+//! {"t_us":1840,"port":"/dev/ttyUSB0","event":"received","frame":"A50100006B"}
+//! {"t_us":5002113,"port":"/dev/ttyUSB0","event":"timeout"}
+//! ```
+//!
+//! Sent frames carry `seq` once sequence numbers are on. Raw reads of
+//! `v4 monitor` aren't frames and are not recorded.
+
+use crate::protocol::{Checksum, Command, Frame, Framing, Incoming};
+use crate::session::{from_hex, to_hex};
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Value of the header's `format` field
+pub const TRACE_FORMAT: &str = "v4-trace";
+
+/// Start-of-frame marker
+const STX: u8 = 0xA5;
+
+/// Trace file format written by this version
+pub const TRACE_VERSION: u32 = 1;
+
+/// First line of a trace file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceHeader {
+    pub format: String,
+    pub version: u32,
+    /// Wall-clock start of the capture
+    pub started_unix_ms: u64,
+}
+
+/// What happened on the link
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// Frame sent to the device
+    Sent {
+        #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+        frame: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u8>,
+    },
+    /// Frame received from the device
+    Received {
+        #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+        frame: Vec<u8>,
+    },
+    /// No frame arrived in time
+    Timeout,
+    /// Receiving failed
+    Error { message: String },
+}
+
+/// One line after the header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// Microseconds since the capture started
+    pub t_us: u64,
+    /// Port the frame went through, as shown in messages
+    pub port: String,
+    #[serde(flatten)]
+    pub event: TraceEvent,
+}
+
+impl TraceRecord {
+    /// Time since the capture started
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_micros(self.t_us)
+    }
+}
+
+/// Contents of a trace file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub header: TraceHeader,
+    pub records: Vec<TraceRecord>,
+}
+
+/// Recorded frame taken apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decoded {
+    /// Request from the host; `command` is `None` for unknown codes
+    Request {
+        code: u8,
+        command: Option<Command>,
+        seq: Option<u8>,
+        payload: Vec<u8>,
+    },
+    /// Response or output notification from the device
+    Incoming(Incoming),
+    /// Frame that doesn't parse
+    Invalid(String),
+}
+
+impl Trace {
+    /// Read a trace file, rejecting newer format versions
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|e| match e {
+            V4Error::Trace(message) => V4Error::Trace(format!("{}: {}", path, message)),
+            e => e,
+        })
+    }
+
+    /// Parse the JSON Lines of a trace file
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let invalid =
+            |n: usize, e: serde_json::Error| V4Error::Trace(format!("line {}: {}", n + 1, e));
+
+        let (n, first) = lines
+            .next()
+            .ok_or_else(|| V4Error::Trace("empty file".to_string()))?;
+        let header: TraceHeader = serde_json::from_str(first).map_err(|e| invalid(n, e))?;
+        if header.format != TRACE_FORMAT {
+            return Err(V4Error::Trace(format!(
+                "not a V4 trace (format '{}')",
+                header.format
+            )));
+        }
+        if header.version > TRACE_VERSION {
+            return Err(V4Error::Trace(format!(
+                "format version {} is newer than supported ({})",
+                header.version, TRACE_VERSION
+            )));
+        }
+
+        let records = lines
+            .map(|(n, line)| serde_json::from_str(line).map_err(|e| invalid(n, e)))
+            .collect::<Result<_>>()?;
+        Ok(Self { header, records })
+    }
+
+    /// Every record with its frame taken apart (`None` for timeouts and errors)
+    ///
+    /// Responses are decoded with a SEQ byte when the last frame sent
+    /// through their port carried one, and with the checksum it ended in.
+    pub fn decode(&self) -> Vec<(&TraceRecord, Option<Decoded>)> {
+        let mut last_sent: HashMap<&str, (bool, Checksum)> = HashMap::new();
+        self.records
+            .iter()
+            .map(|record| {
+                let decoded = match &record.event {
+                    TraceEvent::Sent { frame, seq } => {
+                        let checksum = request_checksum(frame).unwrap_or_default();
+                        last_sent.insert(&record.port, (seq.is_some(), checksum));
+                        Some(decode_request(frame, *seq))
+                    }
+                    TraceEvent::Received { frame } => {
+                        let (sequenced, checksum) = last_sent
+                            .get(record.port.as_str())
+                            .copied()
+                            .unwrap_or_default();
+                        Some(
+                            Frame::decode_incoming(frame, sequenced, checksum).map_or_else(
+                                |e| Decoded::Invalid(e.to_string()),
+                                Decoded::Incoming,
+                            ),
+                        )
+                    }
+                    TraceEvent::Timeout | TraceEvent::Error { .. } => None,
+                };
+                (record, decoded)
+            })
+            .collect()
+    }
+}
+
+/// Checksum a captured host frame ends in, told by its size
+///
+/// Captures don't record the negotiated framing; a host frame's LEN gives
+/// its exact size without the checksum.
+fn request_checksum(frame: &[u8]) -> Option<Checksum> {
+    let length = u16::from_le_bytes([*frame.get(1)?, *frame.get(2)?]) as usize;
+    frame
+        .len()
+        .checked_sub(4 + length)
+        .and_then(Checksum::from_size)
+}
+
+/// Take apart a host frame, whose LEN doesn't count the command byte
+fn decode_request(frame: &[u8], seq: Option<u8>) -> Decoded {
+    let (&[STX, len_l, len_h, code], rest) = frame.split_at(4.min(frame.len())) else {
+        return Decoded::Invalid(format!(
+            "Frame too short or without STX: {} bytes",
+            frame.len()
+        ));
+    };
+    let length = u16::from_le_bytes([len_l, len_h]) as usize;
+    let Some(checksum) = request_checksum(frame) else {
+        return Decoded::Invalid(format!(
+            "Frame is {} bytes, LEN {} says {}",
+            frame.len(),
+            length,
+            length + 5
+        ));
+    };
+    let (body, sum) = rest.split_at(length);
+    if let Err(e) = checksum.verify(&frame[1..4 + length], sum) {
+        return Decoded::Invalid(e.to_string());
+    }
+    Decoded::Request {
+        code,
+        command: Command::from_u8(code),
+        seq,
+        payload: body[usize::from(seq.is_some()).min(body.len())..].to_vec(),
+    }
+}
+
+/// Destination of captured frames, shared by every traced transport
+pub struct Recorder {
+    start: Instant,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Recorder {
+    /// Start a capture in a new file at `path`
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| V4Error::Trace(format!("Cannot create {}: {}", path, e)))?;
+        Self::new(file)
+    }
+
+    /// Start a capture written to `out`, beginning with the header
+    pub fn new(out: impl Write + Send + 'static) -> Result<Self> {
+        let header = TraceHeader {
+            format: TRACE_FORMAT.to_string(),
+            version: TRACE_VERSION,
+            started_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        };
+        let recorder = Self {
+            start: Instant::now(),
+            out: Mutex::new(Box::new(out)),
+        };
+        recorder.write_line(&header)?;
+        Ok(recorder)
+    }
+
+    /// Append one event
+    ///
+    /// Write failures are logged, not returned: a broken capture shouldn't
+    /// fail the command being traced.
+    pub fn record(&self, port: &str, event: TraceEvent) {
+        let record = TraceRecord {
+            t_us: self.start.elapsed().as_micros() as u64,
+            port: port.to_string(),
+            event,
+        };
+        if let Err(e) = self.write_line(&record) {
+            log::warn!("Cannot write trace record: {}", e);
+        }
+    }
+
+    /// Write a whole line at once; records must survive Ctrl+C exits
+    fn write_line(&self, value: &impl Serialize) -> Result<()> {
+        let mut line = serde_json::to_vec(value).map_err(|e| V4Error::Trace(e.to_string()))?;
+        line.push(b'\n');
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        out.write_all(&line)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Capture of this process, see [`record_to`]
+static RECORDER: OnceLock<Arc<Recorder>> = OnceLock::new();
+
+/// Record the frames of every transport opened from now on to `path`
+pub fn record_to(path: &str) -> Result<()> {
+    let recorder = Arc::new(Recorder::create(path)?);
+    RECORDER
+        .set(recorder)
+        .map_err(|_| V4Error::Trace("A capture is already running".to_string()))
+}
+
+/// Wrap `transport` in [`Traced`] if a capture is running
+pub fn wrap(transport: Box<dyn Transport>, port: &str) -> Box<dyn Transport> {
+    match RECORDER.get() {
+        Some(recorder) => Box::new(Traced::new(transport, port, Arc::clone(recorder))),
+        None => transport,
+    }
+}
+
+/// Transport that records every frame it moves
+///
+/// Sits below [`Retrying`](crate::transport::Retrying), so resends show up
+/// as separate frames. Waits that Ctrl+C handling splits into short reads
+/// time out repeatedly; only the first timeout in a row is recorded.
+pub struct Traced {
+    inner: Box<dyn Transport>,
+    port: String,
+    recorder: Arc<Recorder>,
+    /// Last receive timed out and nothing was sent since
+    timed_out: bool,
+    /// Checksum sent frames are recorded with; COBS stuffing is left out,
+    /// as it is from received frames
+    checksum: Checksum,
+}
+
+impl Traced {
+    pub fn new(
+        inner: Box<dyn Transport>,
+        port: impl Into<String>,
+        recorder: Arc<Recorder>,
+    ) -> Self {
+        Self {
+            inner,
+            port: port.into(),
+            recorder,
+            timed_out: false,
+            checksum: Checksum::Crc8,
+        }
+    }
+}
+
+impl Transport for Traced {
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.timed_out = false;
+        self.recorder.record(
+            &self.port,
+            TraceEvent::Sent {
+                frame: frame.encode_with(self.checksum),
+                seq: frame.seq,
+            },
+        );
+        self.inner.send_frame(frame)
+    }
+
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let result = self.inner.recv_response(timeout);
+        let event = match &result {
+            Ok(frame) => Some(TraceEvent::Received {
+                frame: frame.clone(),
+            }),
+            Err(V4Error::Timeout) => (!self.timed_out).then_some(TraceEvent::Timeout),
+            Err(e) => Some(TraceEvent::Error {
+                message: e.to_string(),
+            }),
+        };
+        self.timed_out = matches!(result, Err(V4Error::Timeout));
+        if let Some(event) = event {
+            self.recorder.record(&self.port, event);
+        }
+        result
+    }
+
+    fn read_raw(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.inner.read_raw(buf, timeout)
+    }
+
+    fn discard_input(&mut self) -> Result<()> {
+        self.inner.discard_input()
+    }
+
+    fn device_output(&mut self, data: &[u8]) {
+        self.inner.device_output(data)
+    }
+
+    fn switches_framing(&self) -> bool {
+        self.inner.switches_framing()
+    }
+
+    fn set_framing(&mut self, framing: Framing) -> Result<()> {
+        self.inner.set_framing(framing)?;
+        self.checksum = framing.checksum;
+        Ok(())
+    }
+
+    fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    fn reconnect(&mut self, wait: Duration) -> Result<String> {
+        self.timed_out = false;
+        self.inner.reconnect(wait)
+    }
+}
+
+/// Transport playing a capture back instead of talking to a device
+///
+/// Every frame sent must equal the next frame the capture sent, so tests
+/// notice when the host side changes what it puts on the wire. Received
+/// frames are returned in recorded order; receiving when the capture sends
+/// next (or has ended) times out after the requested timeout.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use v4_cli::V4Device;
+/// use v4_cli::trace::ReplayTransport;
+///
+/// let replay = ReplayTransport::load("capture.jsonl")?;
+/// let mut device = V4Device::from_transport(Box::new(replay), "replay");
+/// device.exec_source("3 4 +", Duration::from_millis(100))?;
+/// # Ok::<(), v4_cli::V4Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    events: VecDeque<TraceEvent>,
+    /// Records replayed so far, for messages
+    position: usize,
+    /// Device output received so far
+    pub output: Vec<u8>,
+    /// Checksum the host's frames are encoded with for comparison
+    checksum: Checksum,
+}
+
+impl ReplayTransport {
+    /// Replay what `trace` recorded for `port`
+    pub fn new(trace: &Trace, port: &str) -> Self {
+        Self {
+            events: trace
+                .records
+                .iter()
+                .filter(|record| record.port == port)
+                .map(|record| record.event.clone())
+                .collect(),
+            position: 0,
+            output: Vec::new(),
+            checksum: Checksum::Crc8,
+        }
+    }
+
+    /// Replay a capture of a single port
+    pub fn from_trace(trace: &Trace) -> Result<Self> {
+        let mut ports = trace.records.iter().map(|record| record.port.as_str());
+        let port = ports.next().unwrap_or_default();
+        if let Some(other) = ports.find(|&other| other != port) {
+            return Err(V4Error::Trace(format!(
+                "capture has several ports ({}, {}); pick one",
+                port, other
+            )));
+        }
+        Ok(Self::new(trace, port))
+    }
+
+    /// Replay a single-port trace file
+    pub fn load(path: &str) -> Result<Self> {
+        Self::from_trace(&Trace::load(path)?)
+    }
+
+    /// Frames sent or received in the capture that weren't replayed yet
+    pub fn remaining(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event, TraceEvent::Sent { .. } | TraceEvent::Received { .. }))
+            .count()
+    }
+
+    /// Fail unless the whole capture was replayed
+    pub fn finish(&self) -> Result<()> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(V4Error::Trace(format!(
+                "replay stopped after record {} with {} frame(s) left",
+                self.position, n
+            ))),
+        }
+    }
+
+    /// Drop the timeouts ahead; replay times out wherever the device is silent
+    fn skip_timeouts(&mut self) {
+        while let Some(TraceEvent::Timeout) = self.events.front() {
+            self.advance();
+        }
+    }
+
+    fn advance(&mut self) {
+        self.events.pop_front();
+        self.position += 1;
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.skip_timeouts();
+        let encoded = frame.encode_with(self.checksum);
+        let recorded = match self.events.front() {
+            Some(TraceEvent::Sent { frame, .. }) if *frame == encoded => {
+                self.advance();
+                return Ok(());
+            }
+            Some(TraceEvent::Sent { frame, .. }) => format!("sent {}", hex(frame)),
+            Some(TraceEvent::Received { frame }) => format!("received {}", hex(frame)),
+            Some(TraceEvent::Error { message }) => format!("failed: {}", message),
+            Some(TraceEvent::Timeout) | None => "ended".to_string(),
+        };
+        Err(V4Error::Trace(format!(
+            "replay diverged at record {}: host sent {:?} {}, capture {}",
+            self.position + 1,
+            frame.command,
+            hex(&encoded),
+            recorded
+        )))
+    }
+
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        self.skip_timeouts();
+        let result = match self.events.front() {
+            Some(TraceEvent::Received { frame }) => Ok(frame.clone()),
+            Some(TraceEvent::Error { message }) => {
+                Err(V4Error::Io(std::io::Error::other(message.clone())))
+            }
+            _ => {
+                std::thread::sleep(timeout);
+                return Err(V4Error::Timeout);
+            }
+        };
+        self.advance();
+        result
+    }
+
+    fn device_output(&mut self, data: &[u8]) {
+        self.output.extend_from_slice(data);
+    }
+
+    /// Captures come from transports that switch framing
+    fn switches_framing(&self) -> bool {
+        true
+    }
+
+    fn set_framing(&mut self, framing: Framing) -> Result<()> {
+        self.checksum = framing.checksum;
+        Ok(())
+    }
+
+    fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ErrorCode, Response};
+    use crate::transport::mock::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn test_traced_records_frames_and_timeouts() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let recorder = Arc::new(Recorder::create(path).unwrap());
+
+        let mut mock = MockTransport::new();
+        mock.push_response(ErrorCode::Ok, &[]);
+        let mut traced = Traced::new(Box::new(mock), "COM3", recorder);
+        assert_eq!(traced.ping(TIMEOUT).unwrap(), ErrorCode::Ok);
+        assert!(matches!(traced.ping(TIMEOUT), Err(V4Error::Timeout)));
+        assert!(matches!(
+            traced.recv_response(TIMEOUT),
+            Err(V4Error::Timeout)
+        ));
+
+        let trace = Trace::load(path).unwrap();
+        assert_eq!(trace.header.version, TRACE_VERSION);
+        let ping = Frame::new(Command::Ping, vec![]).unwrap().encode();
+        match &trace.records[..] {
+            [first, reply, second, timeout] => {
+                assert_eq!(
+                    first.event,
+                    TraceEvent::Sent {
+                        frame: ping,
+                        seq: None
+                    }
+                );
+                let TraceEvent::Received { frame } = &reply.event else {
+                    panic!("expected a received frame, got {:?}", reply.event);
+                };
+                assert_eq!(
+                    Frame::decode_response(frame).unwrap().error_code,
+                    ErrorCode::Ok
+                );
+                assert_eq!(second.event, first.event);
+                assert_eq!(timeout.event, TraceEvent::Timeout);
+            }
+            records => panic!("unexpected records: {:?}", records),
+        }
+        assert!(trace.records.iter().all(|r| r.port == "COM3"));
+        assert!(trace.records.windows(2).all(|w| w[0].t_us <= w[1].t_us));
+    }
+
+    #[test]
+    fn test_decode_tracks_sequence_numbers() {
+        let sent = Frame::new(Command::QueryStack, vec![]).unwrap().with_seq(7);
+        let mut mock = MockTransport::new();
+        mock.push_sequenced(7, ErrorCode::Ok, &[0]);
+        let reply = mock.recv_response(TIMEOUT).unwrap();
+        let text = [
+            format!(
+                r#"{{"format":"{}","version":1,"started_unix_ms":0}}"#,
+                TRACE_FORMAT
+            ),
+            serde_json::to_string(&TraceRecord {
+                t_us: 0,
+                port: "p".to_string(),
+                event: TraceEvent::Sent {
+                    frame: sent.encode(),
+                    seq: sent.seq,
+                },
+            })
+            .unwrap(),
+            serde_json::to_string(&TraceRecord {
+                t_us: 5,
+                port: "p".to_string(),
+                event: TraceEvent::Received { frame: reply },
+            })
+            .unwrap(),
+        ]
+        .join("\n");
+
+        let trace = Trace::parse(&text).unwrap();
+        let decoded: Vec<_> = trace.decode().into_iter().map(|(_, d)| d).collect();
+        assert_eq!(
+            decoded[0],
+            Some(Decoded::Request {
+                code: Command::QueryStack as u8,
+                command: Some(Command::QueryStack),
+                seq: Some(7),
+                payload: vec![],
+            })
+        );
+        assert_eq!(
+            decoded[1],
+            Some(Decoded::Incoming(Incoming::Response(Response {
+                error_code: ErrorCode::Ok,
+                word_indices: vec![],
+                data: vec![0],
+                seq: Some(7),
+            })))
+        );
+    }
+
+    #[test]
+    fn test_replay_times_out_where_the_device_was_silent() {
+        let ping = Frame::new(Command::Ping, vec![]).unwrap();
+        let record = |port: &str, event| TraceRecord {
+            t_us: 0,
+            port: port.to_string(),
+            event,
+        };
+        let sent = TraceEvent::Sent {
+            frame: ping.encode(),
+            seq: None,
+        };
+        let mut trace = Trace {
+            header: TraceHeader {
+                format: TRACE_FORMAT.to_string(),
+                version: TRACE_VERSION,
+                started_unix_ms: 0,
+            },
+            records: vec![
+                record("a", sent.clone()),
+                record("a", TraceEvent::Timeout),
+                record("a", sent.clone()),
+                record(
+                    "a",
+                    TraceEvent::Received {
+                        frame: vec![0xA5, 0x01, 0x00, 0x00, 0x6B],
+                    },
+                ),
+            ],
+        };
+
+        let mut replay = ReplayTransport::from_trace(&trace).unwrap();
+        assert!(matches!(replay.ping(TIMEOUT), Err(V4Error::Timeout)));
+        assert!(replay.finish().is_err());
+        assert_eq!(replay.ping(TIMEOUT).unwrap(), ErrorCode::Ok);
+        assert_eq!(replay.remaining(), 0);
+        replay.finish().unwrap();
+        assert!(matches!(replay.ping(TIMEOUT), Err(V4Error::Trace(_))));
+
+        trace.records.push(record("b", sent));
+        assert!(matches!(
+            ReplayTransport::from_trace(&trace),
+            Err(V4Error::Trace(_))
+        ));
+        assert_eq!(ReplayTransport::new(&trace, "b").remaining(), 1);
+    }
+
+    #[test]
+    fn test_parse_rejects_foreign_and_newer_files() {
+        assert!(matches!(Trace::parse(""), Err(V4Error::Trace(_))));
+        assert!(matches!(
+            Trace::parse(r#"{"format":"pcap","version":1,"started_unix_ms":0}"#),
+            Err(V4Error::Trace(_))
+        ));
+        let newer = format!(
+            r#"{{"format":"{}","version":{},"started_unix_ms":0}}"#,
+            TRACE_FORMAT,
+            TRACE_VERSION + 1
+        );
+        assert!(matches!(Trace::parse(&newer), Err(V4Error::Trace(_))));
+        let bad_record = format!(
+            "{}\n{{\"t_us\":0,\"port\":\"p\",\"event\":\"sent\",\"frame\":\"XYZ\"}}",
+            r#"{"format":"v4-trace","version":1,"started_unix_ms":0}"#
+        );
+        let err = Trace::parse(&bad_record).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+}
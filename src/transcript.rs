@@ -0,0 +1,339 @@
+//! REPL session transcripts (`v4 repl --transcript`, `.record`, `--replay`)
+//!
+//! A transcript is a JSON Lines file: a header line, then one record per
+//! event with the milliseconds since the recording started. Each input is
+//! followed by the device output it produced and how it ended, so
+//! `v4 repl --replay` can run the inputs again and point out where the
+//! session goes differently.
+//!
+//! ```text
+//! {"format":"v4-transcript","version":1,"started_unix_ms":1760000000000,"cli_version":"0.1.0"}
+//! {"t_ms":0,"event":"input","text":": SQ DUP * ;"}
+//! {"t_ms":14,"event":"ok"}
+//! {"t_ms":2210,"event":"input","text":"7 SQ ."}
+//! {"t_ms":2231,"event":"output","text":"49 "}
+//! {"t_ms":2231,"event":"ok"}
+//! {"t_ms":5120,"event":"input","text":"NOPE"}
+//! {"t_ms":5121,"event":"error","message":"Compilation error: Unknown word: NOPE"}
+//! ```
+//!
+//! Only device output is recorded; what the host prints, such as `.stack`
+//! listings, is not.
+
+use crate::{Result, V4Error};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Value of the header's `format` field
+pub const TRANSCRIPT_FORMAT: &str = "v4-transcript";
+
+/// Transcript format written by this version
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+/// First line of a transcript
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptHeader {
+    pub format: String,
+    pub version: u32,
+    /// Wall-clock start of the recording
+    pub started_unix_ms: u64,
+    /// Version of the `v4` that recorded it
+    pub cli_version: String,
+}
+
+/// What happened in the session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TranscriptEvent {
+    /// Complete input: a line, or a definition spanning lines
+    Input { text: String },
+    /// Device output the input produced
+    Output { text: String },
+    /// The input ran without error
+    Ok,
+    /// The input failed
+    Error { message: String },
+}
+
+/// One line after the header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptRecord {
+    /// Milliseconds since the header's `started_unix_ms`
+    pub t_ms: u64,
+    #[serde(flatten)]
+    pub event: TranscriptEvent,
+}
+
+/// How a recorded input ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Error(String),
+    /// The transcript ends before the input finished
+    Unknown,
+}
+
+/// Recorded input with what it produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub input: String,
+    pub output: String,
+    pub outcome: Outcome,
+}
+
+/// Contents of a transcript file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    pub header: TranscriptHeader,
+    pub records: Vec<TranscriptRecord>,
+}
+
+impl Transcript {
+    /// Read a transcript file, rejecting newer format versions
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| V4Error::Repl(format!("Cannot read {}: {}", path, e)))?;
+        Self::parse(&text).map_err(|e| match e {
+            V4Error::Repl(message) => V4Error::Repl(format!("{}: {}", path, message)),
+            e => e,
+        })
+    }
+
+    /// Parse the JSON Lines of a transcript
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let invalid =
+            |n: usize, e: serde_json::Error| V4Error::Repl(format!("line {}: {}", n + 1, e));
+
+        let (n, first) = lines
+            .next()
+            .ok_or_else(|| V4Error::Repl("empty transcript".to_string()))?;
+        let header = parse_header(first).map_err(|e| match e {
+            V4Error::Repl(message) => V4Error::Repl(format!("line {}: {}", n + 1, message)),
+            e => e,
+        })?;
+        let records = lines
+            .map(|(n, line)| serde_json::from_str(line).map_err(|e| invalid(n, e)))
+            .collect::<Result<_>>()?;
+        Ok(Self { header, records })
+    }
+
+    /// Inputs in order, each with its output and outcome
+    pub fn steps(&self) -> Vec<Step> {
+        let mut steps: Vec<Step> = Vec::new();
+        for record in &self.records {
+            match (&record.event, steps.last_mut()) {
+                (TranscriptEvent::Input { text }, _) => steps.push(Step {
+                    input: text.clone(),
+                    output: String::new(),
+                    outcome: Outcome::Unknown,
+                }),
+                (TranscriptEvent::Output { text }, Some(step)) => step.output.push_str(text),
+                (TranscriptEvent::Ok, Some(step)) => step.outcome = Outcome::Ok,
+                (TranscriptEvent::Error { message }, Some(step)) => {
+                    step.outcome = Outcome::Error(message.clone())
+                }
+                // Events before the first input belong to no step
+                (_, None) => {}
+            }
+        }
+        steps
+    }
+}
+
+fn parse_header(line: &str) -> Result<TranscriptHeader> {
+    let header: TranscriptHeader =
+        serde_json::from_str(line).map_err(|e| V4Error::Repl(e.to_string()))?;
+    if header.format != TRANSCRIPT_FORMAT {
+        return Err(V4Error::Repl(format!(
+            "not a V4 transcript (format '{}')",
+            header.format
+        )));
+    }
+    if header.version > TRANSCRIPT_VERSION {
+        return Err(V4Error::Repl(format!(
+            "format version {} is newer than supported ({})",
+            header.version, TRANSCRIPT_VERSION
+        )));
+    }
+    Ok(header)
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Transcript being recorded
+#[derive(Debug)]
+pub struct TranscriptWriter {
+    path: String,
+    file: File,
+    started_unix_ms: u64,
+}
+
+impl TranscriptWriter {
+    /// Record to `path`, appending if it already holds a transcript
+    ///
+    /// A new or empty file gets a header. Otherwise its header must be a
+    /// transcript's, whose start time the appended records count from, so
+    /// recording can stop and resume in the same file.
+    pub fn open(path: &str) -> Result<Self> {
+        let cannot_open = |e: std::io::Error| V4Error::Repl(format!("Cannot open {}: {}", path, e));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(cannot_open)?;
+        if file.metadata()?.len() > 0 {
+            let mut first = String::new();
+            BufReader::new(&file)
+                .read_line(&mut first)
+                .map_err(cannot_open)?;
+            let header = parse_header(&first)
+                .map_err(|e| V4Error::Repl(format!("Cannot append to {}: {}", path, e)))?;
+            return Ok(Self {
+                path: path.to_string(),
+                file,
+                started_unix_ms: header.started_unix_ms,
+            });
+        }
+
+        let header = TranscriptHeader {
+            format: TRANSCRIPT_FORMAT.to_string(),
+            version: TRANSCRIPT_VERSION,
+            started_unix_ms: unix_ms(),
+            cli_version: env!("CARGO_PKG_VERSION").to_string(),
+        };
+        write_line(&mut file, &header)?;
+        Ok(Self {
+            path: path.to_string(),
+            file,
+            started_unix_ms: header.started_unix_ms,
+        })
+    }
+
+    /// File being recorded to
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Append one event
+    ///
+    /// Write failures are logged, not returned: a broken transcript
+    /// shouldn't end the session being recorded.
+    pub fn record(&mut self, event: TranscriptEvent) {
+        let record = TranscriptRecord {
+            t_ms: unix_ms().saturating_sub(self.started_unix_ms),
+            event,
+        };
+        if let Err(e) = write_line(&mut self.file, &record) {
+            log::warn!("Cannot write transcript record: {}", e);
+        }
+    }
+
+    /// Append how an input ended, after the device output it produced
+    pub fn record_result<T>(&mut self, output: &[u8], result: &Result<T>) {
+        if !output.is_empty() {
+            self.record(TranscriptEvent::Output {
+                text: String::from_utf8_lossy(output).into_owned(),
+            });
+        }
+        self.record(match result {
+            Ok(_) => TranscriptEvent::Ok,
+            Err(e) => TranscriptEvent::Error {
+                message: e.to_string(),
+            },
+        });
+    }
+}
+
+/// Write a whole line at once; records must survive Ctrl+C exits
+fn write_line(file: &mut File, value: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_vec(value).map_err(|e| V4Error::Repl(e.to_string()))?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_session_reads_back_as_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let path = path.to_str().unwrap();
+
+        let mut writer = TranscriptWriter::open(path).unwrap();
+        writer.record(TranscriptEvent::Input {
+            text: "7 SQ .".to_string(),
+        });
+        writer.record_result(b"49 ", &Ok(()));
+        drop(writer);
+        // Resuming appends without a second header
+        let mut writer = TranscriptWriter::open(path).unwrap();
+        writer.record(TranscriptEvent::Input {
+            text: "NOPE".to_string(),
+        });
+        writer.record_result::<()>(b"", &Err(V4Error::Compilation("Unknown word".into())));
+        writer.record(TranscriptEvent::Input {
+            text: "BLINK".to_string(),
+        });
+
+        let transcript = Transcript::load(path).unwrap();
+        assert_eq!(transcript.header.format, TRANSCRIPT_FORMAT);
+        assert_eq!(
+            transcript.steps(),
+            vec![
+                Step {
+                    input: "7 SQ .".to_string(),
+                    output: "49 ".to_string(),
+                    outcome: Outcome::Ok,
+                },
+                Step {
+                    input: "NOPE".to_string(),
+                    output: String::new(),
+                    outcome: Outcome::Error("Compilation error: Unknown word".to_string()),
+                },
+                Step {
+                    input: "BLINK".to_string(),
+                    output: String::new(),
+                    outcome: Outcome::Unknown,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_other_files() {
+        assert!(Transcript::parse("").is_err());
+        let trace = r#"{"format":"v4-trace","version":1,"started_unix_ms":0}"#;
+        assert!(Transcript::parse(trace).is_err());
+
+        // Recording never appends to another kind of file
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "shopping list\n").unwrap();
+        assert!(TranscriptWriter::open(path.to_str().unwrap()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "shopping list\n");
+
+        let newer =
+            r#"{"format":"v4-transcript","version":9,"started_unix_ms":0,"cli_version":"9.0.0"}"#;
+        assert!(
+            Transcript::parse(newer)
+                .unwrap_err()
+                .to_string()
+                .contains("newer")
+        );
+    }
+}
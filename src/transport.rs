@@ -0,0 +1,1361 @@
+use crate::device::HANDSHAKE_TIMEOUT;
+use crate::interrupt;
+use crate::protocol::{
+    Checksum, Command, ErrorCode, FEATURE_COBS, FEATURE_CRC16, FEATURE_CRC32, FEATURE_SEQUENCE,
+    Frame, Framing, Handshake, Incoming, PROTOCOL_VERSION, Response, StackSnapshot,
+};
+use crate::reconnect::Reconnecting;
+use crate::rfc2217::{self, V4Rfc2217};
+use crate::serial::{SerialSettings, V4Serial};
+use crate::tcp::{self, V4Tcp};
+use crate::trace;
+use crate::usb::{self, UsbSelector};
+use crate::websocket::{self, V4WebSocket};
+use crate::{Result, V4Error};
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// `--port` prefix selecting the Bluetooth LE transport
+pub const BLE_SCHEME: &str = "ble://";
+
+/// Delay between readiness pings
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Bytecode bytes per EXEC_DATA frame (payload minus the u32 offset)
+const EXEC_CHUNK_SIZE: usize = Frame::MAX_PAYLOAD_SIZE - 4;
+
+/// Longest single read while a wait can be cancelled, so cancelling is noticed
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time for the answers to an ABORT to arrive before leftovers are dropped
+const ABORT_SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// Delay before the first resend when none is configured
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Device output written to stdout ended without a newline
+static OUTPUT_LINE_OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Whether device output left a line unfinished on stdout since the last call
+pub fn take_open_line() -> bool {
+    OUTPUT_LINE_OPEN.swap(false, Ordering::Relaxed)
+}
+
+thread_local! {
+    /// Copy of the device output written to stdout, while capturing
+    static CAPTURED_OUTPUT: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Keep a copy of the device output this thread writes to stdout from now on
+pub fn capture_output() {
+    CAPTURED_OUTPUT.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+}
+
+/// Stop capturing and return the output written since [`capture_output`]
+pub fn take_captured_output() -> Vec<u8> {
+    CAPTURED_OUTPUT.with(|captured| captured.borrow_mut().take().unwrap_or_default())
+}
+
+fn transfer_size(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| V4Error::Protocol(format!("Bytecode too large: {} bytes", len)))
+}
+
+/// EXEC_BEGIN with `begin` as payload, `data` in EXEC_DATA chunks, EXEC_END
+fn exec_chunks<T: Transport + ?Sized>(
+    transport: &mut T,
+    begin: &[u8],
+    data: &[u8],
+    timeout: Duration,
+    on_progress: &mut dyn FnMut(usize),
+) -> Result<Response> {
+    let response = transport.send_command(Command::ExecBegin, begin, timeout)?;
+    if response.error_code != ErrorCode::Ok {
+        return Ok(response);
+    }
+
+    let mut sent = 0;
+    for chunk in data.chunks(EXEC_CHUNK_SIZE) {
+        let mut payload = Vec::with_capacity(4 + chunk.len());
+        payload.extend_from_slice(&(sent as u32).to_le_bytes());
+        payload.extend_from_slice(chunk);
+
+        let response = transport.send_command(Command::ExecData, &payload, timeout)?;
+        if response.error_code != ErrorCode::Ok {
+            return Ok(response);
+        }
+        sent += chunk.len();
+        on_progress(sent);
+    }
+
+    transport.send_command(Command::ExecEnd, &[], timeout)
+}
+
+/// Resend policy for lost or corrupted frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Resends after the first attempt (0 disables retrying)
+    pub retries: u32,
+    /// Delay before the first resend; doubles for each further one
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before resend number `attempt` (starting at 0)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.delay.saturating_mul(1 << attempt.min(16))
+    }
+}
+
+/// How long to wait for the result of a command the device ACCEPTED
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultWait {
+    /// Give up with a timeout after this long
+    Within(Duration),
+    /// Wait until the result arrives or the wait is cancelled
+    Forever,
+}
+
+/// Overall time limit for an operation made of several exchanges
+///
+/// Each exchange keeps its own timeout, cut short when less time is left.
+/// Timeouts are reported with the phase of the operation they hit.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    start: Instant,
+    limit: Option<Duration>,
+}
+
+impl Deadline {
+    /// Start the clock; `None` sets no overall limit
+    pub fn start(limit: Option<Duration>) -> Self {
+        Self {
+            start: Instant::now(),
+            limit,
+        }
+    }
+
+    /// Time left, `None` without a limit
+    pub fn remaining(&self) -> Option<Duration> {
+        self.limit
+            .map(|limit| limit.saturating_sub(self.start.elapsed()))
+    }
+
+    fn expired(&self) -> bool {
+        self.remaining().is_some_and(|left| left.is_zero())
+    }
+
+    fn exceeded(&self, phase: &str) -> V4Error {
+        V4Error::DeadlineExceeded {
+            phase: phase.to_string(),
+            limit: self.limit.unwrap_or_default(),
+        }
+    }
+
+    /// Timeout for the next exchange of `phase`
+    pub fn timeout(&self, timeout: Duration, phase: &str) -> Result<Duration> {
+        match self.remaining() {
+            Some(left) if left.is_zero() => Err(self.exceeded(phase)),
+            Some(left) => Ok(timeout.min(left)),
+            None => Ok(timeout),
+        }
+    }
+
+    /// How long to wait for an ACCEPTED result in `phase`
+    pub fn wait(&self, wait: ResultWait, phase: &str) -> Result<ResultWait> {
+        Ok(match (wait, self.remaining()) {
+            (_, Some(left)) if left.is_zero() => return Err(self.exceeded(phase)),
+            (ResultWait::Within(timeout), Some(left)) => ResultWait::Within(timeout.min(left)),
+            (ResultWait::Forever, Some(left)) => ResultWait::Within(left),
+            (wait, None) => wait,
+        })
+    }
+
+    /// Name `phase` in a timeout of `result`
+    pub fn check<T>(&self, result: Result<T>, phase: &str) -> Result<T> {
+        match result {
+            Err(V4Error::Timeout) if self.expired() => Err(self.exceeded(phase)),
+            Err(V4Error::Timeout) => Err(V4Error::PhaseTimeout {
+                phase: phase.to_string(),
+            }),
+            result => result,
+        }
+    }
+}
+
+/// Open the transport selected by a `--port` value
+///
+/// `tcp://host[:port]` connects to a network gateway, `rfc2217://host:port`
+/// to a serial port shared by a terminal server, `ws://host/path` to a
+/// WebSocket bridge and `ble://<address>` to a Bluetooth LE device (with the
+/// `ble` feature); `usb:SERIAL` picks a serial port by USB serial number
+/// (see [`usb`]), anything else is a serial port path, auto-detected when
+/// omitted. Returns the transport and the resolved port name for messages.
+///
+/// While a capture runs (`--trace-file`) the transport records its frames,
+/// see [`trace`](crate::trace).
+pub fn open(port: Option<&str>, settings: &SerialSettings) -> Result<(Box<dyn Transport>, String)> {
+    let (transport, port) = connect(port, settings)?;
+    Ok((trace::wrap(transport, &port), port))
+}
+
+fn connect(port: Option<&str>, settings: &SerialSettings) -> Result<(Box<dyn Transport>, String)> {
+    if let Some(addr) = port.and_then(tcp::parse_address) {
+        let transport = V4Tcp::connect(&addr)?;
+        return Ok((Box::new(transport), format!("{}{}", tcp::TCP_SCHEME, addr)));
+    }
+
+    if let Some(addr) = port.and_then(rfc2217::parse_address) {
+        let transport = V4Rfc2217::connect(&addr, settings)?;
+        return Ok((
+            Box::new(transport),
+            format!("{}{}", rfc2217::RFC2217_SCHEME, addr),
+        ));
+    }
+
+    if let Some(url) = port.filter(|port| websocket::is_websocket_url(port)) {
+        let transport = V4WebSocket::connect(url)?;
+        return Ok((Box::new(transport), url.to_string()));
+    }
+
+    if let Some(addr) = port.and_then(|port| port.strip_prefix(BLE_SCHEME)) {
+        #[cfg(feature = "ble")]
+        {
+            let transport = crate::ble::V4Ble::connect(addr)?;
+            return Ok((Box::new(transport), format!("{}{}", BLE_SCHEME, addr)));
+        }
+        #[cfg(not(feature = "ble"))]
+        return Err(V4Error::Cli(format!(
+            "Cannot connect to {}{}: built without Bluetooth support (enable the `ble` feature)",
+            BLE_SCHEME, addr
+        )));
+    }
+
+    let usb = port.map(usb::selector).transpose()?.flatten();
+    let located = usb.as_ref().map(UsbSelector::locate).transpose()?;
+    let port = located.as_deref().or(port);
+
+    #[cfg(unix)]
+    if let Some(port) = port
+        && let Some(transport) = crate::daemon::connect(port)
+    {
+        return Ok((Box::new(transport), port.to_string()));
+    }
+
+    let port = V4Serial::resolve_port(port, settings)?;
+    let transport = Reconnecting::open(&port, settings)?.with_usb(usb);
+    Ok((Box::new(transport), port))
+}
+
+/// Frame transport to a V4-link device
+///
+/// Implementors only need to move raw frames; the command helpers are
+/// provided on top of `send_frame`/`recv_response`. Connections that are
+/// plain byte streams can implement [`ByteStream`](crate::stream::ByteStream)
+/// and use [`StreamTransport`](crate::stream::StreamTransport) instead.
+/// Any transport can be passed to [`V4Device::from_transport`](crate::V4Device::from_transport).
+pub trait Transport {
+    /// Send a frame
+    fn send_frame(&mut self, frame: &Frame) -> Result<()>;
+
+    /// Receive one raw response frame with timeout
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>>;
+
+    /// Read raw bytes as they arrive, for passive monitoring
+    ///
+    /// Returns 0 when nothing arrived within `timeout`.
+    fn read_raw(&mut self, _buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        Err(unsupported("This transport doesn't support raw reads"))
+    }
+
+    /// Drop any received bytes not yet returned as a frame
+    ///
+    /// Called before a resend so a late response to the previous attempt
+    /// isn't mistaken for the new one.
+    fn discard_input(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Handle program output (`."`, `EMIT`) the device sends while a command runs
+    ///
+    /// Written to stdout as it arrives by default, and kept while
+    /// [`capture_output`] is on; override to capture it otherwise.
+    fn device_output(&mut self, data: &[u8]) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(data);
+        let _ = stdout.flush();
+        if let Some(&last) = data.last() {
+            OUTPUT_LINE_OPEN.store(last != b'\n', Ordering::Relaxed);
+        }
+        CAPTURED_OUTPUT.with(|captured| {
+            if let Some(captured) = captured.borrow_mut().as_mut() {
+                captured.extend_from_slice(data);
+            }
+        });
+    }
+
+    /// Reopen the connection after it went away, e.g. the device re-enumerated
+    ///
+    /// Waits up to `wait` for the device to come back and returns the path
+    /// it was reopened under. Only serial ports can reconnect, see
+    /// [`Reconnecting`].
+    fn reconnect(&mut self, _wait: Duration) -> Result<String> {
+        Err(unsupported("This transport can't reconnect"))
+    }
+
+    /// Whether frames can be COBS-encoded or end in a checksum stronger
+    /// than CRC-8
+    ///
+    /// Transports that split the byte stream into frames themselves can, see
+    /// [`set_framing`](Self::set_framing).
+    fn switches_framing(&self) -> bool {
+        false
+    }
+
+    /// Send and expect frames in `framing` from now on
+    ///
+    /// Called once a HELLO handshake enabled it.
+    fn set_framing(&mut self, _framing: Framing) -> Result<()> {
+        Err(unsupported(
+            "This transport only supports plain frames with CRC-8",
+        ))
+    }
+
+    /// Checksum received frames end in, as last set with
+    /// [`set_framing`](Self::set_framing)
+    fn checksum(&self) -> Checksum {
+        Checksum::Crc8
+    }
+
+    /// Wait for the response to a command, passing output notifications on
+    ///
+    /// The timeout restarts with each output frame, so a program that keeps
+    /// printing isn't cut off. While the thread catches Ctrl+C (see
+    /// [`interrupt::catch`]) the wait fails with [`V4Error::Interrupted`]
+    /// once it is pressed.
+    fn recv_incoming(&mut self, timeout: Duration, sequenced: bool) -> Result<Response> {
+        let mut start = Instant::now();
+        loop {
+            if interrupt::interrupted() {
+                return Err(V4Error::Interrupted);
+            }
+            let remaining = timeout.saturating_sub(start.elapsed());
+            let slice = match interrupt::catching() {
+                true => remaining.min(CANCEL_POLL_INTERVAL),
+                false => remaining,
+            };
+            let frame = match self.recv_response(slice) {
+                Err(V4Error::Timeout) if slice < remaining => continue,
+                result => result?,
+            };
+            match Frame::decode_incoming(&frame, sequenced, self.checksum())? {
+                Incoming::Output(text) => {
+                    self.device_output(&text);
+                    start = Instant::now();
+                }
+                Incoming::Response(response) => return Ok(response),
+            }
+        }
+    }
+
+    /// Wait for the result of a command the device answered with ACCEPTED
+    ///
+    /// Devices may acknowledge a long-running EXEC right away and send the
+    /// outcome once the program finishes. The result is the next response
+    /// carrying the sequence number of `accepted` (any response when
+    /// sequence numbers are off); repeated ACCEPTED answers are skipped.
+    ///
+    /// Waits in short slices and returns [`V4Error::Interrupted`] once
+    /// `cancel` is set.
+    fn recv_result(
+        &mut self,
+        accepted: &Response,
+        wait: ResultWait,
+        cancel: &AtomicBool,
+    ) -> Result<Response> {
+        let start = Instant::now();
+        loop {
+            if cancel.load(Ordering::SeqCst) {
+                return Err(V4Error::Interrupted);
+            }
+            let slice = match wait {
+                ResultWait::Within(timeout) => {
+                    let remaining = timeout.saturating_sub(start.elapsed());
+                    if remaining.is_zero() {
+                        return Err(V4Error::Timeout);
+                    }
+                    remaining.min(CANCEL_POLL_INTERVAL)
+                }
+                ResultWait::Forever => CANCEL_POLL_INTERVAL,
+            };
+
+            let response = match self.recv_incoming(slice, accepted.seq.is_some()) {
+                Err(V4Error::Timeout) => continue,
+                result => result?,
+            };
+            if response.seq != accepted.seq {
+                log::debug!("Skipping stale response #{:?}", response.seq);
+            } else if response.error_code != ErrorCode::Accepted {
+                log::debug!(
+                    "Result after {:?}: {}",
+                    start.elapsed(),
+                    response.error_code.name()
+                );
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Send command and wait for response
+    fn send_command(
+        &mut self,
+        command: Command,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Response> {
+        let frame = Frame::new(command, payload.to_vec())?;
+        log::debug!("Sending {:?} ({} byte payload)", command, payload.len());
+        self.send_frame(&frame)?;
+
+        let response = self.recv_incoming(timeout, false)?;
+        log::debug!("{:?} -> {}", command, response.error_code.name());
+        Ok(response)
+    }
+
+    /// Negotiate protocol version and features with HELLO
+    ///
+    /// Requests sequence numbers, CRC-16, CRC-32 and COBS framing if the
+    /// transport can switch framing, and the `extra` feature bits. Returns `None` when
+    /// the device refuses HELLO, as firmware predating the handshake does.
+    fn hello(&mut self, extra: u8, timeout: Duration) -> Result<Option<Handshake>> {
+        let mut features = FEATURE_SEQUENCE | extra;
+        if self.switches_framing() {
+            features |= FEATURE_CRC16 | FEATURE_CRC32 | FEATURE_COBS;
+        }
+        let payload = [PROTOCOL_VERSION, features];
+        let response = self.send_command(Command::Hello, &payload, timeout)?;
+        if response.error_code != ErrorCode::Ok {
+            return Ok(None);
+        }
+        Handshake::from_payload(&response.data)
+            .map(Some)
+            .ok_or_else(|| V4Error::Protocol("Malformed HELLO response".to_string()))
+    }
+
+    /// Send PING command
+    fn ping(&mut self, timeout: Duration) -> Result<ErrorCode> {
+        Ok(self.send_command(Command::Ping, &[], timeout)?.error_code)
+    }
+
+    /// Send RESET command
+    fn reset(&mut self, timeout: Duration) -> Result<ErrorCode> {
+        Ok(self.send_command(Command::Reset, &[], timeout)?.error_code)
+    }
+
+    /// Poll with PING until the device answers OK
+    ///
+    /// Used after RESET, while the VM is restarting. Errors from individual
+    /// pings are treated as "not ready yet". Returns the time it took.
+    fn wait_ready(&mut self, ready_timeout: Duration) -> Result<Duration> {
+        let start = Instant::now();
+        loop {
+            let remaining = ready_timeout.saturating_sub(start.elapsed());
+            let attempt_timeout = remaining.min(Duration::from_millis(500));
+            if let Ok(ErrorCode::Ok) = self.ping(attempt_timeout) {
+                return Ok(start.elapsed());
+            }
+            if start.elapsed() >= ready_timeout {
+                return Err(V4Error::Device(format!(
+                    "Device not ready after {:.1}s",
+                    ready_timeout.as_secs_f64()
+                )));
+            }
+            std::thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+
+    /// Send EXEC command with bytecode
+    ///
+    /// Bytecode larger than one frame is sent in chunks, see
+    /// [`exec_with_progress`](Transport::exec_with_progress).
+    fn exec(&mut self, bytecode: &[u8], timeout: Duration) -> Result<Response> {
+        self.exec_with_progress(bytecode, timeout, &mut |_| {})
+    }
+
+    /// Send EXEC, reporting the number of bytecode bytes acknowledged so far
+    ///
+    /// Bytecode that fits in one frame goes out as a single EXEC. Larger
+    /// bytecode is split into EXEC_BEGIN, one EXEC_DATA per chunk and
+    /// EXEC_END; the device ACKs each frame and runs the program on
+    /// EXEC_END. A non-OK ACK stops the transfer and is returned as the
+    /// response.
+    ///
+    /// The answer to EXEC or EXEC_END may be ACCEPTED, meaning the program
+    /// is still running; wait for its result with
+    /// [`recv_result`](Transport::recv_result).
+    fn exec_with_progress(
+        &mut self,
+        bytecode: &[u8],
+        timeout: Duration,
+        on_progress: &mut dyn FnMut(usize),
+    ) -> Result<Response> {
+        if bytecode.len() <= Frame::MAX_PAYLOAD_SIZE {
+            let response = self.send_command(Command::Exec, bytecode, timeout)?;
+            if response.error_code == ErrorCode::Ok {
+                on_progress(bytecode.len());
+            }
+            return Ok(response);
+        }
+
+        let total = transfer_size(bytecode.len())?;
+        exec_chunks(self, &total.to_le_bytes(), bytecode, timeout, on_progress)
+    }
+
+    /// Send bytecode compressed into an LZ4 `block` as a chunked EXEC
+    ///
+    /// EXEC_BEGIN carries the bytecode size followed by the block size (u32
+    /// LE each); EXEC_DATA offsets and `on_progress` count block bytes. Only
+    /// for devices that enabled [`FEATURE_LZ4`](crate::protocol::FEATURE_LZ4).
+    fn exec_compressed(
+        &mut self,
+        block: &[u8],
+        size: usize,
+        timeout: Duration,
+        on_progress: &mut dyn FnMut(usize),
+    ) -> Result<Response> {
+        let mut begin = transfer_size(size)?.to_le_bytes().to_vec();
+        begin.extend_from_slice(&transfer_size(block.len())?.to_le_bytes());
+        exec_chunks(self, &begin, block, timeout, on_progress)
+    }
+
+    /// Register a word definition compiled by V4-front
+    ///
+    /// Devices recognize definitions sent with EXEC themselves, so this is a
+    /// plain EXEC by default. The response carries the assigned word index.
+    /// Transports without a firmware behind them (the engine) override it.
+    fn define_word(&mut self, name: &str, bytecode: &[u8], timeout: Duration) -> Result<Response> {
+        let _ = name;
+        self.exec(bytecode, timeout)
+    }
+
+    /// Query stack state (data stack + return stack)
+    fn query_stack(&mut self, timeout: Duration) -> Result<Response> {
+        self.send_command(Command::QueryStack, &[], timeout)
+    }
+
+    /// Query the stacks and parse them into a [`StackSnapshot`]
+    fn stack_snapshot(&mut self, timeout: Duration) -> Result<StackSnapshot> {
+        let response = self.query_stack(timeout)?;
+        if response.error_code != ErrorCode::Ok {
+            return Err(V4Error::Device(format!(
+                "Query stack failed: {}",
+                response.error_code.name()
+            )));
+        }
+        StackSnapshot::from_payload(&response.data)
+    }
+
+    /// Query memory dump at address
+    fn query_memory(&mut self, addr: u32, len: u16, timeout: Duration) -> Result<Response> {
+        let mut payload = Vec::with_capacity(6);
+        // Address (little-endian u32)
+        payload.extend_from_slice(&addr.to_le_bytes());
+        // Length (little-endian u16)
+        payload.extend_from_slice(&len.to_le_bytes());
+        self.send_command(Command::QueryMemory, &payload, timeout)
+    }
+
+    /// Write bytes to VM memory at `addr`
+    ///
+    /// Data larger than one frame is sent as consecutive WRITE_MEMORY
+    /// frames; a non-OK answer stops the write and is returned.
+    fn write_memory(&mut self, addr: u32, data: &[u8], timeout: Duration) -> Result<Response> {
+        let mut response = None;
+        for (i, chunk) in data.chunks(EXEC_CHUNK_SIZE).enumerate() {
+            let chunk_addr = addr.wrapping_add((i * EXEC_CHUNK_SIZE) as u32);
+            let mut payload = Vec::with_capacity(4 + chunk.len());
+            payload.extend_from_slice(&chunk_addr.to_le_bytes());
+            payload.extend_from_slice(chunk);
+
+            let answer = self.send_command(Command::WriteMemory, &payload, timeout)?;
+            if answer.error_code != ErrorCode::Ok {
+                return Ok(answer);
+            }
+            response = Some(answer);
+        }
+        match response {
+            Some(response) => Ok(response),
+            // Empty data is a single frame holding just the address
+            None => self.send_command(Command::WriteMemory, &addr.to_le_bytes(), timeout),
+        }
+    }
+
+    /// Query firmware and VM information
+    fn query_info(&mut self, timeout: Duration) -> Result<Response> {
+        self.send_command(Command::QueryInfo, &[], timeout)
+    }
+
+    /// Read the VM cycle counter
+    fn query_ticks(&mut self, timeout: Duration) -> Result<Response> {
+        self.send_command(Command::QueryTicks, &[], timeout)
+    }
+
+    /// Query word information by index
+    fn query_word(&mut self, word_idx: u16, timeout: Duration) -> Result<Response> {
+        let payload = word_idx.to_le_bytes();
+        self.send_command(Command::QueryWord, &payload, timeout)
+    }
+
+    /// Set a breakpoint at a bytecode offset within a word
+    fn set_breakpoint(
+        &mut self,
+        word_idx: u16,
+        offset: u16,
+        timeout: Duration,
+    ) -> Result<Response> {
+        let mut payload = Vec::with_capacity(4);
+        payload.extend_from_slice(&word_idx.to_le_bytes());
+        payload.extend_from_slice(&offset.to_le_bytes());
+        self.send_command(Command::SetBreakpoint, &payload, timeout)
+    }
+
+    /// Execute one instruction of a halted VM
+    fn step(&mut self, timeout: Duration) -> Result<Response> {
+        self.send_command(Command::Step, &[], timeout)
+    }
+
+    /// Resume a halted VM
+    fn resume(&mut self, timeout: Duration) -> Result<Response> {
+        self.send_command(Command::Continue, &[], timeout)
+    }
+
+    /// Stop the running program with ABORT
+    ///
+    /// A device busy with a command answers that command first, then the
+    /// ABORT. Without sequence numbers the two answers can't be told apart,
+    /// so whatever arrives shortly after the first one is dropped.
+    fn abort(&mut self, timeout: Duration) -> Result<ErrorCode> {
+        let response = self.send_command(Command::Abort, &[], timeout)?;
+        std::thread::sleep(ABORT_SETTLE_TIME);
+        self.discard_input()?;
+        Ok(response.error_code)
+    }
+}
+
+/// Error for an operation the transport can't do, an I/O failure to callers
+fn unsupported(message: &str) -> V4Error {
+    V4Error::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        message,
+    ))
+}
+
+/// Transport that resends frames according to a [`RetryPolicy`]
+///
+/// A command is resent when its response times out or fails the CRC check,
+/// unless it isn't idempotent (see [`Command::is_idempotent`]): the device
+/// may already have run the code. An INVALID_FRAME error means the device
+/// rejected the request itself, so any command is resent then.
+///
+/// With sequence numbers enabled every command is resent under its original
+/// number. The device recognizes the duplicate and repeats its last response
+/// instead of running the command again, and stale responses to earlier
+/// numbers are skipped.
+pub struct Retrying {
+    inner: Box<dyn Transport>,
+    policy: RetryPolicy,
+    /// Next sequence number, `None` until negotiated
+    next_seq: Option<u8>,
+    /// Framing negotiated with HELLO
+    framing: Framing,
+    /// Feature bits requested beyond sequence numbers and framing
+    extra_features: u8,
+}
+
+impl Retrying {
+    pub fn new(inner: Box<dyn Transport>, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            next_seq: None,
+            framing: Framing::default(),
+            extra_features: 0,
+        }
+    }
+
+    /// Also request these feature bits in every HELLO
+    pub fn requesting(mut self, features: u8) -> Self {
+        self.extra_features = features;
+        self
+    }
+
+    /// Send HELLO and turn on what the device enabled
+    ///
+    /// The HELLO exchange itself uses plain CRC-8 frames; the framing the
+    /// device enabled applies from the next frame on. Returns `None` for firmware without HELLO.
+    pub fn negotiate(&mut self, timeout: Duration) -> Result<Option<Handshake>> {
+        let handshake = match self.hello(self.extra_features, timeout) {
+            Ok(Some(handshake)) => handshake,
+            Ok(None) | Err(V4Error::Timeout) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if handshake.supports_sequence() {
+            self.enable_sequence();
+        }
+        let framing = handshake.framing();
+        if framing != self.framing {
+            self.set_framing(framing)?;
+        }
+        Ok(Some(handshake))
+    }
+
+    /// Attach sequence numbers to all further frames
+    ///
+    /// Only valid after a HELLO handshake in which the device enabled
+    /// [`FEATURE_SEQUENCE`].
+    pub fn enable_sequence(&mut self) {
+        self.next_seq.get_or_insert(0);
+    }
+
+    /// Send a sequenced frame and wait for the response carrying its number
+    fn exchange(
+        &mut self,
+        command: Command,
+        payload: &[u8],
+        seq: u8,
+        timeout: Duration,
+    ) -> Result<Response> {
+        let frame = Frame::new(command, payload.to_vec())?.with_seq(seq);
+        log::debug!(
+            "Sending {:?} #{} ({} byte payload)",
+            command,
+            seq,
+            payload.len()
+        );
+        self.inner.send_frame(&frame)?;
+
+        let start = Instant::now();
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            let response = self.inner.recv_incoming(remaining, true)?;
+            if response.seq == Some(seq) {
+                log::debug!("{:?} #{} -> {}", command, seq, response.error_code.name());
+                return Ok(response);
+            }
+            log::debug!("Skipping stale response #{:?}", response.seq);
+        }
+    }
+}
+
+impl Transport for Retrying {
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.inner.send_frame(frame)
+    }
+
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        self.inner.recv_response(timeout)
+    }
+
+    fn read_raw(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.inner.read_raw(buf, timeout)
+    }
+
+    fn discard_input(&mut self) -> Result<()> {
+        self.inner.discard_input()
+    }
+
+    fn device_output(&mut self, data: &[u8]) {
+        self.inner.device_output(data)
+    }
+
+    fn switches_framing(&self) -> bool {
+        self.inner.switches_framing()
+    }
+
+    fn set_framing(&mut self, framing: Framing) -> Result<()> {
+        self.inner.set_framing(framing)?;
+        self.framing = framing;
+        Ok(())
+    }
+
+    fn checksum(&self) -> Checksum {
+        self.inner.checksum()
+    }
+
+    fn send_command(
+        &mut self,
+        command: Command,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Response> {
+        let seq = self.next_seq;
+        if let Some(seq) = seq {
+            self.next_seq = Some(seq.wrapping_add(1));
+        }
+
+        let mut attempt = 0;
+        loop {
+            let result = match seq {
+                Some(seq) => self.exchange(command, payload, seq, timeout),
+                None => self.inner.send_command(command, payload, timeout),
+            };
+            let failure = match &result {
+                Ok(response) if response.error_code == ErrorCode::InvalidFrame => {
+                    ErrorCode::InvalidFrame.name().to_string()
+                }
+                Err(e @ (V4Error::Timeout | V4Error::CrcMismatch { .. })) => {
+                    if seq.is_none() && !command.is_idempotent() {
+                        log::warn!("{:?} not resent after '{}': it may have run", command, e);
+                        return result;
+                    }
+                    e.to_string()
+                }
+                _ => return result,
+            };
+            if attempt >= self.policy.retries {
+                return result;
+            }
+
+            let delay = self.policy.backoff(attempt);
+            attempt += 1;
+            log::warn!(
+                "{:?} failed ({}), retrying in {} ms ({}/{})",
+                command,
+                failure,
+                delay.as_millis(),
+                attempt,
+                self.policy.retries
+            );
+            std::thread::sleep(delay);
+            self.inner.discard_input()?;
+        }
+    }
+
+    /// Readiness polling already repeats PING; don't multiply it
+    fn wait_ready(&mut self, ready_timeout: Duration) -> Result<Duration> {
+        self.inner.wait_ready(ready_timeout)
+    }
+
+    /// Negotiate again; the device may have restarted and forgotten the
+    /// features it enabled
+    fn reconnect(&mut self, wait: Duration) -> Result<String> {
+        let port = self.inner.reconnect(wait)?;
+        let negotiated = self.next_seq.take().is_some() || self.framing != Framing::default();
+        if negotiated {
+            if self.framing != Framing::default() {
+                self.set_framing(Framing::default())?;
+            }
+            if self.negotiate(HANDSHAKE_TIMEOUT)?.is_none() {
+                log::debug!("Device no longer supports HELLO");
+            }
+        }
+        Ok(port)
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use crate::V4Error;
+    use crate::protocol::{NOTIFY_OUTPUT, device_frame};
+    use std::collections::VecDeque;
+
+    /// Scripted transport for tests
+    ///
+    /// Records every frame sent and answers with queued responses in order.
+    /// Receiving with an empty queue behaves like a device timeout.
+    #[derive(Default)]
+    pub struct MockTransport {
+        pub sent: Vec<Frame>,
+        /// Device output received so far
+        pub output: Vec<u8>,
+        /// `None` entries time out
+        responses: VecDeque<Option<Vec<u8>>>,
+        /// Times the connection was reopened
+        pub reconnects: usize,
+    }
+
+    impl MockTransport {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Queue a response frame with the given error code and payload
+        pub fn push_response(&mut self, error_code: ErrorCode, payload: &[u8]) {
+            self.push_frame(error_code as u8, payload);
+        }
+
+        /// Queue an output notification
+        pub fn push_output(&mut self, text: &str) {
+            self.push_frame(NOTIFY_OUTPUT, text.as_bytes());
+        }
+
+        fn push_frame(&mut self, code: u8, payload: &[u8]) {
+            self.responses.push_back(Some(device_frame(code, payload)));
+        }
+
+        /// Queue a response to a sequenced request
+        pub fn push_sequenced(&mut self, seq: u8, error_code: ErrorCode, payload: &[u8]) {
+            let mut data = vec![seq];
+            data.extend_from_slice(payload);
+            self.push_response(error_code, &data);
+        }
+
+        /// Queue an OK response carrying registered word indices
+        pub fn push_word_indices(&mut self, indices: &[u16]) {
+            let mut payload = vec![indices.len() as u8];
+            for idx in indices {
+                payload.extend_from_slice(&idx.to_le_bytes());
+            }
+            self.push_response(ErrorCode::Ok, &payload);
+        }
+
+        /// Queue a QUERY_STACK answer with `data` on the data stack
+        pub fn push_stack(&mut self, data: &[i32]) {
+            let stack = StackSnapshot {
+                data: data.to_vec(),
+                ret: Vec::new(),
+            };
+            self.push_response(ErrorCode::Ok, &stack.to_payload());
+        }
+
+        /// Queue raw bytes to be returned as-is
+        pub fn push_raw(&mut self, raw: Vec<u8>) {
+            self.responses.push_back(Some(raw));
+        }
+
+        /// Queue a lost response
+        pub fn push_timeout(&mut self) {
+            self.responses.push_back(None);
+        }
+
+        /// Commands sent so far, in order
+        pub fn sent_commands(&self) -> Vec<Command> {
+            self.sent.iter().map(|f| f.command).collect()
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+            self.sent.push(frame.clone());
+            Ok(())
+        }
+
+        fn recv_response(&mut self, _timeout: Duration) -> Result<Vec<u8>> {
+            self.responses.pop_front().flatten().ok_or(V4Error::Timeout)
+        }
+
+        fn device_output(&mut self, data: &[u8]) {
+            self.output.extend_from_slice(data);
+        }
+
+        fn reconnect(&mut self, _wait: Duration) -> Result<String> {
+            self.reconnects += 1;
+            Ok("mock".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockTransport;
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn test_mock_ping() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        assert_eq!(transport.ping(TIMEOUT).unwrap(), ErrorCode::Ok);
+        assert_eq!(transport.sent_commands(), vec![Command::Ping]);
+    }
+
+    #[test]
+    fn test_unsupported_operation_is_an_io_failure() {
+        struct Plain;
+        impl Transport for Plain {
+            fn send_frame(&mut self, _frame: &Frame) -> Result<()> {
+                Ok(())
+            }
+            fn recv_response(&mut self, _timeout: Duration) -> Result<Vec<u8>> {
+                Err(V4Error::Timeout)
+            }
+        }
+
+        let err = Plain.read_raw(&mut [0; 4], TIMEOUT).unwrap_err();
+        assert_eq!(err.exit_code(), crate::error::EXIT_IO);
+        let err = Plain.reconnect(TIMEOUT).unwrap_err();
+        assert_eq!(err.exit_code(), crate::error::EXIT_IO);
+    }
+
+    #[test]
+    fn test_mock_exec_word_indices() {
+        let mut transport = MockTransport::new();
+        transport.push_word_indices(&[3, 4]);
+
+        let response = transport.exec(&[0x51], TIMEOUT).unwrap();
+        assert_eq!(response.word_indices, vec![3, 4]);
+        assert_eq!(transport.sent[0].payload, vec![0x51]);
+    }
+
+    #[test]
+    fn test_exec_chunked() {
+        let bytecode: Vec<u8> = (0..1200).map(|i| i as u8).collect();
+        let mut transport = MockTransport::new();
+        for _ in 0..4 {
+            transport.push_response(ErrorCode::Ok, &[]);
+        }
+        transport.push_word_indices(&[9]);
+
+        let mut progress = Vec::new();
+        let response = transport
+            .exec_with_progress(&bytecode, TIMEOUT, &mut |n| progress.push(n))
+            .unwrap();
+
+        assert_eq!(response.word_indices, vec![9]);
+        assert_eq!(
+            transport.sent_commands(),
+            vec![
+                Command::ExecBegin,
+                Command::ExecData,
+                Command::ExecData,
+                Command::ExecData,
+                Command::ExecEnd,
+            ]
+        );
+        assert_eq!(transport.sent[0].payload, 1200u32.to_le_bytes());
+        assert_eq!(transport.sent[2].payload[..4], 508u32.to_le_bytes());
+        assert_eq!(progress, vec![508, 1016, 1200]);
+
+        let reassembled: Vec<u8> = transport.sent[1..4]
+            .iter()
+            .flat_map(|f| f.payload[4..].to_vec())
+            .collect();
+        assert_eq!(reassembled, bytecode);
+    }
+
+    #[test]
+    fn test_exec_compressed() {
+        let bytecode = vec![0x51; 1200];
+        let block = crate::protocol::compress::compress(&bytecode);
+        let mut transport = MockTransport::new();
+        for _ in 0..3 {
+            transport.push_response(ErrorCode::Ok, &[]);
+        }
+
+        transport
+            .exec_compressed(&block, bytecode.len(), TIMEOUT, &mut |_| {})
+            .unwrap();
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::ExecBegin, Command::ExecData, Command::ExecEnd]
+        );
+        let begin = &transport.sent[0].payload;
+        assert_eq!(begin[..4], 1200u32.to_le_bytes());
+        assert_eq!(begin[4..], (block.len() as u32).to_le_bytes());
+        assert_eq!(transport.sent[1].payload[4..], block);
+    }
+
+    #[test]
+    fn test_recv_result_after_accepted() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Accepted, &[]);
+        transport.push_timeout();
+        transport.push_output("tick");
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        let accepted = transport.exec(&[0x01], TIMEOUT).unwrap();
+        assert_eq!(accepted.error_code, ErrorCode::Accepted);
+        let cancel = AtomicBool::new(false);
+        let result = transport
+            .recv_result(&accepted, ResultWait::Forever, &cancel)
+            .unwrap();
+        assert_eq!(result.error_code, ErrorCode::Ok);
+        assert_eq!(transport.output, b"tick");
+        assert_eq!(transport.sent_commands(), vec![Command::Exec]);
+    }
+
+    #[test]
+    fn test_recv_result_matches_sequence() {
+        let mut transport = MockTransport::new();
+        transport.push_sequenced(3, ErrorCode::Ok, &[]);
+        transport.push_sequenced(4, ErrorCode::Accepted, &[]);
+        transport.push_sequenced(4, ErrorCode::VmError, &[]);
+
+        let accepted = Response {
+            error_code: ErrorCode::Accepted,
+            word_indices: vec![],
+            data: vec![],
+            seq: Some(4),
+        };
+        let cancel = AtomicBool::new(false);
+        let result = transport
+            .recv_result(&accepted, ResultWait::Within(TIMEOUT), &cancel)
+            .unwrap();
+        assert_eq!(result.error_code, ErrorCode::VmError);
+        assert_eq!(result.seq, Some(4));
+    }
+
+    #[test]
+    fn test_recv_result_timeout_and_cancel() {
+        let mut transport = MockTransport::new();
+        let accepted = Response {
+            error_code: ErrorCode::Accepted,
+            word_indices: vec![],
+            data: vec![],
+            seq: None,
+        };
+        let cancel = AtomicBool::new(false);
+        assert!(matches!(
+            transport.recv_result(&accepted, ResultWait::Within(TIMEOUT), &cancel),
+            Err(V4Error::Timeout)
+        ));
+
+        cancel.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            transport.recv_result(&accepted, ResultWait::Forever, &cancel),
+            Err(V4Error::Interrupted)
+        ));
+    }
+
+    #[test]
+    fn test_abort() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        assert_eq!(transport.abort(TIMEOUT).unwrap(), ErrorCode::Ok);
+        assert_eq!(transport.sent_commands(), vec![Command::Abort]);
+    }
+
+    #[test]
+    fn test_recv_incoming_polls_while_catching() {
+        let mut transport = MockTransport::new();
+        transport.push_timeout();
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        // The lost frame only ends one short read, not the whole wait
+        let _catch = interrupt::catch();
+        let response = transport.recv_incoming(Duration::from_secs(1), false);
+        assert_eq!(response.unwrap().error_code, ErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_exec_chunked_stops_on_nak() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::BufferFull, &[]);
+
+        let response = transport.exec(&[0; 1000], TIMEOUT).unwrap();
+        assert_eq!(response.error_code, ErrorCode::BufferFull);
+        assert_eq!(
+            transport.sent_commands(),
+            vec![Command::ExecBegin, Command::ExecData]
+        );
+    }
+
+    #[test]
+    fn test_output_before_response() {
+        let mut transport = MockTransport::new();
+        transport.push_output("h");
+        transport.push_output("i");
+        transport.push_word_indices(&[]);
+
+        let response = transport.exec(&[0x51], TIMEOUT).unwrap();
+        assert_eq!(response.error_code, ErrorCode::Ok);
+        assert_eq!(transport.output, b"hi");
+    }
+
+    #[test]
+    fn test_stack_snapshot_from_captured_frame() {
+        let mut transport = MockTransport::new();
+        // OK, data stack [10, -3], return stack [0x1F40]
+        transport.push_raw(vec![
+            0xA5, 0x0F, 0x00, 0x00, 0x02, 0x0A, 0x00, 0x00, 0x00, 0xFD, 0xFF, 0xFF, 0xFF, 0x01,
+            0x40, 0x1F, 0x00, 0x00, 0xD5,
+        ]);
+        // OK, data stack depth 2 but only one cell
+        transport.push_raw(vec![
+            0xA5, 0x06, 0x00, 0x00, 0x02, 0x0A, 0x00, 0x00, 0x00, 0x32,
+        ]);
+        transport.push_response(ErrorCode::VmError, &[]);
+
+        let stack = transport.stack_snapshot(TIMEOUT).unwrap();
+        assert_eq!(stack.data, vec![10, -3]);
+        assert_eq!(stack.ret, vec![0x1F40]);
+        assert_eq!(transport.sent_commands(), vec![Command::QueryStack]);
+
+        assert!(matches!(
+            transport.stack_snapshot(TIMEOUT),
+            Err(V4Error::Protocol(_))
+        ));
+        assert!(matches!(
+            transport.stack_snapshot(TIMEOUT),
+            Err(V4Error::Device(_))
+        ));
+    }
+
+    #[test]
+    fn test_query_memory_payload() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        transport.query_memory(0x1234, 16, TIMEOUT).unwrap();
+        assert_eq!(
+            transport.sent[0].payload,
+            vec![0x34, 0x12, 0x00, 0x00, 0x10, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_write_memory_chunks() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[]);
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let response = transport.write_memory(0x1000, &data, TIMEOUT).unwrap();
+        assert_eq!(response.error_code, ErrorCode::Ok);
+        assert_eq!(transport.sent_commands(), vec![Command::WriteMemory; 2]);
+        assert_eq!(transport.sent[0].payload[..4], 0x1000u32.to_le_bytes());
+        assert_eq!(
+            transport.sent[1].payload[..4],
+            (0x1000u32 + 508).to_le_bytes()
+        );
+        assert_eq!(transport.sent[1].payload[4..], data[508..]);
+    }
+
+    #[test]
+    fn test_wait_ready_retries_until_ok() {
+        let mut transport = MockTransport::new();
+        transport.push_raw(vec![0x00, 0x01]);
+        transport.push_response(ErrorCode::Error, &[]);
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        transport.wait_ready(Duration::from_secs(2)).unwrap();
+        assert_eq!(transport.sent_commands(), vec![Command::Ping; 3]);
+    }
+
+    #[test]
+    fn test_wait_ready_gives_up() {
+        let mut transport = MockTransport::new();
+        let result = transport.wait_ready(Duration::from_millis(120));
+        assert!(matches!(result, Err(crate::V4Error::Device(_))));
+        assert!(!transport.sent.is_empty());
+    }
+
+    #[test]
+    fn test_mock_timeout_when_empty() {
+        let mut transport = MockTransport::new();
+        assert!(matches!(
+            transport.ping(TIMEOUT),
+            Err(crate::V4Error::Timeout)
+        ));
+    }
+
+    fn retrying(transport: MockTransport, retries: u32) -> Retrying {
+        let policy = RetryPolicy {
+            retries,
+            delay: Duration::from_millis(1),
+        };
+        Retrying::new(Box::new(transport), policy)
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles() {
+        let policy = RetryPolicy {
+            retries: 3,
+            delay: Duration::from_millis(100),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_retry_on_timeout_and_crc() {
+        let mut transport = MockTransport::new();
+        transport.push_timeout();
+        transport.push_raw(vec![0xA5, 0x01, 0x00, 0x00, 0xFF]);
+        transport.push_response(ErrorCode::Ok, &[]);
+
+        let mut transport = retrying(transport, 2);
+        assert_eq!(transport.ping(TIMEOUT).unwrap(), ErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let mut transport = retrying(MockTransport::new(), 2);
+        assert!(matches!(
+            transport.ping(TIMEOUT),
+            Err(crate::V4Error::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_exec_not_resent_after_timeout() {
+        let mut transport = MockTransport::new();
+        transport.push_timeout();
+        transport.push_word_indices(&[]);
+
+        let mut transport = retrying(transport, 3);
+        assert!(matches!(
+            transport.exec(&[0x51], TIMEOUT),
+            Err(crate::V4Error::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_hello_handshake() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::Ok, &[1, FEATURE_SEQUENCE]);
+        transport.push_response(ErrorCode::Error, &[]);
+
+        let handshake = transport.hello(0, TIMEOUT).unwrap().unwrap();
+        assert!(handshake.supports_sequence());
+        assert_eq!(
+            transport.sent[0].payload,
+            vec![PROTOCOL_VERSION, FEATURE_SEQUENCE]
+        );
+
+        // Older firmware refuses HELLO
+        assert_eq!(transport.hello(0, TIMEOUT).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sequenced_exec_resent_with_same_number() {
+        let mut transport = MockTransport::new();
+        transport.push_sequenced(0, ErrorCode::Ok, &[]);
+        transport.push_timeout();
+        // Late response to #0, then the device's repeated answer for #1
+        transport.push_sequenced(0, ErrorCode::Ok, &[]);
+        transport.push_sequenced(1, ErrorCode::Ok, &[1, 5, 0]);
+
+        let mut transport = retrying(transport, 2);
+        transport.enable_sequence();
+        transport.ping(TIMEOUT).unwrap();
+        let response = transport.exec(&[0x51], TIMEOUT).unwrap();
+        assert_eq!(response.word_indices, vec![5]);
+    }
+
+    #[test]
+    fn test_exec_resent_on_invalid_frame() {
+        let mut transport = MockTransport::new();
+        transport.push_response(ErrorCode::InvalidFrame, &[]);
+        transport.push_word_indices(&[2]);
+
+        let mut transport = retrying(transport, 1);
+        let response = transport.exec(&[0x51], TIMEOUT).unwrap();
+        assert_eq!(response.word_indices, vec![2]);
+    }
+}
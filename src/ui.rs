@@ -0,0 +1,100 @@
+//! Terminal colors for the CLI and the REPL
+//!
+//! With `--color auto` (the default) styles are only emitted when the stream
+//! is a terminal and `NO_COLOR` is not set; `always` and `never` override
+//! that for stdout and stderr alike.
+
+use console::{StyledObject, style};
+use std::fmt::Display;
+
+/// `--color` setting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Parse a `--color` value (auto, always, never)
+pub fn parse_color_choice(value: &str) -> std::result::Result<ColorChoice, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "auto" => Ok(ColorChoice::Auto),
+        "always" => Ok(ColorChoice::Always),
+        "never" => Ok(ColorChoice::Never),
+        _ => Err(format!(
+            "invalid color mode '{}' (expected auto, always or never)",
+            value
+        )),
+    }
+}
+
+/// Apply the color setting for the rest of the process
+pub fn set_color_choice(choice: ColorChoice) {
+    let enabled = match choice {
+        // console already checks for a terminal and NO_COLOR
+        ColorChoice::Auto => return,
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    console::set_colors_enabled(enabled);
+    console::set_colors_enabled_stderr(enabled);
+}
+
+/// Green check mark for successful steps
+pub fn success() -> StyledObject<&'static str> {
+    style("✓").green()
+}
+
+/// Red cross for failed steps
+pub fn failure() -> StyledObject<&'static str> {
+    style("✗").red()
+}
+
+/// `Error:` label for messages on stderr
+pub fn error_label() -> StyledObject<&'static str> {
+    style("Error:").for_stderr().red().bold()
+}
+
+/// Error message on stderr
+pub fn error<D: Display>(message: D) -> StyledObject<D> {
+    style(message).for_stderr().red()
+}
+
+/// Address column of a hexdump
+pub fn address<D: Display>(value: D) -> StyledObject<D> {
+    style(value).dim()
+}
+
+/// ASCII column of a hexdump
+pub fn ascii<D: Display>(value: D) -> StyledObject<D> {
+    style(value).cyan()
+}
+
+/// Line only in the old text of a diff
+pub fn removed<D: Display>(line: D) -> StyledObject<D> {
+    style(line).red()
+}
+
+/// Line only in the new text of a diff
+pub fn added<D: Display>(line: D) -> StyledObject<D> {
+    style(line).green()
+}
+
+/// REPL prompt, e.g. `v4> `
+pub fn prompt(text: &str) -> String {
+    format!("{} ", style(text.trim_end()).green().bold())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color_choice() {
+        assert_eq!(parse_color_choice("auto"), Ok(ColorChoice::Auto));
+        assert_eq!(parse_color_choice("ALWAYS"), Ok(ColorChoice::Always));
+        assert_eq!(parse_color_choice("never"), Ok(ColorChoice::Never));
+        assert!(parse_color_choice("sometimes").is_err());
+    }
+}
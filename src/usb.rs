@@ -0,0 +1,202 @@
+//! Address serial ports by the USB device behind them
+//!
+//! `--port usb:SERIAL` (or `usb:VID:PID:SERIAL`) picks the port whose USB
+//! device reports that serial number, so scripts keep working when
+//! `/dev/ttyACM0` comes back as `/dev/ttyACM1`. `v4 ports` shows the serial
+//! numbers.
+
+use crate::{Result, V4Error};
+use serialport::{SerialPortInfo, SerialPortType};
+use std::fmt;
+
+/// Prefix of `--port` values naming a USB device instead of a path
+pub const USB_SCHEME: &str = "usb:";
+
+/// USB serial port picked by serial number, optionally with its IDs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbSelector {
+    /// Vendor and product ID
+    pub ids: Option<(u16, u16)>,
+    pub serial: String,
+}
+
+impl UsbSelector {
+    /// Parse the part after `usb:`: `SERIAL` or `VID:PID:SERIAL` (hex IDs)
+    ///
+    /// Serial numbers may contain colons themselves (ESP32 chips report
+    /// their MAC address), so only two leading 4-digit hex fields count as
+    /// IDs.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut parts = spec.splitn(3, ':');
+        let ids = match (parts.next(), parts.next(), parts.next()) {
+            (Some(vid), Some(pid), Some(serial)) => match (parse_id(vid), parse_id(pid)) {
+                (Some(vid), Some(pid)) => Some((vid, pid, serial)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let selector = match ids {
+            Some((vid, pid, serial)) => Self {
+                ids: Some((vid, pid)),
+                serial: serial.to_string(),
+            },
+            None => Self {
+                ids: None,
+                serial: spec.to_string(),
+            },
+        };
+        if selector.serial.is_empty() {
+            return Err(V4Error::Cli(format!(
+                "Missing serial number in {}{}",
+                USB_SCHEME, spec
+            )));
+        }
+        Ok(selector)
+    }
+
+    /// Path of the connected port that matches
+    pub fn locate(&self) -> Result<String> {
+        self.find(&serialport::available_ports()?)
+    }
+
+    /// Path of the single port in `ports` that matches
+    ///
+    /// macOS lists each device as `/dev/cu.*` and `/dev/tty.*`; the `cu`
+    /// one is taken.
+    pub fn find(&self, ports: &[SerialPortInfo]) -> Result<String> {
+        let mut found: Vec<&str> = ports
+            .iter()
+            .filter(|info| self.matches(info))
+            .map(|info| info.port_name.as_str())
+            .collect();
+        let twins: Vec<String> = found
+            .iter()
+            .filter_map(|path| path.strip_prefix("/dev/cu."))
+            .map(|name| format!("/dev/tty.{}", name))
+            .collect();
+        found.retain(|path| !twins.iter().any(|twin| twin == path));
+
+        match found.as_slice() {
+            [path] => Ok(path.to_string()),
+            [] => Err(V4Error::Cli(format!(
+                "No USB serial port matches {}; run 'v4 ports' to list serial numbers",
+                self
+            ))),
+            _ => Err(V4Error::Cli(format!(
+                "Several ports match {} ({}); add VID:PID to choose one",
+                self,
+                found.join(", ")
+            ))),
+        }
+    }
+
+    fn matches(&self, info: &SerialPortInfo) -> bool {
+        let SerialPortType::UsbPort(usb) = &info.port_type else {
+            return false;
+        };
+        self.ids.is_none_or(|ids| ids == (usb.vid, usb.pid))
+            && usb
+                .serial_number
+                .as_deref()
+                .is_some_and(|serial| serial.eq_ignore_ascii_case(&self.serial))
+    }
+}
+
+impl fmt::Display for UsbSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(USB_SCHEME)?;
+        if let Some((vid, pid)) = self.ids {
+            write!(f, "{:04x}:{:04x}:", vid, pid)?;
+        }
+        f.write_str(&self.serial)
+    }
+}
+
+/// Selector in a `--port` value, `None` for a path or URL
+pub fn selector(port: &str) -> Result<Option<UsbSelector>> {
+    port.strip_prefix(USB_SCHEME)
+        .map(UsbSelector::parse)
+        .transpose()
+}
+
+/// Port path for a `--port` value, looking `usb:` selectors up
+pub fn resolve(port: &str) -> Result<String> {
+    match selector(port)? {
+        Some(usb) => usb.locate(),
+        None => Ok(port.to_string()),
+    }
+}
+
+/// Four hex digits
+fn parse_id(text: &str) -> Option<u16> {
+    (text.len() == 4)
+        .then(|| u16::from_str_radix(text, 16).ok())
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    fn usb_port(path: &str, vid: u16, pid: u16, serial: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: path.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid,
+                serial_number: Some(serial.to_string()),
+                manufacturer: None,
+                product: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_parse_selector() {
+        let usb = UsbSelector::parse("303a:1001:F4:12:FA:5A:8B:C4").unwrap();
+        assert_eq!(usb.ids, Some((0x303a, 0x1001)));
+        assert_eq!(usb.serial, "F4:12:FA:5A:8B:C4");
+        assert_eq!(usb.to_string(), "usb:303a:1001:F4:12:FA:5A:8B:C4");
+
+        let usb = UsbSelector::parse("F4:12:FA:5A:8B:C4").unwrap();
+        assert_eq!(usb.ids, None);
+        assert_eq!(usb.serial, "F4:12:FA:5A:8B:C4");
+
+        assert!(UsbSelector::parse("").is_err());
+        assert!(UsbSelector::parse("303a:1001:").is_err());
+        assert_eq!(selector("/dev/ttyACM0").unwrap(), None);
+        assert!(selector("usb:A1").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_find_port_by_serial() {
+        let ports = [
+            usb_port("/dev/ttyACM0", 0x303a, 0x1001, "AAAA"),
+            usb_port("/dev/ttyACM1", 0x303a, 0x1001, "BBBB"),
+            usb_port("/dev/ttyUSB0", 0x10c4, 0xea60, "BBBB"),
+            SerialPortInfo {
+                port_name: "/dev/ttyS0".to_string(),
+                port_type: SerialPortType::PciPort,
+            },
+        ];
+        let find = |spec| UsbSelector::parse(spec).unwrap().find(&ports);
+
+        assert_eq!(find("aaaa").unwrap(), "/dev/ttyACM0");
+        assert_eq!(find("10c4:ea60:BBBB").unwrap(), "/dev/ttyUSB0");
+        let err = find("BBBB").unwrap_err().to_string();
+        assert!(err.contains("/dev/ttyACM1, /dev/ttyUSB0"), "{}", err);
+        let err = find("CCCC").unwrap_err().to_string();
+        assert!(err.contains("usb:CCCC"), "{}", err);
+    }
+
+    #[test]
+    fn test_find_prefers_callout_device() {
+        let ports = [
+            usb_port("/dev/tty.usbmodem1101", 0x303a, 0x1001, "AAAA"),
+            usb_port("/dev/cu.usbmodem1101", 0x303a, 0x1001, "AAAA"),
+        ];
+        let usb = UsbSelector::parse("AAAA").unwrap();
+        assert_eq!(usb.find(&ports).unwrap(), "/dev/cu.usbmodem1101");
+    }
+}
@@ -0,0 +1,354 @@
+use crate::protocol::{Checksum, Frame, FrameDecoder, Framing};
+use crate::transport::Transport;
+use crate::{Result, V4Error};
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::{Message, WebSocket};
+
+/// `--port` prefix selecting the WebSocket transport
+pub const WS_SCHEME: &str = "ws://";
+
+/// Connect timeout for the underlying TCP connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Idle time after which a ping keeps the bridge from dropping the connection
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Connection attempts when the bridge has dropped the connection
+const RECONNECT_ATTEMPTS: u32 = 3;
+
+/// Delay between reconnection attempts
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// V4-link over WebSocket (browser-hosted devices behind a bridge)
+///
+/// V4-link bytes travel in binary messages; a frame may span messages. A
+/// connection the bridge closes is reopened on the next send.
+pub struct V4WebSocket {
+    url: String,
+    /// `None` after the connection was lost
+    socket: Option<WebSocket<TcpStream>>,
+    /// Bytes received but not yet returned as a frame
+    pending: FrameDecoder,
+    /// When a message was last sent, for keep-alive pings
+    last_sent: Instant,
+}
+
+impl V4WebSocket {
+    /// Connect to a `ws://host[:port][/path]` URL
+    pub fn connect(url: &str) -> Result<Self> {
+        let socket = open_socket(url)?;
+        Ok(Self {
+            url: url.to_string(),
+            socket: Some(socket),
+            pending: FrameDecoder::new(),
+            last_sent: Instant::now(),
+        })
+    }
+
+    /// Reopen the connection, retrying a few times
+    fn reconnect(&mut self) -> Result<&mut WebSocket<TcpStream>> {
+        log::warn!("WebSocket connection lost, reconnecting to {}", self.url);
+        self.socket = None;
+        self.pending.clear();
+
+        let mut attempt = 1;
+        let socket = loop {
+            match open_socket(&self.url) {
+                Ok(socket) => break socket,
+                Err(e) if attempt < RECONNECT_ATTEMPTS => {
+                    log::debug!("Reconnect attempt {} failed: {}", attempt, e);
+                    attempt += 1;
+                    sleep(RECONNECT_DELAY);
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        self.last_sent = Instant::now();
+        Ok(self.socket.insert(socket))
+    }
+
+    /// The open connection, or an error if it was lost
+    fn socket(&mut self) -> Result<&mut WebSocket<TcpStream>> {
+        self.socket.as_mut().ok_or_else(|| {
+            V4Error::Io(std::io::Error::new(
+                ErrorKind::NotConnected,
+                "WebSocket connection lost",
+            ))
+        })
+    }
+
+    /// Ping the bridge if nothing was sent for a while
+    fn keep_alive(&mut self) -> Result<()> {
+        if self.last_sent.elapsed() < KEEPALIVE_INTERVAL {
+            return Ok(());
+        }
+        log::trace!("WebSocket keep-alive ping");
+        let result = self.socket()?.send(Message::Ping(Default::default()));
+        self.last_sent = Instant::now();
+        self.check(result)
+    }
+
+    /// Read one message within `timeout`, appending binary data to the pending bytes
+    ///
+    /// Returns `false` on timeout.
+    fn read_message(&mut self, timeout: Duration) -> Result<bool> {
+        let socket = self.socket()?;
+        socket
+            .get_mut()
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let result = socket.read();
+        match result {
+            Ok(Message::Binary(data)) => {
+                self.pending.push(&data);
+                Ok(true)
+            }
+            Ok(Message::Close(_)) => {
+                self.socket = None;
+                Err(closed())
+            }
+            Ok(other) => {
+                log::trace!("Ignoring WebSocket message: {:?}", other);
+                Ok(true)
+            }
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => self.check(Err(e)).map(|_| false),
+        }
+    }
+
+    /// Map a WebSocket error, forgetting the connection if it is gone
+    fn check(&mut self, result: tungstenite::Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if is_disconnect(&e) => {
+                self.socket = None;
+                Err(closed())
+            }
+            Err(e) => Err(V4Error::WebSocket(e.to_string())),
+        }
+    }
+}
+
+/// Whether `port` selects the WebSocket transport
+pub fn is_websocket_url(port: &str) -> bool {
+    port.starts_with(WS_SCHEME) || port.starts_with("wss://")
+}
+
+/// Open the TCP connection and perform the WebSocket handshake
+fn open_socket(url: &str) -> Result<WebSocket<TcpStream>> {
+    if url.starts_with("wss://") {
+        return Err(V4Error::WebSocket(
+            "wss:// is not supported; use ws:// (e.g. behind a TLS-terminating proxy)".to_string(),
+        ));
+    }
+    let request = url
+        .into_client_request()
+        .map_err(|e| V4Error::WebSocket(format!("Invalid URL {}: {}", url, e)))?;
+    let host = request
+        .uri()
+        .host()
+        .ok_or_else(|| V4Error::WebSocket(format!("Invalid URL {}: missing host", url)))?;
+    let addr = format!("{}:{}", host, request.uri().port_u16().unwrap_or(80));
+
+    let stream = connect_tcp(&addr).map_err(|source| V4Error::TcpConnect {
+        addr: url.to_string(),
+        source,
+    })?;
+    stream.set_nodelay(true)?;
+    let (socket, _) = tungstenite::client(request, stream)
+        .map_err(|e| V4Error::WebSocket(format!("Handshake with {} failed: {}", url, e)))?;
+    Ok(socket)
+}
+
+fn connect_tcp(addr: &str) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for sock_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&sock_addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no addresses resolved")))
+}
+
+fn is_disconnect(error: &tungstenite::Error) -> bool {
+    match error {
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => true,
+        tungstenite::Error::Io(e) => matches!(
+            e.kind(),
+            ErrorKind::BrokenPipe
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::NotConnected
+        ),
+        tungstenite::Error::Protocol(_) => true,
+        _ => false,
+    }
+}
+
+fn closed() -> V4Error {
+    V4Error::Io(std::io::Error::new(
+        ErrorKind::UnexpectedEof,
+        "connection closed by WebSocket bridge",
+    ))
+}
+
+impl Transport for V4WebSocket {
+    /// Send a frame as one binary message, reconnecting if the connection was lost
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        let encoded = self.pending.framing().encode(frame);
+        log::trace!("TX frame ({} bytes): {:02X?}", encoded.len(), encoded);
+
+        if self.socket.is_none() {
+            self.reconnect()?;
+        }
+        let result = self.socket()?.send(Message::binary(encoded.clone()));
+        let result = match result {
+            Err(e) if is_disconnect(&e) => self.reconnect()?.send(Message::binary(encoded)),
+            other => other,
+        };
+        self.last_sent = Instant::now();
+        self.check(result)
+    }
+
+    /// Read whatever arrives within the timeout, pending bytes first
+    fn read_raw(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        if self.pending.is_empty() {
+            self.keep_alive()?;
+            self.read_message(timeout.min(KEEPALIVE_INTERVAL))?;
+        }
+        Ok(self.pending.read_into(buf))
+    }
+
+    fn switches_framing(&self) -> bool {
+        true
+    }
+
+    fn set_framing(&mut self, framing: Framing) -> Result<()> {
+        self.pending.set_framing(framing);
+        Ok(())
+    }
+
+    fn checksum(&self) -> Checksum {
+        self.pending.framing().checksum
+    }
+
+    /// Drop pending bytes and any messages already received
+    fn discard_input(&mut self) -> Result<()> {
+        self.pending.clear();
+        if self.socket.is_some() {
+            while self.read_message(Duration::ZERO)? {}
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Receive response with timeout, pinging the bridge during long waits
+    fn recv_response(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let start = Instant::now();
+
+        loop {
+            if let Some(frame) = self.pending.next_frame() {
+                let frame = frame?;
+                log::trace!("RX frame ({} bytes): {:02X?}", frame.len(), frame);
+                return Ok(frame);
+            }
+
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(V4Error::Timeout);
+            }
+            self.keep_alive()?;
+            self.read_message(remaining.min(KEEPALIVE_INTERVAL))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ErrorCode, device_frame};
+    use std::net::TcpListener;
+
+    fn ok_response() -> Vec<u8> {
+        device_frame(ErrorCode::Ok as u8, &[])
+    }
+
+    /// Answer one request on an accepted connection, splitting the response
+    fn answer(ws: &mut WebSocket<TcpStream>) -> Vec<u8> {
+        let request = loop {
+            match ws.read().unwrap() {
+                Message::Binary(data) => break data.to_vec(),
+                _ => continue,
+            }
+        };
+        let response = ok_response();
+        ws.send(Message::binary(response[..2].to_vec())).unwrap();
+        ws.send(Message::binary(response[2..].to_vec())).unwrap();
+        request
+    }
+
+    #[test]
+    fn test_ping_over_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/v4", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept(stream).unwrap();
+            answer(&mut ws)
+        });
+
+        let mut ws = V4WebSocket::connect(&url).unwrap();
+        let code = ws.ping(Duration::from_secs(2)).unwrap();
+        assert_eq!(code, ErrorCode::Ok);
+
+        let request = server.join().unwrap();
+        assert_eq!(request[0], 0xA5);
+    }
+
+    #[test]
+    fn test_reconnects_after_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            // First connection: the bridge goes away
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept(stream).unwrap();
+            ws.close(None).unwrap();
+            while ws.read().is_ok() {}
+
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept(stream).unwrap();
+            answer(&mut ws);
+        });
+
+        let mut ws = V4WebSocket::connect(&url).unwrap();
+        assert!(ws.ping(Duration::from_secs(2)).is_err());
+        assert_eq!(ws.ping(Duration::from_secs(2)).unwrap(), ErrorCode::Ok);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_rejects_unsupported_urls() {
+        assert!(is_websocket_url("ws://bridge.local/dev/1"));
+        assert!(!is_websocket_url("tcp://bridge.local"));
+        assert!(matches!(
+            V4WebSocket::connect("wss://bridge.local"),
+            Err(V4Error::WebSocket(_))
+        ));
+    }
+}
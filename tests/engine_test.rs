@@ -0,0 +1,100 @@
+/*!
+ * Tests for `--simulate`: the V4 engine in a `v4 engine-server` process
+ *
+ * Each test starts the engine from the `v4` binary built alongside, and
+ * talks to it through `EngineTransport` as `v4 exec` and the REPL do.
+ */
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use v4_cli::V4Error;
+use v4_cli::commands;
+use v4_cli::device;
+use v4_cli::engine::{EngineProcess, EngineTransport};
+use v4_cli::ffi::Compiler;
+use v4_cli::interrupt;
+use v4_cli::protocol::ErrorCode;
+use v4_cli::transport::Transport;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// JMP to itself
+const RUNAWAY: &[u8] = &[0x40, 0xFD, 0xFF];
+
+fn engine() -> EngineTransport {
+    let process = EngineProcess::spawn(env!("CARGO_BIN_EXE_v4")).expect("engine should start");
+    EngineTransport::with_engine(process)
+}
+
+#[test]
+fn engine_exec_and_query() {
+    let mut transport = engine();
+
+    let response = transport
+        .define_word("SQ", &[0x01, 0x12, 0x51], TIMEOUT)
+        .unwrap();
+    let [lo, hi] = response.word_indices[0].to_le_bytes();
+
+    let response = transport
+        .exec(&[0x76, 5, 0x50, lo, hi, 0x51], TIMEOUT)
+        .unwrap();
+    assert_eq!(response.error_code, ErrorCode::Ok);
+
+    let response = transport.query_stack(TIMEOUT).unwrap();
+    assert_eq!(response.data, vec![1, 25, 0, 0, 0, 0]);
+
+    assert_eq!(transport.reset(TIMEOUT).unwrap(), ErrorCode::Ok);
+    assert!(transport.engine().data_stack().unwrap().is_empty());
+}
+
+#[test]
+fn engine_errors_are_vm_errors() {
+    let mut transport = engine();
+    // DROP on an empty stack
+    let response = transport.exec(&[0x02, 0x51], TIMEOUT).unwrap();
+    assert_eq!(response.error_code, ErrorCode::VmError);
+}
+
+#[test]
+fn runaway_program_times_out_and_abort_stops_it() {
+    let mut transport = engine();
+
+    let result = transport.exec(RUNAWAY, Duration::from_millis(200));
+    assert!(matches!(result, Err(V4Error::Timeout)), "{:?}", result);
+    assert_eq!(transport.abort(TIMEOUT).unwrap(), ErrorCode::Ok);
+
+    // No new VM until RESET
+    let response = transport.query_stack(TIMEOUT).unwrap();
+    assert_eq!(response.error_code, ErrorCode::Error);
+
+    assert_eq!(transport.reset(TIMEOUT).unwrap(), ErrorCode::Ok);
+    let response = transport.exec(&[0x76, 4, 0x51], TIMEOUT).unwrap();
+    assert_eq!(response.error_code, ErrorCode::Ok);
+    assert_eq!(transport.engine().data_stack().unwrap(), [4]);
+}
+
+#[test]
+fn ctrl_c_aborts_a_runaway_program() {
+    let mut transport = engine();
+
+    let catch = interrupt::catch();
+    std::thread::spawn(|| {
+        std::thread::sleep(Duration::from_millis(200));
+        interrupt::flag().store(true, Ordering::SeqCst);
+    });
+    let result = transport.exec(RUNAWAY, Duration::from_secs(30));
+    drop(catch);
+    let result = device::abort_on_interrupt(&mut transport, result, TIMEOUT);
+    assert!(matches!(result, Err(V4Error::Aborted)), "{:?}", result);
+    assert!(!transport.engine().is_running());
+}
+
+#[test]
+fn offline_repl_runs_on_the_engine() {
+    let mut transport = engine();
+    let mut compiler = Compiler::new().unwrap();
+
+    let input = ": SQ DUP * ;\n3 SQ\n";
+    commands::pipe_loop(&mut transport, &mut compiler, None, input.as_bytes(), None).unwrap();
+    assert_eq!(transport.engine().data_stack().unwrap(), [9]);
+}
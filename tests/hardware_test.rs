@@ -74,7 +74,7 @@ fn reset_vm(port: &str) -> Result<(), String> {
     }
 
     let output = Command::new(get_v4_binary())
-        .args(&["reset", "--port", port])
+        .args(["reset", "--port", port])
         .output()
         .map_err(|e| format!("Failed to execute reset: {}", e))?;
 
@@ -85,8 +85,7 @@ fn reset_vm(port: &str) -> Result<(), String> {
         ));
     }
 
-    // Wait for VM to stabilize
-    thread::sleep(Duration::from_millis(100));
+    // `v4 reset` returns once the device answers PING again
     Ok(())
 }
 
@@ -100,7 +99,7 @@ fn compile_and_push(port: &str, source: &str, name: &str) -> Result<(), String>
     let bytecode_path = source_path.with_extension("v4b");
 
     let compile_output = Command::new(&v4_bin)
-        .args(&[
+        .args([
             "compile",
             source_path.to_str().unwrap(),
             "-o",
@@ -118,7 +117,7 @@ fn compile_and_push(port: &str, source: &str, name: &str) -> Result<(), String>
 
     // Push bytecode
     let push_output = Command::new(&v4_bin)
-        .args(&["push", bytecode_path.to_str().unwrap(), "--port", port])
+        .args(["push", bytecode_path.to_str().unwrap(), "--port", port])
         .output()
         .map_err(|e| format!("Failed to push: {}", e))?;
 
@@ -0,0 +1,103 @@
+/*!
+ * Protocol regression tests replaying captured device traffic
+ *
+ * The captures in `tests/traces` were recorded with `--trace-file`. Each test
+ * runs the same operation against a `ReplayTransport`, which fails as soon
+ * as the host sends a frame that differs from the capture.
+ *
+ * To update a capture, record the operation again against a device:
+ *
+ * ```bash
+ * v4 --trace-file tests/traces/push.jsonl push --retries 1 square.v4b
+ * ```
+ */
+
+use std::path::PathBuf;
+use std::time::Duration;
+use v4_cli::commands;
+use v4_cli::ffi::Compiler;
+use v4_cli::trace::ReplayTransport;
+use v4_cli::transport::RetryPolicy;
+use v4_cli::{V4Device, V4Error};
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// `: SQUARE DUP * ;` compiled to a v0.2 .v4b file
+const SQUARE_V4B: &[u8] = &[
+    0x56, 0x34, 0x42, 0x43, 0x00, 0x02, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x51,
+];
+
+fn trace_path(name: &str) -> String {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests/traces");
+    path.push(name);
+    path.to_string_lossy().into_owned()
+}
+
+fn replay(name: &str) -> ReplayTransport {
+    ReplayTransport::load(&trace_path(name)).expect("capture should load")
+}
+
+/// .v4b file too large for one frame: 699 zero bytes and RET, no words
+fn large_v4b() -> Vec<u8> {
+    let mut code = vec![0u8; 699];
+    code.push(0x51);
+    let mut data = b"V4BC".to_vec();
+    data.extend_from_slice(&[0x00, 0x02, 0x00, 0x00]);
+    data.extend_from_slice(&(code.len() as u32).to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&code);
+    data
+}
+
+#[test]
+fn replay_push_with_sequence_numbers() {
+    let policy = RetryPolicy {
+        retries: 1,
+        ..RetryPolicy::default()
+    };
+    let mut device = V4Device::from_transport(Box::new(replay("push.jsonl")), "replay")
+        .with_retry(policy)
+        .unwrap();
+
+    let report = device.push(SQUARE_V4B, TIMEOUT, &mut |_| {}).unwrap();
+    assert_eq!(report.size, SQUARE_V4B.len());
+    assert_eq!(report.word_indices, vec![0]);
+}
+
+#[test]
+fn replay_chunked_push_with_accepted_result() {
+    let mut device = V4Device::from_transport(Box::new(replay("push_chunked.jsonl")), "replay");
+
+    let mut acknowledged = Vec::new();
+    let report = device
+        .push(&large_v4b(), TIMEOUT, &mut |sent| acknowledged.push(sent))
+        .unwrap();
+    assert_eq!(report.size, 716);
+    assert!(report.word_indices.is_empty());
+    assert_eq!(acknowledged.last(), Some(&716));
+}
+
+#[test]
+fn replay_script_meta_commands_and_assertions() {
+    let mut transport = replay("memory.jsonl");
+    let mut compiler = Compiler::new().unwrap();
+
+    let report =
+        commands::run_script(&mut transport, &mut compiler, &trace_path("memory.v4s")).unwrap();
+    assert_eq!(report.lines, 5);
+    assert_eq!(report.checks, 3);
+    transport.finish().unwrap();
+}
+
+#[test]
+fn replay_fails_when_the_host_sends_something_else() {
+    let mut device = V4Device::from_transport(Box::new(replay("push_chunked.jsonl")), "replay");
+
+    let mut changed = large_v4b();
+    changed[100] = 0xFF;
+    let err = device.push(&changed, TIMEOUT, &mut |_| {}).unwrap_err();
+    assert!(matches!(err, V4Error::Trace(_)), "{}", err);
+    assert!(err.to_string().contains("diverged"), "{}", err);
+}